@@ -0,0 +1,482 @@
+//! Adaptive concurrency limits, adjusted according to observed latency.
+//!
+//! **Experimental** - the algorithms and their parameters may change.
+//!
+//! Rather than hand-tuning a static limit on the number of requests in
+//! flight, an `AdaptiveLimit` estimates the concurrency a service can sustain
+//! from the latency of the requests it completes, in the style of Netflix's
+//! concurrency-limits library. Requests beyond the limit are rejected
+//! immediately, rather than queueing and adding latency for every request.
+//!
+//! The same `AdaptiveLimitService` can protect a server, or limit the
+//! requests a client sends to a downstream service, and the current limit is
+//! exposed by `AdaptiveLimit::stats` for export as metrics.
+use futures::future::{BoxFuture, FutureExt};
+use hyper::header::{HeaderValue, RETRY_AFTER};
+use hyper::service::Service;
+use hyper::{Request, Response, StatusCode};
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::{Duration, Instant};
+
+/// Algorithm used to adjust an `AdaptiveLimit`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum LimitAlgorithm {
+    /// Additive increase, multiplicative decrease.
+    ///
+    /// The limit grows by one for each request completing within the latency
+    /// threshold while at least half the limit is in use, and is multiplied
+    /// by the backoff ratio for each request which is dropped or exceeds it.
+    Aimd {
+        /// Ratio by which to reduce the limit, between 0 and 1.
+        backoff_ratio: f64,
+        /// Latency above which a request is treated as dropped.
+        latency_threshold: Duration,
+    },
+    /// Gradient of the latency.
+    ///
+    /// The limit is scaled by the ratio of the long-term average latency to
+    /// that of each request, so it shrinks as requests queue and latency
+    /// rises, plus an allowance for a queue of the square root of the limit,
+    /// so that it grows while latency is steady.
+    Gradient {
+        /// Weight given to each new estimate of the limit, between 0 and 1.
+        smoothing: f64,
+        /// Ratio by which latency may exceed the long-term average before the
+        /// limit is reduced, at least 1.
+        tolerance: f64,
+    },
+}
+
+impl Default for LimitAlgorithm {
+    fn default() -> Self {
+        LimitAlgorithm::Gradient {
+            smoothing: 0.2,
+            tolerance: 1.5,
+        }
+    }
+}
+
+/// Snapshot of the state of an `AdaptiveLimit`, for export as metrics.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct AdaptiveLimitStats {
+    /// Current limit on the number of requests in flight.
+    pub limit: usize,
+    /// Number of requests in flight.
+    pub in_flight: usize,
+    /// Number of requests rejected because the limit was reached.
+    pub rejected: u64,
+}
+
+#[derive(Debug)]
+struct State {
+    limit: f64,
+    min_limit: usize,
+    max_limit: usize,
+    /// Long-term average latency, in seconds.
+    long_rtt: Option<f64>,
+}
+
+#[derive(Debug)]
+struct Shared {
+    algorithm: LimitAlgorithm,
+    state: Mutex<State>,
+    in_flight: AtomicUsize,
+    rejected: AtomicU64,
+}
+
+/// Limit on the number of requests in flight, adjusted according to their
+/// latency.
+///
+/// Clones share the same limit, so one limit can be used by all connections
+/// to a server, or by all clients of a downstream service.
+///
+/// ```ignore
+/// let limit = AdaptiveLimit::new(LimitAlgorithm::default())
+///     .with_initial_limit(50)
+///     .with_bounds(10, 500);
+/// let service = MakeAdaptiveLimitService::new(inner, limit.clone());
+/// // Later, when exporting metrics:
+/// let stats = limit.stats();
+/// ```
+#[derive(Clone, Debug)]
+pub struct AdaptiveLimit(Arc<Shared>);
+
+impl Default for AdaptiveLimit {
+    fn default() -> Self {
+        AdaptiveLimit::new(LimitAlgorithm::default())
+    }
+}
+
+impl AdaptiveLimit {
+    /// Create a limit adjusted by the given algorithm, initially allowing 20
+    /// requests in flight, and bounded between 1 and 1000.
+    pub fn new(algorithm: LimitAlgorithm) -> Self {
+        AdaptiveLimit(Arc::new(Shared {
+            algorithm,
+            state: Mutex::new(State {
+                limit: 20.0,
+                min_limit: 1,
+                max_limit: 1000,
+                long_rtt: None,
+            }),
+            in_flight: AtomicUsize::new(0),
+            rejected: AtomicU64::new(0),
+        }))
+    }
+
+    /// Set the initial limit, which is shared with any clones.
+    pub fn with_initial_limit(self, limit: usize) -> Self {
+        let mut state = self.0.lock();
+        state.limit = limit.clamp(state.min_limit, state.max_limit) as f64;
+        drop(state);
+        self
+    }
+
+    /// Set the bounds of the limit, which are shared with any clones.
+    pub fn with_bounds(self, min_limit: usize, max_limit: usize) -> Self {
+        let mut state = self.0.lock();
+        state.min_limit = min_limit.max(1);
+        state.max_limit = max_limit.max(state.min_limit);
+        state.limit = state
+            .limit
+            .clamp(state.min_limit as f64, state.max_limit as f64);
+        drop(state);
+        self
+    }
+
+    /// Current limit on the number of requests in flight.
+    pub fn limit(&self) -> usize {
+        self.0.lock().limit as usize
+    }
+
+    /// Snapshot of the state of the limit.
+    pub fn stats(&self) -> AdaptiveLimitStats {
+        AdaptiveLimitStats {
+            limit: self.limit(),
+            in_flight: self.0.in_flight.load(Ordering::Relaxed),
+            rejected: self.0.rejected.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Admit a request, if the limit allows, returning a permit which must be
+    /// held until it completes.
+    pub fn try_acquire(&self) -> Option<LimitPermit> {
+        let limit = self.limit();
+        let admitted =
+            self.0
+                .in_flight
+                .fetch_update(Ordering::AcqRel, Ordering::Acquire, |in_flight| {
+                    (in_flight < limit).then_some(in_flight + 1)
+                });
+        match admitted {
+            Ok(in_flight) => Some(LimitPermit {
+                limit: self.clone(),
+                start: Instant::now(),
+                in_flight: in_flight + 1,
+                recorded: false,
+            }),
+            Err(_) => {
+                self.0.rejected.fetch_add(1, Ordering::Relaxed);
+                None
+            }
+        }
+    }
+
+    /// Adjust the limit for a request which completed after the given time,
+    /// with the given number of requests in flight.
+    fn update(&self, rtt: Duration, in_flight: usize, dropped: bool) {
+        let shared = &self.0;
+        let rtt = rtt.as_secs_f64();
+        let mut state = shared.lock();
+        let limit = state.limit;
+
+        let new_limit = match shared.algorithm {
+            LimitAlgorithm::Aimd {
+                backoff_ratio,
+                latency_threshold,
+            } => {
+                if dropped || rtt > latency_threshold.as_secs_f64() {
+                    limit * backoff_ratio
+                } else if in_flight * 2 >= limit as usize {
+                    limit + 1.0
+                } else {
+                    limit
+                }
+            }
+            LimitAlgorithm::Gradient {
+                smoothing,
+                tolerance,
+            } => {
+                let long_rtt = state
+                    .long_rtt
+                    .map_or(rtt, |long_rtt| long_rtt * 0.95 + rtt * 0.05);
+                state.long_rtt = Some(long_rtt);
+
+                if dropped {
+                    limit * 0.9
+                } else if (in_flight as f64) < limit / 2.0 {
+                    // Too few requests in flight to tell whether the limit
+                    // is too high.
+                    limit
+                } else {
+                    let gradient = (tolerance * long_rtt / rtt.max(f64::EPSILON)).clamp(0.5, 1.0);
+                    let estimate = limit * gradient + limit.sqrt();
+                    limit * (1.0 - smoothing) + estimate * smoothing
+                }
+            }
+        };
+
+        state.limit = new_limit.clamp(state.min_limit as f64, state.max_limit as f64);
+    }
+}
+
+impl Shared {
+    fn lock(&self) -> std::sync::MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+/// Permit for a request admitted by an `AdaptiveLimit`, held until the
+/// request completes.
+///
+/// Dropping the permit without recording the outcome, for example because
+/// the request was cancelled, releases it without adjusting the limit.
+#[derive(Debug)]
+pub struct LimitPermit {
+    limit: AdaptiveLimit,
+    start: Instant,
+    in_flight: usize,
+    recorded: bool,
+}
+
+impl LimitPermit {
+    /// Record that the request completed successfully, adjusting the limit
+    /// according to its latency.
+    pub fn success(self) {
+        self.record(false)
+    }
+
+    /// Record that the request was dropped, for example because it timed out
+    /// or the service reported it was overloaded, reducing the limit.
+    pub fn dropped(self) {
+        self.record(true)
+    }
+
+    fn record(mut self, dropped: bool) {
+        self.recorded = true;
+        self.limit
+            .update(self.start.elapsed(), self.in_flight, dropped);
+    }
+}
+
+impl Drop for LimitPermit {
+    fn drop(&mut self) {
+        self.limit.0.in_flight.fetch_sub(1, Ordering::AcqRel);
+    }
+}
+
+/// Middleware which limits the number of requests in flight according to an
+/// `AdaptiveLimit`.
+#[derive(Debug)]
+pub struct MakeAdaptiveLimitService<T> {
+    inner: T,
+    limit: AdaptiveLimit,
+}
+
+impl<T> MakeAdaptiveLimitService<T> {
+    /// Create a middleware which limits requests according to the given limit,
+    /// which is shared by all services it makes.
+    pub fn new(inner: T, limit: AdaptiveLimit) -> Self {
+        MakeAdaptiveLimitService { inner, limit }
+    }
+}
+
+impl<Inner, Target> Service<Target> for MakeAdaptiveLimitService<Inner>
+where
+    Inner: Service<Target>,
+    Inner::Future: Send + 'static,
+{
+    type Error = Inner::Error;
+    type Response = AdaptiveLimitService<Inner::Response>;
+    type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn call(&self, target: Target) -> Self::Future {
+        let limit = self.limit.clone();
+        Box::pin(
+            self.inner
+                .call(target)
+                .map(|s| Ok(AdaptiveLimitService::new(s?, limit))),
+        )
+    }
+}
+
+/// Middleware which limits the number of requests in flight according to an
+/// `AdaptiveLimit`.
+///
+/// Requests beyond the limit are rejected with `503 Service Unavailable` and
+/// a `Retry-After` header, without calling the inner service. Requests for
+/// which the inner service fails, or responds with `429 Too Many Requests` or
+/// `503 Service Unavailable`, are treated as dropped, reducing the limit.
+///
+/// As a client middleware, this rejects requests locally once the downstream
+/// service appears to be saturated, rather than adding to its queue.
+#[derive(Clone, Debug)]
+pub struct AdaptiveLimitService<T> {
+    inner: T,
+    limit: AdaptiveLimit,
+}
+
+impl<T> AdaptiveLimitService<T> {
+    /// Create a middleware which limits requests according to the given limit.
+    pub fn new(inner: T, limit: AdaptiveLimit) -> Self {
+        AdaptiveLimitService { inner, limit }
+    }
+}
+
+impl<T, B, ResBody, RC> Service<(Request<B>, RC)> for AdaptiveLimitService<T>
+where
+    T: Service<(Request<B>, RC), Response = Response<ResBody>>,
+    T::Future: Send + 'static,
+    T::Error: Send + 'static,
+    ResBody: Default + Send + 'static,
+{
+    type Response = Response<ResBody>;
+    type Error = T::Error;
+    type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn call(&self, req: (Request<B>, RC)) -> Self::Future {
+        let Some(permit) = self.limit.try_acquire() else {
+            let mut response = Response::new(ResBody::default());
+            *response.status_mut() = StatusCode::SERVICE_UNAVAILABLE;
+            response
+                .headers_mut()
+                .insert(RETRY_AFTER, HeaderValue::from_static("1"));
+            return Box::pin(futures::future::ok(response));
+        };
+
+        Box::pin(self.inner.call(req).map(move |result| {
+            match &result {
+                Ok(response)
+                    if response.status() != StatusCode::SERVICE_UNAVAILABLE
+                        && response.status() != StatusCode::TOO_MANY_REQUESTS =>
+                {
+                    permit.success()
+                }
+                _ => permit.dropped(),
+            }
+            result
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::EmptyContext;
+    use futures::channel::oneshot;
+
+    #[test]
+    fn test_aimd() {
+        let limit = AdaptiveLimit::new(LimitAlgorithm::Aimd {
+            backoff_ratio: 0.5,
+            latency_threshold: Duration::from_millis(100),
+        })
+        .with_initial_limit(10)
+        .with_bounds(2, 12);
+
+        // Grows only while at least half the limit is in use.
+        limit.update(Duration::from_millis(10), 2, false);
+        assert_eq!(limit.limit(), 10);
+        for _ in 0..5 {
+            limit.update(Duration::from_millis(10), 6, false);
+        }
+        assert_eq!(limit.limit(), 12);
+
+        limit.update(Duration::from_millis(200), 5, false);
+        assert_eq!(limit.limit(), 6);
+        limit.update(Duration::from_millis(10), 5, true);
+        assert_eq!(limit.limit(), 3);
+        limit.update(Duration::from_millis(10), 5, true);
+        assert_eq!(limit.limit(), 2);
+
+        // Settings made on a clone apply to the shared limit.
+        let clone = limit.clone().with_bounds(4, 8);
+        assert_eq!(limit.limit(), 4);
+        assert_eq!(clone.with_initial_limit(20).limit(), 8);
+        assert_eq!(limit.limit(), 8);
+    }
+
+    #[test]
+    fn test_gradient() {
+        let limit = AdaptiveLimit::new(LimitAlgorithm::Gradient {
+            smoothing: 0.5,
+            tolerance: 1.0,
+        })
+        .with_initial_limit(16);
+
+        // Steady latency grows the limit by the queue allowance.
+        limit.update(Duration::from_millis(10), 16, false);
+        assert_eq!(limit.limit(), 18);
+
+        // Rising latency shrinks it.
+        for _ in 0..10 {
+            limit.update(Duration::from_millis(100), 16, false);
+        }
+        assert!(limit.limit() < 18);
+    }
+
+    #[test]
+    fn test_permits() {
+        let limit = AdaptiveLimit::default().with_initial_limit(2);
+        let first = limit.try_acquire().unwrap();
+        let _second = limit.try_acquire().unwrap();
+        assert!(limit.try_acquire().is_none());
+        assert_eq!(
+            limit.stats(),
+            AdaptiveLimitStats {
+                limit: 2,
+                in_flight: 2,
+                rejected: 1
+            }
+        );
+
+        drop(first);
+        assert!(limit.try_acquire().is_some());
+    }
+
+    #[derive(Clone)]
+    struct PendingService(Arc<Mutex<Vec<oneshot::Sender<()>>>>);
+
+    impl Service<(Request<()>, EmptyContext)> for PendingService {
+        type Response = Response<String>;
+        type Error = ();
+        type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+        fn call(&self, _: (Request<()>, EmptyContext)) -> Self::Future {
+            let (sender, receiver) = oneshot::channel();
+            self.0.lock().unwrap().push(sender);
+            Box::pin(async move {
+                let _ = receiver.await;
+                Ok(Response::new(String::new()))
+            })
+        }
+    }
+
+    #[tokio::test]
+    async fn test_adaptive_limit_service() {
+        let pending = PendingService(Arc::default());
+        let limit = AdaptiveLimit::default().with_initial_limit(1);
+        let service = AdaptiveLimitService::new(pending.clone(), limit.clone());
+        let call = || service.call((Request::get("/").body(()).unwrap(), EmptyContext));
+
+        let first = call();
+        let response = call().await.unwrap();
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(response.headers()[RETRY_AFTER], "1");
+
+        pending.0.lock().unwrap().pop().unwrap().send(()).unwrap();
+        assert_eq!(first.await.unwrap().status(), StatusCode::OK);
+        assert_eq!(limit.stats().in_flight, 0);
+    }
+}
@@ -56,8 +56,11 @@ where
 
 /// Middleware wrapper service, that should be used as the outermost layer in a
 /// stack of hyper services. Adds a context to a plain `hyper::Request` that can be
-/// used by subsequent layers in the stack. The `AddContextService` struct should
-/// not usually be used directly - when constructing a hyper stack use
+/// used by subsequent layers in the stack. Requests without an `X-Span-ID` header
+/// take their span ID from the trace ID in their `traceparent` header, if any. The
+/// request itself is left unchanged - add a `MakeExtractContextService` for
+/// `Option<TraceContext>` to read its trace into the context. The `AddContextService`
+/// struct should not usually be used directly - when constructing a hyper stack use
 /// `AddContextMakeService`, which will create `AddContextService` instances as needed.
 #[derive(Debug)]
 pub struct AddContextService<T, C>
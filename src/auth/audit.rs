@@ -0,0 +1,223 @@
+//! Hook for auditing the decisions made by authenticators.
+use super::Authorization;
+use crate::XSpanIdString;
+use hyper::{Method, Request};
+use std::fmt;
+use std::sync::Arc;
+
+/// Decision made by an authenticator about a request.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum AuditOutcome {
+    /// The request was passed on to the inner service, with or without an
+    /// authorization.
+    Accepted,
+    /// The request was rejected.
+    Denied,
+}
+
+impl fmt::Display for AuditOutcome {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            AuditOutcome::Accepted => "accepted",
+            AuditOutcome::Denied => "denied",
+        })
+    }
+}
+
+/// Record of a decision made by an authenticator, as passed to an
+/// `AuditSink`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct AuditEvent {
+    /// Whether the request was accepted or denied.
+    pub outcome: AuditOutcome,
+    /// Span ID of the request, from the `XSpanIdString` in its context.
+    pub span_id: String,
+    /// Method of the request.
+    pub method: Method,
+    /// Path of the request.
+    pub path: String,
+    /// Authentication scheme the decision was based on, if any, such as
+    /// `Basic`, or the names of the schemes in an OpenAPI security
+    /// requirement.
+    pub scheme: Option<String>,
+    /// Subject of the authorization granted to the request, if any.
+    pub subject: Option<String>,
+    /// Why the request was denied, or accepted without an authorization.
+    pub reason: Option<String>,
+}
+
+impl fmt::Display for AuditEvent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} {} {} span_id={:?}",
+            self.outcome, self.method, self.path, self.span_id
+        )?;
+        for (name, value) in [
+            ("scheme", &self.scheme),
+            ("subject", &self.subject),
+            ("reason", &self.reason),
+        ] {
+            if let Some(value) = value {
+                write!(f, " {}={:?}", name, value)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Destination for the decisions made by authenticators, such as a log, or an
+/// adapter for a message queue.
+///
+/// Sinks are called synchronously on each request, so must not block - for
+/// example, by queueing events to be sent by a background task.
+///
+/// This is implemented for closures taking an `AuditEvent`:
+///
+/// ```ignore
+/// let authenticator = BasicAuthenticator::new(inner, validator, "my-api")
+///     .with_audit_sink(|event: AuditEvent| eprintln!("auth: {}", event));
+/// ```
+pub trait AuditSink: Send + Sync {
+    /// Record a decision.
+    fn record(&self, event: AuditEvent);
+}
+
+impl<F> AuditSink for F
+where
+    F: Fn(AuditEvent) + Send + Sync,
+{
+    fn record(&self, event: AuditEvent) {
+        self(event)
+    }
+}
+
+/// The audit sink of an authenticator, if it has one.
+#[derive(Clone, Default)]
+pub(crate) struct Auditor(Option<Arc<dyn AuditSink>>);
+
+impl fmt::Debug for Auditor {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("Auditor").field(&self.0.is_some()).finish()
+    }
+}
+
+impl Auditor {
+    pub(crate) fn new<S: AuditSink + 'static>(sink: S) -> Self {
+        Auditor(Some(Arc::new(sink)))
+    }
+
+    fn record<B>(
+        &self,
+        request: &Request<B>,
+        span_id: &XSpanIdString,
+        outcome: AuditOutcome,
+        scheme: Option<&str>,
+        subject: Option<&str>,
+        reason: Option<&str>,
+    ) {
+        if let Some(sink) = &self.0 {
+            sink.record(AuditEvent {
+                outcome,
+                span_id: span_id.0.clone(),
+                method: request.method().clone(),
+                path: request.uri().path().to_string(),
+                scheme: scheme.map(ToString::to_string),
+                subject: subject.map(ToString::to_string),
+                reason: reason.map(ToString::to_string),
+            });
+        }
+    }
+
+    /// Record that the request, with the given span ID, was accepted with the
+    /// given authorization.
+    pub(crate) fn accept<B>(
+        &self,
+        request: &Request<B>,
+        span_id: &XSpanIdString,
+        scheme: Option<&str>,
+        authorization: Option<&Authorization>,
+    ) {
+        let subject = authorization.map(|authorization| authorization.subject.as_str());
+        let reason = subject.is_none().then_some("No credentials");
+        self.record(
+            request,
+            span_id,
+            AuditOutcome::Accepted,
+            scheme,
+            subject,
+            reason,
+        );
+    }
+
+    /// Record that the request, with the given span ID, was denied for the
+    /// given reason.
+    pub(crate) fn deny<B>(
+        &self,
+        request: &Request<B>,
+        span_id: &XSpanIdString,
+        scheme: Option<&str>,
+        subject: Option<&str>,
+        reason: &str,
+    ) {
+        self.record(
+            request,
+            span_id,
+            AuditOutcome::Denied,
+            scheme,
+            subject,
+            Some(reason),
+        );
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use std::sync::Mutex;
+
+    /// Sink collecting the events recorded.
+    #[derive(Clone, Default)]
+    pub(crate) struct TestSink(pub(crate) Arc<Mutex<Vec<AuditEvent>>>);
+
+    impl AuditSink for TestSink {
+        fn record(&self, event: AuditEvent) {
+            self.0.lock().unwrap().push(event);
+        }
+    }
+
+    impl TestSink {
+        pub(crate) fn take(&self) -> Vec<AuditEvent> {
+            std::mem::take(&mut self.0.lock().unwrap())
+        }
+    }
+
+    #[test]
+    fn test_auditor() {
+        let sink = TestSink::default();
+        let auditor = Auditor::new(sink.clone());
+        let request = Request::get("/pets/1").body(()).unwrap();
+        let span_id = XSpanIdString("span".to_string());
+
+        auditor.accept(&request, &span_id, Some("Basic"), None);
+        auditor.deny(
+            &request,
+            &span_id,
+            None,
+            Some("alice"),
+            "Insufficient scope",
+        );
+
+        let events = sink.take();
+        assert_eq!(
+            events[0].to_string(),
+            "accepted GET /pets/1 span_id=\"span\" scheme=\"Basic\" reason=\"No credentials\""
+        );
+        assert_eq!(events[1].outcome, AuditOutcome::Denied);
+        assert_eq!(events[1].subject.as_deref(), Some("alice"));
+        assert_eq!(events[1].reason.as_deref(), Some("Insufficient scope"));
+
+        // Without a sink, nothing is recorded.
+        Auditor::default().deny(&request, &span_id, None, None, "Denied");
+    }
+}
@@ -0,0 +1,450 @@
+//! Authenticator middleware for HTTP Basic authentication.
+use super::audit::Auditor;
+use super::{
+    AuditSink, AuthData, AuthMode, AuthModePolicy, Authorization, Challenge, RcBound,
+    SchemeRegistry,
+};
+use crate::context::Has;
+use crate::XSpanIdString;
+use futures::future::{BoxFuture, FutureExt};
+use hyper::header::AUTHORIZATION;
+use hyper::service::Service;
+use hyper::{Request, Response};
+use std::future::Future;
+use std::marker::PhantomData;
+use std::sync::Arc;
+
+const SCHEME: &str = "Basic";
+
+/// Build a `401 Unauthorized` response, challenging the client to authenticate
+/// using HTTP Basic authentication in the given realm.
+pub fn basic_challenge<B: Default>(realm: &str) -> Response<B> {
+    Challenge::basic()
+        .with_realm(realm)
+        .with_param("charset", "UTF-8")
+        .to_response()
+}
+
+/// Authenticator which validates HTTP Basic credentials using a user-provided
+/// validator, such as a lookup in a credential store.
+///
+/// The validator is passed the username and password, and returns the
+/// authorization granted, or `None` if the credentials are invalid.
+#[derive(Debug)]
+pub struct MakeBasicAuthenticator<T, F, RC>
+where
+    RC: RcBound,
+    RC::Result: Send + 'static,
+{
+    inner: T,
+    validator: F,
+    realm: String,
+    modes: Arc<AuthModePolicy>,
+    schemes: Arc<SchemeRegistry>,
+    audit: Auditor,
+    marker: PhantomData<RC>,
+}
+
+impl<T, F, RC> MakeBasicAuthenticator<T, F, RC>
+where
+    RC: RcBound,
+    RC::Result: Send + 'static,
+{
+    /// Create a middleware that validates Basic credentials for the given realm.
+    pub fn new<U: Into<String>>(inner: T, validator: F, realm: U) -> Self {
+        MakeBasicAuthenticator {
+            inner,
+            validator,
+            realm: realm.into(),
+            modes: Arc::new(AuthModePolicy::default()),
+            schemes: Arc::new(SchemeRegistry::default()),
+            audit: Auditor::default(),
+            marker: PhantomData,
+        }
+    }
+
+    /// Set the routes on which credentials are optional. By default,
+    /// credentials are required on all routes.
+    pub fn with_modes(mut self, modes: AuthModePolicy) -> Self {
+        self.modes = Arc::new(modes);
+        self
+    }
+
+    /// Parse the `Authorization` header using the given registry, which may
+    /// replace the built-in parsing of Basic credentials.
+    pub fn with_scheme_registry(mut self, schemes: SchemeRegistry) -> Self {
+        self.schemes = Arc::new(schemes);
+        self
+    }
+
+    /// Record each decision to accept or deny a request in the given sink.
+    pub fn with_audit_sink<S: AuditSink + 'static>(mut self, sink: S) -> Self {
+        self.audit = Auditor::new(sink);
+        self
+    }
+}
+
+impl<Inner, F, RC, Target> Service<Target> for MakeBasicAuthenticator<Inner, F, RC>
+where
+    RC: RcBound,
+    RC::Result: Send + 'static,
+    Inner: Service<Target>,
+    Inner::Future: Send + 'static,
+    F: Clone + Send + 'static,
+{
+    type Error = Inner::Error;
+    type Response = BasicAuthenticator<Inner::Response, F, RC>;
+    type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn call(&self, target: Target) -> Self::Future {
+        let validator = self.validator.clone();
+        let realm = self.realm.clone();
+        let modes = self.modes.clone();
+        let schemes = self.schemes.clone();
+        let audit = self.audit.clone();
+        Box::pin(self.inner.call(target).map(|s| {
+            Ok(BasicAuthenticator {
+                inner: s?,
+                validator,
+                realm,
+                modes,
+                schemes,
+                audit,
+                marker: PhantomData,
+            })
+        }))
+    }
+}
+
+/// Authenticator which validates HTTP Basic credentials using a user-provided
+/// validator.
+///
+/// Requests with valid credentials have the resulting authorization pushed to
+/// the context, and are passed to the inner service. Requests with missing or
+/// invalid credentials are rejected with a `401 Unauthorized` response,
+/// including a `WWW-Authenticate: Basic` challenge, except that requests
+/// without an `Authorization` header on routes where credentials are optional
+/// are passed on with `None` as their authorization. Malformed headers, or
+/// those for other schemes, are always rejected.
+///
+/// ```ignore
+/// let authenticator = BasicAuthenticator::new(
+///     inner,
+///     |username: &str, password: &str| {
+///         let lookup = credential_store.check(username, password);
+///         let subject = username.to_string();
+///         async move {
+///             lookup.await.then(|| Authorization::new(subject, Scopes::All))
+///         }
+///     },
+///     "my-api",
+/// );
+/// ```
+#[derive(Debug)]
+pub struct BasicAuthenticator<T, F, RC>
+where
+    RC: RcBound,
+    RC::Result: Send + 'static,
+{
+    inner: T,
+    validator: F,
+    realm: String,
+    modes: Arc<AuthModePolicy>,
+    schemes: Arc<SchemeRegistry>,
+    audit: Auditor,
+    marker: PhantomData<RC>,
+}
+
+impl<T, F, RC> BasicAuthenticator<T, F, RC>
+where
+    RC: RcBound,
+    RC::Result: Send + 'static,
+{
+    /// Create a middleware that validates Basic credentials for the given realm.
+    pub fn new<U: Into<String>>(inner: T, validator: F, realm: U) -> Self {
+        BasicAuthenticator {
+            inner,
+            validator,
+            realm: realm.into(),
+            modes: Arc::new(AuthModePolicy::default()),
+            schemes: Arc::new(SchemeRegistry::default()),
+            audit: Auditor::default(),
+            marker: PhantomData,
+        }
+    }
+
+    /// Set the routes on which credentials are optional. By default,
+    /// credentials are required on all routes.
+    pub fn with_modes(mut self, modes: AuthModePolicy) -> Self {
+        self.modes = Arc::new(modes);
+        self
+    }
+
+    /// Parse the `Authorization` header using the given registry, which may
+    /// replace the built-in parsing of Basic credentials.
+    pub fn with_scheme_registry(mut self, schemes: SchemeRegistry) -> Self {
+        self.schemes = Arc::new(schemes);
+        self
+    }
+
+    /// Record each decision to accept or deny a request in the given sink.
+    pub fn with_audit_sink<S: AuditSink + 'static>(mut self, sink: S) -> Self {
+        self.audit = Auditor::new(sink);
+        self
+    }
+}
+
+impl<T, F, RC> Clone for BasicAuthenticator<T, F, RC>
+where
+    T: Clone,
+    F: Clone,
+    RC: RcBound,
+    RC::Result: Send + 'static,
+{
+    fn clone(&self) -> Self {
+        Self {
+            inner: self.inner.clone(),
+            validator: self.validator.clone(),
+            realm: self.realm.clone(),
+            modes: self.modes.clone(),
+            schemes: self.schemes.clone(),
+            audit: self.audit.clone(),
+            marker: PhantomData,
+        }
+    }
+}
+
+impl<T, F, Fut, B, ResBody, RC> Service<(Request<B>, RC)> for BasicAuthenticator<T, F, RC>
+where
+    RC: RcBound + Has<XSpanIdString>,
+    RC::Result: Send + 'static,
+    T: Service<(Request<B>, RC::Result), Response = Response<ResBody>> + Clone + Send + 'static,
+    T::Future: Send + 'static,
+    F: Fn(&str, &str) -> Fut,
+    Fut: Future<Output = Option<Authorization>> + Send + 'static,
+    B: Send + 'static,
+    ResBody: Default + Send + 'static,
+{
+    type Response = Response<ResBody>;
+    type Error = T::Error;
+    type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn call(&self, req: (Request<B>, RC)) -> Self::Future {
+        let (request, context) = req;
+        let span_id = Has::<XSpanIdString>::get(&context).clone();
+        let missing = !request.headers().contains_key(AUTHORIZATION);
+
+        let validation = match self.schemes.from_headers(request.headers()) {
+            Some(AuthData::Basic(ref username, ref password)) => {
+                Some((self.validator)(username, password.expose_secret()))
+            }
+            // Only requests without any credentials are anonymous. Anything
+            // else which isn't valid Basic credentials is challenged, even
+            // where credentials are optional.
+            _ if missing
+                && self.modes.mode(request.method(), request.uri().path())
+                    == AuthMode::Optional =>
+            {
+                self.audit.accept(&request, &span_id, Some(SCHEME), None);
+                let context = context.push(None);
+                return Box::pin(self.inner.call((request, context)));
+            }
+            _ => None,
+        };
+
+        let inner = self.inner.clone();
+        let realm = self.realm.clone();
+        let audit = self.audit.clone();
+
+        Box::pin(async move {
+            let validation = match validation {
+                Some(validation) => validation,
+                None => {
+                    let reason = if missing {
+                        "Missing credentials"
+                    } else {
+                        "Malformed credentials"
+                    };
+                    audit.deny(&request, &span_id, Some(SCHEME), None, reason);
+                    return Ok(basic_challenge(&realm));
+                }
+            };
+
+            match validation.await {
+                Some(authorization) => {
+                    audit.accept(&request, &span_id, Some(SCHEME), Some(&authorization));
+                    let context = context.push(Some(authorization));
+                    inner.call((request, context)).await
+                }
+                None => {
+                    audit.deny(
+                        &request,
+                        &span_id,
+                        Some(SCHEME),
+                        None,
+                        "Invalid credentials",
+                    );
+                    Ok(basic_challenge(&realm))
+                }
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::auth::{AuditOutcome, Scopes};
+    use crate::context::{ContextBuilder, Push};
+    use crate::EmptyContext;
+    use hyper::header::{AUTHORIZATION, WWW_AUTHENTICATE};
+    use hyper::StatusCode;
+
+    type Context = ContextBuilder<XSpanIdString, EmptyContext>;
+
+    #[derive(Clone)]
+    struct SubjectService;
+
+    impl Service<(Request<()>, ContextBuilder<Option<Authorization>, Context>)> for SubjectService {
+        type Response = Response<String>;
+        type Error = ();
+        type Future = futures::future::Ready<Result<Self::Response, Self::Error>>;
+
+        fn call(
+            &self,
+            req: (Request<()>, ContextBuilder<Option<Authorization>, Context>),
+        ) -> Self::Future {
+            let auth: &Option<Authorization> = req.1.get();
+            futures::future::ok(Response::new(
+                auth.as_ref()
+                    .map_or("anonymous".to_string(), |auth| auth.subject.clone()),
+            ))
+        }
+    }
+
+    fn authenticator() -> BasicAuthenticator<
+        SubjectService,
+        impl Fn(&str, &str) -> futures::future::Ready<Option<Authorization>> + Clone,
+        Context,
+    > {
+        BasicAuthenticator::new(
+            SubjectService,
+            |username: &str, password: &str| {
+                futures::future::ready(
+                    (username == "foo" && password == "bar")
+                        .then(|| Authorization::new(username.to_string(), Scopes::All)),
+                )
+            },
+            "test",
+        )
+    }
+
+    fn request(authorization: Option<&'static str>) -> (Request<()>, Context) {
+        let mut request = Request::builder().uri("/pets");
+        if let Some(authorization) = authorization {
+            request = request.header(AUTHORIZATION, authorization);
+        }
+        let context = EmptyContext.push(XSpanIdString("span".to_string()));
+        (request.body(()).unwrap(), context)
+    }
+
+    #[tokio::test]
+    async fn test_valid_credentials() {
+        let response = authenticator()
+            .call(request(Some("Basic Zm9vOmJhcg==")))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.into_body(), "foo");
+    }
+
+    #[tokio::test]
+    async fn test_invalid_credentials() {
+        for authorization in [None, Some("Basic Zm9vOmJheg=="), Some("Bearer foo")] {
+            let response = authenticator().call(request(authorization)).await.unwrap();
+            assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+            assert_eq!(
+                response.headers().get(WWW_AUTHENTICATE).unwrap(),
+                "Basic realm=\"test\", charset=\"UTF-8\""
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn test_scheme_registry() {
+        // Accept unencoded credentials in place of the built-in parsing.
+        let schemes = SchemeRegistry::new().with_scheme("Basic", |credentials| {
+            let (username, password) = credentials.split_once(':')?;
+            Some(AuthData::basic(username, password))
+        });
+        let authenticator = authenticator().with_scheme_registry(schemes);
+
+        let response = authenticator
+            .call(request(Some("Basic foo:bar")))
+            .await
+            .unwrap();
+        assert_eq!(response.into_body(), "foo");
+
+        let response = authenticator
+            .call(request(Some("Basic Zm9vOmJhcg==")))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    }
+
+    #[tokio::test]
+    async fn test_optional_credentials() {
+        let authenticator = authenticator()
+            .with_modes(AuthModePolicy::new(AuthMode::Required).route("/pets", AuthMode::Optional));
+
+        let response = authenticator.call(request(None)).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.into_body(), "anonymous");
+
+        let response = authenticator
+            .call(request(Some("Basic Zm9vOmJhcg==")))
+            .await
+            .unwrap();
+        assert_eq!(response.into_body(), "foo");
+
+        let response = authenticator
+            .call(request(Some("Basic Zm9vOmJheg==")))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+        for authorization in ["Basic !!!", "Bearer token"] {
+            let response = authenticator
+                .call(request(Some(authorization)))
+                .await
+                .unwrap();
+            assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_audit() {
+        let sink = crate::auth::audit::tests::TestSink::default();
+        let authenticator = authenticator().with_audit_sink(sink.clone());
+
+        for authorization in [
+            Some("Basic Zm9vOmJhcg=="),
+            Some("Basic Zm9vOmJheg=="),
+            None,
+            Some("Bearer token"),
+        ] {
+            authenticator.call(request(authorization)).await.unwrap();
+        }
+
+        let events = sink.take();
+        assert_eq!(events.len(), 4);
+        assert_eq!(events[0].outcome, AuditOutcome::Accepted);
+        assert_eq!(events[0].span_id, "span");
+        assert_eq!(events[0].subject.as_deref(), Some("foo"));
+        assert_eq!(events[0].scheme.as_deref(), Some("Basic"));
+        assert_eq!(events[1].outcome, AuditOutcome::Denied);
+        assert_eq!(events[1].reason.as_deref(), Some("Invalid credentials"));
+        assert_eq!(events[2].reason.as_deref(), Some("Missing credentials"));
+        assert_eq!(events[3].reason.as_deref(), Some("Malformed credentials"));
+    }
+}
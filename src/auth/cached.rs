@@ -0,0 +1,488 @@
+//! Caching of the results of expensive credential validation.
+use super::{AuthData, Authorization};
+use futures::future::{BoxFuture, FutureExt};
+use sha2::{Digest, Sha256};
+use std::collections::hash_map::RandomState;
+use std::collections::HashMap;
+use std::fmt;
+use std::future::Future;
+use std::hash::BuildHasher;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::{Duration, Instant};
+
+/// Validator of credentials, such as a database lookup or a call to a token
+/// introspection endpoint.
+///
+/// This is implemented for closures taking `&AuthData` and returning a future
+/// of the authorization granted, or `None` if the credentials are invalid.
+pub trait CredentialValidator: Send + Sync {
+    /// Future returned by `validate`.
+    type Future: Future<Output = Option<Authorization>> + Send + 'static;
+
+    /// Validate the credentials, returning the authorization granted.
+    fn validate(&self, credentials: &AuthData) -> Self::Future;
+}
+
+impl<F, Fut> CredentialValidator for F
+where
+    F: Fn(&AuthData) -> Fut + Send + Sync,
+    Fut: Future<Output = Option<Authorization>> + Send + 'static,
+{
+    type Future = Fut;
+
+    fn validate(&self, credentials: &AuthData) -> Self::Future {
+        self(credentials)
+    }
+}
+
+/// Statistics of a `CachedAuthenticator`, for export as metrics.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct AuthCacheStats {
+    /// Number of lookups answered from the cache with an authorization.
+    pub hits: u64,
+    /// Number of lookups answered from the cache with a rejection.
+    pub negative_hits: u64,
+    /// Number of lookups passed to the validator.
+    pub misses: u64,
+    /// Number of unexpired entries evicted to bound the size of the cache.
+    pub evictions: u64,
+}
+
+#[derive(Debug, Default)]
+struct Counters {
+    hits: AtomicU64,
+    negative_hits: AtomicU64,
+    misses: AtomicU64,
+    evictions: AtomicU64,
+}
+
+/// SHA-256 digest of a salt and the credentials, identifying an entry.
+type Key = [u8; 32];
+
+#[derive(Debug)]
+struct Entry {
+    authorization: Option<Authorization>,
+    expires: Instant,
+}
+
+#[derive(Debug)]
+struct Node {
+    key: Key,
+    entry: Entry,
+    prev: Option<usize>,
+    next: Option<usize>,
+}
+
+/// Entries in order of use, as a doubly linked list through a slab of nodes,
+/// so that lookups, insertions and evictions take constant time.
+#[derive(Debug, Default)]
+struct Lru {
+    index: HashMap<Key, usize>,
+    nodes: Vec<Node>,
+    free: Vec<usize>,
+    /// Most recently used node.
+    head: Option<usize>,
+    /// Least recently used node.
+    tail: Option<usize>,
+}
+
+impl Lru {
+    fn len(&self) -> usize {
+        self.index.len()
+    }
+
+    fn unlink(&mut self, i: usize) {
+        let (prev, next) = (self.nodes[i].prev, self.nodes[i].next);
+        match prev {
+            Some(prev) => self.nodes[prev].next = next,
+            None => self.head = next,
+        }
+        match next {
+            Some(next) => self.nodes[next].prev = prev,
+            None => self.tail = prev,
+        }
+    }
+
+    fn push_front(&mut self, i: usize) {
+        self.nodes[i].prev = None;
+        self.nodes[i].next = self.head;
+        match self.head {
+            Some(head) => self.nodes[head].prev = Some(i),
+            None => self.tail = Some(i),
+        }
+        self.head = Some(i);
+    }
+
+    /// Look up the entry for the key, marking it as the most recently used.
+    fn get(&mut self, key: &Key) -> Option<&Entry> {
+        let i = *self.index.get(key)?;
+        self.unlink(i);
+        self.push_front(i);
+        Some(&self.nodes[i].entry)
+    }
+
+    fn insert(&mut self, key: Key, entry: Entry) {
+        if let Some(&i) = self.index.get(&key) {
+            self.nodes[i].entry = entry;
+            self.unlink(i);
+            self.push_front(i);
+            return;
+        }
+
+        let node = Node {
+            key,
+            entry,
+            prev: None,
+            next: None,
+        };
+        let i = match self.free.pop() {
+            Some(i) => {
+                self.nodes[i] = node;
+                i
+            }
+            None => {
+                self.nodes.push(node);
+                self.nodes.len() - 1
+            }
+        };
+        self.index.insert(key, i);
+        self.push_front(i);
+    }
+
+    fn remove_node(&mut self, i: usize) {
+        self.unlink(i);
+        self.index.remove(&self.nodes[i].key);
+        self.nodes[i].entry.authorization = None;
+        self.free.push(i);
+    }
+
+    fn remove(&mut self, key: &Key) {
+        if let Some(&i) = self.index.get(key) {
+            self.remove_node(i);
+        }
+    }
+
+    /// Remove the least recently used entry, returning when it expires.
+    fn pop_lru(&mut self) -> Option<Instant> {
+        let i = self.tail?;
+        let expires = self.nodes[i].entry.expires;
+        self.remove_node(i);
+        Some(expires)
+    }
+}
+
+/// Bounded cache of the results of a `CredentialValidator`, so that
+/// expensive validation is only done once per credential within a TTL.
+///
+/// Both successful and failed validations are cached, with separate TTLs, so
+/// that repeated attempts with invalid credentials don't reach the validator
+/// either. Entries are keyed by a SHA-256 digest of the credentials, salted
+/// randomly for each cache, so the credentials themselves aren't retained.
+/// When the cache is full, the least recently used entry is evicted. Clones
+/// share the same cache.
+///
+/// The cache can be used from any authenticator by calling `validate`:
+///
+/// ```ignore
+/// let cache = CachedAuthenticator::new(|credentials: &AuthData| introspect(credentials))
+///     .with_ttl(Duration::from_secs(300));
+/// let authenticator = BasicAuthenticator::new(
+///     inner,
+///     move |username: &str, password: &str| cache.validate(&AuthData::basic(username, password)),
+///     "my-api",
+/// );
+/// ```
+pub struct CachedAuthenticator<V> {
+    validator: Arc<V>,
+    capacity: usize,
+    ttl: Duration,
+    negative_ttl: Duration,
+    salt: [u8; 16],
+    entries: Arc<Mutex<Lru>>,
+    counters: Arc<Counters>,
+}
+
+impl<V> Clone for CachedAuthenticator<V> {
+    fn clone(&self) -> Self {
+        Self {
+            validator: self.validator.clone(),
+            capacity: self.capacity,
+            ttl: self.ttl,
+            negative_ttl: self.negative_ttl,
+            salt: self.salt,
+            entries: self.entries.clone(),
+            counters: self.counters.clone(),
+        }
+    }
+}
+
+impl<V> fmt::Debug for CachedAuthenticator<V> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("CachedAuthenticator")
+            .field("capacity", &self.capacity)
+            .field("ttl", &self.ttl)
+            .field("negative_ttl", &self.negative_ttl)
+            .field("stats", &self.stats())
+            .finish()
+    }
+}
+
+impl<V: CredentialValidator + 'static> CachedAuthenticator<V> {
+    /// Create a cache of up to 10,000 entries, caching successful validations
+    /// for 60 seconds and failed validations for 10 seconds.
+    pub fn new(validator: V) -> Self {
+        CachedAuthenticator {
+            validator: Arc::new(validator),
+            capacity: 10_000,
+            ttl: Duration::from_secs(60),
+            negative_ttl: Duration::from_secs(10),
+            salt: salt(),
+            entries: Arc::new(Mutex::new(Lru::default())),
+            counters: Arc::new(Counters::default()),
+        }
+    }
+
+    /// Set the maximum number of entries in the cache.
+    pub fn with_capacity(mut self, capacity: usize) -> Self {
+        self.capacity = capacity;
+        self
+    }
+
+    /// Set how long successful validations are cached.
+    pub fn with_ttl<D: Into<Duration>>(mut self, ttl: D) -> Self {
+        self.ttl = ttl.into();
+        self
+    }
+
+    /// Set how long failed validations are cached.
+    pub fn with_negative_ttl<D: Into<Duration>>(mut self, ttl: D) -> Self {
+        self.negative_ttl = ttl.into();
+        self
+    }
+
+    /// Validate the credentials, using the cached result if there is one.
+    pub fn validate(&self, credentials: &AuthData) -> BoxFuture<'static, Option<Authorization>> {
+        let key = self.key(credentials);
+        let now = Instant::now();
+
+        let mut entries = self.lock();
+        match entries.get(&key) {
+            Some(entry) if entry.expires > now => {
+                let counter = match entry.authorization {
+                    Some(_) => &self.counters.hits,
+                    None => &self.counters.negative_hits,
+                };
+                counter.fetch_add(1, Ordering::Relaxed);
+                return futures::future::ready(entry.authorization.clone()).boxed();
+            }
+            Some(_) => entries.remove(&key),
+            None => {}
+        }
+        drop(entries);
+
+        self.counters.misses.fetch_add(1, Ordering::Relaxed);
+        let cache = self.clone();
+        self.validator
+            .validate(credentials)
+            .map(move |authorization| {
+                cache.insert(key, authorization.clone());
+                authorization
+            })
+            .boxed()
+    }
+
+    fn insert(&self, key: Key, authorization: Option<Authorization>) {
+        let ttl = match authorization {
+            Some(_) => self.ttl,
+            None => self.negative_ttl,
+        };
+        if self.capacity == 0 || ttl.is_zero() {
+            return;
+        }
+
+        let now = Instant::now();
+        let mut entries = self.lock();
+        if !entries.index.contains_key(&key) {
+            while entries.len() >= self.capacity {
+                match entries.pop_lru() {
+                    Some(expires) if expires > now => {
+                        self.counters.evictions.fetch_add(1, Ordering::Relaxed);
+                    }
+                    Some(_) => {}
+                    None => break,
+                }
+            }
+        }
+        entries.insert(
+            key,
+            Entry {
+                authorization,
+                expires: now + ttl,
+            },
+        );
+    }
+
+    /// Remove the cached result for the given credentials, for example after
+    /// they have been revoked.
+    pub fn invalidate(&self, credentials: &AuthData) {
+        let key = self.key(credentials);
+        self.lock().remove(&key);
+    }
+
+    /// Remove all cached results.
+    pub fn clear(&self) {
+        *self.lock() = Lru::default();
+    }
+}
+
+impl<V: CredentialValidator + 'static> CredentialValidator for CachedAuthenticator<V> {
+    type Future = BoxFuture<'static, Option<Authorization>>;
+
+    fn validate(&self, credentials: &AuthData) -> Self::Future {
+        CachedAuthenticator::validate(self, credentials)
+    }
+}
+
+impl<V> CachedAuthenticator<V> {
+    /// Digest of the credentials, with each part prefixed by its length so
+    /// that different credentials can't produce the same input.
+    fn key(&self, credentials: &AuthData) -> Key {
+        let (scheme, name, secret) = match credentials {
+            AuthData::Basic(username, password) => ("Basic", username.as_str(), password),
+            AuthData::Bearer(token) => ("Bearer", "", token),
+            AuthData::ApiKey(key) => ("ApiKey", "", key),
+            AuthData::Digest(username, password) => ("Digest", username.as_str(), password),
+            AuthData::Other(scheme, credentials) => ("Other", scheme.as_str(), credentials),
+        };
+
+        let mut hasher = Sha256::new();
+        hasher.update(self.salt);
+        for part in [scheme, name, secret.expose_secret()] {
+            hasher.update((part.len() as u64).to_be_bytes());
+            hasher.update(part);
+        }
+        hasher.finalize().into()
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Lru> {
+        self.entries.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Statistics of lookups in the cache since it was created.
+    pub fn stats(&self) -> AuthCacheStats {
+        AuthCacheStats {
+            hits: self.counters.hits.load(Ordering::Relaxed),
+            negative_hits: self.counters.negative_hits.load(Ordering::Relaxed),
+            misses: self.counters.misses.load(Ordering::Relaxed),
+            evictions: self.counters.evictions.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// Random salt for the keys of a cache.
+fn salt() -> [u8; 16] {
+    let state = RandomState::new();
+    let mut salt = [0; 16];
+    salt[..8].copy_from_slice(&state.hash_one(0u8).to_ne_bytes());
+    salt[8..].copy_from_slice(&state.hash_one(1u8).to_ne_bytes());
+    salt
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::auth::Scopes;
+    use std::sync::atomic::AtomicUsize;
+
+    fn cache(calls: Arc<AtomicUsize>) -> CachedAuthenticator<impl CredentialValidator> {
+        CachedAuthenticator::new(move |credentials: &AuthData| {
+            calls.fetch_add(1, Ordering::SeqCst);
+            let authorization = match credentials {
+                AuthData::Bearer(token) if token.expose_secret().starts_with("valid") => {
+                    Some(Authorization::new(token.expose_secret(), Scopes::All))
+                }
+                _ => None,
+            };
+            futures::future::ready(authorization)
+        })
+    }
+
+    #[tokio::test]
+    async fn test_cached_authenticator() {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let cache = cache(calls.clone());
+        let valid = AuthData::Bearer("valid".into());
+        let invalid = AuthData::Bearer("invalid".into());
+
+        for _ in 0..3 {
+            assert_eq!(cache.validate(&valid).await.unwrap().subject, "valid");
+            assert!(cache.validate(&invalid).await.is_none());
+        }
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+        assert_eq!(
+            cache.stats(),
+            AuthCacheStats {
+                hits: 2,
+                negative_hits: 2,
+                misses: 2,
+                evictions: 0,
+            }
+        );
+
+        cache.invalidate(&valid);
+        assert!(cache.validate(&valid).await.is_some());
+        assert_eq!(calls.load(Ordering::SeqCst), 3);
+    }
+
+    #[tokio::test]
+    async fn test_expiry_and_eviction() {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let cache = cache(calls.clone())
+            .with_capacity(2)
+            .with_negative_ttl(Duration::ZERO);
+
+        // Failed validations aren't cached with a zero TTL.
+        let invalid = AuthData::Bearer("invalid".into());
+        cache.validate(&invalid).await;
+        cache.validate(&invalid).await;
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+
+        for token in ["valid-1", "valid-2", "valid-3"] {
+            cache.validate(&AuthData::Bearer(token.into())).await;
+        }
+        assert_eq!(cache.stats().evictions, 1);
+        assert_eq!(cache.lock().len(), 2);
+
+        // The least recently used entry was evicted.
+        cache.validate(&AuthData::Bearer("valid-3".into())).await;
+        assert_eq!(calls.load(Ordering::SeqCst), 5);
+        cache.validate(&AuthData::Bearer("valid-1".into())).await;
+        assert_eq!(calls.load(Ordering::SeqCst), 6);
+        cache.validate(&AuthData::Bearer("valid-3".into())).await;
+        assert_eq!(calls.load(Ordering::SeqCst), 6);
+        cache.validate(&AuthData::Bearer("valid-2".into())).await;
+        assert_eq!(calls.load(Ordering::SeqCst), 7);
+        assert_eq!(cache.stats().evictions, 3);
+    }
+
+    #[test]
+    fn test_keys() {
+        let cache = cache(Arc::default());
+        let keys = [
+            AuthData::basic("ab", "c"),
+            AuthData::basic("a", "bc"),
+            AuthData::Bearer("abc".into()),
+            AuthData::ApiKey("abc".into()),
+            AuthData::Digest("ab".into(), "c".into()),
+        ]
+        .map(|credentials| cache.key(&credentials));
+        for (i, key) in keys.iter().enumerate() {
+            assert!(!keys[..i].contains(key));
+        }
+
+        // Keys are salted differently for each cache.
+        let other = CachedAuthenticator::new(|_: &AuthData| futures::future::ready(None));
+        assert_ne!(other.key(&AuthData::basic("ab", "c")), keys[0]);
+    }
+}
@@ -0,0 +1,277 @@
+//! Builder for responses rejecting requests, with `WWW-Authenticate` challenges.
+use crate::ApiError;
+use hyper::header::{HeaderValue, WWW_AUTHENTICATE};
+use hyper::{Response, StatusCode};
+use std::fmt;
+
+/// Error code of a challenge, as defined in RFC 6750 section 3.1.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum BearerError {
+    /// The request is malformed, such as having credentials in more than one
+    /// place.
+    InvalidRequest,
+    /// The credentials are expired, revoked, malformed or otherwise invalid.
+    InvalidToken,
+    /// The credentials don't grant the privileges required by the request.
+    InsufficientScope,
+}
+
+impl BearerError {
+    /// Error code, as sent in the `error` parameter.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            BearerError::InvalidRequest => "invalid_request",
+            BearerError::InvalidToken => "invalid_token",
+            BearerError::InsufficientScope => "insufficient_scope",
+        }
+    }
+
+    /// Status code of responses with this error.
+    pub fn status(&self) -> StatusCode {
+        match self {
+            BearerError::InvalidRequest => StatusCode::BAD_REQUEST,
+            BearerError::InvalidToken => StatusCode::UNAUTHORIZED,
+            BearerError::InsufficientScope => StatusCode::FORBIDDEN,
+        }
+    }
+}
+
+impl fmt::Display for BearerError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// Challenge to authenticate, sent in the `WWW-Authenticate` header of
+/// responses rejecting a request, as defined in RFC 9110 section 11.6.1 and,
+/// for bearer tokens, RFC 6750 section 3.
+///
+/// Authenticators use this to reject requests consistently, and it is
+/// equally suitable for rejections in user code:
+///
+/// ```
+/// use swagger::auth::{BearerError, Challenge};
+/// use hyper::StatusCode;
+///
+/// let response: hyper::Response<String> = Challenge::bearer()
+///     .with_realm("pets")
+///     .with_error(BearerError::InsufficientScope)
+///     .with_scope(["pets:write"])
+///     .to_response();
+///
+/// assert_eq!(response.status(), StatusCode::FORBIDDEN);
+/// assert_eq!(
+///     response.headers()["WWW-Authenticate"],
+///     "Bearer realm=\"pets\", error=\"insufficient_scope\", scope=\"pets:write\""
+/// );
+/// ```
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Challenge {
+    scheme: String,
+    realm: Option<String>,
+    error: Option<BearerError>,
+    error_description: Option<String>,
+    scope: Vec<String>,
+    params: Vec<(String, String)>,
+}
+
+impl Challenge {
+    /// Challenge to authenticate using the given scheme.
+    pub fn new<S: Into<String>>(scheme: S) -> Self {
+        Challenge {
+            scheme: scheme.into(),
+            realm: None,
+            error: None,
+            error_description: None,
+            scope: Vec::new(),
+            params: Vec::new(),
+        }
+    }
+
+    /// Challenge to authenticate using HTTP Basic authentication.
+    pub fn basic() -> Self {
+        Challenge::new("Basic")
+    }
+
+    /// Challenge to authenticate using a bearer token.
+    pub fn bearer() -> Self {
+        Challenge::new("Bearer")
+    }
+
+    /// Set the protection space of the challenge.
+    pub fn with_realm<S: Into<String>>(mut self, realm: S) -> Self {
+        self.realm = Some(realm.into());
+        self
+    }
+
+    /// Set the error code explaining why the request was rejected.
+    ///
+    /// This also determines the status of the response.
+    pub fn with_error(mut self, error: BearerError) -> Self {
+        self.error = Some(error);
+        self
+    }
+
+    /// Set the human-readable explanation of the error.
+    pub fn with_error_description<S: Into<String>>(mut self, description: S) -> Self {
+        self.error_description = Some(description.into());
+        self
+    }
+
+    /// Set the scopes required by the request.
+    pub fn with_scope<I, S>(mut self, scope: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.scope = scope.into_iter().map(Into::into).collect();
+        self
+    }
+
+    /// Add a further parameter to the challenge.
+    pub fn with_param<N: Into<String>, V: Into<String>>(mut self, name: N, value: V) -> Self {
+        self.params.push((name.into(), value.into()));
+        self
+    }
+
+    /// Authentication scheme of the challenge.
+    pub fn scheme(&self) -> &str {
+        &self.scheme
+    }
+
+    /// Error code of the challenge, if any.
+    pub fn error(&self) -> Option<BearerError> {
+        self.error
+    }
+
+    /// Status of responses with this challenge - that of the error code, or
+    /// `401 Unauthorized` if there is none.
+    pub fn status(&self) -> StatusCode {
+        self.error
+            .map_or(StatusCode::UNAUTHORIZED, |error| error.status())
+    }
+
+    /// Add the challenge to the `WWW-Authenticate` headers of the response,
+    /// and set its status.
+    ///
+    /// A challenge which isn't a valid header value, because of control
+    /// characters in its parameters, is omitted.
+    pub fn apply<B>(&self, response: &mut Response<B>) {
+        *response.status_mut() = self.status();
+        if let Ok(challenge) = HeaderValue::try_from(self) {
+            response.headers_mut().append(WWW_AUTHENTICATE, challenge);
+        }
+    }
+
+    /// Build a response rejecting the request with this challenge.
+    pub fn to_response<B: Default>(&self) -> Response<B> {
+        let mut response = Response::new(B::default());
+        self.apply(&mut response);
+        response
+    }
+}
+
+/// Write a parameter value as a quoted string.
+fn write_quoted(f: &mut fmt::Formatter<'_>, value: &str) -> fmt::Result {
+    f.write_str("\"")?;
+    for c in value.chars() {
+        if c == '"' || c == '\\' {
+            f.write_str("\\")?;
+        }
+        write!(f, "{}", c)?;
+    }
+    f.write_str("\"")
+}
+
+impl fmt::Display for Challenge {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.scheme)?;
+
+        let scope = self.scope.join(" ");
+        let params = self
+            .realm
+            .as_deref()
+            .map(|realm| ("realm", realm))
+            .into_iter()
+            .chain(self.params.iter().map(|(n, v)| (n.as_str(), v.as_str())))
+            .chain(self.error.map(|error| ("error", error.as_str())))
+            .chain(
+                self.error_description
+                    .as_deref()
+                    .map(|description| ("error_description", description)),
+            )
+            .chain(Some(("scope", scope.as_str())).filter(|_| !self.scope.is_empty()));
+
+        for (index, (name, value)) in params.enumerate() {
+            f.write_str(if index == 0 { " " } else { ", " })?;
+            write!(f, "{}=", name)?;
+            write_quoted(f, value)?;
+        }
+        Ok(())
+    }
+}
+
+impl TryFrom<&Challenge> for HeaderValue {
+    type Error = ApiError;
+
+    fn try_from(challenge: &Challenge) -> Result<Self, Self::Error> {
+        HeaderValue::try_from(challenge.to_string())
+            .map_err(|e| ApiError(format!("Invalid challenge {}: {}", challenge, e)))
+    }
+}
+
+impl TryFrom<Challenge> for HeaderValue {
+    type Error = ApiError;
+
+    fn try_from(challenge: Challenge) -> Result<Self, Self::Error> {
+        HeaderValue::try_from(&challenge)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_display() {
+        assert_eq!(Challenge::bearer().to_string(), "Bearer");
+        assert_eq!(
+            Challenge::basic()
+                .with_realm("say \"hi\"")
+                .with_param("charset", "UTF-8")
+                .to_string(),
+            "Basic realm=\"say \\\"hi\\\"\", charset=\"UTF-8\""
+        );
+        assert_eq!(
+            Challenge::bearer()
+                .with_error(BearerError::InvalidToken)
+                .with_error_description("The access token expired")
+                .with_scope(["pets:read", "pets:write"])
+                .to_string(),
+            "Bearer error=\"invalid_token\", error_description=\"The access token expired\", \
+             scope=\"pets:read pets:write\""
+        );
+    }
+
+    #[test]
+    fn test_response() {
+        let response: Response<()> = Challenge::bearer().to_response();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        assert_eq!(response.headers()[WWW_AUTHENTICATE], "Bearer");
+
+        for (error, status) in [
+            (BearerError::InvalidRequest, StatusCode::BAD_REQUEST),
+            (BearerError::InvalidToken, StatusCode::UNAUTHORIZED),
+            (BearerError::InsufficientScope, StatusCode::FORBIDDEN),
+        ] {
+            let response: Response<()> = Challenge::bearer().with_error(error).to_response();
+            assert_eq!(response.status(), status);
+        }
+
+        let response: Response<()> = Challenge::bearer()
+            .with_error_description("Line\nbreak")
+            .to_response();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        assert!(!response.headers().contains_key(WWW_AUTHENTICATE));
+    }
+}
@@ -0,0 +1,203 @@
+//! Declarative configuration of the security requirements of an API.
+use super::{AuthMode, AuthModePolicy, ScopePolicy};
+use crate::ApiError;
+use hyper::Method;
+use serde::Deserialize;
+use std::collections::BTreeMap;
+
+/// Scopes required for requests under a path prefix.
+#[derive(Clone, Debug, Deserialize, PartialEq, Eq)]
+pub struct PathSecurity {
+    /// Path prefix, matched on whole path segments.
+    pub prefix: String,
+    /// Method to which the requirement applies, or all methods if not given.
+    #[serde(default)]
+    pub method: Option<String>,
+    /// Scopes required.
+    #[serde(default)]
+    pub scopes: Vec<String>,
+}
+
+/// Security requirements of an API, typically loaded from a configuration file.
+///
+/// This maps operation IDs and path prefixes to the scopes they require, and
+/// lists the audiences accepted in bearer tokens, so that the `security`
+/// requirements of an OpenAPI spec can be enforced from one table.
+///
+/// ```json
+/// {
+///   "audiences": ["https://api.example.com"],
+///   "operations": {
+///     "deletePet": ["pets:write"]
+///   },
+///   "optional": ["listPets"],
+///   "paths": [
+///     { "prefix": "/pets", "scopes": ["pets:read"] },
+///     { "prefix": "/pets", "method": "POST", "scopes": ["pets:write"] }
+///   ]
+/// }
+/// ```
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub struct SecurityConfig {
+    /// Audiences accepted in bearer tokens. Any audience is accepted if empty.
+    #[serde(default)]
+    pub audiences: Vec<String>,
+    /// Scopes required by each operation, keyed by operation ID.
+    #[serde(default)]
+    pub operations: BTreeMap<String, Vec<String>>,
+    /// Scopes required for requests under path prefixes.
+    #[serde(default)]
+    pub paths: Vec<PathSecurity>,
+    /// Operations which may be called without credentials, keyed by operation
+    /// ID, as for operations listing an empty security requirement in the spec.
+    #[serde(default)]
+    pub optional: Vec<String>,
+}
+
+impl SecurityConfig {
+    /// Build the `ScopePolicy` to be enforced by a `ScopeEnforcer`.
+    ///
+    /// The operations of the API are given as `(operation ID, method, path
+    /// template)`, as generated from the spec. Fails if the configuration
+    /// refers to an unknown operation or invalid method.
+    pub fn scope_policy<'a, I>(&self, operations: I) -> Result<ScopePolicy, ApiError>
+    where
+        I: IntoIterator<Item = (&'a str, Method, &'a str)>,
+    {
+        let mut policy = ScopePolicy::new();
+
+        for path in &self.paths {
+            policy = match path.method {
+                Some(ref method) => {
+                    let method = method
+                        .parse()
+                        .map_err(|_| ApiError(format!("Invalid method {}", method)))?;
+                    policy.require_for(method, path.prefix.as_str(), path.scopes.iter().cloned())
+                }
+                None => policy.require(path.prefix.as_str(), path.scopes.iter().cloned()),
+            };
+        }
+
+        let mut unknown = self.operations.keys().collect::<Vec<_>>();
+        for (operation_id, method, path) in operations {
+            if let Some(scopes) = self.operations.get(operation_id) {
+                unknown.retain(|id| *id != operation_id);
+                policy = policy.require_operation(method, path, scopes.iter().cloned());
+            }
+        }
+
+        match unknown.first() {
+            Some(operation_id) => Err(ApiError(format!("Unknown operation {}", operation_id))),
+            None => Ok(policy),
+        }
+    }
+
+    /// Build the `AuthModePolicy` to be used by authenticators, under which
+    /// credentials are optional for the listed operations.
+    ///
+    /// The operations of the API are given as for `scope_policy`. Fails if the
+    /// configuration refers to an unknown operation.
+    pub fn auth_modes<'a, I>(&self, operations: I) -> Result<AuthModePolicy, ApiError>
+    where
+        I: IntoIterator<Item = (&'a str, Method, &'a str)>,
+    {
+        let mut modes = AuthModePolicy::new(AuthMode::Required);
+
+        let mut unknown = self.optional.iter().collect::<Vec<_>>();
+        for (operation_id, method, path) in operations {
+            if self.optional.iter().any(|id| id == operation_id) {
+                unknown.retain(|id| *id != operation_id);
+                modes = modes.operation(method, path, AuthMode::Optional);
+            }
+        }
+
+        match unknown.first() {
+            Some(operation_id) => Err(ApiError(format!("Unknown operation {}", operation_id))),
+            None => Ok(modes),
+        }
+    }
+
+    /// Audiences accepted in bearer tokens, for passing to a token verifier,
+    /// or `None` if any audience is accepted.
+    pub fn audiences(&self) -> Option<&[String]> {
+        if self.audiences.is_empty() {
+            None
+        } else {
+            Some(&self.audiences)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const OPERATIONS: [(&str, Method, &str); 2] = [
+        ("getPet", Method::GET, "/pets/{petId}"),
+        ("deletePet", Method::DELETE, "/pets/{petId}"),
+    ];
+
+    #[test]
+    fn test_scope_policy() {
+        let config: SecurityConfig = serde_json::from_str(
+            r#"{
+                "audiences": ["https://api.example.com"],
+                "operations": { "deletePet": ["pets:write"] },
+                "paths": [
+                    { "prefix": "/pets", "scopes": ["pets:read"] },
+                    { "prefix": "/pets", "method": "POST", "scopes": ["pets:write"] }
+                ]
+            }"#,
+        )
+        .unwrap();
+        assert_eq!(
+            config.audiences(),
+            Some(&["https://api.example.com".to_string()][..])
+        );
+
+        let policy = config.scope_policy(OPERATIONS).unwrap();
+        let scopes = |method, path| {
+            policy
+                .required_scopes(&method, path)
+                .map(|s| s.iter().cloned().collect::<Vec<_>>())
+        };
+        assert_eq!(
+            scopes(Method::GET, "/pets/1"),
+            Some(vec!["pets:read".into()])
+        );
+        assert_eq!(
+            scopes(Method::POST, "/pets"),
+            Some(vec!["pets:write".into()])
+        );
+        assert_eq!(
+            scopes(Method::DELETE, "/pets/1"),
+            Some(vec!["pets:write".into()])
+        );
+    }
+
+    #[test]
+    fn test_auth_modes() {
+        let config: SecurityConfig = serde_json::from_str(r#"{ "optional": ["getPet"] }"#).unwrap();
+        let modes = config.auth_modes(OPERATIONS).unwrap();
+        assert_eq!(modes.mode(&Method::GET, "/pets/1"), AuthMode::Optional);
+        assert_eq!(modes.mode(&Method::DELETE, "/pets/1"), AuthMode::Required);
+
+        let config: SecurityConfig =
+            serde_json::from_str(r#"{ "optional": ["listPets"] }"#).unwrap();
+        assert!(config.auth_modes(OPERATIONS).is_err());
+    }
+
+    #[test]
+    fn test_invalid_config() {
+        let config: SecurityConfig =
+            serde_json::from_str(r#"{ "operations": { "deletePets": [] } }"#).unwrap();
+        assert!(config.scope_policy(OPERATIONS).is_err());
+
+        let config: SecurityConfig =
+            serde_json::from_str(r#"{ "paths": [{ "prefix": "/", "method": "G T" }] }"#).unwrap();
+        assert!(config.scope_policy(OPERATIONS).is_err());
+
+        assert!(serde_json::from_str::<SecurityConfig>(r#"{ "audience": [] }"#).is_err());
+    }
+}
@@ -0,0 +1,270 @@
+//! Authenticator middleware wrapping user-provided authentication logic.
+use super::audit::Auditor;
+use super::{AuditSink, AuthData, Authorization, RcBound};
+use crate::context::Has;
+use crate::XSpanIdString;
+use futures::future::{BoxFuture, FutureExt};
+use hyper::service::Service;
+use hyper::{Request, Response};
+use std::future::Future;
+use std::marker::PhantomData;
+
+/// Authenticator which runs a user-provided function on each request.
+#[derive(Debug)]
+pub struct MakeFnAuthenticator<T, F, RC>
+where
+    RC: RcBound,
+    RC::Result: Send + 'static,
+{
+    inner: T,
+    authenticate: F,
+    audit: Auditor,
+    marker: PhantomData<RC>,
+}
+
+impl<T, F, RC> MakeFnAuthenticator<T, F, RC>
+where
+    RC: RcBound,
+    RC::Result: Send + 'static,
+{
+    /// Create a middleware that authenticates requests using the given function.
+    pub fn new(inner: T, authenticate: F) -> Self {
+        MakeFnAuthenticator {
+            inner,
+            authenticate,
+            audit: Auditor::default(),
+            marker: PhantomData,
+        }
+    }
+
+    /// Record each decision to accept or deny a request in the given sink.
+    pub fn with_audit_sink<S: AuditSink + 'static>(mut self, sink: S) -> Self {
+        self.audit = Auditor::new(sink);
+        self
+    }
+}
+
+impl<Inner, F, RC, Target> Service<Target> for MakeFnAuthenticator<Inner, F, RC>
+where
+    RC: RcBound,
+    RC::Result: Send + 'static,
+    Inner: Service<Target>,
+    Inner::Future: Send + 'static,
+    F: Clone + Send + 'static,
+{
+    type Error = Inner::Error;
+    type Response = FnAuthenticator<Inner::Response, F, RC>;
+    type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn call(&self, target: Target) -> Self::Future {
+        let authenticate = self.authenticate.clone();
+        let audit = self.audit.clone();
+        Box::pin(self.inner.call(target).map(|s| {
+            Ok(FnAuthenticator {
+                inner: s?,
+                authenticate,
+                audit,
+                marker: PhantomData,
+            })
+        }))
+    }
+}
+
+/// Authenticator which runs a user-provided function on each request.
+///
+/// The function is passed the `Option<AuthData>` from the context, as
+/// extracted from the request by earlier middleware, along with the request
+/// itself. It returns the authorization to push to the context, which may be
+/// `None` for unauthenticated requests, or a response with which to reject
+/// the request.
+///
+/// ```ignore
+/// let authenticator = FnAuthenticator::new(inner, |auth_data: Option<AuthData>, _: &Request<_>| {
+///     let lookup = auth_data.map(|auth_data| sessions.lookup(auth_data));
+///     async move {
+///         match lookup {
+///             Some(lookup) => lookup.await.map(Some).ok_or_else(|| basic_challenge("my-api")),
+///             None => Ok(None),
+///         }
+///     }
+/// });
+/// ```
+#[derive(Debug)]
+pub struct FnAuthenticator<T, F, RC>
+where
+    RC: RcBound,
+    RC::Result: Send + 'static,
+{
+    inner: T,
+    authenticate: F,
+    audit: Auditor,
+    marker: PhantomData<RC>,
+}
+
+impl<T, F, RC> FnAuthenticator<T, F, RC>
+where
+    RC: RcBound,
+    RC::Result: Send + 'static,
+{
+    /// Create a middleware that authenticates requests using the given function.
+    pub fn new(inner: T, authenticate: F) -> Self {
+        FnAuthenticator {
+            inner,
+            authenticate,
+            audit: Auditor::default(),
+            marker: PhantomData,
+        }
+    }
+
+    /// Record each decision to accept or deny a request in the given sink.
+    pub fn with_audit_sink<S: AuditSink + 'static>(mut self, sink: S) -> Self {
+        self.audit = Auditor::new(sink);
+        self
+    }
+}
+
+impl<T, F, RC> Clone for FnAuthenticator<T, F, RC>
+where
+    T: Clone,
+    F: Clone,
+    RC: RcBound,
+    RC::Result: Send + 'static,
+{
+    fn clone(&self) -> Self {
+        Self {
+            inner: self.inner.clone(),
+            authenticate: self.authenticate.clone(),
+            audit: self.audit.clone(),
+            marker: PhantomData,
+        }
+    }
+}
+
+impl<T, F, Fut, B, ResBody, RC> Service<(Request<B>, RC)> for FnAuthenticator<T, F, RC>
+where
+    RC: RcBound + Has<Option<AuthData>> + Has<XSpanIdString>,
+    RC::Result: Send + 'static,
+    T: Service<(Request<B>, RC::Result), Response = Response<ResBody>> + Clone + Send + 'static,
+    T::Future: Send + 'static,
+    F: Fn(Option<AuthData>, &Request<B>) -> Fut,
+    Fut: Future<Output = Result<Option<Authorization>, Response<ResBody>>> + Send + 'static,
+    B: Send + 'static,
+    ResBody: Send + 'static,
+{
+    type Response = Response<ResBody>;
+    type Error = T::Error;
+    type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn call(&self, req: (Request<B>, RC)) -> Self::Future {
+        let (request, context) = req;
+        let span_id = Has::<XSpanIdString>::get(&context).clone();
+        let auth_data = Has::<Option<AuthData>>::get(&context).clone();
+        let scheme = auth_data.as_ref().map(|a| a.scheme().to_string());
+        let authentication = (self.authenticate)(auth_data, &request);
+        let inner = self.inner.clone();
+        let audit = self.audit.clone();
+
+        Box::pin(async move {
+            match authentication.await {
+                Ok(authorization) => {
+                    audit.accept(
+                        &request,
+                        &span_id,
+                        scheme.as_deref(),
+                        authorization.as_ref(),
+                    );
+                    let context = context.push(authorization);
+                    inner.call((request, context)).await
+                }
+                Err(response) => {
+                    let reason = format!("Rejected with status {}", response.status());
+                    audit.deny(&request, &span_id, scheme.as_deref(), None, &reason);
+                    Ok(response)
+                }
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::auth::audit::tests::TestSink;
+    use crate::auth::{AuditOutcome, Scopes};
+    use crate::context::{ContextBuilder, Push};
+    use crate::EmptyContext;
+    use hyper::StatusCode;
+
+    type InputContext =
+        ContextBuilder<Option<AuthData>, ContextBuilder<XSpanIdString, EmptyContext>>;
+    type Context = ContextBuilder<Option<Authorization>, InputContext>;
+
+    #[derive(Clone)]
+    struct SubjectService;
+
+    impl Service<(Request<()>, Context)> for SubjectService {
+        type Response = Response<String>;
+        type Error = ();
+        type Future = futures::future::Ready<Result<Self::Response, Self::Error>>;
+
+        fn call(&self, req: (Request<()>, Context)) -> Self::Future {
+            let auth: &Option<Authorization> = req.1.get();
+            futures::future::ok(Response::new(
+                auth.as_ref()
+                    .map_or("anonymous".to_string(), |auth| auth.subject.clone()),
+            ))
+        }
+    }
+
+    #[tokio::test]
+    async fn test_fn_authenticator() {
+        let sink = TestSink::default();
+        let authenticator = FnAuthenticator::<_, _, InputContext>::new(
+            SubjectService,
+            |auth_data: Option<AuthData>, request: &Request<()>| {
+                let result = match &auth_data {
+                    Some(AuthData::ApiKey(key)) if key == "key" => Ok(Some(Authorization::new(
+                        request.uri().path().to_string(),
+                        Scopes::All,
+                    ))),
+                    Some(_) => {
+                        let mut response = Response::new(String::new());
+                        *response.status_mut() = StatusCode::FORBIDDEN;
+                        Err(response)
+                    }
+                    None => Ok(None),
+                };
+                futures::future::ready(result)
+            },
+        )
+        .with_audit_sink(sink.clone());
+        let call = |auth_data: Option<AuthData>| {
+            let context = EmptyContext
+                .push(XSpanIdString("span".to_string()))
+                .push(auth_data);
+            authenticator.call((Request::get("/pets").body(()).unwrap(), context))
+        };
+
+        let response = call(Some(AuthData::apikey("key"))).await.unwrap();
+        assert_eq!(response.into_body(), "/pets");
+
+        let response = call(None).await.unwrap();
+        assert_eq!(response.into_body(), "anonymous");
+
+        let response = call(Some(AuthData::apikey("wrong"))).await.unwrap();
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+
+        let events = sink.take();
+        assert_eq!(events.len(), 3);
+        assert_eq!(events[0].span_id, "span");
+        assert_eq!(events[0].subject.as_deref(), Some("/pets"));
+        assert_eq!(events[0].scheme.as_deref(), Some("ApiKey"));
+        assert_eq!(events[1].outcome, AuditOutcome::Accepted);
+        assert_eq!(events[1].subject, None);
+        assert_eq!(events[2].outcome, AuditOutcome::Denied);
+        assert_eq!(
+            events[2].reason.as_deref(),
+            Some("Rejected with status 403 Forbidden")
+        );
+    }
+}
@@ -0,0 +1,331 @@
+//! Authenticator which fails closed, rejecting requests outside an allowlist.
+use super::audit::Auditor;
+use super::route::RouteTable;
+use super::{AuditSink, Authorization, RcBound};
+use crate::context::Has;
+use crate::XSpanIdString;
+use futures::future::{BoxFuture, FutureExt};
+use hyper::header::{HeaderValue, WWW_AUTHENTICATE};
+use hyper::service::Service;
+use hyper::{Method, Request, Response, StatusCode};
+use std::marker::PhantomData;
+use std::sync::Arc;
+
+#[derive(Clone, Debug)]
+struct DenyConfig {
+    challenge: HeaderValue,
+    exempt: RouteTable<()>,
+    audit: Auditor,
+}
+
+impl DenyConfig {
+    fn exempt(&mut self, prefix: String) {
+        self.exempt.insert(prefix, None, false, ());
+    }
+
+    fn is_exempt(&self, method: &Method, path: &str) -> bool {
+        self.exempt.get(method, path).is_some()
+    }
+}
+
+/// Authenticator which rejects all requests, except those to exempt paths.
+#[derive(Debug)]
+pub struct MakeDenyAllAuthenticator<T, RC>
+where
+    RC: RcBound,
+    RC::Result: Send + 'static,
+{
+    inner: T,
+    config: Arc<DenyConfig>,
+    marker: PhantomData<RC>,
+}
+
+impl<T, RC> MakeDenyAllAuthenticator<T, RC>
+where
+    RC: RcBound,
+    RC::Result: Send + 'static,
+{
+    /// Create a middleware that rejects requests with the given `WWW-Authenticate` challenge.
+    pub fn new(inner: T, challenge: HeaderValue) -> Self {
+        MakeDenyAllAuthenticator {
+            inner,
+            config: Arc::new(DenyConfig {
+                challenge,
+                exempt: RouteTable::default(),
+                audit: Auditor::default(),
+            }),
+            marker: PhantomData,
+        }
+    }
+
+    /// Exempt requests under the path prefix, which are passed on without authorization.
+    pub fn with_exempt<P: Into<String>>(mut self, prefix: P) -> Self {
+        Arc::make_mut(&mut self.config).exempt(prefix.into());
+        self
+    }
+
+    /// Record each decision to accept or deny a request in the given sink.
+    pub fn with_audit_sink<S: AuditSink + 'static>(mut self, sink: S) -> Self {
+        Arc::make_mut(&mut self.config).audit = Auditor::new(sink);
+        self
+    }
+}
+
+impl<Inner, RC, Target> Service<Target> for MakeDenyAllAuthenticator<Inner, RC>
+where
+    RC: RcBound,
+    RC::Result: Send + 'static,
+    Inner: Service<Target>,
+    Inner::Future: Send + 'static,
+{
+    type Error = Inner::Error;
+    type Response = DenyAllAuthenticator<Inner::Response, RC>;
+    type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn call(&self, target: Target) -> Self::Future {
+        let config = self.config.clone();
+        Box::pin(self.inner.call(target).map(|s| {
+            Ok(DenyAllAuthenticator {
+                inner: s?,
+                config,
+                marker: PhantomData,
+            })
+        }))
+    }
+}
+
+/// Authenticator which rejects all requests, except those to exempt paths.
+///
+/// This allows a server to fail closed, for example while no authentication
+/// has been configured, or as the last of a chain of authenticators. Requests
+/// already authorized by an earlier authenticator are passed on with their
+/// authorization. Requests to exempt paths, such as health checks, have `None`
+/// pushed to the context as their authorization, and are passed on. All other
+/// requests are rejected with `401 Unauthorized`, carrying the configured
+/// `WWW-Authenticate` challenge.
+///
+/// ```ignore
+/// let authenticator = DenyAllAuthenticator::new(inner, HeaderValue::from_static("Bearer"))
+///     .with_exempt("/healthz")
+///     .with_exempt("/metrics");
+/// ```
+#[derive(Debug)]
+pub struct DenyAllAuthenticator<T, RC>
+where
+    RC: RcBound,
+    RC::Result: Send + 'static,
+{
+    inner: T,
+    config: Arc<DenyConfig>,
+    marker: PhantomData<RC>,
+}
+
+impl<T, RC> DenyAllAuthenticator<T, RC>
+where
+    RC: RcBound,
+    RC::Result: Send + 'static,
+{
+    /// Create a middleware that rejects requests with the given `WWW-Authenticate` challenge.
+    pub fn new(inner: T, challenge: HeaderValue) -> Self {
+        DenyAllAuthenticator {
+            inner,
+            config: Arc::new(DenyConfig {
+                challenge,
+                exempt: RouteTable::default(),
+                audit: Auditor::default(),
+            }),
+            marker: PhantomData,
+        }
+    }
+
+    /// Exempt requests under the path prefix, which are passed on without authorization.
+    pub fn with_exempt<P: Into<String>>(mut self, prefix: P) -> Self {
+        Arc::make_mut(&mut self.config).exempt(prefix.into());
+        self
+    }
+
+    /// Record each decision to accept or deny a request in the given sink.
+    pub fn with_audit_sink<S: AuditSink + 'static>(mut self, sink: S) -> Self {
+        Arc::make_mut(&mut self.config).audit = Auditor::new(sink);
+        self
+    }
+}
+
+impl<T, RC> Clone for DenyAllAuthenticator<T, RC>
+where
+    T: Clone,
+    RC: RcBound,
+    RC::Result: Send + 'static,
+{
+    fn clone(&self) -> Self {
+        Self {
+            inner: self.inner.clone(),
+            config: self.config.clone(),
+            marker: PhantomData,
+        }
+    }
+}
+
+impl<T, B, ResBody, RC> Service<(Request<B>, RC)> for DenyAllAuthenticator<T, RC>
+where
+    RC: RcBound + Has<Option<Authorization>> + Has<XSpanIdString>,
+    RC::Result: Send + 'static,
+    T: Service<(Request<B>, RC::Result), Response = Response<ResBody>>,
+    T::Future: Send + 'static,
+    T::Error: Send + 'static,
+    ResBody: Default + Send + 'static,
+{
+    type Response = Response<ResBody>;
+    type Error = T::Error;
+    type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn call(&self, req: (Request<B>, RC)) -> Self::Future {
+        let (request, context) = req;
+        let span_id = Has::<XSpanIdString>::get(&context);
+
+        if let Some(authorization) = Has::<Option<Authorization>>::get(&context) {
+            let authorization = authorization.clone();
+            self.config
+                .audit
+                .accept(&request, span_id, None, Some(&authorization));
+            let context = context.push(Some(authorization));
+            return Box::pin(self.inner.call((request, context)));
+        }
+
+        if self
+            .config
+            .is_exempt(request.method(), request.uri().path())
+        {
+            self.config.audit.accept(&request, span_id, None, None);
+            let context = context.push(None);
+            return Box::pin(self.inner.call((request, context)));
+        }
+
+        self.config
+            .audit
+            .deny(&request, span_id, None, None, "Not exempt");
+
+        let mut response = Response::new(ResBody::default());
+        *response.status_mut() = StatusCode::UNAUTHORIZED;
+        response
+            .headers_mut()
+            .insert(WWW_AUTHENTICATE, self.config.challenge.clone());
+        Box::pin(futures::future::ok(response))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::auth::audit::tests::TestSink;
+    use crate::auth::{AuditOutcome, Scopes};
+    use crate::context::{ContextBuilder, Push};
+    use crate::EmptyContext;
+
+    type Context =
+        ContextBuilder<Option<Authorization>, ContextBuilder<XSpanIdString, EmptyContext>>;
+
+    fn request(path: &str) -> (Request<()>, Context) {
+        authorized_request(path, None)
+    }
+
+    fn authorized_request(
+        path: &str,
+        authorization: Option<Authorization>,
+    ) -> (Request<()>, Context) {
+        let context = EmptyContext
+            .push(XSpanIdString("span".to_string()))
+            .push(authorization);
+        (Request::get(path).body(()).unwrap(), context)
+    }
+
+    #[derive(Clone)]
+    struct OkService;
+
+    impl Service<(Request<()>, ContextBuilder<Option<Authorization>, Context>)> for OkService {
+        type Response = Response<String>;
+        type Error = ();
+        type Future = futures::future::Ready<Result<Self::Response, Self::Error>>;
+
+        fn call(
+            &self,
+            req: (Request<()>, ContextBuilder<Option<Authorization>, Context>),
+        ) -> Self::Future {
+            let authorization = Has::<Option<Authorization>>::get(&req.1);
+            futures::future::ok(Response::new(
+                authorization
+                    .as_ref()
+                    .map_or("anonymous".to_string(), |a| a.subject.clone()),
+            ))
+        }
+    }
+
+    #[tokio::test]
+    async fn test_deny_all_authenticator() {
+        let sink = TestSink::default();
+        let authenticator = DenyAllAuthenticator::<_, Context>::new(
+            OkService,
+            HeaderValue::from_static("Bearer realm=\"test\""),
+        )
+        .with_exempt("/healthz")
+        .with_audit_sink(sink.clone());
+        let call = |path: &str| authenticator.call(request(path));
+
+        let response = call("/healthz").await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        for path in ["/pets", "/healthzz"] {
+            let response = call(path).await.unwrap();
+            assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+            assert_eq!(
+                response.headers().get(WWW_AUTHENTICATE).unwrap(),
+                "Bearer realm=\"test\""
+            );
+        }
+
+        let events = sink.take();
+        assert_eq!(events[0].outcome, AuditOutcome::Accepted);
+        assert_eq!(events[0].span_id, "span");
+        assert_eq!(events[0].reason.as_deref(), Some("No credentials"));
+        assert_eq!(events[1].outcome, AuditOutcome::Denied);
+        assert_eq!(events[1].path, "/pets");
+    }
+
+    #[tokio::test]
+    async fn test_authorized_requests() {
+        let sink = TestSink::default();
+        let authenticator =
+            DenyAllAuthenticator::<_, Context>::new(OkService, HeaderValue::from_static("Bearer"))
+                .with_audit_sink(sink.clone());
+
+        // Requests authorized by an earlier authenticator are passed on.
+        let authorization = Authorization::new("alice", Scopes::All);
+        let response = authenticator
+            .call(authorized_request("/pets", Some(authorization)))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.into_body(), "alice");
+
+        let response = authenticator.call(request("/pets")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+        let events = sink.take();
+        assert_eq!(events[0].outcome, AuditOutcome::Accepted);
+        assert_eq!(events[0].subject.as_deref(), Some("alice"));
+        assert_eq!(events[1].outcome, AuditOutcome::Denied);
+    }
+
+    #[tokio::test]
+    async fn test_configure_after_clone() {
+        let authenticator =
+            DenyAllAuthenticator::<_, Context>::new(OkService, HeaderValue::from_static("Bearer"));
+        let original = authenticator.clone();
+        let authenticator = authenticator.with_exempt("/healthz");
+
+        let response = authenticator.call(request("/healthz")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let response = original.call(request("/healthz")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    }
+}
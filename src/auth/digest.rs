@@ -0,0 +1,452 @@
+//! HTTP Digest authentication, as described in RFC 7616.
+//!
+//! This provides parsing and formatting of `WWW-Authenticate: Digest`
+//! challenges and `Authorization: Digest` responses, computation of responses
+//! by clients, and their verification by servers. Only the `auth` quality of
+//! protection is supported, along with the legacy RFC 2069 scheme for servers
+//! which offer no quality of protection.
+use super::secret::constant_time_eq;
+use hyper::header::{HeaderValue, AUTHORIZATION, WWW_AUTHENTICATE};
+use hyper::{HeaderMap, Method, Response, StatusCode};
+use md5::Md5;
+use sha2::{Digest, Sha256};
+use std::fmt;
+
+/// Hash algorithm used to compute a digest response.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum DigestAlgorithm {
+    /// MD5, the default for compatibility with legacy clients and servers.
+    #[default]
+    Md5,
+    /// MD5, with the session key including the nonces.
+    Md5Sess,
+    /// SHA-256.
+    Sha256,
+    /// SHA-256, with the session key including the nonces.
+    Sha256Sess,
+}
+
+impl DigestAlgorithm {
+    /// Name of the algorithm, as used in the `algorithm` parameter.
+    pub fn name(&self) -> &'static str {
+        match self {
+            DigestAlgorithm::Md5 => "MD5",
+            DigestAlgorithm::Md5Sess => "MD5-sess",
+            DigestAlgorithm::Sha256 => "SHA-256",
+            DigestAlgorithm::Sha256Sess => "SHA-256-sess",
+        }
+    }
+
+    fn parse(name: &str) -> Option<Self> {
+        [
+            DigestAlgorithm::Md5,
+            DigestAlgorithm::Md5Sess,
+            DigestAlgorithm::Sha256,
+            DigestAlgorithm::Sha256Sess,
+        ]
+        .into_iter()
+        .find(|algorithm| algorithm.name().eq_ignore_ascii_case(name))
+    }
+
+    fn hash(&self, data: &str) -> String {
+        let digest = match self {
+            DigestAlgorithm::Md5 | DigestAlgorithm::Md5Sess => Md5::digest(data).to_vec(),
+            DigestAlgorithm::Sha256 | DigestAlgorithm::Sha256Sess => Sha256::digest(data).to_vec(),
+        };
+        digest.iter().map(|b| format!("{:02x}", b)).collect()
+    }
+
+    fn is_session(&self) -> bool {
+        matches!(self, DigestAlgorithm::Md5Sess | DigestAlgorithm::Sha256Sess)
+    }
+}
+
+/// Split the parameters of a `Digest` header value into name-value pairs,
+/// unquoting quoted values.
+fn parse_params(value: &str) -> Option<Vec<(String, String)>> {
+    let (scheme, params) = value.trim_start().split_once(' ')?;
+    if !scheme.eq_ignore_ascii_case("digest") {
+        return None;
+    }
+
+    let mut chars = params.chars().peekable();
+    let mut params = Vec::new();
+    loop {
+        while chars.next_if(|c| c.is_whitespace() || *c == ',').is_some() {}
+        let name = std::iter::from_fn(|| chars.next_if(|c| *c != '=')).collect::<String>();
+        if name.is_empty() {
+            break;
+        }
+        chars.next()?;
+
+        let mut value = String::new();
+        if chars.next_if_eq(&'"').is_some() {
+            loop {
+                match chars.next()? {
+                    '"' => break,
+                    '\\' => value.push(chars.next()?),
+                    c => value.push(c),
+                }
+            }
+        } else {
+            value.extend(std::iter::from_fn(|| chars.next_if(|c| *c != ',')));
+        }
+        params.push((name.trim().to_ascii_lowercase(), value.trim().to_string()));
+    }
+    Some(params)
+}
+
+fn quote(value: &str) -> String {
+    format!("\"{}\"", value.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+/// Challenge issued by a server requiring Digest authentication, in a
+/// `WWW-Authenticate` header.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DigestChallenge {
+    /// Protection space, typically displayed to users.
+    pub realm: String,
+    /// Server-specified nonce, to be included in the response.
+    pub nonce: String,
+    /// Opaque data, to be returned unchanged in the response.
+    pub opaque: Option<String>,
+    /// Hash algorithm to be used.
+    pub algorithm: DigestAlgorithm,
+    /// Qualities of protection supported by the server, such as `auth`. If
+    /// empty, the legacy RFC 2069 response is used.
+    pub qop: Vec<String>,
+    /// Whether the challenge was issued because a previous nonce was stale,
+    /// in which case the client may retry without prompting for credentials.
+    pub stale: bool,
+}
+
+impl DigestChallenge {
+    /// Create a challenge for the given realm and nonce, using MD5 and the
+    /// `auth` quality of protection.
+    pub fn new<R: Into<String>, N: Into<String>>(realm: R, nonce: N) -> Self {
+        DigestChallenge {
+            realm: realm.into(),
+            nonce: nonce.into(),
+            opaque: None,
+            algorithm: DigestAlgorithm::default(),
+            qop: vec!["auth".to_string()],
+            stale: false,
+        }
+    }
+
+    /// Set the hash algorithm.
+    pub fn with_algorithm(mut self, algorithm: DigestAlgorithm) -> Self {
+        self.algorithm = algorithm;
+        self
+    }
+
+    /// Set the opaque data.
+    pub fn with_opaque<O: Into<String>>(mut self, opaque: O) -> Self {
+        self.opaque = Some(opaque.into());
+        self
+    }
+
+    /// Mark the challenge as being issued because the previous nonce was stale.
+    pub fn with_stale(mut self, stale: bool) -> Self {
+        self.stale = stale;
+        self
+    }
+
+    /// Parse a challenge from a `WWW-Authenticate` header value.
+    pub fn parse(value: &str) -> Option<Self> {
+        let (mut realm, mut nonce) = (None, None);
+        let mut challenge = DigestChallenge::new("", "");
+        challenge.qop.clear();
+
+        for (name, value) in parse_params(value)? {
+            match name.as_str() {
+                "realm" => realm = Some(value),
+                "nonce" => nonce = Some(value),
+                "opaque" => challenge.opaque = Some(value),
+                "algorithm" => challenge.algorithm = DigestAlgorithm::parse(&value)?,
+                "qop" => {
+                    challenge.qop = value
+                        .split(',')
+                        .map(|qop| qop.trim().to_string())
+                        .filter(|qop| !qop.is_empty())
+                        .collect()
+                }
+                "stale" => challenge.stale = value.eq_ignore_ascii_case("true"),
+                _ => {}
+            }
+        }
+
+        challenge.realm = realm?;
+        challenge.nonce = nonce?;
+        Some(challenge)
+    }
+
+    /// Find a Digest challenge among the `WWW-Authenticate` headers of a response.
+    pub fn from_headers(headers: &HeaderMap) -> Option<Self> {
+        headers
+            .get_all(WWW_AUTHENTICATE)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .find_map(Self::parse)
+    }
+
+    /// Compute the response to this challenge, for a request with the given
+    /// method and URI.
+    ///
+    /// `cnonce` is a client-chosen nonce, and `nc` the number of requests
+    /// previously made with this challenge's nonce, plus one.
+    pub fn respond(
+        &self,
+        username: &str,
+        password: &str,
+        method: &Method,
+        uri: &str,
+        cnonce: &str,
+        nc: u32,
+    ) -> DigestResponse {
+        let qop = self.qop.iter().find(|qop| *qop == "auth").cloned();
+        let (cnonce, nc) = match qop {
+            Some(_) => (Some(cnonce.to_string()), Some(nc)),
+            None => (None, None),
+        };
+
+        let mut response = DigestResponse {
+            username: username.to_string(),
+            realm: self.realm.clone(),
+            nonce: self.nonce.clone(),
+            uri: uri.to_string(),
+            algorithm: self.algorithm,
+            response: String::new(),
+            cnonce,
+            nc,
+            qop,
+            opaque: self.opaque.clone(),
+        };
+        response.response = response.expected(method, password);
+        response
+    }
+}
+
+impl fmt::Display for DigestChallenge {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Digest realm={}, nonce={}, algorithm={}",
+            quote(&self.realm),
+            quote(&self.nonce),
+            self.algorithm.name()
+        )?;
+        if !self.qop.is_empty() {
+            write!(f, ", qop={}", quote(&self.qop.join(",")))?;
+        }
+        if let Some(ref opaque) = self.opaque {
+            write!(f, ", opaque={}", quote(opaque))?;
+        }
+        if self.stale {
+            write!(f, ", stale=true")?;
+        }
+        Ok(())
+    }
+}
+
+/// Build a `401 Unauthorized` response, challenging the client to authenticate
+/// using HTTP Digest authentication.
+pub fn digest_challenge<B: Default>(challenge: &DigestChallenge) -> Response<B> {
+    let mut response = Response::new(B::default());
+    *response.status_mut() = StatusCode::UNAUTHORIZED;
+    if let Ok(challenge) = HeaderValue::from_str(&challenge.to_string()) {
+        response.headers_mut().insert(WWW_AUTHENTICATE, challenge);
+    }
+    response
+}
+
+/// Response to a Digest challenge, sent by a client in an `Authorization` header.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DigestResponse {
+    /// Username.
+    pub username: String,
+    /// Realm, from the challenge.
+    pub realm: String,
+    /// Nonce, from the challenge.
+    pub nonce: String,
+    /// Request URI.
+    pub uri: String,
+    /// Hash algorithm used.
+    pub algorithm: DigestAlgorithm,
+    /// Computed response, proving knowledge of the password.
+    pub response: String,
+    /// Client-chosen nonce, if a quality of protection was used.
+    pub cnonce: Option<String>,
+    /// Nonce count, if a quality of protection was used.
+    pub nc: Option<u32>,
+    /// Quality of protection used.
+    pub qop: Option<String>,
+    /// Opaque data, from the challenge.
+    pub opaque: Option<String>,
+}
+
+impl DigestResponse {
+    /// Parse a response from an `Authorization` header value.
+    pub fn parse(value: &str) -> Option<Self> {
+        let (mut username, mut realm, mut nonce, mut uri, mut response) =
+            (None, None, None, None, None);
+        let (mut cnonce, mut nc, mut qop, mut opaque) = (None, None, None, None);
+        let mut algorithm = DigestAlgorithm::default();
+
+        for (name, value) in parse_params(value)? {
+            match name.as_str() {
+                "username" => username = Some(value),
+                "realm" => realm = Some(value),
+                "nonce" => nonce = Some(value),
+                "uri" => uri = Some(value),
+                "response" => response = Some(value),
+                "algorithm" => algorithm = DigestAlgorithm::parse(&value)?,
+                "cnonce" => cnonce = Some(value),
+                "nc" => nc = Some(u32::from_str_radix(&value, 16).ok()?),
+                "qop" => qop = Some(value),
+                "opaque" => opaque = Some(value),
+                _ => {}
+            }
+        }
+
+        Some(DigestResponse {
+            username: username?,
+            realm: realm?,
+            nonce: nonce?,
+            uri: uri?,
+            algorithm,
+            response: response?,
+            cnonce,
+            nc,
+            qop,
+            opaque,
+        })
+    }
+
+    /// Retrieve a Digest response from the `Authorization` header of a request.
+    pub fn from_headers(headers: &HeaderMap) -> Option<Self> {
+        headers
+            .get(AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+            .and_then(Self::parse)
+    }
+
+    fn expected(&self, method: &Method, password: &str) -> String {
+        let hash = |data: String| self.algorithm.hash(&data);
+
+        let mut ha1 = hash(format!("{}:{}:{}", self.username, self.realm, password));
+        if self.algorithm.is_session() {
+            let cnonce = self.cnonce.as_deref().unwrap_or_default();
+            ha1 = hash(format!("{}:{}:{}", ha1, self.nonce, cnonce));
+        }
+        let ha2 = hash(format!("{}:{}", method, self.uri));
+
+        match (&self.qop, &self.cnonce, self.nc) {
+            (Some(qop), Some(cnonce), Some(nc)) => hash(format!(
+                "{}:{}:{:08x}:{}:{}:{}",
+                ha1, self.nonce, nc, cnonce, qop, ha2
+            )),
+            _ => hash(format!("{}:{}:{}", ha1, self.nonce, ha2)),
+        }
+    }
+
+    /// Verify that this response was computed using the given password, for a
+    /// request with the given method.
+    ///
+    /// The caller is responsible for checking that the nonce is one it issued
+    /// and has not expired, that the nonce count has not been seen before,
+    /// and that the URI matches that of the request.
+    pub fn verify(&self, method: &Method, password: &str) -> bool {
+        if self.qop.as_deref().is_some_and(|qop| qop != "auth") {
+            return false;
+        }
+        constant_time_eq(&self.expected(method, password), &self.response)
+    }
+}
+
+impl fmt::Display for DigestResponse {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Digest username={}, realm={}, nonce={}, uri={}, algorithm={}, response={}",
+            quote(&self.username),
+            quote(&self.realm),
+            quote(&self.nonce),
+            quote(&self.uri),
+            self.algorithm.name(),
+            quote(&self.response)
+        )?;
+        if let (Some(qop), Some(cnonce), Some(nc)) = (&self.qop, &self.cnonce, self.nc) {
+            write!(f, ", qop={}, nc={:08x}, cnonce={}", qop, nc, quote(cnonce))?;
+        }
+        if let Some(ref opaque) = self.opaque {
+            write!(f, ", opaque={}", quote(opaque))?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rfc_7616_example() {
+        // Example from RFC 7616 section 3.9.1.
+        let challenge = DigestChallenge::parse(
+            r#"Digest realm="http-auth@example.org", qop="auth, auth-int", algorithm=SHA-256, nonce="7ypf/xlj9XXwfDPEoM4URrv/xwf94BcCAzFZH4GiTo0v", opaque="FQhe/qaU925kfnzjCev0ciny7QMkPqMAFRtzCUYo5tdS""#,
+        )
+        .unwrap();
+        assert_eq!(challenge.algorithm, DigestAlgorithm::Sha256);
+        assert_eq!(challenge.qop, vec!["auth", "auth-int"]);
+
+        let response = challenge.respond(
+            "Mufasa",
+            "Circle of Life",
+            &Method::GET,
+            "/dir/index.html",
+            "f2/wE4q74E6zIJEtWaHKaf5wv/H5QzzpXusqGemxURZJ",
+            1,
+        );
+        assert_eq!(
+            response.response,
+            "753927fa0e85d155564e2e272a28d1802ca10daf4496794697cf8db5856cb6c1"
+        );
+
+        let parsed = DigestResponse::parse(&response.to_string()).unwrap();
+        assert_eq!(parsed, response);
+        assert!(parsed.verify(&Method::GET, "Circle of Life"));
+        assert!(!parsed.verify(&Method::GET, "Circle of Death"));
+        assert!(!parsed.verify(&Method::POST, "Circle of Life"));
+    }
+
+    #[test]
+    fn test_md5_without_qop() {
+        let challenge = DigestChallenge::new("test", "nonce");
+        assert_eq!(
+            DigestChallenge::parse(&challenge.to_string()).unwrap(),
+            challenge
+        );
+
+        let mut challenge = challenge.with_opaque("opaque \"quoted\"");
+        challenge.qop.clear();
+        let response = challenge.respond("foo", "bar", &Method::GET, "/", "cnonce", 1);
+        assert_eq!(response.qop, None);
+        assert_eq!(response.opaque.as_deref(), Some("opaque \"quoted\""));
+
+        let header = response.to_string();
+        let parsed = DigestResponse::parse(&header).unwrap();
+        assert_eq!(parsed, response);
+        assert!(parsed.verify(&Method::GET, "bar"));
+    }
+
+    #[test]
+    fn test_digest_challenge_response() {
+        let response =
+            digest_challenge::<String>(&DigestChallenge::new("test", "nonce").with_stale(true));
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        let challenge = DigestChallenge::from_headers(response.headers()).unwrap();
+        assert!(challenge.stale);
+        assert!(DigestChallenge::parse("Basic realm=\"test\"").is_none());
+    }
+}
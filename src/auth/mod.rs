@@ -0,0 +1,775 @@
+//! Authentication and authorization data structures
+
+#[cfg(feature = "serdejson")]
+use crate::context::Has;
+use crate::context::Push;
+use futures::future::FutureExt;
+use headers::authorization::{Basic, Bearer, Credentials};
+use headers::Authorization as Header;
+use hyper::header::{AUTHORIZATION, COOKIE};
+use hyper::service::Service;
+use hyper::{HeaderMap, Request, Uri};
+use percent_encoding::percent_decode_str;
+use std::collections::{BTreeMap, BTreeSet};
+use std::marker::PhantomData;
+use std::string::ToString;
+use zeroize::ZeroizeOnDrop;
+
+mod audit;
+pub use audit::{AuditEvent, AuditOutcome, AuditSink};
+
+mod basic;
+pub use basic::{basic_challenge, BasicAuthenticator, MakeBasicAuthenticator};
+
+mod cached;
+pub use cached::{AuthCacheStats, CachedAuthenticator, CredentialValidator};
+
+mod challenge;
+pub use challenge::{BearerError, Challenge};
+
+mod custom;
+pub use custom::{FnAuthenticator, MakeFnAuthenticator};
+
+mod deny;
+pub use deny::{DenyAllAuthenticator, MakeDenyAllAuthenticator};
+
+mod mode;
+pub use mode::{AuthMode, AuthModePolicy};
+
+mod mtls;
+pub use mtls::{
+    HasPeerIdentity, MakeTlsIdentityService, SubjectAltName, TlsClientIdentity, TlsIdentityService,
+};
+
+mod route;
+
+mod scheme;
+pub use scheme::SchemeRegistry;
+
+mod secret;
+pub use secret::Secret;
+
+mod security;
+pub use security::{MakeSecurityEvaluator, SecurityEvaluator, SecurityPolicy, SecurityRequirement};
+
+mod scope;
+pub use scope::{MakeScopeEnforcer, ScopeEnforcer, ScopePolicy};
+
+#[cfg(feature = "serdejson")]
+mod config;
+#[cfg(feature = "serdejson")]
+pub use config::{PathSecurity, SecurityConfig};
+
+#[cfg(feature = "oidc")]
+pub mod oidc;
+
+#[cfg(feature = "signing")]
+pub mod signing;
+
+#[cfg(feature = "digest")]
+pub mod digest;
+
+/// Authorization scopes.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Scopes {
+    /// Some set of scopes.
+    Some(BTreeSet<String>),
+    /// All possible scopes, authorization checking disabled.
+    All,
+}
+
+impl Scopes {
+    /// Whether these scopes include all of the required scopes.
+    pub fn covers<'a, I>(&self, required: I) -> bool
+    where
+        I: IntoIterator<Item = &'a String>,
+    {
+        match self {
+            Scopes::All => true,
+            Scopes::Some(scopes) => required.into_iter().all(|scope| scopes.contains(scope)),
+        }
+    }
+}
+
+/// Storage of authorization parameters for an incoming request, used for
+/// REST API authorization.
+///
+/// This can't be constructed with a struct expression outside this crate, as
+/// its fields depend on the enabled features. Use `Authorization::new`, then
+/// set any further fields.
+#[derive(Clone, Debug, PartialEq)]
+#[non_exhaustive]
+pub struct Authorization {
+    /// Subject for which authorization is granted
+    /// (i.e., what may be accessed.)
+    pub subject: String,
+
+    /// Scopes for which authorization is granted
+    /// (i.e., what types of access are permitted).
+    pub scopes: Scopes,
+
+    /// Identity of the party to whom authorization was granted, if available
+    /// (i.e., who is responsible for the access).
+    ///
+    /// In an OAuth environment, this is the identity of the client which
+    /// issued an authorization request to the resource owner (end-user),
+    /// and which has been directly authorized by the resource owner
+    /// to access the protected resource. If the client delegates that
+    /// authorization to another service (e.g., a proxy or other delegate),
+    /// the `issuer` is still the original client which was authorized by
+    /// the resource owner.
+    pub issuer: Option<String>,
+
+    /// Further claims made about the request by the authenticator, such as
+    /// the claims of a verified token, keyed by name.
+    ///
+    /// These allow handlers to make use of custom claims, such as a tenant
+    /// ID or roles, without parsing the credentials again.
+    #[cfg(feature = "serdejson")]
+    pub claims: BTreeMap<String, serde_json::Value>,
+}
+
+impl Authorization {
+    /// Authorization of the subject for the given scopes, with no issuer or
+    /// further claims.
+    pub fn new<S: Into<String>>(subject: S, scopes: Scopes) -> Self {
+        Authorization {
+            subject: subject.into(),
+            scopes,
+            issuer: None,
+            #[cfg(feature = "serdejson")]
+            claims: BTreeMap::new(),
+        }
+    }
+
+    /// Set the identity of the party to whom authorization was granted.
+    pub fn with_issuer<I: Into<String>>(mut self, issuer: I) -> Self {
+        self.issuer = Some(issuer.into());
+        self
+    }
+
+    /// Add a claim.
+    #[cfg(feature = "serdejson")]
+    pub fn with_claim<N: Into<String>>(mut self, name: N, value: serde_json::Value) -> Self {
+        self.claims.insert(name.into(), value);
+        self
+    }
+
+    /// The value of a claim, if present.
+    #[cfg(feature = "serdejson")]
+    pub fn claim(&self, name: &str) -> Option<&serde_json::Value> {
+        self.claims.get(name)
+    }
+
+    /// The value of a claim, if present and a string.
+    #[cfg(feature = "serdejson")]
+    pub fn claim_str(&self, name: &str) -> Option<&str> {
+        self.claim(name).and_then(|value| value.as_str())
+    }
+}
+
+/// Access to the claims of the `Authorization` in a context.
+///
+/// This is implemented for all contexts holding an `Option<Authorization>`,
+/// including the default `ContextBuilder`.
+#[cfg(feature = "serdejson")]
+pub trait HasClaims {
+    /// The claims of the request's authorization, if it is authorized.
+    fn claims(&self) -> Option<&BTreeMap<String, serde_json::Value>>;
+
+    /// The value of a claim, if the request is authorized and the claim is present.
+    fn claim(&self, name: &str) -> Option<&serde_json::Value> {
+        self.claims().and_then(|claims| claims.get(name))
+    }
+}
+
+#[cfg(feature = "serdejson")]
+impl<C: Has<Option<Authorization>>> HasClaims for C {
+    fn claims(&self) -> Option<&BTreeMap<String, serde_json::Value>> {
+        Has::<Option<Authorization>>::get(self)
+            .as_ref()
+            .map(|authorization| &authorization.claims)
+    }
+}
+
+/// Storage of raw authentication data, used both for storing incoming
+/// request authentication, and for authenticating outgoing client requests.
+///
+/// Passwords, tokens and API keys are held as `Secret`s, so they are redacted
+/// from `Debug` output and compared in constant time.
+// Derive Zeroize for AuthData to prevent any sensitive data from being left in memory.
+#[derive(Clone, Debug, PartialEq, Eq, Hash, ZeroizeOnDrop)]
+pub enum AuthData {
+    /// HTTP Basic auth - username and password.
+    Basic(String, Secret),
+    /// HTTP Bearer auth, used for OAuth2 - token.
+    Bearer(Secret),
+    /// Header-based or query parameter-based API key auth.
+    ApiKey(Secret),
+    /// HTTP Digest auth - username and password.
+    Digest(String, Secret),
+    /// Another scheme in the `Authorization` header, parsed by a parser
+    /// in a `SchemeRegistry` - scheme name and credentials.
+    Other(String, Secret),
+}
+
+impl AuthData {
+    /// Set Basic authentication
+    pub fn basic(username: &str, password: &str) -> Self {
+        AuthData::Basic(username.to_owned(), password.into())
+    }
+
+    /// Set Bearer token authentication.  Returns None if the token was invalid.
+    pub fn bearer(token: &str) -> Option<Self> {
+        Some(AuthData::Bearer(Header::bearer(token).ok()?.token().into()))
+    }
+
+    /// Set ApiKey authentication
+    pub fn apikey(apikey: &str) -> Self {
+        AuthData::ApiKey(apikey.into())
+    }
+
+    /// Set Digest authentication
+    pub fn digest(username: &str, password: &str) -> Self {
+        AuthData::Digest(username.to_owned(), password.into())
+    }
+
+    /// Set authentication using another scheme
+    pub fn other(scheme: &str, credentials: &str) -> Self {
+        AuthData::Other(scheme.to_owned(), credentials.into())
+    }
+
+    /// Name of the authentication scheme of the credentials.
+    pub fn scheme(&self) -> &str {
+        match self {
+            AuthData::Basic(..) => "Basic",
+            AuthData::Bearer(_) => "Bearer",
+            AuthData::ApiKey(_) => "ApiKey",
+            AuthData::Digest(..) => "Digest",
+            AuthData::Other(scheme, _) => scheme,
+        }
+    }
+}
+
+/// Bound for Request Context for MakeService wrappers
+pub trait RcBound: Push<Option<Authorization>> + Send + 'static {}
+
+impl<T> RcBound for T where T: Push<Option<Authorization>> + Send + 'static {}
+
+/// Dummy Authenticator, that blindly inserts authorization data, allowing all
+/// access to an endpoint with the specified subject.
+#[derive(Debug)]
+pub struct MakeAllowAllAuthenticator<T, RC>
+where
+    RC: RcBound,
+    RC::Result: Send + 'static,
+{
+    inner: T,
+    subject: String,
+    marker: PhantomData<RC>,
+}
+
+impl<T, RC> MakeAllowAllAuthenticator<T, RC>
+where
+    RC: RcBound,
+    RC::Result: Send + 'static,
+{
+    /// Create a middleware that authorizes with the configured subject.
+    pub fn new<U: Into<String>>(inner: T, subject: U) -> Self {
+        MakeAllowAllAuthenticator {
+            inner,
+            subject: subject.into(),
+            marker: PhantomData,
+        }
+    }
+}
+
+impl<Inner, RC, Target> Service<Target> for MakeAllowAllAuthenticator<Inner, RC>
+where
+    RC: RcBound,
+    RC::Result: Send + 'static,
+    Inner: Service<Target>,
+    Inner::Future: Send + 'static,
+{
+    type Error = Inner::Error;
+    type Response = AllowAllAuthenticator<Inner::Response, RC>;
+    type Future = futures::future::BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn call(&self, target: Target) -> Self::Future {
+        let subject = self.subject.clone();
+        Box::pin(
+            self.inner
+                .call(target)
+                .map(|s| Ok(AllowAllAuthenticator::new(s?, subject))),
+        )
+    }
+}
+
+/// Dummy Authenticator, that blindly inserts authorization data, allowing all
+/// access to an endpoint with the specified subject.
+#[derive(Debug)]
+pub struct AllowAllAuthenticator<T, RC>
+where
+    RC: RcBound,
+    RC::Result: Send + 'static,
+{
+    inner: T,
+    subject: String,
+    marker: PhantomData<RC>,
+}
+
+impl<T, RC> AllowAllAuthenticator<T, RC>
+where
+    RC: RcBound,
+    RC::Result: Send + 'static,
+{
+    /// Create a middleware that authorizes with the configured subject.
+    pub fn new<U: Into<String>>(inner: T, subject: U) -> Self {
+        AllowAllAuthenticator {
+            inner,
+            subject: subject.into(),
+            marker: PhantomData,
+        }
+    }
+}
+
+impl<T, RC> Clone for AllowAllAuthenticator<T, RC>
+where
+    T: Clone,
+    RC: RcBound,
+    RC::Result: Send + 'static,
+{
+    fn clone(&self) -> Self {
+        Self {
+            inner: self.inner.clone(),
+            subject: self.subject.clone(),
+            marker: PhantomData,
+        }
+    }
+}
+
+impl<T, B, RC> Service<(Request<B>, RC)> for AllowAllAuthenticator<T, RC>
+where
+    RC: RcBound,
+    RC::Result: Send + 'static,
+    T: Service<(Request<B>, RC::Result)>,
+{
+    type Response = T::Response;
+    type Error = T::Error;
+    type Future = T::Future;
+
+    fn call(&self, req: (Request<B>, RC)) -> Self::Future {
+        let (request, context) = req;
+        let context = context.push(Some(Authorization::new(self.subject.clone(), Scopes::All)));
+
+        self.inner.call((request, context))
+    }
+}
+
+/// Retrieve an authorization scheme data from a set of headers
+///
+/// Only Basic and Bearer credentials are parsed. Use a `SchemeRegistry` to
+/// parse other schemes.
+pub fn from_headers(headers: &HeaderMap) -> Option<AuthData> {
+    headers.get(AUTHORIZATION).and_then(|value| {
+        if let Ok(value_str) = value.to_str() {
+            // Auth schemes in HTTP are case insensitive so we match on lowercase.
+            // Ideally we would use decode without checking for a hardcoded string.
+            // Unfortunately `decode` has a debug_assert that verifies the header starts with the scheme.
+            // We therefore can only call `decode` if we have a header with a matching scheme.
+            if value_str.to_lowercase().starts_with("basic ") {
+                Basic::decode(value).map(|basic| {
+                    AuthData::Basic(basic.username().to_string(), basic.password().into())
+                })
+            } else if value_str.to_lowercase().starts_with("bearer ") {
+                Bearer::decode(value).map(|bearer| AuthData::Bearer(bearer.token().into()))
+            } else {
+                None
+            }
+        } else {
+            None
+        }
+    })
+}
+
+/// Retrieve an API key from a header
+pub fn api_key_from_header(headers: &HeaderMap, header: &str) -> Option<String> {
+    headers
+        .get(header)
+        .and_then(|v| v.to_str().ok())
+        .map(ToString::to_string)
+}
+
+/// Retrieve an API key from a query parameter
+pub fn api_key_from_query(uri: &Uri, name: &str) -> Option<String> {
+    // Query parameters are form encoded, so `+` represents a space.
+    let decode = |s: &str| {
+        percent_decode_str(&s.replace('+', " "))
+            .decode_utf8()
+            .ok()
+            .map(|s| s.into_owned())
+    };
+
+    uri.query()?.split('&').find_map(|pair| {
+        let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+        if decode(key)? == name {
+            decode(value)
+        } else {
+            None
+        }
+    })
+}
+
+/// Retrieve an API key from a cookie
+pub fn api_key_from_cookie(headers: &HeaderMap, name: &str) -> Option<String> {
+    headers
+        .get_all(COOKIE)
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(';'))
+        .find_map(|cookie| {
+            let (key, value) = cookie.split_once('=')?;
+            if key.trim() == name {
+                // Cookie values may optionally be wrapped in double quotes.
+                let value = value.trim();
+                let value = value
+                    .strip_prefix('"')
+                    .and_then(|v| v.strip_suffix('"'))
+                    .unwrap_or(value);
+                Some(value.to_string())
+            } else {
+                None
+            }
+        })
+}
+
+/// Location of an API key in a request, matching the `in` field of an
+/// OpenAPI `apiKey` security scheme.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ApiKeyLocation {
+    /// API key is provided in the named header.
+    Header(String),
+    /// API key is provided in the named query parameter.
+    Query(String),
+    /// API key is provided in the named cookie.
+    Cookie(String),
+}
+
+impl ApiKeyLocation {
+    /// Retrieve the API key from a request, if present.
+    pub fn extract<B>(&self, request: &Request<B>) -> Option<String> {
+        match self {
+            ApiKeyLocation::Header(name) => api_key_from_header(request.headers(), name),
+            ApiKeyLocation::Query(name) => api_key_from_query(request.uri(), name),
+            ApiKeyLocation::Cookie(name) => api_key_from_cookie(request.headers(), name),
+        }
+    }
+}
+
+/// Middleware which extracts an API key from the configured location of an
+/// incoming request, and stores it in the context as `AuthData::ApiKey`.
+#[derive(Debug)]
+pub struct MakeApiKeyExtractor<T, RC>
+where
+    RC: Push<Option<AuthData>> + Send + 'static,
+    RC::Result: Send + 'static,
+{
+    inner: T,
+    location: ApiKeyLocation,
+    marker: PhantomData<RC>,
+}
+
+impl<T, RC> MakeApiKeyExtractor<T, RC>
+where
+    RC: Push<Option<AuthData>> + Send + 'static,
+    RC::Result: Send + 'static,
+{
+    /// Create a middleware that extracts API keys from the configured location.
+    pub fn new(inner: T, location: ApiKeyLocation) -> Self {
+        MakeApiKeyExtractor {
+            inner,
+            location,
+            marker: PhantomData,
+        }
+    }
+}
+
+impl<Inner, RC, Target> Service<Target> for MakeApiKeyExtractor<Inner, RC>
+where
+    RC: Push<Option<AuthData>> + Send + 'static,
+    RC::Result: Send + 'static,
+    Inner: Service<Target>,
+    Inner::Future: Send + 'static,
+{
+    type Error = Inner::Error;
+    type Response = ApiKeyExtractor<Inner::Response, RC>;
+    type Future = futures::future::BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn call(&self, target: Target) -> Self::Future {
+        let location = self.location.clone();
+        Box::pin(
+            self.inner
+                .call(target)
+                .map(|s| Ok(ApiKeyExtractor::new(s?, location))),
+        )
+    }
+}
+
+/// Middleware which extracts an API key from the configured location of an
+/// incoming request, and stores it in the context as `AuthData::ApiKey`.
+///
+/// If no API key is present, `None` is stored in the context.
+#[derive(Debug)]
+pub struct ApiKeyExtractor<T, RC>
+where
+    RC: Push<Option<AuthData>> + Send + 'static,
+    RC::Result: Send + 'static,
+{
+    inner: T,
+    location: ApiKeyLocation,
+    marker: PhantomData<RC>,
+}
+
+impl<T, RC> ApiKeyExtractor<T, RC>
+where
+    RC: Push<Option<AuthData>> + Send + 'static,
+    RC::Result: Send + 'static,
+{
+    /// Create a middleware that extracts API keys from the configured location.
+    pub fn new(inner: T, location: ApiKeyLocation) -> Self {
+        ApiKeyExtractor {
+            inner,
+            location,
+            marker: PhantomData,
+        }
+    }
+}
+
+impl<T, RC> Clone for ApiKeyExtractor<T, RC>
+where
+    T: Clone,
+    RC: Push<Option<AuthData>> + Send + 'static,
+    RC::Result: Send + 'static,
+{
+    fn clone(&self) -> Self {
+        Self {
+            inner: self.inner.clone(),
+            location: self.location.clone(),
+            marker: PhantomData,
+        }
+    }
+}
+
+impl<T, B, RC> Service<(Request<B>, RC)> for ApiKeyExtractor<T, RC>
+where
+    RC: Push<Option<AuthData>> + Send + 'static,
+    RC::Result: Send + 'static,
+    T: Service<(Request<B>, RC::Result)>,
+{
+    type Response = T::Response;
+    type Error = T::Error;
+    type Future = T::Future;
+
+    fn call(&self, req: (Request<B>, RC)) -> Self::Future {
+        let (request, context) = req;
+        let api_key = self
+            .location
+            .extract(&request)
+            .map(|key| AuthData::ApiKey(key.into()));
+        let context = context.push(api_key);
+
+        self.inner.call((request, context))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::context::{ContextBuilder, Has};
+    use crate::EmptyContext;
+    use http_body_util::Full;
+    use hyper::body::Bytes;
+    use hyper::service::Service;
+    use hyper::Response;
+
+    struct MakeTestService;
+
+    type ReqWithAuth = (
+        Request<Full<Bytes>>,
+        ContextBuilder<Option<Authorization>, EmptyContext>,
+    );
+
+    impl<Target> Service<Target> for MakeTestService {
+        type Response = TestService;
+        type Error = ();
+        type Future = futures::future::Ready<Result<Self::Response, Self::Error>>;
+
+        fn call(&self, _target: Target) -> Self::Future {
+            futures::future::ok(TestService)
+        }
+    }
+
+    struct TestService;
+
+    impl Service<ReqWithAuth> for TestService {
+        type Response = Response<Full<Bytes>>;
+        type Error = String;
+        type Future = futures::future::BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+        fn call(&self, req: ReqWithAuth) -> Self::Future {
+            Box::pin(async move {
+                let auth: &Option<Authorization> = req.1.get();
+                let expected = Some(Authorization::new("foo".to_string(), Scopes::All));
+
+                if *auth == expected {
+                    Ok(Response::new(Full::default()))
+                } else {
+                    Err(format!("{:?} != {:?}", auth, expected))
+                }
+            })
+        }
+    }
+
+    #[tokio::test]
+    async fn test_make_service() {
+        let make_svc = MakeTestService;
+
+        let a: MakeAllowAllAuthenticator<_, EmptyContext> =
+            MakeAllowAllAuthenticator::new(make_svc, "foo");
+
+        let service = a.call(&()).await.unwrap();
+
+        let response = service
+            .call((
+                Request::get("http://localhost")
+                    .body(Full::default())
+                    .unwrap(),
+                EmptyContext,
+            ))
+            .await;
+
+        response.unwrap();
+    }
+
+    #[test]
+    fn test_claims_from_context() {
+        let context: ContextBuilder<Option<Authorization>, EmptyContext> = EmptyContext.push(Some(
+            Authorization::new("alice", Scopes::All)
+                .with_claim("tenant", serde_json::json!("acme"))
+                .with_claim("roles", serde_json::json!(["admin"])),
+        ));
+        assert_eq!(context.claim("tenant"), Some(&serde_json::json!("acme")));
+        assert_eq!(context.claims().map(BTreeMap::len), Some(2));
+        assert_eq!(context.claim("missing"), None);
+
+        let context: ContextBuilder<Option<Authorization>, EmptyContext> =
+            EmptyContext.push(None::<Authorization>);
+        assert_eq!(context.claims(), None);
+    }
+
+    #[test]
+    fn test_from_headers_basic() {
+        let mut headers = HeaderMap::new();
+        headers.append(
+            AUTHORIZATION,
+            headers::HeaderValue::from_static("Basic Zm9vOmJhcg=="),
+        );
+        assert_eq!(from_headers(&headers), Some(AuthData::basic("foo", "bar")))
+    }
+
+    #[test]
+    fn test_api_key_from_query() {
+        let uri: Uri = "http://localhost/foo?a=b&api_key=abc%2F123+4&c"
+            .parse()
+            .unwrap();
+        assert_eq!(
+            api_key_from_query(&uri, "api_key"),
+            Some("abc/123 4".to_string())
+        );
+        assert_eq!(api_key_from_query(&uri, "c"), Some("".to_string()));
+        assert_eq!(api_key_from_query(&uri, "missing"), None);
+
+        let uri: Uri = "http://localhost/foo".parse().unwrap();
+        assert_eq!(api_key_from_query(&uri, "api_key"), None);
+    }
+
+    #[test]
+    fn test_api_key_from_cookie() {
+        let mut headers = HeaderMap::new();
+        headers.append(
+            COOKIE,
+            headers::HeaderValue::from_static("session=xyz; api_key=\"abc\""),
+        );
+        headers.append(COOKIE, headers::HeaderValue::from_static("other=def"));
+        assert_eq!(
+            api_key_from_cookie(&headers, "api_key"),
+            Some("abc".to_string())
+        );
+        assert_eq!(
+            api_key_from_cookie(&headers, "other"),
+            Some("def".to_string())
+        );
+        assert_eq!(api_key_from_cookie(&headers, "missing"), None);
+    }
+
+    #[tokio::test]
+    async fn test_api_key_extractor() {
+        struct ApiKeyService;
+
+        impl
+            Service<(
+                Request<Full<Bytes>>,
+                ContextBuilder<Option<AuthData>, EmptyContext>,
+            )> for ApiKeyService
+        {
+            type Response = Option<AuthData>;
+            type Error = ();
+            type Future = futures::future::Ready<Result<Self::Response, Self::Error>>;
+
+            fn call(
+                &self,
+                req: (
+                    Request<Full<Bytes>>,
+                    ContextBuilder<Option<AuthData>, EmptyContext>,
+                ),
+            ) -> Self::Future {
+                futures::future::ok(Has::<Option<AuthData>>::get(&req.1).clone())
+            }
+        }
+
+        let service: ApiKeyExtractor<_, EmptyContext> =
+            ApiKeyExtractor::new(ApiKeyService, ApiKeyLocation::Query("key".to_string()));
+
+        let auth = service
+            .call((
+                Request::get("http://localhost?key=secret")
+                    .body(Full::default())
+                    .unwrap(),
+                EmptyContext,
+            ))
+            .await
+            .unwrap();
+        assert_eq!(auth, Some(AuthData::apikey("secret")));
+
+        let auth = service
+            .call((
+                Request::get("http://localhost")
+                    .body(Full::default())
+                    .unwrap(),
+                EmptyContext,
+            ))
+            .await
+            .unwrap();
+        assert_eq!(auth, None);
+    }
+
+    #[test]
+    fn test_from_headers_bearer() {
+        let mut headers = HeaderMap::new();
+        headers.append(
+            AUTHORIZATION,
+            headers::HeaderValue::from_static("Bearer foo"),
+        );
+        assert_eq!(from_headers(&headers), Some(AuthData::Bearer("foo".into())))
+    }
+}
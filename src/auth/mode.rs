@@ -0,0 +1,93 @@
+//! Whether authenticators require credentials on each route.
+use super::route::RouteTable;
+use hyper::Method;
+
+/// Whether an authenticator requires requests to carry credentials.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum AuthMode {
+    /// Requests without credentials are rejected with `401 Unauthorized`.
+    #[default]
+    Required,
+    /// Requests without credentials are passed on, with `None` stored in the
+    /// context as their authorization. Requests with invalid credentials are
+    /// still rejected.
+    ///
+    /// This corresponds to an OpenAPI operation whose security requirements
+    /// include the empty requirement, as in `security: [{}, {"oauth": []}]`.
+    Optional,
+}
+
+/// Map from route to the `AuthMode` of an authenticator.
+///
+/// Routes are matched as by a `ScopePolicy`, and requests matching no route
+/// use the default mode.
+///
+/// ```ignore
+/// let modes = AuthModePolicy::new(AuthMode::Required)
+///     .route("/public", AuthMode::Optional)
+///     .operation(Method::GET, "/pets/{petId}", AuthMode::Optional);
+/// let authenticator = BasicAuthenticator::new(inner, validator, "my-api").with_modes(modes);
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct AuthModePolicy {
+    default: AuthMode,
+    routes: RouteTable<AuthMode>,
+}
+
+impl AuthModePolicy {
+    /// Create a policy using the given mode for all routes.
+    pub fn new(default: AuthMode) -> Self {
+        AuthModePolicy {
+            default,
+            routes: RouteTable::default(),
+        }
+    }
+
+    /// Use the given mode for requests with any method under the path prefix.
+    pub fn route<P: Into<String>>(mut self, prefix: P, mode: AuthMode) -> Self {
+        self.routes.insert(prefix.into(), None, false, mode);
+        self
+    }
+
+    /// Use the given mode for requests with the given method under the path prefix.
+    pub fn route_for<P: Into<String>>(mut self, method: Method, prefix: P, mode: AuthMode) -> Self {
+        self.routes.insert(prefix.into(), Some(method), false, mode);
+        self
+    }
+
+    /// Use the given mode for the operation with the given method and path template.
+    pub fn operation<P: Into<String>>(mut self, method: Method, path: P, mode: AuthMode) -> Self {
+        self.routes.insert(path.into(), Some(method), true, mode);
+        self
+    }
+
+    /// Mode for a request with the given method and path.
+    pub fn mode(&self, method: &Method, path: &str) -> AuthMode {
+        self.routes
+            .get(method, path)
+            .copied()
+            .unwrap_or(self.default)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_auth_modes() {
+        let modes = AuthModePolicy::new(AuthMode::Required)
+            .route("/public", AuthMode::Optional)
+            .route_for(Method::POST, "/public", AuthMode::Required)
+            .operation(Method::GET, "/pets/{petId}", AuthMode::Optional);
+
+        assert_eq!(modes.mode(&Method::GET, "/public/1"), AuthMode::Optional);
+        assert_eq!(modes.mode(&Method::POST, "/public"), AuthMode::Required);
+        assert_eq!(modes.mode(&Method::GET, "/pets/1"), AuthMode::Optional);
+        assert_eq!(modes.mode(&Method::GET, "/pets"), AuthMode::Required);
+        assert_eq!(
+            AuthModePolicy::new(AuthMode::Optional).mode(&Method::GET, "/"),
+            AuthMode::Optional
+        );
+    }
+}
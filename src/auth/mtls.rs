@@ -0,0 +1,288 @@
+//! Identification of clients by their mutual TLS certificate.
+use crate::context::Push;
+use futures::future::{BoxFuture, FutureExt};
+use hyper::service::Service;
+use hyper::Request;
+use std::marker::PhantomData;
+use std::net::IpAddr;
+
+/// Subject alternative name from a client certificate.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum SubjectAltName {
+    /// DNS name.
+    Dns(String),
+    /// URI, such as a SPIFFE ID.
+    Uri(String),
+    /// Email address.
+    Email(String),
+    /// IP address.
+    Ip(IpAddr),
+}
+
+/// Identity of a client, taken from the certificate it presented during a
+/// mutual TLS handshake.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TlsClientIdentity {
+    /// Distinguished name of the certificate subject, e.g. `CN=client,O=Example`.
+    pub subject: String,
+    /// Subject alternative names of the certificate.
+    pub subject_alt_names: Vec<SubjectAltName>,
+}
+
+impl TlsClientIdentity {
+    /// DNS names among the subject alternative names.
+    pub fn dns_names(&self) -> impl Iterator<Item = &str> {
+        self.subject_alt_names.iter().filter_map(|san| match san {
+            SubjectAltName::Dns(name) => Some(name.as_str()),
+            _ => None,
+        })
+    }
+
+    /// URIs among the subject alternative names.
+    pub fn uris(&self) -> impl Iterator<Item = &str> {
+        self.subject_alt_names.iter().filter_map(|san| match san {
+            SubjectAltName::Uri(uri) => Some(uri.as_str()),
+            _ => None,
+        })
+    }
+
+    /// Identity of the client of an OpenSSL connection, if it presented a certificate.
+    #[cfg(all(
+        feature = "tls",
+        not(any(target_os = "macos", target_os = "windows", target_os = "ios"))
+    ))]
+    pub fn from_ssl(ssl: &openssl::ssl::SslRef) -> Option<Self> {
+        let certificate = ssl.peer_certificate()?;
+
+        let subject = certificate
+            .subject_name()
+            .entries()
+            .map(|entry| {
+                format!(
+                    "{}={}",
+                    entry.object().nid().short_name().unwrap_or("UNKNOWN"),
+                    entry
+                        .data()
+                        .as_utf8()
+                        .map(|data| data.to_string())
+                        .unwrap_or_default()
+                )
+            })
+            .collect::<Vec<_>>()
+            .join(",");
+
+        let subject_alt_names = certificate
+            .subject_alt_names()
+            .map(|names| {
+                names
+                    .iter()
+                    .filter_map(|name| {
+                        if let Some(dns) = name.dnsname() {
+                            Some(SubjectAltName::Dns(dns.to_string()))
+                        } else if let Some(uri) = name.uri() {
+                            Some(SubjectAltName::Uri(uri.to_string()))
+                        } else if let Some(email) = name.email() {
+                            Some(SubjectAltName::Email(email.to_string()))
+                        } else {
+                            match name.ipaddress()? {
+                                [a, b, c, d] => {
+                                    Some(SubjectAltName::Ip(IpAddr::from([*a, *b, *c, *d])))
+                                }
+                                ip => <[u8; 16]>::try_from(ip)
+                                    .ok()
+                                    .map(|ip| SubjectAltName::Ip(IpAddr::from(ip))),
+                            }
+                        }
+                    })
+                    .collect()
+            })
+            .unwrap_or_default();
+
+        Some(TlsClientIdentity {
+            subject,
+            subject_alt_names,
+        })
+    }
+}
+
+/// Connection over which a client may have authenticated using mutual TLS.
+///
+/// This should be implemented by the connection passed to the make service
+/// by the server's TLS acceptor.
+pub trait HasPeerIdentity {
+    /// Get the identity from the client's certificate, if one was presented.
+    fn peer_identity(&self) -> Option<TlsClientIdentity>;
+}
+
+impl HasPeerIdentity for Option<TlsClientIdentity> {
+    fn peer_identity(&self) -> Option<TlsClientIdentity> {
+        self.clone()
+    }
+}
+
+impl HasPeerIdentity for &Option<TlsClientIdentity> {
+    fn peer_identity(&self) -> Option<TlsClientIdentity> {
+        (*self).clone()
+    }
+}
+
+#[cfg(all(
+    feature = "tls",
+    not(any(target_os = "macos", target_os = "windows", target_os = "ios"))
+))]
+impl HasPeerIdentity for &openssl::ssl::SslRef {
+    fn peer_identity(&self) -> Option<TlsClientIdentity> {
+        TlsClientIdentity::from_ssl(self)
+    }
+}
+
+/// Middleware which stores the identity of a mutual TLS client in the context
+/// of each request on its connection, as `Option<TlsClientIdentity>`.
+#[derive(Debug)]
+pub struct MakeTlsIdentityService<T, RC> {
+    inner: T,
+    marker: PhantomData<RC>,
+}
+
+impl<T, RC> MakeTlsIdentityService<T, RC> {
+    /// Create a middleware that stores client TLS identities in the context.
+    pub fn new(inner: T) -> Self {
+        MakeTlsIdentityService {
+            inner,
+            marker: PhantomData,
+        }
+    }
+}
+
+impl<Inner, RC, Target> Service<Target> for MakeTlsIdentityService<Inner, RC>
+where
+    Inner: Service<Target>,
+    Inner::Future: Send + 'static,
+    Target: HasPeerIdentity,
+    RC: Send + 'static,
+{
+    type Error = Inner::Error;
+    type Response = TlsIdentityService<Inner::Response, RC>;
+    type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn call(&self, target: Target) -> Self::Future {
+        let identity = target.peer_identity();
+        Box::pin(
+            self.inner
+                .call(target)
+                .map(|s| Ok(TlsIdentityService::new(s?, identity))),
+        )
+    }
+}
+
+/// Middleware which stores the identity of a mutual TLS client in the context
+/// of each request on its connection, as `Option<TlsClientIdentity>`.
+///
+/// Handlers can authorize requests based on the identity, or a later
+/// authenticator can convert it to an `Authorization`.
+#[derive(Debug)]
+pub struct TlsIdentityService<T, RC> {
+    inner: T,
+    identity: Option<TlsClientIdentity>,
+    marker: PhantomData<RC>,
+}
+
+impl<T, RC> TlsIdentityService<T, RC> {
+    /// Create a middleware that stores the given client identity in the context.
+    pub fn new(inner: T, identity: Option<TlsClientIdentity>) -> Self {
+        TlsIdentityService {
+            inner,
+            identity,
+            marker: PhantomData,
+        }
+    }
+}
+
+impl<T, RC> Clone for TlsIdentityService<T, RC>
+where
+    T: Clone,
+{
+    fn clone(&self) -> Self {
+        Self {
+            inner: self.inner.clone(),
+            identity: self.identity.clone(),
+            marker: PhantomData,
+        }
+    }
+}
+
+impl<T, B, RC> Service<(Request<B>, RC)> for TlsIdentityService<T, RC>
+where
+    RC: Push<Option<TlsClientIdentity>>,
+    T: Service<(Request<B>, RC::Result)>,
+{
+    type Response = T::Response;
+    type Error = T::Error;
+    type Future = T::Future;
+
+    fn call(&self, req: (Request<B>, RC)) -> Self::Future {
+        let (request, context) = req;
+        let context = context.push(self.identity.clone());
+
+        self.inner.call((request, context))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::context::Has;
+
+    crate::new_context_type!(TestContext, TestEmptyContext, Option<TlsClientIdentity>);
+
+    type Context = TestContext<Option<TlsClientIdentity>, TestEmptyContext>;
+
+    struct MakeIdentityService;
+
+    impl<Target> Service<Target> for MakeIdentityService {
+        type Response = IdentityService;
+        type Error = ();
+        type Future = futures::future::Ready<Result<Self::Response, Self::Error>>;
+
+        fn call(&self, _: Target) -> Self::Future {
+            futures::future::ok(IdentityService)
+        }
+    }
+
+    struct IdentityService;
+
+    impl Service<(Request<()>, Context)> for IdentityService {
+        type Response = Option<TlsClientIdentity>;
+        type Error = ();
+        type Future = futures::future::Ready<Result<Self::Response, Self::Error>>;
+
+        fn call(&self, req: (Request<()>, Context)) -> Self::Future {
+            futures::future::ok(Has::<Option<TlsClientIdentity>>::get(&req.1).clone())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_tls_identity_service() {
+        let identity = TlsClientIdentity {
+            subject: "CN=client".to_string(),
+            subject_alt_names: vec![
+                SubjectAltName::Dns("client.example.com".to_string()),
+                SubjectAltName::Uri("spiffe://example.com/client".to_string()),
+            ],
+        };
+        assert_eq!(
+            identity.uris().collect::<Vec<_>>(),
+            vec!["spiffe://example.com/client"]
+        );
+
+        let make_service = MakeTlsIdentityService::<_, TestEmptyContext>::new(MakeIdentityService);
+        for target in [Some(identity), None] {
+            let service = make_service.call(&target).await.unwrap();
+            let stored = service
+                .call((Request::new(()), TestEmptyContext))
+                .await
+                .unwrap();
+            assert_eq!(stored, target);
+        }
+    }
+}
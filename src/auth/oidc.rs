@@ -0,0 +1,586 @@
+//! OpenID Connect discovery and token verification.
+//!
+//! An `OidcVerifier` discovers the JSON Web Key Set (JWKS) of an OpenID Connect
+//! issuer, caches the keys, and uses them to verify incoming ID and access
+//! tokens. The key set is refreshed periodically, and whenever a token is
+//! signed with an unknown key, so that key rotation by the issuer is handled
+//! transparently.
+use super::{Authorization, Scopes};
+use crate::ApiError;
+use futures::future::BoxFuture;
+use http_body_util::{BodyExt, Empty};
+use hyper::body::{Body, Bytes};
+use hyper::service::Service;
+use hyper::{Request, Response, Uri};
+use jsonwebtoken::jwk::{Jwk, JwkSet};
+use jsonwebtoken::{Algorithm, DecodingKey, Validation};
+use serde::Deserialize;
+use std::fmt;
+use std::str::FromStr;
+use std::sync::{Arc, PoisonError, RwLock, RwLockReadGuard, RwLockWriteGuard};
+use std::time::{Duration, Instant};
+
+/// Claims of a verified token.
+pub type Claims = serde_json::Map<String, serde_json::Value>;
+
+/// Retrieves documents over HTTP, for OpenID Connect discovery.
+///
+/// This is implemented for any hyper client service, such as a
+/// `hyper_util::client::legacy::Client` wrapped in a `TowerToHyperService`.
+pub trait Fetch: Send + Sync + 'static {
+    /// Retrieve the body of the document at the given URI.
+    fn fetch(&self, uri: Uri) -> BoxFuture<'static, Result<Bytes, ApiError>>;
+}
+
+impl<S, B> Fetch for S
+where
+    S: Service<Request<Empty<Bytes>>, Response = Response<B>> + Send + Sync + 'static,
+    S::Future: Send + 'static,
+    S::Error: fmt::Display,
+    B: Body + Send + 'static,
+    B::Data: Send,
+    B::Error: fmt::Display,
+{
+    fn fetch(&self, uri: Uri) -> BoxFuture<'static, Result<Bytes, ApiError>> {
+        let request = Request::get(uri.clone())
+            .body(Empty::new())
+            .map_err(|e| ApiError(format!("Invalid request to {}: {}", uri, e)));
+        let response = request.map(|request| self.call(request));
+
+        Box::pin(async move {
+            let response = response?
+                .await
+                .map_err(|e| ApiError(format!("Failed to fetch {}: {}", uri, e)))?;
+
+            if !response.status().is_success() {
+                return Err(ApiError(format!(
+                    "Failed to fetch {}: {}",
+                    uri,
+                    response.status()
+                )));
+            }
+
+            let body = response
+                .into_body()
+                .collect()
+                .await
+                .map_err(|e| ApiError(format!("Failed to read {}: {}", uri, e)))?;
+
+            Ok(body.to_bytes())
+        })
+    }
+}
+
+/// The subset of an OpenID Connect discovery document used for token verification.
+#[derive(Clone, Debug, Deserialize, PartialEq, Eq)]
+pub struct DiscoveryDocument {
+    /// Issuer identifier, which must match the `iss` claim of tokens.
+    pub issuer: String,
+    /// URL of the issuer's JSON Web Key Set.
+    pub jwks_uri: String,
+}
+
+#[derive(Debug)]
+struct KeyCache {
+    keys: JwkSet,
+    fetched: Option<Instant>,
+}
+
+impl Default for KeyCache {
+    fn default() -> Self {
+        KeyCache {
+            keys: JwkSet { keys: Vec::new() },
+            fetched: None,
+        }
+    }
+}
+
+struct Inner<F> {
+    fetcher: F,
+    issuer: String,
+    jwks_uri: Uri,
+    cache: RwLock<KeyCache>,
+    // Held while refreshing the key set, so concurrent requests share a single fetch.
+    refreshing: futures::lock::Mutex<()>,
+}
+
+#[derive(Clone, Debug)]
+struct Config {
+    audience: Option<Vec<String>>,
+    algorithms: Option<Vec<Algorithm>>,
+    refresh_interval: Duration,
+    min_refresh_interval: Duration,
+}
+
+/// Verifier for tokens issued by an OpenID Connect provider.
+///
+/// Clones share the same key cache.
+pub struct OidcVerifier<F> {
+    inner: Arc<Inner<F>>,
+    config: Config,
+}
+
+impl<F> Clone for OidcVerifier<F> {
+    fn clone(&self) -> Self {
+        OidcVerifier {
+            inner: self.inner.clone(),
+            config: self.config.clone(),
+        }
+    }
+}
+
+impl<F> fmt::Debug for OidcVerifier<F> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("OidcVerifier")
+            .field("issuer", &self.inner.issuer)
+            .field("jwks_uri", &self.inner.jwks_uri)
+            .field("audience", &self.config.audience)
+            .field("algorithms", &self.config.algorithms)
+            .finish()
+    }
+}
+
+impl<F: Fetch> OidcVerifier<F> {
+    /// Create a verifier for the given issuer, using a known JWKS URI rather than discovery.
+    pub fn new(fetcher: F, issuer: String, jwks_uri: Uri) -> Self {
+        OidcVerifier {
+            inner: Arc::new(Inner {
+                fetcher,
+                issuer,
+                jwks_uri,
+                cache: RwLock::new(KeyCache::default()),
+                refreshing: futures::lock::Mutex::new(()),
+            }),
+            config: Config {
+                audience: None,
+                algorithms: None,
+                refresh_interval: Duration::from_secs(3600),
+                min_refresh_interval: Duration::from_secs(30),
+            },
+        }
+    }
+
+    /// Create a verifier by performing OpenID Connect discovery against the issuer URL.
+    pub async fn discover(fetcher: F, issuer: &str) -> Result<Self, ApiError> {
+        let uri = format!(
+            "{}/.well-known/openid-configuration",
+            issuer.trim_end_matches('/')
+        )
+        .parse::<Uri>()
+        .map_err(|e| ApiError(format!("Invalid issuer URL {}: {}", issuer, e)))?;
+
+        let body = fetcher.fetch(uri).await?;
+        let document: DiscoveryDocument = serde_json::from_slice(&body)
+            .map_err(|e| ApiError(format!("Invalid discovery document: {}", e)))?;
+
+        if document.issuer.trim_end_matches('/') != issuer.trim_end_matches('/') {
+            return Err(ApiError(format!(
+                "Discovery document issuer {} does not match {}",
+                document.issuer, issuer
+            )));
+        }
+
+        let jwks_uri = document
+            .jwks_uri
+            .parse()
+            .map_err(|e| ApiError(format!("Invalid JWKS URI {}: {}", document.jwks_uri, e)))?;
+
+        let verifier = Self::new(fetcher, document.issuer, jwks_uri);
+        verifier.refresh().await?;
+        Ok(verifier)
+    }
+
+    /// Require tokens to be issued for one of the given audiences.
+    pub fn with_audience<T: ToString>(mut self, audience: &[T]) -> Self {
+        self.config.audience = Some(audience.iter().map(ToString::to_string).collect());
+        self
+    }
+
+    /// Accept tokens signed with any of the given algorithms.
+    ///
+    /// By default, tokens must be signed with the algorithm given by the
+    /// `alg` parameter of the key, and keys without one can't be used. If
+    /// algorithms are given, keys without an `alg` parameter may be used with
+    /// any of them, and those with one still only with that algorithm.
+    pub fn with_algorithms(mut self, algorithms: &[Algorithm]) -> Self {
+        self.config.algorithms = Some(algorithms.to_vec());
+        self
+    }
+
+    /// Set how often the key set is refreshed. Defaults to one hour.
+    pub fn with_refresh_interval<D: Into<Duration>>(mut self, interval: D) -> Self {
+        self.config.refresh_interval = interval.into();
+        self
+    }
+
+    /// Set the minimum time between refreshes of the key set triggered by
+    /// tokens signed with unknown keys. Defaults to 30 seconds.
+    pub fn with_min_refresh_interval<D: Into<Duration>>(mut self, interval: D) -> Self {
+        self.config.min_refresh_interval = interval.into();
+        self
+    }
+
+    /// Issuer whose tokens this verifier accepts.
+    pub fn issuer(&self) -> &str {
+        &self.inner.issuer
+    }
+
+    /// Download the key set from the issuer, replacing the cached keys.
+    pub async fn refresh(&self) -> Result<(), ApiError> {
+        let body = self.inner.fetcher.fetch(self.inner.jwks_uri.clone()).await;
+
+        let mut cache = self.write_cache();
+        // Record the attempt even on failure, to avoid hammering a failing issuer.
+        cache.fetched = Some(Instant::now());
+        cache.keys = serde_json::from_slice(&body?)
+            .map_err(|e| ApiError(format!("Invalid JWKS document: {}", e)))?;
+        Ok(())
+    }
+
+    /// Discard the cached keys, forcing a refresh on the next verification.
+    pub fn invalidate(&self) {
+        *self.write_cache() = KeyCache::default();
+    }
+
+    fn read_cache(&self) -> RwLockReadGuard<'_, KeyCache> {
+        self.inner
+            .cache
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn write_cache(&self) -> RwLockWriteGuard<'_, KeyCache> {
+        self.inner
+            .cache
+            .write()
+            .unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Look up the key with the given ID, and when the key set was fetched.
+    fn cached_key(&self, kid: Option<&str>) -> (Option<Jwk>, Option<Instant>) {
+        let cache = self.read_cache();
+        let key = match kid {
+            Some(kid) => cache.keys.find(kid),
+            None if cache.keys.keys.len() == 1 => cache.keys.keys.first(),
+            None => None,
+        };
+        (key.cloned(), cache.fetched)
+    }
+
+    /// Whether the key set should be refreshed, given the key found in it and
+    /// when it was fetched.
+    fn needs_refresh(&self, key: &Option<Jwk>, fetched: Option<Instant>) -> bool {
+        match (key, fetched.map(|fetched| fetched.elapsed())) {
+            (_, None) => true,
+            (Some(_), Some(age)) => age >= self.config.refresh_interval,
+            // Unknown key - the issuer may have rotated its keys.
+            (None, Some(age)) => age >= self.config.min_refresh_interval,
+        }
+    }
+
+    async fn key(&self, kid: Option<&str>) -> Result<Jwk, ApiError> {
+        let (mut key, fetched) = self.cached_key(kid);
+
+        if self.needs_refresh(&key, fetched) {
+            let _refreshing = self.inner.refreshing.lock().await;
+            // Another request may have refreshed the key set while we were
+            // waiting, in which case its result is used rather than fetching
+            // it again.
+            let (cached, refetched) = self.cached_key(kid);
+            if refetched != fetched {
+                return cached.ok_or_else(|| ApiError(format!("Unknown signing key {:?}", kid)));
+            }
+            key = cached;
+
+            match self.refresh().await {
+                Ok(()) => {}
+                // Continue to use a stale, but known, key if the issuer is unavailable.
+                Err(_) if key.is_some() => return Ok(key.unwrap()),
+                Err(e) => return Err(e),
+            }
+            if let (Some(key), _) = self.cached_key(kid) {
+                return Ok(key);
+            }
+        }
+
+        key.ok_or_else(|| ApiError(format!("Unknown signing key {:?}", kid)))
+    }
+
+    /// Check that tokens may be signed with the key using the algorithm.
+    fn check_algorithm(&self, key: &Jwk, algorithm: Algorithm) -> Result<(), ApiError> {
+        let allowed = match (&key.common.key_algorithm, &self.config.algorithms) {
+            (Some(key_algorithm), algorithms) => {
+                Algorithm::from_str(&key_algorithm.to_string()).ok() == Some(algorithm)
+                    && algorithms
+                        .as_ref()
+                        .is_none_or(|algorithms| algorithms.contains(&algorithm))
+            }
+            (None, Some(algorithms)) => algorithms.contains(&algorithm),
+            (None, None) => false,
+        };
+        if allowed {
+            Ok(())
+        } else {
+            Err(ApiError(format!(
+                "Invalid token: algorithm {:?} not allowed for signing key {:?}",
+                algorithm, key.common.key_id
+            )))
+        }
+    }
+
+    /// Verify a token, returning its claims.
+    ///
+    /// The token must be signed with an algorithm allowed for its key, as
+    /// described for [`with_algorithms`](Self::with_algorithms), regardless
+    /// of the algorithm given in the token's header.
+    pub async fn verify(&self, token: &str) -> Result<Claims, ApiError> {
+        let header = jsonwebtoken::decode_header(token)
+            .map_err(|e| ApiError(format!("Invalid token: {}", e)))?;
+        let jwk = self.key(header.kid.as_deref()).await?;
+        self.check_algorithm(&jwk, header.alg)?;
+        let key = DecodingKey::from_jwk(&jwk)
+            .map_err(|e| ApiError(format!("Invalid signing key {:?}: {}", header.kid, e)))?;
+
+        let mut validation = Validation::new(header.alg);
+        validation.set_issuer(&[&self.inner.issuer]);
+        match &self.config.audience {
+            Some(audience) => validation.set_audience(audience),
+            None => validation.validate_aud = false,
+        }
+
+        jsonwebtoken::decode::<Claims>(token, &key, &validation)
+            .map(|data| data.claims)
+            .map_err(|e| ApiError(format!("Invalid token: {}", e)))
+    }
+
+    /// Verify a token, returning the authorization it grants.
+    ///
+    /// The subject is taken from the `sub` claim, the scopes from the `scope`
+    /// or `scp` claims, and the issuer from the `azp` or `client_id` claims.
+    pub async fn authorize(&self, token: &str) -> Result<Authorization, ApiError> {
+        let claims = self.verify(token).await?;
+        Ok(authorization_from_claims(&claims))
+    }
+}
+
+/// Build an `Authorization` from the standard claims of a token.
+///
+/// All of the token's claims are also made available as the authorization's `claims`.
+pub fn authorization_from_claims(claims: &Claims) -> Authorization {
+    let string_claim = |name: &str| claims.get(name).and_then(|v| v.as_str());
+
+    let scopes = match (claims.get("scope"), claims.get("scp")) {
+        (Some(serde_json::Value::String(scope)), _) => {
+            scope.split_whitespace().map(ToString::to_string).collect()
+        }
+        (_, Some(serde_json::Value::Array(scp))) => scp
+            .iter()
+            .filter_map(|v| v.as_str())
+            .map(ToString::to_string)
+            .collect(),
+        (_, Some(serde_json::Value::String(scp))) => {
+            scp.split_whitespace().map(ToString::to_string).collect()
+        }
+        _ => Default::default(),
+    };
+
+    Authorization {
+        subject: string_claim("sub").unwrap_or_default().to_string(),
+        scopes: Scopes::Some(scopes),
+        issuer: string_claim("azp")
+            .or_else(|| string_claim("client_id"))
+            .map(ToString::to_string),
+        claims: claims.clone().into_iter().collect(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use http_body_util::Full;
+    use jsonwebtoken::{encode, Algorithm, EncodingKey, Header};
+    use std::collections::HashMap;
+    use std::sync::Mutex;
+
+    const ISSUER: &str = "https://issuer.example.com";
+
+    /// Fetcher serving fixed documents, counting the fetches made.
+    #[derive(Default)]
+    struct TestFetcher {
+        documents: Mutex<HashMap<String, String>>,
+        fetches: Mutex<u32>,
+    }
+
+    impl TestFetcher {
+        fn set(&self, uri: &str, body: serde_json::Value) {
+            self.documents
+                .lock()
+                .unwrap()
+                .insert(uri.to_string(), body.to_string());
+        }
+    }
+
+    impl Service<Request<Empty<Bytes>>> for TestFetcher {
+        type Response = Response<Full<Bytes>>;
+        type Error = std::convert::Infallible;
+        type Future = futures::future::Ready<Result<Self::Response, Self::Error>>;
+
+        fn call(&self, req: Request<Empty<Bytes>>) -> Self::Future {
+            *self.fetches.lock().unwrap() += 1;
+            let body = self
+                .documents
+                .lock()
+                .unwrap()
+                .get(&req.uri().to_string())
+                .cloned();
+            futures::future::ok(match body {
+                Some(body) => Response::new(Full::new(Bytes::from(body))),
+                None => Response::builder()
+                    .status(hyper::StatusCode::NOT_FOUND)
+                    .body(Full::default())
+                    .unwrap(),
+            })
+        }
+    }
+
+    fn jwks(kid: &str, secret: &[u8]) -> serde_json::Value {
+        use base64::engine::{general_purpose::URL_SAFE_NO_PAD, Engine};
+        serde_json::json!({
+            "keys": [{ "kty": "oct", "kid": kid, "alg": "HS256", "k": URL_SAFE_NO_PAD.encode(secret) }]
+        })
+    }
+
+    fn token(kid: &str, secret: &[u8]) -> String {
+        signed_token(Algorithm::HS256, kid, secret)
+    }
+
+    fn signed_token(algorithm: Algorithm, kid: &str, secret: &[u8]) -> String {
+        let mut header = Header::new(algorithm);
+        header.kid = Some(kid.to_string());
+        let claims = serde_json::json!({
+            "iss": ISSUER,
+            "sub": "alice",
+            "scope": "read write",
+            "azp": "my-client",
+            "exp": jsonwebtoken::get_current_timestamp() + 60,
+        });
+        encode(&header, &claims, &EncodingKey::from_secret(secret)).unwrap()
+    }
+
+    fn fetcher() -> Arc<TestFetcher> {
+        let fetcher = Arc::new(TestFetcher::default());
+        fetcher.set(
+            "https://issuer.example.com/.well-known/openid-configuration",
+            serde_json::json!({ "issuer": ISSUER, "jwks_uri": "https://issuer.example.com/jwks" }),
+        );
+        fetcher.set("https://issuer.example.com/jwks", jwks("key1", b"secret1"));
+        fetcher
+    }
+
+    #[tokio::test]
+    async fn test_discover_and_authorize() {
+        let verifier = OidcVerifier::discover(fetcher(), ISSUER).await.unwrap();
+
+        let authorization = verifier
+            .authorize(&token("key1", b"secret1"))
+            .await
+            .unwrap();
+        assert_eq!(authorization.subject, "alice");
+        assert_eq!(
+            authorization.scopes,
+            Scopes::Some(["read".to_string(), "write".to_string()].into())
+        );
+        assert_eq!(authorization.issuer, Some("my-client".to_string()));
+        assert_eq!(authorization.claim_str("iss"), Some(ISSUER));
+
+        assert!(verifier.verify(&token("key1", b"wrong")).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_key_rotation() {
+        let fetcher = fetcher();
+        let verifier = OidcVerifier::discover(fetcher.clone(), ISSUER)
+            .await
+            .unwrap();
+        // Clones share the key cache, but may be configured separately.
+        let _clone = verifier.clone();
+        let verifier = verifier.with_min_refresh_interval(Duration::from_secs(0));
+        assert_eq!(*fetcher.fetches.lock().unwrap(), 2);
+
+        fetcher.set("https://issuer.example.com/jwks", jwks("key2", b"secret2"));
+        verifier.verify(&token("key2", b"secret2")).await.unwrap();
+        assert_eq!(*fetcher.fetches.lock().unwrap(), 3);
+
+        // Known keys are served from the cache.
+        verifier.verify(&token("key2", b"secret2")).await.unwrap();
+        assert_eq!(*fetcher.fetches.lock().unwrap(), 3);
+    }
+
+    #[tokio::test]
+    async fn test_algorithms() {
+        let fetcher = fetcher();
+        let verifier = OidcVerifier::discover(fetcher.clone(), ISSUER)
+            .await
+            .unwrap();
+
+        // Tokens must be signed with the algorithm of the key, whatever their
+        // header claims.
+        let hs384 = signed_token(Algorithm::HS384, "key1", b"secret1");
+        assert!(verifier.verify(&hs384).await.is_err());
+
+        // Keys without an algorithm may only be used with those configured.
+        let mut keys = jwks("key1", b"secret1");
+        keys["keys"][0].as_object_mut().unwrap().remove("alg");
+        fetcher.set("https://issuer.example.com/jwks", keys);
+        verifier.refresh().await.unwrap();
+        assert!(verifier.verify(&token("key1", b"secret1")).await.is_err());
+
+        let verifier = verifier.with_algorithms(&[Algorithm::HS384]);
+        assert!(verifier.verify(&token("key1", b"secret1")).await.is_err());
+        assert!(verifier.verify(&hs384).await.is_ok());
+    }
+
+    /// Fetcher which yields before each fetch, so that verifications run
+    /// concurrently.
+    struct SlowFetcher(Arc<TestFetcher>);
+
+    impl Fetch for SlowFetcher {
+        fn fetch(&self, uri: Uri) -> BoxFuture<'static, Result<Bytes, ApiError>> {
+            let fetch = self.0.fetch(uri);
+            Box::pin(async move {
+                tokio::task::yield_now().await;
+                fetch.await
+            })
+        }
+    }
+
+    #[tokio::test]
+    async fn test_refresh_coalesced() {
+        let fetcher = fetcher();
+        let verifier = OidcVerifier::new(
+            SlowFetcher(fetcher.clone()),
+            ISSUER.to_string(),
+            "https://issuer.example.com/jwks".parse().unwrap(),
+        );
+
+        // Concurrent verifications share a single fetch of the key set, and
+        // those with unknown keys don't fetch it again within the minimum
+        // refresh interval.
+        let known = token("key1", b"secret1");
+        let unknown = token("key2", b"secret2");
+        let results = futures::future::join_all((0..5).map(|_| verifier.verify(&known))).await;
+        assert!(results.iter().all(Result::is_ok));
+        let results = futures::future::join_all((0..5).map(|_| verifier.verify(&unknown))).await;
+        assert!(results.iter().all(Result::is_err));
+        assert_eq!(*fetcher.fetches.lock().unwrap(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_issuer_mismatch() {
+        assert!(
+            OidcVerifier::discover(fetcher(), "https://other.example.com")
+                .await
+                .is_err()
+        );
+    }
+}
@@ -0,0 +1,79 @@
+//! Matching of requests against per-route authentication and authorization rules.
+use hyper::Method;
+
+#[derive(Clone, Debug)]
+struct Route {
+    prefix: String,
+    method: Option<Method>,
+    /// Whether the prefix is the path template of a single operation.
+    operation: bool,
+}
+
+impl Route {
+    fn matches(&self, method: &Method, path: &str) -> bool {
+        if self.method.as_ref().is_some_and(|m| m != method) {
+            return false;
+        }
+
+        if self.operation {
+            let mut template = self.prefix.split('/');
+            let mut path = path.split('/');
+            return loop {
+                match (template.next(), path.next()) {
+                    (None, None) => break true,
+                    (Some(t), Some(p)) if t == p || (t.starts_with('{') && t.ends_with('}')) => {}
+                    _ => break false,
+                }
+            };
+        }
+
+        match path.strip_prefix(self.prefix.as_str()) {
+            Some(rest) => rest.is_empty() || rest.starts_with('/') || self.prefix.ends_with('/'),
+            None => false,
+        }
+    }
+}
+
+/// Values attached to routes, identified by a path prefix or operation path
+/// template, and optionally a method.
+///
+/// Where several routes match a request, operations take precedence over path
+/// prefixes, then longer prefixes over shorter ones, then routes for a specific
+/// method over routes for any method.
+#[derive(Clone, Debug)]
+pub(super) struct RouteTable<V> {
+    routes: Vec<(Route, V)>,
+}
+
+impl<V> Default for RouteTable<V> {
+    fn default() -> Self {
+        RouteTable { routes: Vec::new() }
+    }
+}
+
+impl<V> RouteTable<V> {
+    pub(super) fn insert(
+        &mut self,
+        prefix: String,
+        method: Option<Method>,
+        operation: bool,
+        value: V,
+    ) {
+        self.routes.push((
+            Route {
+                prefix,
+                method,
+                operation,
+            },
+            value,
+        ));
+    }
+
+    pub(super) fn get(&self, method: &Method, path: &str) -> Option<&V> {
+        self.routes
+            .iter()
+            .filter(|(route, _)| route.matches(method, path))
+            .max_by_key(|(route, _)| (route.operation, route.prefix.len(), route.method.is_some()))
+            .map(|(_, value)| value)
+    }
+}
@@ -0,0 +1,118 @@
+//! Registry of parsers for custom authentication schemes.
+use super::AuthData;
+use hyper::header::{HeaderMap, AUTHORIZATION};
+use std::fmt;
+use std::sync::Arc;
+
+type SchemeParser = Arc<dyn Fn(&str) -> Option<AuthData> + Send + Sync>;
+
+/// Parsers for credentials in an `Authorization` header using schemes other
+/// than Basic and Bearer.
+///
+/// A registry is passed to the middleware which should use it, such as
+/// `BasicAuthenticator::with_scheme_registry` and
+/// `SecurityPolicy::with_scheme_registry`, or used directly in place of
+/// `from_headers`:
+///
+/// ```
+/// use swagger::auth::{AuthData, SchemeRegistry};
+/// use hyper::header::{HeaderMap, HeaderValue, AUTHORIZATION};
+///
+/// let registry = SchemeRegistry::new()
+///     .with_scheme("Hawk", |credentials| Some(AuthData::other("Hawk", credentials)));
+///
+/// let mut headers = HeaderMap::new();
+/// headers.insert(AUTHORIZATION, HeaderValue::from_static("Hawk id=\"dh37fgj492je\""));
+/// assert_eq!(
+///     registry.from_headers(&headers),
+///     Some(AuthData::other("Hawk", "id=\"dh37fgj492je\""))
+/// );
+/// ```
+#[derive(Clone, Default)]
+pub struct SchemeRegistry {
+    schemes: Vec<(String, SchemeParser)>,
+}
+
+impl fmt::Debug for SchemeRegistry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_list()
+            .entries(self.schemes.iter().map(|(name, _)| name))
+            .finish()
+    }
+}
+
+impl SchemeRegistry {
+    /// Create a registry with no parsers, which only parses Basic and Bearer
+    /// credentials.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Register a parser for credentials using the given scheme.
+    ///
+    /// The parser is passed the credentials following the scheme name, and
+    /// returns the `AuthData` to store in the context, or `None` if they are
+    /// malformed. Scheme names are matched case insensitively, and a parser
+    /// registered for `Basic` or `Bearer` replaces the built-in parsing.
+    pub fn with_scheme<F>(mut self, scheme: &str, parser: F) -> Self
+    where
+        F: Fn(&str) -> Option<AuthData> + Send + Sync + 'static,
+    {
+        self.schemes
+            .retain(|(name, _)| !name.eq_ignore_ascii_case(scheme));
+        self.schemes.push((scheme.to_string(), Arc::new(parser)));
+        self
+    }
+
+    /// Retrieve credentials from a set of headers, using the parser
+    /// registered for their scheme, or as by `from_headers` if there is none.
+    pub fn from_headers(&self, headers: &HeaderMap) -> Option<AuthData> {
+        let value = headers.get(AUTHORIZATION)?.to_str().ok()?;
+        let (scheme, credentials) = value.split_once(' ').unwrap_or((value, ""));
+        match self
+            .schemes
+            .iter()
+            .find(|(name, _)| name.eq_ignore_ascii_case(scheme))
+        {
+            Some((_, parser)) => parser(credentials.trim()),
+            None => super::from_headers(headers),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hyper::header::HeaderValue;
+
+    #[test]
+    fn test_registered_scheme() {
+        let mut headers = HeaderMap::new();
+        headers.insert(AUTHORIZATION, HeaderValue::from_static("test-scheme  abc"));
+        assert_eq!(SchemeRegistry::new().from_headers(&headers), None);
+
+        let registry = SchemeRegistry::new().with_scheme("Test-Scheme", |credentials| {
+            (credentials != "invalid").then(|| AuthData::other("Test-Scheme", credentials))
+        });
+        assert_eq!(
+            registry.from_headers(&headers),
+            Some(AuthData::other("Test-Scheme", "abc"))
+        );
+
+        headers.insert(
+            AUTHORIZATION,
+            HeaderValue::from_static("Test-Scheme invalid"),
+        );
+        assert_eq!(registry.from_headers(&headers), None);
+
+        // Other schemes are parsed as usual.
+        headers.insert(AUTHORIZATION, HeaderValue::from_static("Bearer foo"));
+        assert_eq!(
+            registry.from_headers(&headers),
+            Some(AuthData::Bearer("foo".into()))
+        );
+
+        let registry = registry.with_scheme("bearer", |_| None);
+        assert_eq!(registry.from_headers(&headers), None);
+    }
+}
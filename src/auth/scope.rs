@@ -0,0 +1,388 @@
+//! Middleware enforcing the authorization scopes required by each route.
+use super::audit::Auditor;
+use super::route::RouteTable;
+use super::{AuditSink, Authorization, BearerError, Challenge};
+use crate::context::Has;
+use crate::XSpanIdString;
+use futures::future::{BoxFuture, FutureExt};
+use hyper::header::{HeaderValue, CONTENT_TYPE};
+use hyper::service::Service;
+use hyper::{Method, Request, Response};
+use std::collections::BTreeSet;
+use std::marker::PhantomData;
+use std::sync::Arc;
+
+/// Map from route to the authorization scopes required to access it.
+///
+/// Routes are identified by a path prefix, matched on whole path segments, and
+/// optionally a method. Where several rules match a request, the one with the
+/// longest prefix is used, preferring rules for a specific method over rules
+/// for any method. Requests matching no rule require no scopes.
+///
+/// Rules may also be given for individual operations, identified by a method
+/// and an OpenAPI path template such as `/pets/{petId}`, which take precedence
+/// over rules for path prefixes.
+#[derive(Clone, Debug, Default)]
+pub struct ScopePolicy {
+    rules: RouteTable<BTreeSet<String>>,
+}
+
+impl ScopePolicy {
+    /// Create an empty policy, which requires no scopes for any route.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Require the given scopes for requests with any method under the path prefix.
+    pub fn require<P, I, S>(self, prefix: P, scopes: I) -> Self
+    where
+        P: Into<String>,
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.add_rule(prefix.into(), None, scopes, false)
+    }
+
+    /// Require the given scopes for requests with the given method under the path prefix.
+    pub fn require_for<P, I, S>(self, method: Method, prefix: P, scopes: I) -> Self
+    where
+        P: Into<String>,
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.add_rule(prefix.into(), Some(method), scopes, false)
+    }
+
+    /// Require the given scopes for the operation with the given method and path template.
+    pub fn require_operation<P, I, S>(self, method: Method, path: P, scopes: I) -> Self
+    where
+        P: Into<String>,
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.add_rule(path.into(), Some(method), scopes, true)
+    }
+
+    fn add_rule<I, S>(
+        mut self,
+        prefix: String,
+        method: Option<Method>,
+        scopes: I,
+        operation: bool,
+    ) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.rules.insert(
+            prefix,
+            method,
+            operation,
+            scopes.into_iter().map(Into::into).collect(),
+        );
+        self
+    }
+
+    /// Scopes required for a request with the given method and path, or `None`
+    /// if no rule matches.
+    pub fn required_scopes(&self, method: &Method, path: &str) -> Option<&BTreeSet<String>> {
+        self.rules.get(method, path)
+    }
+}
+
+/// Build the response rejecting a request, following RFC 6750 section 3.
+fn reject<B: From<String>>(required: &BTreeSet<String>, authorized: bool) -> Response<B> {
+    let challenge = Challenge::bearer().with_scope(required.iter().cloned());
+    if !authorized {
+        let mut response = Response::new(B::from(String::new()));
+        challenge.apply(&mut response);
+        return response;
+    }
+
+    let description = "The request requires higher privileges than provided by the access token.";
+    let challenge = challenge.with_error(BearerError::InsufficientScope);
+    let body = format!(
+        "{{\"error\":\"{}\",\"error_description\":\"{}\",\"scope\":\"{}\"}}",
+        BearerError::InsufficientScope,
+        description,
+        required.iter().cloned().collect::<Vec<_>>().join(" ")
+    );
+    let mut response = Response::new(B::from(body));
+    challenge.apply(&mut response);
+    response
+        .headers_mut()
+        .insert(CONTENT_TYPE, HeaderValue::from_static("application/json"));
+    response
+}
+
+/// Middleware which rejects requests whose authorization doesn't cover the
+/// scopes required by the route, according to a `ScopePolicy`.
+#[derive(Debug)]
+pub struct MakeScopeEnforcer<T, RC> {
+    inner: T,
+    policy: Arc<ScopePolicy>,
+    audit: Auditor,
+    marker: PhantomData<RC>,
+}
+
+impl<T, RC> MakeScopeEnforcer<T, RC> {
+    /// Create a middleware that enforces the given policy.
+    pub fn new(inner: T, policy: ScopePolicy) -> Self {
+        MakeScopeEnforcer {
+            inner,
+            policy: Arc::new(policy),
+            audit: Auditor::default(),
+            marker: PhantomData,
+        }
+    }
+
+    /// Record each decision to accept or deny a request in the given sink.
+    pub fn with_audit_sink<S: AuditSink + 'static>(mut self, sink: S) -> Self {
+        self.audit = Auditor::new(sink);
+        self
+    }
+}
+
+impl<Inner, RC, Target> Service<Target> for MakeScopeEnforcer<Inner, RC>
+where
+    Inner: Service<Target>,
+    Inner::Future: Send + 'static,
+    RC: Send + 'static,
+{
+    type Error = Inner::Error;
+    type Response = ScopeEnforcer<Inner::Response, RC>;
+    type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn call(&self, target: Target) -> Self::Future {
+        let policy = self.policy.clone();
+        let audit = self.audit.clone();
+        Box::pin(self.inner.call(target).map(|s| {
+            Ok(ScopeEnforcer {
+                inner: s?,
+                policy,
+                audit,
+                marker: PhantomData,
+            })
+        }))
+    }
+}
+
+/// Middleware which rejects requests whose authorization doesn't cover the
+/// scopes required by the route, according to a `ScopePolicy`.
+///
+/// This must be placed after an authenticator, which stores the
+/// `Option<Authorization>` in the context. Requests without authorization
+/// are rejected with `401 Unauthorized`, and requests with insufficient scopes
+/// with `403 Forbidden` and an `insufficient_scope` error.
+///
+/// ```ignore
+/// let policy = ScopePolicy::new()
+///     .require("/pets", ["pets:read"])
+///     .require_for(Method::POST, "/pets", ["pets:write"]);
+/// let service = ScopeEnforcer::new(inner, policy);
+/// ```
+#[derive(Debug)]
+pub struct ScopeEnforcer<T, RC> {
+    inner: T,
+    policy: Arc<ScopePolicy>,
+    audit: Auditor,
+    marker: PhantomData<RC>,
+}
+
+impl<T, RC> ScopeEnforcer<T, RC> {
+    /// Create a middleware that enforces the given policy.
+    pub fn new(inner: T, policy: ScopePolicy) -> Self {
+        ScopeEnforcer {
+            inner,
+            policy: Arc::new(policy),
+            audit: Auditor::default(),
+            marker: PhantomData,
+        }
+    }
+
+    /// Record each decision to accept or deny a request in the given sink.
+    pub fn with_audit_sink<S: AuditSink + 'static>(mut self, sink: S) -> Self {
+        self.audit = Auditor::new(sink);
+        self
+    }
+}
+
+impl<T, RC> Clone for ScopeEnforcer<T, RC>
+where
+    T: Clone,
+{
+    fn clone(&self) -> Self {
+        Self {
+            inner: self.inner.clone(),
+            policy: self.policy.clone(),
+            audit: self.audit.clone(),
+            marker: PhantomData,
+        }
+    }
+}
+
+impl<T, B, ResBody, RC> Service<(Request<B>, RC)> for ScopeEnforcer<T, RC>
+where
+    RC: Has<Option<Authorization>> + Has<XSpanIdString>,
+    T: Service<(Request<B>, RC), Response = Response<ResBody>>,
+    T::Future: Send + 'static,
+    T::Error: Send + 'static,
+    ResBody: From<String> + Send + 'static,
+{
+    type Response = Response<ResBody>;
+    type Error = T::Error;
+    type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn call(&self, req: (Request<B>, RC)) -> Self::Future {
+        let (request, context) = req;
+
+        if let Some(required) = self
+            .policy
+            .required_scopes(request.method(), request.uri().path())
+        {
+            let span_id = Has::<XSpanIdString>::get(&context);
+            let authorization: &Option<Authorization> = context.get();
+            match authorization {
+                Some(authorization) if authorization.scopes.covers(required) => {
+                    self.audit
+                        .accept(&request, span_id, None, Some(authorization));
+                }
+                Some(authorization) => {
+                    let subject = Some(authorization.subject.as_str());
+                    self.audit
+                        .deny(&request, span_id, None, subject, "Insufficient scope");
+                    return Box::pin(futures::future::ok(reject(required, true)));
+                }
+                None => {
+                    self.audit
+                        .deny(&request, span_id, None, None, "Not authorized");
+                    return Box::pin(futures::future::ok(reject(required, false)));
+                }
+            }
+        }
+
+        Box::pin(self.inner.call((request, context)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::auth::audit::tests::TestSink;
+    use crate::auth::{AuditOutcome, Scopes};
+    use crate::context::{ContextBuilder, Push};
+    use crate::EmptyContext;
+    use hyper::header::WWW_AUTHENTICATE;
+    use hyper::StatusCode;
+
+    type Context =
+        ContextBuilder<Option<Authorization>, ContextBuilder<XSpanIdString, EmptyContext>>;
+
+    struct OkService;
+
+    impl Service<(Request<()>, Context)> for OkService {
+        type Response = Response<String>;
+        type Error = ();
+        type Future = futures::future::Ready<Result<Self::Response, Self::Error>>;
+
+        fn call(&self, _: (Request<()>, Context)) -> Self::Future {
+            futures::future::ok(Response::new(String::new()))
+        }
+    }
+
+    fn policy() -> ScopePolicy {
+        ScopePolicy::new()
+            .require("/pets", ["pets:read"])
+            .require_for(Method::POST, "/pets", ["pets:write"])
+            .require("/pets/admin", ["pets:read", "admin"])
+            .require_operation(Method::DELETE, "/pets/{petId}", ["pets:delete"])
+    }
+
+    #[test]
+    fn test_required_scopes() {
+        let policy = policy();
+        let scopes = |method, path| {
+            policy
+                .required_scopes(&method, path)
+                .map(|s| s.iter().cloned().collect::<Vec<_>>())
+        };
+
+        assert_eq!(
+            scopes(Method::GET, "/pets/1"),
+            Some(vec!["pets:read".into()])
+        );
+        assert_eq!(
+            scopes(Method::POST, "/pets"),
+            Some(vec!["pets:write".into()])
+        );
+        assert_eq!(
+            scopes(Method::POST, "/pets/admin"),
+            Some(vec!["admin".into(), "pets:read".into()])
+        );
+        assert_eq!(
+            scopes(Method::DELETE, "/pets/1"),
+            Some(vec!["pets:delete".into()])
+        );
+        assert_eq!(
+            scopes(Method::DELETE, "/pets/1/owner"),
+            Some(vec!["pets:read".into()])
+        );
+        assert_eq!(scopes(Method::GET, "/petshop"), None);
+        assert_eq!(scopes(Method::GET, "/"), None);
+    }
+
+    #[tokio::test]
+    async fn test_scope_enforcer() {
+        let sink = TestSink::default();
+        let service = ScopeEnforcer::new(OkService, policy()).with_audit_sink(sink.clone());
+        let call = |path: &'static str, scopes: Option<Scopes>| {
+            let authorization = scopes.map(|scopes| Authorization::new("foo".to_string(), scopes));
+            let context: Context = EmptyContext
+                .push(XSpanIdString("span".to_string()))
+                .push(authorization);
+            service.call((Request::get(path).body(()).unwrap(), context))
+        };
+        let read = || Scopes::Some(["pets:read".to_string()].into_iter().collect());
+
+        let response = call("/pets/1", Some(read())).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let response = call("/unprotected", None).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let response = call("/pets/admin", Some(Scopes::All)).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let response = call("/pets/1", None).await.unwrap();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        assert_eq!(
+            response.headers().get(WWW_AUTHENTICATE).unwrap(),
+            "Bearer scope=\"pets:read\""
+        );
+
+        let response = call("/pets/admin", Some(read())).await.unwrap();
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+        assert_eq!(
+            response.headers().get(WWW_AUTHENTICATE).unwrap(),
+            "Bearer error=\"insufficient_scope\", scope=\"admin pets:read\""
+        );
+        assert!(response
+            .into_body()
+            .contains("\"error\":\"insufficient_scope\""));
+
+        let events = sink.take();
+        let outcomes: Vec<_> = events.iter().map(|event| event.outcome).collect();
+        assert_eq!(
+            outcomes,
+            [
+                AuditOutcome::Accepted,
+                AuditOutcome::Accepted,
+                AuditOutcome::Denied,
+                AuditOutcome::Denied
+            ]
+        );
+        assert_eq!(events[3].span_id, "span");
+        assert_eq!(events[3].subject.as_deref(), Some("foo"));
+        assert_eq!(events[3].reason.as_deref(), Some("Insufficient scope"));
+    }
+}
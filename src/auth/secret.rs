@@ -0,0 +1,95 @@
+//! Wrapper for secret credentials, such as passwords and tokens.
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use zeroize::{Zeroize, ZeroizeOnDrop};
+
+/// A secret, such as a password, token or API key.
+///
+/// The secret is redacted from `Debug` output, so that it can't be logged by
+/// accident, is compared in constant time, and is zeroized when dropped. Use
+/// `expose_secret` to access its value.
+#[derive(Clone, Default, Zeroize, ZeroizeOnDrop)]
+pub struct Secret(String);
+
+impl Secret {
+    /// Wrap a secret value.
+    pub fn new<S: Into<String>>(secret: S) -> Self {
+        Secret(secret.into())
+    }
+
+    /// The value of the secret.
+    pub fn expose_secret(&self) -> &str {
+        &self.0
+    }
+}
+
+impl From<String> for Secret {
+    fn from(secret: String) -> Self {
+        Secret(secret)
+    }
+}
+
+impl From<&str> for Secret {
+    fn from(secret: &str) -> Self {
+        Secret(secret.to_owned())
+    }
+}
+
+impl fmt::Debug for Secret {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("Secret([REDACTED])")
+    }
+}
+
+impl PartialEq for Secret {
+    fn eq(&self, other: &Self) -> bool {
+        constant_time_eq(&self.0, &other.0)
+    }
+}
+
+impl Eq for Secret {}
+
+impl PartialEq<str> for Secret {
+    fn eq(&self, other: &str) -> bool {
+        constant_time_eq(&self.0, other)
+    }
+}
+
+impl PartialEq<&str> for Secret {
+    fn eq(&self, other: &&str) -> bool {
+        constant_time_eq(&self.0, other)
+    }
+}
+
+impl Hash for Secret {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.0.hash(state)
+    }
+}
+
+/// Compare two strings in time independent of where they first differ.
+pub(crate) fn constant_time_eq(a: &str, b: &str) -> bool {
+    a.len() == b.len()
+        && a.bytes()
+            .zip(b.bytes())
+            .fold(0, |acc, (a, b)| acc | (a ^ b))
+            == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::AuthData;
+
+    #[test]
+    fn test_secret() {
+        let secret = Secret::new("hunter2");
+        assert_eq!(secret, "hunter2");
+        assert_ne!(secret, "hunter3");
+        assert_ne!(secret, Secret::from("hunter"));
+        assert_eq!(secret.expose_secret(), "hunter2");
+
+        let debug = format!("{:?}", AuthData::basic("alice", "hunter2"));
+        assert_eq!(debug, "Basic(\"alice\", Secret([REDACTED]))");
+    }
+}
@@ -0,0 +1,637 @@
+//! Evaluation of OpenAPI security requirements, combining several schemes.
+use super::audit::Auditor;
+use super::route::RouteTable;
+use super::{
+    ApiKeyLocation, AuditSink, AuthData, Authorization, Challenge, CredentialValidator, RcBound,
+    SchemeRegistry, Scopes,
+};
+use crate::context::Has;
+use crate::XSpanIdString;
+use futures::future::{BoxFuture, FutureExt};
+use hyper::service::Service;
+use hyper::{Method, Request, Response, StatusCode};
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::marker::PhantomData;
+use std::sync::Arc;
+
+type Validate = Arc<dyn Fn(&AuthData) -> BoxFuture<'static, Option<Authorization>> + Send + Sync>;
+
+#[derive(Clone, Debug)]
+enum SchemeKind {
+    Basic { realm: String },
+    Bearer,
+    ApiKey(ApiKeyLocation),
+}
+
+#[derive(Clone)]
+struct Scheme {
+    kind: SchemeKind,
+    validate: Validate,
+}
+
+impl fmt::Debug for Scheme {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.kind.fmt(f)
+    }
+}
+
+impl Scheme {
+    fn credentials<B>(&self, request: &Request<B>, registry: &SchemeRegistry) -> Option<AuthData> {
+        match &self.kind {
+            SchemeKind::Basic { .. } => registry
+                .from_headers(request.headers())
+                .filter(|c| matches!(c, AuthData::Basic(..))),
+            SchemeKind::Bearer => registry
+                .from_headers(request.headers())
+                .filter(|c| matches!(c, AuthData::Bearer(..))),
+            SchemeKind::ApiKey(location) => location
+                .extract(request)
+                .map(|key| AuthData::ApiKey(key.into())),
+        }
+    }
+
+    fn challenge(&self) -> Option<Challenge> {
+        match &self.kind {
+            SchemeKind::Basic { realm } => Some(
+                Challenge::basic()
+                    .with_realm(realm.as_str())
+                    .with_param("charset", "UTF-8"),
+            ),
+            SchemeKind::Bearer => Some(Challenge::bearer()),
+            SchemeKind::ApiKey(_) => None,
+        }
+    }
+}
+
+/// A combination of security schemes which must all be satisfied, matching
+/// an entry in the `security` list of an OpenAPI operation.
+///
+/// The empty requirement is satisfied by any request, making authentication
+/// optional.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct SecurityRequirement {
+    schemes: Vec<String>,
+}
+
+impl SecurityRequirement {
+    /// Require all of the named schemes.
+    pub fn new<I, S>(schemes: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        SecurityRequirement {
+            schemes: schemes.into_iter().map(Into::into).collect(),
+        }
+    }
+
+    /// The empty requirement, satisfied by unauthenticated requests.
+    pub fn anonymous() -> Self {
+        Self::default()
+    }
+
+    /// Names of the schemes required.
+    pub fn schemes(&self) -> &[String] {
+        &self.schemes
+    }
+}
+
+/// Security schemes of an API, and the alternative requirements for each route.
+///
+/// A request is authenticated if it satisfies any one of the alternative
+/// requirements for its route, as for the `security` list of an OpenAPI
+/// operation. Routes are matched as by a `ScopePolicy`.
+///
+/// ```ignore
+/// let policy = SecurityPolicy::new()
+///     .basic("basicAuth", "my-api", check_password)
+///     .bearer("oauth", introspect)
+///     .api_key("apiKey", ApiKeyLocation::Header("X-API-Key".to_string()), check_key)
+///     .require(vec![
+///         SecurityRequirement::new(["apiKey", "oauth"]),
+///         SecurityRequirement::new(["basicAuth"]),
+///     ])
+///     .operation(Method::GET, "/pets", vec![
+///         SecurityRequirement::anonymous(),
+///         SecurityRequirement::new(["oauth"]),
+///     ]);
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct SecurityPolicy {
+    schemes: BTreeMap<String, Scheme>,
+    registry: SchemeRegistry,
+    default: Vec<SecurityRequirement>,
+    routes: RouteTable<Vec<SecurityRequirement>>,
+}
+
+impl SecurityPolicy {
+    /// Create a policy with no schemes, which requires no authentication.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn scheme<N, V>(mut self, name: N, kind: SchemeKind, validator: V) -> Self
+    where
+        N: Into<String>,
+        V: CredentialValidator + 'static,
+    {
+        let validate: Validate =
+            Arc::new(move |credentials| validator.validate(credentials).boxed());
+        self.schemes.insert(name.into(), Scheme { kind, validate });
+        self
+    }
+
+    /// Add a scheme taking HTTP Basic credentials, validated by the validator.
+    pub fn basic<N, R, V>(self, name: N, realm: R, validator: V) -> Self
+    where
+        N: Into<String>,
+        R: Into<String>,
+        V: CredentialValidator + 'static,
+    {
+        let realm = realm.into();
+        self.scheme(name, SchemeKind::Basic { realm }, validator)
+    }
+
+    /// Add a scheme taking bearer tokens, validated by the validator.
+    pub fn bearer<N, V>(self, name: N, validator: V) -> Self
+    where
+        N: Into<String>,
+        V: CredentialValidator + 'static,
+    {
+        self.scheme(name, SchemeKind::Bearer, validator)
+    }
+
+    /// Add a scheme taking API keys from the given location, validated by the validator.
+    pub fn api_key<N, V>(self, name: N, location: ApiKeyLocation, validator: V) -> Self
+    where
+        N: Into<String>,
+        V: CredentialValidator + 'static,
+    {
+        self.scheme(name, SchemeKind::ApiKey(location), validator)
+    }
+
+    /// Parse the `Authorization` header using the given registry, which may
+    /// replace the built-in parsing of Basic and Bearer credentials.
+    pub fn with_scheme_registry(mut self, registry: SchemeRegistry) -> Self {
+        self.registry = registry;
+        self
+    }
+
+    /// Set the alternative requirements for requests matching no route.
+    pub fn require(mut self, requirements: Vec<SecurityRequirement>) -> Self {
+        self.default = requirements;
+        self
+    }
+
+    /// Set the alternative requirements for requests with any method under the path prefix.
+    pub fn route<P: Into<String>>(
+        mut self,
+        prefix: P,
+        requirements: Vec<SecurityRequirement>,
+    ) -> Self {
+        self.routes.insert(prefix.into(), None, false, requirements);
+        self
+    }
+
+    /// Set the alternative requirements for the operation with the given method and path template.
+    pub fn operation<P: Into<String>>(
+        mut self,
+        method: Method,
+        path: P,
+        requirements: Vec<SecurityRequirement>,
+    ) -> Self {
+        self.routes
+            .insert(path.into(), Some(method), true, requirements);
+        self
+    }
+
+    /// Alternative requirements for a request with the given method and path.
+    /// If empty, no authentication is required.
+    pub fn requirements(&self, method: &Method, path: &str) -> &[SecurityRequirement] {
+        self.routes.get(method, path).unwrap_or(&self.default)
+    }
+}
+
+/// Outcome of evaluating the security requirements of a request.
+enum Outcome {
+    /// Authorized by the schemes of a requirement, with their names.
+    Authorized(Option<Authorization>, Option<String>),
+    /// Unauthorized, with the challenges to send and the reason.
+    Unauthorized(Vec<Challenge>, &'static str),
+}
+
+/// Combine the authorizations granted by several schemes, taking the subject
+/// and issuer of the first and the scopes granted by all.
+fn combine(authorizations: Vec<Authorization>) -> Option<Authorization> {
+    let mut authorizations = authorizations.into_iter();
+    let mut combined = authorizations.next()?;
+    for authorization in authorizations {
+        combined.scopes = match (combined.scopes, authorization.scopes) {
+            (Scopes::All, scopes) | (scopes, Scopes::All) => scopes,
+            (Scopes::Some(a), Scopes::Some(b)) => {
+                Scopes::Some(a.intersection(&b).cloned().collect())
+            }
+        };
+    }
+    Some(combined)
+}
+
+async fn evaluate(
+    policy: &SecurityPolicy,
+    requirements: &[SecurityRequirement],
+    credentials: HashMap<&str, AuthData>,
+) -> Outcome {
+    let mut results: HashMap<&str, Option<Authorization>> = HashMap::new();
+    let mut invalid = false;
+
+    'requirements: for requirement in requirements.iter().filter(|r| !r.schemes.is_empty()) {
+        let mut authorizations = Vec::new();
+        for name in &requirement.schemes {
+            let (scheme, credentials) = match (
+                policy.schemes.get(name.as_str()),
+                credentials.get(name.as_str()),
+            ) {
+                (Some(scheme), Some(credentials)) => (scheme, credentials),
+                _ => continue 'requirements,
+            };
+            let authorization = match results.get(name.as_str()) {
+                Some(result) => result.clone(),
+                None => {
+                    let result = (scheme.validate)(credentials).await;
+                    invalid |= result.is_none();
+                    results.insert(name.as_str(), result.clone());
+                    result
+                }
+            };
+            match authorization {
+                Some(authorization) => authorizations.push(authorization),
+                None => continue 'requirements,
+            }
+        }
+        let schemes = requirement.schemes.join(" ");
+        return Outcome::Authorized(combine(authorizations), Some(schemes));
+    }
+
+    if !invalid && requirements.iter().any(|r| r.schemes.is_empty()) {
+        return Outcome::Authorized(None, None);
+    }
+
+    let mut challenges = Vec::new();
+    for name in requirements.iter().flat_map(|r| &r.schemes) {
+        if let Some(challenge) = policy.schemes.get(name).and_then(Scheme::challenge) {
+            if !challenges.contains(&challenge) {
+                challenges.push(challenge);
+            }
+        }
+    }
+    let reason = if invalid {
+        "Invalid credentials"
+    } else {
+        "Missing credentials"
+    };
+    Outcome::Unauthorized(challenges, reason)
+}
+
+/// Middleware which authenticates requests according to a `SecurityPolicy`.
+#[derive(Debug)]
+pub struct MakeSecurityEvaluator<T, RC> {
+    inner: T,
+    policy: Arc<SecurityPolicy>,
+    audit: Auditor,
+    marker: PhantomData<RC>,
+}
+
+impl<T, RC> MakeSecurityEvaluator<T, RC> {
+    /// Create a middleware that authenticates requests according to the policy.
+    pub fn new(inner: T, policy: SecurityPolicy) -> Self {
+        MakeSecurityEvaluator {
+            inner,
+            policy: Arc::new(policy),
+            audit: Auditor::default(),
+            marker: PhantomData,
+        }
+    }
+
+    /// Record each decision to accept or deny a request in the given sink.
+    pub fn with_audit_sink<S: AuditSink + 'static>(mut self, sink: S) -> Self {
+        self.audit = Auditor::new(sink);
+        self
+    }
+}
+
+impl<Inner, RC, Target> Service<Target> for MakeSecurityEvaluator<Inner, RC>
+where
+    Inner: Service<Target>,
+    Inner::Future: Send + 'static,
+    RC: Send + 'static,
+{
+    type Error = Inner::Error;
+    type Response = SecurityEvaluator<Inner::Response, RC>;
+    type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn call(&self, target: Target) -> Self::Future {
+        let policy = self.policy.clone();
+        let audit = self.audit.clone();
+        Box::pin(self.inner.call(target).map(|s| {
+            Ok(SecurityEvaluator {
+                inner: s?,
+                policy,
+                audit,
+                marker: PhantomData,
+            })
+        }))
+    }
+}
+
+/// Middleware which authenticates requests according to a `SecurityPolicy`.
+///
+/// The credentials for each scheme are validated at most once per request.
+/// If any of the alternative requirements for the route is satisfied, the
+/// combined authorization of its schemes is pushed to the context, with the
+/// subject and issuer of the first scheme, and the scopes granted by every
+/// scheme. If only the empty requirement is satisfied, `None` is pushed,
+/// unless the request carried invalid credentials. Otherwise, the request is
+/// rejected with `401 Unauthorized`, challenging the client to authenticate
+/// with the HTTP schemes of the route.
+#[derive(Debug)]
+pub struct SecurityEvaluator<T, RC> {
+    inner: T,
+    policy: Arc<SecurityPolicy>,
+    audit: Auditor,
+    marker: PhantomData<RC>,
+}
+
+impl<T, RC> SecurityEvaluator<T, RC> {
+    /// Create a middleware that authenticates requests according to the policy.
+    pub fn new(inner: T, policy: SecurityPolicy) -> Self {
+        SecurityEvaluator {
+            inner,
+            policy: Arc::new(policy),
+            audit: Auditor::default(),
+            marker: PhantomData,
+        }
+    }
+
+    /// Record each decision to accept or deny a request in the given sink.
+    pub fn with_audit_sink<S: AuditSink + 'static>(mut self, sink: S) -> Self {
+        self.audit = Auditor::new(sink);
+        self
+    }
+}
+
+impl<T, RC> Clone for SecurityEvaluator<T, RC>
+where
+    T: Clone,
+{
+    fn clone(&self) -> Self {
+        Self {
+            inner: self.inner.clone(),
+            policy: self.policy.clone(),
+            audit: self.audit.clone(),
+            marker: PhantomData,
+        }
+    }
+}
+
+impl<T, B, ResBody, RC> Service<(Request<B>, RC)> for SecurityEvaluator<T, RC>
+where
+    RC: RcBound + Has<XSpanIdString>,
+    RC::Result: Send + 'static,
+    T: Service<(Request<B>, RC::Result), Response = Response<ResBody>> + Clone + Send + 'static,
+    T::Future: Send + 'static,
+    B: Send + 'static,
+    ResBody: Default + Send + 'static,
+{
+    type Response = Response<ResBody>;
+    type Error = T::Error;
+    type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn call(&self, req: (Request<B>, RC)) -> Self::Future {
+        let (request, context) = req;
+        let evaluator = self.clone();
+
+        Box::pin(async move {
+            let span_id = Has::<XSpanIdString>::get(&context).clone();
+            let policy = &evaluator.policy;
+            let requirements = policy.requirements(request.method(), request.uri().path());
+            if requirements.is_empty() {
+                evaluator.audit.accept(&request, &span_id, None, None);
+                let context = context.push(None);
+                return evaluator.inner.call((request, context)).await;
+            }
+
+            let credentials = policy
+                .schemes
+                .iter()
+                .filter_map(|(name, scheme)| {
+                    Some((
+                        name.as_str(),
+                        scheme.credentials(&request, &policy.registry)?,
+                    ))
+                })
+                .collect();
+
+            match evaluate(policy, requirements, credentials).await {
+                Outcome::Authorized(authorization, schemes) => {
+                    evaluator.audit.accept(
+                        &request,
+                        &span_id,
+                        schemes.as_deref(),
+                        authorization.as_ref(),
+                    );
+                    let context = context.push(authorization);
+                    evaluator.inner.call((request, context)).await
+                }
+                Outcome::Unauthorized(challenges, reason) => {
+                    evaluator.audit.deny(&request, &span_id, None, None, reason);
+                    let mut response = Response::new(ResBody::default());
+                    *response.status_mut() = StatusCode::UNAUTHORIZED;
+                    for challenge in challenges {
+                        challenge.apply(&mut response);
+                    }
+                    Ok(response)
+                }
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::auth::audit::tests::TestSink;
+    use crate::auth::AuditOutcome;
+    use crate::context::{ContextBuilder, Push};
+    use crate::EmptyContext;
+    use hyper::header::{AUTHORIZATION, WWW_AUTHENTICATE};
+
+    type InputContext = ContextBuilder<XSpanIdString, EmptyContext>;
+    type Context = ContextBuilder<Option<Authorization>, InputContext>;
+
+    fn context() -> InputContext {
+        EmptyContext.push(XSpanIdString("span".to_string()))
+    }
+
+    #[derive(Clone)]
+    struct AuthorizationService;
+
+    impl Service<(Request<()>, Context)> for AuthorizationService {
+        type Response = Response<Option<Authorization>>;
+        type Error = ();
+        type Future = futures::future::Ready<Result<Self::Response, Self::Error>>;
+
+        fn call(&self, req: (Request<()>, Context)) -> Self::Future {
+            futures::future::ok(Response::new(
+                Has::<Option<Authorization>>::get(&req.1).clone(),
+            ))
+        }
+    }
+
+    fn validator(
+        expected: &'static str,
+        scopes: &'static [&'static str],
+    ) -> impl CredentialValidator {
+        move |credentials: &AuthData| {
+            let secret = match credentials {
+                AuthData::Basic(_, secret)
+                | AuthData::Bearer(secret)
+                | AuthData::ApiKey(secret) => secret.expose_secret(),
+                AuthData::Digest(..) | AuthData::Other(..) => "",
+            };
+            futures::future::ready((secret == expected).then(|| {
+                Authorization::new(
+                    expected.to_string(),
+                    Scopes::Some(scopes.iter().map(|s| s.to_string()).collect()),
+                )
+            }))
+        }
+    }
+
+    fn evaluator() -> SecurityEvaluator<AuthorizationService, InputContext> {
+        let policy = SecurityPolicy::new()
+            .basic("basicAuth", "test", validator("bar", &["read"]))
+            .bearer("oauth", validator("token", &["read", "write"]))
+            .api_key(
+                "apiKey",
+                ApiKeyLocation::Header("X-API-Key".to_string()),
+                validator("key", &["write"]),
+            )
+            .require(vec![
+                SecurityRequirement::new(["apiKey", "oauth"]),
+                SecurityRequirement::new(["basicAuth"]),
+            ])
+            .operation(
+                Method::GET,
+                "/pets",
+                vec![
+                    SecurityRequirement::anonymous(),
+                    SecurityRequirement::new(["oauth"]),
+                ],
+            )
+            .route("/health", vec![]);
+        SecurityEvaluator::new(AuthorizationService, policy)
+    }
+
+    async fn call(
+        path: &str,
+        headers: &[(&'static str, &'static str)],
+    ) -> Response<Option<Authorization>> {
+        let mut request = Request::get(path);
+        for (name, value) in headers {
+            request = request.header(*name, *value);
+        }
+        evaluator()
+            .call((request.body(()).unwrap(), context()))
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_alternatives() {
+        // Basic alone satisfies the second alternative.
+        let response = call("/pets/1", &[(AUTHORIZATION.as_str(), "Basic Zm9vOmJhcg==")]).await;
+        assert_eq!(response.into_body().unwrap().subject, "bar");
+
+        // Bearer alone satisfies neither alternative.
+        let response = call("/pets/1", &[(AUTHORIZATION.as_str(), "Bearer token")]).await;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        let challenges = response
+            .headers()
+            .get_all(WWW_AUTHENTICATE)
+            .iter()
+            .collect::<Vec<_>>();
+        assert_eq!(
+            challenges,
+            vec!["Bearer", "Basic realm=\"test\", charset=\"UTF-8\""]
+        );
+
+        // API key and bearer together satisfy the first, with the scopes granted by both.
+        let response = call(
+            "/pets/1",
+            &[
+                ("X-API-Key", "key"),
+                (AUTHORIZATION.as_str(), "Bearer token"),
+            ],
+        )
+        .await;
+        let authorization = response.into_body().unwrap();
+        assert_eq!(authorization.subject, "key");
+        assert_eq!(
+            authorization.scopes,
+            Scopes::Some(["write".to_string()].into_iter().collect())
+        );
+
+        let response = call(
+            "/pets/1",
+            &[
+                ("X-API-Key", "wrong"),
+                (AUTHORIZATION.as_str(), "Bearer token"),
+            ],
+        )
+        .await;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    }
+
+    #[tokio::test]
+    async fn test_optional_and_unauthenticated_routes() {
+        let response = call("/pets", &[]).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.into_body(), None);
+
+        let response = call("/pets", &[(AUTHORIZATION.as_str(), "Bearer token")]).await;
+        assert_eq!(response.into_body().unwrap().subject, "token");
+
+        let response = call("/pets", &[(AUTHORIZATION.as_str(), "Bearer wrong")]).await;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+        let response = call("/health", &[]).await;
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn test_audit() {
+        let sink = TestSink::default();
+        let evaluator = evaluator().with_audit_sink(sink.clone());
+        for (path, authorization) in [
+            ("/pets/1", "Basic Zm9vOmJhcg=="),
+            ("/pets/1", "Basic Zm9vOmJheg=="),
+            ("/pets/1", "Bearer token"),
+        ] {
+            let request = Request::get(path)
+                .header(AUTHORIZATION, authorization)
+                .body(())
+                .unwrap();
+            evaluator.call((request, context())).await.unwrap();
+        }
+
+        let events = sink.take();
+        assert_eq!(events[0].outcome, AuditOutcome::Accepted);
+        assert_eq!(events[0].span_id, "span");
+        assert_eq!(events[0].scheme.as_deref(), Some("basicAuth"));
+        assert_eq!(events[0].subject.as_deref(), Some("bar"));
+        assert_eq!(events[1].outcome, AuditOutcome::Denied);
+        assert_eq!(events[1].reason.as_deref(), Some("Invalid credentials"));
+        assert_eq!(events[2].reason.as_deref(), Some("Missing credentials"));
+    }
+}
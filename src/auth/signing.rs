@@ -0,0 +1,756 @@
+//! HMAC request signing, for webhooks and service-to-service APIs.
+//!
+//! Clients sign a canonical form of each request, covering its method, path,
+//! query, selected headers, a timestamp and a digest of its body, using a
+//! secret shared with the server. The signature is sent in the `Authorization`
+//! header, along with the identifier of the key used:
+//!
+//! ```text
+//! Authorization: HMAC-SHA256 keyId="key-1", signedHeaders="content-type;host", signature="..."
+//! X-Signature-Timestamp: 1700000000
+//! ```
+//!
+//! `RequestSigner` is client middleware which signs outgoing requests, and
+//! `SignatureVerifier` server middleware which verifies them.
+use super::audit::Auditor;
+use super::{AuditSink, AuthMode, AuthModePolicy, Authorization, Challenge, RcBound, Scopes};
+use crate::context::Has;
+use crate::{ApiError, XSpanIdString};
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use futures::future::{BoxFuture, FutureExt};
+use hmac::{Hmac, Mac};
+use http_body_util::{BodyExt, Full, LengthLimitError, Limited};
+use hyper::body::{Body, Bytes};
+use hyper::header::{HeaderName, HeaderValue, AUTHORIZATION, HOST};
+use hyper::http::request::Parts;
+use hyper::service::Service;
+use hyper::{Request, Response, StatusCode};
+use sha2::{Digest, Sha256};
+use std::fmt;
+use std::marker::PhantomData;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Header containing the time at which a request was signed, in seconds since the Unix epoch.
+pub const X_SIGNATURE_TIMESTAMP: &str = "x-signature-timestamp";
+
+const SCHEME: &str = "HMAC-SHA256";
+
+/// Configuration of request signing, which must match between clients and servers.
+#[derive(Clone, Debug)]
+pub struct SigningConfig {
+    signed_headers: Vec<HeaderName>,
+    tolerance: Duration,
+    max_body_size: usize,
+}
+
+impl Default for SigningConfig {
+    fn default() -> Self {
+        SigningConfig {
+            signed_headers: vec![HOST, hyper::header::CONTENT_TYPE],
+            tolerance: Duration::from_secs(300),
+            max_body_size: 1024 * 1024,
+        }
+    }
+}
+
+impl SigningConfig {
+    /// Sign the `Host` and `Content-Type` headers, and accept signatures made
+    /// up to five minutes from the current time on bodies of up to 1 MiB.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set the headers which are signed by clients, and which servers require to be signed.
+    pub fn with_signed_headers<I: IntoIterator<Item = HeaderName>>(mut self, headers: I) -> Self {
+        self.signed_headers = headers.into_iter().collect();
+        self
+    }
+
+    /// Set how far a request's timestamp may be from the current time.
+    pub fn with_tolerance<D: Into<Duration>>(mut self, tolerance: D) -> Self {
+        self.tolerance = tolerance.into();
+        self
+    }
+
+    /// Set the largest request body, in bytes, which servers buffer to
+    /// verify its signature.
+    pub fn with_max_body_size(mut self, max_body_size: usize) -> Self {
+        self.max_body_size = max_body_size;
+        self
+    }
+}
+
+/// Build the canonical form of a request, which is signed.
+fn canonical_request(
+    parts: &Parts,
+    signed_headers: &[HeaderName],
+    timestamp: u64,
+    body: &[u8],
+) -> String {
+    let mut query = parts
+        .uri
+        .query()
+        .map(|query| {
+            query
+                .split('&')
+                .filter(|p| !p.is_empty())
+                .collect::<Vec<_>>()
+        })
+        .unwrap_or_default();
+    query.sort_unstable();
+
+    let mut canonical = format!(
+        "{}\n{}\n{}\n",
+        parts.method,
+        parts.uri.path(),
+        query.join("&")
+    );
+
+    for name in signed_headers {
+        let values = if name == HOST && !parts.headers.contains_key(HOST) {
+            // HTTP/2 requests, and requests not yet sent, carry the host in the URI.
+            parts
+                .uri
+                .authority()
+                .map(|a| a.to_string())
+                .unwrap_or_default()
+        } else {
+            parts
+                .headers
+                .get_all(name)
+                .iter()
+                .map(|value| String::from_utf8_lossy(value.as_bytes()).trim().to_string())
+                .collect::<Vec<_>>()
+                .join(",")
+        };
+        canonical.push_str(&format!("{}:{}\n", name, values));
+    }
+
+    canonical.push_str(&format!(
+        "{}\n{}",
+        timestamp,
+        STANDARD.encode(Sha256::digest(body))
+    ));
+    canonical
+}
+
+fn mac(secret: &[u8], canonical: &str) -> Hmac<Sha256> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret).expect("HMAC accepts keys of any length");
+    mac.update(canonical.as_bytes());
+    mac
+}
+
+fn unix_time() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+/// Sign a request with the given key, adding the `Authorization` and
+/// `X-Signature-Timestamp` headers.
+pub fn sign_request(
+    parts: &mut Parts,
+    body: &[u8],
+    key_id: &str,
+    secret: &[u8],
+    config: &SigningConfig,
+) -> Result<(), ApiError> {
+    let timestamp = unix_time();
+    parts
+        .headers
+        .insert(X_SIGNATURE_TIMESTAMP, HeaderValue::from(timestamp));
+
+    let canonical = canonical_request(parts, &config.signed_headers, timestamp, body);
+    let signature = STANDARD.encode(mac(secret, &canonical).finalize().into_bytes());
+    let signed_headers = config
+        .signed_headers
+        .iter()
+        .map(HeaderName::as_str)
+        .collect::<Vec<_>>()
+        .join(";");
+
+    let authorization = format!(
+        "{} keyId=\"{}\", signedHeaders=\"{}\", signature=\"{}\"",
+        SCHEME, key_id, signed_headers, signature
+    );
+    parts.headers.insert(
+        AUTHORIZATION,
+        HeaderValue::from_str(&authorization)
+            .map_err(|e| ApiError(format!("Invalid signing key ID {}: {}", key_id, e)))?,
+    );
+    Ok(())
+}
+
+/// Verify the signature of a request, returning the ID of the key used to sign it.
+///
+/// The key ID is passed to `lookup` to find the corresponding secret.
+pub fn verify_request<K>(
+    parts: &Parts,
+    body: &[u8],
+    lookup: K,
+    config: &SigningConfig,
+) -> Result<String, ApiError>
+where
+    K: FnOnce(&str) -> Option<Vec<u8>>,
+{
+    Signature::from_parts(parts, lookup, config)?.verify(parts, body)
+}
+
+/// A request signature whose header, key and timestamp have been checked,
+/// but not yet the signature over the body.
+struct Signature {
+    key_id: String,
+    signed_headers: Vec<HeaderName>,
+    timestamp: u64,
+    secret: Vec<u8>,
+    signature: Vec<u8>,
+}
+
+impl Signature {
+    /// Check everything about a request's signature which doesn't need its
+    /// body, so that unauthenticated requests are rejected before reading it.
+    fn from_parts<K>(parts: &Parts, lookup: K, config: &SigningConfig) -> Result<Self, ApiError>
+    where
+        K: FnOnce(&str) -> Option<Vec<u8>>,
+    {
+        let authorization = parts
+            .headers
+            .get(AUTHORIZATION)
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.strip_prefix(SCHEME))
+            .ok_or_else(|| ApiError("Missing request signature".to_string()))?;
+
+        let (mut key_id, mut signed_headers, mut signature) = (None, None, None);
+        for param in authorization.split(',') {
+            if let Some((name, value)) = param.split_once('=') {
+                let value = value.trim().trim_matches('"');
+                match name.trim() {
+                    "keyId" => key_id = Some(value),
+                    "signedHeaders" => signed_headers = Some(value),
+                    "signature" => signature = Some(value),
+                    _ => {}
+                }
+            }
+        }
+        let (key_id, signed_headers, signature) = match (key_id, signed_headers, signature) {
+            (Some(k), Some(h), Some(s)) => (k, h, s),
+            _ => return Err(ApiError("Malformed request signature".to_string())),
+        };
+
+        let signed_headers = signed_headers
+            .split(';')
+            .filter(|h| !h.is_empty())
+            .map(HeaderName::from_str)
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|_| ApiError("Malformed request signature".to_string()))?;
+        if let Some(missing) = config
+            .signed_headers
+            .iter()
+            .find(|h| !signed_headers.contains(h))
+        {
+            return Err(ApiError(format!("Header {} must be signed", missing)));
+        }
+
+        let timestamp = parts
+            .headers
+            .get(X_SIGNATURE_TIMESTAMP)
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.parse::<u64>().ok())
+            .ok_or_else(|| ApiError("Missing signature timestamp".to_string()))?;
+        if unix_time().abs_diff(timestamp) > config.tolerance.as_secs() {
+            return Err(ApiError("Signature timestamp out of range".to_string()));
+        }
+
+        let secret = lookup(key_id).ok_or_else(|| ApiError(format!("Unknown key {}", key_id)))?;
+        let signature = STANDARD
+            .decode(signature)
+            .map_err(|_| ApiError("Malformed request signature".to_string()))?;
+
+        Ok(Signature {
+            key_id: key_id.to_string(),
+            signed_headers,
+            timestamp,
+            secret,
+            signature,
+        })
+    }
+
+    /// Verify the signature over the request, returning the ID of the key
+    /// used to sign it.
+    fn verify(self, parts: &Parts, body: &[u8]) -> Result<String, ApiError> {
+        let canonical = canonical_request(parts, &self.signed_headers, self.timestamp, body);
+        mac(&self.secret, &canonical)
+            .verify_slice(&self.signature)
+            .map_err(|_| ApiError("Invalid request signature".to_string()))?;
+        Ok(self.key_id)
+    }
+}
+
+/// Client middleware which signs outgoing requests.
+///
+/// Request bodies are buffered in order to sign them, so the inner service
+/// receives requests with a `Full<Bytes>` body.
+///
+/// ```ignore
+/// let client = RequestSigner::new(
+///     DropContextService::new(http_client),
+///     "key-1",
+///     secret,
+///     SigningConfig::new(),
+/// );
+/// ```
+#[derive(Clone)]
+pub struct RequestSigner<T> {
+    inner: T,
+    key_id: String,
+    secret: Arc<[u8]>,
+    config: SigningConfig,
+}
+
+impl<T: fmt::Debug> fmt::Debug for RequestSigner<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RequestSigner")
+            .field("inner", &self.inner)
+            .field("key_id", &self.key_id)
+            .field("config", &self.config)
+            .finish()
+    }
+}
+
+impl<T> RequestSigner<T> {
+    /// Create a middleware which signs requests with the given key.
+    pub fn new<I: Into<String>>(inner: T, key_id: I, secret: &[u8], config: SigningConfig) -> Self {
+        RequestSigner {
+            inner,
+            key_id: key_id.into(),
+            secret: secret.into(),
+            config,
+        }
+    }
+}
+
+impl<T, B, C> Service<(Request<B>, C)> for RequestSigner<T>
+where
+    T: Service<(Request<Full<Bytes>>, C)> + Clone + Send + 'static,
+    T::Future: Send,
+    T::Error: From<ApiError>,
+    B: Body + Send + 'static,
+    B::Data: Send,
+    B::Error: fmt::Display,
+    C: Send + 'static,
+{
+    type Response = T::Response;
+    type Error = T::Error;
+    type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn call(&self, req: (Request<B>, C)) -> Self::Future {
+        let (request, context) = req;
+        let signer = self.clone();
+
+        Box::pin(async move {
+            let (mut parts, body) = request.into_parts();
+            let body = body
+                .collect()
+                .await
+                .map_err(|e| ApiError(format!("Failed to read request body: {}", e)))?
+                .to_bytes();
+
+            sign_request(
+                &mut parts,
+                &body,
+                &signer.key_id,
+                &signer.secret,
+                &signer.config,
+            )?;
+            let request = Request::from_parts(parts, Full::new(body));
+            signer.inner.call((request, context)).await
+        })
+    }
+}
+
+/// Server middleware which verifies request signatures.
+#[derive(Debug)]
+pub struct MakeSignatureVerifier<T, K, RC> {
+    inner: T,
+    lookup: K,
+    config: SigningConfig,
+    modes: Arc<AuthModePolicy>,
+    audit: Auditor,
+    marker: PhantomData<RC>,
+}
+
+impl<T, K, RC> MakeSignatureVerifier<T, K, RC> {
+    /// Create a middleware that verifies signatures using keys from the given lookup.
+    pub fn new(inner: T, lookup: K, config: SigningConfig) -> Self {
+        MakeSignatureVerifier {
+            inner,
+            lookup,
+            config,
+            modes: Arc::new(AuthModePolicy::default()),
+            audit: Auditor::default(),
+            marker: PhantomData,
+        }
+    }
+
+    /// Set the routes on which signatures are optional. By default,
+    /// signatures are required on all routes.
+    pub fn with_modes(mut self, modes: AuthModePolicy) -> Self {
+        self.modes = Arc::new(modes);
+        self
+    }
+
+    /// Record each decision to accept or deny a request in the given sink.
+    pub fn with_audit_sink<S: AuditSink + 'static>(mut self, sink: S) -> Self {
+        self.audit = Auditor::new(sink);
+        self
+    }
+}
+
+impl<Inner, K, RC, Target> Service<Target> for MakeSignatureVerifier<Inner, K, RC>
+where
+    Inner: Service<Target>,
+    Inner::Future: Send + 'static,
+    K: Clone + Send + 'static,
+    RC: Send + 'static,
+{
+    type Error = Inner::Error;
+    type Response = SignatureVerifier<Inner::Response, K, RC>;
+    type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn call(&self, target: Target) -> Self::Future {
+        let lookup = self.lookup.clone();
+        let config = self.config.clone();
+        let modes = self.modes.clone();
+        let audit = self.audit.clone();
+        Box::pin(self.inner.call(target).map(|s| {
+            Ok(SignatureVerifier {
+                inner: s?,
+                lookup,
+                config,
+                modes,
+                audit,
+                marker: PhantomData,
+            })
+        }))
+    }
+}
+
+/// Server middleware which verifies request signatures.
+///
+/// Requests with a valid signature are passed to the inner service, with an
+/// `Authorization` for the signing key ID, granting all scopes, pushed to the
+/// context. Request bodies are buffered to verify them, so the inner service
+/// receives requests with a `Full<Bytes>` body, but only once the signature's
+/// key and timestamp have been checked, and those larger than the configured
+/// maximum are rejected with `413 Payload Too Large`. Requests with a missing
+/// or invalid signature are rejected with `401 Unauthorized`, except that
+/// unsigned requests on routes where signatures are optional are passed on
+/// with `None` as their authorization.
+///
+/// ```ignore
+/// let keys = Arc::new(keys);
+/// let service = SignatureVerifier::new(
+///     inner,
+///     move |key_id: &str| keys.get(key_id).cloned(),
+///     SigningConfig::new(),
+/// );
+/// ```
+#[derive(Debug)]
+pub struct SignatureVerifier<T, K, RC> {
+    inner: T,
+    lookup: K,
+    config: SigningConfig,
+    modes: Arc<AuthModePolicy>,
+    audit: Auditor,
+    marker: PhantomData<RC>,
+}
+
+impl<T, K, RC> SignatureVerifier<T, K, RC> {
+    /// Create a middleware that verifies signatures using keys from the given lookup.
+    pub fn new(inner: T, lookup: K, config: SigningConfig) -> Self {
+        SignatureVerifier {
+            inner,
+            lookup,
+            config,
+            modes: Arc::new(AuthModePolicy::default()),
+            audit: Auditor::default(),
+            marker: PhantomData,
+        }
+    }
+
+    /// Set the routes on which signatures are optional. By default,
+    /// signatures are required on all routes.
+    pub fn with_modes(mut self, modes: AuthModePolicy) -> Self {
+        self.modes = Arc::new(modes);
+        self
+    }
+
+    /// Record each decision to accept or deny a request in the given sink.
+    pub fn with_audit_sink<S: AuditSink + 'static>(mut self, sink: S) -> Self {
+        self.audit = Auditor::new(sink);
+        self
+    }
+}
+
+impl<T, K, RC> Clone for SignatureVerifier<T, K, RC>
+where
+    T: Clone,
+    K: Clone,
+{
+    fn clone(&self) -> Self {
+        Self {
+            inner: self.inner.clone(),
+            lookup: self.lookup.clone(),
+            config: self.config.clone(),
+            modes: self.modes.clone(),
+            audit: self.audit.clone(),
+            marker: PhantomData,
+        }
+    }
+}
+
+impl<T, K, B, ResBody, RC> Service<(Request<B>, RC)> for SignatureVerifier<T, K, RC>
+where
+    RC: RcBound + Has<XSpanIdString>,
+    RC::Result: Send + 'static,
+    T: Service<(Request<Full<Bytes>>, RC::Result), Response = Response<ResBody>>
+        + Clone
+        + Send
+        + 'static,
+    T::Future: Send,
+    K: Fn(&str) -> Option<Vec<u8>> + Clone + Send + 'static,
+    B: Body + Send + 'static,
+    B::Data: Send,
+    B::Error: Into<Box<dyn std::error::Error + Send + Sync>>,
+    ResBody: Default + Send + 'static,
+{
+    type Response = Response<ResBody>;
+    type Error = T::Error;
+    type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn call(&self, req: (Request<B>, RC)) -> Self::Future {
+        let (request, context) = req;
+        let verifier = self.clone();
+
+        Box::pin(async move {
+            let span_id = Has::<XSpanIdString>::get(&context).clone();
+            let unauthorized = || Challenge::new(SCHEME).to_response();
+
+            let (parts, body) = request.into_parts();
+            let signed = parts
+                .headers
+                .get(AUTHORIZATION)
+                .is_some_and(|v| v.as_bytes().starts_with(SCHEME.as_bytes()));
+
+            // Check the header, key and timestamp before reading the body, so
+            // that unauthenticated requests can't make us buffer anything.
+            let signature = match (signed
+                || verifier.modes.mode(&parts.method, parts.uri.path()) == AuthMode::Required)
+                .then(|| Signature::from_parts(&parts, &verifier.lookup, &verifier.config))
+            {
+                Some(Ok(signature)) => Some(signature),
+                Some(Err(ApiError(reason))) => {
+                    let request = Request::from_parts(parts, ());
+                    verifier
+                        .audit
+                        .deny(&request, &span_id, Some(SCHEME), None, &reason);
+                    return Ok(unauthorized());
+                }
+                None => None,
+            };
+
+            let body = match Limited::new(body, verifier.config.max_body_size)
+                .collect()
+                .await
+            {
+                Ok(body) => body.to_bytes(),
+                Err(e) => {
+                    let mut response = Response::new(ResBody::default());
+                    *response.status_mut() = if e.is::<LengthLimitError>() {
+                        StatusCode::PAYLOAD_TOO_LARGE
+                    } else {
+                        StatusCode::BAD_REQUEST
+                    };
+                    return Ok(response);
+                }
+            };
+
+            let verification = signature.map(|signature| signature.verify(&parts, &body));
+            let request = Request::from_parts(parts, Full::new(body));
+
+            let authorization = match verification {
+                Some(Ok(key_id)) => Some(Authorization::new(key_id, Scopes::All)),
+                Some(Err(ApiError(reason))) => {
+                    verifier
+                        .audit
+                        .deny(&request, &span_id, Some(SCHEME), None, &reason);
+                    return Ok(unauthorized());
+                }
+                None => None,
+            };
+
+            verifier
+                .audit
+                .accept(&request, &span_id, Some(SCHEME), authorization.as_ref());
+            let context = context.push(authorization);
+            verifier.inner.call((request, context)).await
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::auth::audit::tests::TestSink;
+    use crate::auth::AuditOutcome;
+    use crate::context::{ContextBuilder, Push};
+    use crate::EmptyContext;
+    use hyper::header::CONTENT_TYPE;
+
+    fn secret(key_id: &str) -> Option<Vec<u8>> {
+        (key_id == "key-1").then(|| b"secret".to_vec())
+    }
+
+    fn signed_request(body: &'static str) -> (Parts, Bytes) {
+        let (mut parts, _) = Request::post("http://example.com/hooks?b=2&a=1")
+            .header(CONTENT_TYPE, "application/json")
+            .body(())
+            .unwrap()
+            .into_parts();
+        let body = Bytes::from(body);
+        sign_request(&mut parts, &body, "key-1", b"secret", &SigningConfig::new()).unwrap();
+        (parts, body)
+    }
+
+    #[test]
+    fn test_sign_and_verify() {
+        let config = SigningConfig::new();
+        let (parts, body) = signed_request("{}");
+        assert_eq!(
+            verify_request(&parts, &body, secret, &config).unwrap(),
+            "key-1"
+        );
+
+        // Tampering with the body, a signed header or the path is detected.
+        assert!(verify_request(&parts, b"{\"a\":1}", secret, &config).is_err());
+
+        let (mut tampered, body) = signed_request("{}");
+        tampered
+            .headers
+            .insert(CONTENT_TYPE, HeaderValue::from_static("text/plain"));
+        assert!(verify_request(&tampered, &body, secret, &config).is_err());
+
+        let (mut tampered, body) = signed_request("{}");
+        tampered.uri = "http://example.com/other?b=2&a=1".parse().unwrap();
+        assert!(verify_request(&tampered, &body, secret, &config).is_err());
+
+        // Unknown keys and stale timestamps are rejected.
+        assert!(verify_request(&parts, &body, |_: &str| None, &config).is_err());
+        let (mut stale, body) = signed_request("{}");
+        let timestamp = unix_time() - 600;
+        stale
+            .headers
+            .insert(X_SIGNATURE_TIMESTAMP, HeaderValue::from(timestamp));
+        assert!(verify_request(&stale, &body, secret, &config).is_err());
+    }
+
+    #[test]
+    fn test_required_headers() {
+        let (parts, body) = signed_request("{}");
+        let config = SigningConfig::new()
+            .with_signed_headers([HOST, HeaderName::from_static("x-request-id")]);
+        assert!(verify_request(&parts, &body, secret, &config).is_err());
+    }
+
+    #[derive(Clone)]
+    struct SubjectService;
+
+    type InputContext = ContextBuilder<XSpanIdString, EmptyContext>;
+    type Context = ContextBuilder<Option<Authorization>, InputContext>;
+
+    fn context() -> InputContext {
+        EmptyContext.push(XSpanIdString("span".to_string()))
+    }
+
+    impl Service<(Request<Full<Bytes>>, Context)> for SubjectService {
+        type Response = Response<String>;
+        type Error = ApiError;
+        type Future = futures::future::Ready<Result<Self::Response, Self::Error>>;
+
+        fn call(&self, req: (Request<Full<Bytes>>, Context)) -> Self::Future {
+            let authorization: &Option<Authorization> = req.1.get();
+            futures::future::ok(Response::new(authorization.clone().unwrap().subject))
+        }
+    }
+
+    #[tokio::test]
+    async fn test_signing_middleware() {
+        let sink = TestSink::default();
+        let server = SignatureVerifier::<_, _, InputContext>::new(
+            SubjectService,
+            secret as fn(&str) -> Option<Vec<u8>>,
+            SigningConfig::new(),
+        )
+        .with_audit_sink(sink.clone());
+        let client = RequestSigner::new(server.clone(), "key-1", b"secret", SigningConfig::new());
+
+        let request = || {
+            Request::post("http://example.com/hooks")
+                .body(Full::new(Bytes::from("{}")))
+                .unwrap()
+        };
+
+        let response = client.call((request(), context())).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.into_body(), "key-1");
+
+        let response = server.call((request(), context())).await.unwrap();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        let events = sink.take();
+        assert_eq!(events[0].outcome, AuditOutcome::Accepted);
+        assert_eq!(events[0].span_id, "span");
+        assert_eq!(events[0].subject.as_deref(), Some("key-1"));
+        assert_eq!(events[1].outcome, AuditOutcome::Denied);
+        assert_eq!(
+            events[1].reason.as_deref(),
+            Some("Missing request signature")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_signing_middleware_body_limit() {
+        let config = SigningConfig::new().with_max_body_size(4);
+        let server = SignatureVerifier::<_, _, InputContext>::new(
+            SubjectService,
+            secret as fn(&str) -> Option<Vec<u8>>,
+            config.clone(),
+        );
+        let client = RequestSigner::new(server.clone(), "key-1", b"secret", config);
+
+        let request = |body: &'static str| {
+            Request::post("http://example.com/hooks")
+                .body(Full::new(Bytes::from(body)))
+                .unwrap()
+        };
+
+        let response = client.call((request("{}"), context())).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let response = client
+            .call((request("{\"a\":1}"), context()))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::PAYLOAD_TOO_LARGE);
+
+        // Unsigned requests are rejected without reading the body.
+        let response = server
+            .call((request("{\"a\":1}"), context()))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    }
+}
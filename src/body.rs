@@ -1,6 +1,11 @@
 /// Helper methods to act on hyper::Body
 use futures::stream::{Stream, StreamExt};
-use hyper::body::Bytes;
+use hyper::body::{Body, Bytes, Frame, SizeHint};
+use hyper::header::HeaderValue;
+use std::error;
+use std::fmt;
+use std::pin::Pin;
+use std::task::{Context, Poll};
 
 /// Additional function for hyper::Body
 pub trait BodyExt {
@@ -12,6 +17,34 @@ pub trait BodyExt {
 
     /// Collect the body into a raw form
     fn into_raw(self) -> futures::future::BoxFuture<'static, Result<Self::Raw, Self::Error>>;
+
+    /// Collect the body into text, decoded according to the `charset`
+    /// parameter of the given `Content-Type`, or as UTF-8 if there is none.
+    ///
+    /// In `TextDecoding::Lossy` mode, invalid sequences are replaced with
+    /// U+FFFD, and unsupported charsets are decoded as UTF-8.
+    fn into_text(
+        self,
+        content_type: Option<&HeaderValue>,
+        decoding: TextDecoding,
+    ) -> futures::future::BoxFuture<'static, Result<String, TextError<Self::Error>>>
+    where
+        Self: Sized,
+        Self::Raw: AsRef<[u8]> + Send + 'static,
+        Self::Error: Send + 'static,
+    {
+        let charset = Charset::from_content_type(content_type);
+        let raw = self.into_raw();
+        Box::pin(async move {
+            let raw = raw.await.map_err(TextError::Body)?;
+            let charset = match charset {
+                Ok(charset) => charset,
+                Err(_) if decoding == TextDecoding::Lossy => Charset::Utf8,
+                Err(charset) => return Err(TextError::UnsupportedCharset(charset)),
+            };
+            charset.decode(raw.as_ref(), decoding)
+        })
+    }
 }
 
 impl<T, E> BodyExt for T
@@ -32,3 +65,268 @@ where
         })
     }
 }
+
+/// Body whose start has already been read, followed by the rest of the body.
+///
+/// `CompressionService` and `Cache` pass through responses this way when
+/// they turn out to be too large to compress or cache.
+pub struct PrefixedBody<B> {
+    prefix: Option<Bytes>,
+    rest: Pin<Box<B>>,
+}
+
+impl<B> PrefixedBody<B> {
+    /// Body which is the given data followed by the rest of the body.
+    pub fn new(prefix: Bytes, rest: Pin<Box<B>>) -> Self {
+        PrefixedBody {
+            prefix: Some(prefix).filter(|prefix| !prefix.is_empty()),
+            rest,
+        }
+    }
+}
+
+impl<B> fmt::Debug for PrefixedBody<B> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PrefixedBody")
+            .field("prefix", &self.prefix.as_ref().map_or(0, Bytes::len))
+            .finish()
+    }
+}
+
+impl<B: Body<Data = Bytes>> Body for PrefixedBody<B> {
+    type Data = Bytes;
+    type Error = B::Error;
+
+    fn poll_frame(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<Bytes>, B::Error>>> {
+        if let Some(prefix) = self.prefix.take() {
+            return Poll::Ready(Some(Ok(Frame::data(prefix))));
+        }
+        self.rest.as_mut().poll_frame(cx)
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.prefix.is_none() && self.rest.is_end_stream()
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        let prefix = self.prefix.as_ref().map_or(0, |prefix| prefix.len() as u64);
+        let rest = self.rest.size_hint();
+        let mut hint = SizeHint::new();
+        hint.set_lower(rest.lower() + prefix);
+        if let Some(upper) = rest.upper() {
+            hint.set_upper(upper + prefix);
+        }
+        hint
+    }
+}
+
+/// Read a body in full, unless it is larger than `max_size` bytes, in which
+/// case what has been read is returned along with the rest of the body.
+#[cfg(any(feature = "client", feature = "gzip", feature = "brotli"))]
+pub(crate) async fn read_to_limit<B: Body<Data = Bytes>>(
+    body: B,
+    max_size: usize,
+) -> Result<Result<Bytes, PrefixedBody<B>>, B::Error> {
+    let mut body = Box::pin(body);
+    let mut buffered = Vec::new();
+    while let Some(frame) = std::future::poll_fn(|cx| body.as_mut().poll_frame(cx)).await {
+        if let Ok(data) = frame?.into_data() {
+            buffered.extend_from_slice(&data);
+        }
+        if buffered.len() > max_size {
+            return Ok(Err(PrefixedBody::new(Bytes::from(buffered), body)));
+        }
+    }
+    Ok(Ok(Bytes::from(buffered)))
+}
+
+/// How to handle text which is invalid in its charset.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum TextDecoding {
+    /// Fail to decode.
+    Strict,
+    /// Replace invalid sequences with U+FFFD.
+    Lossy,
+}
+
+/// Character encoding of a text body.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Charset {
+    /// UTF-8, the default.
+    Utf8,
+    /// US-ASCII.
+    Ascii,
+    /// ISO-8859-1, also known as Latin-1.
+    Latin1,
+    /// UTF-16, big-endian unless the text starts with a byte order mark.
+    Utf16,
+    /// UTF-16, big-endian.
+    Utf16Be,
+    /// UTF-16, little-endian.
+    Utf16Le,
+}
+
+impl Charset {
+    /// Find the charset from its name, ignoring case.
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.trim().to_ascii_lowercase().as_str() {
+            "utf-8" | "utf8" => Some(Charset::Utf8),
+            "us-ascii" | "ascii" => Some(Charset::Ascii),
+            "iso-8859-1" | "iso8859-1" | "latin1" | "l1" => Some(Charset::Latin1),
+            "utf-16" => Some(Charset::Utf16),
+            "utf-16be" => Some(Charset::Utf16Be),
+            "utf-16le" => Some(Charset::Utf16Le),
+            _ => None,
+        }
+    }
+
+    /// Find the charset from the `charset` parameter of a `Content-Type`,
+    /// defaulting to UTF-8. Returns the name of the charset if it isn't
+    /// supported.
+    pub fn from_content_type(content_type: Option<&HeaderValue>) -> Result<Self, String> {
+        let charset = content_type
+            .and_then(|value| value.to_str().ok())
+            .into_iter()
+            .flat_map(|value| value.split(';').skip(1))
+            .filter_map(|param| param.split_once('='))
+            .find(|(name, _)| name.trim().eq_ignore_ascii_case("charset"))
+            .map(|(_, value)| value.trim().trim_matches('"'));
+        match charset {
+            None => Ok(Charset::Utf8),
+            Some(name) => Charset::from_name(name).ok_or_else(|| name.to_string()),
+        }
+    }
+
+    /// Decode text in this charset.
+    pub fn decode<E>(&self, raw: &[u8], decoding: TextDecoding) -> Result<String, TextError<E>> {
+        let lossy = decoding == TextDecoding::Lossy;
+        let invalid = Err(TextError::InvalidText(*self));
+        match self {
+            Charset::Utf8 => {
+                let raw = raw.strip_prefix(b"\xef\xbb\xbf").unwrap_or(raw);
+                match std::str::from_utf8(raw) {
+                    Ok(text) => Ok(text.to_string()),
+                    Err(_) if lossy => Ok(String::from_utf8_lossy(raw).into_owned()),
+                    Err(_) => invalid,
+                }
+            }
+            Charset::Ascii => {
+                if !lossy && !raw.is_ascii() {
+                    return invalid;
+                }
+                Ok(raw
+                    .iter()
+                    .map(|&b| if b.is_ascii() { b as char } else { '\u{fffd}' })
+                    .collect())
+            }
+            Charset::Latin1 => Ok(raw.iter().map(|&b| b as char).collect()),
+            Charset::Utf16 | Charset::Utf16Be | Charset::Utf16Le => {
+                let (raw, little_endian) = match (self, raw) {
+                    (Charset::Utf16, [0xff, 0xfe, rest @ ..]) => (rest, true),
+                    (Charset::Utf16, [0xfe, 0xff, rest @ ..]) => (rest, false),
+                    _ => (raw, *self == Charset::Utf16Le),
+                };
+                if !lossy && raw.len() % 2 != 0 {
+                    return invalid;
+                }
+                let units = raw.chunks(2).map(|pair| match (pair, little_endian) {
+                    ([a, b], true) => u16::from_le_bytes([*a, *b]),
+                    ([a, b], false) => u16::from_be_bytes([*a, *b]),
+                    // Trailing odd byte, only reached in lossy mode
+                    _ => 0xfffd,
+                });
+                let mut text = String::with_capacity(raw.len() / 2);
+                for c in char::decode_utf16(units) {
+                    match c {
+                        Ok(c) => text.push(c),
+                        Err(_) if lossy => text.push('\u{fffd}'),
+                        Err(_) => return invalid,
+                    }
+                }
+                Ok(text)
+            }
+        }
+    }
+}
+
+/// Failure to collect a body into text.
+#[derive(Debug)]
+pub enum TextError<E> {
+    /// The body couldn't be read.
+    Body(E),
+    /// The body has a charset which isn't supported.
+    UnsupportedCharset(String),
+    /// The body isn't valid text in its charset.
+    InvalidText(Charset),
+}
+
+impl<E: fmt::Display> fmt::Display for TextError<E> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TextError::Body(e) => write!(f, "Failed to read body: {}", e),
+            TextError::UnsupportedCharset(charset) => {
+                write!(f, "Unsupported charset: {}", charset)
+            }
+            TextError::InvalidText(charset) => write!(f, "Body is not valid {:?} text", charset),
+        }
+    }
+}
+
+impl<E: fmt::Debug + fmt::Display> error::Error for TextError<E> {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn body(raw: &'static [u8]) -> impl Stream<Item = Result<Bytes, ()>> + Unpin + Send {
+        futures::stream::iter(raw.chunks(3).map(|chunk| Ok(Bytes::from_static(chunk))))
+    }
+
+    async fn text(raw: &'static [u8], content_type: &'static str) -> Result<String, TextError<()>> {
+        let content_type = HeaderValue::from_static(content_type);
+        body(raw)
+            .into_text(Some(&content_type), TextDecoding::Strict)
+            .await
+    }
+
+    #[tokio::test]
+    async fn test_into_text() {
+        assert_eq!(text("café".as_bytes(), "text/plain").await.unwrap(), "café");
+        assert_eq!(
+            text(b"caf\xe9", "text/plain; charset=\"ISO-8859-1\"")
+                .await
+                .unwrap(),
+            "café"
+        );
+        assert_eq!(
+            text(b"\xff\xfec\x00\xe9\x00", "text/plain;charset=utf-16")
+                .await
+                .unwrap(),
+            "cé"
+        );
+        assert_eq!(
+            text(b"\x00c\x00\xe9", "text/plain;charset=utf-16")
+                .await
+                .unwrap(),
+            "cé"
+        );
+
+        assert!(matches!(
+            text(b"caf\xe9", "text/plain").await,
+            Err(TextError::InvalidText(Charset::Utf8))
+        ));
+        assert!(matches!(
+            text(b"abc", "text/plain; charset=koi8-r").await,
+            Err(TextError::UnsupportedCharset(name)) if name == "koi8-r"
+        ));
+
+        let lossy = body(b"caf\xe9")
+            .into_text(None, TextDecoding::Lossy)
+            .await
+            .unwrap();
+        assert_eq!(lossy, "caf\u{fffd}");
+    }
+}
@@ -0,0 +1,430 @@
+//! Typed representation of the `Cache-Control` header.
+//!
+//! `CacheControl` holds the directives defined by RFC 9111, along with the
+//! `immutable` extension from RFC 8246 and the `stale-while-revalidate` and
+//! `stale-if-error` extensions from RFC 5861. It can be parsed from a request
+//! or response, or built and added to one:
+//!
+//! ```
+//! use std::time::Duration;
+//! use swagger::CacheControl;
+//!
+//! let cache_control = CacheControl::new()
+//!     .public()
+//!     .max_age(Duration::from_secs(3600))
+//!     .immutable();
+//! assert_eq!(cache_control.to_string(), "public, max-age=3600, immutable");
+//! ```
+use crate::ApiError;
+use hyper::header::{HeaderMap, HeaderValue, InvalidHeaderValue, CACHE_CONTROL};
+use std::fmt;
+use std::str::FromStr;
+use std::time::Duration;
+
+/// Directives of a `Cache-Control` header.
+///
+/// The field names given to the `no-cache` and `private` directives are not
+/// retained, so those directives are treated as applying to the whole
+/// response. Unrecognised directives are kept in `extensions`.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct CacheControl {
+    /// `max-age`: how long the response remains fresh.
+    pub max_age: Option<Duration>,
+    /// `s-maxage`: how long the response remains fresh in shared caches.
+    pub s_maxage: Option<Duration>,
+    /// `max-stale`: how stale a response the client will accept. A bare
+    /// `max-stale` accepts any staleness, and is represented as `Duration::MAX`.
+    pub max_stale: Option<Duration>,
+    /// `min-fresh`: how much longer a response must remain fresh to be
+    /// acceptable to the client.
+    pub min_fresh: Option<Duration>,
+    /// `stale-while-revalidate`: how long a stale response may be served while
+    /// it is revalidated in the background.
+    pub stale_while_revalidate: Option<Duration>,
+    /// `stale-if-error`: how long a stale response may be served if the
+    /// server is failing.
+    pub stale_if_error: Option<Duration>,
+    /// `no-cache`: the response must be revalidated before each use.
+    pub no_cache: bool,
+    /// `no-store`: the request or response must not be stored.
+    pub no_store: bool,
+    /// `no-transform`: intermediaries must not transform the content.
+    pub no_transform: bool,
+    /// `must-revalidate`: the response must not be used once stale without
+    /// revalidation.
+    pub must_revalidate: bool,
+    /// `proxy-revalidate`: as `must-revalidate`, but only for shared caches.
+    pub proxy_revalidate: bool,
+    /// `must-understand`: the response may only be stored by caches which
+    /// understand its status code.
+    pub must_understand: bool,
+    /// `only-if-cached`: the client only wants a stored response.
+    pub only_if_cached: bool,
+    /// `private`: the response must not be stored by shared caches.
+    pub private: bool,
+    /// `public`: the response may be stored by any cache.
+    pub public: bool,
+    /// `immutable`: the response won't change while fresh.
+    pub immutable: bool,
+    /// Unrecognised directives, with their values, if any.
+    pub extensions: Vec<(String, Option<String>)>,
+}
+
+impl CacheControl {
+    /// Empty `Cache-Control`, with no directives.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set `max-age`.
+    pub fn max_age(mut self, max_age: Duration) -> Self {
+        self.max_age = Some(max_age);
+        self
+    }
+
+    /// Set `s-maxage`.
+    pub fn s_maxage(mut self, s_maxage: Duration) -> Self {
+        self.s_maxage = Some(s_maxage);
+        self
+    }
+
+    /// Set `max-stale`.
+    pub fn max_stale(mut self, max_stale: Duration) -> Self {
+        self.max_stale = Some(max_stale);
+        self
+    }
+
+    /// Set `min-fresh`.
+    pub fn min_fresh(mut self, min_fresh: Duration) -> Self {
+        self.min_fresh = Some(min_fresh);
+        self
+    }
+
+    /// Set `stale-while-revalidate`.
+    pub fn stale_while_revalidate(mut self, duration: Duration) -> Self {
+        self.stale_while_revalidate = Some(duration);
+        self
+    }
+
+    /// Set `stale-if-error`.
+    pub fn stale_if_error(mut self, duration: Duration) -> Self {
+        self.stale_if_error = Some(duration);
+        self
+    }
+
+    /// Set `no-cache`.
+    pub fn no_cache(mut self) -> Self {
+        self.no_cache = true;
+        self
+    }
+
+    /// Set `no-store`.
+    pub fn no_store(mut self) -> Self {
+        self.no_store = true;
+        self
+    }
+
+    /// Set `no-transform`.
+    pub fn no_transform(mut self) -> Self {
+        self.no_transform = true;
+        self
+    }
+
+    /// Set `must-revalidate`.
+    pub fn must_revalidate(mut self) -> Self {
+        self.must_revalidate = true;
+        self
+    }
+
+    /// Set `proxy-revalidate`.
+    pub fn proxy_revalidate(mut self) -> Self {
+        self.proxy_revalidate = true;
+        self
+    }
+
+    /// Set `must-understand`.
+    pub fn must_understand(mut self) -> Self {
+        self.must_understand = true;
+        self
+    }
+
+    /// Set `only-if-cached`.
+    pub fn only_if_cached(mut self) -> Self {
+        self.only_if_cached = true;
+        self
+    }
+
+    /// Set `private`.
+    pub fn private(mut self) -> Self {
+        self.private = true;
+        self
+    }
+
+    /// Set `public`.
+    pub fn public(mut self) -> Self {
+        self.public = true;
+        self
+    }
+
+    /// Set `immutable`.
+    pub fn immutable(mut self) -> Self {
+        self.immutable = true;
+        self
+    }
+
+    /// Add an unrecognised directive, with an optional value.
+    pub fn extension<N: Into<String>>(mut self, name: N, value: Option<String>) -> Self {
+        self.extensions.push((name.into(), value));
+        self
+    }
+
+    /// Parse a `Cache-Control` header value.
+    ///
+    /// Parsing is lenient, as required by RFC 9111: directives with invalid
+    /// values are treated as if absent, except for `max-age`, which is
+    /// treated as zero so that the response is stale.
+    pub fn parse(value: &str) -> Self {
+        let mut cache_control = CacheControl::default();
+        cache_control.add(value);
+        cache_control
+    }
+
+    /// Retrieve the directives from all `Cache-Control` headers in a request
+    /// or response.
+    pub fn from_headers(headers: &HeaderMap) -> Self {
+        let mut cache_control = CacheControl::default();
+        for value in headers
+            .get_all(CACHE_CONTROL)
+            .iter()
+            .filter_map(|v| v.to_str().ok())
+        {
+            cache_control.add(value);
+        }
+        cache_control
+    }
+
+    /// Set the `Cache-Control` header of a request or response to these
+    /// directives, or remove it if there are none.
+    pub fn apply(&self, headers: &mut HeaderMap) {
+        match HeaderValue::try_from(self) {
+            Ok(value) if !value.is_empty() => {
+                headers.insert(CACHE_CONTROL, value);
+            }
+            _ => {
+                headers.remove(CACHE_CONTROL);
+            }
+        }
+    }
+
+    fn add(&mut self, value: &str) {
+        for directive in split_directives(value) {
+            let (name, value) = match directive.split_once('=') {
+                Some((name, value)) => (name.trim(), Some(unquote(value.trim()))),
+                None => (directive, None),
+            };
+            let seconds = || value.and_then(|v| v.parse().ok()).map(Duration::from_secs);
+
+            match name.to_ascii_lowercase().as_str() {
+                "max-age" => self.max_age = Some(seconds().unwrap_or_default()),
+                "s-maxage" => self.s_maxage = seconds(),
+                "max-stale" => {
+                    self.max_stale = match value {
+                        Some(_) => seconds(),
+                        None => Some(Duration::MAX),
+                    }
+                }
+                "min-fresh" => self.min_fresh = seconds(),
+                "stale-while-revalidate" => self.stale_while_revalidate = seconds(),
+                "stale-if-error" => self.stale_if_error = seconds(),
+                "no-cache" => self.no_cache = true,
+                "no-store" => self.no_store = true,
+                "no-transform" => self.no_transform = true,
+                "must-revalidate" => self.must_revalidate = true,
+                "proxy-revalidate" => self.proxy_revalidate = true,
+                "must-understand" => self.must_understand = true,
+                "only-if-cached" => self.only_if_cached = true,
+                "private" => self.private = true,
+                "public" => self.public = true,
+                "immutable" => self.immutable = true,
+                "" => {}
+                _ => self
+                    .extensions
+                    .push((name.to_string(), value.map(str::to_string))),
+            }
+        }
+    }
+}
+
+/// Split a header value into its directives, ignoring commas within quoted strings.
+fn split_directives(value: &str) -> impl Iterator<Item = &str> {
+    let mut directives = Vec::new();
+    let mut quoted = false;
+    let mut escaped = false;
+    let mut start = 0;
+    for (index, c) in value.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' if quoted => escaped = true,
+            '"' => quoted = !quoted,
+            ',' if !quoted => {
+                directives.push(value[start..index].trim());
+                start = index + 1;
+            }
+            _ => {}
+        }
+    }
+    directives.push(value[start..].trim());
+    directives.into_iter().filter(|d| !d.is_empty())
+}
+
+fn unquote(value: &str) -> &str {
+    value
+        .strip_prefix('"')
+        .and_then(|v| v.strip_suffix('"'))
+        .unwrap_or(value)
+}
+
+impl fmt::Display for CacheControl {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let flags = [
+            (self.public, "public"),
+            (self.private, "private"),
+            (self.no_cache, "no-cache"),
+            (self.no_store, "no-store"),
+            (self.no_transform, "no-transform"),
+            (self.must_revalidate, "must-revalidate"),
+            (self.proxy_revalidate, "proxy-revalidate"),
+            (self.must_understand, "must-understand"),
+            (self.only_if_cached, "only-if-cached"),
+        ];
+        let durations = [
+            (self.max_age, "max-age"),
+            (self.s_maxage, "s-maxage"),
+            (self.max_stale, "max-stale"),
+            (self.min_fresh, "min-fresh"),
+            (self.stale_while_revalidate, "stale-while-revalidate"),
+            (self.stale_if_error, "stale-if-error"),
+        ];
+
+        let mut directives = Vec::new();
+        directives.extend(
+            flags
+                .iter()
+                .filter(|(set, _)| *set)
+                .map(|(_, name)| name.to_string()),
+        );
+        for (duration, name) in durations {
+            match duration {
+                Some(Duration::MAX) if name == "max-stale" => directives.push(name.to_string()),
+                Some(duration) => directives.push(format!("{}={}", name, duration.as_secs())),
+                None => {}
+            }
+        }
+        if self.immutable {
+            directives.push("immutable".to_string());
+        }
+        for (name, value) in &self.extensions {
+            match value {
+                Some(value) if value.contains(|c: char| !is_token_char(c)) => {
+                    directives.push(format!("{}=\"{}\"", name, value.replace('"', "\\\"")))
+                }
+                Some(value) => directives.push(format!("{}={}", name, value)),
+                None => directives.push(name.clone()),
+            }
+        }
+
+        write!(f, "{}", directives.join(", "))
+    }
+}
+
+fn is_token_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || "!#$%&'*+-.^_`|~".contains(c)
+}
+
+impl FromStr for CacheControl {
+    type Err = std::convert::Infallible;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        Ok(CacheControl::parse(value))
+    }
+}
+
+impl TryFrom<&HeaderValue> for CacheControl {
+    type Error = ApiError;
+
+    fn try_from(value: &HeaderValue) -> Result<Self, Self::Error> {
+        let value = value
+            .to_str()
+            .map_err(|e| ApiError(format!("Invalid Cache-Control header: {}", e)))?;
+        Ok(CacheControl::parse(value))
+    }
+}
+
+impl TryFrom<&CacheControl> for HeaderValue {
+    type Error = InvalidHeaderValue;
+
+    fn try_from(cache_control: &CacheControl) -> Result<Self, Self::Error> {
+        HeaderValue::from_str(&cache_control.to_string())
+    }
+}
+
+impl TryFrom<CacheControl> for HeaderValue {
+    type Error = InvalidHeaderValue;
+
+    fn try_from(cache_control: CacheControl) -> Result<Self, Self::Error> {
+        HeaderValue::try_from(&cache_control)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let cache_control = CacheControl::parse(
+            "Public, max-age=\"60\", s-maxage=x, private=\"set-cookie, x-foo\", \
+             stale-while-revalidate=30, max-stale, community=\"UCI\"",
+        );
+        assert_eq!(
+            cache_control,
+            CacheControl::new()
+                .public()
+                .private()
+                .max_age(Duration::from_secs(60))
+                .stale_while_revalidate(Duration::from_secs(30))
+                .max_stale(Duration::MAX)
+                .extension("community", Some("UCI".to_string()))
+        );
+
+        // An invalid max-age makes the response stale.
+        let cache_control = CacheControl::parse("max-age=-1");
+        assert_eq!(cache_control.max_age, Some(Duration::ZERO));
+    }
+
+    #[test]
+    fn test_headers() {
+        let mut headers = HeaderMap::new();
+        headers.append(CACHE_CONTROL, HeaderValue::from_static("no-cache"));
+        headers.append(CACHE_CONTROL, HeaderValue::from_static("max-age=10"));
+        let cache_control = CacheControl::from_headers(&headers);
+        assert!(cache_control.no_cache);
+        assert_eq!(cache_control.max_age, Some(Duration::from_secs(10)));
+
+        let cache_control = CacheControl::new()
+            .no_store()
+            .extension("x-note", Some("a, b".to_string()));
+        cache_control.apply(&mut headers);
+        assert_eq!(
+            headers.get_all(CACHE_CONTROL).iter().collect::<Vec<_>>(),
+            vec!["no-store, x-note=\"a, b\""]
+        );
+        assert_eq!(
+            CacheControl::try_from(headers.get(CACHE_CONTROL).unwrap()).unwrap(),
+            cache_control
+        );
+
+        CacheControl::new().apply(&mut headers);
+        assert!(!headers.contains_key(CACHE_CONTROL));
+    }
+}
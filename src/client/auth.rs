@@ -0,0 +1,216 @@
+//! Middleware which authenticates outgoing requests.
+use crate::auth::{ApiKeyLocation, AuthData};
+use crate::context::Has;
+use headers::authorization::Credentials;
+use headers::Authorization as Header;
+use hyper::header::{HeaderName, HeaderValue, AUTHORIZATION, COOKIE};
+use hyper::service::Service;
+use hyper::{Request, Uri};
+use percent_encoding::{utf8_percent_encode, AsciiSet, NON_ALPHANUMERIC};
+
+/// Characters which must be percent-encoded in a query parameter, leaving only
+/// the unreserved characters from RFC 3986.
+pub(super) const QUERY_ENCODE_SET: &AsciiSet = &NON_ALPHANUMERIC
+    .remove(b'-')
+    .remove(b'.')
+    .remove(b'_')
+    .remove(b'~');
+
+/// Middleware which reads the `AuthData` from the context of outgoing requests,
+/// and adds it to the request.
+///
+/// Basic and Bearer credentials are sent in the `Authorization` header, and
+/// API keys at the configured location. Requests which already have an
+/// `Authorization` header are left unchanged, as are API keys if no location
+/// is configured.
+///
+/// ```ignore
+/// let client = AuthInjector::new(DropContextService::new(http_client))
+///     .with_api_key(ApiKeyLocation::Header("X-API-Key".to_string()));
+/// ```
+#[derive(Clone, Debug)]
+pub struct AuthInjector<T> {
+    inner: T,
+    api_key: Option<ApiKeyLocation>,
+}
+
+impl<T> AuthInjector<T> {
+    /// Create a middleware which adds Basic and Bearer credentials to requests.
+    pub fn new(inner: T) -> Self {
+        AuthInjector {
+            inner,
+            api_key: None,
+        }
+    }
+
+    /// Add API keys to requests at the given location.
+    pub fn with_api_key(mut self, location: ApiKeyLocation) -> Self {
+        self.api_key = Some(location);
+        self
+    }
+
+    fn inject<B>(&self, request: &mut Request<B>, auth_data: &AuthData) {
+        match auth_data {
+            AuthData::Basic(username, password) => {
+                let value = Header::basic(username, password.expose_secret()).0.encode();
+                request.headers_mut().entry(AUTHORIZATION).or_insert(value);
+            }
+            AuthData::Bearer(token) => {
+                if let Ok(header) = Header::bearer(token.expose_secret()) {
+                    let value = header.0.encode();
+                    request.headers_mut().entry(AUTHORIZATION).or_insert(value);
+                }
+            }
+            AuthData::ApiKey(key) => match &self.api_key {
+                Some(ApiKeyLocation::Header(name)) => {
+                    if let Ok(value) = HeaderValue::from_str(key.expose_secret()) {
+                        if let Ok(name) = HeaderName::try_from(name.as_str()) {
+                            request.headers_mut().insert(name, value);
+                        }
+                    }
+                }
+                Some(ApiKeyLocation::Query(name)) => {
+                    if let Some(uri) = with_query_param(request.uri(), name, key.expose_secret()) {
+                        *request.uri_mut() = uri;
+                    }
+                }
+                Some(ApiKeyLocation::Cookie(name)) => {
+                    if let Ok(value) =
+                        HeaderValue::from_str(&format!("{}={}", name, key.expose_secret()))
+                    {
+                        request.headers_mut().append(COOKIE, value);
+                    }
+                }
+                None => {}
+            },
+            // Digest credentials can only be sent in response to a challenge,
+            // by a `DigestAuthenticator`.
+            AuthData::Digest(..) => {}
+            AuthData::Other(scheme, credentials) => {
+                let value = format!("{} {}", scheme, credentials.expose_secret());
+                if let Ok(value) = HeaderValue::from_str(&value) {
+                    request.headers_mut().entry(AUTHORIZATION).or_insert(value);
+                }
+            }
+        }
+    }
+}
+
+/// Append a query parameter to a URI.
+fn with_query_param(uri: &Uri, name: &str, value: &str) -> Option<Uri> {
+    let param = format!(
+        "{}={}",
+        utf8_percent_encode(name, QUERY_ENCODE_SET),
+        utf8_percent_encode(value, QUERY_ENCODE_SET)
+    );
+    let path_and_query = match uri.query() {
+        Some(query) if !query.is_empty() => format!("{}?{}&{}", uri.path(), query, param),
+        _ => format!("{}?{}", uri.path(), param),
+    };
+
+    let mut parts = uri.clone().into_parts();
+    parts.path_and_query = Some(path_and_query.parse().ok()?);
+    Uri::from_parts(parts).ok()
+}
+
+impl<T, B, C> Service<(Request<B>, C)> for AuthInjector<T>
+where
+    T: Service<(Request<B>, C)>,
+    C: Has<Option<AuthData>>,
+{
+    type Response = T::Response;
+    type Error = T::Error;
+    type Future = T::Future;
+
+    fn call(&self, req: (Request<B>, C)) -> Self::Future {
+        let (mut request, context) = req;
+
+        if let Some(auth_data) = Has::<Option<AuthData>>::get(&context) {
+            self.inject(&mut request, auth_data);
+        }
+
+        self.inner.call((request, context))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::context::{ContextBuilder, Push};
+    use crate::EmptyContext;
+
+    type Context = ContextBuilder<Option<AuthData>, EmptyContext>;
+
+    struct EchoService;
+
+    impl Service<(Request<()>, Context)> for EchoService {
+        type Response = Request<()>;
+        type Error = ();
+        type Future = futures::future::Ready<Result<Self::Response, Self::Error>>;
+
+        fn call(&self, req: (Request<()>, Context)) -> Self::Future {
+            futures::future::ok(req.0)
+        }
+    }
+
+    async fn send(injector: &AuthInjector<EchoService>, auth_data: AuthData) -> Request<()> {
+        let request = Request::get("http://example.com/pets?limit=1")
+            .body(())
+            .unwrap();
+        injector
+            .call((request, EmptyContext.push(Some(auth_data))))
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_authorization_header() {
+        let injector = AuthInjector::new(EchoService);
+
+        let request = send(&injector, AuthData::basic("foo", "bar")).await;
+        assert_eq!(
+            request.headers().get(AUTHORIZATION).unwrap(),
+            "Basic Zm9vOmJhcg=="
+        );
+
+        let request = send(&injector, AuthData::bearer("token").unwrap()).await;
+        assert_eq!(
+            request.headers().get(AUTHORIZATION).unwrap(),
+            "Bearer token"
+        );
+
+        let request = send(&injector, AuthData::other("Hawk", "id=\"abc\"")).await;
+        assert_eq!(
+            request.headers().get(AUTHORIZATION).unwrap(),
+            "Hawk id=\"abc\""
+        );
+
+        let request = send(&injector, AuthData::apikey("key")).await;
+        assert!(request.headers().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_api_key() {
+        let injector = AuthInjector::new(EchoService)
+            .with_api_key(ApiKeyLocation::Header("X-API-Key".to_string()));
+        let request = send(&injector, AuthData::apikey("key")).await;
+        assert_eq!(request.headers().get("X-API-Key").unwrap(), "key");
+
+        let injector = AuthInjector::new(EchoService)
+            .with_api_key(ApiKeyLocation::Query("api key".to_string()));
+        let request = send(&injector, AuthData::apikey("a&b")).await;
+        assert_eq!(
+            request.uri(),
+            "http://example.com/pets?limit=1&api%20key=a%26b"
+        );
+        assert_eq!(
+            crate::auth::api_key_from_query(request.uri(), "api key").as_deref(),
+            Some("a&b")
+        );
+
+        let injector = AuthInjector::new(EchoService)
+            .with_api_key(ApiKeyLocation::Cookie("session".to_string()));
+        let request = send(&injector, AuthData::apikey("key")).await;
+        assert_eq!(request.headers().get(COOKIE).unwrap(), "session=key");
+    }
+}
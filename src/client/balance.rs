@@ -0,0 +1,196 @@
+//! Middleware which balances requests across several instances of a service.
+use hyper::http::request::Parts;
+use hyper::service::Service;
+use hyper::Request;
+use std::collections::hash_map::DefaultHasher;
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+type Affinity = dyn Fn(&Parts) -> Option<String> + Send + Sync;
+
+/// Middleware which balances requests across several endpoints, each a
+/// client for one instance of the service.
+///
+/// Requests are sent to each endpoint in turn. For sharded or stateful
+/// services, an affinity extractor can instead key requests, for example by
+/// tenant or by the resource in their path, so that requests with the same key
+/// are sent to the same endpoint. Keys are mapped to endpoints by rendezvous
+/// hashing of the endpoint names, so adding or removing an endpoint only moves
+/// the keys mapped to it.
+///
+/// ```ignore
+/// let client = BalancedService::new("pets-0", pets_0)
+///     .with_endpoint("pets-1", pets_1)
+///     .with_affinity(|parts: &Parts| {
+///         parts.uri.path().strip_prefix("/pets/")?.split('/').next().map(str::to_string)
+///     });
+/// ```
+pub struct BalancedService<T> {
+    endpoints: Vec<(String, T)>,
+    next: Arc<AtomicUsize>,
+    affinity: Option<Arc<Affinity>>,
+}
+
+impl<T> BalancedService<T> {
+    /// Create a middleware which sends requests to the given endpoint.
+    pub fn new<N: Into<String>>(name: N, endpoint: T) -> Self {
+        BalancedService {
+            endpoints: vec![(name.into(), endpoint)],
+            next: Arc::new(AtomicUsize::new(0)),
+            affinity: None,
+        }
+    }
+
+    /// Add an endpoint, identified by a name which is stable as endpoints are
+    /// added and removed, such as its host and port.
+    pub fn with_endpoint<N: Into<String>>(mut self, name: N, endpoint: T) -> Self {
+        self.endpoints.push((name.into(), endpoint));
+        self
+    }
+
+    /// Send requests with the same affinity key, as extracted by the given
+    /// function, to the same endpoint. Requests without a key are still sent
+    /// to each endpoint in turn.
+    pub fn with_affinity<F>(mut self, affinity: F) -> Self
+    where
+        F: Fn(&Parts) -> Option<String> + Send + Sync + 'static,
+    {
+        self.affinity = Some(Arc::new(affinity));
+        self
+    }
+
+    /// Index of the endpoint for requests with the given affinity key.
+    fn endpoint_for_key(&self, key: &str) -> usize {
+        self.endpoints
+            .iter()
+            .enumerate()
+            .max_by_key(|(_, (name, _))| {
+                let mut hasher = DefaultHasher::new();
+                (key, name).hash(&mut hasher);
+                hasher.finish()
+            })
+            .map_or(0, |(index, _)| index)
+    }
+
+    /// Index of the endpoint for the next request without an affinity key.
+    fn next_endpoint(&self) -> usize {
+        self.next.fetch_add(1, Ordering::Relaxed) % self.endpoints.len()
+    }
+}
+
+impl<T: Clone> Clone for BalancedService<T> {
+    fn clone(&self) -> Self {
+        BalancedService {
+            endpoints: self.endpoints.clone(),
+            next: self.next.clone(),
+            affinity: self.affinity.clone(),
+        }
+    }
+}
+
+impl<T> fmt::Debug for BalancedService<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("BalancedService")
+            .field(
+                "endpoints",
+                &self
+                    .endpoints
+                    .iter()
+                    .map(|(name, _)| name)
+                    .collect::<Vec<_>>(),
+            )
+            .field("affinity", &self.affinity.is_some())
+            .finish()
+    }
+}
+
+impl<T, B, C> Service<(Request<B>, C)> for BalancedService<T>
+where
+    T: Service<(Request<B>, C)>,
+{
+    type Response = T::Response;
+    type Error = T::Error;
+    type Future = T::Future;
+
+    fn call(&self, req: (Request<B>, C)) -> Self::Future {
+        let (request, context) = req;
+        let (parts, body) = request.into_parts();
+        let index = match self.affinity.as_ref().and_then(|affinity| affinity(&parts)) {
+            Some(key) => self.endpoint_for_key(&key),
+            None => self.next_endpoint(),
+        };
+        let request = Request::from_parts(parts, body);
+
+        self.endpoints[index].1.call((request, context))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::EmptyContext;
+
+    #[derive(Clone)]
+    struct Endpoint(&'static str);
+
+    impl Service<(Request<()>, EmptyContext)> for Endpoint {
+        type Response = &'static str;
+        type Error = ();
+        type Future = futures::future::Ready<Result<Self::Response, Self::Error>>;
+
+        fn call(&self, _: (Request<()>, EmptyContext)) -> Self::Future {
+            futures::future::ok(self.0)
+        }
+    }
+
+    async fn endpoint(client: &BalancedService<Endpoint>, path: &str) -> &'static str {
+        let request = Request::get(path).body(()).unwrap();
+        client.call((request, EmptyContext)).await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_round_robin() {
+        let client = BalancedService::new("a", Endpoint("a")).with_endpoint("b", Endpoint("b"));
+        let clone = client.clone();
+
+        assert_eq!(endpoint(&client, "/").await, "a");
+        assert_eq!(endpoint(&clone, "/").await, "b");
+        assert_eq!(endpoint(&client, "/").await, "a");
+    }
+
+    #[tokio::test]
+    async fn test_affinity() {
+        let affinity = |parts: &Parts| {
+            parts
+                .uri
+                .path()
+                .strip_prefix("/pets/")
+                .map(|id| id.to_string())
+        };
+        let client = BalancedService::new("a", Endpoint("a"))
+            .with_endpoint("b", Endpoint("b"))
+            .with_endpoint("c", Endpoint("c"))
+            .with_affinity(affinity);
+
+        let mut chosen = Vec::new();
+        for id in 0..20 {
+            let path = format!("/pets/{}", id);
+            let first = endpoint(&client, &path).await;
+            assert_eq!(endpoint(&client, &path).await, first);
+            chosen.push(first);
+        }
+        assert!(chosen.contains(&"a") && chosen.contains(&"b") && chosen.contains(&"c"));
+
+        // Removing an endpoint only moves the keys which were mapped to it.
+        let reduced = BalancedService::new("a", Endpoint("a"))
+            .with_endpoint("b", Endpoint("b"))
+            .with_affinity(affinity);
+        for (id, first) in chosen.into_iter().enumerate() {
+            if first != "c" {
+                assert_eq!(endpoint(&reduced, &format!("/pets/{}", id)).await, first);
+            }
+        }
+    }
+}
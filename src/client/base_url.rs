@@ -0,0 +1,199 @@
+//! Middleware which points requests at a configured base URL.
+use crate::ApiError;
+use futures::future::{ready, Either, Ready};
+use hyper::header::HOST;
+use hyper::http::uri::{Authority, Parts, PathAndQuery, Scheme};
+use hyper::service::Service;
+use hyper::{Request, Uri};
+
+/// Middleware which rewrites the scheme, host and port of requests to those
+/// of a base URL, and adds its path as a prefix to theirs.
+///
+/// This lets a client built for one server send its requests to another,
+/// such as a gateway, or the server for a different environment, without
+/// changing how requests are built. If the requests already have a prefix,
+/// such as the base path of the API, it can be replaced by stripping it
+/// first. Any `Host` header is removed, so that the HTTP client sets it for
+/// the new host.
+///
+/// ```ignore
+/// // Sends requests for http://localhost/v2/pets to https://gateway/petstore/v2/pets
+/// let client = BaseUrl::new(DropContextService::new(http_client), "https://gateway/petstore")?;
+/// ```
+#[derive(Clone, Debug)]
+pub struct BaseUrl<T> {
+    inner: T,
+    scheme: Scheme,
+    authority: Authority,
+    prefix: String,
+    strip_prefix: Option<String>,
+}
+
+impl<T> BaseUrl<T> {
+    /// Create a middleware which sends requests to the base URL, which must
+    /// be absolute, and mustn't have a query string.
+    pub fn new(inner: T, base_url: &str) -> Result<Self, ApiError> {
+        let invalid = |reason| ApiError(format!("Invalid base URL {}: {}", base_url, reason));
+        let uri: Uri = base_url.parse().map_err(|_| invalid("not a URI"))?;
+        let parts = uri.into_parts();
+        let (Some(scheme), Some(authority)) = (parts.scheme, parts.authority) else {
+            return Err(invalid("not absolute"));
+        };
+        let path_and_query = parts.path_and_query;
+        if path_and_query
+            .as_ref()
+            .and_then(PathAndQuery::query)
+            .is_some()
+        {
+            return Err(invalid("has a query string"));
+        }
+        let prefix = path_and_query
+            .as_ref()
+            .map_or("", PathAndQuery::path)
+            .trim_end_matches('/')
+            .to_string();
+
+        Ok(BaseUrl {
+            inner,
+            scheme,
+            authority,
+            prefix,
+            strip_prefix: None,
+        })
+    }
+
+    /// Remove the given prefix from the paths of requests which have it,
+    /// before adding the prefix of the base URL.
+    pub fn with_strip_prefix<P: Into<String>>(mut self, prefix: P) -> Self {
+        let prefix = prefix.into().trim_end_matches('/').to_string();
+        self.strip_prefix = Some(prefix).filter(|prefix| !prefix.is_empty());
+        self
+    }
+
+    /// The URI to send a request for the given URI to.
+    fn rewrite(&self, uri: &Uri) -> Result<Uri, ApiError> {
+        let mut path = uri.path();
+        if let Some(strip_prefix) = &self.strip_prefix {
+            if let Some(rest) = path.strip_prefix(strip_prefix.as_str()) {
+                if rest.is_empty() || rest.starts_with('/') {
+                    path = rest;
+                }
+            }
+        }
+        let path = match (self.prefix.as_str(), path) {
+            ("", "") => "/".to_string(),
+            (prefix, "/") if !prefix.is_empty() => prefix.to_string(),
+            (prefix, path) => format!("{}{}", prefix, path),
+        };
+        let path_and_query = match uri.query() {
+            Some(query) => format!("{}?{}", path, query),
+            None => path,
+        };
+
+        let mut parts = Parts::default();
+        parts.scheme = Some(self.scheme.clone());
+        parts.authority = Some(self.authority.clone());
+        parts.path_and_query = Some(
+            path_and_query
+                .parse()
+                .map_err(|e| ApiError(format!("Invalid path {}: {}", path_and_query, e)))?,
+        );
+        Uri::from_parts(parts).map_err(|e| ApiError(format!("Invalid URI: {}", e)))
+    }
+}
+
+impl<T, B, C> Service<(Request<B>, C)> for BaseUrl<T>
+where
+    T: Service<(Request<B>, C)>,
+    T::Error: From<ApiError>,
+{
+    type Response = T::Response;
+    type Error = T::Error;
+    type Future = Either<T::Future, Ready<Result<T::Response, T::Error>>>;
+
+    fn call(&self, req: (Request<B>, C)) -> Self::Future {
+        let (mut request, context) = req;
+        match self.rewrite(request.uri()) {
+            Ok(uri) => {
+                *request.uri_mut() = uri;
+                request.headers_mut().remove(HOST);
+                Either::Left(self.inner.call((request, context)))
+            }
+            Err(e) => Either::Right(ready(Err(e.into()))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::EmptyContext;
+
+    /// Server responding with the URI and headers of the request.
+    #[derive(Debug)]
+    struct EchoServer;
+
+    impl Service<(Request<()>, EmptyContext)> for EchoServer {
+        type Response = String;
+        type Error = ApiError;
+        type Future = Ready<Result<Self::Response, Self::Error>>;
+
+        fn call(&self, req: (Request<()>, EmptyContext)) -> Self::Future {
+            let request = req.0;
+            ready(Ok(format!(
+                "{} {:?}",
+                request.uri(),
+                request.headers().keys().collect::<Vec<_>>()
+            )))
+        }
+    }
+
+    async fn call(client: &BaseUrl<EchoServer>, uri: &str) -> String {
+        let request = Request::get(uri)
+            .header(HOST, "localhost")
+            .body(())
+            .unwrap();
+        client.call((request, EmptyContext)).await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_base_url() {
+        let client = BaseUrl::new(EchoServer, "https://gateway:8443/petstore/").unwrap();
+        assert_eq!(
+            call(&client, "http://localhost/v2/pets?limit=1").await,
+            "https://gateway:8443/petstore/v2/pets?limit=1 []"
+        );
+        assert_eq!(call(&client, "/").await, "https://gateway:8443/petstore []");
+
+        let client = client.with_strip_prefix("/v2");
+        assert_eq!(
+            call(&client, "http://localhost/v2/pets").await,
+            "https://gateway:8443/petstore/pets []"
+        );
+        assert_eq!(
+            call(&client, "http://localhost/v2").await,
+            "https://gateway:8443/petstore []"
+        );
+        assert_eq!(
+            call(&client, "http://localhost/v20/pets").await,
+            "https://gateway:8443/petstore/v20/pets []"
+        );
+
+        let client = BaseUrl::new(EchoServer, "http://staging").unwrap();
+        assert_eq!(
+            call(&client, "http://localhost/v2").await,
+            "http://staging/v2 []"
+        );
+
+        for (base_url, reason) in [
+            ("/petstore", "not absolute"),
+            ("http://gateway/?a=b", "has a query string"),
+            ("http://gate way", "not a URI"),
+        ] {
+            assert_eq!(
+                BaseUrl::new(EchoServer, base_url).unwrap_err().0,
+                format!("Invalid base URL {}: {}", base_url, reason)
+            );
+        }
+    }
+}
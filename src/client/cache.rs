@@ -0,0 +1,741 @@
+//! Middleware which caches responses to outgoing requests.
+//!
+//! Responses to `GET` requests are cached according to their `Cache-Control`
+//! header, as described in RFC 9111. Stale responses with an `ETag` are
+//! revalidated using `If-None-Match`. The `stale-while-revalidate` and
+//! `stale-if-error` extensions from RFC 5861 are supported, allowing stale
+//! responses to be served while revalidating in the background, or while the
+//! server is failing. Responses with a `Last-Modified` date are also
+//! revalidated, using `If-Modified-Since`.
+//!
+//! Responses are kept in a `CacheStore`, by default a `MemoryStore` holding
+//! the most recently used responses in memory.
+use crate::body::read_to_limit;
+use crate::{ApiError, CacheControl, PrefixedBody};
+use futures::future::BoxFuture;
+use http_body_util::{Either, Full};
+use hyper::body::{Body, Bytes};
+use hyper::header::{
+    HeaderMap, HeaderValue, AGE, CONTENT_LENGTH, ETAG, IF_MODIFIED_SINCE, IF_NONE_MATCH,
+    LAST_MODIFIED, VARY,
+};
+use hyper::rt::Executor;
+use hyper::service::Service;
+use hyper::{Method, Request, Response, StatusCode};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fmt;
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::{Duration, Instant};
+
+/// Body of a response from a `Cache`, which is either streamed from the
+/// server, or served from the cache.
+pub type CacheBody<B> = Either<PrefixedBody<B>, Full<Bytes>>;
+
+/// Default limit on the size of the bodies a `Cache` will cache, in bytes.
+pub const DEFAULT_MAX_SIZE: usize = 1024 * 1024;
+
+/// Freshness of a cached response.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Freshness {
+    Fresh,
+    StaleWhileRevalidate,
+    Stale,
+}
+
+/// Response held by a `CacheStore`.
+#[derive(Clone, Debug)]
+pub struct CachedResponse {
+    status: StatusCode,
+    headers: HeaderMap,
+    body: Bytes,
+    directives: CacheControl,
+    /// When the response was received, less its age on receipt.
+    date: Instant,
+}
+
+impl CachedResponse {
+    /// Status of the response.
+    pub fn status(&self) -> StatusCode {
+        self.status
+    }
+
+    /// Headers of the response.
+    pub fn headers(&self) -> &HeaderMap {
+        &self.headers
+    }
+
+    /// Body of the response.
+    pub fn body(&self) -> &Bytes {
+        &self.body
+    }
+
+    fn new(status: StatusCode, headers: HeaderMap, body: Bytes, received: Instant) -> Self {
+        let age = headers
+            .get(AGE)
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.parse().ok())
+            .map(Duration::from_secs)
+            .unwrap_or_default();
+
+        CachedResponse {
+            status,
+            directives: CacheControl::from_headers(&headers),
+            headers,
+            body,
+            date: received.checked_sub(age).unwrap_or(received),
+        }
+    }
+
+    /// Whether a response with the given status and headers may be cached.
+    fn cacheable(status: StatusCode, headers: &HeaderMap) -> bool {
+        let directives = CacheControl::from_headers(headers);
+        status == StatusCode::OK
+            && !directives.no_store
+            && !headers.contains_key(VARY)
+            && (directives.max_age.is_some()
+                || headers.contains_key(ETAG)
+                || headers.contains_key(LAST_MODIFIED))
+    }
+
+    fn age(&self, now: Instant) -> Duration {
+        now.saturating_duration_since(self.date)
+    }
+
+    fn freshness(&self, now: Instant) -> Freshness {
+        let lifetime = match self.directives.max_age {
+            Some(max_age) if !self.directives.no_cache => max_age,
+            _ => Duration::ZERO,
+        };
+        let age = self.age(now);
+        if age < lifetime {
+            Freshness::Fresh
+        } else if age < lifetime + self.directives.stale_while_revalidate.unwrap_or_default() {
+            Freshness::StaleWhileRevalidate
+        } else {
+            Freshness::Stale
+        }
+    }
+
+    /// Whether the response may be served if the server fails.
+    fn usable_on_error(&self, now: Instant) -> bool {
+        let lifetime = self.directives.max_age.unwrap_or_default();
+        self.age(now) < lifetime + self.directives.stale_if_error.unwrap_or_default()
+    }
+
+    /// Update the entry following a `304 Not Modified` response.
+    fn refresh(&mut self, headers: &HeaderMap, received: Instant) {
+        for name in headers.keys() {
+            self.headers.remove(name);
+        }
+        for (name, value) in headers {
+            self.headers.append(name, value.clone());
+        }
+        *self = CachedResponse::new(
+            self.status,
+            std::mem::take(&mut self.headers),
+            self.body.clone(),
+            received,
+        );
+    }
+
+    fn to_response<B>(&self, now: Instant) -> Response<CacheBody<B>> {
+        let mut response = Response::new(Either::Right(Full::new(self.body.clone())));
+        *response.status_mut() = self.status;
+        *response.headers_mut() = self.headers.clone();
+        response
+            .headers_mut()
+            .insert(AGE, HeaderValue::from(self.age(now).as_secs()));
+        response
+    }
+}
+
+/// Storage for the responses cached by a `Cache`, keyed by request URI.
+///
+/// Implementations may discard responses at any time, for example to limit
+/// their memory use.
+pub trait CacheStore: Send + Sync {
+    /// Cached response for the key, if there is one.
+    fn get(&self, key: &str) -> Option<CachedResponse>;
+
+    /// Cache a response, replacing any already cached for the key.
+    fn insert(&self, key: String, response: CachedResponse);
+
+    /// Discard the response cached for the key.
+    fn remove(&self, key: &str);
+
+    /// Discard all cached responses.
+    fn clear(&self);
+}
+
+#[derive(Debug, Default)]
+struct Lru {
+    entries: HashMap<String, (CachedResponse, u64)>,
+    /// Keys of the entries, by when they were last used.
+    used: BTreeMap<u64, String>,
+    tick: u64,
+}
+
+impl Lru {
+    /// Mark the entry for the key as the most recently used.
+    fn touch(&mut self, key: &str) -> Option<&CachedResponse> {
+        let (response, used) = self.entries.get_mut(key)?;
+        let key = self.used.remove(used)?;
+        self.tick += 1;
+        *used = self.tick;
+        self.used.insert(self.tick, key);
+        Some(response)
+    }
+}
+
+/// Store keeping up to a given number of responses in memory, discarding
+/// the least recently used when full.
+#[derive(Debug)]
+pub struct MemoryStore {
+    capacity: usize,
+    lru: Mutex<Lru>,
+}
+
+impl MemoryStore {
+    /// Create a store holding up to the given number of responses.
+    pub fn new(capacity: usize) -> Self {
+        MemoryStore {
+            capacity: capacity.max(1),
+            lru: Mutex::new(Lru::default()),
+        }
+    }
+
+    /// Number of responses held.
+    pub fn len(&self) -> usize {
+        self.lock().entries.len()
+    }
+
+    /// Whether no responses are held.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Lru> {
+        self.lru.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+impl Default for MemoryStore {
+    /// Store holding up to 1024 responses.
+    fn default() -> Self {
+        MemoryStore::new(1024)
+    }
+}
+
+impl CacheStore for MemoryStore {
+    fn get(&self, key: &str) -> Option<CachedResponse> {
+        self.lock().touch(key).cloned()
+    }
+
+    fn insert(&self, key: String, response: CachedResponse) {
+        let mut lru = self.lock();
+        if let Some(entry) = lru.entries.get_mut(&key) {
+            entry.0 = response;
+            lru.touch(&key);
+            return;
+        }
+        if lru.entries.len() >= self.capacity {
+            if let Some((_, oldest)) = lru.used.pop_first() {
+                lru.entries.remove(&oldest);
+            }
+        }
+        lru.tick += 1;
+        let tick = lru.tick;
+        lru.used.insert(tick, key.clone());
+        lru.entries.insert(key, (response, tick));
+    }
+
+    fn remove(&self, key: &str) {
+        let mut lru = self.lock();
+        if let Some((_, used)) = lru.entries.remove(key) {
+            lru.used.remove(&used);
+        }
+    }
+
+    fn clear(&self) {
+        let mut lru = self.lock();
+        lru.entries.clear();
+        lru.used.clear();
+    }
+}
+
+type Store = Arc<dyn CacheStore>;
+
+/// Middleware which caches responses to `GET` requests.
+///
+/// Cached responses are served from the store while fresh. Once stale, they
+/// are revalidated using their `ETag` or `Last-Modified` date, or served stale
+/// while being revalidated on the provided executor if the response allows
+/// `stale-while-revalidate`.
+/// Responses with a `Vary` header are not cached, nor are responses whose
+/// bodies are larger than the limit set by `with_max_size`, which are passed
+/// through instead.
+///
+/// Failures to read the body of a cacheable response are returned as errors
+/// from the inner service.
+///
+/// ```ignore
+/// let client = Cache::new(DropContextService::new(http_client), TokioExecutor::new());
+/// ```
+pub struct Cache<S, E> {
+    inner: S,
+    executor: E,
+    store: Store,
+    max_size: usize,
+    /// Keys of the entries being revalidated in the background.
+    revalidating: Arc<Mutex<HashSet<String>>>,
+}
+
+impl<S, E> Cache<S, E> {
+    /// Create a middleware which caches responses from the inner service,
+    /// spawning background revalidation on the provided executor.
+    ///
+    /// Responses are kept in a default `MemoryStore`.
+    pub fn new(inner: S, executor: E) -> Self {
+        Cache {
+            inner,
+            executor,
+            store: Arc::new(MemoryStore::default()),
+            max_size: DEFAULT_MAX_SIZE,
+            revalidating: Arc::new(Mutex::new(HashSet::new())),
+        }
+    }
+
+    /// Keep responses in the given store.
+    pub fn with_store<T: CacheStore + 'static>(mut self, store: T) -> Self {
+        self.store = Arc::new(store);
+        self
+    }
+
+    /// Cache only responses with bodies of up to the given number of bytes,
+    /// rather than `DEFAULT_MAX_SIZE`.
+    pub fn with_max_size(mut self, max_size: usize) -> Self {
+        self.max_size = max_size;
+        self
+    }
+
+    /// Discard all cached responses.
+    pub fn clear(&self) {
+        self.store.clear();
+    }
+}
+
+impl<S: Clone, E: Clone> Clone for Cache<S, E> {
+    fn clone(&self) -> Self {
+        Cache {
+            inner: self.inner.clone(),
+            executor: self.executor.clone(),
+            store: self.store.clone(),
+            max_size: self.max_size,
+            revalidating: self.revalidating.clone(),
+        }
+    }
+}
+
+impl<S: fmt::Debug, E> fmt::Debug for Cache<S, E> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Cache")
+            .field("inner", &self.inner)
+            .field("max_size", &self.max_size)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Send a request, which is conditional if there's a cached response, and
+/// update the cache with the result.
+async fn fetch<S, B, C, ResBody>(
+    inner: S,
+    store: Store,
+    max_size: usize,
+    key: String,
+    mut request: Request<B>,
+    context: C,
+    cached: Option<CachedResponse>,
+) -> Result<Response<CacheBody<ResBody>>, S::Error>
+where
+    S: Service<(Request<B>, C), Response = Response<ResBody>>,
+    S::Error: From<ApiError>,
+    ResBody: Body<Data = Bytes>,
+    ResBody::Error: fmt::Display,
+{
+    if let Some(cached) = &cached {
+        if let Some(etag) = cached.headers.get(ETAG) {
+            request.headers_mut().insert(IF_NONE_MATCH, etag.clone());
+        }
+        if let Some(last_modified) = cached.headers.get(LAST_MODIFIED) {
+            request
+                .headers_mut()
+                .insert(IF_MODIFIED_SINCE, last_modified.clone());
+        }
+    }
+
+    let result = inner.call((request, context)).await;
+    let now = Instant::now();
+
+    let response = match (result, cached) {
+        (Ok(response), Some(mut cached)) if response.status() == StatusCode::NOT_MODIFIED => {
+            cached.refresh(response.headers(), now);
+            let response = cached.to_response(now);
+            store.insert(key, cached);
+            return Ok(response);
+        }
+        (Ok(response), Some(cached))
+            if response.status().is_server_error() && cached.usable_on_error(now) =>
+        {
+            return Ok(cached.to_response(now));
+        }
+        (Err(_), Some(cached)) if cached.usable_on_error(now) => {
+            return Ok(cached.to_response(now));
+        }
+        (result, _) => result?,
+    };
+
+    let too_large = content_length(response.headers())
+        .unwrap_or_else(|| response.body().size_hint().lower())
+        > max_size as u64;
+    if too_large || !CachedResponse::cacheable(response.status(), response.headers()) {
+        store.remove(&key);
+        return Ok(response.map(pass_through));
+    }
+
+    let (parts, body) = response.into_parts();
+    let body = match read_to_limit(body, max_size)
+        .await
+        .map_err(|e| ApiError(format!("Failed to read response body: {}", e)))?
+    {
+        Ok(body) => body,
+        Err(body) => {
+            store.remove(&key);
+            return Ok(Response::from_parts(parts, Either::Left(body)));
+        }
+    };
+
+    let entry = CachedResponse::new(parts.status, parts.headers.clone(), body.clone(), now);
+    store.insert(key, entry);
+    Ok(Response::from_parts(parts, Either::Right(Full::new(body))))
+}
+
+fn content_length(headers: &HeaderMap) -> Option<u64> {
+    headers
+        .get(CONTENT_LENGTH)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.parse().ok())
+}
+
+fn pass_through<B>(body: B) -> CacheBody<B> {
+    Either::Left(PrefixedBody::new(Bytes::new(), Box::pin(body)))
+}
+
+impl<S, E, B, C, ResBody> Service<(Request<B>, C)> for Cache<S, E>
+where
+    S: Service<(Request<B>, C), Response = Response<ResBody>> + Clone + Send + 'static,
+    S::Future: Send,
+    S::Error: From<ApiError> + Send + 'static,
+    E: Executor<BoxFuture<'static, ()>>,
+    B: Default + Send + 'static,
+    C: Clone + Send + 'static,
+    ResBody: Body<Data = Bytes> + Send + 'static,
+    ResBody::Error: fmt::Display,
+{
+    type Response = Response<CacheBody<ResBody>>;
+    type Error = S::Error;
+    type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn call(&self, req: (Request<B>, C)) -> Self::Future {
+        let (request, context) = req;
+        let inner = self.inner.clone();
+
+        if request.method() != Method::GET {
+            return Box::pin(
+                async move { Ok(inner.call((request, context)).await?.map(pass_through)) },
+            );
+        }
+
+        let key = request.uri().to_string();
+        let now = Instant::now();
+        let cached = self.store.get(&key).map(|entry| {
+            let freshness = entry.freshness(now);
+            // Only one background revalidation is started per entry.
+            let revalidate = freshness == Freshness::StaleWhileRevalidate
+                && self
+                    .revalidating
+                    .lock()
+                    .unwrap_or_else(PoisonError::into_inner)
+                    .insert(key.clone());
+            (freshness, revalidate, entry)
+        });
+
+        match cached {
+            Some((Freshness::Fresh, _, entry)) => {
+                Box::pin(futures::future::ok(entry.to_response(now)))
+            }
+            Some((Freshness::StaleWhileRevalidate, revalidate, entry)) => {
+                if revalidate {
+                    let mut background = Request::new(B::default());
+                    *background.method_mut() = request.method().clone();
+                    *background.uri_mut() = request.uri().clone();
+                    *background.version_mut() = request.version();
+                    *background.headers_mut() = request.headers().clone();
+
+                    let revalidating = self.revalidating.clone();
+                    let response = fetch(
+                        inner,
+                        self.store.clone(),
+                        self.max_size,
+                        key.clone(),
+                        background,
+                        context,
+                        Some(entry.clone()),
+                    );
+                    self.executor.execute(Box::pin(async move {
+                        let _ = response.await;
+                        revalidating
+                            .lock()
+                            .unwrap_or_else(PoisonError::into_inner)
+                            .remove(&key);
+                    }));
+                }
+                Box::pin(futures::future::ok(entry.to_response(now)))
+            }
+            Some((Freshness::Stale, _, entry)) => Box::pin(fetch(
+                inner,
+                self.store.clone(),
+                self.max_size,
+                key,
+                request,
+                context,
+                Some(entry),
+            )),
+            None => Box::pin(fetch(
+                inner,
+                self.store.clone(),
+                self.max_size,
+                key,
+                request,
+                context,
+                None,
+            )),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::EmptyContext;
+    use http_body_util::combinators::UnsyncBoxBody;
+    use http_body_util::{BodyExt, StreamBody};
+    use hyper::body::Frame;
+    use hyper::header::CACHE_CONTROL;
+    use hyper_util::rt::TokioExecutor;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    const LAST_MODIFIED_DATE: &str = "Wed, 21 Oct 2015 07:28:00 GMT";
+
+    /// Server whose responses are configured by the test, recording the
+    /// `If-None-Match` and `If-Modified-Since` headers of each request.
+    #[derive(Clone, Default)]
+    struct TestServer {
+        response: Arc<Mutex<Option<(StatusCode, &'static str)>>>,
+        conditional: Arc<Mutex<Vec<Option<HeaderValue>>>>,
+        modified_since: Arc<Mutex<Vec<Option<HeaderValue>>>>,
+        count: Arc<AtomicUsize>,
+    }
+
+    impl TestServer {
+        fn respond(&self, status: StatusCode, cache_control: &'static str) {
+            *self.response.lock().unwrap() = Some((status, cache_control));
+        }
+
+        fn fail(&self) {
+            *self.response.lock().unwrap() = None;
+        }
+    }
+
+    impl Service<(Request<()>, EmptyContext)> for TestServer {
+        type Response = Response<Full<Bytes>>;
+        type Error = ApiError;
+        type Future = futures::future::Ready<Result<Self::Response, Self::Error>>;
+
+        fn call(&self, req: (Request<()>, EmptyContext)) -> Self::Future {
+            let count = self.count.fetch_add(1, Ordering::SeqCst) + 1;
+            self.conditional
+                .lock()
+                .unwrap()
+                .push(req.0.headers().get(IF_NONE_MATCH).cloned());
+            self.modified_since
+                .lock()
+                .unwrap()
+                .push(req.0.headers().get(IF_MODIFIED_SINCE).cloned());
+
+            let result = match *self.response.lock().unwrap() {
+                Some((status, cache_control)) => {
+                    let mut response = Response::new(Full::new(Bytes::from(count.to_string())));
+                    *response.status_mut() = status;
+                    let headers = response.headers_mut();
+                    headers.insert(CACHE_CONTROL, HeaderValue::from_static(cache_control));
+                    headers.insert(ETAG, HeaderValue::from_static("\"v1\""));
+                    headers.insert(LAST_MODIFIED, HeaderValue::from_static(LAST_MODIFIED_DATE));
+                    Ok(response)
+                }
+                None => Err(ApiError("Connection refused".to_string())),
+            };
+            futures::future::ready(result)
+        }
+    }
+
+    /// Server whose cacheable responses have bodies of unknown size.
+    #[derive(Clone)]
+    struct ChunkedServer;
+
+    impl Service<(Request<()>, EmptyContext)> for ChunkedServer {
+        type Response = Response<UnsyncBoxBody<Bytes, ApiError>>;
+        type Error = ApiError;
+        type Future = futures::future::Ready<Result<Self::Response, Self::Error>>;
+
+        fn call(&self, _: (Request<()>, EmptyContext)) -> Self::Future {
+            let chunks = ["ab", "cd"].map(|chunk| Ok(Frame::data(Bytes::from(chunk))));
+            let body = StreamBody::new(futures::stream::iter(chunks));
+            let mut response = Response::new(UnsyncBoxBody::new(body));
+            response
+                .headers_mut()
+                .insert(CACHE_CONTROL, HeaderValue::from_static("max-age=3600"));
+            futures::future::ok(response)
+        }
+    }
+
+    async fn get(
+        cache: &Cache<TestServer, TokioExecutor>,
+    ) -> Result<(StatusCode, String), ApiError> {
+        let response = cache
+            .call((Request::get("/pets").body(()).unwrap(), EmptyContext))
+            .await?;
+        let status = response.status();
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        Ok((status, String::from_utf8(body.to_vec()).unwrap()))
+    }
+
+    #[tokio::test]
+    async fn test_fresh_and_revalidated() {
+        let server = TestServer::default();
+        let cache = Cache::new(server.clone(), TokioExecutor::new());
+
+        server.respond(StatusCode::OK, "max-age=3600");
+        assert_eq!(get(&cache).await.unwrap(), (StatusCode::OK, "1".into()));
+        assert_eq!(get(&cache).await.unwrap(), (StatusCode::OK, "1".into()));
+        assert_eq!(server.count.load(Ordering::SeqCst), 1);
+
+        // Responses which must be revalidated are requested conditionally,
+        // and the cached body served if not modified.
+        cache.clear();
+        server.respond(StatusCode::OK, "no-cache");
+        assert_eq!(get(&cache).await.unwrap(), (StatusCode::OK, "2".into()));
+        server.respond(StatusCode::NOT_MODIFIED, "no-cache");
+        assert_eq!(get(&cache).await.unwrap(), (StatusCode::OK, "2".into()));
+        assert_eq!(
+            *server.conditional.lock().unwrap(),
+            vec![None, None, Some(HeaderValue::from_static("\"v1\""))]
+        );
+        assert_eq!(
+            server.modified_since.lock().unwrap()[2],
+            Some(HeaderValue::from_static(LAST_MODIFIED_DATE))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_max_size() {
+        let server = TestServer::default();
+        server.respond(StatusCode::OK, "max-age=3600");
+
+        // Bodies with a known size over the limit are passed through.
+        let cache = Cache::new(server.clone(), TokioExecutor::new()).with_max_size(0);
+        assert_eq!(get(&cache).await.unwrap(), (StatusCode::OK, "1".into()));
+        assert_eq!(get(&cache).await.unwrap(), (StatusCode::OK, "2".into()));
+
+        // As are bodies found to be over the limit while reading them.
+        let cache = Cache::new(ChunkedServer, TokioExecutor::new()).with_max_size(3);
+        let response = cache
+            .call((Request::get("/pets").body(()).unwrap(), EmptyContext))
+            .await
+            .unwrap();
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        assert_eq!(body, "abcd");
+        assert!(cache.store.get("/pets").is_none());
+
+        let cache = cache.with_max_size(4);
+        cache
+            .call((Request::get("/pets").body(()).unwrap(), EmptyContext))
+            .await
+            .unwrap();
+        assert_eq!(cache.store.get("/pets").unwrap().body(), "abcd");
+    }
+
+    #[test]
+    fn test_memory_store() {
+        let response = |body: &'static str| {
+            CachedResponse::new(
+                StatusCode::OK,
+                HeaderMap::new(),
+                Bytes::from(body),
+                Instant::now(),
+            )
+        };
+        let store = MemoryStore::new(2);
+        store.insert("a".into(), response("a"));
+        store.insert("b".into(), response("b"));
+
+        // Using an entry keeps it over the least recently used.
+        assert!(store.get("a").is_some());
+        store.insert("c".into(), response("c"));
+        assert_eq!(store.len(), 2);
+        assert!(store.get("b").is_none());
+        assert_eq!(store.get("a").unwrap().body(), "a");
+
+        store.insert("c".into(), response("d"));
+        assert_eq!(store.get("c").unwrap().body(), "d");
+        store.remove("a");
+        assert_eq!(store.len(), 1);
+        store.clear();
+        assert!(store.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_stale_if_error() {
+        let server = TestServer::default();
+        let cache = Cache::new(server.clone(), TokioExecutor::new());
+
+        server.respond(StatusCode::OK, "max-age=0, stale-if-error=3600");
+        assert_eq!(get(&cache).await.unwrap(), (StatusCode::OK, "1".into()));
+
+        server.fail();
+        assert_eq!(get(&cache).await.unwrap(), (StatusCode::OK, "1".into()));
+
+        server.respond(StatusCode::SERVICE_UNAVAILABLE, "no-store");
+        assert_eq!(get(&cache).await.unwrap(), (StatusCode::OK, "1".into()));
+
+        cache.clear();
+        server.fail();
+        assert!(get(&cache).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_stale_while_revalidate() {
+        let server = TestServer::default();
+        let cache = Cache::new(server.clone(), TokioExecutor::new());
+
+        server.respond(StatusCode::OK, "max-age=0, stale-while-revalidate=3600");
+        assert_eq!(get(&cache).await.unwrap(), (StatusCode::OK, "1".into()));
+
+        // The stale response is served while it's revalidated in the background.
+        assert_eq!(get(&cache).await.unwrap(), (StatusCode::OK, "1".into()));
+        for _ in 0..10 {
+            tokio::task::yield_now().await;
+        }
+        assert_eq!(server.count.load(Ordering::SeqCst), 2);
+        assert_eq!(get(&cache).await.unwrap(), (StatusCode::OK, "2".into()));
+    }
+}
@@ -0,0 +1,105 @@
+//! Middleware which passes the deadline of a request on to the services it
+//! calls.
+use crate::context::Has;
+use crate::deadline::{Deadline, DeadlineHeader};
+use hyper::service::Service;
+use hyper::Request;
+
+/// Middleware which reads the `Option<Deadline>` from the context of outgoing
+/// requests, and sends the time remaining until it in a header.
+///
+/// A server handling a request with a deadline can pass its context on to
+/// the requests it makes, so that the services it calls give up when it does.
+/// Requests which already have the header are left unchanged.
+///
+/// ```ignore
+/// let client = DeadlinePropagator::new(DropContextService::new(http_client))
+///     .with_header(DeadlineHeader::grpc_timeout());
+/// ```
+#[derive(Clone, Debug)]
+pub struct DeadlinePropagator<T> {
+    inner: T,
+    header: DeadlineHeader,
+}
+
+impl<T> DeadlinePropagator<T> {
+    /// Create a middleware which sends deadlines in the `X-Request-Deadline`
+    /// header.
+    pub fn new(inner: T) -> Self {
+        DeadlinePropagator {
+            inner,
+            header: DeadlineHeader::default(),
+        }
+    }
+
+    /// Send deadlines in the given header.
+    pub fn with_header(mut self, header: DeadlineHeader) -> Self {
+        self.header = header;
+        self
+    }
+}
+
+impl<T, B, C> Service<(Request<B>, C)> for DeadlinePropagator<T>
+where
+    T: Service<(Request<B>, C)>,
+    C: Has<Option<Deadline>>,
+{
+    type Response = T::Response;
+    type Error = T::Error;
+    type Future = T::Future;
+
+    fn call(&self, req: (Request<B>, C)) -> Self::Future {
+        let (mut request, context) = req;
+        if let Some(deadline) = context.get() {
+            if self.header.deadline(request.headers()).is_none() {
+                self.header.insert(request.headers_mut(), *deadline);
+            }
+        }
+        self.inner.call((request, context))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::context::Push;
+    use crate::deadline::GRPC_TIMEOUT;
+    use hyper::header::HeaderMap;
+    use std::time::Duration;
+
+    crate::new_context_type!(TestContext, TestEmptyContext, Option<Deadline>);
+
+    type Context = TestContext<Option<Deadline>, TestEmptyContext>;
+
+    struct HeaderService;
+
+    impl Service<(Request<()>, Context)> for HeaderService {
+        type Response = HeaderMap;
+        type Error = ();
+        type Future = futures::future::Ready<Result<Self::Response, Self::Error>>;
+
+        fn call(&self, req: (Request<()>, Context)) -> Self::Future {
+            futures::future::ok(req.0.headers().clone())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_deadline_propagator() {
+        let client =
+            DeadlinePropagator::new(HeaderService).with_header(DeadlineHeader::grpc_timeout());
+        let deadline = Some(Deadline::after(Duration::from_secs(5)));
+
+        let headers = client
+            .call((Request::new(()), TestEmptyContext.push(deadline)))
+            .await
+            .unwrap();
+        let value = headers[GRPC_TIMEOUT].to_str().unwrap();
+        assert!(value.ends_with('u') && value.len() == 8, "{}", value);
+
+        let headers = client
+            .call((Request::new(()), TestEmptyContext.push(None::<Deadline>)))
+            .await
+            .unwrap();
+        assert!(!headers.contains_key(GRPC_TIMEOUT));
+    }
+}
@@ -0,0 +1,147 @@
+//! Middleware which decompresses the bodies of responses to outgoing requests.
+use crate::compression::{self, CompressionBody};
+use crate::{ApiError, ContentCoding};
+use futures::future::BoxFuture;
+use http_body_util::{BodyExt, Either, Full};
+use hyper::body::{Body, Bytes};
+use hyper::header::{ACCEPT_ENCODING, CONTENT_ENCODING, CONTENT_LENGTH};
+use hyper::service::Service;
+use hyper::{Request, Response};
+use std::fmt;
+
+/// Middleware which asks for compressed responses, and decompresses them
+/// before they reach the client.
+///
+/// Requests without an `Accept-Encoding` header are sent with one listing
+/// the codings enabled by the crate features. Responses with a supported
+/// `Content-Encoding` are read in full and decompressed, failing with an
+/// `ApiError` if they would decompress to more than the maximum size.
+/// Other responses are passed through unchanged.
+///
+/// ```ignore
+/// let client = Decompression::new(DropContextService::new(http_client));
+/// ```
+#[derive(Clone, Debug)]
+pub struct Decompression<T> {
+    inner: T,
+    max_size: usize,
+}
+
+impl<T> Decompression<T> {
+    /// Create a middleware which decompresses response bodies of up to 64MB.
+    pub fn new(inner: T) -> Self {
+        Decompression {
+            inner,
+            max_size: 64 * 1024 * 1024,
+        }
+    }
+
+    /// Fail responses which decompress to more than the given number of
+    /// bytes.
+    pub fn with_max_size(mut self, max_size: usize) -> Self {
+        self.max_size = max_size;
+        self
+    }
+}
+
+impl<T, B, C, ResBody> Service<(Request<B>, C)> for Decompression<T>
+where
+    T: Service<(Request<B>, C), Response = Response<ResBody>>,
+    T::Future: Send + 'static,
+    T::Error: From<ApiError>,
+    ResBody: Body + Send + 'static,
+    ResBody::Data: Send,
+    ResBody::Error: fmt::Display,
+{
+    type Response = Response<CompressionBody<ResBody>>;
+    type Error = T::Error;
+    type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn call(&self, req: (Request<B>, C)) -> Self::Future {
+        let (mut request, context) = req;
+        request
+            .headers_mut()
+            .entry(ACCEPT_ENCODING)
+            .or_insert_with(compression::accept_encoding);
+        let max_size = self.max_size;
+        let response = self.inner.call((request, context));
+
+        Box::pin(async move {
+            let response = response.await?;
+            let coding = response
+                .headers()
+                .get(CONTENT_ENCODING)
+                .and_then(|value| value.to_str().ok())
+                .and_then(|value| value.parse::<ContentCoding>().ok())
+                .filter(|coding| compression::SUPPORTED.contains(coding));
+            let Some(coding) = coding else {
+                return Ok(response.map(Either::Left));
+            };
+
+            let (mut parts, body) = response.into_parts();
+            let body = body
+                .collect()
+                .await
+                .map_err(|e| ApiError(format!("Failed to read response body: {}", e)))?
+                .to_bytes();
+            let decompressed = compression::decompress(coding, &body, max_size)?;
+            parts.headers.remove(CONTENT_ENCODING);
+            parts.headers.remove(CONTENT_LENGTH);
+            Ok(Response::from_parts(
+                parts,
+                Either::Right(Full::new(Bytes::from(decompressed))),
+            ))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::EmptyContext;
+    use hyper::header::HeaderValue;
+
+    /// Server compressing its response with the first coding accepted.
+    struct CompressingServer;
+
+    impl Service<(Request<()>, EmptyContext)> for CompressingServer {
+        type Response = Response<Full<Bytes>>;
+        type Error = ApiError;
+        type Future = futures::future::Ready<Result<Self::Response, Self::Error>>;
+
+        fn call(&self, req: (Request<()>, EmptyContext)) -> Self::Future {
+            let accept_encoding = req.0.headers()[ACCEPT_ENCODING].to_str().unwrap();
+            let coding: ContentCoding = accept_encoding.split(',').next().unwrap().parse().unwrap();
+            let body = compression::compress(coding, b"Hello, world!").unwrap();
+            let mut response = Response::new(Full::new(Bytes::from(body)));
+            response
+                .headers_mut()
+                .insert(CONTENT_ENCODING, HeaderValue::from_static(coding.as_str()));
+            futures::future::ok(response)
+        }
+    }
+
+    #[tokio::test]
+    async fn test_decompression() {
+        let client = Decompression::new(CompressingServer);
+        let response = client.call((Request::new(()), EmptyContext)).await.unwrap();
+        assert!(!response.headers().contains_key(CONTENT_ENCODING));
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        assert_eq!(body, "Hello, world!");
+
+        let request = Request::builder()
+            .header(ACCEPT_ENCODING, "identity")
+            .body(())
+            .unwrap();
+        let response = client.call((request, EmptyContext)).await.unwrap();
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        assert_eq!(body, "Hello, world!");
+
+        let client = Decompression::new(CompressingServer).with_max_size(5);
+        let result = client.call((Request::new(()), EmptyContext)).await;
+        assert_eq!(
+            result.unwrap_err().0,
+            "Decompressed body is larger than 5 bytes"
+        );
+    }
+}
@@ -0,0 +1,124 @@
+//! Middleware which detects responses from deprecated operations.
+use crate::deprecation::Deprecation;
+use futures::future::{BoxFuture, FutureExt};
+use hyper::service::Service;
+use hyper::{Method, Request, Response, Uri};
+use std::fmt;
+use std::sync::Arc;
+
+type Callback = dyn Fn(&Method, &Uri, &Deprecation) + Send + Sync;
+
+/// Middleware which passes the details of responses with deprecation headers
+/// to a callback, e.g. to log a warning.
+///
+/// ```ignore
+/// let client = DeprecationDetector::new(client, |method, uri, deprecation| {
+///     log::warn!("{} {} is deprecated: {:?}", method, uri, deprecation);
+/// });
+/// ```
+pub struct DeprecationDetector<T> {
+    inner: T,
+    callback: Arc<Callback>,
+}
+
+impl<T> DeprecationDetector<T> {
+    /// Create a middleware which passes deprecations to the given callback.
+    pub fn new<F>(inner: T, callback: F) -> Self
+    where
+        F: Fn(&Method, &Uri, &Deprecation) + Send + Sync + 'static,
+    {
+        DeprecationDetector {
+            inner,
+            callback: Arc::new(callback),
+        }
+    }
+}
+
+impl<T: Clone> Clone for DeprecationDetector<T> {
+    fn clone(&self) -> Self {
+        DeprecationDetector {
+            inner: self.inner.clone(),
+            callback: self.callback.clone(),
+        }
+    }
+}
+
+impl<T: fmt::Debug> fmt::Debug for DeprecationDetector<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("DeprecationDetector")
+            .field("inner", &self.inner)
+            .finish()
+    }
+}
+
+impl<T, B, C, ResBody> Service<(Request<B>, C)> for DeprecationDetector<T>
+where
+    T: Service<(Request<B>, C), Response = Response<ResBody>>,
+    T::Future: Send + 'static,
+{
+    type Response = T::Response;
+    type Error = T::Error;
+    type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn call(&self, req: (Request<B>, C)) -> Self::Future {
+        let method = req.0.method().clone();
+        let uri = req.0.uri().clone();
+        let callback = self.callback.clone();
+
+        Box::pin(self.inner.call(req).map(move |response| {
+            let response = response?;
+            if let Some(deprecation) = Deprecation::from_headers(response.headers()) {
+                callback(&method, &uri, &deprecation);
+            }
+            Ok(response)
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::deprecation::DEPRECATION;
+    use crate::EmptyContext;
+    use hyper::header::HeaderValue;
+    use std::sync::Mutex;
+
+    struct DeprecatedService;
+
+    impl Service<(Request<()>, EmptyContext)> for DeprecatedService {
+        type Response = Response<()>;
+        type Error = ();
+        type Future = futures::future::Ready<Result<Self::Response, Self::Error>>;
+
+        fn call(&self, req: (Request<()>, EmptyContext)) -> Self::Future {
+            let mut response = Response::new(());
+            if req.0.uri().path() == "/old" {
+                response
+                    .headers_mut()
+                    .insert(DEPRECATION, HeaderValue::from_static("@0"));
+            }
+            futures::future::ok(response)
+        }
+    }
+
+    #[tokio::test]
+    async fn test_deprecation_detector() {
+        let detected = Arc::new(Mutex::new(Vec::new()));
+        let reported = detected.clone();
+        let client = DeprecationDetector::new(
+            DeprecatedService,
+            move |_: &Method, uri: &Uri, _: &Deprecation| {
+                reported.lock().unwrap().push(uri.to_string())
+            },
+        );
+
+        for path in ["/old", "/new"] {
+            client
+                .call((Request::get(path).body(()).unwrap(), EmptyContext))
+                .await
+                .unwrap();
+        }
+
+        assert_eq!(*detected.lock().unwrap(), vec!["/old".to_string()]);
+    }
+}
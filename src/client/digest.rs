@@ -0,0 +1,352 @@
+//! Middleware which authenticates outgoing requests using HTTP Digest authentication.
+use crate::auth::digest::DigestChallenge;
+use crate::auth::AuthData;
+use crate::context::Has;
+use crate::ApiError;
+use futures::future::BoxFuture;
+use http_body_util::{BodyExt, Either, Full};
+use hyper::body::{Body, Bytes};
+use hyper::header::{HeaderValue, AUTHORIZATION, HOST};
+use hyper::http::request::Parts;
+use hyper::service::Service;
+use hyper::{Request, Response, StatusCode};
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+
+/// Middleware which answers Digest challenges using the `AuthData::Digest`
+/// credentials in the context of outgoing requests.
+///
+/// The most recent challenge from each server is remembered, so later
+/// requests to it are sent with credentials straight away. Requests rejected
+/// with a new or stale challenge are retried once with a response to it.
+///
+/// Requests with Digest credentials have their bodies buffered to allow this,
+/// and are passed to the inner service with an `Either::Left` body. Requests
+/// without them are passed on unchanged, with an `Either::Right` body.
+///
+/// ```ignore
+/// let client = DigestAuthenticator::new(DropContextService::new(http_client));
+/// let context = EmptyContext.push(Some(AuthData::digest("user", "password")));
+/// ```
+#[derive(Clone, Debug)]
+pub struct DigestAuthenticator<T> {
+    inner: T,
+    challenges: Arc<Mutex<Challenges>>,
+}
+
+/// Challenges received, by protection space.
+#[derive(Debug, Default)]
+struct Challenges {
+    /// Challenges by server authority and realm, with the number of requests
+    /// made with each nonce.
+    spaces: HashMap<(String, String), (DigestChallenge, u32)>,
+    /// Realm of the most recent challenge from each server authority.
+    realms: HashMap<String, String>,
+}
+
+impl<T> DigestAuthenticator<T> {
+    /// Create a middleware that answers Digest challenges.
+    pub fn new(inner: T) -> Self {
+        DigestAuthenticator {
+            inner,
+            challenges: Arc::default(),
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Challenges> {
+        self.challenges
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Remember a challenge from the server the request was sent to.
+    fn store(&self, parts: &Parts, challenge: DigestChallenge) {
+        let authority = authority(parts);
+        let mut challenges = self.lock();
+        challenges
+            .realms
+            .insert(authority.clone(), challenge.realm.clone());
+        challenges
+            .spaces
+            .insert((authority, challenge.realm.clone()), (challenge, 0));
+    }
+
+    /// Build the `Authorization` header responding to the most recent
+    /// challenge from the server the request is for, returning it along with
+    /// the nonce used.
+    fn authorization(
+        &self,
+        parts: &Parts,
+        username: &str,
+        password: &str,
+    ) -> Option<(HeaderValue, String)> {
+        let authority = authority(parts);
+        let mut challenges = self.lock();
+        let Challenges { spaces, realms } = &mut *challenges;
+        let realm = realms.get(&authority)?.clone();
+        let (challenge, count) = spaces.get_mut(&(authority, realm))?;
+        *count += 1;
+
+        let uri = parts.uri.path_and_query().map_or("/", |p| p.as_str());
+        let cnonce = uuid::Uuid::new_v4().simple().to_string();
+        let response = challenge.respond(username, password, &parts.method, uri, &cnonce, *count);
+        let value = HeaderValue::from_str(&response.to_string()).ok()?;
+        Some((value, challenge.nonce.clone()))
+    }
+}
+
+/// Authority of the server a request is for, from its URI or `Host` header.
+fn authority(parts: &Parts) -> String {
+    parts
+        .uri
+        .authority()
+        .map(|authority| authority.as_str())
+        .or_else(|| parts.headers.get(HOST).and_then(|host| host.to_str().ok()))
+        .unwrap_or_default()
+        .to_ascii_lowercase()
+}
+
+fn build_request<B>(
+    parts: &Parts,
+    body: &Bytes,
+    authorization: Option<HeaderValue>,
+) -> Request<Either<Full<Bytes>, B>> {
+    let mut request = Request::new(Either::Left(Full::new(body.clone())));
+    *request.method_mut() = parts.method.clone();
+    *request.uri_mut() = parts.uri.clone();
+    *request.version_mut() = parts.version;
+    *request.headers_mut() = parts.headers.clone();
+    if let Some(authorization) = authorization {
+        request.headers_mut().insert(AUTHORIZATION, authorization);
+    }
+    request
+}
+
+impl<T, B, C, ResBody> Service<(Request<B>, C)> for DigestAuthenticator<T>
+where
+    T: Service<(Request<Either<Full<Bytes>, B>>, C), Response = Response<ResBody>>
+        + Clone
+        + Send
+        + 'static,
+    T::Future: Send,
+    T::Error: From<ApiError>,
+    B: Body + Send + 'static,
+    B::Data: Send,
+    B::Error: fmt::Display,
+    C: Has<Option<AuthData>> + Clone + Send + 'static,
+    ResBody: Send + 'static,
+{
+    type Response = T::Response;
+    type Error = T::Error;
+    type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn call(&self, req: (Request<B>, C)) -> Self::Future {
+        let (request, context) = req;
+        let credentials = match Has::<Option<AuthData>>::get(&context) {
+            Some(AuthData::Digest(username, password)) => (username.clone(), password.clone()),
+            _ => {
+                let request = request.map(Either::Right);
+                return Box::pin(self.inner.call((request, context)));
+            }
+        };
+        let authenticator = self.clone();
+
+        Box::pin(async move {
+            let (username, password) = &credentials;
+            let (parts, body) = request.into_parts();
+            let body = body
+                .collect()
+                .await
+                .map_err(|e| ApiError(format!("Failed to read request body: {}", e)))?
+                .to_bytes();
+
+            let (authorization, nonce) = authenticator
+                .authorization(&parts, username, password.expose_secret())
+                .unzip();
+            let request = build_request(&parts, &body, authorization);
+            let response = authenticator.inner.call((request, context.clone())).await?;
+
+            if response.status() != StatusCode::UNAUTHORIZED {
+                return Ok(response);
+            }
+            let challenge = match DigestChallenge::from_headers(response.headers()) {
+                // A fresh challenge rejecting a response to the same nonce
+                // means the credentials are wrong, so there's no point retrying.
+                Some(challenge) if challenge.stale || nonce.as_ref() != Some(&challenge.nonce) => {
+                    challenge
+                }
+                _ => return Ok(response),
+            };
+
+            authenticator.store(&parts, challenge);
+            let authorization = authenticator
+                .authorization(&parts, username, password.expose_secret())
+                .map(|(authorization, _)| authorization);
+            let request = build_request(&parts, &body, authorization);
+            authenticator.inner.call((request, context)).await
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::auth::digest::{digest_challenge, DigestResponse};
+    use crate::context::{ContextBuilder, Push};
+    use crate::EmptyContext;
+    use std::sync::atomic::{AtomicU32, Ordering};
+
+    type Context = ContextBuilder<Option<AuthData>, EmptyContext>;
+
+    /// Server accepting responses to its current nonce, which changes after a
+    /// fixed number of requests, using its host as the realm.
+    #[derive(Clone)]
+    struct DigestServer {
+        nonce_requests: u32,
+        requests: Arc<AtomicU32>,
+        /// Requests sent without credentials.
+        anonymous: Arc<AtomicU32>,
+        /// Requests with buffered bodies.
+        buffered: Arc<AtomicU32>,
+    }
+
+    impl DigestServer {
+        fn new(nonce_requests: u32) -> Self {
+            DigestServer {
+                nonce_requests,
+                requests: Arc::default(),
+                anonymous: Arc::default(),
+                buffered: Arc::default(),
+            }
+        }
+    }
+
+    impl Service<(Request<Either<Full<Bytes>, Full<Bytes>>>, Context)> for DigestServer {
+        type Response = Response<String>;
+        type Error = ApiError;
+        type Future = futures::future::Ready<Result<Self::Response, Self::Error>>;
+
+        fn call(&self, req: (Request<Either<Full<Bytes>, Full<Bytes>>>, Context)) -> Self::Future {
+            let (request, _) = req;
+            let count = self.requests.fetch_add(1, Ordering::SeqCst);
+            if let Either::Left(_) = request.body() {
+                self.buffered.fetch_add(1, Ordering::SeqCst);
+            }
+            let realm = request
+                .uri()
+                .host()
+                .unwrap_or_default()
+                .to_ascii_lowercase();
+            let nonce = format!("nonce-{}", count / self.nonce_requests);
+            let challenge = DigestChallenge::new(realm.clone(), nonce.clone());
+
+            let response = match DigestResponse::from_headers(request.headers()) {
+                Some(response) if response.realm != realm => digest_challenge(&challenge),
+                Some(response) if response.nonce != nonce => {
+                    digest_challenge(&challenge.with_stale(true))
+                }
+                Some(response) if response.verify(request.method(), "password") => {
+                    Response::new(response.username)
+                }
+                Some(_) => digest_challenge(&challenge),
+                None => {
+                    self.anonymous.fetch_add(1, Ordering::SeqCst);
+                    digest_challenge(&challenge)
+                }
+            };
+            futures::future::ok(response)
+        }
+    }
+
+    #[tokio::test]
+    async fn test_digest_authenticator() {
+        let server = DigestServer::new(3);
+        let client = DigestAuthenticator::new(server.clone());
+        let call = |password: &str| {
+            let context: Context = EmptyContext.push(Some(AuthData::digest("user", password)));
+            client.call((
+                Request::post("http://example.com/pets?limit=1")
+                    .body(Full::new(Bytes::from("{}")))
+                    .unwrap(),
+                context,
+            ))
+        };
+
+        // The first request is challenged and retried.
+        let response = call("password").await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.into_body(), "user");
+        assert_eq!(server.requests.load(Ordering::SeqCst), 2);
+
+        // The next reuses the challenge.
+        let response = call("password").await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(server.requests.load(Ordering::SeqCst), 3);
+
+        // Once the nonce is stale, the request is retried.
+        let response = call("password").await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(server.requests.load(Ordering::SeqCst), 5);
+
+        // Wrong credentials are not retried.
+        let response = call("wrong").await.unwrap();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        assert_eq!(server.requests.load(Ordering::SeqCst), 6);
+    }
+
+    #[tokio::test]
+    async fn test_protection_spaces() {
+        let server = DigestServer::new(u32::MAX);
+        let client = DigestAuthenticator::new(server.clone());
+        let call = |uri: &str| {
+            let context: Context = EmptyContext.push(Some(AuthData::digest("user", "password")));
+            client.call((
+                Request::get(uri).body(Full::new(Bytes::new())).unwrap(),
+                context,
+            ))
+        };
+
+        let response = call("http://example.com/pets").await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(server.requests.load(Ordering::SeqCst), 2);
+        assert_eq!(server.anonymous.load(Ordering::SeqCst), 1);
+
+        // Credentials for one server aren't sent to another.
+        let response = call("http://other.example.com/pets").await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(server.requests.load(Ordering::SeqCst), 4);
+        assert_eq!(server.anonymous.load(Ordering::SeqCst), 2);
+
+        // Each server's challenge is reused for later requests to it.
+        for uri in ["http://EXAMPLE.com/pets", "http://other.example.com/pets"] {
+            let response = call(uri).await.unwrap();
+            assert_eq!(response.status(), StatusCode::OK);
+        }
+        assert_eq!(server.requests.load(Ordering::SeqCst), 6);
+        assert_eq!(server.anonymous.load(Ordering::SeqCst), 2);
+        assert_eq!(server.buffered.load(Ordering::SeqCst), 6);
+    }
+
+    #[tokio::test]
+    async fn test_no_credentials() {
+        let server = DigestServer::new(3);
+        let client = DigestAuthenticator::new(server.clone());
+        let context: Context = EmptyContext.push(None::<AuthData>);
+
+        // Requests without Digest credentials are passed on without buffering
+        // or retrying.
+        let response = client
+            .call((
+                Request::get("http://example.com/pets")
+                    .body(Full::new(Bytes::new()))
+                    .unwrap(),
+                context,
+            ))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        assert_eq!(server.requests.load(Ordering::SeqCst), 1);
+        assert_eq!(server.buffered.load(Ordering::SeqCst), 0);
+    }
+}
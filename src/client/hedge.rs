@@ -0,0 +1,182 @@
+//! Middleware which sends a second request when the first is slow.
+use super::RetryBudget;
+use futures::future::{BoxFuture, Either};
+use hyper::rt::Timer;
+use hyper::service::Service;
+use hyper::Request;
+use hyper_util::rt::TokioTimer;
+use std::fmt;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Middleware which hedges slow requests, by sending the same request again
+/// if no response has been received after a delay, and returning whichever
+/// response arrives first.
+///
+/// Setting the delay to around the 95th percentile latency of the service
+/// cuts the tail latency seen by the client, at the cost of a few percent
+/// more requests. Only requests with idempotent methods are hedged. With a
+/// `RetryBudget`, hedged requests are limited to a fraction of all requests,
+/// so that a slow service isn't sent twice the load.
+///
+/// If the first response to arrive is an error, the other is waited for.
+///
+/// ```ignore
+/// let client = Hedge::new(DropContextService::new(http_client), Duration::from_millis(50))
+///     .with_budget(budget);
+/// ```
+pub struct Hedge<T> {
+    inner: Arc<T>,
+    delay: Duration,
+    budget: Option<RetryBudget>,
+    timer: Arc<dyn Timer + Send + Sync>,
+}
+
+impl<T> Hedge<T> {
+    /// Create a middleware which hedges requests without a response after
+    /// the given delay, using a Tokio timer.
+    pub fn new(inner: T, delay: Duration) -> Self {
+        Hedge {
+            inner: Arc::new(inner),
+            delay,
+            budget: None,
+            timer: Arc::new(TokioTimer::new()),
+        }
+    }
+
+    /// Limit hedged requests by the given budget, which should be shared with
+    /// the other middleware retrying requests to the same service.
+    pub fn with_budget(mut self, budget: RetryBudget) -> Self {
+        self.budget = Some(budget);
+        self
+    }
+
+    /// Use the given timer, rather than Tokio's.
+    pub fn with_timer<M: Timer + Send + Sync + 'static>(mut self, timer: M) -> Self {
+        self.timer = Arc::new(timer);
+        self
+    }
+}
+
+impl<T> Clone for Hedge<T> {
+    fn clone(&self) -> Self {
+        Hedge {
+            inner: self.inner.clone(),
+            delay: self.delay,
+            budget: self.budget.clone(),
+            timer: self.timer.clone(),
+        }
+    }
+}
+
+impl<T: fmt::Debug> fmt::Debug for Hedge<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Hedge")
+            .field("inner", &self.inner)
+            .field("delay", &self.delay)
+            .field("budget", &self.budget)
+            .finish()
+    }
+}
+
+impl<T, B, C> Service<(Request<B>, C)> for Hedge<T>
+where
+    T: Service<(Request<B>, C)> + Send + Sync + 'static,
+    T::Future: Send + 'static,
+    T::Response: Send,
+    T::Error: Send,
+    B: Clone + Send + 'static,
+    C: Clone + Send + 'static,
+{
+    type Response = T::Response;
+    type Error = T::Error;
+    type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn call(&self, req: (Request<B>, C)) -> Self::Future {
+        if !req.0.method().is_idempotent() {
+            return Box::pin(self.inner.call(req));
+        }
+        if let Some(budget) = &self.budget {
+            budget.deposit();
+        }
+
+        let hedge = (req.0.clone(), req.1.clone());
+        let first = Box::pin(self.inner.call(req));
+        let sleep = self.timer.sleep(self.delay);
+        let inner = self.inner.clone();
+        let budget = self.budget.clone();
+
+        Box::pin(async move {
+            let first = match futures::future::select(first, sleep).await {
+                Either::Left((result, _)) => return result,
+                Either::Right((_, first)) => first,
+            };
+            if let Some(budget) = budget {
+                if !budget.try_withdraw() {
+                    return first.await;
+                }
+            }
+
+            let second = Box::pin(inner.call(hedge));
+            match futures::future::select(first, second).await {
+                Either::Left((Ok(response), _)) | Either::Right((Ok(response), _)) => Ok(response),
+                Either::Left((Err(error), other)) | Either::Right((Err(error), other)) => {
+                    other.await.or(Err(error))
+                }
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::EmptyContext;
+    use hyper::Method;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    /// Service whose first response takes 200ms, and whose later
+    /// responses are immediate, responding with the number of the request.
+    #[derive(Default)]
+    struct SlowFirstService(AtomicUsize);
+
+    impl Service<(Request<()>, EmptyContext)> for SlowFirstService {
+        type Response = usize;
+        type Error = ();
+        type Future = BoxFuture<'static, Result<usize, ()>>;
+
+        fn call(&self, _req: (Request<()>, EmptyContext)) -> Self::Future {
+            let count = self.0.fetch_add(1, Ordering::SeqCst) + 1;
+            Box::pin(async move {
+                if count == 1 {
+                    tokio::time::sleep(Duration::from_millis(200)).await;
+                }
+                Ok(count)
+            })
+        }
+    }
+
+    async fn call(client: &Hedge<SlowFirstService>, method: Method) -> usize {
+        let request = Request::builder().method(method).body(()).unwrap();
+        client.call((request, EmptyContext)).await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_hedge() {
+        let client = Hedge::new(SlowFirstService::default(), Duration::from_millis(10));
+        assert_eq!(call(&client, Method::GET).await, 2);
+        assert_eq!(call(&client, Method::GET).await, 3);
+
+        let client = Hedge::new(SlowFirstService::default(), Duration::from_millis(10));
+        let start = std::time::Instant::now();
+        assert_eq!(call(&client, Method::POST).await, 1);
+        assert!(start.elapsed() >= Duration::from_millis(200));
+
+        let budget = RetryBudget::new(0.0)
+            .with_min_per_second(0)
+            .with_capacity(1);
+        let client =
+            Hedge::new(SlowFirstService::default(), Duration::from_millis(10)).with_budget(budget);
+        assert_eq!(call(&client, Method::GET).await, 1);
+    }
+}
@@ -0,0 +1,116 @@
+//! Middleware which adds idempotency keys to outgoing requests.
+use crate::context::Has;
+use crate::header::{IdempotencyKey, IDEMPOTENCY_KEY};
+use hyper::header::HeaderValue;
+use hyper::service::Service;
+use hyper::Request;
+
+/// Middleware which adds an `Idempotency-Key` header to requests with unsafe
+/// methods, such as `POST` and `PATCH`.
+///
+/// The key is the `Option<IdempotencyKey>` from the context of the request if
+/// there is one, and is otherwise generated. Requests which already have the
+/// header are left unchanged.
+///
+/// The `Retry` middleware retries requests with the header whatever their
+/// method, so this should be layered outside it, so that each attempt has
+/// the same key:
+///
+/// ```ignore
+/// let client = IdempotencyKeyInjector::new(Retry::new(
+///     DropContextService::new(http_client),
+///     RetryPolicy::new(),
+/// ));
+/// ```
+#[derive(Clone, Debug)]
+pub struct IdempotencyKeyInjector<T> {
+    inner: T,
+}
+
+impl<T> IdempotencyKeyInjector<T> {
+    /// Create a middleware which adds idempotency keys to requests.
+    pub fn new(inner: T) -> Self {
+        IdempotencyKeyInjector { inner }
+    }
+}
+
+impl<T, B, C> Service<(Request<B>, C)> for IdempotencyKeyInjector<T>
+where
+    T: Service<(Request<B>, C)>,
+    C: Has<Option<IdempotencyKey>>,
+{
+    type Response = T::Response;
+    type Error = T::Error;
+    type Future = T::Future;
+
+    fn call(&self, req: (Request<B>, C)) -> Self::Future {
+        let (mut request, context) = req;
+        if !request.method().is_safe() && !request.headers().contains_key(IDEMPOTENCY_KEY) {
+            let key = context.get().clone().unwrap_or_default();
+            if let Ok(value) = HeaderValue::from_str(&key.0) {
+                request.headers_mut().insert(IDEMPOTENCY_KEY, value);
+            }
+        }
+        self.inner.call((request, context))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client::{Retry, RetryPolicy};
+    use crate::context::Push;
+    use crate::ApiError;
+    use hyper::{Method, Response, StatusCode};
+    use std::sync::{Arc, Mutex};
+
+    crate::new_context_type!(TestContext, TestEmptyContext, Option<IdempotencyKey>);
+
+    type Context = TestContext<Option<IdempotencyKey>, TestEmptyContext>;
+
+    /// Server recording the idempotency keys of requests, and failing the
+    /// first.
+    #[derive(Clone, Default)]
+    struct FlakyServer(Arc<Mutex<Vec<Option<HeaderValue>>>>);
+
+    impl Service<(Request<()>, Context)> for FlakyServer {
+        type Response = Response<()>;
+        type Error = ApiError;
+        type Future = futures::future::Ready<Result<Self::Response, Self::Error>>;
+
+        fn call(&self, req: (Request<()>, Context)) -> Self::Future {
+            let mut keys = self.0.lock().unwrap();
+            keys.push(req.0.headers().get(IDEMPOTENCY_KEY).cloned());
+            let mut response = Response::new(());
+            if keys.len() == 1 {
+                *response.status_mut() = StatusCode::SERVICE_UNAVAILABLE;
+            }
+            futures::future::ok(response)
+        }
+    }
+
+    async fn keys(method: Method, key: Option<IdempotencyKey>) -> Vec<Option<HeaderValue>> {
+        let server = FlakyServer::default();
+        let client = IdempotencyKeyInjector::new(Retry::new(server.clone(), RetryPolicy::new()));
+        let request = Request::builder().method(method).body(()).unwrap();
+        let context = TestEmptyContext.push(key);
+        client.call((request, context)).await.unwrap();
+        let keys = server.0.lock().unwrap().clone();
+        keys
+    }
+
+    #[tokio::test]
+    async fn test_idempotency_key() {
+        // Generated keys are the same for each attempt.
+        let generated = keys(Method::POST, None).await;
+        assert_eq!(generated.len(), 2);
+        assert!(generated[0].is_some());
+        assert_eq!(generated[0], generated[1]);
+
+        let given = keys(Method::PATCH, Some(IdempotencyKey("abc".to_string()))).await;
+        let abc = Some(HeaderValue::from_static("abc"));
+        assert_eq!(given, [abc.clone(), abc]);
+
+        assert_eq!(keys(Method::GET, None).await, [None, None]);
+    }
+}
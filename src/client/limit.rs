@@ -0,0 +1,255 @@
+//! Middleware which limits the rate and concurrency of outgoing requests.
+use futures::future::BoxFuture;
+use hyper::rt::Timer;
+use hyper::service::Service;
+use hyper::Request;
+use hyper_util::rt::TokioTimer;
+use std::fmt;
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::{Duration, Instant};
+use tokio::sync::Semaphore;
+
+#[derive(Debug)]
+struct Bucket {
+    tokens: f64,
+    refilled: Instant,
+}
+
+/// Middleware which limits the rate of outgoing requests with a token bucket,
+/// delaying requests which would exceed it.
+///
+/// Up to `burst` requests can be sent at once, after which requests are
+/// sent at `rate` per second, in the order they were made. Clones share the
+/// same limit.
+///
+/// ```ignore
+/// // At most 10 requests per second, in bursts of up to 20.
+/// let client = RateLimit::new(DropContextService::new(http_client), 10.0, 20);
+/// ```
+pub struct RateLimit<T> {
+    inner: Arc<T>,
+    rate: f64,
+    burst: f64,
+    bucket: Arc<Mutex<Bucket>>,
+    timer: Arc<dyn Timer + Send + Sync>,
+}
+
+impl<T> RateLimit<T> {
+    /// Create a middleware which limits requests to the given number per
+    /// second, allowing bursts of up to the given size, using a Tokio timer
+    /// to delay requests.
+    pub fn new(inner: T, rate: f64, burst: u32) -> Self {
+        let burst = f64::from(burst.max(1));
+        RateLimit {
+            inner: Arc::new(inner),
+            rate: rate.max(f64::MIN_POSITIVE),
+            burst,
+            bucket: Arc::new(Mutex::new(Bucket {
+                tokens: burst,
+                refilled: Instant::now(),
+            })),
+            timer: Arc::new(TokioTimer::new()),
+        }
+    }
+
+    /// Use the given timer, rather than Tokio's.
+    pub fn with_timer<M: Timer + Send + Sync + 'static>(mut self, timer: M) -> Self {
+        self.timer = Arc::new(timer);
+        self
+    }
+
+    /// Take a token for a request made at the given time, returning how long
+    /// the request must wait for it.
+    ///
+    /// Tokens are taken even if they aren't available yet, leaving the
+    /// bucket in debt, so that later requests queue behind earlier ones.
+    fn reserve(&self, now: Instant) -> Duration {
+        let mut bucket = self.bucket.lock().unwrap_or_else(PoisonError::into_inner);
+        let elapsed = now.saturating_duration_since(bucket.refilled);
+        bucket.tokens = (bucket.tokens + elapsed.as_secs_f64() * self.rate).min(self.burst);
+        bucket.refilled = now;
+        bucket.tokens -= 1.0;
+        if bucket.tokens >= 0.0 {
+            Duration::ZERO
+        } else {
+            Duration::from_secs_f64(-bucket.tokens / self.rate)
+        }
+    }
+}
+
+impl<T> Clone for RateLimit<T> {
+    fn clone(&self) -> Self {
+        RateLimit {
+            inner: self.inner.clone(),
+            rate: self.rate,
+            burst: self.burst,
+            bucket: self.bucket.clone(),
+            timer: self.timer.clone(),
+        }
+    }
+}
+
+impl<T: fmt::Debug> fmt::Debug for RateLimit<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RateLimit")
+            .field("inner", &self.inner)
+            .field("rate", &self.rate)
+            .field("burst", &self.burst)
+            .finish()
+    }
+}
+
+impl<T, B, C> Service<(Request<B>, C)> for RateLimit<T>
+where
+    T: Service<(Request<B>, C)> + Send + Sync + 'static,
+    T::Future: Send,
+    B: Send + 'static,
+    C: Send + 'static,
+{
+    type Response = T::Response;
+    type Error = T::Error;
+    type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn call(&self, req: (Request<B>, C)) -> Self::Future {
+        let delay = self.reserve(Instant::now());
+        if delay.is_zero() {
+            return Box::pin(self.inner.call(req));
+        }
+
+        let inner = self.inner.clone();
+        let sleep = self.timer.sleep(delay);
+        Box::pin(async move {
+            sleep.await;
+            inner.call(req).await
+        })
+    }
+}
+
+/// Middleware which limits the number of outgoing requests in progress at
+/// once, delaying further requests until earlier ones complete.
+///
+/// A request is in progress until its response headers have been received,
+/// or it fails. Clones share the same limit.
+///
+/// ```ignore
+/// let client = ConcurrencyLimit::new(DropContextService::new(http_client), 8);
+/// ```
+pub struct ConcurrencyLimit<T> {
+    inner: Arc<T>,
+    semaphore: Arc<Semaphore>,
+}
+
+impl<T> ConcurrencyLimit<T> {
+    /// Create a middleware which allows up to the given number of requests
+    /// in progress at once.
+    pub fn new(inner: T, max: usize) -> Self {
+        ConcurrencyLimit {
+            inner: Arc::new(inner),
+            semaphore: Arc::new(Semaphore::new(max.max(1))),
+        }
+    }
+
+    /// Number of further requests which can be started without waiting.
+    pub fn available(&self) -> usize {
+        self.semaphore.available_permits()
+    }
+}
+
+impl<T> Clone for ConcurrencyLimit<T> {
+    fn clone(&self) -> Self {
+        ConcurrencyLimit {
+            inner: self.inner.clone(),
+            semaphore: self.semaphore.clone(),
+        }
+    }
+}
+
+impl<T: fmt::Debug> fmt::Debug for ConcurrencyLimit<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ConcurrencyLimit")
+            .field("inner", &self.inner)
+            .field("available", &self.available())
+            .finish()
+    }
+}
+
+impl<T, B, C> Service<(Request<B>, C)> for ConcurrencyLimit<T>
+where
+    T: Service<(Request<B>, C)> + Send + Sync + 'static,
+    T::Future: Send,
+    B: Send + 'static,
+    C: Send + 'static,
+{
+    type Response = T::Response;
+    type Error = T::Error;
+    type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn call(&self, req: (Request<B>, C)) -> Self::Future {
+        let inner = self.inner.clone();
+        let semaphore = self.semaphore.clone();
+        Box::pin(async move {
+            // The semaphore is never closed.
+            let _permit = semaphore.acquire_owned().await;
+            inner.call(req).await
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::EmptyContext;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    /// Service which takes 20ms to respond, recording the most requests it
+    /// has handled at once.
+    #[derive(Clone, Default)]
+    struct SlowService {
+        current: Arc<AtomicUsize>,
+        peak: Arc<AtomicUsize>,
+    }
+
+    impl Service<(Request<()>, EmptyContext)> for SlowService {
+        type Response = ();
+        type Error = ();
+        type Future = BoxFuture<'static, Result<(), ()>>;
+
+        fn call(&self, _req: (Request<()>, EmptyContext)) -> Self::Future {
+            let service = self.clone();
+            Box::pin(async move {
+                let current = service.current.fetch_add(1, Ordering::SeqCst) + 1;
+                service.peak.fetch_max(current, Ordering::SeqCst);
+                tokio::time::sleep(Duration::from_millis(20)).await;
+                service.current.fetch_sub(1, Ordering::SeqCst);
+                Ok(())
+            })
+        }
+    }
+
+    #[test]
+    fn test_rate_limit() {
+        let client = RateLimit::new((), 10.0, 2);
+        let start = Instant::now();
+        assert_eq!(client.reserve(start), Duration::ZERO);
+        assert_eq!(client.reserve(start), Duration::ZERO);
+        assert_eq!(client.reserve(start), Duration::from_millis(100));
+        assert_eq!(client.reserve(start), Duration::from_millis(200));
+
+        // The bucket refills over time, up to the burst size.
+        let later = start + Duration::from_secs(10);
+        assert_eq!(client.reserve(later), Duration::ZERO);
+        assert_eq!(client.reserve(later), Duration::ZERO);
+        assert!(client.reserve(later) > Duration::ZERO);
+    }
+
+    #[tokio::test]
+    async fn test_concurrency_limit() {
+        let service = SlowService::default();
+        let client = ConcurrencyLimit::new(service.clone(), 2);
+
+        let calls = (0..5).map(|_| client.call((Request::new(()), EmptyContext)));
+        futures::future::join_all(calls).await;
+        assert_eq!(service.peak.load(Ordering::SeqCst), 2);
+        assert_eq!(client.available(), 2);
+    }
+}
@@ -0,0 +1,367 @@
+//! Middleware which records metrics about requests to other services.
+use futures::future::BoxFuture;
+use hyper::service::Service;
+use hyper::{Method, Request, Response, StatusCode};
+use std::fmt;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// Outcome of a request sent through a `Metrics` middleware.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RequestMetrics {
+    /// Name of the service the request was sent to.
+    pub service: &'static str,
+    /// Method of the request.
+    pub method: Method,
+    /// Path template the request matched, such as `/pets/{id}`, or `None`
+    /// if it matched none.
+    pub path: Option<Arc<str>>,
+    /// Status of the response, or `None` if the request failed.
+    pub status: Option<StatusCode>,
+    /// Time from the request being sent to the response headers being
+    /// received.
+    pub latency: Duration,
+}
+
+impl RequestMetrics {
+    /// Whether the request failed, or received a `5xx` response.
+    pub fn is_error(&self) -> bool {
+        self.status.is_none_or(|status| status.is_server_error())
+    }
+}
+
+/// Destination for metrics from a `Metrics` middleware, such as
+/// `PrometheusSink`, or an adapter which sends statsd packets.
+///
+/// Sinks are called synchronously as each response is received, so must not
+/// block.
+///
+/// This is implemented for closures taking a `RequestMetrics`:
+///
+/// ```ignore
+/// let client = Metrics::new(inner, "petstore", |metrics: RequestMetrics| {
+///     eprintln!("{:?}", metrics)
+/// });
+/// ```
+pub trait RequestMetricsSink: Send + Sync {
+    /// Record the outcome of a request.
+    fn record(&self, metrics: RequestMetrics);
+}
+
+impl<F> RequestMetricsSink for F
+where
+    F: Fn(RequestMetrics) + Send + Sync,
+{
+    fn record(&self, metrics: RequestMetrics) {
+        self(metrics)
+    }
+}
+
+/// Middleware which records the method, path template, status and latency of
+/// each request to a `RequestMetricsSink`.
+///
+/// Paths are reported by the template they match, so that metrics aren't
+/// labelled with IDs. Templates are matched segment by segment against the
+/// whole path, including any base path, with segments in braces matching any
+/// single segment. Requests matching none of the templates are reported
+/// with no path.
+///
+/// ```ignore
+/// let client = Metrics::new(DropContextService::new(http_client), "petstore", sink)
+///     .with_path_templates(["/v2/pets", "/v2/pets/{id}"]);
+/// ```
+pub struct Metrics<T> {
+    inner: T,
+    service: &'static str,
+    templates: Arc<Vec<Arc<str>>>,
+    sink: Arc<dyn RequestMetricsSink>,
+}
+
+impl<T> Metrics<T> {
+    /// Create a middleware which records metrics for requests to the named
+    /// service.
+    pub fn new<S: RequestMetricsSink + 'static>(inner: T, service: &'static str, sink: S) -> Self {
+        Metrics {
+            inner,
+            service,
+            templates: Arc::new(Vec::new()),
+            sink: Arc::new(sink),
+        }
+    }
+
+    /// Report paths by the first of these templates they match.
+    pub fn with_path_templates<I, P>(mut self, templates: I) -> Self
+    where
+        I: IntoIterator<Item = P>,
+        P: Into<String>,
+    {
+        self.templates = Arc::new(
+            templates
+                .into_iter()
+                .map(|template| Arc::from(template.into()))
+                .collect(),
+        );
+        self
+    }
+
+    /// The first template matching the path.
+    fn template(&self, path: &str) -> Option<Arc<str>> {
+        self.templates
+            .iter()
+            .find(|template| {
+                let mut segments = path.split('/');
+                template.split('/').all(|expected| {
+                    segments.next().is_some_and(|segment| {
+                        segment == expected
+                            || (expected.starts_with('{') && expected.ends_with('}'))
+                    })
+                }) && segments.next().is_none()
+            })
+            .cloned()
+    }
+}
+
+impl<T: Clone> Clone for Metrics<T> {
+    fn clone(&self) -> Self {
+        Metrics {
+            inner: self.inner.clone(),
+            service: self.service,
+            templates: self.templates.clone(),
+            sink: self.sink.clone(),
+        }
+    }
+}
+
+impl<T: fmt::Debug> fmt::Debug for Metrics<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Metrics")
+            .field("inner", &self.inner)
+            .field("service", &self.service)
+            .field("templates", &self.templates)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<T, B, C, RB> Service<(Request<B>, C)> for Metrics<T>
+where
+    T: Service<(Request<B>, C), Response = Response<RB>>,
+    T::Future: Send + 'static,
+{
+    type Response = T::Response;
+    type Error = T::Error;
+    type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn call(&self, req: (Request<B>, C)) -> Self::Future {
+        let method = req.0.method().clone();
+        let path = self.template(req.0.uri().path());
+        let service = self.service;
+        let sink = self.sink.clone();
+        let start = Instant::now();
+        let response = self.inner.call(req);
+
+        Box::pin(async move {
+            let result = response.await;
+            sink.record(RequestMetrics {
+                service,
+                method,
+                path,
+                status: result.as_ref().ok().map(Response::status),
+                latency: start.elapsed(),
+            });
+            result
+        })
+    }
+}
+
+/// `RequestMetricsSink` which updates Prometheus metrics, labelled by
+/// service, method and path template:
+///
+/// - `http_client_requests_total`, with the status of the response, or
+///   `error` if the request failed
+/// - `http_client_request_errors_total`, counting failed requests and `5xx`
+///   responses
+/// - `http_client_request_duration_seconds`, a histogram of latencies
+///
+/// Requests which matched no path template are labelled with the path
+/// `other`. Clones update the same metrics, so one sink can be shared by
+/// the middleware for every service.
+#[cfg(feature = "prometheus")]
+#[derive(Clone, Debug)]
+pub struct PrometheusSink {
+    requests: prometheus::IntCounterVec,
+    errors: prometheus::IntCounterVec,
+    latency: prometheus::HistogramVec,
+}
+
+#[cfg(feature = "prometheus")]
+impl PrometheusSink {
+    /// Create the metrics, and register them with the registry.
+    pub fn new(registry: &prometheus::Registry) -> prometheus::Result<Self> {
+        use prometheus::{HistogramOpts, HistogramVec, IntCounterVec, Opts};
+
+        let labels = ["service", "method", "path"];
+        let requests = IntCounterVec::new(
+            Opts::new(
+                "http_client_requests_total",
+                "Requests sent to other services",
+            ),
+            &["service", "method", "path", "status"],
+        )?;
+        let errors = IntCounterVec::new(
+            Opts::new(
+                "http_client_request_errors_total",
+                "Requests to other services which failed or received a 5xx response",
+            ),
+            &labels,
+        )?;
+        let latency = HistogramVec::new(
+            HistogramOpts::new(
+                "http_client_request_duration_seconds",
+                "Time taken for other services to respond to requests",
+            ),
+            &labels,
+        )?;
+        registry.register(Box::new(requests.clone()))?;
+        registry.register(Box::new(errors.clone()))?;
+        registry.register(Box::new(latency.clone()))?;
+        Ok(PrometheusSink {
+            requests,
+            errors,
+            latency,
+        })
+    }
+}
+
+#[cfg(feature = "prometheus")]
+impl RequestMetricsSink for PrometheusSink {
+    fn record(&self, metrics: RequestMetrics) {
+        let path = metrics.path.as_deref().unwrap_or("other");
+        let labels = [metrics.service, metrics.method.as_str(), path];
+        let status = metrics.status.as_ref().map_or("error", StatusCode::as_str);
+        self.requests
+            .with_label_values(&[labels[0], labels[1], labels[2], status])
+            .inc();
+        if metrics.is_error() {
+            self.errors.with_label_values(&labels).inc();
+        }
+        self.latency
+            .with_label_values(&labels)
+            .observe(metrics.latency.as_secs_f64());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ApiError, EmptyContext};
+    use std::sync::Mutex;
+
+    /// Server responding with the status in the first segment of the path,
+    /// or failing if it isn't a status.
+    struct StatusServer;
+
+    impl Service<(Request<()>, EmptyContext)> for StatusServer {
+        type Response = Response<()>;
+        type Error = ApiError;
+        type Future = futures::future::Ready<Result<Self::Response, Self::Error>>;
+
+        fn call(&self, req: (Request<()>, EmptyContext)) -> Self::Future {
+            let status = req.0.uri().path()[1..].split('/').next().unwrap();
+            futures::future::ready(match status.parse::<u16>() {
+                Ok(status) => Ok(Response::builder().status(status).body(()).unwrap()),
+                Err(_) => Err(ApiError("Connection refused".to_string())),
+            })
+        }
+    }
+
+    async fn record(templates: &[&str], paths: &[&str]) -> Vec<RequestMetrics> {
+        let recorded = Arc::new(Mutex::new(Vec::new()));
+        let sink = {
+            let recorded = recorded.clone();
+            move |metrics| recorded.lock().unwrap().push(metrics)
+        };
+        let client =
+            Metrics::new(StatusServer, "test", sink).with_path_templates(templates.to_vec());
+        for path in paths {
+            let request = Request::get(*path).body(()).unwrap();
+            let _ = client.call((request, EmptyContext)).await;
+        }
+        let recorded = recorded.lock().unwrap().clone();
+        recorded
+    }
+
+    #[tokio::test]
+    async fn test_metrics() {
+        let recorded = record(
+            &["/{status}/pets", "/{status}/pets/{id}"],
+            &[
+                "/200/pets/1?a=b",
+                "/503/pets",
+                "/fail/pets",
+                "/200/pets/1/x",
+            ],
+        )
+        .await;
+        let summary: Vec<_> = recorded
+            .iter()
+            .map(|m| (m.service, m.path.as_deref(), m.status, m.is_error()))
+            .collect();
+        assert_eq!(
+            summary,
+            [
+                (
+                    "test",
+                    Some("/{status}/pets/{id}"),
+                    Some(StatusCode::OK),
+                    false
+                ),
+                (
+                    "test",
+                    Some("/{status}/pets"),
+                    Some(StatusCode::SERVICE_UNAVAILABLE),
+                    true
+                ),
+                ("test", Some("/{status}/pets"), None, true),
+                ("test", None, Some(StatusCode::OK), false),
+            ]
+        );
+        assert!(recorded.iter().all(|m| m.method == Method::GET));
+    }
+
+    #[cfg(feature = "prometheus")]
+    #[test]
+    fn test_prometheus_sink() {
+        let registry = prometheus::Registry::new();
+        let sink = PrometheusSink::new(&registry).unwrap();
+        for status in [Some(StatusCode::OK), Some(StatusCode::BAD_GATEWAY), None] {
+            sink.record(RequestMetrics {
+                service: "petstore",
+                method: Method::GET,
+                path: Some(Arc::from("/pets")),
+                status,
+                latency: Duration::from_millis(5),
+            });
+        }
+
+        let families = registry.gather();
+        let value = |name: &str, status: Option<&str>| {
+            let family = families.iter().find(|f| f.get_name() == name).unwrap();
+            family
+                .get_metric()
+                .iter()
+                .find(|m| {
+                    status.is_none_or(|status| {
+                        m.get_label()
+                            .iter()
+                            .any(|l| l.get_name() == "status" && l.get_value() == status)
+                    })
+                })
+                .map(|m| m.get_counter().get_value())
+                .unwrap()
+        };
+        assert_eq!(value("http_client_requests_total", Some("200")), 1.0);
+        assert_eq!(value("http_client_requests_total", Some("error")), 1.0);
+        assert_eq!(value("http_client_request_errors_total", None), 2.0);
+        assert!(PrometheusSink::new(&registry).is_err());
+    }
+}
@@ -0,0 +1,337 @@
+//! Mock client service, for testing code which uses generated clients.
+use crate::ApiError;
+use futures::future::BoxFuture;
+use http_body_util::{BodyExt, Full};
+use hyper::body::{Body, Bytes};
+use hyper::header::{HeaderMap, HeaderName, HeaderValue};
+use hyper::service::Service;
+use hyper::{Method, Request, Response, StatusCode, Uri};
+use std::fmt;
+use std::sync::{Arc, Mutex, PoisonError};
+
+/// Request expected by a `MockService`, and the response to it.
+///
+/// An expectation matches requests with its method and path, and with the
+/// headers and body it is given, if any. Paths including a query string must
+/// match the query string too. By default, an expectation must be matched at
+/// least once, and responds with `200 OK` and an empty body.
+///
+/// ```
+/// # use swagger::client::Expectation;
+/// # use hyper::{Method, StatusCode};
+/// let expectation = Expectation::new(Method::POST, "/pets")
+///     .with_header("content-type", "application/json")
+///     .with_body(r#"{"name":"Rex"}"#)
+///     .respond_with(StatusCode::CREATED, r#"{"id":1}"#)
+///     .times(1);
+/// ```
+#[derive(Clone, Debug)]
+pub struct Expectation {
+    method: Method,
+    path: String,
+    headers: Vec<(HeaderName, HeaderValue)>,
+    body: Option<Bytes>,
+    status: StatusCode,
+    response_headers: HeaderMap,
+    response_body: Bytes,
+    times: Option<usize>,
+    matched: usize,
+}
+
+impl Expectation {
+    /// Expect a request with the method and path.
+    pub fn new<P: Into<String>>(method: Method, path: P) -> Self {
+        Expectation {
+            method,
+            path: path.into(),
+            headers: Vec::new(),
+            body: None,
+            status: StatusCode::OK,
+            response_headers: HeaderMap::new(),
+            response_body: Bytes::new(),
+            times: None,
+            matched: 0,
+        }
+    }
+
+    /// Only match requests with the header set to the value.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the name or value isn't a valid header.
+    pub fn with_header(mut self, name: &str, value: &str) -> Self {
+        self.headers.push((
+            HeaderName::try_from(name).expect("invalid header name"),
+            HeaderValue::try_from(value).expect("invalid header value"),
+        ));
+        self
+    }
+
+    /// Only match requests with the body.
+    pub fn with_body<B: Into<Bytes>>(mut self, body: B) -> Self {
+        self.body = Some(body.into());
+        self
+    }
+
+    /// Respond to matching requests with the status and body.
+    pub fn respond_with<B: Into<Bytes>>(mut self, status: StatusCode, body: B) -> Self {
+        self.status = status;
+        self.response_body = body.into();
+        self
+    }
+
+    /// Add a header to the response to matching requests.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the name or value isn't a valid header.
+    pub fn with_response_header(mut self, name: &str, value: &str) -> Self {
+        self.response_headers.append(
+            HeaderName::try_from(name).expect("invalid header name"),
+            HeaderValue::try_from(value).expect("invalid header value"),
+        );
+        self
+    }
+
+    /// Expect exactly the given number of matching requests. Further requests
+    /// aren't matched.
+    pub fn times(mut self, times: usize) -> Self {
+        self.times = Some(times);
+        self
+    }
+
+    fn matches(&self, request: &RecordedRequest) -> bool {
+        let path = if self.path.contains('?') {
+            request.uri.path_and_query().map_or("/", |p| p.as_str())
+        } else {
+            request.uri.path()
+        };
+        self.times.is_none_or(|times| self.matched < times)
+            && request.method == self.method
+            && path == self.path
+            && self
+                .headers
+                .iter()
+                .all(|(name, value)| request.headers.get_all(name).iter().any(|v| v == value))
+            && self.body.as_ref().is_none_or(|body| *body == request.body)
+    }
+
+    fn satisfied(&self) -> bool {
+        match self.times {
+            Some(times) => self.matched == times,
+            None => self.matched > 0,
+        }
+    }
+
+    fn response(&self) -> Response<Full<Bytes>> {
+        let mut response = Response::new(Full::new(self.response_body.clone()));
+        *response.status_mut() = self.status;
+        *response.headers_mut() = self.response_headers.clone();
+        response
+    }
+}
+
+impl fmt::Display for Expectation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {}", self.method, self.path)?;
+        match self.times {
+            Some(times) => write!(f, " (matched {} of {} times)", self.matched, times),
+            None => write!(f, " (never matched)"),
+        }
+    }
+}
+
+/// Request received by a `MockService`.
+#[derive(Clone, Debug)]
+pub struct RecordedRequest {
+    /// Method of the request.
+    pub method: Method,
+    /// URI of the request.
+    pub uri: Uri,
+    /// Headers of the request.
+    pub headers: HeaderMap,
+    /// Body of the request.
+    pub body: Bytes,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    expectations: Vec<Expectation>,
+    requests: Vec<RecordedRequest>,
+}
+
+/// Client service which responds to requests as programmed by the test, and
+/// records them for later assertions.
+///
+/// It can replace the `DropContextService` wrapping an HTTP client at the
+/// bottom of a client stack, so that code using a generated client can be
+/// tested without a server. Each request is matched against the
+/// expectations in the order they were added, and answered with the response
+/// of the first match. Requests which match no expectation fail with an
+/// `ApiError`. Clones share the same expectations and recorded requests.
+///
+/// ```ignore
+/// let mock = MockService::new();
+/// mock.expect(Expectation::new(Method::GET, "/pets/1").respond_with(StatusCode::OK, PET));
+///
+/// let client = Client::new_with_service(mock.clone());
+/// assert_eq!(client.get_pet(1, &context).await?, expected);
+/// mock.verify()?;
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct MockService {
+    state: Arc<Mutex<State>>,
+}
+
+impl MockService {
+    /// Create a service with no expectations.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Add an expectation.
+    pub fn expect(&self, expectation: Expectation) {
+        self.lock().expectations.push(expectation);
+    }
+
+    /// Requests received so far, in the order they were received.
+    pub fn requests(&self) -> Vec<RecordedRequest> {
+        self.lock().requests.clone()
+    }
+
+    /// Check that every expectation has been matched the expected number of
+    /// times, returning an error listing those which haven't.
+    pub fn verify(&self) -> Result<(), ApiError> {
+        let unsatisfied: Vec<_> = self
+            .lock()
+            .expectations
+            .iter()
+            .filter(|expectation| !expectation.satisfied())
+            .map(Expectation::to_string)
+            .collect();
+        if unsatisfied.is_empty() {
+            Ok(())
+        } else {
+            Err(ApiError(format!(
+                "Unsatisfied expectations: {}",
+                unsatisfied.join(", ")
+            )))
+        }
+    }
+
+    /// Record a request, and find the response to it.
+    fn respond(&self, request: RecordedRequest) -> Result<Response<Full<Bytes>>, ApiError> {
+        let mut state = self.lock();
+        let response = match state.expectations.iter_mut().find(|e| e.matches(&request)) {
+            Some(expectation) => {
+                expectation.matched += 1;
+                Ok(expectation.response())
+            }
+            None => Err(ApiError(format!(
+                "Unexpected request: {} {}",
+                request.method, request.uri
+            ))),
+        };
+        state.requests.push(request);
+        response
+    }
+}
+
+impl<B, C> Service<(Request<B>, C)> for MockService
+where
+    B: Body + Send + 'static,
+    B::Data: Send,
+    B::Error: fmt::Display,
+{
+    type Response = Response<Full<Bytes>>;
+    type Error = ApiError;
+    type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn call(&self, req: (Request<B>, C)) -> Self::Future {
+        let service = self.clone();
+        let (parts, body) = req.0.into_parts();
+        Box::pin(async move {
+            let body = body
+                .collect()
+                .await
+                .map_err(|e| ApiError(format!("Failed to read request body: {}", e)))?
+                .to_bytes();
+            service.respond(RecordedRequest {
+                method: parts.method,
+                uri: parts.uri,
+                headers: parts.headers,
+                body,
+            })
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::EmptyContext;
+
+    async fn call(
+        mock: &MockService,
+        request: Request<Full<Bytes>>,
+    ) -> Result<(StatusCode, Bytes), ApiError> {
+        let response = mock.call((request, EmptyContext)).await?;
+        let status = response.status();
+        Ok((
+            status,
+            response.into_body().collect().await.unwrap().to_bytes(),
+        ))
+    }
+
+    #[tokio::test]
+    async fn test_mock_service() {
+        let mock = MockService::new();
+        mock.expect(
+            Expectation::new(Method::POST, "/pets")
+                .with_header("content-type", "application/json")
+                .with_body("{}")
+                .respond_with(StatusCode::CREATED, "1")
+                .with_response_header("location", "/pets/1")
+                .times(1),
+        );
+        mock.expect(Expectation::new(Method::GET, "/pets/1").respond_with(StatusCode::OK, "{}"));
+        assert_eq!(
+            mock.verify().unwrap_err().0,
+            "Unsatisfied expectations: POST /pets (matched 0 of 1 times), GET /pets/1 (never matched)"
+        );
+
+        let post = || {
+            Request::post("http://petstore/pets")
+                .header("content-type", "application/json")
+                .body(Full::new(Bytes::from("{}")))
+                .unwrap()
+        };
+        assert_eq!(
+            call(&mock, post()).await.unwrap(),
+            (StatusCode::CREATED, Bytes::from("1"))
+        );
+        assert_eq!(
+            call(&mock, post()).await.unwrap_err().0,
+            "Unexpected request: POST http://petstore/pets"
+        );
+
+        let get = Request::get("http://petstore/pets/1?fields=name")
+            .body(Full::default())
+            .unwrap();
+        assert_eq!(
+            call(&mock, get).await.unwrap(),
+            (StatusCode::OK, Bytes::from("{}"))
+        );
+        assert!(mock.verify().is_ok());
+
+        let requests = mock.requests();
+        assert_eq!(requests.len(), 3);
+        assert_eq!(requests[0].headers["content-type"], "application/json");
+        assert_eq!(requests[0].body, "{}");
+        assert_eq!(requests[2].uri.query(), Some("fields=name"));
+    }
+}
@@ -0,0 +1,77 @@
+//! Middleware for use by generated API clients.
+//!
+//! Client middleware takes a `(hyper::Request, Context)` tuple, so it can be
+//! layered between a generated client and a `DropContextService` wrapping the
+//! underlying HTTP client.
+
+mod auth;
+pub use auth::AuthInjector;
+
+mod balance;
+pub use balance::BalancedService;
+
+mod base_url;
+pub use base_url::BaseUrl;
+
+pub mod cache;
+pub use cache::Cache;
+
+mod deadline;
+pub use deadline::DeadlinePropagator;
+
+#[cfg(any(feature = "gzip", feature = "brotli"))]
+mod decompression;
+#[cfg(any(feature = "gzip", feature = "brotli"))]
+pub use decompression::Decompression;
+
+mod deprecation;
+pub use deprecation::DeprecationDetector;
+
+mod hedge;
+pub use hedge::Hedge;
+
+mod idempotency;
+pub use idempotency::IdempotencyKeyInjector;
+
+mod limit;
+pub use limit::{ConcurrencyLimit, RateLimit};
+
+mod metrics;
+#[cfg(feature = "prometheus")]
+pub use metrics::PrometheusSink;
+pub use metrics::{Metrics, RequestMetrics, RequestMetricsSink};
+
+#[cfg(feature = "mock")]
+mod mock;
+#[cfg(feature = "mock")]
+pub use mock::{Expectation, MockService, RecordedRequest};
+
+mod redirect;
+pub use redirect::{FollowRedirects, RedirectPolicy};
+
+mod retry;
+pub use retry::{Retry, RetryPolicy};
+
+mod retry_budget;
+pub use retry_budget::RetryBudget;
+
+mod timeout;
+pub use timeout::{RequestTimeout, Timeout, TimeoutError};
+
+mod trace;
+pub use trace::TracePropagator;
+
+#[cfg(feature = "digest")]
+mod digest;
+#[cfg(feature = "digest")]
+pub use digest::DigestAuthenticator;
+
+#[cfg(feature = "serdejson")]
+pub mod token;
+#[cfg(feature = "serdejson")]
+pub use token::{TokenInjector, TokenManager};
+
+#[cfg(all(feature = "mock", feature = "serdejson"))]
+pub mod vcr;
+#[cfg(all(feature = "mock", feature = "serdejson"))]
+pub use vcr::{Vcr, VcrMode};
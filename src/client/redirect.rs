@@ -0,0 +1,368 @@
+//! Middleware which follows redirects from the services it calls.
+use crate::ApiError;
+use futures::future::BoxFuture;
+use hyper::header::{
+    HeaderMap, AUTHORIZATION, CONTENT_ENCODING, CONTENT_LENGTH, CONTENT_TYPE, COOKIE, HOST,
+    LOCATION, TRANSFER_ENCODING,
+};
+use hyper::http::uri::{Parts, PathAndQuery, Scheme};
+use hyper::service::Service;
+use hyper::{Method, Request, Response, StatusCode, Uri};
+use std::fmt;
+use std::sync::Arc;
+
+/// Which redirects `FollowRedirects` follows, and how.
+///
+/// The default follows up to 10 redirects, doesn't follow redirects from
+/// HTTPS to HTTP, re-sends the body on `307` and `308` redirects, and strips
+/// credentials from requests redirected to a different origin.
+#[derive(Clone, Debug)]
+pub struct RedirectPolicy {
+    max_redirects: usize,
+    allow_downgrade: bool,
+    resend_body: bool,
+    strip_credentials: bool,
+}
+
+impl Default for RedirectPolicy {
+    fn default() -> Self {
+        RedirectPolicy {
+            max_redirects: 10,
+            allow_downgrade: false,
+            resend_body: true,
+            strip_credentials: true,
+        }
+    }
+}
+
+impl RedirectPolicy {
+    /// Create the default policy.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set the maximum number of redirects followed for a request, beyond
+    /// which it fails.
+    pub fn with_max_redirects(mut self, max_redirects: usize) -> Self {
+        self.max_redirects = max_redirects;
+        self
+    }
+
+    /// Set whether to follow redirects from HTTPS to HTTP.
+    pub fn with_allow_downgrade(mut self, allow_downgrade: bool) -> Self {
+        self.allow_downgrade = allow_downgrade;
+        self
+    }
+
+    /// Set whether to follow `307 Temporary Redirect` and `308 Permanent
+    /// Redirect` responses, which require the request to be sent again with
+    /// the same method and body.
+    pub fn with_resend_body(mut self, resend_body: bool) -> Self {
+        self.resend_body = resend_body;
+        self
+    }
+
+    /// Set whether to remove the `Authorization` and `Cookie` headers from
+    /// requests redirected to a different scheme, host or port.
+    pub fn with_strip_credentials(mut self, strip_credentials: bool) -> Self {
+        self.strip_credentials = strip_credentials;
+        self
+    }
+}
+
+/// Resolve a `Location` header value against the URI of the request it was
+/// a response to.
+fn resolve(base: &Uri, location: &str) -> Option<Uri> {
+    let location = location.trim();
+    if let Ok(uri) = location.parse::<Uri>() {
+        if uri.scheme().is_some() {
+            return Some(uri);
+        }
+    }
+
+    let scheme = base.scheme().cloned().unwrap_or(Scheme::HTTP);
+    if let Some(rest) = location.strip_prefix("//") {
+        return format!("{}://{}", scheme, rest).parse().ok();
+    }
+
+    let path_and_query = if location.starts_with('/') {
+        location.to_string()
+    } else {
+        let path = base.path();
+        let directory = &path[..path.rfind('/').map_or(0, |i| i + 1)];
+        if location.starts_with('?') {
+            format!("{}{}", path, location)
+        } else {
+            format!("/{}{}", directory.trim_start_matches('/'), location)
+        }
+    };
+
+    let mut parts = Parts::default();
+    parts.scheme = Some(scheme);
+    parts.authority = base.authority().cloned();
+    parts.path_and_query = Some(path_and_query.parse::<PathAndQuery>().ok()?);
+    Uri::from_parts(parts).ok()
+}
+
+/// Scheme, host and port of a URI.
+fn origin(uri: &Uri) -> (Option<&str>, Option<&str>, Option<u16>) {
+    let scheme = uri.scheme_str();
+    let port = uri.port_u16().or(match scheme {
+        Some("http") => Some(80),
+        Some("https") => Some(443),
+        _ => None,
+    });
+    (scheme, uri.host(), port)
+}
+
+/// Middleware which follows redirects, according to a `RedirectPolicy`.
+///
+/// `301 Moved Permanently`, `302 Found` and `303 See Other` redirects of
+/// requests other than `GET` and `HEAD` are followed with a `GET` request
+/// without a body, as browsers do. `307 Temporary Redirect` and `308
+/// Permanent Redirect` redirects are followed with the same method and body,
+/// which is cloned for each request. Redirects which aren't followed, such
+/// as those without a `Location` header, are returned to the client, and
+/// requests redirected more times than allowed fail with an `ApiError`.
+///
+/// ```ignore
+/// let client = FollowRedirects::new(
+///     DropContextService::new(http_client),
+///     RedirectPolicy::new().with_max_redirects(5),
+/// );
+/// ```
+pub struct FollowRedirects<T> {
+    inner: Arc<T>,
+    policy: Arc<RedirectPolicy>,
+}
+
+impl<T> FollowRedirects<T> {
+    /// Create a middleware which follows redirects according to the policy.
+    pub fn new(inner: T, policy: RedirectPolicy) -> Self {
+        FollowRedirects {
+            inner: Arc::new(inner),
+            policy: Arc::new(policy),
+        }
+    }
+}
+
+impl<T> Clone for FollowRedirects<T> {
+    fn clone(&self) -> Self {
+        FollowRedirects {
+            inner: self.inner.clone(),
+            policy: self.policy.clone(),
+        }
+    }
+}
+
+impl<T: fmt::Debug> fmt::Debug for FollowRedirects<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("FollowRedirects")
+            .field("inner", &self.inner)
+            .field("policy", &self.policy)
+            .finish()
+    }
+}
+
+impl<T, B, C, RB> Service<(Request<B>, C)> for FollowRedirects<T>
+where
+    T: Service<(Request<B>, C), Response = Response<RB>> + Send + Sync + 'static,
+    T::Future: Send,
+    T::Error: From<ApiError> + Send,
+    RB: Send,
+    B: Clone + Default + Send + 'static,
+    C: Clone + Send + 'static,
+{
+    type Response = T::Response;
+    type Error = T::Error;
+    type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn call(&self, req: (Request<B>, C)) -> Self::Future {
+        let inner = self.inner.clone();
+        let policy = self.policy.clone();
+
+        Box::pin(async move {
+            let (mut request, context) = req;
+            let mut redirects = 0;
+            loop {
+                let response = inner.call((request.clone(), context.clone())).await?;
+
+                let status = response.status();
+                let resend = match status {
+                    StatusCode::MOVED_PERMANENTLY | StatusCode::FOUND | StatusCode::SEE_OTHER => {
+                        false
+                    }
+                    StatusCode::TEMPORARY_REDIRECT | StatusCode::PERMANENT_REDIRECT
+                        if policy.resend_body =>
+                    {
+                        true
+                    }
+                    _ => return Ok(response),
+                };
+                let Some(location) = response
+                    .headers()
+                    .get(LOCATION)
+                    .and_then(|value| value.to_str().ok())
+                    .and_then(|value| resolve(request.uri(), value))
+                else {
+                    return Ok(response);
+                };
+                if !policy.allow_downgrade
+                    && request.uri().scheme() == Some(&Scheme::HTTPS)
+                    && location.scheme() != Some(&Scheme::HTTPS)
+                {
+                    return Ok(response);
+                }
+
+                redirects += 1;
+                if redirects > policy.max_redirects {
+                    return Err(ApiError(format!(
+                        "Too many redirects, after {}",
+                        policy.max_redirects
+                    ))
+                    .into());
+                }
+
+                if !resend && !matches!(*request.method(), Method::GET | Method::HEAD) {
+                    *request.method_mut() = Method::GET;
+                    *request.body_mut() = B::default();
+                    remove_body_headers(request.headers_mut());
+                }
+                if origin(request.uri()) != origin(&location) {
+                    let headers = request.headers_mut();
+                    headers.remove(HOST);
+                    if policy.strip_credentials {
+                        headers.remove(AUTHORIZATION);
+                        headers.remove(COOKIE);
+                    }
+                }
+                *request.uri_mut() = location;
+            }
+        })
+    }
+}
+
+fn remove_body_headers(headers: &mut HeaderMap) {
+    for name in [
+        CONTENT_ENCODING,
+        CONTENT_LENGTH,
+        CONTENT_TYPE,
+        TRANSFER_ENCODING,
+    ] {
+        headers.remove(name);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::EmptyContext;
+
+    /// Server redirecting requests for `/<status>/<path>` to `/<path>`, or to
+    /// `<path>` if it is an absolute URI, with its `//` collapsed to `/`, and
+    /// responding to other requests with their method, URI and headers.
+    struct RedirectingServer;
+
+    impl Service<(Request<String>, EmptyContext)> for RedirectingServer {
+        type Response = Response<String>;
+        type Error = ApiError;
+        type Future = futures::future::Ready<Result<Self::Response, Self::Error>>;
+
+        fn call(&self, req: (Request<String>, EmptyContext)) -> Self::Future {
+            let request = req.0;
+            let path = &request.uri().path()[1..];
+            let response = match path.split_once('/') {
+                Some((status, location)) if status.len() == 3 => Response::builder()
+                    .status(status.parse::<u16>().unwrap())
+                    .header(
+                        LOCATION,
+                        if location.starts_with("http") {
+                            location.replacen(":/", "://", 1)
+                        } else {
+                            format!("/{}", location)
+                        },
+                    )
+                    .body(String::new()),
+                _ => Response::builder().body(format!(
+                    "{} {} {:?} {}",
+                    request.method(),
+                    request.uri(),
+                    request.headers().keys().collect::<Vec<_>>(),
+                    request.body()
+                )),
+            };
+            futures::future::ok(response.unwrap())
+        }
+    }
+
+    async fn call(
+        client: &FollowRedirects<RedirectingServer>,
+        method: Method,
+        uri: &str,
+    ) -> Result<Response<String>, ApiError> {
+        let request = Request::builder()
+            .method(method)
+            .uri(uri)
+            .header(AUTHORIZATION, "Bearer secret")
+            .header(CONTENT_TYPE, "text/plain")
+            .body("pet".to_string())
+            .unwrap();
+        client.call((request, EmptyContext)).await
+    }
+
+    #[test]
+    fn test_resolve() {
+        let base: Uri = "http://example.com/pets/1?a=b".parse().unwrap();
+        let resolve = |location| resolve(&base, location).unwrap().to_string();
+        assert_eq!(resolve("https://other.com/x"), "https://other.com/x");
+        assert_eq!(resolve("//other.com/x"), "http://other.com/x");
+        assert_eq!(resolve("/x?y"), "http://example.com/x?y");
+        assert_eq!(resolve("2"), "http://example.com/pets/2");
+        assert_eq!(resolve("?c=d"), "http://example.com/pets/1?c=d");
+    }
+
+    #[tokio::test]
+    async fn test_follow_redirects() {
+        let client = FollowRedirects::new(RedirectingServer, RedirectPolicy::new());
+
+        // Requests redirected with 303 are changed to GET.
+        let response = call(&client, Method::POST, "http://example.com/303/307/pets")
+            .await
+            .unwrap();
+        assert_eq!(
+            response.body(),
+            r#"GET http://example.com/pets ["authorization"] "#
+        );
+
+        // Requests redirected with 307 are sent again, without credentials if
+        // to a different origin.
+        let response = call(
+            &client,
+            Method::PUT,
+            "http://example.com/307/https:/other.com/pets",
+        )
+        .await
+        .unwrap();
+        assert_eq!(
+            response.body(),
+            r#"PUT https://other.com/pets ["content-type"] pet"#
+        );
+
+        // Downgrades aren't followed.
+        let response = call(
+            &client,
+            Method::GET,
+            "https://example.com/302/http:/example.com/",
+        )
+        .await
+        .unwrap();
+        assert_eq!(response.status(), StatusCode::FOUND);
+
+        let client = FollowRedirects::new(
+            RedirectingServer,
+            RedirectPolicy::new().with_max_redirects(1),
+        );
+        let result = call(&client, Method::GET, "http://example.com/301/301/pets").await;
+        assert_eq!(result.unwrap_err().0, "Too many redirects, after 1");
+    }
+}
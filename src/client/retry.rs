@@ -0,0 +1,423 @@
+//! Middleware which retries failed outgoing requests.
+use super::RetryBudget;
+use crate::header::IDEMPOTENCY_KEY;
+use futures::future::BoxFuture;
+use hyper::header::{HeaderMap, RETRY_AFTER};
+use hyper::rt::Timer;
+use hyper::service::Service;
+use hyper::{Request, Response, StatusCode};
+use hyper_util::rt::TokioTimer;
+use std::collections::hash_map::RandomState;
+use std::fmt;
+use std::hash::{BuildHasher, Hasher};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+
+/// When, and how often, `Retry` retries requests.
+///
+/// The default makes up to 3 attempts, retrying errors and `429 Too Many
+/// Requests`, `502 Bad Gateway`, `503 Service Unavailable` and `504 Gateway
+/// Timeout` responses, after an exponential backoff starting at 100ms and
+/// capped at 10s. Only requests with idempotent methods, or with an
+/// `Idempotency-Key` header, are retried.
+#[derive(Clone, Debug)]
+pub struct RetryPolicy {
+    max_attempts: u32,
+    initial_backoff: Duration,
+    max_backoff: Duration,
+    multiplier: f64,
+    jitter: f64,
+    retry_errors: bool,
+    statuses: Vec<StatusCode>,
+    max_retry_after: Option<Duration>,
+    retry_non_idempotent: bool,
+    budget: Option<RetryBudget>,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            max_attempts: 3,
+            initial_backoff: Duration::from_millis(100),
+            max_backoff: Duration::from_secs(10),
+            multiplier: 2.0,
+            jitter: 0.5,
+            retry_errors: true,
+            statuses: vec![
+                StatusCode::TOO_MANY_REQUESTS,
+                StatusCode::BAD_GATEWAY,
+                StatusCode::SERVICE_UNAVAILABLE,
+                StatusCode::GATEWAY_TIMEOUT,
+            ],
+            max_retry_after: Some(Duration::from_secs(30)),
+            retry_non_idempotent: false,
+            budget: None,
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Create the default policy.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set the maximum number of attempts, including the first.
+    pub fn with_max_attempts(mut self, max_attempts: u32) -> Self {
+        self.max_attempts = max_attempts.max(1);
+        self
+    }
+
+    /// Set the backoff before the first retry, and the factor it is
+    /// multiplied by for each retry after that, up to the given maximum.
+    pub fn with_backoff(mut self, initial: Duration, multiplier: f64, max: Duration) -> Self {
+        self.initial_backoff = initial;
+        self.multiplier = multiplier.max(1.0);
+        self.max_backoff = max;
+        self
+    }
+
+    /// Set the fraction of each backoff which is randomised, so that clients
+    /// which failed together don't retry together. With `0.0` backoffs are
+    /// exact, and with `1.0` they are anywhere between zero and the full
+    /// backoff.
+    pub fn with_jitter(mut self, jitter: f64) -> Self {
+        self.jitter = jitter.clamp(0.0, 1.0);
+        self
+    }
+
+    /// Set whether to retry requests which fail with an error, such as a
+    /// failure to connect, rather than a response.
+    pub fn with_retry_errors(mut self, retry_errors: bool) -> Self {
+        self.retry_errors = retry_errors;
+        self
+    }
+
+    /// Set the response statuses which are retried.
+    pub fn with_statuses<I: IntoIterator<Item = StatusCode>>(mut self, statuses: I) -> Self {
+        self.statuses = statuses.into_iter().collect();
+        self
+    }
+
+    /// Set the longest `Retry-After` delay to wait for, or `None` to ignore
+    /// the header. Responses asking for a longer delay aren't retried.
+    pub fn with_max_retry_after(mut self, max: Option<Duration>) -> Self {
+        self.max_retry_after = max;
+        self
+    }
+
+    /// Set whether to retry requests with methods which aren't idempotent,
+    /// such as `POST` and `PATCH`, which may then be applied more than once.
+    pub fn with_retry_non_idempotent(mut self, retry_non_idempotent: bool) -> Self {
+        self.retry_non_idempotent = retry_non_idempotent;
+        self
+    }
+
+    /// Limit retries by the given budget, which should be shared with the
+    /// other middleware retrying requests to the same service.
+    pub fn with_budget(mut self, budget: RetryBudget) -> Self {
+        self.budget = Some(budget);
+        self
+    }
+
+    /// Whether the request may be retried.
+    fn allows<B>(&self, request: &Request<B>) -> bool {
+        self.retry_non_idempotent
+            || request.method().is_idempotent()
+            || request.headers().contains_key(IDEMPOTENCY_KEY)
+    }
+
+    /// Delay before the given retry, counting from one, given the headers of
+    /// the response to the previous attempt, or `None` if the request
+    /// shouldn't be retried.
+    fn delay(&self, retry: u32, headers: Option<&HeaderMap>) -> Option<Duration> {
+        if let (Some(max), Some(headers)) = (self.max_retry_after, headers) {
+            if let Some(retry_after) = retry_after(headers) {
+                return (retry_after <= max).then_some(retry_after);
+            }
+        }
+
+        let exponent = retry.saturating_sub(1).min(i32::MAX as u32) as i32;
+        let backoff = (self.initial_backoff.as_secs_f64() * self.multiplier.powi(exponent))
+            .min(self.max_backoff.as_secs_f64());
+        Some(Duration::from_secs_f64(
+            backoff * (1.0 - self.jitter * random()),
+        ))
+    }
+}
+
+/// Delay requested by the `Retry-After` header, given either in seconds or
+/// as an HTTP date.
+fn retry_after(headers: &HeaderMap) -> Option<Duration> {
+    let value = headers.get(RETRY_AFTER)?.to_str().ok()?.trim();
+    if let Ok(seconds) = value.parse() {
+        return Some(Duration::from_secs(seconds));
+    }
+    let date = httpdate::parse_http_date(value).ok()?;
+    Some(
+        date.duration_since(SystemTime::now())
+            .unwrap_or(Duration::ZERO),
+    )
+}
+
+/// Random number in `[0, 1)`.
+fn random() -> f64 {
+    let bits = RandomState::new().build_hasher().finish();
+    (bits >> 11) as f64 / (1u64 << 53) as f64
+}
+
+/// Middleware which retries requests which fail, or get a response with a
+/// retryable status, according to a `RetryPolicy`.
+///
+/// The request and its context are cloned for each attempt, so the body must
+/// be cloneable. Streaming bodies should be buffered first.
+///
+/// ```ignore
+/// let client = Retry::new(
+///     DropContextService::new(http_client),
+///     RetryPolicy::new().with_max_attempts(5).with_budget(budget),
+/// );
+/// ```
+pub struct Retry<T> {
+    inner: Arc<T>,
+    policy: Arc<RetryPolicy>,
+    timer: Arc<dyn Timer + Send + Sync>,
+}
+
+impl<T> Retry<T> {
+    /// Create a middleware which retries requests according to the policy,
+    /// using a Tokio timer to wait between attempts.
+    pub fn new(inner: T, policy: RetryPolicy) -> Self {
+        Retry {
+            inner: Arc::new(inner),
+            policy: Arc::new(policy),
+            timer: Arc::new(TokioTimer::new()),
+        }
+    }
+
+    /// Use the given timer, rather than Tokio's.
+    pub fn with_timer<M: Timer + Send + Sync + 'static>(mut self, timer: M) -> Self {
+        self.timer = Arc::new(timer);
+        self
+    }
+}
+
+impl<T> Clone for Retry<T> {
+    fn clone(&self) -> Self {
+        Retry {
+            inner: self.inner.clone(),
+            policy: self.policy.clone(),
+            timer: self.timer.clone(),
+        }
+    }
+}
+
+impl<T: fmt::Debug> fmt::Debug for Retry<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Retry")
+            .field("inner", &self.inner)
+            .field("policy", &self.policy)
+            .finish()
+    }
+}
+
+impl<T, B, C, RB> Service<(Request<B>, C)> for Retry<T>
+where
+    T: Service<(Request<B>, C), Response = Response<RB>> + Send + Sync + 'static,
+    T::Future: Send,
+    T::Error: Send,
+    RB: Send,
+    B: Clone + Send + 'static,
+    C: Clone + Send + 'static,
+{
+    type Response = T::Response;
+    type Error = T::Error;
+    type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn call(&self, req: (Request<B>, C)) -> Self::Future {
+        let inner = self.inner.clone();
+        let policy = self.policy.clone();
+        let timer = self.timer.clone();
+
+        if !policy.allows(&req.0) || policy.max_attempts == 1 {
+            return Box::pin(inner.call(req));
+        }
+
+        Box::pin(async move {
+            let (request, context) = req;
+            if let Some(budget) = &policy.budget {
+                budget.deposit();
+            }
+
+            let mut attempt = 1;
+            loop {
+                let result = inner.call((request.clone(), context.clone())).await;
+                let headers = match &result {
+                    Ok(response) if policy.statuses.contains(&response.status()) => {
+                        Some(response.headers())
+                    }
+                    Err(_) if policy.retry_errors => None,
+                    _ => return result,
+                };
+                if attempt >= policy.max_attempts {
+                    return result;
+                }
+                let Some(delay) = policy.delay(attempt, headers) else {
+                    return result;
+                };
+                if let Some(budget) = &policy.budget {
+                    if !budget.try_withdraw() {
+                        return result;
+                    }
+                }
+                timer.sleep(delay).await;
+                attempt += 1;
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ApiError, EmptyContext};
+    use hyper::Method;
+    use std::sync::Mutex;
+
+    /// Service responding with each status in turn, and failing once they
+    /// run out.
+    #[derive(Default)]
+    struct StatusService(Mutex<Vec<u16>>);
+
+    impl Service<(Request<()>, EmptyContext)> for StatusService {
+        type Response = Response<()>;
+        type Error = ApiError;
+        type Future = futures::future::Ready<Result<Self::Response, Self::Error>>;
+
+        fn call(&self, _req: (Request<()>, EmptyContext)) -> Self::Future {
+            let mut statuses = self.0.lock().unwrap();
+            futures::future::ready(if statuses.is_empty() {
+                Err(ApiError("connection refused".to_string()))
+            } else {
+                let status = statuses.remove(0);
+                let mut response = Response::new(());
+                *response.status_mut() = StatusCode::from_u16(status).unwrap();
+                if status == 429 {
+                    response
+                        .headers_mut()
+                        .insert(RETRY_AFTER, "3600".parse().unwrap());
+                }
+                Ok(response)
+            })
+        }
+    }
+
+    fn retry(statuses: &[u16], policy: RetryPolicy) -> Retry<StatusService> {
+        Retry::new(
+            StatusService(Mutex::new(statuses.to_vec())),
+            policy.with_backoff(Duration::from_millis(1), 2.0, Duration::from_millis(10)),
+        )
+    }
+
+    async fn call(client: &Retry<StatusService>, method: Method) -> Result<StatusCode, String> {
+        let request = Request::builder().method(method).body(()).unwrap();
+        client
+            .call((request, EmptyContext))
+            .await
+            .map(|response| response.status())
+            .map_err(|e| e.0)
+    }
+
+    #[tokio::test]
+    async fn test_retry() {
+        // Retryable statuses are retried until one succeeds.
+        let client = retry(&[503, 502, 200], RetryPolicy::new());
+        assert_eq!(call(&client, Method::GET).await, Ok(StatusCode::OK));
+
+        // Other statuses aren't retried.
+        let client = retry(&[500, 200], RetryPolicy::new());
+        assert_eq!(
+            call(&client, Method::GET).await,
+            Ok(StatusCode::INTERNAL_SERVER_ERROR)
+        );
+
+        // Attempts are limited, and the last result returned.
+        let client = retry(&[503, 503, 503, 200], RetryPolicy::new());
+        assert_eq!(
+            call(&client, Method::GET).await,
+            Ok(StatusCode::SERVICE_UNAVAILABLE)
+        );
+
+        // Errors are retried.
+        let client = retry(&[], RetryPolicy::new().with_max_attempts(2));
+        assert_eq!(
+            call(&client, Method::GET).await,
+            Err("connection refused".to_string())
+        );
+        assert!(client.inner.0.lock().unwrap().is_empty());
+
+        // Non-idempotent requests aren't retried unless allowed.
+        let client = retry(&[503, 200], RetryPolicy::new());
+        assert_eq!(
+            call(&client, Method::POST).await,
+            Ok(StatusCode::SERVICE_UNAVAILABLE)
+        );
+        let client = retry(
+            &[503, 200],
+            RetryPolicy::new().with_retry_non_idempotent(true),
+        );
+        assert_eq!(call(&client, Method::POST).await, Ok(StatusCode::OK));
+        let client = retry(&[503, 200], RetryPolicy::new());
+        let request = Request::post("/")
+            .header(IDEMPOTENCY_KEY, "1")
+            .body(())
+            .unwrap();
+        let response = client.call((request, EmptyContext)).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        // Retry-After delays longer than allowed aren't waited for.
+        let client = retry(&[429, 200], RetryPolicy::new());
+        assert_eq!(
+            call(&client, Method::GET).await,
+            Ok(StatusCode::TOO_MANY_REQUESTS)
+        );
+        let client = retry(&[429, 200], RetryPolicy::new().with_max_retry_after(None));
+        assert_eq!(call(&client, Method::GET).await, Ok(StatusCode::OK));
+
+        // Retries are limited by the budget.
+        let budget = RetryBudget::new(0.0)
+            .with_min_per_second(0)
+            .with_capacity(1);
+        let client = retry(&[503, 200], RetryPolicy::new().with_budget(budget));
+        assert_eq!(
+            call(&client, Method::GET).await,
+            Ok(StatusCode::SERVICE_UNAVAILABLE)
+        );
+    }
+
+    #[test]
+    fn test_backoff() {
+        let policy = RetryPolicy::new()
+            .with_backoff(Duration::from_millis(100), 2.0, Duration::from_secs(1))
+            .with_jitter(0.0);
+        assert_eq!(policy.delay(1, None), Some(Duration::from_millis(100)));
+        assert_eq!(policy.delay(3, None), Some(Duration::from_millis(400)));
+        assert_eq!(policy.delay(10, None), Some(Duration::from_secs(1)));
+        assert_eq!(policy.delay(u32::MAX, None), Some(Duration::from_secs(1)));
+
+        let policy = policy.with_jitter(1.0);
+        for _ in 0..10 {
+            assert!(policy.delay(1, None).unwrap() <= Duration::from_millis(100));
+        }
+
+        let mut headers = HeaderMap::new();
+        headers.insert(RETRY_AFTER, "5".parse().unwrap());
+        assert_eq!(
+            policy.delay(1, Some(&headers)),
+            Some(Duration::from_secs(5))
+        );
+        let date = httpdate::fmt_http_date(SystemTime::now() - Duration::from_secs(60));
+        headers.insert(RETRY_AFTER, date.parse().unwrap());
+        assert_eq!(policy.delay(1, Some(&headers)), Some(Duration::ZERO));
+    }
+}
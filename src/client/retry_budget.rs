@@ -0,0 +1,193 @@
+//! Budget limiting the retries made by a client.
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::{Duration, Instant};
+
+#[derive(Debug)]
+struct Bucket {
+    tokens: f64,
+    refilled: Instant,
+    min_per_second: f64,
+    capacity: f64,
+}
+
+#[derive(Debug)]
+struct Shared {
+    ratio: f64,
+    bucket: Mutex<Bucket>,
+}
+
+/// Token bucket limiting retries to a fraction of a client's requests.
+///
+/// Each request deposits `ratio` tokens, and each retry, or hedged request,
+/// withdraws one, so when an upstream service is failing, retries add at most
+/// that fraction to the load on it, rather than multiplying it. A minimum
+/// number of retries per second is always allowed, so that clients making few
+/// requests can still retry.
+///
+/// Clones share the same budget, so one budget should be shared by all the
+/// middleware making retries to the same upstream service.
+///
+/// ```ignore
+/// let budget = RetryBudget::new(0.2);
+///
+/// // For each request:
+/// budget.deposit();
+/// // Before each retry:
+/// if budget.try_withdraw_before(deadline, expected_latency) { ... }
+/// ```
+#[derive(Clone, Debug)]
+pub struct RetryBudget(Arc<Shared>);
+
+impl Default for RetryBudget {
+    /// Budget allowing retries of 20% of requests, and 10 per second.
+    fn default() -> Self {
+        RetryBudget::new(0.2)
+    }
+}
+
+impl RetryBudget {
+    /// Create a budget allowing retries of the given fraction of requests, and
+    /// at least 10 per second.
+    pub fn new(ratio: f64) -> Self {
+        let min_per_second = 10.0;
+        RetryBudget(Arc::new(Shared {
+            ratio: ratio.max(0.0),
+            bucket: Mutex::new(Bucket {
+                tokens: min_per_second,
+                refilled: Instant::now(),
+                min_per_second,
+                capacity: min_per_second,
+            }),
+        }))
+    }
+
+    /// Set the number of retries allowed per second regardless of the number
+    /// of requests, which is shared with any clones.
+    pub fn with_min_per_second(self, min_per_second: u32) -> Self {
+        let mut bucket = self.0.lock();
+        bucket.min_per_second = f64::from(min_per_second);
+        bucket.capacity = bucket.capacity.max(bucket.min_per_second);
+        bucket.tokens = bucket.min_per_second;
+        drop(bucket);
+        self
+    }
+
+    /// Set the maximum number of tokens which can accumulate, and so the
+    /// largest burst of retries allowed, which is shared with any clones.
+    pub fn with_capacity(self, capacity: u32) -> Self {
+        let mut bucket = self.0.lock();
+        bucket.capacity = f64::from(capacity.max(1));
+        bucket.tokens = bucket.tokens.min(bucket.capacity);
+        drop(bucket);
+        self
+    }
+
+    /// Record a request, adding to the budget for retries.
+    pub fn deposit(&self) {
+        let mut bucket = self.0.lock();
+        bucket.tokens = (bucket.tokens + self.0.ratio).min(bucket.capacity);
+    }
+
+    /// Withdraw from the budget for a retry, returning whether it is allowed.
+    pub fn try_withdraw(&self) -> bool {
+        self.withdraw(Instant::now())
+    }
+
+    /// Withdraw from the budget for a retry, returning whether it is allowed.
+    ///
+    /// A retry which can't complete before the deadline, because less than
+    /// the expected latency of the request remains, isn't allowed, and doesn't
+    /// withdraw from the budget.
+    pub fn try_withdraw_before(&self, deadline: Instant, expected_latency: Duration) -> bool {
+        let now = Instant::now();
+        now + expected_latency <= deadline && self.withdraw(now)
+    }
+
+    /// Number of retries currently allowed.
+    pub fn balance(&self) -> u32 {
+        let mut bucket = self.0.lock();
+        bucket.refill(Instant::now());
+        bucket.tokens as u32
+    }
+
+    fn withdraw(&self, now: Instant) -> bool {
+        let mut bucket = self.0.lock();
+        bucket.refill(now);
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+impl Shared {
+    fn lock(&self) -> std::sync::MutexGuard<'_, Bucket> {
+        self.bucket.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+impl Bucket {
+    /// Add the minimum retries for the time since the bucket was last refilled.
+    fn refill(&mut self, now: Instant) {
+        let elapsed = now.saturating_duration_since(self.refilled);
+        self.tokens =
+            (self.tokens + elapsed.as_secs_f64() * self.min_per_second).min(self.capacity);
+        self.refilled = now;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_retry_budget() {
+        let budget = RetryBudget::new(0.5)
+            .with_min_per_second(0)
+            .with_capacity(2);
+        assert_eq!(budget.balance(), 0);
+        assert!(!budget.try_withdraw());
+
+        // Two requests allow one retry.
+        budget.deposit();
+        assert!(!budget.try_withdraw());
+        budget.deposit();
+        assert!(budget.try_withdraw());
+        assert!(!budget.try_withdraw());
+
+        // The balance is capped.
+        for _ in 0..10 {
+            budget.deposit();
+        }
+        assert_eq!(budget.balance(), 2);
+
+        // Retries which can't meet the deadline are refused without spending.
+        let deadline = Instant::now() + Duration::from_millis(100);
+        assert!(!budget.try_withdraw_before(deadline, Duration::from_secs(1)));
+        assert_eq!(budget.balance(), 2);
+        assert!(budget.try_withdraw_before(deadline, Duration::ZERO));
+        assert_eq!(budget.balance(), 1);
+
+        // Settings made on a clone apply to the shared budget.
+        let _clone = budget.clone().with_capacity(1);
+        for _ in 0..10 {
+            budget.deposit();
+        }
+        assert_eq!(budget.balance(), 1);
+    }
+
+    #[test]
+    fn test_min_per_second() {
+        let budget = RetryBudget::new(0.0).with_min_per_second(10);
+        let start = Instant::now();
+        for _ in 0..10 {
+            assert!(budget.withdraw(start));
+        }
+        assert!(!budget.withdraw(start));
+        assert!(budget.withdraw(start + Duration::from_millis(200)));
+        assert!(budget.withdraw(start + Duration::from_millis(200)));
+        assert!(!budget.withdraw(start + Duration::from_millis(200)));
+    }
+}
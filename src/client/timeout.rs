@@ -0,0 +1,267 @@
+//! Middleware which limits the time taken by outgoing requests.
+use crate::context::Has;
+use crate::deadline::Deadline;
+use crate::ApiError;
+use futures::future::{BoxFuture, Either, FutureExt};
+use hyper::rt::Timer;
+use hyper::service::Service;
+use hyper::Request;
+use hyper_util::rt::TokioTimer;
+use std::error;
+use std::fmt;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Middleware which fails outgoing requests that take longer than a timeout
+/// to respond, with an `ApiError`.
+///
+/// The timeout covers connecting, sending the request and receiving the
+/// response headers, but not reading the response body, which can be limited
+/// using `connector::Builder::read_timeout`.
+///
+/// ```ignore
+/// let client = RequestTimeout::new(
+///     DropContextService::new(http_client),
+///     Duration::from_secs(30),
+/// );
+/// ```
+pub struct RequestTimeout<T> {
+    inner: T,
+    timeout: Duration,
+    timer: Arc<dyn Timer + Send + Sync>,
+}
+
+impl<T> RequestTimeout<T> {
+    /// Create a middleware which times out requests after the given
+    /// duration, using a Tokio timer.
+    pub fn new(inner: T, timeout: Duration) -> Self {
+        RequestTimeout {
+            inner,
+            timeout,
+            timer: Arc::new(TokioTimer::new()),
+        }
+    }
+
+    /// Use the given timer, rather than Tokio's.
+    pub fn with_timer<M: Timer + Send + Sync + 'static>(mut self, timer: M) -> Self {
+        self.timer = Arc::new(timer);
+        self
+    }
+}
+
+impl<T: Clone> Clone for RequestTimeout<T> {
+    fn clone(&self) -> Self {
+        RequestTimeout {
+            inner: self.inner.clone(),
+            timeout: self.timeout,
+            timer: self.timer.clone(),
+        }
+    }
+}
+
+impl<T: fmt::Debug> fmt::Debug for RequestTimeout<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RequestTimeout")
+            .field("inner", &self.inner)
+            .field("timeout", &self.timeout)
+            .finish()
+    }
+}
+
+impl<T, B, C> Service<(Request<B>, C)> for RequestTimeout<T>
+where
+    T: Service<(Request<B>, C)>,
+    T::Future: Send + 'static,
+    T::Error: From<ApiError>,
+{
+    type Response = T::Response;
+    type Error = T::Error;
+    type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn call(&self, req: (Request<B>, C)) -> Self::Future {
+        let timeout = self.timeout;
+        let sleep = self.timer.sleep(timeout);
+        let response = self.inner.call(req);
+        futures::future::select(Box::pin(response), sleep)
+            .map(move |result| match result {
+                Either::Left((response, _)) => response,
+                Either::Right(_) => {
+                    Err(ApiError(format!("Request timed out after {:?}", timeout)).into())
+                }
+            })
+            .boxed()
+    }
+}
+
+/// Error from a `Timeout` middleware when a request doesn't complete in
+/// time.
+///
+/// Clients whose error type has a variant for timeouts can convert this
+/// error into it, to tell timeouts apart from other failures. It can also be
+/// converted into an `ApiError`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TimeoutError(Duration);
+
+impl TimeoutError {
+    /// Time allowed for the request.
+    pub fn timeout(&self) -> Duration {
+        self.0
+    }
+}
+
+impl fmt::Display for TimeoutError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Request timed out after {:?}", self.0)
+    }
+}
+
+impl error::Error for TimeoutError {}
+
+impl From<TimeoutError> for ApiError {
+    fn from(error: TimeoutError) -> Self {
+        ApiError(error.to_string())
+    }
+}
+
+/// Middleware which fails outgoing requests that take longer than a timeout
+/// to respond, or which outlast the `Option<Deadline>` in their context, with
+/// a `TimeoutError`.
+///
+/// Unlike `RequestTimeout`, the time allowed depends on the request, so a
+/// server passing its context on to the requests it makes stops waiting for
+/// them when its own deadline passes.
+///
+/// ```ignore
+/// let client = Timeout::new(
+///     DropContextService::new(http_client),
+///     Duration::from_secs(30),
+/// );
+/// ```
+pub struct Timeout<T> {
+    inner: T,
+    timeout: Duration,
+    timer: Arc<dyn Timer + Send + Sync>,
+}
+
+impl<T> Timeout<T> {
+    /// Create a middleware which times out requests after the given
+    /// duration, or their deadline if sooner, using a Tokio timer.
+    pub fn new(inner: T, timeout: Duration) -> Self {
+        Timeout {
+            inner,
+            timeout,
+            timer: Arc::new(TokioTimer::new()),
+        }
+    }
+
+    /// Use the given timer, rather than Tokio's.
+    pub fn with_timer<M: Timer + Send + Sync + 'static>(mut self, timer: M) -> Self {
+        self.timer = Arc::new(timer);
+        self
+    }
+}
+
+impl<T: Clone> Clone for Timeout<T> {
+    fn clone(&self) -> Self {
+        Timeout {
+            inner: self.inner.clone(),
+            timeout: self.timeout,
+            timer: self.timer.clone(),
+        }
+    }
+}
+
+impl<T: fmt::Debug> fmt::Debug for Timeout<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Timeout")
+            .field("inner", &self.inner)
+            .field("timeout", &self.timeout)
+            .finish()
+    }
+}
+
+impl<T, B, C> Service<(Request<B>, C)> for Timeout<T>
+where
+    T: Service<(Request<B>, C)>,
+    T::Future: Send + 'static,
+    T::Error: From<TimeoutError>,
+    C: Has<Option<Deadline>>,
+{
+    type Response = T::Response;
+    type Error = T::Error;
+    type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn call(&self, req: (Request<B>, C)) -> Self::Future {
+        let timeout = match req.1.get() {
+            Some(deadline) => self.timeout.min(deadline.remaining()),
+            None => self.timeout,
+        };
+        let sleep = self.timer.sleep(timeout);
+        let response = self.inner.call(req);
+        futures::future::select(Box::pin(response), sleep)
+            .map(move |result| match result {
+                Either::Left((response, _)) => response,
+                Either::Right(_) => Err(TimeoutError(timeout).into()),
+            })
+            .boxed()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::context::Push;
+
+    crate::new_context_type!(TestContext, TestEmptyContext, Option<Deadline>);
+
+    struct SleepService;
+
+    impl<C> Service<(Request<()>, C)> for SleepService {
+        type Response = ();
+        type Error = ApiError;
+        type Future = BoxFuture<'static, Result<(), ApiError>>;
+
+        fn call(&self, req: (Request<()>, C)) -> Self::Future {
+            let sleep: u64 = req.0.uri().path()[1..].parse().unwrap();
+            Box::pin(async move {
+                tokio::time::sleep(Duration::from_millis(sleep)).await;
+                Ok(())
+            })
+        }
+    }
+
+    #[tokio::test]
+    async fn test_request_timeout() {
+        let client = RequestTimeout::new(SleepService, Duration::from_millis(50));
+        let call =
+            |path: &str| client.call((Request::get(path).body(()).unwrap(), TestEmptyContext));
+
+        assert!(call("/0").await.is_ok());
+        assert_eq!(
+            call("/1000").await.unwrap_err().0,
+            "Request timed out after 50ms"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_timeout() {
+        let client = Timeout::new(SleepService, Duration::from_millis(200));
+        let call = |path: &str, deadline: Option<Deadline>| {
+            let context: TestContext<Option<Deadline>, TestEmptyContext> =
+                TestEmptyContext.push(deadline);
+            client.call((Request::get(path).body(()).unwrap(), context))
+        };
+
+        assert!(call("/0", None).await.is_ok());
+        assert_eq!(
+            call("/1000", None).await.unwrap_err().0,
+            "Request timed out after 200ms"
+        );
+
+        // The deadline in the context shortens the timeout.
+        let deadline = Deadline::after(Duration::from_millis(20));
+        let start = std::time::Instant::now();
+        assert!(call("/1000", Some(deadline)).await.is_err());
+        assert!(start.elapsed() < Duration::from_millis(200));
+    }
+}
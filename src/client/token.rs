@@ -0,0 +1,415 @@
+//! OAuth2 access tokens, obtained using the client credentials grant.
+//!
+//! A `TokenManager` requests access tokens from an authorization server as
+//! described in RFC 6749 section 4.4, caches them, and refreshes them shortly
+//! before they expire. The `TokenInjector` middleware stores the token in the
+//! context of outgoing requests as `AuthData::Bearer`, for an `AuthInjector`
+//! to add to the request.
+use super::auth::QUERY_ENCODE_SET;
+use crate::auth::AuthData;
+use crate::context::Has;
+use crate::ApiError;
+use futures::future::BoxFuture;
+use headers::authorization::Credentials;
+use headers::Authorization as Header;
+use http_body_util::{BodyExt, Full};
+use hyper::body::{Body, Bytes};
+use hyper::header::{AUTHORIZATION, CONTENT_TYPE};
+use hyper::service::Service;
+use hyper::{Request, Response, Uri};
+use percent_encoding::utf8_percent_encode;
+use serde::Deserialize;
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// Successful response from a token endpoint, per RFC 6749 section 5.1.
+#[derive(Debug, Deserialize)]
+struct TokenResponse {
+    access_token: String,
+    token_type: String,
+    expires_in: Option<u64>,
+}
+
+/// Error response from a token endpoint, per RFC 6749 section 5.2.
+#[derive(Debug, Deserialize)]
+struct ErrorResponse {
+    error: String,
+    error_description: Option<String>,
+}
+
+#[derive(Clone, Debug)]
+struct CachedToken {
+    token: String,
+    expires: Option<Instant>,
+}
+
+struct Inner<C> {
+    client: C,
+    token_uri: Uri,
+    client_id: String,
+    client_secret: String,
+}
+
+#[derive(Default)]
+struct Cache {
+    token: Mutex<Option<CachedToken>>,
+    // Held while requesting a token, so concurrent requests share a single fetch.
+    refreshing: futures::lock::Mutex<()>,
+}
+
+/// Manager for OAuth2 access tokens, obtained using the client credentials grant.
+///
+/// Clones share the same cached token, unless they're given different scopes.
+///
+/// ```ignore
+/// let tokens = TokenManager::new(
+///     http_client,
+///     "https://auth.example.com/token".parse()?,
+///     "my-client",
+///     "my-secret",
+/// )
+/// .with_scopes(&["pets:read"]);
+/// let client = TokenInjector::new(AuthInjector::new(DropContextService::new(api_client)), tokens);
+/// ```
+pub struct TokenManager<C> {
+    inner: Arc<Inner<C>>,
+    scopes: Vec<String>,
+    refresh_margin: Duration,
+    cache: Arc<Cache>,
+}
+
+impl<C> Clone for TokenManager<C> {
+    fn clone(&self) -> Self {
+        TokenManager {
+            inner: self.inner.clone(),
+            scopes: self.scopes.clone(),
+            refresh_margin: self.refresh_margin,
+            cache: self.cache.clone(),
+        }
+    }
+}
+
+impl<C> fmt::Debug for TokenManager<C> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TokenManager")
+            .field("token_uri", &self.inner.token_uri)
+            .field("client_id", &self.inner.client_id)
+            .field("scopes", &self.scopes)
+            .finish()
+    }
+}
+
+impl<C, B> TokenManager<C>
+where
+    C: Service<Request<Full<Bytes>>, Response = Response<B>>,
+    C::Error: fmt::Display,
+    B: Body,
+    B::Error: fmt::Display,
+{
+    /// Create a manager which requests tokens from the given token endpoint,
+    /// authenticating with the client ID and secret.
+    pub fn new<I: Into<String>, S: Into<String>>(
+        client: C,
+        token_uri: Uri,
+        client_id: I,
+        client_secret: S,
+    ) -> Self {
+        TokenManager {
+            inner: Arc::new(Inner {
+                client,
+                token_uri,
+                client_id: client_id.into(),
+                client_secret: client_secret.into(),
+            }),
+            scopes: Vec::new(),
+            refresh_margin: Duration::from_secs(60),
+            cache: Arc::default(),
+        }
+    }
+
+    /// Request tokens with the given scopes.
+    pub fn with_scopes<T: ToString>(mut self, scopes: &[T]) -> Self {
+        self.scopes = scopes.iter().map(ToString::to_string).collect();
+        // Tokens cached for other scopes can't be used.
+        self.cache = Arc::default();
+        self
+    }
+
+    /// Set how long before a token expires it is refreshed. Defaults to one minute.
+    pub fn with_refresh_margin<D: Into<Duration>>(mut self, margin: D) -> Self {
+        self.refresh_margin = margin.into();
+        self
+    }
+
+    fn cached(&self) -> Option<String> {
+        let cache = self.cache.token.lock().unwrap();
+        cache
+            .as_ref()
+            .filter(|cached| match cached.expires {
+                Some(expires) => Instant::now() + self.refresh_margin < expires,
+                None => true,
+            })
+            .map(|cached| cached.token.clone())
+    }
+
+    /// Discard the cached token, e.g. because it was rejected by a server, so
+    /// that a new one is requested.
+    pub fn invalidate(&self) {
+        *self.cache.token.lock().unwrap() = None;
+    }
+
+    /// Get a valid access token, requesting a new one if necessary.
+    pub async fn token(&self) -> Result<String, ApiError> {
+        if let Some(token) = self.cached() {
+            return Ok(token);
+        }
+
+        let _refreshing = self.cache.refreshing.lock().await;
+        // Another request may have obtained a token while we were waiting.
+        if let Some(token) = self.cached() {
+            return Ok(token);
+        }
+
+        let requested = Instant::now();
+        let response = self.request_token().await?;
+        let token = response.access_token;
+        *self.cache.token.lock().unwrap() = Some(CachedToken {
+            token: token.clone(),
+            expires: response
+                .expires_in
+                .map(|expires_in| requested + Duration::from_secs(expires_in)),
+        });
+        Ok(token)
+    }
+
+    /// Get a valid access token as `AuthData`, requesting a new one if necessary.
+    pub async fn auth_data(&self) -> Result<AuthData, ApiError> {
+        Ok(AuthData::Bearer(self.token().await?.into()))
+    }
+
+    async fn request_token(&self) -> Result<TokenResponse, ApiError> {
+        let uri = &self.inner.token_uri;
+        let encode = |s: &str| utf8_percent_encode(s, QUERY_ENCODE_SET).to_string();
+
+        let mut body = "grant_type=client_credentials".to_string();
+        if !self.scopes.is_empty() {
+            body.push_str("&scope=");
+            body.push_str(&encode(&self.scopes.join(" ")));
+        }
+
+        // RFC 6749 section 2.3.1 requires the credentials to be form encoded.
+        let credentials = Header::basic(
+            &encode(&self.inner.client_id),
+            &encode(&self.inner.client_secret),
+        )
+        .0
+        .encode();
+
+        let request = Request::post(uri.clone())
+            .header(AUTHORIZATION, credentials)
+            .header(CONTENT_TYPE, "application/x-www-form-urlencoded")
+            .body(Full::new(Bytes::from(body)))
+            .map_err(|e| ApiError(format!("Invalid request to {}: {}", uri, e)))?;
+
+        let response = self
+            .inner
+            .client
+            .call(request)
+            .await
+            .map_err(|e| ApiError(format!("Failed to request token from {}: {}", uri, e)))?;
+        let status = response.status();
+        let body = response
+            .into_body()
+            .collect()
+            .await
+            .map_err(|e| ApiError(format!("Failed to read token from {}: {}", uri, e)))?
+            .to_bytes();
+
+        if !status.is_success() {
+            return Err(ApiError(
+                match serde_json::from_slice::<ErrorResponse>(&body) {
+                    Ok(error) => format!(
+                        "Token request to {} failed: {}{}",
+                        uri,
+                        error.error,
+                        error
+                            .error_description
+                            .map(|d| format!(" ({})", d))
+                            .unwrap_or_default()
+                    ),
+                    Err(_) => format!("Token request to {} failed: {}", uri, status),
+                },
+            ));
+        }
+
+        let response: TokenResponse = serde_json::from_slice(&body)
+            .map_err(|e| ApiError(format!("Invalid token response from {}: {}", uri, e)))?;
+        if !response.token_type.eq_ignore_ascii_case("bearer") {
+            return Err(ApiError(format!(
+                "Unsupported token type from {}: {}",
+                uri, response.token_type
+            )));
+        }
+        Ok(response)
+    }
+}
+
+/// Middleware which stores an access token from a `TokenManager` in the
+/// context of outgoing requests, as `AuthData::Bearer`.
+///
+/// Requests whose context already contains `AuthData` are left unchanged.
+/// Failures to obtain a token are returned as errors from the inner service.
+#[derive(Clone, Debug)]
+pub struct TokenInjector<T, C> {
+    inner: T,
+    manager: TokenManager<C>,
+}
+
+impl<T, C> TokenInjector<T, C> {
+    /// Create a middleware which obtains tokens from the given manager.
+    pub fn new(inner: T, manager: TokenManager<C>) -> Self {
+        TokenInjector { inner, manager }
+    }
+}
+
+impl<T, C, B, Ctx, ResBody> Service<(Request<B>, Ctx)> for TokenInjector<T, C>
+where
+    T: Service<(Request<B>, Ctx)> + Clone + Send + 'static,
+    T::Future: Send + 'static,
+    T::Error: From<ApiError>,
+    C: Service<Request<Full<Bytes>>, Response = Response<ResBody>> + Send + Sync + 'static,
+    C::Future: Send,
+    C::Error: fmt::Display,
+    ResBody: Body + Send,
+    ResBody::Data: Send,
+    ResBody::Error: fmt::Display,
+    B: Send + 'static,
+    Ctx: Has<Option<AuthData>> + Send + 'static,
+{
+    type Response = T::Response;
+    type Error = T::Error;
+    type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn call(&self, req: (Request<B>, Ctx)) -> Self::Future {
+        let (request, mut context) = req;
+        let inner = self.inner.clone();
+        let manager = self.manager.clone();
+
+        Box::pin(async move {
+            if Has::<Option<AuthData>>::get(&context).is_none() {
+                context.set(Some(manager.auth_data().await?));
+            }
+            inner.call((request, context)).await
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::context::{ContextBuilder, Push};
+    use crate::EmptyContext;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    struct TokenServer {
+        expires_in: u64,
+        count: AtomicUsize,
+    }
+
+    impl Service<Request<Full<Bytes>>> for TokenServer {
+        type Response = Response<Full<Bytes>>;
+        type Error = String;
+        type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+        fn call(&self, req: Request<Full<Bytes>>) -> Self::Future {
+            let count = self.count.fetch_add(1, Ordering::SeqCst) + 1;
+            let expires_in = self.expires_in;
+            Box::pin(async move {
+                assert_eq!(
+                    req.headers().get(AUTHORIZATION).unwrap(),
+                    "Basic bXklMjBjbGllbnQ6c2VjcmV0"
+                );
+                let body = req.into_body().collect().await.unwrap().to_bytes();
+                assert_eq!(body, "grant_type=client_credentials&scope=a%20b");
+
+                let body = format!(
+                    r#"{{"access_token":"token-{}","token_type":"Bearer","expires_in":{}}}"#,
+                    count, expires_in
+                );
+                Ok(Response::new(Full::new(Bytes::from(body))))
+            })
+        }
+    }
+
+    fn manager(expires_in: u64) -> TokenManager<TokenServer> {
+        TokenManager::new(
+            TokenServer {
+                expires_in,
+                count: AtomicUsize::new(0),
+            },
+            "https://auth.example.com/token".parse().unwrap(),
+            "my client",
+            "secret",
+        )
+        .with_scopes(&["a", "b"])
+    }
+
+    #[tokio::test]
+    async fn test_token_cached() {
+        let manager = manager(3600);
+        assert_eq!(manager.token().await.unwrap(), "token-1");
+        assert_eq!(manager.clone().token().await.unwrap(), "token-1");
+
+        // Clones given scopes of their own don't share the cached token.
+        let clone = manager.clone().with_scopes(&["a", "b"]);
+        assert_eq!(clone.token().await.unwrap(), "token-2");
+        assert_eq!(manager.token().await.unwrap(), "token-1");
+
+        manager.invalidate();
+        assert_eq!(manager.token().await.unwrap(), "token-3");
+    }
+
+    #[tokio::test]
+    async fn test_token_refreshed_before_expiry() {
+        // Tokens expiring within the refresh margin are never reused.
+        let manager = manager(30);
+        assert_eq!(manager.token().await.unwrap(), "token-1");
+        assert_eq!(manager.token().await.unwrap(), "token-2");
+    }
+
+    #[derive(Clone)]
+    struct AuthDataService;
+
+    type Context = ContextBuilder<Option<AuthData>, EmptyContext>;
+
+    impl Service<(Request<()>, Context)> for AuthDataService {
+        type Response = Option<AuthData>;
+        type Error = ApiError;
+        type Future = futures::future::Ready<Result<Self::Response, Self::Error>>;
+
+        fn call(&self, req: (Request<()>, Context)) -> Self::Future {
+            futures::future::ok(Has::<Option<AuthData>>::get(&req.1).clone())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_token_injector() {
+        let service = TokenInjector::new(AuthDataService, manager(3600));
+
+        let auth_data = service
+            .call((Request::new(()), EmptyContext.push(None::<AuthData>)))
+            .await
+            .unwrap();
+        assert_eq!(auth_data, Some(AuthData::Bearer("token-1".into())));
+
+        let auth_data = service
+            .call((
+                Request::new(()),
+                EmptyContext.push(Some(AuthData::apikey("key"))),
+            ))
+            .await
+            .unwrap();
+        assert_eq!(auth_data, Some(AuthData::apikey("key")));
+    }
+}
@@ -0,0 +1,167 @@
+//! Middleware which passes the trace of a request on to the services it
+//! calls.
+use crate::context::Has;
+use crate::header::{TraceContext, XSpanIdString, TRACEPARENT, X_SPAN_ID};
+use hyper::header::HeaderValue;
+use hyper::service::Service;
+use hyper::Request;
+
+/// Middleware which writes the `XSpanIdString` and `Option<TraceContext>`
+/// from the context of outgoing requests to their `X-Span-ID` and
+/// `traceparent` headers.
+///
+/// Each request is sent with a new span, a child of the span in the context,
+/// so that the services it calls record it as their parent. Without a trace
+/// context, a new trace is started with the ID of the `XSpanIdString`, so
+/// that it still lines up with the logs of the services it calls. Headers
+/// already set on the request are left unchanged.
+///
+/// Unlike `InjectContextService`, which sends the context items unchanged,
+/// this doesn't need a middleware for each header.
+///
+/// ```ignore
+/// let client = TracePropagator::new(DropContextService::new(http_client));
+/// ```
+#[derive(Clone, Debug)]
+pub struct TracePropagator<T> {
+    inner: T,
+}
+
+impl<T> TracePropagator<T> {
+    /// Create a middleware which propagates traces to outgoing requests.
+    pub fn new(inner: T) -> Self {
+        TracePropagator { inner }
+    }
+}
+
+impl<T, B, C> Service<(Request<B>, C)> for TracePropagator<T>
+where
+    T: Service<(Request<B>, C)>,
+    C: Has<XSpanIdString> + Has<Option<TraceContext>>,
+{
+    type Response = T::Response;
+    type Error = T::Error;
+    type Future = T::Future;
+
+    fn call(&self, req: (Request<B>, C)) -> Self::Future {
+        let (mut request, context) = req;
+        let x_span_id = Has::<XSpanIdString>::get(&context);
+        let headers = request.headers_mut();
+        if !headers.contains_key(X_SPAN_ID) {
+            if let Ok(value) = HeaderValue::from_str(&x_span_id.0) {
+                headers.insert(X_SPAN_ID, value);
+            }
+        }
+        if !headers.contains_key(TRACEPARENT) {
+            let trace = match Has::<Option<TraceContext>>::get(&context) {
+                Some(trace) => trace.child(),
+                None => TraceContext::from_span_id(x_span_id),
+            };
+            trace.insert(headers);
+        }
+        self.inner.call((request, context))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::context::propagation::ExtractContextService;
+    use crate::context::Push;
+    use crate::AddContextService;
+    use hyper::header::HeaderMap;
+
+    crate::new_context_type!(
+        TestContext,
+        TestEmptyContext,
+        XSpanIdString,
+        Option<TraceContext>
+    );
+
+    type Context = TestContext<Option<TraceContext>, TestContext<XSpanIdString, TestEmptyContext>>;
+
+    struct HeaderService;
+
+    impl Service<(Request<()>, Context)> for HeaderService {
+        type Response = HeaderMap;
+        type Error = ();
+        type Future = futures::future::Ready<Result<Self::Response, Self::Error>>;
+
+        fn call(&self, req: (Request<()>, Context)) -> Self::Future {
+            futures::future::ok(req.0.headers().clone())
+        }
+    }
+
+    async fn call(trace: Option<TraceContext>) -> (XSpanIdString, TraceContext) {
+        let context = TestEmptyContext
+            .push(XSpanIdString(
+                "1b4e28ba-2fa1-11d2-883f-0016d3cca427".to_string(),
+            ))
+            .push(trace);
+        let headers = TracePropagator::new(HeaderService)
+            .call((Request::new(()), context))
+            .await
+            .unwrap();
+        (
+            XSpanIdString(headers[X_SPAN_ID].to_str().unwrap().to_string()),
+            TraceContext::from_headers(&headers).unwrap(),
+        )
+    }
+
+    #[tokio::test]
+    async fn test_trace_propagator() {
+        // Without a trace context, a trace is started from the span ID.
+        let (x_span_id, first) = call(None).await;
+        assert_eq!(x_span_id.0, "1b4e28ba-2fa1-11d2-883f-0016d3cca427");
+        assert_eq!(XSpanIdString::from(&first).0, x_span_id.0);
+
+        // Each request is sent in a child span of the context's span.
+        let parent =
+            TraceContext::parse("00-0af7651916cd43dd8448eb211c80319c-b7ad6b7169203331-01").unwrap();
+        let (_, first) = call(Some(parent.clone())).await;
+        let (_, second) = call(Some(parent.clone())).await;
+        assert_eq!(first.trace_id, parent.trace_id);
+        assert!(first.is_sampled());
+        assert_ne!(first.span_id, parent.span_id);
+        assert_ne!(first.span_id, second.span_id);
+    }
+
+    /// Server which forwards requests through a `TracePropagator` with its
+    /// context, returning the headers it received and those it sent.
+    struct ForwardService;
+
+    impl Service<(Request<()>, Context)> for ForwardService {
+        type Response = (HeaderMap, HeaderMap);
+        type Error = ();
+        type Future = futures::future::Ready<Result<Self::Response, Self::Error>>;
+
+        fn call(&self, req: (Request<()>, Context)) -> Self::Future {
+            let (request, context) = req;
+            let sent = TracePropagator::new(HeaderService).call((Request::new(()), context));
+            futures::future::ok((request.headers().clone(), sent.into_inner().unwrap()))
+        }
+    }
+
+    #[tokio::test]
+    async fn test_server_trace() {
+        let server = ExtractContextService::<_, Option<TraceContext>, _>::new(ForwardService);
+        let server = AddContextService::<_, TestEmptyContext>::new(server);
+
+        // Requests without a trace are passed on unchanged, and a new trace is
+        // started for the requests made in turn.
+        let (received, sent) = server.call(Request::new(())).await.unwrap();
+        assert!(received.is_empty());
+        let trace = TraceContext::from_headers(&sent).unwrap();
+        assert_eq!(XSpanIdString::from(&trace).0, sent[X_SPAN_ID]);
+
+        // Otherwise the trace is continued.
+        let parent =
+            TraceContext::parse("00-0af7651916cd43dd8448eb211c80319c-b7ad6b7169203331-01").unwrap();
+        let mut request = Request::new(());
+        parent.insert(request.headers_mut());
+        let (_, sent) = server.call(request).await.unwrap();
+        let trace = TraceContext::from_headers(&sent).unwrap();
+        assert_eq!(trace.trace_id, parent.trace_id);
+        assert_ne!(trace.span_id, parent.span_id);
+    }
+}
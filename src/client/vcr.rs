@@ -0,0 +1,394 @@
+//! Middleware which records requests and their responses to a file, and
+//! replays them, for hermetic tests of clients against real services.
+//!
+//! A test is first run against the real service in `VcrMode::Record`, saving
+//! each request and response to a JSON cassette, which is checked in. Later
+//! runs in `VcrMode::Replay` answer requests from the cassette, without
+//! calling the service:
+//!
+//! ```ignore
+//! let mode = if std::env::var_os("RECORD").is_some() {
+//!     VcrMode::Record
+//! } else {
+//!     VcrMode::Replay
+//! };
+//! let client = Vcr::new(
+//!     DropContextService::new(http_client),
+//!     "tests/cassettes/pets.json",
+//!     mode,
+//! )?;
+//! ```
+use crate::redact::Redaction;
+use crate::ApiError;
+use base64::{engine::general_purpose::STANDARD, Engine};
+use futures::future::BoxFuture;
+use http_body_util::{BodyExt, Full};
+use hyper::body::{Body, Bytes};
+use hyper::header::{HeaderMap, HeaderName, HeaderValue};
+use hyper::service::Service;
+use hyper::{Request, Response, StatusCode};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, PoisonError};
+
+/// Whether a `Vcr` records or replays requests.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum VcrMode {
+    /// Send requests to the inner service, and record them and their
+    /// responses, replacing the cassette.
+    Record,
+    /// Answer requests from the cassette, without calling the inner service.
+    Replay,
+}
+
+/// Message body, held as a string if it is UTF-8, and in base64 otherwise.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+struct RecordedBody {
+    body: String,
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    base64: bool,
+}
+
+impl RecordedBody {
+    fn new(body: &Bytes) -> Self {
+        match std::str::from_utf8(body) {
+            Ok(body) => RecordedBody {
+                body: body.to_string(),
+                base64: false,
+            },
+            Err(_) => RecordedBody {
+                body: STANDARD.encode(body),
+                base64: true,
+            },
+        }
+    }
+
+    fn to_bytes(&self) -> Result<Bytes, ApiError> {
+        if self.base64 {
+            STANDARD
+                .decode(&self.body)
+                .map(Bytes::from)
+                .map_err(|e| ApiError(format!("Invalid body in cassette: {}", e)))
+        } else {
+            Ok(Bytes::from(self.body.clone()))
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+struct RecordedRequest {
+    method: String,
+    uri: String,
+    headers: Vec<(String, String)>,
+    #[serde(flatten)]
+    body: RecordedBody,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+struct RecordedResponse {
+    status: u16,
+    headers: Vec<(String, String)>,
+    #[serde(flatten)]
+    body: RecordedBody,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+struct Interaction {
+    request: RecordedRequest,
+    response: RecordedResponse,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct Cassette {
+    interactions: Vec<Interaction>,
+    /// Which interactions have been replayed.
+    #[serde(skip)]
+    replayed: Vec<bool>,
+}
+
+impl Cassette {
+    fn load(path: &Path) -> io::Result<Self> {
+        let mut cassette: Cassette = serde_json::from_reader(BufReader::new(File::open(path)?))?;
+        cassette.replayed = vec![false; cassette.interactions.len()];
+        Ok(cassette)
+    }
+
+    fn save(&self, path: &Path) -> io::Result<()> {
+        serde_json::to_writer_pretty(BufWriter::new(File::create(path)?), self)?;
+        Ok(())
+    }
+
+    /// Find the first interaction not yet replayed with the same method, URI
+    /// and body as the request.
+    fn replay(&mut self, request: &RecordedRequest) -> Option<&RecordedResponse> {
+        let index =
+            self.interactions
+                .iter()
+                .zip(&self.replayed)
+                .position(|(interaction, replayed)| {
+                    !replayed
+                        && interaction.request.method == request.method
+                        && interaction.request.uri == request.uri
+                        && interaction.request.body == request.body
+                })?;
+        self.replayed[index] = true;
+        Some(&self.interactions[index].response)
+    }
+}
+
+/// Record headers, masking the values of those which are redacted.
+fn record_headers(headers: &HeaderMap, redaction: &Redaction) -> Vec<(String, String)> {
+    headers
+        .iter()
+        .map(|(name, value)| {
+            let value = if redaction.is_redacted(name) {
+                "[REDACTED]".to_string()
+            } else {
+                String::from_utf8_lossy(value.as_bytes()).into_owned()
+            };
+            (name.to_string(), value)
+        })
+        .collect()
+}
+
+fn to_response(recorded: &RecordedResponse) -> Result<Response<Full<Bytes>>, ApiError> {
+    let mut response = Response::new(Full::new(recorded.body.to_bytes()?));
+    *response.status_mut() = StatusCode::from_u16(recorded.status)
+        .map_err(|e| ApiError(format!("Invalid status in cassette: {}", e)))?;
+    for (name, value) in &recorded.headers {
+        let name = HeaderName::try_from(name.as_str());
+        let value = HeaderValue::try_from(value.as_str());
+        if let (Ok(name), Ok(value)) = (name, value) {
+            response.headers_mut().append(name, value);
+        }
+    }
+    Ok(response)
+}
+
+/// Middleware which records requests and their responses to a cassette file,
+/// or replays them from it.
+///
+/// Recorded headers are masked according to a `Redaction`, by default the
+/// default redaction, so that credentials aren't saved. Requests are replayed
+/// by matching their method, URI and body against the recorded requests, in
+/// the order they were recorded, with each recorded response used once.
+/// Requests which match none fail with an `ApiError`.
+///
+/// Response bodies are read in full, and the cassette is rewritten after
+/// each request, so it is intended for tests rather than production use.
+pub struct Vcr<T> {
+    inner: Arc<T>,
+    path: PathBuf,
+    mode: VcrMode,
+    redaction: Arc<Redaction>,
+    cassette: Arc<Mutex<Cassette>>,
+}
+
+impl<T> Vcr<T> {
+    /// Create a middleware recording to, or replaying from, the cassette at
+    /// the given path.
+    ///
+    /// In replay mode, the cassette is read immediately, failing if it
+    /// doesn't exist or is invalid.
+    pub fn new<P: Into<PathBuf>>(inner: T, path: P, mode: VcrMode) -> io::Result<Self> {
+        let path = path.into();
+        let cassette = match mode {
+            VcrMode::Record => Cassette::default(),
+            VcrMode::Replay => Cassette::load(&path)?,
+        };
+        Ok(Vcr {
+            inner: Arc::new(inner),
+            path,
+            mode,
+            redaction: Arc::new(Redaction::default()),
+            cassette: Arc::new(Mutex::new(cassette)),
+        })
+    }
+
+    /// Mask the values of recorded headers according to the given redaction.
+    pub fn with_redaction(mut self, redaction: Redaction) -> Self {
+        self.redaction = Arc::new(redaction);
+        self
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Cassette> {
+        self.cassette.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+impl<T> Clone for Vcr<T> {
+    fn clone(&self) -> Self {
+        Vcr {
+            inner: self.inner.clone(),
+            path: self.path.clone(),
+            mode: self.mode,
+            redaction: self.redaction.clone(),
+            cassette: self.cassette.clone(),
+        }
+    }
+}
+
+impl<T: fmt::Debug> fmt::Debug for Vcr<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Vcr")
+            .field("inner", &self.inner)
+            .field("path", &self.path)
+            .field("mode", &self.mode)
+            .finish()
+    }
+}
+
+impl<T, B, C, RB> Service<(Request<B>, C)> for Vcr<T>
+where
+    T: Service<(Request<Full<Bytes>>, C), Response = Response<RB>> + Send + Sync + 'static,
+    T::Future: Send,
+    T::Error: From<ApiError>,
+    B: Body + Send + 'static,
+    B::Data: Send,
+    B::Error: fmt::Display,
+    C: Send + 'static,
+    RB: Body + Send,
+    RB::Data: Send,
+    RB::Error: fmt::Display,
+{
+    type Response = Response<Full<Bytes>>;
+    type Error = T::Error;
+    type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn call(&self, req: (Request<B>, C)) -> Self::Future {
+        let vcr = self.clone();
+        let (request, context) = req;
+        Box::pin(async move {
+            let (parts, body) = request.into_parts();
+            let body = body
+                .collect()
+                .await
+                .map_err(|e| ApiError(format!("Failed to read request body: {}", e)))?
+                .to_bytes();
+            let recorded_request = RecordedRequest {
+                method: parts.method.to_string(),
+                uri: parts.uri.to_string(),
+                headers: record_headers(&parts.headers, &vcr.redaction),
+                body: RecordedBody::new(&body),
+            };
+
+            if vcr.mode == VcrMode::Replay {
+                let mut cassette = vcr.lock();
+                let response = cassette.replay(&recorded_request).ok_or_else(|| {
+                    ApiError(format!(
+                        "No recorded response to {} {}",
+                        recorded_request.method, recorded_request.uri
+                    ))
+                })?;
+                return Ok(to_response(response)?);
+            }
+
+            let request = Request::from_parts(parts, Full::new(body));
+            let response = vcr.inner.call((request, context)).await?;
+            let (parts, body) = response.into_parts();
+            let body = body
+                .collect()
+                .await
+                .map_err(|e| ApiError(format!("Failed to read response body: {}", e)))?
+                .to_bytes();
+
+            let interaction = Interaction {
+                request: recorded_request,
+                response: RecordedResponse {
+                    status: parts.status.as_u16(),
+                    headers: record_headers(&parts.headers, &vcr.redaction),
+                    body: RecordedBody::new(&body),
+                },
+            };
+            {
+                let mut cassette = vcr.lock();
+                cassette.interactions.push(interaction);
+                cassette.replayed.push(false);
+                cassette.save(&vcr.path).map_err(|e| {
+                    ApiError(format!(
+                        "Failed to save cassette {}: {}",
+                        vcr.path.display(),
+                        e
+                    ))
+                })?;
+            }
+            Ok(Response::from_parts(parts, Full::new(body)))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::EmptyContext;
+
+    /// Server echoing the body of each request, and the number of requests
+    /// it has received.
+    #[derive(Default)]
+    struct EchoServer(Mutex<usize>);
+
+    impl Service<(Request<Full<Bytes>>, EmptyContext)> for EchoServer {
+        type Response = Response<Full<Bytes>>;
+        type Error = ApiError;
+        type Future = futures::future::Ready<Result<Self::Response, Self::Error>>;
+
+        fn call(&self, req: (Request<Full<Bytes>>, EmptyContext)) -> Self::Future {
+            let mut count = self.0.lock().unwrap();
+            *count += 1;
+            let mut response = Response::new(req.0.into_body());
+            response
+                .headers_mut()
+                .insert("x-count", HeaderValue::from(*count));
+            response
+                .headers_mut()
+                .insert("set-cookie", HeaderValue::from_static("session=secret"));
+            futures::future::ok(response)
+        }
+    }
+
+    async fn call(
+        client: &Vcr<EchoServer>,
+        body: &'static [u8],
+    ) -> Result<Response<Full<Bytes>>, ApiError> {
+        let request = Request::post("http://petstore/echo")
+            .header("authorization", "Bearer secret")
+            .body(Full::new(Bytes::from_static(body)))
+            .unwrap();
+        client.call((request, EmptyContext)).await
+    }
+
+    #[tokio::test]
+    async fn test_record_and_replay() {
+        let path = std::env::temp_dir().join(format!("swagger-vcr-{}.json", std::process::id()));
+
+        let recorder = Vcr::new(EchoServer::default(), &path, VcrMode::Record).unwrap();
+        call(&recorder, b"hello").await.unwrap();
+        call(&recorder, b"\xff").await.unwrap();
+        call(&recorder, b"hello").await.unwrap();
+
+        let cassette = std::fs::read_to_string(&path).unwrap();
+        assert!(!cassette.contains("secret"), "{}", cassette);
+
+        // Responses are replayed in order, without calling the server.
+        let server = EchoServer::default();
+        *server.0.lock().unwrap() = 100;
+        let player = Vcr::new(server, &path, VcrMode::Replay).unwrap();
+        let response = call(&player, b"hello").await.unwrap();
+        assert_eq!(response.headers()["x-count"], "1");
+        let response = call(&player, b"\xff").await.unwrap();
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        assert_eq!(body, &b"\xff"[..]);
+        let response = call(&player, b"hello").await.unwrap();
+        assert_eq!(response.headers()["x-count"], "3");
+        assert_eq!(
+            call(&player, b"hello").await.unwrap_err().0,
+            "No recorded response to POST http://petstore/echo"
+        );
+
+        std::fs::remove_file(&path).unwrap();
+        assert!(Vcr::new(EchoServer::default(), &path, VcrMode::Replay).is_err());
+    }
+}
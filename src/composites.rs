@@ -2,13 +2,20 @@
 //!
 //! Use by passing `hyper::server::MakeService` instances to a `CompositeMakeService`
 //! together with the base path for requests that should be handled by that service.
+use crate::request_info::{MatchedBasePath, RouteCaptures};
+use crate::split::{SlowStart, SlowStartService};
+use crate::ApiError;
 use futures::future::{BoxFuture, FutureExt, TryFutureExt};
+use hyper::header::{HeaderValue, ALLOW, CONTENT_TYPE, HOST};
 use hyper::service::Service;
-use hyper::{Request, Response, StatusCode};
+use hyper::{Method, Request, Response, StatusCode, Uri};
 use std::fmt;
 use std::future::Future;
+use std::marker::PhantomData;
 use std::net::SocketAddr;
 use std::ops::{Deref, DerefMut};
+use std::sync::{Arc, Mutex, PoisonError, RwLock, RwLockReadGuard, RwLockWriteGuard};
+use std::time::{Duration, Instant};
 
 /// Trait for generating a default "not found" response. Must be implemented on
 /// the `Response` associated type for `MakeService`s being combined in a
@@ -34,7 +41,7 @@ pub trait HasRemoteAddr {
     fn remote_addr(&self) -> Option<SocketAddr>;
 }
 
-impl<'a> HasRemoteAddr for &'a Option<SocketAddr> {
+impl HasRemoteAddr for &Option<SocketAddr> {
     fn remote_addr(&self) -> Option<SocketAddr> {
         **self
     }
@@ -47,7 +54,7 @@ impl HasRemoteAddr for Option<SocketAddr> {
 }
 
 #[cfg(feature = "uds")]
-impl<'a> HasRemoteAddr for &'a tokio::net::UnixStream {
+impl HasRemoteAddr for &tokio::net::UnixStream {
     fn remote_addr(&self) -> Option<SocketAddr> {
         None
     }
@@ -104,14 +111,833 @@ where
     }
 }
 
+/// Middleware which wraps a `MakeService`, such as one of this crate's
+/// `Make*Service`s, for use with `CompositeMakeService::push_with_layers`.
+///
+/// Implemented for closures taking the inner `MakeService`, and for pairs of
+/// layers, the first of which is outermost. Stacks of layers are most easily
+/// built with the `layers!` macro.
+pub trait Layer<M> {
+    /// The wrapped `MakeService`.
+    type Output;
+
+    /// Wrap the inner `MakeService`.
+    fn layer(&self, inner: M) -> Self::Output;
+}
+
+impl<F, M, O> Layer<M> for F
+where
+    F: Fn(M) -> O,
+{
+    type Output = O;
+
+    fn layer(&self, inner: M) -> O {
+        self(inner)
+    }
+}
+
+impl<A, B, M> Layer<M> for (A, B)
+where
+    B: Layer<M>,
+    A: Layer<B::Output>,
+{
+    type Output = A::Output;
+
+    fn layer(&self, inner: M) -> Self::Output {
+        self.0.layer(self.1.layer(inner))
+    }
+}
+
+/// Stack layers for `CompositeMakeService::push_with_layers`, listed from
+/// outermost to innermost, so that requests pass through them in order.
+///
+/// ```ignore
+/// composite_make_service.push_with_layers(
+///     "/pets",
+///     pets_make_service,
+///     layers![
+///         |inner| MakeAllowAllAuthenticator::new(inner, "anonymous"),
+///         |inner| MakeLocaleService::new(inner, Locale::new("en")),
+///     ],
+/// );
+/// ```
+#[macro_export]
+macro_rules! layers {
+    ($layer:expr $(,)?) => {
+        $layer
+    };
+    ($layer:expr, $($layers:expr),+ $(,)?) => {
+        ($layer, $crate::layers!($($layers),+))
+    };
+}
+
+/// How a route conflicts with one registered before it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RouteConflictKind {
+    /// The routes are the same, other than the names of any path
+    /// parameters, as in `/pets/{id}` and `/pets/{petId}`.
+    Duplicate,
+    /// The earlier route is a prefix of the later route, so swallows all of
+    /// its requests, as `/pet` does for `/pets`.
+    Prefix,
+}
+
+/// A route which can never be matched, because requests for it are handled
+/// by a route registered before it.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RouteConflict {
+    /// The route which can't be matched.
+    pub route: String,
+    /// The earlier route which matches its requests.
+    pub shadowed_by: String,
+    /// How the routes conflict.
+    pub kind: RouteConflictKind,
+}
+
+impl fmt::Display for RouteConflict {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.kind {
+            RouteConflictKind::Duplicate => write!(
+                f,
+                "route {:?} duplicates earlier route {:?}",
+                self.route, self.shadowed_by
+            ),
+            RouteConflictKind::Prefix => write!(
+                f,
+                "route {:?} is shadowed by earlier route {:?}, which is a prefix of it",
+                self.route, self.shadowed_by
+            ),
+        }
+    }
+}
+
+/// Find the routes which can never be matched, given routes which are
+/// matched by prefix in order, the first match winning.
+///
+/// Path parameters, such as `{petId}`, are treated as equal regardless of
+/// their names.
+pub fn route_conflicts<'a, I>(routes: I) -> Vec<RouteConflict>
+where
+    I: IntoIterator<Item = &'a str>,
+{
+    let mut earlier: Vec<(&str, String)> = Vec::new();
+    let mut conflicts = Vec::new();
+    for route in routes {
+        let normalized = normalize_route(route);
+        let conflict = earlier.iter().find_map(|(earlier, earlier_normalized)| {
+            let kind = if *earlier_normalized == normalized {
+                RouteConflictKind::Duplicate
+            } else if normalized.starts_with(earlier_normalized.as_str()) {
+                RouteConflictKind::Prefix
+            } else {
+                return None;
+            };
+            Some(RouteConflict {
+                route: route.to_string(),
+                shadowed_by: earlier.to_string(),
+                kind,
+            })
+        });
+        conflicts.extend(conflict);
+        earlier.push((route, normalized));
+    }
+    conflicts
+}
+
+/// Replace the names of path parameters in a route with `{}`.
+fn normalize_route(route: &str) -> String {
+    route
+        .split('/')
+        .map(|segment| {
+            if segment.starts_with('{') && segment.ends_with('}') {
+                "{}"
+            } else {
+                segment
+            }
+        })
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+/// Fail if any routes can never be matched, describing the conflicts.
+fn check_conflicts(conflicts: Vec<RouteConflict>) -> Result<(), ApiError> {
+    if conflicts.is_empty() {
+        return Ok(());
+    }
+    let conflicts: Vec<String> = conflicts.iter().map(ToString::to_string).collect();
+    Err(ApiError(format!(
+        "Conflicting routes: {}",
+        conflicts.join("; ")
+    )))
+}
+
+/// How the base paths of a composite are matched against request paths.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum MatchMode {
+    /// The first base path, in the order they were added, which is a prefix
+    /// of the request path matches. An earlier base path shadows any later
+    /// base path which it is a prefix of, as `/api` does `/api/v2`.
+    #[default]
+    PrefixFirstMatch,
+    /// The longest base path which is a prefix of the request path matches,
+    /// whatever order they were added in.
+    LongestPrefix,
+    /// Only a base path equal to the request path matches.
+    Exact,
+}
+
+/// Pattern matched against request paths in place of a base path.
+///
+/// Unlike a base path, which is matched as a prefix of the request path, a
+/// pattern can match a varying segment, such as the user ID in
+/// `/users/{userId}/documents`. Anything it captures is stored in the
+/// request's extensions as `RouteCaptures`.
+#[derive(Clone, Debug)]
+pub struct RoutePattern(PatternKind);
+
+#[derive(Clone, Debug)]
+enum PatternKind {
+    Glob(Vec<GlobSegment>),
+    #[cfg(feature = "regex")]
+    Regex(regex::Regex),
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum GlobSegment {
+    Literal(String),
+    /// `*` or `{name}`, matching a single segment.
+    Any(Option<String>),
+    /// `**`, matching all remaining segments.
+    Rest,
+}
+
+impl RoutePattern {
+    /// Pattern matching the path segment by segment: `*` matches any one
+    /// segment, `{name}` matches any one segment and captures it under the
+    /// name, and a trailing `**` matches all remaining segments. Other
+    /// segments must match exactly.
+    ///
+    /// As with base paths, the pattern matches the start of the request path,
+    /// so `/users/*/documents` matches `/users/1/documents/2`, but not
+    /// `/users/1/documentsX`.
+    pub fn glob(pattern: &str) -> Self {
+        let segments = pattern
+            .split('/')
+            .map(|segment| match segment {
+                "*" => GlobSegment::Any(None),
+                "**" => GlobSegment::Rest,
+                _ if segment.starts_with('{') && segment.ends_with('}') && segment.len() > 2 => {
+                    GlobSegment::Any(Some(segment[1..segment.len() - 1].to_string()))
+                }
+                _ => GlobSegment::Literal(segment.to_string()),
+            })
+            .collect();
+        RoutePattern(PatternKind::Glob(segments))
+    }
+
+    /// Pattern matching the path against a regular expression, capturing
+    /// the values of its groups. Anchor it with `^` to match the start of
+    /// the path only.
+    #[cfg(feature = "regex")]
+    pub fn regex(regex: regex::Regex) -> Self {
+        RoutePattern(PatternKind::Regex(regex))
+    }
+
+    /// Match the path, returning the captures if it matches. If `exact`, the
+    /// pattern must match the whole path.
+    fn captures(&self, path: &str, exact: bool) -> Option<RouteCaptures> {
+        match &self.0 {
+            PatternKind::Glob(pattern) => {
+                let mut captures = Vec::new();
+                let mut segments = path.split('/');
+                for glob in pattern {
+                    if *glob == GlobSegment::Rest {
+                        let rest: Vec<&str> = segments.by_ref().collect();
+                        captures.push((None, rest.join("/")));
+                        break;
+                    }
+                    let segment = segments.next()?;
+                    match glob {
+                        GlobSegment::Literal(literal) if literal == segment => {}
+                        GlobSegment::Any(name) if !segment.is_empty() => {
+                            captures.push((name.clone(), segment.to_string()))
+                        }
+                        _ => return None,
+                    }
+                }
+                if exact && segments.next().is_some() {
+                    return None;
+                }
+                Some(captures.into_iter().collect())
+            }
+            #[cfg(feature = "regex")]
+            PatternKind::Regex(regex) => {
+                let found = regex.captures(path)?;
+                let whole = found.get(0)?;
+                if exact && whole.as_str().len() != path.len() {
+                    return None;
+                }
+                Some(
+                    regex
+                        .capture_names()
+                        .zip(found.iter())
+                        .skip(1)
+                        .map(|(name, value)| {
+                            (
+                                name.map(str::to_string),
+                                value.map_or_else(String::new, |v| v.as_str().to_string()),
+                            )
+                        })
+                        .collect(),
+                )
+            }
+        }
+    }
+}
+
+/// Outcome of a request handled by a `CompositeService`, for metrics.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RouteMetrics {
+    /// Base path the request was routed to, or `None` if it matched none.
+    pub base_path: Option<&'static str>,
+    /// Method of the request.
+    pub method: Method,
+    /// Status of the response, or `None` if the service failed.
+    pub status: Option<StatusCode>,
+    /// Time from the request being routed to the response headers being
+    /// ready.
+    pub latency: Duration,
+}
+
+impl RouteMetrics {
+    /// Class of the status, from 1 for `1xx` to 5 for `5xx`, or `None` if
+    /// the service failed.
+    pub fn status_class(&self) -> Option<u16> {
+        self.status.map(|status| status.as_u16() / 100)
+    }
+}
+
+/// Destination for per-base-path metrics from a `CompositeService`, such as
+/// an adapter which updates Prometheus counters and histograms, or sends
+/// statsd packets.
+///
+/// Sinks are called synchronously as each response is ready, so must not
+/// block.
+///
+/// This is implemented for closures taking a `RouteMetrics`:
+///
+/// ```ignore
+/// let composite_make_service = CompositeMakeService::new()
+///     .with_metrics_sink(|metrics: RouteMetrics| eprintln!("{:?}", metrics));
+/// ```
+pub trait MetricsSink: Send + Sync {
+    /// Record the outcome of a request.
+    fn record(&self, metrics: RouteMetrics);
+}
+
+impl<F> MetricsSink for F
+where
+    F: Fn(RouteMetrics) + Send + Sync,
+{
+    fn record(&self, metrics: RouteMetrics) {
+        self(metrics)
+    }
+}
+
+/// The metrics sink of a composite, if it has one.
+#[derive(Clone, Default)]
+struct Metrics(Option<Arc<dyn MetricsSink>>);
+
+impl fmt::Debug for Metrics {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("Metrics").field(&self.0.is_some()).finish()
+    }
+}
+
+/// Routing configuration shared by a `CompositeMakeService` and the
+/// `CompositeService`s it makes.
+#[derive(Clone, Debug, Default)]
+struct Routing {
+    mode: MatchMode,
+    /// Methods allowed by each base path which restricts them.
+    methods: Vec<(&'static str, Vec<Method>)>,
+    /// Prefix to replace each stripped base path with.
+    rewrites: Vec<(&'static str, &'static str)>,
+    /// Pattern to match each base path which isn't matched as a prefix.
+    patterns: Vec<(&'static str, RoutePattern)>,
+    metrics: Metrics,
+    /// OpenAPI document of each base path which has one.
+    #[cfg(feature = "serdejson")]
+    documents: Arc<RwLock<Vec<(&'static str, serde_json::Value)>>>,
+}
+
+impl Routing {
+    /// Base paths which can never be matched in this mode.
+    fn conflicts<'a, I>(&self, routes: I) -> Vec<RouteConflict>
+    where
+        I: IntoIterator<Item = &'a str>,
+    {
+        let conflicts = route_conflicts(routes);
+        match self.mode {
+            MatchMode::PrefixFirstMatch => conflicts,
+            // Only duplicates conflict when the order doesn't matter.
+            MatchMode::LongestPrefix | MatchMode::Exact => conflicts
+                .into_iter()
+                .filter(|conflict| conflict.kind == RouteConflictKind::Duplicate)
+                .collect(),
+        }
+    }
+
+    /// Order in which to try the base paths, so that the first match wins.
+    fn order(&self, base_paths: &[&str]) -> Vec<usize> {
+        let mut order: Vec<usize> = (0..base_paths.len()).collect();
+        if self.mode == MatchMode::LongestPrefix {
+            // Stable, so equal base paths keep their order.
+            order.sort_by_key(|&i| std::cmp::Reverse(base_paths[i].len()));
+        }
+        order
+    }
+
+    /// Match the request path against the base path, returning anything
+    /// captured by its pattern.
+    fn matches(&self, base_path: &str, path: &str) -> Option<RouteCaptures> {
+        let exact = self.mode == MatchMode::Exact;
+        if let Some((_, pattern)) = self.patterns.iter().find(|(p, _)| *p == base_path) {
+            return pattern.captures(path, exact);
+        }
+        let matched = if exact {
+            path == base_path
+        } else {
+            path.starts_with(base_path)
+        };
+        matched.then(RouteCaptures::default)
+    }
+
+    fn allowed_methods(&self, base_path: &str) -> Option<&[Method]> {
+        self.methods
+            .iter()
+            .find(|(path, _)| *path == base_path)
+            .map(|(_, methods)| methods.as_slice())
+    }
+
+    fn replacement(&self, base_path: &str) -> Option<&'static str> {
+        // The request path needn't start with the base path of a pattern.
+        if self.patterns.iter().any(|(path, _)| *path == base_path) {
+            return None;
+        }
+        self.rewrites
+            .iter()
+            .find(|(path, _)| *path == base_path)
+            .map(|&(_, replacement)| replacement)
+    }
+
+    fn set_pattern(&mut self, base_path: &'static str, pattern: RoutePattern) {
+        match self
+            .patterns
+            .iter_mut()
+            .find(|(path, _)| *path == base_path)
+        {
+            Some(entry) => entry.1 = pattern,
+            None => self.patterns.push((base_path, pattern)),
+        }
+    }
+
+    fn set_methods(&mut self, base_path: &'static str, methods: Vec<Method>) {
+        match self.methods.iter_mut().find(|(path, _)| *path == base_path) {
+            Some(entry) => entry.1 = methods,
+            None => self.methods.push((base_path, methods)),
+        }
+    }
+
+    fn set_replacement(&mut self, base_path: &'static str, replacement: &'static str) {
+        match self
+            .rewrites
+            .iter_mut()
+            .find(|(path, _)| *path == base_path)
+        {
+            Some(entry) => entry.1 = replacement,
+            None => self.rewrites.push((base_path, replacement)),
+        }
+    }
+
+    /// Rewrite the request for the route it was matched to, and record the
+    /// route in its extensions.
+    fn prepare<B>(&self, req: &mut Request<B>, base_path: &'static str, captures: RouteCaptures) {
+        if let Some(replacement) = self.replacement(base_path) {
+            if let Some(uri) = rewrite_uri(req.uri(), base_path, replacement) {
+                *req.uri_mut() = uri;
+            }
+        }
+        req.extensions_mut().insert(MatchedBasePath(base_path));
+        if !captures.is_empty() {
+            req.extensions_mut().insert(captures);
+        }
+    }
+}
+
+/// Merge the OpenAPI documents of each base path into one describing the
+/// composite, as seen by clients.
+#[cfg(feature = "serdejson")]
+fn merge_documents(routing: &Routing) -> Result<serde_json::Value, ApiError> {
+    use serde_json::{Map, Value};
+
+    let documents = routing
+        .documents
+        .read()
+        .unwrap_or_else(PoisonError::into_inner);
+    let mut merged = Map::new();
+    let mut paths = Map::new();
+    let mut components: Map<String, Value> = Map::new();
+    let mut tags: Vec<Value> = Vec::new();
+
+    for (base_path, document) in documents.iter() {
+        let document = document.as_object().ok_or_else(|| {
+            ApiError(format!(
+                "OpenAPI document for {:?} is not an object",
+                base_path
+            ))
+        })?;
+        for key in ["openapi", "info"] {
+            if let (Some(value), false) = (document.get(key), merged.contains_key(key)) {
+                merged.insert(key.to_string(), value.clone());
+            }
+        }
+
+        // The service sees the request path with its base path replaced, if
+        // it is rewritten, so its document describes paths under the
+        // replacement.
+        let replacement = routing.replacement(base_path);
+        for (path, item) in document
+            .get("paths")
+            .and_then(Value::as_object)
+            .into_iter()
+            .flatten()
+        {
+            let path = match replacement {
+                Some(replacement) => match path.strip_prefix(replacement.trim_end_matches('/')) {
+                    Some(rest) => format!("{}{}", base_path.trim_end_matches('/'), rest),
+                    None => continue,
+                },
+                None => path.clone(),
+            };
+            if paths.insert(path.clone(), item.clone()).is_some() {
+                return Err(ApiError(format!(
+                    "OpenAPI path {:?} is defined twice",
+                    path
+                )));
+            }
+        }
+
+        let sections = document.get("components").and_then(Value::as_object);
+        for (section, entries) in sections.into_iter().flatten() {
+            let merged_section = components
+                .entry(section.clone())
+                .or_insert_with(|| Value::Object(Map::new()));
+            let (Some(merged_section), Some(entries)) =
+                (merged_section.as_object_mut(), entries.as_object())
+            else {
+                continue;
+            };
+            for (name, entry) in entries {
+                match merged_section.get(name) {
+                    Some(existing) if existing != entry => {
+                        return Err(ApiError(format!(
+                            "OpenAPI component {}/{} is defined differently by {:?}",
+                            section, name, base_path
+                        )))
+                    }
+                    Some(_) => {}
+                    None => {
+                        merged_section.insert(name.clone(), entry.clone());
+                    }
+                }
+            }
+        }
+
+        for tag in document
+            .get("tags")
+            .and_then(Value::as_array)
+            .into_iter()
+            .flatten()
+        {
+            let name = tag.get("name");
+            if !tags.iter().any(|t| t.get("name") == name) {
+                tags.push(tag.clone());
+            }
+        }
+    }
+
+    merged
+        .entry("openapi")
+        .or_insert_with(|| Value::from("3.0.3"));
+    merged.insert("paths".to_string(), Value::Object(paths));
+    if !components.is_empty() {
+        merged.insert("components".to_string(), Value::Object(components));
+    }
+    if !tags.is_empty() {
+        merged.insert("tags".to_string(), Value::Array(tags));
+    }
+    Ok(Value::Object(merged))
+}
+
+/// Make service for the merged OpenAPI document of a composite.
+#[cfg(feature = "serdejson")]
+#[allow(clippy::type_complexity)]
+struct MakeOpenApiService<ResBody, Error, MakeError>(
+    Routing,
+    PhantomData<fn() -> (ResBody, Error, MakeError)>,
+);
+
+#[cfg(feature = "serdejson")]
+impl<ResBody, Error, MakeError, Target> Service<Target>
+    for MakeOpenApiService<ResBody, Error, MakeError>
+{
+    type Response = OpenApiService<ResBody, Error>;
+    type Error = MakeError;
+    type Future = futures::future::Ready<Result<Self::Response, MakeError>>;
+
+    fn call(&self, _target: Target) -> Self::Future {
+        futures::future::ok(OpenApiService(self.0.clone(), PhantomData))
+    }
+}
+
+/// Service serving the merged OpenAPI document of a composite.
+#[cfg(feature = "serdejson")]
+struct OpenApiService<ResBody, Error>(Routing, PhantomData<fn() -> (ResBody, Error)>);
+
+#[cfg(feature = "serdejson")]
+impl<ReqBody, ResBody, Error> Service<Request<ReqBody>> for OpenApiService<ResBody, Error>
+where
+    ResBody: From<String>,
+{
+    type Response = Response<ResBody>;
+    type Error = Error;
+    type Future = futures::future::Ready<Result<Response<ResBody>, Error>>;
+
+    fn call(&self, _req: Request<ReqBody>) -> Self::Future {
+        let (status, body) = match merge_documents(&self.0) {
+            Ok(document) => (StatusCode::OK, document.to_string()),
+            Err(error) => (StatusCode::INTERNAL_SERVER_ERROR, error.0),
+        };
+        let mut response = Response::new(ResBody::from(body));
+        *response.status_mut() = status;
+        if status == StatusCode::OK {
+            response
+                .headers_mut()
+                .insert(CONTENT_TYPE, HeaderValue::from_static("application/json"));
+        }
+        futures::future::ok(response)
+    }
+}
+
+/// Replace the base path at the start of the URI's path with the given
+/// prefix, keeping the rest of the URI.
+fn rewrite_uri(uri: &Uri, base_path: &str, replacement: &str) -> Option<Uri> {
+    let rest = uri.path().get(base_path.len()..)?;
+    let prefix = replacement.trim_end_matches('/');
+    let mut path = match rest {
+        "" if !prefix.is_empty() => prefix.to_string(),
+        rest if rest.starts_with('/') => format!("{}{}", prefix, rest),
+        rest => format!("{}/{}", prefix, rest),
+    };
+    if !path.starts_with('/') {
+        path.insert(0, '/');
+    }
+    if let Some(query) = uri.query() {
+        path.push('?');
+        path.push_str(query);
+    }
+    let mut parts = uri.clone().into_parts();
+    parts.path_and_query = Some(path.parse().ok()?);
+    Uri::from_parts(parts).ok()
+}
+
+/// Host a request is for, without any port, from its URI or `Host` header.
+fn request_host<B>(req: &Request<B>) -> Option<&str> {
+    let host = match req.uri().host() {
+        Some(host) => host,
+        None => req.headers().get(HOST)?.to_str().ok()?,
+    };
+    match host.strip_prefix('[') {
+        // IPv6 literals contain colons, so only drop a port after the `]`.
+        Some(rest) => rest.split(']').next(),
+        None => host.split(':').next(),
+    }
+}
+
+/// Whether the host matches the pattern, which is either a host name, `*`
+/// for any host, or `*.` followed by a domain for any subdomain of it.
+fn host_matches(pattern: &str, host: &str) -> bool {
+    if pattern == "*" {
+        return true;
+    }
+    match pattern.strip_prefix("*.") {
+        Some(domain) => {
+            host.len() > domain.len() + 1
+                && host.as_bytes()[host.len() - domain.len() - 1] == b'.'
+                && host[host.len() - domain.len()..].eq_ignore_ascii_case(domain)
+        }
+        None => host.eq_ignore_ascii_case(pattern),
+    }
+}
+
+fn base_paths<T>(services: &[(&'static str, T)]) -> Vec<&'static str> {
+    services.iter().map(|&(base_path, _)| base_path).collect()
+}
+
+/// Group entries by host pattern, in the order the patterns first appear.
+fn group_by_host<T>(entries: Vec<(&'static str, T)>) -> Vec<(&'static str, Vec<T>)> {
+    let mut groups: Vec<(&'static str, Vec<T>)> = Vec::new();
+    for (host, entry) in entries {
+        match groups.iter_mut().find(|(pattern, _)| *pattern == host) {
+            Some((_, group)) => group.push(entry),
+            None => groups.push((host, vec![entry])),
+        }
+    }
+    groups
+}
+
 type CompositeServiceVec<ReqBody, ResBody, Error> = Vec<(
     &'static str,
     Box<dyn CompositedService<ReqBody, ResBody, Error> + Send>,
 )>;
 
+/// Respond with `405 Method Not Allowed`, listing the allowed methods in the
+/// `Allow` header. The body is that of the "not found" response.
+fn method_not_allowed<ResBody: NotFound<ResBody>>(allowed: &[Method]) -> Response<ResBody> {
+    let mut response = ResBody::not_found();
+    *response.status_mut() = StatusCode::METHOD_NOT_ALLOWED;
+    let allowed = allowed
+        .iter()
+        .map(Method::as_str)
+        .collect::<Vec<_>>()
+        .join(", ");
+    if let Ok(value) = HeaderValue::from_str(&allowed) {
+        response.headers_mut().insert(ALLOW, value);
+    }
+    response
+}
+
+/// Handler for requests which match no base path.
+type Fallback<ReqBody, ResBody, Error> = Arc<
+    dyn Fn(Request<ReqBody>) -> BoxFuture<'static, Result<Response<ResBody>, Error>> + Send + Sync,
+>;
+
 type CompositeMakeServiceVec<Target, ReqBody, ResBody, Error, MakeError> =
     Vec<CompositeMakeServiceEntry<Target, ReqBody, ResBody, Error, MakeError>>;
 
+/// Call to the next route, made if an earlier route responds `404 Not Found`.
+type Deferred<ResBody, Error> =
+    Box<dyn FnOnce() -> BoxFuture<'static, Result<Response<ResBody>, Error>> + Send>;
+
+type Defer<ReqBody, ResBody, Error> =
+    fn(Request<ReqBody>, SharedService<ReqBody, ResBody, Error>) -> Deferred<ResBody, Error>;
+
+/// How to fall through to later routes, which needs the request body to be
+/// `Clone` and `Send`.
+struct FallthroughFns<ReqBody, ResBody, Error> {
+    clone_request: fn(&Request<ReqBody>) -> Request<ReqBody>,
+    defer: Defer<ReqBody, ResBody, Error>,
+}
+
+impl<ReqBody, ResBody, Error> Clone for FallthroughFns<ReqBody, ResBody, Error> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<ReqBody, ResBody, Error> Copy for FallthroughFns<ReqBody, ResBody, Error> {}
+
+fn defer<ReqBody, ResBody, Error>(
+    req: Request<ReqBody>,
+    service: SharedService<ReqBody, ResBody, Error>,
+) -> Deferred<ResBody, Error>
+where
+    ReqBody: Send + 'static,
+    ResBody: 'static,
+    Error: 'static,
+{
+    Box::new(move || {
+        service
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .call(req)
+    })
+}
+
+fn clone_request<B: Clone>(req: &Request<B>) -> Request<B> {
+    let mut clone = Request::new(req.body().clone());
+    *clone.method_mut() = req.method().clone();
+    *clone.uri_mut() = req.uri().clone();
+    *clone.version_mut() = req.version();
+    *clone.headers_mut() = req.headers().clone();
+    *clone.extensions_mut() = req.extensions().clone();
+    clone
+}
+
+/// Service which can be called from a future, after an earlier route has
+/// responded `404 Not Found`.
+type SharedService<ReqBody, ResBody, Error> =
+    Arc<Mutex<Box<dyn CompositedService<ReqBody, ResBody, Error> + Send>>>;
+
+struct SharedEntry<ReqBody, ResBody, Error>(SharedService<ReqBody, ResBody, Error>);
+
+impl<ReqBody, ResBody, Error> CompositedService<ReqBody, ResBody, Error>
+    for SharedEntry<ReqBody, ResBody, Error>
+{
+    fn call(&self, req: Request<ReqBody>) -> BoxFuture<'static, Result<Response<ResBody>, Error>> {
+        self.0
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .call(req)
+    }
+}
+
+/// Share the services, so that requests can fall through to them.
+#[allow(clippy::type_complexity)]
+fn share_services<ReqBody, ResBody, Error>(
+    services: CompositeServiceVec<ReqBody, ResBody, Error>,
+) -> (
+    CompositeServiceVec<ReqBody, ResBody, Error>,
+    Vec<SharedService<ReqBody, ResBody, Error>>,
+)
+where
+    ReqBody: 'static,
+    ResBody: 'static,
+    Error: 'static,
+{
+    services
+        .into_iter()
+        .map(|(base_path, service)| {
+            let shared = Arc::new(Mutex::new(service));
+            let entry: Box<dyn CompositedService<ReqBody, ResBody, Error> + Send> =
+                Box::new(SharedEntry(shared.clone()));
+            ((base_path, entry), shared)
+        })
+        .unzip()
+}
+
+/// Services of each route, for falling through to later routes when one
+/// responds `404 Not Found`.
+struct Fallthrough<ReqBody, ResBody, Error> {
+    fns: FallthroughFns<ReqBody, ResBody, Error>,
+    services: Vec<SharedService<ReqBody, ResBody, Error>>,
+    hosts: Vec<Vec<SharedService<ReqBody, ResBody, Error>>>,
+}
+
+/// Make services added for a host pattern, in the order they were added.
+type HostMakeServiceVec<Target, ReqBody, ResBody, Error, MakeError> = Vec<(
+    &'static str,
+    CompositeMakeServiceEntry<Target, ReqBody, ResBody, Error, MakeError>,
+)>;
+
+/// Services for each host pattern, with the order to try their base paths.
+type VirtualHosts<ReqBody, ResBody, Error> = Vec<(
+    &'static str,
+    CompositeServiceVec<ReqBody, ResBody, Error>,
+    Vec<usize>,
+)>;
+
 /// Service which can be composited with other services as part of a CompositeMakeService
 ///
 /// Consists of a base path for requests which should be handled by this service, and a boxed
@@ -127,7 +953,16 @@ pub type CompositeMakeServiceEntry<Target, ReqBody, ResBody, Error, MakeError> =
 ///
 /// The `Service` returned by calling `make_service()` will pass an incoming
 /// request to the first `Service` in the list for which the associated
-/// base path is a prefix of the request path.
+/// base path is a prefix of the request path. Base paths can be restricted
+/// to a set of methods with `allow_methods`, in which case requests with
+/// other methods get a `405 Method Not Allowed` response, and can be removed
+/// from the request path with `strip_base_path`. Requests which match no
+/// base path get the `NotFound` response, or are passed to the fallback set
+/// with `with_fallback`, and with `with_fallthrough`, requests to which a
+/// service responds `404 Not Found` are passed to the next matching base
+/// path. Services added with `push_for_host` only handle
+/// requests for hosts matching their pattern, and base paths can be matched
+/// with a glob or regular expression set with `match_pattern`.
 ///
 /// Example Usage
 /// =============
@@ -139,15 +974,27 @@ pub type CompositeMakeServiceEntry<Target, ReqBody, ResBody, Error, MakeError> =
 /// let mut composite_make_service = CompositeMakeService::new();
 /// composite_make_service.push(("/base/path/1", my_make_service1));
 /// composite_make_service.push(("/base/path/2", my_make_service2));
+/// composite_make_service.allow_methods("/base/path/2", [Method::GET, Method::HEAD]);
+/// composite_make_service.strip_base_path("/base/path/2");
+///
+/// // fail at startup if any base path is shadowed by an earlier one
+/// composite_make_service.check()?;
 ///
 /// // use as you would any `MakeService` instance
 /// ```
 #[derive(Default)]
-pub struct CompositeMakeService<Target, ReqBody, ResBody, Error, MakeError>(
-    CompositeMakeServiceVec<Target, ReqBody, ResBody, Error, MakeError>,
-)
+pub struct CompositeMakeService<Target, ReqBody, ResBody, Error, MakeError>
 where
-    ResBody: NotFound<ResBody>;
+    ResBody: NotFound<ResBody>,
+{
+    services: CompositeMakeServiceVec<Target, ReqBody, ResBody, Error, MakeError>,
+    routing: Routing,
+    fallback: Option<Fallback<ReqBody, ResBody, Error>>,
+    /// Make services added for a host pattern, routed before `services`.
+    hosts: HostMakeServiceVec<Target, ReqBody, ResBody, Error, MakeError>,
+    /// Set if requests fall through to later routes on `404 Not Found`.
+    fallthrough: Option<FallthroughFns<ReqBody, ResBody, Error>>,
+}
 
 impl<Target, ReqBody, ResBody, Error, MakeError>
     CompositeMakeService<Target, ReqBody, ResBody, Error, MakeError>
@@ -156,7 +1003,215 @@ where
 {
     /// create an empty `CompositeMakeService`
     pub fn new() -> Self {
-        CompositeMakeService(Vec::new())
+        CompositeMakeService {
+            services: Vec::new(),
+            routing: Routing::default(),
+            fallback: None,
+            hosts: Vec::new(),
+            fallthrough: None,
+        }
+    }
+
+    /// Match base paths against request paths in the given mode, rather than
+    /// the first base path which is a prefix of the request path.
+    pub fn with_match_mode(mut self, mode: MatchMode) -> Self {
+        self.routing.mode = mode;
+        self
+    }
+
+    /// Pass requests which match no base path to the given function, rather
+    /// than responding with `NotFound::not_found()`. This can serve a problem
+    /// document, a redirect or the index page of a single-page application.
+    pub fn with_fallback<F, Fut>(mut self, fallback: F) -> Self
+    where
+        F: Fn(Request<ReqBody>) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<Response<ResBody>, Error>> + Send + 'static,
+    {
+        self.fallback = Some(Arc::new(move |req| Box::pin(fallback(req))));
+        self
+    }
+
+    /// When the service for a request responds `404 Not Found`, pass the
+    /// request to the next route which matches it instead, and so on until
+    /// one responds with anything else. This allows a handler to override
+    /// some operations of a generated API by being mounted in front of it at
+    /// the same base path.
+    ///
+    /// Each route is passed a copy of the request, so the request body must
+    /// be `Clone`, such as a buffered `Bytes` body.
+    pub fn with_fallthrough(mut self) -> Self
+    where
+        ReqBody: Clone + Send + 'static,
+        ResBody: 'static,
+        Error: 'static,
+    {
+        self.fallthrough = Some(FallthroughFns {
+            clone_request: clone_request::<ReqBody>,
+            defer: defer::<ReqBody, ResBody, Error>,
+        });
+        self
+    }
+
+    /// Register the OpenAPI document describing the service at the base path,
+    /// for inclusion in the merged document.
+    ///
+    /// If the base path is rewritten with `strip_base_path` or
+    /// `replace_base_path`, the document's paths are taken to be relative to
+    /// the replacement, and are re-prefixed by the base path when merged.
+    /// Otherwise, they are taken to be the full request paths.
+    #[cfg(feature = "serdejson")]
+    pub fn register_openapi(&mut self, base_path: &'static str, document: serde_json::Value) {
+        let mut documents = self
+            .routing
+            .documents
+            .write()
+            .unwrap_or_else(PoisonError::into_inner);
+        match documents.iter_mut().find(|(path, _)| *path == base_path) {
+            Some(entry) => entry.1 = document,
+            None => documents.push((base_path, document)),
+        }
+    }
+
+    /// OpenAPI document describing the whole composite, merging the paths,
+    /// components and tags of the registered documents.
+    ///
+    /// Fails if two documents describe the same path, or components with the
+    /// same name differently.
+    #[cfg(feature = "serdejson")]
+    pub fn openapi(&self) -> Result<serde_json::Value, ApiError> {
+        merge_documents(&self.routing)
+    }
+
+    /// Serve the merged OpenAPI document at the route, such as
+    /// `/openapi.json`. Documents registered later are included.
+    #[cfg(feature = "serdejson")]
+    pub fn serve_openapi(&mut self, route: &'static str)
+    where
+        Target: Send + 'static,
+        ReqBody: 'static,
+        ResBody: From<String> + Send + 'static,
+        Error: Send + 'static,
+        MakeError: Send + 'static,
+    {
+        let make_service: MakeOpenApiService<ResBody, Error, MakeError> =
+            MakeOpenApiService(self.routing.clone(), PhantomData);
+        self.services.push((route, Box::new(make_service)));
+    }
+
+    /// Record the outcome of each request, labelled with the base path it
+    /// was routed to, in the sink.
+    pub fn with_metrics_sink<S: MetricsSink + 'static>(mut self, sink: S) -> Self {
+        self.routing.metrics = Metrics(Some(Arc::new(sink)));
+        self
+    }
+
+    /// Pass requests which match no base path to the given service, rather
+    /// than responding with `NotFound::not_found()`.
+    pub fn with_fallback_service<S>(self, service: S) -> Self
+    where
+        S: Service<Request<ReqBody>, Response = Response<ResBody>, Error = Error>
+            + Send
+            + Sync
+            + 'static,
+        S::Future: Send + 'static,
+    {
+        self.with_fallback(move |req| service.call(req))
+    }
+
+    /// Add a `MakeService` for the base path, wrapped in middleware for
+    /// requests to that base path only, such as authentication or rate
+    /// limiting which other base paths don't need.
+    pub fn push_with_layers<M, L>(&mut self, base_path: &'static str, make_service: M, layers: L)
+    where
+        L: Layer<M>,
+        L::Output:
+            CompositedMakeService<Target, ReqBody, ResBody, Error, MakeError> + Send + 'static,
+    {
+        self.services
+            .push((base_path, Box::new(layers.layer(make_service))));
+    }
+
+    /// Add a service for requests to hosts matching the pattern, which is
+    /// either a host name, such as `api.example.com`, `*.` followed by a
+    /// domain for any of its subdomains, or `*` for any host.
+    ///
+    /// Requests are routed by host first: a request for a host matching the
+    /// pattern of an earlier call is routed to the base paths added for that
+    /// pattern only, and other requests to those in the `Vec`.
+    pub fn push_for_host(
+        &mut self,
+        host_pattern: &'static str,
+        entry: CompositeMakeServiceEntry<Target, ReqBody, ResBody, Error, MakeError>,
+    ) {
+        self.hosts.push((host_pattern, entry));
+    }
+
+    /// Match requests for the base path with the pattern, rather than
+    /// requiring the base path to be a prefix of the request path. The base
+    /// path still identifies the entry, and is recorded in `MatchedBasePath`,
+    /// but isn't stripped from the request path by `strip_base_path`.
+    ///
+    /// Calling this again for the same base path replaces its pattern.
+    pub fn match_pattern(&mut self, base_path: &'static str, pattern: RoutePattern) {
+        self.routing.set_pattern(base_path, pattern);
+    }
+
+    /// Only pass requests for the base path with one of the given methods to
+    /// its service, responding to others with `405 Method Not Allowed`.
+    ///
+    /// Calling this again for the same base path replaces its methods.
+    pub fn allow_methods<I>(&mut self, base_path: &'static str, methods: I)
+    where
+        I: IntoIterator<Item = Method>,
+    {
+        self.routing
+            .set_methods(base_path, methods.into_iter().collect());
+    }
+
+    /// Remove the base path from the start of the request path before
+    /// passing requests to its service, so that a service expecting paths
+    /// relative to the root can be mounted under the base path.
+    ///
+    /// The `MatchedBasePath` request extension still records the base path.
+    pub fn strip_base_path(&mut self, base_path: &'static str) {
+        self.replace_base_path(base_path, "");
+    }
+
+    /// Replace the base path at the start of the request path with the given
+    /// prefix before passing requests to its service, as when mounting
+    /// `/api` under `/v1/pets`.
+    ///
+    /// Calling this, or `strip_base_path`, again for the same base path
+    /// replaces its prefix.
+    pub fn replace_base_path(&mut self, base_path: &'static str, replacement: &'static str) {
+        self.routing.set_replacement(base_path, replacement);
+    }
+
+    /// Base paths which can never be matched, because an earlier base path
+    /// duplicates them, or, when matching the first prefix, is a prefix of
+    /// them. With fallthrough, later base paths can always be matched.
+    pub fn conflicts(&self) -> Vec<RouteConflict> {
+        if self.fallthrough.is_some() {
+            return Vec::new();
+        }
+        let mut conflicts = self
+            .routing
+            .conflicts(self.services.iter().map(|&(base_path, _)| base_path));
+        let hosts = self
+            .hosts
+            .iter()
+            .map(|(host, (base_path, _))| (*host, *base_path))
+            .collect();
+        for (_, base_paths) in group_by_host(hosts) {
+            conflicts.extend(self.routing.conflicts(base_paths));
+        }
+        conflicts
+    }
+
+    /// Fail if any base paths can never be matched, so that misrouting can
+    /// be caught at startup.
+    pub fn check(&self) -> Result<(), ApiError> {
+        check_conflicts(self.conflicts())
     }
 }
 
@@ -173,15 +1228,64 @@ where
     type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
 
     fn call(&self, target: Option<SocketAddr>) -> Self::Future {
-        let mut services = Vec::with_capacity(self.0.len());
-        for (path, service) in &self.0 {
+        let routing = self.routing.clone();
+        let fallback = self.fallback.clone();
+        let fallthrough_fns = self.fallthrough;
+        let mut services = Vec::with_capacity(self.services.len());
+        for (path, service) in &self.services {
             let path: &'static str = path;
             services.push(service.call(target).map_ok(move |s| (path, s)));
         }
-        Box::pin(futures::future::join_all(services).map(|results| {
+        let mut host_services = Vec::with_capacity(self.hosts.len());
+        for (host, (path, service)) in &self.hosts {
+            let (host, path): (&'static str, &'static str) = (host, path);
+            host_services.push(service.call(target).map_ok(move |s| (host, (path, s))));
+        }
+        let services = futures::future::join(
+            futures::future::join_all(services),
+            futures::future::join_all(host_services),
+        );
+        Box::pin(services.map(move |(results, host_results)| {
             let services: Result<Vec<_>, MakeError> = results.into_iter().collect();
+            let host_services: Result<Vec<_>, MakeError> = host_results.into_iter().collect();
+
+            let mut services = services?;
+            let mut host_groups = group_by_host(host_services?);
+            let mut fallthrough = None;
+            if let Some(fns) = fallthrough_fns {
+                let shared;
+                (services, shared) = share_services(services);
+                let hosts = host_groups
+                    .iter_mut()
+                    .map(|(_, group)| {
+                        let shared;
+                        (*group, shared) = share_services(std::mem::take(group));
+                        shared
+                    })
+                    .collect();
+                fallthrough = Some(Fallthrough {
+                    fns,
+                    services: shared,
+                    hosts,
+                });
+            }
 
-            Ok(CompositeService(services?))
+            let order = routing.order(&base_paths(&services));
+            let hosts = host_groups
+                .into_iter()
+                .map(|(host, services)| {
+                    let order = routing.order(&base_paths(&services));
+                    (host, services, order)
+                })
+                .collect();
+            Ok(CompositeService {
+                services,
+                routing,
+                order,
+                fallback,
+                hosts,
+                fallthrough,
+            })
         }))
     }
 }
@@ -193,7 +1297,11 @@ where
 {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> Result<(), fmt::Error> {
         // Get vector of base paths
-        let str_vec: Vec<&'static str> = self.0.iter().map(|&(base_path, _)| base_path).collect();
+        let str_vec: Vec<&'static str> = self
+            .services
+            .iter()
+            .map(|&(base_path, _)| base_path)
+            .collect();
         write!(
             f,
             "CompositeMakeService accepting base paths: {:?}",
@@ -210,7 +1318,7 @@ where
     type Target = CompositeMakeServiceVec<Target, ReqBody, ResBody, Error, MakeError>;
 
     fn deref(&self) -> &Self::Target {
-        &self.0
+        &self.services
     }
 }
 
@@ -220,15 +1328,141 @@ where
     ResBody: NotFound<ResBody>,
 {
     fn deref_mut(&mut self) -> &mut Self::Target {
-        &mut self.0
+        &mut self.services
     }
 }
 
 /// Wraps a vector of pairs, each consisting of a base path as a `&'static str`
-/// and a `Service` instance.
-pub struct CompositeService<ReqBody, ResBody, Error>(CompositeServiceVec<ReqBody, ResBody, Error>)
+/// and a `Service` instance. Implements `Deref<Vec>` so the base paths can be
+/// inspected, but its routes are fixed when it is made: change those of the
+/// `CompositeMakeService`, or use a `SharedCompositeService` to change them
+/// while serving.
+pub struct CompositeService<ReqBody, ResBody, Error>
 where
-    ResBody: NotFound<ResBody>;
+    ResBody: NotFound<ResBody>,
+{
+    services: CompositeServiceVec<ReqBody, ResBody, Error>,
+    routing: Routing,
+    /// Order in which to try `services`.
+    order: Vec<usize>,
+    fallback: Option<Fallback<ReqBody, ResBody, Error>>,
+    /// Services for each host pattern, routed before `services`.
+    hosts: VirtualHosts<ReqBody, ResBody, Error>,
+    /// Set if requests fall through to later routes on `404 Not Found`.
+    fallthrough: Option<Fallthrough<ReqBody, ResBody, Error>>,
+}
+
+impl<ReqBody, ResBody, Error> CompositeService<ReqBody, ResBody, Error>
+where
+    ResBody: NotFound<ResBody>,
+{
+    /// Base paths which can never be matched, because an earlier base path
+    /// duplicates them, or, when matching the first prefix, is a prefix of
+    /// them. With fallthrough, later base paths can always be matched.
+    pub fn conflicts(&self) -> Vec<RouteConflict> {
+        if self.fallthrough.is_some() {
+            return Vec::new();
+        }
+        let mut conflicts = self
+            .routing
+            .conflicts(self.services.iter().map(|&(base_path, _)| base_path));
+        for (_, services, _) in &self.hosts {
+            conflicts.extend(self.routing.conflicts(base_paths(services)));
+        }
+        conflicts
+    }
+
+    /// Fail if any base paths can never be matched, so that misrouting can
+    /// be caught at startup.
+    pub fn check(&self) -> Result<(), ApiError> {
+        check_conflicts(self.conflicts())
+    }
+}
+
+impl<ReqBody, ResBody, Error> CompositeService<ReqBody, ResBody, Error>
+where
+    Error: Send + 'static,
+    ResBody: NotFound<ResBody> + Send + 'static,
+{
+    /// Pass the request to the service for its route, returning the base
+    /// path of the route.
+    fn route(
+        &self,
+        mut req: Request<ReqBody>,
+    ) -> (
+        Option<&'static str>,
+        BoxFuture<'static, Result<Response<ResBody>, Error>>,
+    ) {
+        let (group, (services, order)) = request_host(&req)
+            .and_then(|host| {
+                self.hosts
+                    .iter()
+                    .position(|(pattern, _, _)| host_matches(pattern, host))
+            })
+            .map_or((None, (&self.services, &self.order)), |group| {
+                let (_, services, order) = &self.hosts[group];
+                (Some(group), (services, order))
+            });
+        let path = req.uri().path();
+        let mut matches = order
+            .iter()
+            .filter_map(|&i| self.routing.matches(services[i].0, path).map(|c| (i, c)));
+        let Some((index, captures)) = matches.next() else {
+            if let Some(fallback) = &self.fallback {
+                return (None, fallback(req));
+            }
+            return (None, Box::pin(futures::future::ok(ResBody::not_found())));
+        };
+        let later: Vec<_> = match &self.fallthrough {
+            Some(_) => matches.collect(),
+            None => Vec::new(),
+        };
+
+        let (base_path, ref service) = services[index];
+        if let Some(allowed) = self.routing.allowed_methods(base_path) {
+            if !allowed.contains(req.method()) {
+                return (
+                    Some(base_path),
+                    Box::pin(futures::future::ok(method_not_allowed(allowed))),
+                );
+            }
+        }
+
+        // Prepare the requests to fall through to before this one is changed.
+        let mut fallthrough = Vec::new();
+        if let (Some(state), false) = (&self.fallthrough, later.is_empty()) {
+            let shared = group.map_or(&state.services, |group| &state.hosts[group]);
+            for (i, captures) in later {
+                let base_path = services[i].0;
+                let allowed = self.routing.allowed_methods(base_path);
+                if allowed.is_some_and(|allowed| !allowed.contains(req.method())) {
+                    continue;
+                }
+                let mut req = (state.fns.clone_request)(&req);
+                self.routing.prepare(&mut req, base_path, captures);
+                fallthrough.push((state.fns.defer)(req, shared[i].clone()));
+            }
+        }
+
+        self.routing.prepare(&mut req, base_path, captures);
+        let future = service.call(req);
+        if fallthrough.is_empty() {
+            return (Some(base_path), future);
+        }
+        let future = async move {
+            let mut result = future.await;
+            for call in fallthrough {
+                match &result {
+                    Ok(response) if response.status() == StatusCode::NOT_FOUND => {}
+                    _ => break,
+                }
+                result = call().await;
+            }
+            result
+        };
+        (Some(base_path), Box::pin(future))
+    }
+}
 
 impl<ReqBody, ResBody, Error> Service<Request<ReqBody>>
     for CompositeService<ReqBody, ResBody, Error>
@@ -241,13 +1475,21 @@ where
     type Future = BoxFuture<'static, Result<Response<ResBody>, Error>>;
 
     fn call(&self, req: Request<ReqBody>) -> Self::Future {
-        for &(base_path, ref service) in &self.0 {
-            if req.uri().path().starts_with(base_path) {
-                return service.call(req);
-            }
-        }
-
-        Box::pin(futures::future::ok(ResBody::not_found()))
+        let Some(sink) = self.routing.metrics.0.clone() else {
+            return self.route(req).1;
+        };
+        let method = req.method().clone();
+        let start = Instant::now();
+        let (base_path, future) = self.route(req);
+        Box::pin(future.map(move |result| {
+            sink.record(RouteMetrics {
+                base_path,
+                method,
+                status: result.as_ref().ok().map(Response::status),
+                latency: start.elapsed(),
+            });
+            result
+        }))
     }
 }
 
@@ -257,7 +1499,11 @@ where
 {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> Result<(), fmt::Error> {
         // Get vector of base paths
-        let str_vec: Vec<&'static str> = self.0.iter().map(|&(base_path, _)| base_path).collect();
+        let str_vec: Vec<&'static str> = self
+            .services
+            .iter()
+            .map(|&(base_path, _)| base_path)
+            .collect();
         write!(f, "CompositeService accepting base paths: {:?}", str_vec,)
     }
 }
@@ -269,16 +1515,878 @@ where
 {
     type Target = CompositeServiceVec<ReqBody, ResBody, Error>;
     fn deref(&self) -> &Self::Target {
-        &self.0
+        &self.services
+    }
+}
+
+type SharedRoutes<ReqBody, ResBody, Error> = Vec<(
+    &'static str,
+    Arc<dyn CompositedService<ReqBody, ResBody, Error> + Send + Sync>,
+)>;
+
+/// Service mounted in a `SharedCompositeService`, as a `Service` so that it
+/// can be wrapped in middleware such as `SlowStartService`.
+struct SharedRoute<ReqBody, ResBody, Error>(
+    Arc<dyn CompositedService<ReqBody, ResBody, Error> + Send + Sync>,
+);
+
+impl<ReqBody, ResBody, Error> Service<Request<ReqBody>> for SharedRoute<ReqBody, ResBody, Error> {
+    type Response = Response<ResBody>;
+    type Error = Error;
+    type Future = BoxFuture<'static, Result<Response<ResBody>, Error>>;
+
+    fn call(&self, req: Request<ReqBody>) -> Self::Future {
+        self.0.call(req)
     }
 }
 
-impl<ReqBody, ResBody, Error> DerefMut for CompositeService<ReqBody, ResBody, Error>
+impl<ReqBody, ResBody, Error> fmt::Debug for SharedRoute<ReqBody, ResBody, Error> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> Result<(), fmt::Error> {
+        f.write_str("SharedRoute")
+    }
+}
+
+/// Route table of a `SharedCompositeService`.
+struct SharedTable<ReqBody, ResBody, Error> {
+    routes: SharedRoutes<ReqBody, ResBody, Error>,
+    routing: Routing,
+}
+
+/// Composite service whose route table can be changed while it is serving.
+///
+/// Unlike `CompositeService`, which is made for each connection from a fixed
+/// list of `MakeService`s, this holds the services themselves behind a lock,
+/// and every clone shares the same route table. It is its own `MakeService`,
+/// handing each connection a clone, so services mounted or unmounted at
+/// runtime take effect for new and existing connections alike.
+///
+/// Requests are routed as by a `CompositeService`: base paths are matched in
+/// the `MatchMode` set with `with_match_mode`, or by a pattern set with
+/// `match_pattern`, and can be restricted to a set of methods with
+/// `allow_methods` and removed from the request path with `strip_base_path`.
+/// These settings are kept by base path, so apply to any service mounted
+/// there later.
+pub struct SharedCompositeService<ReqBody, ResBody, Error>(
+    Arc<RwLock<SharedTable<ReqBody, ResBody, Error>>>,
+);
+
+impl<ReqBody, ResBody, Error> SharedCompositeService<ReqBody, ResBody, Error> {
+    /// Create a composite with no services mounted.
+    pub fn new() -> Self {
+        SharedCompositeService(Arc::new(RwLock::new(SharedTable {
+            routes: Vec::new(),
+            routing: Routing::default(),
+        })))
+    }
+
+    fn read(&self) -> RwLockReadGuard<'_, SharedTable<ReqBody, ResBody, Error>> {
+        self.0.read().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn write(&self) -> RwLockWriteGuard<'_, SharedTable<ReqBody, ResBody, Error>> {
+        self.0.write().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Match base paths against request paths in the given mode, rather than
+    /// the first base path which is a prefix of the request path.
+    pub fn with_match_mode(self, mode: MatchMode) -> Self {
+        self.write().routing.mode = mode;
+        self
+    }
+
+    /// Match requests for the base path with the pattern, as with
+    /// `CompositeMakeService::match_pattern`.
+    pub fn match_pattern(&self, base_path: &'static str, pattern: RoutePattern) {
+        self.write().routing.set_pattern(base_path, pattern);
+    }
+
+    /// Only pass requests for the base path with one of the given methods to
+    /// its service, responding to others with `405 Method Not Allowed`.
+    pub fn allow_methods<I>(&self, base_path: &'static str, methods: I)
+    where
+        I: IntoIterator<Item = Method>,
+    {
+        self.write()
+            .routing
+            .set_methods(base_path, methods.into_iter().collect());
+    }
+
+    /// Remove the base path from the start of the request path before
+    /// passing requests to its service.
+    pub fn strip_base_path(&self, base_path: &'static str) {
+        self.replace_base_path(base_path, "");
+    }
+
+    /// Replace the base path at the start of the request path with the given
+    /// prefix before passing requests to its service.
+    pub fn replace_base_path(&self, base_path: &'static str, replacement: &'static str) {
+        self.write().routing.set_replacement(base_path, replacement);
+    }
+
+    /// Mount the service under the base path, replacing and returning any
+    /// service already mounted there.
+    pub fn mount<S>(
+        &self,
+        base_path: &'static str,
+        service: S,
+    ) -> Option<Arc<dyn CompositedService<ReqBody, ResBody, Error> + Send + Sync>>
+    where
+        S: CompositedService<ReqBody, ResBody, Error> + Send + Sync + 'static,
+    {
+        let mut table = self.write();
+        let service = Arc::new(service);
+        match table.routes.iter_mut().find(|(path, _)| *path == base_path) {
+            Some(entry) => Some(std::mem::replace(&mut entry.1, service)),
+            None => {
+                table.routes.push((base_path, service));
+                None
+            }
+        }
+    }
+
+    /// Mount the service under the base path, moving traffic to it from any
+    /// service already mounted there gradually, as configured, rather than
+    /// all at once. The ramp starts now, and the old service is released once
+    /// it is no longer needed.
+    ///
+    /// Returns whether there was a service to move traffic from.
+    pub fn mount_with_slow_start<S>(
+        &self,
+        base_path: &'static str,
+        service: S,
+        slow_start: SlowStart,
+    ) -> bool
+    where
+        S: CompositedService<ReqBody, ResBody, Error> + Send + Sync + 'static,
+        ReqBody: Send + 'static,
+        ResBody: 'static,
+        Error: 'static,
+    {
+        let mut table = self.write();
+        let new: Arc<dyn CompositedService<ReqBody, ResBody, Error> + Send + Sync> =
+            Arc::new(service);
+        match table.routes.iter_mut().find(|(path, _)| *path == base_path) {
+            Some(entry) => {
+                let old = SharedRoute(entry.1.clone());
+                entry.1 = Arc::new(SlowStartService::new(old, SharedRoute(new), slow_start));
+                true
+            }
+            None => {
+                table.routes.push((base_path, new));
+                false
+            }
+        }
+    }
+
+    /// Unmount the service under the base path, returning it if there was
+    /// one. Requests it is already handling are unaffected.
+    pub fn unmount(
+        &self,
+        base_path: &str,
+    ) -> Option<Arc<dyn CompositedService<ReqBody, ResBody, Error> + Send + Sync>> {
+        let mut table = self.write();
+        let index = table
+            .routes
+            .iter()
+            .position(|(path, _)| *path == base_path)?;
+        Some(table.routes.remove(index).1)
+    }
+
+    /// Base paths with services mounted, in the order they were mounted.
+    pub fn base_paths(&self) -> Vec<&'static str> {
+        base_paths(&self.read().routes)
+    }
+
+    /// Base paths which can never be matched, because an earlier base path
+    /// duplicates them, or, when matching the first prefix, is a prefix of
+    /// them.
+    pub fn conflicts(&self) -> Vec<RouteConflict> {
+        let table = self.read();
+        table.routing.conflicts(base_paths(&table.routes))
+    }
+}
+
+impl<ReqBody, ResBody, Error> Default for SharedCompositeService<ReqBody, ResBody, Error> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<ReqBody, ResBody, Error> Clone for SharedCompositeService<ReqBody, ResBody, Error> {
+    fn clone(&self) -> Self {
+        SharedCompositeService(self.0.clone())
+    }
+}
+
+impl<ReqBody, ResBody, Error> fmt::Debug for SharedCompositeService<ReqBody, ResBody, Error> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> Result<(), fmt::Error> {
+        write!(
+            f,
+            "SharedCompositeService accepting base paths: {:?}",
+            self.base_paths(),
+        )
+    }
+}
+
+impl<ReqBody, ResBody, Error> Service<Option<SocketAddr>>
+    for SharedCompositeService<ReqBody, ResBody, Error>
+{
+    type Error = Error;
+    type Response = Self;
+    type Future = futures::future::Ready<Result<Self, Error>>;
+
+    fn call(&self, _: Option<SocketAddr>) -> Self::Future {
+        futures::future::ok(self.clone())
+    }
+}
+
+impl<ReqBody, ResBody, Error> Service<Request<ReqBody>>
+    for SharedCompositeService<ReqBody, ResBody, Error>
 where
-    ResBody: NotFound<ResBody> + 'static,
-    Error: 'static,
+    Error: Send + 'static,
+    ResBody: NotFound<ResBody> + Send + 'static,
 {
-    fn deref_mut(&mut self) -> &mut Self::Target {
-        &mut self.0
+    type Error = Error;
+    type Response = Response<ResBody>;
+    type Future = BoxFuture<'static, Result<Response<ResBody>, Error>>;
+
+    fn call(&self, mut req: Request<ReqBody>) -> Self::Future {
+        // Don't hold the lock while the service handles the request.
+        let service = {
+            let table = self.read();
+            let routes = &table.routes;
+            let path = req.uri().path();
+            let matched = table
+                .routing
+                .order(&base_paths(routes))
+                .into_iter()
+                .find_map(|i| table.routing.matches(routes[i].0, path).map(|c| (i, c)));
+            let Some((index, captures)) = matched else {
+                return Box::pin(futures::future::ok(ResBody::not_found()));
+            };
+            let (base_path, ref service) = routes[index];
+            if let Some(allowed) = table.routing.allowed_methods(base_path) {
+                if !allowed.contains(req.method()) {
+                    return Box::pin(futures::future::ok(method_not_allowed(allowed)));
+                }
+            }
+            table.routing.prepare(&mut req, base_path, captures);
+            service.clone()
+        };
+        service.call(req)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_route_conflicts() {
+        let routes = [
+            "/api/v2",
+            "/api",
+            "/api/v1",
+            "/pet",
+            "/pets/{id}",
+            "/store/{storeId}",
+            "/store/{id}",
+            "/user",
+        ];
+        assert_eq!(
+            route_conflicts(routes),
+            vec![
+                RouteConflict {
+                    route: "/api/v1".to_string(),
+                    shadowed_by: "/api".to_string(),
+                    kind: RouteConflictKind::Prefix,
+                },
+                RouteConflict {
+                    route: "/pets/{id}".to_string(),
+                    shadowed_by: "/pet".to_string(),
+                    kind: RouteConflictKind::Prefix,
+                },
+                RouteConflict {
+                    route: "/store/{id}".to_string(),
+                    shadowed_by: "/store/{storeId}".to_string(),
+                    kind: RouteConflictKind::Duplicate,
+                },
+            ]
+        );
+
+        let error = check_conflicts(route_conflicts(routes)).unwrap_err();
+        assert!(error
+            .0
+            .contains("route \"/api/v1\" is shadowed by earlier route \"/api\""));
+        assert!(check_conflicts(route_conflicts(["/api/v2", "/api", "/user"])).is_ok());
+    }
+
+    #[derive(Clone)]
+    struct OkService;
+
+    impl Service<Option<SocketAddr>> for OkService {
+        type Response = OkService;
+        type Error = ();
+        type Future = futures::future::Ready<Result<OkService, ()>>;
+
+        fn call(&self, _: Option<SocketAddr>) -> Self::Future {
+            futures::future::ok(OkService)
+        }
+    }
+
+    impl Service<Request<()>> for OkService {
+        type Response = Response<String>;
+        type Error = ();
+        type Future = futures::future::Ready<Result<Response<String>, ()>>;
+
+        fn call(&self, req: Request<()>) -> Self::Future {
+            let matched = req.extensions().get::<MatchedBasePath>().unwrap();
+            futures::future::ok(Response::new(matched.0.to_string()))
+        }
+    }
+
+    #[tokio::test]
+    async fn test_allow_methods() {
+        let mut make_service = CompositeMakeService::new();
+        make_service.push(("/pets", Box::new(OkService)));
+        make_service.push(("/store", Box::new(OkService)));
+        make_service.allow_methods("/pets", [Method::GET, Method::POST]);
+        let service = Service::call(&make_service, None).await.unwrap();
+
+        let call = |method: Method, path: &str| {
+            let request = Request::builder().method(method).uri(path).body(());
+            Service::call(&service, request.unwrap())
+        };
+        assert_eq!(call(Method::POST, "/pets").await.unwrap().status(), 200);
+        assert_eq!(call(Method::DELETE, "/store").await.unwrap().status(), 200);
+        assert_eq!(call(Method::GET, "/user").await.unwrap().status(), 404);
+
+        let response = call(Method::DELETE, "/pets/1").await.unwrap();
+        assert_eq!(response.status(), StatusCode::METHOD_NOT_ALLOWED);
+        assert_eq!(response.headers()[ALLOW], "GET, POST");
+    }
+
+    #[tokio::test]
+    async fn test_match_mode() {
+        let routes = |mode| {
+            let mut make_service = CompositeMakeService::new().with_match_mode(mode);
+            make_service.push(("/api", Box::new(OkService)));
+            make_service.push(("/api/v2", Box::new(OkService)));
+            make_service
+        };
+        let matched = |make_service: CompositeMakeService<_, _, _, _, _>, path: &'static str| async move {
+            let service = Service::call(&make_service, None).await.unwrap();
+            let request = Request::get(path).body(()).unwrap();
+            let response = Service::call(&service, request).await.unwrap();
+            (response.status() == 200).then(|| response.into_body())
+        };
+
+        assert!(!routes(MatchMode::PrefixFirstMatch).conflicts().is_empty());
+        assert_eq!(
+            matched(routes(MatchMode::PrefixFirstMatch), "/api/v2/pets").await,
+            Some("/api".to_string())
+        );
+
+        assert!(routes(MatchMode::LongestPrefix).check().is_ok());
+        assert_eq!(
+            matched(routes(MatchMode::LongestPrefix), "/api/v2/pets").await,
+            Some("/api/v2".to_string())
+        );
+        assert_eq!(
+            matched(routes(MatchMode::LongestPrefix), "/api/v1/pets").await,
+            Some("/api".to_string())
+        );
+
+        assert!(routes(MatchMode::Exact).check().is_ok());
+        assert_eq!(
+            matched(routes(MatchMode::Exact), "/api/v2").await,
+            Some("/api/v2".to_string())
+        );
+        assert_eq!(
+            matched(routes(MatchMode::Exact), "/api/v2/pets").await,
+            None
+        );
+    }
+
+    #[test]
+    fn test_rewrite_uri() {
+        let rewrite = |uri: &str, base_path, replacement| {
+            rewrite_uri(&uri.parse().unwrap(), base_path, replacement)
+                .unwrap()
+                .to_string()
+        };
+        assert_eq!(
+            rewrite("/v1/foo/pets?limit=1", "/v1/foo", ""),
+            "/pets?limit=1"
+        );
+        assert_eq!(rewrite("/v1/foo", "/v1/foo", ""), "/");
+        assert_eq!(rewrite("/v1/foo/", "/v1/foo/", ""), "/");
+        assert_eq!(rewrite("/v1/foo/pets", "/v1/foo", "/api/"), "/api/pets");
+        assert_eq!(rewrite("/v1/foo", "/v1/foo", "/api"), "/api");
+        assert_eq!(rewrite("/v1/foobar", "/v1/foo", "/api"), "/api/bar");
+        assert_eq!(
+            rewrite("http://example.com/v1/foo/pets", "/v1/foo", ""),
+            "http://example.com/pets"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_fallback() {
+        let mut make_service = CompositeMakeService::new().with_fallback(|req: Request<()>| {
+            let location = format!("/app{}", req.uri().path());
+            futures::future::ok(
+                Response::builder()
+                    .status(StatusCode::FOUND)
+                    .header("Location", location)
+                    .body(String::new())
+                    .unwrap(),
+            )
+        });
+        make_service.push(("/api", Box::new(OkService)));
+        let service = Service::call(&make_service, None).await.unwrap();
+
+        let request = Request::get("/api/pets").body(()).unwrap();
+        let response = Service::call(&service, request).await.unwrap();
+        assert_eq!(response.status(), 200);
+
+        let request = Request::get("/index.html").body(()).unwrap();
+        let response = Service::call(&service, request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::FOUND);
+        assert_eq!(response.headers()["Location"], "/app/index.html");
+    }
+
+    #[test]
+    fn test_host_matches() {
+        assert!(host_matches("*", "example.com"));
+        assert!(host_matches("API.example.com", "api.example.com"));
+        assert!(host_matches("*.example.com", "api.Example.com"));
+        assert!(!host_matches("*.example.com", "example.com"));
+        assert!(!host_matches("*.example.com", "api.badexample.com"));
+
+        let host = |host| {
+            let request = Request::get("/").header(HOST, host).body(()).unwrap();
+            request_host(&request).map(str::to_string)
+        };
+        assert_eq!(host("example.com:8080").as_deref(), Some("example.com"));
+        assert_eq!(host("[::1]:8080").as_deref(), Some("::1"));
+    }
+
+    #[tokio::test]
+    async fn test_push_for_host() {
+        let mut make_service = CompositeMakeService::new();
+        make_service.push(("/", Box::new(OkService)));
+        make_service.push_for_host("pets.example.com", ("/pets", Box::new(OkService)));
+        make_service.push_for_host("*.example.com", ("/other", Box::new(OkService)));
+        make_service.push_for_host("pets.example.com", ("/store", Box::new(OkService)));
+        assert!(make_service.check().is_ok());
+        let service = Service::call(&make_service, None).await.unwrap();
+
+        let matched = |host: &str, path: &str| {
+            let request = Request::get(path).header(HOST, host).body(()).unwrap();
+            Service::call(&service, request).map(|response| {
+                let response = response.unwrap();
+                (response.status() == 200).then(|| response.into_body())
+            })
+        };
+        assert_eq!(
+            matched("pets.example.com", "/store/1").await.as_deref(),
+            Some("/store")
+        );
+        assert_eq!(matched("pets.example.com", "/user").await, None);
+        assert_eq!(
+            matched("www.example.com:80", "/other").await.as_deref(),
+            Some("/other")
+        );
+        assert_eq!(matched("localhost", "/pets").await.as_deref(), Some("/"));
+    }
+
+    #[tokio::test]
+    async fn test_shared_composite() {
+        let shared = SharedCompositeService::new();
+        shared.mount("/pets", OkService);
+        let service = Service::call(&shared, None).await.unwrap();
+
+        let matched = |path: &str| {
+            let request = Request::get(path).body(()).unwrap();
+            Service::call(&service, request).map(|response| {
+                let response = response.unwrap();
+                (response.status() == 200).then(|| response.into_body())
+            })
+        };
+        assert_eq!(matched("/pets/1").await.as_deref(), Some("/pets"));
+        assert_eq!(matched("/store").await, None);
+
+        // Changes to the route table apply to existing connections.
+        shared.mount("/store", OkService);
+        assert!(shared.mount("/pets", OkService).is_some());
+        assert_eq!(matched("/store").await.as_deref(), Some("/store"));
+        assert!(shared.unmount("/pets").is_some());
+        assert_eq!(matched("/pets/1").await, None);
+        assert_eq!(shared.base_paths(), ["/store"]);
+    }
+
+    #[tokio::test]
+    async fn test_shared_composite_slow_start() {
+        /// Service which holds a reference to its marker until released.
+        struct OldService(#[allow(dead_code)] Arc<()>);
+
+        impl Service<Request<()>> for OldService {
+            type Response = Response<String>;
+            type Error = ();
+            type Future = futures::future::Ready<Result<Response<String>, ()>>;
+
+            fn call(&self, _: Request<()>) -> Self::Future {
+                futures::future::ok(Response::new("old".to_string()))
+            }
+        }
+
+        let shared = SharedCompositeService::new();
+        let old = Arc::new(());
+        assert!(!shared.mount_with_slow_start(
+            "/pets",
+            OldService(old.clone()),
+            SlowStart::new(Duration::from_secs(0))
+        ));
+        let call = || {
+            let request = Request::get("/pets").body(()).unwrap();
+            Service::call(&shared, request).map(|response| response.unwrap().into_body())
+        };
+        assert_eq!(call().await, "old");
+
+        // The ramp starts at the swap, so a long one keeps traffic on the old
+        // service.
+        shared.mount_with_slow_start(
+            "/pets",
+            OkService,
+            SlowStart::new(Duration::from_secs(3600)),
+        );
+        assert_eq!(call().await, "old");
+        assert_eq!(Arc::strong_count(&old), 2);
+
+        // Once the ramp completes, the old service is released.
+        shared.mount("/pets", OldService(old.clone()));
+        assert!(shared.mount_with_slow_start(
+            "/pets",
+            OkService,
+            SlowStart::new(Duration::from_secs(0))
+        ));
+        assert_eq!(Arc::strong_count(&old), 2);
+        assert_eq!(call().await, "/pets");
+        assert_eq!(Arc::strong_count(&old), 1);
+    }
+
+    #[tokio::test]
+    async fn test_shared_composite_routing() {
+        let shared = SharedCompositeService::new().with_match_mode(MatchMode::LongestPrefix);
+        shared.mount("/api", OkService);
+        shared.mount("/api/v2", OkService);
+        shared.allow_methods("/api/v2", [Method::GET]);
+        shared.strip_base_path("/api");
+        let service = Service::call(&shared, None).await.unwrap();
+
+        let call = |method: Method, path: &str| {
+            let request = Request::builder().method(method).uri(path).body(());
+            Service::call(&service, request.unwrap()).map(Result::unwrap)
+        };
+        let response = call(Method::GET, "/api/v2/pets").await;
+        assert_eq!(response.into_body(), "/api/v2");
+        let response = call(Method::POST, "/api/v2/pets").await;
+        assert_eq!(response.status(), StatusCode::METHOD_NOT_ALLOWED);
+        assert_eq!(response.headers()[ALLOW], "GET");
+        assert!(shared.conflicts().is_empty());
+
+        #[derive(Clone)]
+        struct UriService;
+
+        impl Service<Request<()>> for UriService {
+            type Response = Response<String>;
+            type Error = ();
+            type Future = futures::future::Ready<Result<Response<String>, ()>>;
+
+            fn call(&self, req: Request<()>) -> Self::Future {
+                futures::future::ok(Response::new(req.uri().to_string()))
+            }
+        }
+
+        shared.mount("/api", UriService);
+        let response = call(Method::POST, "/api/pets?limit=1").await;
+        assert_eq!(response.into_body(), "/pets?limit=1");
+    }
+
+    #[test]
+    fn test_glob_pattern() {
+        let pattern = RoutePattern::glob("/users/{userId}/*/**");
+        let captures = pattern.captures("/users/1/documents/a/b", false).unwrap();
+        assert_eq!(captures.name("userId"), Some("1"));
+        assert_eq!(captures.get(1), Some("documents"));
+        assert_eq!(captures.get(2), Some("a/b"));
+
+        let pattern = RoutePattern::glob("/users/*/documents");
+        assert!(pattern.captures("/users/1/documents/2", false).is_some());
+        assert!(pattern.captures("/users/1/documents/2", true).is_none());
+        assert!(pattern.captures("/users/1/documentsX", false).is_none());
+        assert!(pattern.captures("/users//documents", false).is_none());
+    }
+
+    #[cfg(feature = "regex")]
+    #[test]
+    fn test_regex_pattern() {
+        let regex = regex::Regex::new(r"^/pets/(?P<petId>\d+)(/photos)?").unwrap();
+        let pattern = RoutePattern::regex(regex);
+        let captures = pattern.captures("/pets/12/photos", false).unwrap();
+        assert_eq!(captures.name("petId"), Some("12"));
+        assert_eq!(captures.get(1), Some("/photos"));
+        assert!(pattern.captures("/pets/12/photos/1", true).is_none());
+        assert!(pattern.captures("/pets/cat", false).is_none());
+    }
+
+    #[tokio::test]
+    async fn test_match_pattern() {
+        #[derive(Clone)]
+        struct CaptureService;
+
+        impl Service<Option<SocketAddr>> for CaptureService {
+            type Response = CaptureService;
+            type Error = ();
+            type Future = futures::future::Ready<Result<CaptureService, ()>>;
+
+            fn call(&self, _: Option<SocketAddr>) -> Self::Future {
+                futures::future::ok(CaptureService)
+            }
+        }
+
+        impl Service<Request<()>> for CaptureService {
+            type Response = Response<String>;
+            type Error = ();
+            type Future = futures::future::Ready<Result<Response<String>, ()>>;
+
+            fn call(&self, req: Request<()>) -> Self::Future {
+                let captures = req.extensions().get::<RouteCaptures>().unwrap();
+                futures::future::ok(Response::new(captures.name("id").unwrap().to_string()))
+            }
+        }
+
+        let mut make_service = CompositeMakeService::new();
+        make_service.push(("/users/{id}/documents", Box::new(CaptureService)));
+        make_service.match_pattern(
+            "/users/{id}/documents",
+            RoutePattern::glob("/users/{id}/documents"),
+        );
+        let service = Service::call(&make_service, None).await.unwrap();
+
+        let request = Request::get("/users/42/documents/1").body(()).unwrap();
+        let response = Service::call(&service, request).await.unwrap();
+        assert_eq!(response.into_body(), "42");
+
+        let request = Request::get("/users/42/photos").body(()).unwrap();
+        let response = Service::call(&service, request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[derive(Clone)]
+    struct MakeTagService<M>(M, &'static str);
+
+    impl<M> Service<Option<SocketAddr>> for MakeTagService<M>
+    where
+        M: Service<Option<SocketAddr>>,
+        M::Future: Send + 'static,
+    {
+        type Response = TagService<M::Response>;
+        type Error = M::Error;
+        type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+        fn call(&self, target: Option<SocketAddr>) -> Self::Future {
+            let tag = self.1;
+            Box::pin(
+                self.0
+                    .call(target)
+                    .map_ok(move |inner| TagService(inner, tag)),
+            )
+        }
+    }
+
+    struct TagService<S>(S, &'static str);
+
+    impl<S> Service<Request<()>> for TagService<S>
+    where
+        S: Service<Request<()>, Response = Response<String>>,
+        S::Future: Send + 'static,
+    {
+        type Response = Response<String>;
+        type Error = S::Error;
+        type Future = BoxFuture<'static, Result<Response<String>, S::Error>>;
+
+        fn call(&self, req: Request<()>) -> Self::Future {
+            let tag = self.1;
+            Box::pin(self.0.call(req).map_ok(move |mut response| {
+                response.body_mut().push_str(tag);
+                response
+            }))
+        }
+    }
+
+    #[tokio::test]
+    async fn test_push_with_layers() {
+        let mut make_service = CompositeMakeService::new();
+        make_service.push_with_layers(
+            "/pets",
+            OkService,
+            layers![|inner| MakeTagService(inner, " outer"), |inner| {
+                MakeTagService(inner, " inner")
+            },],
+        );
+        make_service.push(("/store", Box::new(OkService)));
+        let service = Service::call(&make_service, None).await.unwrap();
+
+        let call = |path: &str| {
+            let request = Request::get(path).body(()).unwrap();
+            Service::call(&service, request).map(|response| response.unwrap().into_body())
+        };
+        // The inner layer sees the response first.
+        assert_eq!(call("/pets").await, "/pets inner outer");
+        assert_eq!(call("/store").await, "/store");
+    }
+
+    #[tokio::test]
+    async fn test_metrics_sink() {
+        let recorded = Arc::new(Mutex::new(Vec::new()));
+        let sink = recorded.clone();
+        let mut make_service = CompositeMakeService::new()
+            .with_metrics_sink(move |metrics| sink.lock().unwrap().push(metrics));
+        make_service.push(("/pets", Box::new(OkService)));
+        let service = Service::call(&make_service, None).await.unwrap();
+
+        for path in ["/pets/1", "/store"] {
+            let request = Request::post(path).body(()).unwrap();
+            Service::call(&service, request).await.unwrap();
+        }
+
+        let recorded = recorded.lock().unwrap();
+        let summary: Vec<_> = recorded
+            .iter()
+            .map(|m| (m.base_path, m.method.clone(), m.status_class()))
+            .collect();
+        assert_eq!(
+            summary,
+            [
+                (Some("/pets"), Method::POST, Some(2)),
+                (None, Method::POST, Some(4)),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_fallthrough() {
+        struct OverrideService;
+
+        impl Service<Option<SocketAddr>> for OverrideService {
+            type Response = OverrideService;
+            type Error = ();
+            type Future = futures::future::Ready<Result<OverrideService, ()>>;
+
+            fn call(&self, _: Option<SocketAddr>) -> Self::Future {
+                futures::future::ok(OverrideService)
+            }
+        }
+
+        impl Service<Request<()>> for OverrideService {
+            type Response = Response<String>;
+            type Error = ();
+            type Future = futures::future::Ready<Result<Response<String>, ()>>;
+
+            fn call(&self, req: Request<()>) -> Self::Future {
+                futures::future::ok(if req.uri().path() == "/pets/special" {
+                    Response::new("override".to_string())
+                } else {
+                    String::not_found()
+                })
+            }
+        }
+
+        let routes = |fallthrough| {
+            let mut make_service = CompositeMakeService::new();
+            if fallthrough {
+                make_service = make_service.with_fallthrough();
+            }
+            make_service.push(("/pets", Box::new(OverrideService)));
+            make_service.push(("/pets", Box::new(OkService)));
+            make_service
+        };
+        let call = |make_service: CompositeMakeService<_, _, _, _, _>, path: &'static str| async move {
+            let service = Service::call(&make_service, None).await.unwrap();
+            let request = Request::get(path).body(()).unwrap();
+            let response = Service::call(&service, request).await.unwrap();
+            (response.status(), response.into_body())
+        };
+
+        assert!(routes(true).check().is_ok());
+        assert!(routes(false).check().is_err());
+        assert_eq!(
+            call(routes(true), "/pets/special").await,
+            (StatusCode::OK, "override".to_string())
+        );
+        assert_eq!(
+            call(routes(true), "/pets/1").await,
+            (StatusCode::OK, "/pets".to_string())
+        );
+        assert_eq!(
+            call(routes(false), "/pets/1").await.0,
+            StatusCode::NOT_FOUND
+        );
+    }
+
+    #[cfg(feature = "serdejson")]
+    #[tokio::test]
+    async fn test_serve_openapi() {
+        let mut make_service = CompositeMakeService::new();
+        make_service.push(("/v1/pets", Box::new(OkService)));
+        make_service.push(("/store", Box::new(OkService)));
+        make_service.strip_base_path("/v1/pets");
+        make_service.serve_openapi("/openapi.json");
+        make_service.register_openapi(
+            "/v1/pets",
+            serde_json::json!({
+                "openapi": "3.0.3",
+                "info": {"title": "Pets", "version": "1"},
+                "paths": {"/{petId}": {"get": {}}},
+                "components": {"schemas": {"Error": {"type": "string"}}},
+                "tags": [{"name": "pets"}],
+            }),
+        );
+        make_service.register_openapi(
+            "/store",
+            serde_json::json!({
+                "openapi": "3.0.3",
+                "info": {"title": "Store", "version": "1"},
+                "paths": {"/store/order": {"post": {}}},
+                "components": {"schemas": {"Error": {"type": "string"}}},
+                "tags": [{"name": "pets"}, {"name": "store"}],
+            }),
+        );
+
+        let service = Service::call(&make_service, None).await.unwrap();
+        let request = Request::get("/openapi.json").body(()).unwrap();
+        let response = Service::call(&service, request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let document: serde_json::Value = serde_json::from_str(response.body()).unwrap();
+        assert_eq!(
+            document,
+            serde_json::json!({
+                "openapi": "3.0.3",
+                "info": {"title": "Pets", "version": "1"},
+                "paths": {
+                    "/v1/pets/{petId}": {"get": {}},
+                    "/store/order": {"post": {}},
+                },
+                "components": {"schemas": {"Error": {"type": "string"}}},
+                "tags": [{"name": "pets"}, {"name": "store"}],
+            })
+        );
+
+        make_service.register_openapi(
+            "/store",
+            serde_json::json!({"components": {"schemas": {"Error": {"type": "object"}}}}),
+        );
+        assert!(make_service.openapi().is_err());
     }
 }
@@ -0,0 +1,382 @@
+//! Compression of message bodies, and middleware compressing responses.
+//!
+//! The codings supported depend on the crate features enabled: **gzip**
+//! enables `gzip` and `deflate`, and **brotli** enables `br`.
+//!
+//! ```ignore
+//! let service = CompressionService::new(inner).with_min_size(256);
+//! ```
+use crate::body::{read_to_limit, PrefixedBody};
+use crate::{AcceptEncoding, ApiError, ContentCoding};
+use futures::future::BoxFuture;
+use http_body_util::{Either, Full};
+use hyper::body::{Body, Bytes};
+use hyper::header::{HeaderValue, CONTENT_ENCODING, CONTENT_LENGTH, CONTENT_RANGE, VARY};
+use hyper::service::Service;
+use hyper::{Method, Request, Response};
+use std::fmt;
+use std::io::{Read, Write};
+
+/// Default maximum size of response bodies buffered for compression.
+const DEFAULT_MAX_SIZE: usize = 8 * 1024 * 1024;
+
+/// Codings supported by this build of the crate, in order of preference.
+pub const SUPPORTED: &[ContentCoding] = &[
+    #[cfg(feature = "brotli")]
+    ContentCoding::Brotli,
+    #[cfg(feature = "gzip")]
+    ContentCoding::Gzip,
+    #[cfg(feature = "gzip")]
+    ContentCoding::Deflate,
+];
+
+/// Body of a response from a compression middleware, which is either passed
+/// through unchanged, or replaced by the compressed or decompressed body.
+pub type CompressionBody<B> = Either<B, Full<Bytes>>;
+
+/// Value of an `Accept-Encoding` header listing the supported codings.
+pub fn accept_encoding() -> HeaderValue {
+    let codings: Vec<_> = SUPPORTED.iter().map(ContentCoding::as_str).collect();
+    HeaderValue::from_str(&codings.join(", ")).unwrap_or(HeaderValue::from_static("identity"))
+}
+
+fn unsupported(coding: ContentCoding) -> ApiError {
+    ApiError(format!("Unsupported content coding: {}", coding))
+}
+
+/// Compress data with the given coding.
+pub fn compress(coding: ContentCoding, data: &[u8]) -> Result<Vec<u8>, ApiError> {
+    let result = match coding {
+        ContentCoding::Identity => Ok(data.to_vec()),
+        #[cfg(feature = "gzip")]
+        ContentCoding::Gzip => {
+            let mut encoder =
+                flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+            encoder.write_all(data).and_then(|_| encoder.finish())
+        }
+        #[cfg(feature = "gzip")]
+        ContentCoding::Deflate => {
+            let mut encoder =
+                flate2::write::ZlibEncoder::new(Vec::new(), flate2::Compression::default());
+            encoder.write_all(data).and_then(|_| encoder.finish())
+        }
+        #[cfg(feature = "brotli")]
+        ContentCoding::Brotli => {
+            let mut encoder = brotli::CompressorWriter::new(Vec::new(), 4096, 5, 22);
+            encoder.write_all(data).map(|_| encoder.into_inner())
+        }
+        coding => return Err(unsupported(coding)),
+    };
+    result.map_err(|e| ApiError(format!("Failed to compress body with {}: {}", coding, e)))
+}
+
+/// Decompress data with the given coding, failing if the decompressed data
+/// would be larger than `max_size` bytes.
+pub fn decompress(
+    coding: ContentCoding,
+    data: &[u8],
+    max_size: usize,
+) -> Result<Vec<u8>, ApiError> {
+    let reader: Box<dyn Read + '_> = match coding {
+        ContentCoding::Identity => Box::new(data),
+        #[cfg(feature = "gzip")]
+        ContentCoding::Gzip => Box::new(flate2::read::GzDecoder::new(data)),
+        #[cfg(feature = "gzip")]
+        ContentCoding::Deflate => Box::new(flate2::read::ZlibDecoder::new(data)),
+        #[cfg(feature = "brotli")]
+        ContentCoding::Brotli => Box::new(brotli::Decompressor::new(data, 4096)),
+        coding => return Err(unsupported(coding)),
+    };
+
+    let mut decompressed = Vec::new();
+    let limit = u64::try_from(max_size)
+        .unwrap_or(u64::MAX)
+        .saturating_add(1);
+    reader
+        .take(limit)
+        .read_to_end(&mut decompressed)
+        .map_err(|e| ApiError(format!("Failed to decompress {} body: {}", coding, e)))?;
+    if decompressed.len() > max_size {
+        return Err(ApiError(format!(
+            "Decompressed body is larger than {} bytes",
+            max_size
+        )));
+    }
+    Ok(decompressed)
+}
+
+/// Middleware which compresses responses with a coding accepted by the
+/// client in its `Accept-Encoding` header.
+///
+/// Responses smaller than the minimum size, larger than the maximum size,
+/// which already have a coding, or which are partial, aren't compressed.
+/// Response bodies are read in full before being compressed.
+#[derive(Clone, Debug)]
+pub struct MakeCompressionService<T> {
+    inner: T,
+    min_size: usize,
+    max_size: usize,
+}
+
+impl<T> MakeCompressionService<T> {
+    /// Create a middleware compressing responses of at least 1kB and at most
+    /// 8MB.
+    pub fn new(inner: T) -> Self {
+        MakeCompressionService {
+            inner,
+            min_size: 1024,
+            max_size: DEFAULT_MAX_SIZE,
+        }
+    }
+
+    /// Only compress responses of at least the given number of bytes.
+    pub fn with_min_size(mut self, min_size: usize) -> Self {
+        self.min_size = min_size;
+        self
+    }
+
+    /// Only compress responses of at most the given number of bytes, which
+    /// is the most that is buffered for each response.
+    pub fn with_max_size(mut self, max_size: usize) -> Self {
+        self.max_size = max_size;
+        self
+    }
+}
+
+impl<Inner, Target> Service<Target> for MakeCompressionService<Inner>
+where
+    Inner: Service<Target>,
+    Inner::Future: Send + 'static,
+{
+    type Error = Inner::Error;
+    type Response = CompressionService<Inner::Response>;
+    type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn call(&self, target: Target) -> Self::Future {
+        let (min_size, max_size) = (self.min_size, self.max_size);
+        let inner = self.inner.call(target);
+        Box::pin(async move {
+            Ok(CompressionService {
+                inner: inner.await?,
+                min_size,
+                max_size,
+            })
+        })
+    }
+}
+
+/// Middleware which compresses responses with a coding accepted by the
+/// client in its `Accept-Encoding` header.
+///
+/// Responses smaller than the minimum size, larger than the maximum size,
+/// which already have a coding, or which are partial, aren't compressed.
+///
+/// Response bodies are read in full before being compressed, and failures to
+/// read them are returned as errors from the inner service. Only up to the
+/// maximum size is read: responses which turn out to be larger are passed on
+/// uncompressed, starting with what has been read.
+#[derive(Clone, Debug)]
+pub struct CompressionService<T> {
+    inner: T,
+    min_size: usize,
+    max_size: usize,
+}
+
+impl<T> CompressionService<T> {
+    /// Create a middleware compressing responses of at least 1kB and at most
+    /// 8MB.
+    pub fn new(inner: T) -> Self {
+        CompressionService {
+            inner,
+            min_size: 1024,
+            max_size: DEFAULT_MAX_SIZE,
+        }
+    }
+
+    /// Only compress responses of at least the given number of bytes.
+    pub fn with_min_size(mut self, min_size: usize) -> Self {
+        self.min_size = min_size;
+        self
+    }
+
+    /// Only compress responses of at most the given number of bytes, which
+    /// is the most that is buffered for each response.
+    pub fn with_max_size(mut self, max_size: usize) -> Self {
+        self.max_size = max_size;
+        self
+    }
+}
+
+impl<T, B, C, ResBody> Service<(Request<B>, C)> for CompressionService<T>
+where
+    T: Service<(Request<B>, C), Response = Response<ResBody>>,
+    T::Future: Send + 'static,
+    T::Error: From<ApiError>,
+    ResBody: Body<Data = Bytes> + Send + 'static,
+    ResBody::Error: fmt::Display,
+{
+    type Response = Response<CompressionBody<PrefixedBody<ResBody>>>;
+    type Error = T::Error;
+    type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn call(&self, req: (Request<B>, C)) -> Self::Future {
+        let coding = if req.0.method() == Method::HEAD {
+            None
+        } else {
+            AcceptEncoding::from_headers(req.0.headers())
+                .and_then(|accept_encoding| accept_encoding.negotiate(SUPPORTED))
+                .filter(|coding| *coding != ContentCoding::Identity)
+        };
+        let (min_size, max_size) = (self.min_size, self.max_size);
+        let response = self.inner.call(req);
+
+        Box::pin(async move {
+            let mut response = response.await?;
+            response
+                .headers_mut()
+                .append(VARY, HeaderValue::from_static("accept-encoding"));
+            let pass_through = |body| Either::Left(PrefixedBody::new(Bytes::new(), Box::pin(body)));
+
+            let Some(coding) = coding else {
+                return Ok(response.map(pass_through));
+            };
+            let headers = response.headers();
+            let size = response.body().size_hint();
+            let too_small = size.upper().is_some_and(|size| size < min_size as u64);
+            let too_large = size.lower() > max_size as u64;
+            if too_small
+                || too_large
+                || headers.contains_key(CONTENT_ENCODING)
+                || headers.contains_key(CONTENT_RANGE)
+                || response.status().is_informational()
+                || response.status() == hyper::StatusCode::NO_CONTENT
+                || response.status() == hyper::StatusCode::NOT_MODIFIED
+            {
+                return Ok(response.map(pass_through));
+            }
+
+            let (mut parts, body) = response.into_parts();
+            let body = match read_to_limit(body, max_size)
+                .await
+                .map_err(|e| ApiError(format!("Failed to read response body: {}", e)))?
+            {
+                Ok(body) => body,
+                Err(body) => return Ok(Response::from_parts(parts, Either::Left(body))),
+            };
+            if body.len() < min_size {
+                return Ok(Response::from_parts(parts, Either::Right(Full::new(body))));
+            }
+
+            let compressed = compress(coding, &body)?;
+            parts.headers.remove(CONTENT_LENGTH);
+            parts
+                .headers
+                .insert(CONTENT_ENCODING, HeaderValue::from_static(coding.as_str()));
+            Ok(Response::from_parts(
+                parts,
+                Either::Right(Full::new(Bytes::from(compressed))),
+            ))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::EmptyContext;
+    use http_body_util::combinators::UnsyncBoxBody;
+    use http_body_util::{BodyExt, StreamBody};
+    use hyper::body::Frame;
+    use hyper::header::ACCEPT_ENCODING;
+    use std::convert::Infallible;
+
+    struct TextService;
+
+    impl Service<(Request<()>, EmptyContext)> for TextService {
+        type Response = Response<UnsyncBoxBody<Bytes, Infallible>>;
+        type Error = ApiError;
+        type Future = futures::future::Ready<Result<Self::Response, Self::Error>>;
+
+        fn call(&self, req: (Request<()>, EmptyContext)) -> Self::Future {
+            let size: usize = req.0.uri().path()[1..].parse().unwrap();
+            let data = Bytes::from("a".repeat(size));
+            let body = if req.0.uri().query() == Some("stream") {
+                // A streamed body, whose size isn't known up front.
+                let frames = futures::stream::iter([Ok(Frame::data(data))]);
+                UnsyncBoxBody::new(StreamBody::new(frames))
+            } else {
+                UnsyncBoxBody::new(Full::new(data))
+            };
+            futures::future::ok(Response::new(body))
+        }
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let data = "Hello, world! ".repeat(100);
+        for coding in SUPPORTED {
+            let compressed = compress(*coding, data.as_bytes()).unwrap();
+            assert!(compressed.len() < data.len(), "{}", coding);
+            let decompressed = decompress(*coding, &compressed, data.len()).unwrap();
+            assert_eq!(decompressed, data.as_bytes());
+            assert!(decompress(*coding, &compressed, data.len() - 1).is_err());
+        }
+        assert!(decompress(ContentCoding::Zstd, b"", 10).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_compression_service() {
+        let service = CompressionService::new(TextService).with_min_size(100);
+        let call = |path: &str, accept_encoding: &'static str| {
+            let request = Request::get(path)
+                .header(ACCEPT_ENCODING, accept_encoding)
+                .body(())
+                .unwrap();
+            service.call((request, EmptyContext))
+        };
+
+        let response = call("/1000", SUPPORTED[0].as_str()).await.unwrap();
+        assert_eq!(response.headers()[CONTENT_ENCODING], SUPPORTED[0].as_str());
+        assert_eq!(response.headers()[VARY], "accept-encoding");
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        assert_eq!(
+            decompress(SUPPORTED[0], &body, 1000).unwrap(),
+            "a".repeat(1000).as_bytes()
+        );
+
+        let response = call("/10", SUPPORTED[0].as_str()).await.unwrap();
+        assert!(!response.headers().contains_key(CONTENT_ENCODING));
+
+        let response = call("/1000", "identity").await.unwrap();
+        assert!(!response.headers().contains_key(CONTENT_ENCODING));
+
+        let response = call("/1000?stream", SUPPORTED[0].as_str()).await.unwrap();
+        assert_eq!(response.headers()[CONTENT_ENCODING], SUPPORTED[0].as_str());
+    }
+
+    #[tokio::test]
+    async fn test_max_size() {
+        let service = CompressionService::new(TextService)
+            .with_min_size(100)
+            .with_max_size(1000);
+        let call = |path: &str| {
+            let request = Request::get(path)
+                .header(ACCEPT_ENCODING, SUPPORTED[0].as_str())
+                .body(())
+                .unwrap();
+            service.call((request, EmptyContext))
+        };
+
+        // Larger responses are passed through, whether their size is known
+        // up front or only once they have been partly read.
+        for path in ["/2000", "/2000?stream"] {
+            let response = call(path).await.unwrap();
+            assert!(!response.headers().contains_key(CONTENT_ENCODING));
+            let body = response.into_body().collect().await.unwrap().to_bytes();
+            assert_eq!(body, "a".repeat(2000).as_bytes());
+        }
+
+        let response = call("/1000?stream").await.unwrap();
+        assert_eq!(response.headers()[CONTENT_ENCODING], SUPPORTED[0].as_str());
+    }
+}
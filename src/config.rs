@@ -0,0 +1,348 @@
+//! Types for configuring sizes and durations in human-readable form.
+//!
+//! `ByteSize` and `Duration` parse values such as `"10MB"`, `"250ms"` or
+//! `"2h"`, so that limits, timeouts and TTLs can be written consistently in
+//! configuration files. With the **serdejson** feature, both can be
+//! deserialized from such strings, or from plain integers. Both convert into
+//! the types taken by this crate's middleware, so a loaded configuration can
+//! be applied directly:
+//!
+//! ```
+//! use swagger::config::Duration;
+//!
+//! let ttl: Duration = "1m30s".parse().unwrap();
+//! assert_eq!(std::time::Duration::from(ttl).as_secs(), 90);
+//! assert_eq!(ttl.to_string(), "1m30s");
+//! ```
+use crate::ApiError;
+use std::fmt;
+use std::str::FromStr;
+use std::time;
+
+/// Size in bytes, parsed from values such as `"512"`, `"10MB"` or `"1.5GiB"`.
+///
+/// Both decimal units (`KB`, `MB`, `GB`, `TB`, as powers of 1000) and binary
+/// units (`KiB`, `MiB`, `GiB`, `TiB`, as powers of 1024) are accepted, case
+/// insensitively, as is `B` for bytes. A plain number is a number of bytes.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct ByteSize(pub u64);
+
+const BYTE_UNITS: [(&str, u64); 9] = [
+    ("TiB", 1 << 40),
+    ("TB", 1_000_000_000_000),
+    ("GiB", 1 << 30),
+    ("GB", 1_000_000_000),
+    ("MiB", 1 << 20),
+    ("MB", 1_000_000),
+    ("KiB", 1 << 10),
+    ("KB", 1_000),
+    ("B", 1),
+];
+
+impl ByteSize {
+    /// Size in bytes.
+    pub fn as_u64(&self) -> u64 {
+        self.0
+    }
+}
+
+impl From<u64> for ByteSize {
+    fn from(bytes: u64) -> Self {
+        ByteSize(bytes)
+    }
+}
+
+impl From<ByteSize> for u64 {
+    fn from(size: ByteSize) -> Self {
+        size.0
+    }
+}
+
+impl FromStr for ByteSize {
+    type Err = ApiError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || ApiError(format!("Invalid size: {:?}", s));
+        let (number, unit) = split_number(s.trim());
+        let multiplier = if unit.is_empty() {
+            1
+        } else {
+            BYTE_UNITS
+                .iter()
+                .chain([("K", 1_000), ("M", 1_000_000), ("G", 1_000_000_000)].iter())
+                .find(|(name, _)| name.eq_ignore_ascii_case(unit))
+                .map(|(_, multiplier)| *multiplier)
+                .ok_or_else(invalid)?
+        };
+        scale(number, multiplier).map(ByteSize).ok_or_else(invalid)
+    }
+}
+
+impl fmt::Display for ByteSize {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // Use the largest unit which represents the size exactly.
+        let (unit, multiplier) = BYTE_UNITS
+            .iter()
+            .find(|(_, multiplier)| self.0.is_multiple_of(*multiplier) && self.0 != 0)
+            .copied()
+            .unwrap_or(("B", 1));
+        write!(f, "{}{}", self.0 / multiplier, unit)
+    }
+}
+
+/// Duration, parsed from values such as `"250ms"`, `"30s"` or `"1h30m"`.
+///
+/// The units accepted are `ns`, `us` (or `µs`), `ms`, `s`, `m` (or `min`),
+/// `h` and `d`. Several values may be combined, as in `"1h 30m"`, and each
+/// may have a fractional part, as in `"1.5s"`. A plain number is a number of
+/// seconds.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Duration(pub time::Duration);
+
+const DURATION_UNITS: [(&str, u64); 7] = [
+    ("d", 86_400_000_000_000),
+    ("h", 3_600_000_000_000),
+    ("m", 60_000_000_000),
+    ("s", 1_000_000_000),
+    ("ms", 1_000_000),
+    ("us", 1_000),
+    ("ns", 1),
+];
+
+impl From<time::Duration> for Duration {
+    fn from(duration: time::Duration) -> Self {
+        Duration(duration)
+    }
+}
+
+impl From<u64> for Duration {
+    fn from(secs: u64) -> Self {
+        Duration(time::Duration::from_secs(secs))
+    }
+}
+
+impl From<Duration> for time::Duration {
+    fn from(duration: Duration) -> Self {
+        duration.0
+    }
+}
+
+impl FromStr for Duration {
+    type Err = ApiError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || ApiError(format!("Invalid duration: {:?}", s));
+        let mut rest = s.trim();
+        if rest.is_empty() {
+            return Err(invalid());
+        }
+
+        let mut nanos: u128 = 0;
+        while !rest.is_empty() {
+            let (number, tail) = split_number(rest);
+            let unit_len = tail
+                .find(|c: char| c.is_ascii_digit() || c == '.' || c.is_whitespace())
+                .unwrap_or(tail.len());
+            let unit = &tail[..unit_len];
+            let multiplier = match unit {
+                "" if nanos == 0 && unit_len == tail.trim_end().len() => 1_000_000_000,
+                "min" => 60_000_000_000,
+                "µs" => 1_000,
+                _ => DURATION_UNITS
+                    .iter()
+                    .find(|(name, _)| *name == unit)
+                    .map(|(_, multiplier)| *multiplier)
+                    .ok_or_else(invalid)?,
+            };
+            nanos += u128::from(scale(number, multiplier).ok_or_else(invalid)?);
+            rest = tail[unit_len..].trim_start();
+        }
+
+        let secs = u64::try_from(nanos / 1_000_000_000).map_err(|_| invalid())?;
+        Ok(Duration(time::Duration::new(
+            secs,
+            (nanos % 1_000_000_000) as u32,
+        )))
+    }
+}
+
+impl fmt::Display for Duration {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut nanos = self.0.as_nanos();
+        if nanos == 0 {
+            return f.write_str("0s");
+        }
+        for (unit, multiplier) in DURATION_UNITS {
+            let multiplier = u128::from(multiplier);
+            if nanos >= multiplier {
+                write!(f, "{}{}", nanos / multiplier, unit)?;
+                nanos %= multiplier;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Split a value into its leading number and the rest.
+fn split_number(s: &str) -> (&str, &str) {
+    let end = s
+        .find(|c: char| !(c.is_ascii_digit() || c == '.'))
+        .unwrap_or(s.len());
+    (&s[..end], s[end..].trim_start())
+}
+
+/// Multiply a decimal number by a whole multiplier, rounding to the nearest integer.
+fn scale(number: &str, multiplier: u64) -> Option<u64> {
+    let (whole, fraction) = number.split_once('.').unwrap_or((number, ""));
+    if whole.is_empty() && fraction.is_empty() {
+        return None;
+    }
+    let whole: u64 = if whole.is_empty() {
+        0
+    } else {
+        whole.parse().ok()?
+    };
+    let mut value = whole.checked_mul(multiplier)?;
+    if !fraction.is_empty() {
+        if !fraction.bytes().all(|b| b.is_ascii_digit()) {
+            return None;
+        }
+        let digits = &fraction[..fraction.len().min(18)];
+        let denominator = 10u128.pow(digits.len() as u32);
+        let numerator = digits.parse::<u128>().ok()? * u128::from(multiplier);
+        let part = (numerator + denominator / 2) / denominator;
+        value = value.checked_add(u64::try_from(part).ok()?)?;
+    }
+    Some(value)
+}
+
+#[cfg(feature = "serdejson")]
+mod serde_impls {
+    use super::{ByteSize, Duration};
+    use serde::de::{self, Deserializer, Visitor};
+    use serde::{Deserialize, Serialize, Serializer};
+    use std::fmt;
+    use std::marker::PhantomData;
+    use std::str::FromStr;
+
+    /// Visitor accepting either a string to parse, or an integer.
+    struct HumaneVisitor<T>(&'static str, PhantomData<T>);
+
+    impl<T> Visitor<'_> for HumaneVisitor<T>
+    where
+        T: FromStr<Err = crate::ApiError> + From<u64>,
+    {
+        type Value = T;
+
+        fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            write!(f, "{} as a string or integer", self.0)
+        }
+
+        fn visit_u64<E: de::Error>(self, value: u64) -> Result<T, E> {
+            Ok(T::from(value))
+        }
+
+        fn visit_i64<E: de::Error>(self, value: i64) -> Result<T, E> {
+            u64::try_from(value)
+                .map(T::from)
+                .map_err(|_| E::invalid_value(de::Unexpected::Signed(value), &self))
+        }
+
+        fn visit_str<E: de::Error>(self, value: &str) -> Result<T, E> {
+            value.parse().map_err(|e: crate::ApiError| E::custom(e.0))
+        }
+    }
+
+    impl<'de> Deserialize<'de> for ByteSize {
+        fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+            deserializer.deserialize_any(HumaneVisitor("a size", PhantomData))
+        }
+    }
+
+    impl<'de> Deserialize<'de> for Duration {
+        fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+            deserializer.deserialize_any(HumaneVisitor("a duration", PhantomData))
+        }
+    }
+
+    impl Serialize for ByteSize {
+        fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+            serializer.collect_str(self)
+        }
+    }
+
+    impl Serialize for Duration {
+        fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+            serializer.collect_str(self)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_byte_size() {
+        let parse = |s: &str| s.parse::<ByteSize>().map(u64::from).ok();
+        assert_eq!(parse("512"), Some(512));
+        assert_eq!(parse("10MB"), Some(10_000_000));
+        assert_eq!(parse("10 mib"), Some(10 << 20));
+        assert_eq!(parse("1.5KiB"), Some(1536));
+        assert_eq!(parse("2k"), Some(2000));
+        assert_eq!(parse("10XB"), None);
+        assert_eq!(parse("MB"), None);
+        assert_eq!(parse("20000000TB"), None);
+
+        assert_eq!(ByteSize(10 << 20).to_string(), "10MiB");
+        assert_eq!(ByteSize(1_500_000).to_string(), "1500KB");
+        assert_eq!(ByteSize(1001).to_string(), "1001B");
+        assert_eq!(ByteSize(0).to_string(), "0B");
+    }
+
+    #[test]
+    fn test_duration() {
+        let parse = |s: &str| s.parse::<Duration>().map(time::Duration::from).ok();
+        assert_eq!(parse("250ms"), Some(time::Duration::from_millis(250)));
+        assert_eq!(parse("2h"), Some(time::Duration::from_secs(7200)));
+        assert_eq!(parse("1h 30m"), Some(time::Duration::from_secs(5400)));
+        assert_eq!(parse("1.5s"), Some(time::Duration::from_millis(1500)));
+        assert_eq!(parse("5min"), Some(time::Duration::from_secs(300)));
+        assert_eq!(parse("10µs"), Some(time::Duration::from_micros(10)));
+        assert_eq!(parse("30"), Some(time::Duration::from_secs(30)));
+        assert_eq!(parse("1h30"), None);
+        assert_eq!(parse("10y"), None);
+        assert_eq!(parse(""), None);
+
+        assert_eq!(
+            Duration(time::Duration::from_millis(5_400_250)).to_string(),
+            "1h30m250ms"
+        );
+        assert_eq!(Duration::default().to_string(), "0s");
+    }
+
+    #[cfg(feature = "serdejson")]
+    #[test]
+    fn test_serde() {
+        #[derive(serde::Deserialize)]
+        struct Config {
+            limit: ByteSize,
+            timeout: Duration,
+            ttl: Duration,
+        }
+
+        let config: Config =
+            serde_json::from_str(r#"{"limit": "1MiB", "timeout": "250ms", "ttl": 60}"#).unwrap();
+        assert_eq!(config.limit, ByteSize(1 << 20));
+        assert_eq!(config.timeout.0, time::Duration::from_millis(250));
+        assert_eq!(config.ttl.0, time::Duration::from_secs(60));
+        assert!(
+            serde_json::from_str::<Config>(r#"{"limit": "x", "timeout": 1, "ttl": 1}"#).is_err()
+        );
+
+        assert_eq!(
+            serde_json::to_string(&Duration(time::Duration::from_secs(90))).unwrap(),
+            r#""1m30s""#
+        );
+    }
+}
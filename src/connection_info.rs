@@ -0,0 +1,230 @@
+//! Hyper service that stores details of the connection a request arrived on
+//! in its context.
+use crate::Push;
+use futures::future::{BoxFuture, FutureExt};
+use hyper::service::Service;
+use hyper::Request;
+use std::marker::PhantomData;
+use std::net::SocketAddr;
+
+/// Details of the connection a request arrived on.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub struct ConnectionInfo {
+    /// Address of the client, if known. This is the address of the immediate
+    /// peer, which may be a proxy.
+    pub remote_addr: Option<SocketAddr>,
+    /// Address on which the server accepted the connection, if known.
+    pub local_addr: Option<SocketAddr>,
+    /// Whether the connection was made over TLS.
+    pub tls: bool,
+}
+
+/// Connection which can describe itself.
+///
+/// This should be implemented by the connection passed to the make service
+/// by the server's acceptor. It is implemented for `ConnectionInfo`, so an
+/// acceptor can build one directly, and for `Option<SocketAddr>`, giving just
+/// the remote address.
+pub trait HasConnectionInfo {
+    /// Get the details of the connection.
+    fn connection_info(&self) -> ConnectionInfo;
+}
+
+impl HasConnectionInfo for ConnectionInfo {
+    fn connection_info(&self) -> ConnectionInfo {
+        *self
+    }
+}
+
+impl HasConnectionInfo for &ConnectionInfo {
+    fn connection_info(&self) -> ConnectionInfo {
+        **self
+    }
+}
+
+impl HasConnectionInfo for Option<SocketAddr> {
+    fn connection_info(&self) -> ConnectionInfo {
+        ConnectionInfo {
+            remote_addr: *self,
+            ..ConnectionInfo::default()
+        }
+    }
+}
+
+impl HasConnectionInfo for &Option<SocketAddr> {
+    fn connection_info(&self) -> ConnectionInfo {
+        (*self).connection_info()
+    }
+}
+
+#[cfg(feature = "uds")]
+impl HasConnectionInfo for &tokio::net::TcpStream {
+    fn connection_info(&self) -> ConnectionInfo {
+        ConnectionInfo {
+            remote_addr: self.peer_addr().ok(),
+            local_addr: self.local_addr().ok(),
+            tls: false,
+        }
+    }
+}
+
+#[cfg(feature = "uds")]
+impl HasConnectionInfo for &tokio::net::UnixStream {
+    fn connection_info(&self) -> ConnectionInfo {
+        ConnectionInfo::default()
+    }
+}
+
+/// Middleware which stores the details of each connection in the context of
+/// each request on it, as a `ConnectionInfo`.
+#[derive(Debug)]
+pub struct MakeConnectionInfoService<T, RC> {
+    inner: T,
+    marker: PhantomData<RC>,
+}
+
+impl<T, RC> MakeConnectionInfoService<T, RC> {
+    /// Create a middleware that stores connection details in the context.
+    pub fn new(inner: T) -> Self {
+        MakeConnectionInfoService {
+            inner,
+            marker: PhantomData,
+        }
+    }
+}
+
+impl<Inner, RC, Target> Service<Target> for MakeConnectionInfoService<Inner, RC>
+where
+    Inner: Service<Target>,
+    Inner::Future: Send + 'static,
+    Target: HasConnectionInfo,
+    RC: Send + 'static,
+{
+    type Error = Inner::Error;
+    type Response = ConnectionInfoService<Inner::Response, RC>;
+    type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn call(&self, target: Target) -> Self::Future {
+        let info = target.connection_info();
+        Box::pin(
+            self.inner
+                .call(target)
+                .map(move |s| Ok(ConnectionInfoService::new(s?, info))),
+        )
+    }
+}
+
+/// Middleware which stores the details of a connection in the context of
+/// each request on it, as a `ConnectionInfo`.
+///
+/// Handlers can use the remote address for logging, or later middleware can
+/// use it for rate limiting. This must be placed after an
+/// `AddContextMakeService`, which creates the context.
+#[derive(Debug)]
+pub struct ConnectionInfoService<T, RC> {
+    inner: T,
+    info: ConnectionInfo,
+    marker: PhantomData<RC>,
+}
+
+impl<T, RC> ConnectionInfoService<T, RC> {
+    /// Create a middleware that stores the given connection details in the
+    /// context.
+    pub fn new(inner: T, info: ConnectionInfo) -> Self {
+        ConnectionInfoService {
+            inner,
+            info,
+            marker: PhantomData,
+        }
+    }
+}
+
+impl<T, RC> Clone for ConnectionInfoService<T, RC>
+where
+    T: Clone,
+{
+    fn clone(&self) -> Self {
+        Self {
+            inner: self.inner.clone(),
+            info: self.info,
+            marker: PhantomData,
+        }
+    }
+}
+
+impl<T, B, RC> Service<(Request<B>, RC)> for ConnectionInfoService<T, RC>
+where
+    RC: Push<ConnectionInfo>,
+    T: Service<(Request<B>, RC::Result)>,
+{
+    type Response = T::Response;
+    type Error = T::Error;
+    type Future = T::Future;
+
+    fn call(&self, req: (Request<B>, RC)) -> Self::Future {
+        let (request, context) = req;
+        let context = context.push(self.info);
+
+        self.inner.call((request, context))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::context::Has;
+
+    crate::new_context_type!(TestContext, TestEmptyContext, ConnectionInfo);
+
+    type Context = TestContext<ConnectionInfo, TestEmptyContext>;
+
+    struct MakeInfoService;
+
+    impl<Target> Service<Target> for MakeInfoService {
+        type Response = InfoService;
+        type Error = ();
+        type Future = futures::future::Ready<Result<Self::Response, Self::Error>>;
+
+        fn call(&self, _: Target) -> Self::Future {
+            futures::future::ok(InfoService)
+        }
+    }
+
+    struct InfoService;
+
+    impl Service<(Request<()>, Context)> for InfoService {
+        type Response = ConnectionInfo;
+        type Error = ();
+        type Future = futures::future::Ready<Result<Self::Response, Self::Error>>;
+
+        fn call(&self, req: (Request<()>, Context)) -> Self::Future {
+            futures::future::ok(*Has::<ConnectionInfo>::get(&req.1))
+        }
+    }
+
+    #[tokio::test]
+    async fn test_connection_info_service() {
+        let make_service = MakeConnectionInfoService::<_, TestEmptyContext>::new(MakeInfoService);
+
+        let info = ConnectionInfo {
+            remote_addr: Some("192.0.2.1:54321".parse().unwrap()),
+            local_addr: Some("198.51.100.1:443".parse().unwrap()),
+            tls: true,
+        };
+        let service = make_service.call(&info).await.unwrap();
+        let stored = service
+            .call((Request::new(()), TestEmptyContext))
+            .await
+            .unwrap();
+        assert_eq!(stored, info);
+
+        let service = make_service.call(info.remote_addr).await.unwrap();
+        let stored = service
+            .call((Request::new(()), TestEmptyContext))
+            .await
+            .unwrap();
+        assert_eq!(stored.remote_addr, info.remote_addr);
+        assert_eq!(stored.local_addr, None);
+        assert!(!stored.tls);
+    }
+}
@@ -0,0 +1,191 @@
+//! In-memory connector, for testing clients without binding sockets.
+//!
+//! A `MockConnector` connects clients to a service running in the same
+//! process, over an in-memory transport, with the service handling each
+//! connection on its own Tokio task:
+//!
+//! ```ignore
+//! let connector = MockConnector::new(service_fn(|_req| async {
+//!     Ok::<_, Infallible>(Response::new(Full::new(Bytes::from("pong"))))
+//! }));
+//! let client = connector.client::<Full<Bytes>>();
+//! let response = client.get("http://petstore/ping".parse()?).await?;
+//! ```
+use futures::future::{self, Ready};
+use hyper::body::{Body, Incoming};
+use hyper::rt::{Read, ReadBufCursor, Write};
+use hyper::service::Service;
+use hyper::{Request, Response, Uri};
+use hyper_util::client::legacy::connect::{Connected, Connection};
+use hyper_util::client::legacy::Client;
+use hyper_util::rt::{TokioExecutor, TokioIo};
+use std::error::Error;
+use std::fmt;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use tokio::io::DuplexStream;
+
+/// Size of the buffer in each direction of a connection.
+const BUFFER_SIZE: usize = 64 * 1024;
+
+/// Connector which serves each connection using the given service, in memory.
+///
+/// Must be used from within a Tokio runtime.
+pub struct MockConnector<S> {
+    service: S,
+    connections: Arc<AtomicUsize>,
+}
+
+impl<S> MockConnector<S> {
+    /// Create a connector serving connections using the service, which
+    /// handles requests whatever their URI.
+    pub fn new(service: S) -> Self {
+        MockConnector {
+            service,
+            connections: Arc::new(AtomicUsize::new(0)),
+        }
+    }
+
+    /// Number of connections made so far, by this connector and its clones.
+    pub fn connections(&self) -> usize {
+        self.connections.load(Ordering::SeqCst)
+    }
+}
+
+impl<S, ResBody> MockConnector<S>
+where
+    S: Service<Request<Incoming>, Response = Response<ResBody>> + Clone + Send + Sync + 'static,
+    S::Future: Send + 'static,
+    S::Error: Into<Box<dyn Error + Send + Sync>>,
+    ResBody: Body + Send + 'static,
+    ResBody::Data: Send,
+    ResBody::Error: Into<Box<dyn Error + Send + Sync>>,
+{
+    /// Create a client whose requests are handled by the service.
+    pub fn client<B>(&self) -> Client<Self, B>
+    where
+        B: Body + Send + 'static + Unpin,
+        B::Data: Send,
+        B::Error: Into<Box<dyn Error + Send + Sync>>,
+    {
+        Client::builder(TokioExecutor::new()).build(self.clone())
+    }
+}
+
+impl<S: Clone> Clone for MockConnector<S> {
+    fn clone(&self) -> Self {
+        MockConnector {
+            service: self.service.clone(),
+            connections: self.connections.clone(),
+        }
+    }
+}
+
+impl<S> fmt::Debug for MockConnector<S> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("MockConnector")
+            .field("connections", &self.connections())
+            .finish()
+    }
+}
+
+impl<S, ResBody> tower_service::Service<Uri> for MockConnector<S>
+where
+    S: Service<Request<Incoming>, Response = Response<ResBody>> + Clone + Send + 'static,
+    S::Future: Send + 'static,
+    S::Error: Into<Box<dyn Error + Send + Sync>>,
+    ResBody: Body + Send + 'static,
+    ResBody::Data: Send,
+    ResBody::Error: Into<Box<dyn Error + Send + Sync>>,
+{
+    type Response = MockStream;
+    type Error = std::io::Error;
+    type Future = Ready<Result<MockStream, std::io::Error>>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, _dst: Uri) -> Self::Future {
+        let (client, server) = tokio::io::duplex(BUFFER_SIZE);
+        self.connections.fetch_add(1, Ordering::SeqCst);
+        let connection = hyper::server::conn::http1::Builder::new()
+            .serve_connection(TokioIo::new(server), self.service.clone());
+        tokio::spawn(async move {
+            // Errors are seen by the client, as the connection closing
+            let _ = connection.await;
+        });
+        future::ok(MockStream(TokioIo::new(client)))
+    }
+}
+
+/// Client end of an in-memory connection made by `MockConnector`.
+#[derive(Debug)]
+pub struct MockStream(TokioIo<DuplexStream>);
+
+impl Read for MockStream {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: ReadBufCursor<'_>,
+    ) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.0).poll_read(cx, buf)
+    }
+}
+
+impl Write for MockStream {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<std::io::Result<usize>> {
+        Pin::new(&mut self.0).poll_write(cx, buf)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.0).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut self.0).poll_shutdown(cx)
+    }
+}
+
+impl Connection for MockStream {
+    fn connected(&self) -> Connected {
+        Connected::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bytes::Bytes;
+    use http_body_util::{BodyExt, Full};
+
+    #[tokio::test]
+    async fn test_mock_connector() {
+        let connector = MockConnector::new(hyper::service::service_fn(
+            |req: Request<Incoming>| async move {
+                let path = req.uri().path().to_string();
+                let body = req.into_body().collect().await?.to_bytes();
+                let reply = format!("{} {}", path, String::from_utf8_lossy(&body));
+                Ok::<_, hyper::Error>(Response::new(Full::new(Bytes::from(reply))))
+            },
+        ));
+        let client = connector.client::<Full<Bytes>>();
+
+        for body in ["cat", "dog"] {
+            let request = Request::post("http://petstore/pets")
+                .body(Full::new(Bytes::from(body)))
+                .unwrap();
+            let response = client.request(request).await.unwrap();
+            let body = response.into_body().collect().await.unwrap().to_bytes();
+            assert!(body.starts_with(b"/pets "));
+        }
+        // The connection is reused
+        assert_eq!(connector.connections(), 1);
+    }
+}
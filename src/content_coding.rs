@@ -0,0 +1,270 @@
+//! Negotiation of content codings using the `Accept-Encoding` header.
+//!
+//! The coding of a response is chosen as described in RFC 9110: each coding
+//! is weighted by the quality value the client gives it, explicitly or through
+//! `*`, and the server's order of preference breaks ties. The `identity`
+//! coding, i.e. no compression, is acceptable unless the client explicitly
+//! excludes it, in which case there may be no acceptable coding, and the
+//! server should respond with `406 Not Acceptable`.
+//!
+//! ```
+//! use hyper::header::{HeaderMap, HeaderValue, ACCEPT_ENCODING};
+//! use swagger::ContentCoding;
+//!
+//! let mut headers = HeaderMap::new();
+//! headers.insert(ACCEPT_ENCODING, HeaderValue::from_static("gzip;q=0.8, br"));
+//! let supported = [ContentCoding::Gzip, ContentCoding::Brotli];
+//! assert_eq!(ContentCoding::negotiate(&headers, &supported), Some(ContentCoding::Brotli));
+//! ```
+use crate::ApiError;
+use hyper::header::{HeaderMap, HeaderValue, ACCEPT_ENCODING};
+use hyper::{Response, StatusCode};
+use std::fmt;
+use std::str::FromStr;
+
+/// A content coding, as used in `Accept-Encoding` and `Content-Encoding` headers.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum ContentCoding {
+    /// No coding.
+    Identity,
+    /// `gzip` - RFC 1952.
+    Gzip,
+    /// `deflate` - the zlib format of RFC 1950.
+    Deflate,
+    /// `br` - Brotli, RFC 7932.
+    Brotli,
+    /// `zstd` - Zstandard, RFC 8878.
+    Zstd,
+}
+
+impl ContentCoding {
+    /// The name of the coding, as used in headers.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ContentCoding::Identity => "identity",
+            ContentCoding::Gzip => "gzip",
+            ContentCoding::Deflate => "deflate",
+            ContentCoding::Brotli => "br",
+            ContentCoding::Zstd => "zstd",
+        }
+    }
+
+    /// Choose the coding of a response to a request with the given headers,
+    /// from the codings supported by the server in order of preference.
+    ///
+    /// If the request has no `Accept-Encoding` header, `identity` is chosen.
+    /// Returns `None` if no coding is acceptable to the client, in which case
+    /// the server should respond with `not_acceptable`.
+    pub fn negotiate(headers: &HeaderMap, supported: &[ContentCoding]) -> Option<ContentCoding> {
+        match AcceptEncoding::from_headers(headers) {
+            Some(accept_encoding) => accept_encoding.negotiate(supported),
+            None => Some(ContentCoding::Identity),
+        }
+    }
+}
+
+impl fmt::Display for ContentCoding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for ContentCoding {
+    type Err = ApiError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "identity" => Ok(ContentCoding::Identity),
+            "gzip" | "x-gzip" => Ok(ContentCoding::Gzip),
+            "deflate" => Ok(ContentCoding::Deflate),
+            "br" => Ok(ContentCoding::Brotli),
+            "zstd" => Ok(ContentCoding::Zstd),
+            _ => Err(ApiError(format!("Unsupported content coding: {}", s))),
+        }
+    }
+}
+
+/// Parsed `Accept-Encoding` header, giving the quality value of each coding.
+///
+/// Quality values are held in thousandths, from 0 (not acceptable) to 1000.
+/// Codings not supported by `ContentCoding` are ignored, as are entries with
+/// invalid quality values.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct AcceptEncoding {
+    codings: Vec<(ContentCoding, u16)>,
+    any: Option<u16>,
+}
+
+impl AcceptEncoding {
+    /// Parse an `Accept-Encoding` header value.
+    pub fn parse(value: &str) -> Self {
+        let mut accept_encoding = AcceptEncoding::default();
+        accept_encoding.add(value);
+        accept_encoding
+    }
+
+    /// Retrieve the `Accept-Encoding` headers of a request, or `None` if it
+    /// has none, in which case any coding is acceptable.
+    pub fn from_headers(headers: &HeaderMap) -> Option<Self> {
+        let mut values = headers.get_all(ACCEPT_ENCODING).iter().peekable();
+        values.peek()?;
+
+        let mut accept_encoding = AcceptEncoding::default();
+        for value in values.filter_map(|v| v.to_str().ok()) {
+            accept_encoding.add(value);
+        }
+        Some(accept_encoding)
+    }
+
+    fn add(&mut self, value: &str) {
+        for entry in value.split(',').map(str::trim).filter(|e| !e.is_empty()) {
+            let mut params = entry.split(';');
+            let name = params.next().unwrap_or_default().trim();
+            let quality = params
+                .filter_map(|param| param.split_once('='))
+                .find(|(name, _)| name.trim().eq_ignore_ascii_case("q"))
+                .map(|(_, value)| parse_quality(value.trim()))
+                .unwrap_or(Some(1000));
+            let Some(quality) = quality else {
+                continue;
+            };
+
+            if name == "*" {
+                self.any = Some(quality);
+            } else if let Ok(coding) = name.parse() {
+                self.codings.retain(|(c, _)| *c != coding);
+                self.codings.push((coding, quality));
+            }
+        }
+    }
+
+    /// Quality value of the coding, in thousandths.
+    pub fn quality(&self, coding: ContentCoding) -> u16 {
+        self.codings
+            .iter()
+            .find(|(c, _)| *c == coding)
+            .map(|(_, quality)| *quality)
+            .or(self.any)
+            .unwrap_or(match coding {
+                // Identity is always acceptable, unless explicitly excluded.
+                ContentCoding::Identity => 1,
+                _ => 0,
+            })
+    }
+
+    /// Whether the coding is acceptable.
+    pub fn accepts(&self, coding: ContentCoding) -> bool {
+        self.quality(coding) > 0
+    }
+
+    /// Choose the acceptable coding with the highest quality value, from the
+    /// codings supported by the server in order of preference.
+    ///
+    /// `identity` is always considered, after the supported codings, and is
+    /// chosen if the client gives no acceptable coding a higher quality.
+    /// Returns `None` if no coding is acceptable.
+    pub fn negotiate(&self, supported: &[ContentCoding]) -> Option<ContentCoding> {
+        let mut best = None;
+        for &coding in supported.iter().chain([ContentCoding::Identity].iter()) {
+            let quality = self.quality(coding);
+            if quality > 0 && best.is_none_or(|(_, best)| quality > best) {
+                best = Some((coding, quality));
+            }
+        }
+        best.map(|(coding, _)| coding)
+    }
+}
+
+/// Parse a quality value, returning thousandths.
+pub(crate) fn parse_quality(value: &str) -> Option<u16> {
+    let (whole, fraction) = value.split_once('.').unwrap_or((value, ""));
+    if fraction.len() > 3 || !fraction.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    let thousandths = format!("{:0<3}", fraction).parse::<u16>().ok()?;
+    match whole {
+        "0" => Some(thousandths),
+        "1" if thousandths == 0 => Some(1000),
+        _ => None,
+    }
+}
+
+/// Build a `406 Not Acceptable` response, for a request which accepts none of
+/// the supported codings, which lists them in its `Accept-Encoding` header.
+pub fn not_acceptable<B: Default>(supported: &[ContentCoding]) -> Response<B> {
+    let mut response = Response::new(B::default());
+    *response.status_mut() = StatusCode::NOT_ACCEPTABLE;
+    let supported = supported
+        .iter()
+        .map(ContentCoding::as_str)
+        .collect::<Vec<_>>()
+        .join(", ");
+    if let Ok(value) = HeaderValue::from_str(&supported) {
+        response.headers_mut().insert(ACCEPT_ENCODING, value);
+    }
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_quality() {
+        assert_eq!(parse_quality("1"), Some(1000));
+        assert_eq!(parse_quality("1.000"), Some(1000));
+        assert_eq!(parse_quality("0.5"), Some(500));
+        assert_eq!(parse_quality("0.125"), Some(125));
+        assert_eq!(parse_quality("0"), Some(0));
+        assert_eq!(parse_quality("1.5"), None);
+        assert_eq!(parse_quality("0.1234"), None);
+        assert_eq!(parse_quality("x"), None);
+    }
+
+    #[test]
+    fn test_negotiate() {
+        let supported = [
+            ContentCoding::Zstd,
+            ContentCoding::Brotli,
+            ContentCoding::Gzip,
+        ];
+        let negotiate = |value: &str| AcceptEncoding::parse(value).negotiate(&supported);
+
+        assert_eq!(negotiate("gzip, br"), Some(ContentCoding::Brotli));
+        assert_eq!(negotiate("gzip, br;q=0.5"), Some(ContentCoding::Gzip));
+        assert_eq!(negotiate("X-GZIP;q=0.1"), Some(ContentCoding::Gzip));
+        assert_eq!(negotiate("*"), Some(ContentCoding::Zstd));
+        assert_eq!(negotiate("*, zstd;q=0"), Some(ContentCoding::Brotli));
+        // Unsupported codings fall back to identity.
+        assert_eq!(negotiate("deflate"), Some(ContentCoding::Identity));
+        assert_eq!(negotiate(""), Some(ContentCoding::Identity));
+        assert_eq!(negotiate("gzip;q=2"), Some(ContentCoding::Identity));
+
+        // Identity may be excluded, explicitly or through `*`.
+        assert_eq!(negotiate("identity;q=0"), None);
+        assert_eq!(negotiate("*;q=0"), None);
+        assert_eq!(negotiate("*;q=0, identity"), Some(ContentCoding::Identity));
+        assert_eq!(negotiate("gzip, identity;q=0"), Some(ContentCoding::Gzip));
+    }
+
+    #[test]
+    fn test_negotiate_headers() {
+        let supported = [ContentCoding::Gzip];
+        let mut headers = HeaderMap::new();
+        assert_eq!(
+            ContentCoding::negotiate(&headers, &supported),
+            Some(ContentCoding::Identity)
+        );
+
+        headers.append(ACCEPT_ENCODING, HeaderValue::from_static("identity;q=0"));
+        headers.append(ACCEPT_ENCODING, HeaderValue::from_static("gzip;q=0.5"));
+        assert_eq!(
+            ContentCoding::negotiate(&headers, &supported),
+            Some(ContentCoding::Gzip)
+        );
+
+        let response = not_acceptable::<()>(&[ContentCoding::Brotli, ContentCoding::Gzip]);
+        assert_eq!(response.status(), StatusCode::NOT_ACCEPTABLE);
+        assert_eq!(response.headers().get(ACCEPT_ENCODING).unwrap(), "br, gzip");
+    }
+}
@@ -0,0 +1,378 @@
+//! Typed representation of the `Content-Disposition` header.
+//!
+//! `ContentDisposition` follows RFC 6266, encoding filenames which aren't
+//! plain ASCII in the `filename*` parameter defined by RFC 8187, along with an
+//! ASCII fallback in `filename` for older clients:
+//!
+//! ```
+//! use swagger::ContentDisposition;
+//!
+//! let disposition = ContentDisposition::attachment().with_filename("résumé.pdf");
+//! assert_eq!(
+//!     disposition.to_string(),
+//!     "attachment; filename=\"r_sum_.pdf\"; filename*=UTF-8''r%C3%A9sum%C3%A9.pdf"
+//! );
+//! ```
+use crate::ApiError;
+use hyper::header::{HeaderMap, HeaderValue, InvalidHeaderValue, CONTENT_DISPOSITION};
+use percent_encoding::{percent_decode_str, utf8_percent_encode, AsciiSet, NON_ALPHANUMERIC};
+use std::fmt;
+use std::str::FromStr;
+
+/// Characters which must be percent-encoded in an extended parameter value,
+/// leaving only the `attr-char`s from RFC 8187.
+const ATTR_ENCODE_SET: &AsciiSet = &NON_ALPHANUMERIC
+    .remove(b'!')
+    .remove(b'#')
+    .remove(b'$')
+    .remove(b'&')
+    .remove(b'+')
+    .remove(b'-')
+    .remove(b'.')
+    .remove(b'^')
+    .remove(b'_')
+    .remove(b'`')
+    .remove(b'|')
+    .remove(b'~');
+
+/// How the content should be presented.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum DispositionType {
+    /// `inline`: display the content as part of the page.
+    Inline,
+    /// `attachment`: save the content to a file, rather than displaying it.
+    Attachment,
+    /// `form-data`: a field of a `multipart/form-data` body, as defined by
+    /// RFC 7578.
+    FormData,
+    /// Any other type, in lower case.
+    Other(String),
+}
+
+impl DispositionType {
+    /// Name of the disposition type.
+    pub fn as_str(&self) -> &str {
+        match self {
+            DispositionType::Inline => "inline",
+            DispositionType::Attachment => "attachment",
+            DispositionType::FormData => "form-data",
+            DispositionType::Other(other) => other,
+        }
+    }
+}
+
+/// Disposition type and parameters of a `Content-Disposition` header.
+///
+/// Path components of parsed filenames are removed, as recommended by RFC
+/// 6266 section 4.3, so they can't be used to write outside a download
+/// directory.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ContentDisposition {
+    /// How the content should be presented.
+    pub disposition: DispositionType,
+    /// `name`: the name of a form field.
+    pub name: Option<String>,
+    /// `filename` or `filename*`: the suggested name of the file.
+    pub filename: Option<String>,
+    /// Other parameters, with their names in lower case.
+    pub parameters: Vec<(String, String)>,
+}
+
+impl ContentDisposition {
+    /// Disposition of the given type, with no parameters.
+    pub fn new(disposition: DispositionType) -> Self {
+        ContentDisposition {
+            disposition,
+            name: None,
+            filename: None,
+            parameters: Vec::new(),
+        }
+    }
+
+    /// Content to be displayed inline.
+    pub fn inline() -> Self {
+        ContentDisposition::new(DispositionType::Inline)
+    }
+
+    /// Content to be saved to a file.
+    pub fn attachment() -> Self {
+        ContentDisposition::new(DispositionType::Attachment)
+    }
+
+    /// Field of a `multipart/form-data` body with the given name.
+    pub fn form_data<S: Into<String>>(name: S) -> Self {
+        ContentDisposition::new(DispositionType::FormData).with_name(name)
+    }
+
+    /// Set the name of the form field.
+    pub fn with_name<S: Into<String>>(mut self, name: S) -> Self {
+        self.name = Some(name.into());
+        self
+    }
+
+    /// Set the suggested name of the file.
+    pub fn with_filename<S: Into<String>>(mut self, filename: S) -> Self {
+        self.filename = Some(filename.into());
+        self
+    }
+
+    /// Add a further parameter.
+    pub fn with_parameter<N: Into<String>, V: Into<String>>(mut self, name: N, value: V) -> Self {
+        self.parameters
+            .push((name.into().to_ascii_lowercase(), value.into()));
+        self
+    }
+
+    /// Parse a `Content-Disposition` header value, returning `None` if it has
+    /// no disposition type.
+    ///
+    /// If both are present, `filename*` takes precedence over `filename`.
+    /// Extended values in character sets other than UTF-8 and ISO-8859-1 are
+    /// ignored.
+    pub fn parse(value: &str) -> Option<Self> {
+        let mut parts = split_parameters(value);
+        let disposition = match parts.next()?.to_ascii_lowercase().as_str() {
+            "" => return None,
+            "inline" => DispositionType::Inline,
+            "attachment" => DispositionType::Attachment,
+            "form-data" => DispositionType::FormData,
+            other if other.chars().all(is_token_char) => DispositionType::Other(other.to_string()),
+            _ => return None,
+        };
+
+        let mut disposition = ContentDisposition::new(disposition);
+        let mut extended_filename = None;
+        for parameter in parts {
+            let Some((name, value)) = parameter.split_once('=') else {
+                continue;
+            };
+            let name = name.trim().to_ascii_lowercase();
+            let value = value.trim();
+            match name.as_str() {
+                "filename*" => extended_filename = decode_extended(value),
+                "filename" => disposition.filename = Some(unquote(value)),
+                "name" => disposition.name = Some(unquote(value)),
+                _ => disposition.parameters.push((name, unquote(value))),
+            }
+        }
+        disposition.filename = extended_filename
+            .or(disposition.filename)
+            .map(|filename| strip_path(&filename).to_string());
+        Some(disposition)
+    }
+
+    /// Retrieve the `Content-Disposition` header of a response, if it has a
+    /// valid one.
+    pub fn from_headers(headers: &HeaderMap) -> Option<Self> {
+        headers
+            .get(CONTENT_DISPOSITION)
+            .and_then(|value| value.to_str().ok())
+            .and_then(ContentDisposition::parse)
+    }
+
+    /// Set the `Content-Disposition` header of a response.
+    pub fn apply(&self, headers: &mut HeaderMap) {
+        if let Ok(value) = HeaderValue::try_from(self) {
+            headers.insert(CONTENT_DISPOSITION, value);
+        }
+    }
+}
+
+/// Split a header value at semicolons, ignoring those within quoted strings.
+fn split_parameters(value: &str) -> impl Iterator<Item = &str> {
+    let mut parameters = Vec::new();
+    let mut quoted = false;
+    let mut escaped = false;
+    let mut start = 0;
+    for (index, c) in value.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' if quoted => escaped = true,
+            '"' => quoted = !quoted,
+            ';' if !quoted => {
+                parameters.push(value[start..index].trim());
+                start = index + 1;
+            }
+            _ => {}
+        }
+    }
+    parameters.push(value[start..].trim());
+    parameters.into_iter()
+}
+
+/// Remove the quotes and escapes from a quoted string.
+fn unquote(value: &str) -> String {
+    match value.strip_prefix('"').and_then(|v| v.strip_suffix('"')) {
+        Some(value) => {
+            let mut unquoted = String::with_capacity(value.len());
+            let mut chars = value.chars();
+            while let Some(c) = chars.next() {
+                match c {
+                    '\\' => unquoted.extend(chars.next()),
+                    c => unquoted.push(c),
+                }
+            }
+            unquoted
+        }
+        None => value.to_string(),
+    }
+}
+
+/// Decode an extended parameter value, as defined by RFC 8187 section 3.2.
+fn decode_extended(value: &str) -> Option<String> {
+    let mut parts = value.splitn(3, '\'');
+    let charset = parts.next()?;
+    let _language = parts.next()?;
+    let decoded = percent_decode_str(parts.next()?);
+    if charset.eq_ignore_ascii_case("UTF-8") {
+        decoded.decode_utf8().ok().map(Into::into)
+    } else if charset.eq_ignore_ascii_case("ISO-8859-1") {
+        Some(decoded.map(char::from).collect())
+    } else {
+        None
+    }
+}
+
+/// Remove any directory path from a filename.
+fn strip_path(filename: &str) -> &str {
+    filename.rsplit(['/', '\\']).next().unwrap_or(filename)
+}
+
+fn is_token_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || "!#$%&'*+-.^_`|~".contains(c)
+}
+
+/// Write a parameter value as a quoted string, replacing characters which
+/// can't be sent in one.
+fn write_quoted(f: &mut fmt::Formatter<'_>, value: &str) -> fmt::Result {
+    f.write_str("\"")?;
+    for c in value.chars() {
+        match c {
+            '"' | '\\' => write!(f, "\\{}", c)?,
+            ' '..='~' => write!(f, "{}", c)?,
+            _ => f.write_str("_")?,
+        }
+    }
+    f.write_str("\"")
+}
+
+impl fmt::Display for ContentDisposition {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.disposition.as_str())?;
+        if let Some(name) = &self.name {
+            f.write_str("; name=")?;
+            write_quoted(f, name)?;
+        }
+        if let Some(filename) = &self.filename {
+            f.write_str("; filename=")?;
+            write_quoted(f, filename)?;
+            if !filename.chars().all(|c| matches!(c, ' '..='~')) {
+                write!(
+                    f,
+                    "; filename*=UTF-8''{}",
+                    utf8_percent_encode(filename, ATTR_ENCODE_SET)
+                )?;
+            }
+        }
+        for (name, value) in &self.parameters {
+            write!(f, "; {}=", name)?;
+            write_quoted(f, value)?;
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for ContentDisposition {
+    type Err = ApiError;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        ContentDisposition::parse(value)
+            .ok_or_else(|| ApiError(format!("Invalid Content-Disposition header: {}", value)))
+    }
+}
+
+impl TryFrom<&HeaderValue> for ContentDisposition {
+    type Error = ApiError;
+
+    fn try_from(value: &HeaderValue) -> Result<Self, Self::Error> {
+        value
+            .to_str()
+            .map_err(|e| ApiError(format!("Invalid Content-Disposition header: {}", e)))?
+            .parse()
+    }
+}
+
+impl TryFrom<&ContentDisposition> for HeaderValue {
+    type Error = InvalidHeaderValue;
+
+    fn try_from(disposition: &ContentDisposition) -> Result<Self, Self::Error> {
+        HeaderValue::from_str(&disposition.to_string())
+    }
+}
+
+impl TryFrom<ContentDisposition> for HeaderValue {
+    type Error = InvalidHeaderValue;
+
+    fn try_from(disposition: ContentDisposition) -> Result<Self, Self::Error> {
+        HeaderValue::try_from(&disposition)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let disposition = ContentDisposition::parse(
+            "Attachment; filename=\"EURO rates\"; filename*=utf-8''%e2%82%ac%20rates; size=12",
+        )
+        .unwrap();
+        assert_eq!(disposition.disposition, DispositionType::Attachment);
+        assert_eq!(disposition.filename.as_deref(), Some("€ rates"));
+        assert_eq!(
+            disposition.parameters,
+            vec![("size".to_string(), "12".to_string())]
+        );
+
+        let disposition =
+            ContentDisposition::parse("form-data; name=\"photo\"; filename=\"a\\\\b;c\\\".jpg\"")
+                .unwrap();
+        assert_eq!(disposition.name.as_deref(), Some("photo"));
+        assert_eq!(disposition.filename.as_deref(), Some("b;c\".jpg"));
+
+        let disposition =
+            ContentDisposition::parse("inline; filename*=iso-8859-1'en'%A3%20rates").unwrap();
+        assert_eq!(disposition.filename.as_deref(), Some("£ rates"));
+
+        let disposition = ContentDisposition::parse("attachment; filename=../../etc/passwd");
+        assert_eq!(disposition.unwrap().filename.as_deref(), Some("passwd"));
+
+        assert!(ContentDisposition::parse("").is_none());
+        assert!("; filename=x".parse::<ContentDisposition>().is_err());
+    }
+
+    #[test]
+    fn test_display() {
+        assert_eq!(
+            ContentDisposition::attachment()
+                .with_filename("report \"final\".csv")
+                .to_string(),
+            "attachment; filename=\"report \\\"final\\\".csv\""
+        );
+        assert_eq!(
+            ContentDisposition::inline()
+                .with_filename("€\n.txt")
+                .to_string(),
+            "inline; filename=\"__.txt\"; filename*=UTF-8''%E2%82%AC%0A.txt"
+        );
+
+        let disposition = ContentDisposition::form_data("photo").with_filename("日本.jpg");
+        let mut headers = HeaderMap::new();
+        disposition.apply(&mut headers);
+        assert_eq!(
+            ContentDisposition::from_headers(&headers),
+            Some(disposition)
+        );
+    }
+}
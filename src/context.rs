@@ -6,8 +6,15 @@
 //!
 //! See the `context_tests` module below for examples of how to use.
 
+pub mod extensions;
+pub mod propagation;
+
 use crate::auth::{AuthData, Authorization};
 use crate::XSpanIdString;
+use std::any::{Any, TypeId};
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::Arc;
 
 /// Defines methods for accessing, modifying, adding and removing the data stored
 /// in a context. Used to specify the requirements that a hyper service makes on
@@ -146,6 +153,69 @@ pub trait Push<T> {
     fn push(self, value: T) -> Self::Result;
 }
 
+/// Lists the items in a context, for debugging which layers of middleware
+/// pushed or popped them.
+///
+/// This is implemented for context types defined by `new_context_type!`
+/// whose items all implement `Debug`. Credentials held by this crate's context
+/// items, such as the password in `AuthData`, are `Secret`s, so are masked in
+/// the listing - custom items should hold credentials the same way.
+///
+/// ```rust
+/// # use swagger::{AuthData, DebugDump, EmptyContext, Push, XSpanIdString};
+/// let context = EmptyContext
+///     .push(XSpanIdString("span".to_string()))
+///     .push(Some(AuthData::basic("user", "password")));
+/// let dump = context.debug_dump().to_string();
+/// assert!(dump.contains("XSpanIdString(\"span\")"));
+/// assert!(!dump.contains("password"));
+/// ```
+pub trait DebugDump {
+    /// Append an entry for each item in the context, outermost first.
+    fn dump_items(&self, items: &mut Vec<ContextItem>);
+
+    /// Listing of the items in the context, outermost first.
+    fn debug_dump(&self) -> ContextDump {
+        let mut items = Vec::new();
+        self.dump_items(&mut items);
+        ContextDump(items)
+    }
+}
+
+/// Entry for an item in a `ContextDump`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ContextItem {
+    /// Name of the type of the item.
+    pub type_name: &'static str,
+    /// `Debug` representation of the item.
+    pub value: String,
+}
+
+/// Listing of the items in a context, produced by `DebugDump`. Displays as
+/// one line per item.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ContextDump(pub Vec<ContextItem>);
+
+impl ContextDump {
+    /// Whether the context has an item of the type with the given name, such
+    /// as `swagger::header::XSpanIdString`.
+    pub fn contains_type(&self, type_name: &str) -> bool {
+        self.0.iter().any(|item| item.type_name == type_name)
+    }
+}
+
+impl fmt::Display for ContextDump {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, item) in self.0.iter().enumerate() {
+            if i > 0 {
+                writeln!(f)?;
+            }
+            write!(f, "{}: {}", item.type_name, item.value)?;
+        }
+        Ok(())
+    }
+}
+
 /// Defines a struct that can be used to build up contexts recursively by
 /// adding one item to the context at a time, and a unit struct representing an
 /// empty context. The first argument is the name of the newly defined context struct
@@ -269,6 +339,22 @@ macro_rules! new_context_type {
         }
         )+
 
+        impl $crate::context::DebugDump for $empty_context_name {
+            fn dump_items(&self, _items: &mut Vec<$crate::context::ContextItem>) {}
+        }
+
+        impl<T: ::std::fmt::Debug, C: $crate::context::DebugDump> $crate::context::DebugDump
+            for $context_name<T, C>
+        {
+            fn dump_items(&self, items: &mut Vec<$crate::context::ContextItem>) {
+                items.push($crate::context::ContextItem {
+                    type_name: ::std::any::type_name::<T>(),
+                    value: format!("{:?}", self.head),
+                });
+                self.tail.dump_items(items);
+            }
+        }
+
         // Add implementations of `Has<T>` and `Pop<T>` when `T` is any type stored in
         // the list, not just the head.
         $crate::new_context_type!(impl extend_has $context_name, $empty_context_name, $($types),+);
@@ -446,6 +532,225 @@ macro_rules! make_context {
     };
 }
 
+/// Macro for building a context value in which only some items are given,
+/// and the rest take their default values. The first two arguments name a
+/// context type created with `new_context_type!`, as for `make_context!`, and
+/// the values after the `;` are set on the context, in any order.
+///
+/// The type of the context is taken from where it is used, so usually needs
+/// to be written out, and every item in it must implement `Default`.
+///
+/// ```rust
+/// # #[macro_use] extern crate swagger;
+/// # use swagger::Push;
+///
+/// # #[derive(PartialEq, Eq, Debug, Default)]
+/// # struct Type1(u32);
+/// # #[derive(PartialEq, Eq, Debug, Default)]
+/// # struct Type2(u32);
+/// # #[derive(PartialEq, Eq, Debug, Default)]
+/// # struct Type3(u32);
+///
+/// # new_context_type!(MyContext, MyEmptyContext, Type1, Type2, Type3);
+///
+/// type Context = make_context_ty!(MyContext, MyEmptyContext, Type1, Type2, Type3);
+///
+/// fn main() {
+///     let context: Context = make_context_with_defaults!(MyContext, MyEmptyContext; Type2(2));
+///     assert_eq!(context, make_context!(MyContext, MyEmptyContext, Type1(0), Type2(2), Type3(0)));
+/// }
+/// ```
+#[macro_export]
+macro_rules! make_context_with_defaults {
+    ($context_name:ident, $empty_context_name:ident; $($values:expr),* $(,)*) => {{
+        #[allow(unused_mut)]
+        let mut context = ::std::default::Default::default();
+        $(
+            $crate::Has::set(&mut context, $values);
+        )*
+        context
+    }};
+    ($context_name:ident, $empty_context_name:ident $(,)*) => {
+        $crate::make_context_with_defaults!($context_name, $empty_context_name;)
+    };
+}
+
+/// Derive `Has<T>`, `Push<T>` and `Pop<T>` for the type of each field of a
+/// struct, so that a plain struct can be used as a context, in place of the
+/// nested types built by `new_context_type!`.
+///
+/// Pushing a value sets the field, and popping it takes the value, leaving
+/// the default in its place, so the context keeps the same type throughout a
+/// stack of middleware. Each field must have a distinct type which
+/// implements `Default`, and the struct should implement `Default` to be
+/// built by `AddContextService`.
+///
+/// ```rust
+/// use swagger::{AuthData, Authorization, Has, HasContext, Pop, Push, XSpanIdString};
+///
+/// #[derive(Debug, Default, HasContext)]
+/// struct MyContext {
+///     span_id: XSpanIdString,
+///     auth_data: Option<AuthData>,
+///     authorization: Option<Authorization>,
+/// }
+///
+/// let context = MyContext::default()
+///     .push(XSpanIdString("span".to_string()))
+///     .push(Some(AuthData::apikey("key")));
+/// assert_eq!(Has::<XSpanIdString>::get(&context).0, "span");
+///
+/// let (auth_data, context): (Option<AuthData>, _) = context.pop();
+/// assert_eq!(auth_data, Some(AuthData::apikey("key")));
+/// assert_eq!(context.auth_data, None);
+/// ```
+#[cfg(feature = "derive")]
+pub use swagger_derive::HasContext;
+
+/// Context storing values of any type, looked up by their type at runtime.
+///
+/// This is an alternative to the contexts created by `new_context_type!` for
+/// large service stacks, where the recursive types of those contexts make for
+/// slow builds and unreadable compile errors. `DynContext` implements `Has<T>`,
+/// `Push<T>` and `Pop<T>` for every `T`, so any middleware accepts it, at the
+/// cost of a hash lookup for each access.
+///
+/// As a consequence, a missing value is only detected at runtime: `Has::get`,
+/// `Has::get_mut` and `Pop::pop` panic if no value of the type has been
+/// pushed. Use `try_get` and `try_pop` for values which may be missing.
+///
+/// Values are shared between clones of a context, and copied when changed
+/// through one of them, so cloning a context is cheap and values must be
+/// `Clone`, `Send` and `Sync`.
+///
+/// ```rust
+/// # use swagger::{DynContext, Has, Pop, Push, XSpanIdString};
+/// # use swagger::auth::AuthData;
+/// let context = DynContext::new()
+///     .push(XSpanIdString("span".to_string()))
+///     .push(Option::<AuthData>::None);
+/// assert_eq!(Has::<XSpanIdString>::get(&context).0, "span");
+///
+/// let (auth_data, context): (Option<AuthData>, _) = context.pop();
+/// assert_eq!(auth_data, None);
+/// assert!(context.try_get::<Option<AuthData>>().is_none());
+/// ```
+#[derive(Clone, Default)]
+pub struct DynContext {
+    values: HashMap<TypeId, Arc<dyn Any + Send + Sync>>,
+}
+
+impl fmt::Debug for DynContext {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("DynContext")
+            .field("len", &self.values.len())
+            .finish()
+    }
+}
+
+impl DynContext {
+    /// Create an empty context.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Get the value of the given type, if there is one.
+    pub fn try_get<T: DynValue>(&self) -> Option<&T> {
+        self.values
+            .get(&TypeId::of::<T>())
+            .and_then(|value| value.downcast_ref())
+    }
+
+    /// Get a mutable reference to the value of the given type, if there is
+    /// one, copying it first if it is shared with a clone of this context.
+    pub fn try_get_mut<T: DynValue>(&mut self) -> Option<&mut T> {
+        let value = self.values.get_mut(&TypeId::of::<T>())?;
+        if Arc::get_mut(value).is_none() {
+            let copy: T = value.downcast_ref::<T>()?.clone();
+            *value = Arc::new(copy);
+        }
+        Arc::get_mut(value)?.downcast_mut()
+    }
+
+    /// Set the value of its type, returning the previous value, if any.
+    pub fn insert<T: DynValue>(&mut self, value: T) -> Option<T> {
+        self.values
+            .insert(TypeId::of::<T>(), Arc::new(value))
+            .and_then(unwrap_value)
+    }
+
+    /// Remove the value of the given type, returning it, if there is one.
+    pub fn remove<T: DynValue>(&mut self) -> Option<T> {
+        self.values
+            .remove(&TypeId::of::<T>())
+            .and_then(unwrap_value)
+    }
+
+    /// Remove the value of the given type, returning it, if there is one,
+    /// along with the rest of the context. Unlike `Pop::pop`, this doesn't
+    /// panic if there is no value.
+    pub fn try_pop<T: DynValue>(mut self) -> (Option<T>, Self) {
+        let value = self.remove();
+        (value, self)
+    }
+
+    /// Whether there is a value of the given type.
+    pub fn contains<T: DynValue>(&self) -> bool {
+        self.values.contains_key(&TypeId::of::<T>())
+    }
+}
+
+/// Value which can be stored in a `DynContext`.
+pub trait DynValue: Any + Clone + Send + Sync {}
+
+impl<T: Any + Clone + Send + Sync> DynValue for T {}
+
+/// Take the value out of a `DynContext` entry, copying it if it is shared
+/// with a clone of the context.
+fn unwrap_value<T: DynValue>(value: Arc<dyn Any + Send + Sync>) -> Option<T> {
+    let value = value.downcast::<T>().ok()?;
+    Some(Arc::try_unwrap(value).unwrap_or_else(|value| T::clone(&value)))
+}
+
+fn missing<T>() -> ! {
+    panic!(
+        "DynContext has no value of type {}",
+        std::any::type_name::<T>()
+    )
+}
+
+impl<T: DynValue> Has<T> for DynContext {
+    fn get(&self) -> &T {
+        self.try_get().unwrap_or_else(|| missing::<T>())
+    }
+
+    fn get_mut(&mut self) -> &mut T {
+        self.try_get_mut().unwrap_or_else(|| missing::<T>())
+    }
+
+    fn set(&mut self, value: T) {
+        self.insert(value);
+    }
+}
+
+impl<T: DynValue> Push<T> for DynContext {
+    type Result = Self;
+
+    fn push(mut self, value: T) -> Self {
+        self.insert(value);
+        self
+    }
+}
+
+impl<T: DynValue> Pop<T> for DynContext {
+    type Result = Self;
+
+    fn pop(mut self) -> (T, Self) {
+        let value = self.remove().unwrap_or_else(|| missing::<T>());
+        (value, self)
+    }
+}
+
 /// Context wrapper, to bind an API with a context.
 #[derive(Debug)]
 pub struct ContextWrapper<T, C> {
@@ -479,8 +784,36 @@ impl<T: Clone, C: Clone> Clone for ContextWrapper<T, C> {
     }
 }
 
+/// Context wrapper sharing its API through an `Arc`, so that wrappers for
+/// each request can be created cheaply, and stored in application state or
+/// moved into spawned tasks.
+pub type ArcContextWrapper<T, C> = ContextWrapper<Arc<T>, C>;
+
+/// Extension for binding a shared API with a context.
+///
+/// ```rust
+/// # use std::sync::Arc;
+/// # use swagger::context::{ArcContextWrapperExt, EmptyContext};
+/// struct Api;
+///
+/// let api = Arc::new(Api);
+/// let wrapper = api.with_context(EmptyContext::default());
+/// assert!(Arc::ptr_eq(wrapper.api(), &api));
+/// ```
+pub trait ArcContextWrapperExt<T: ?Sized> {
+    /// Create an `ArcContextWrapper`, binding the shared API and context.
+    fn with_context<C>(&self, context: C) -> ArcContextWrapper<T, C>;
+}
+
+impl<T: ?Sized> ArcContextWrapperExt<T> for Arc<T> {
+    fn with_context<C>(&self, context: C) -> ArcContextWrapper<T, C> {
+        ContextWrapper::new(self.clone(), context)
+    }
+}
+
 #[cfg(test)]
 mod context_tests {
+    use super::propagation::Tenant;
     use super::Has;
     use super::*;
 
@@ -502,6 +835,8 @@ mod context_tests {
         ContextItem3
     );
 
+    new_context_type!(DumpContext, DumpEmptyContext, XSpanIdString, Option<Tenant>);
+
     #[test]
     fn send_request() {
         let t = MyEmptyContext;
@@ -526,4 +861,81 @@ mod context_tests {
             assert_eq!(v.val, 4);
         }
     }
+
+    #[cfg(feature = "derive")]
+    #[test]
+    fn derived_context() {
+        #[derive(Default, HasContext)]
+        struct DerivedContext {
+            item1: Option<u32>,
+            span_id: XSpanIdString,
+        }
+
+        fn use_context<C: Has<Option<u32>> + Push<XSpanIdString, Result = C>>(context: C) -> C {
+            context.push(XSpanIdString("span".to_string()))
+        }
+
+        let mut context = use_context(DerivedContext::default());
+        assert_eq!(Has::<XSpanIdString>::get(&context).0, "span");
+        Has::<Option<u32>>::set(&mut context, Some(1));
+        *Has::<Option<u32>>::get_mut(&mut context) = Some(2);
+
+        let (item1, context): (Option<u32>, _) = context.pop();
+        assert_eq!(item1, Some(2));
+        assert_eq!(context.item1, None);
+    }
+
+    #[test]
+    fn debug_dump() {
+        let context = DumpEmptyContext
+            .push(XSpanIdString("span".to_string()))
+            .push(Some(Tenant("acme".to_string())));
+        let dump = context.debug_dump();
+        assert_eq!(dump.0.len(), 2);
+        assert_eq!(dump.0[0].value, "Some(Tenant(\"acme\"))");
+        assert!(dump.contains_type(std::any::type_name::<XSpanIdString>()));
+        assert_eq!(
+            dump.to_string(),
+            format!(
+                "{}: Some(Tenant(\"acme\"))\n{}: XSpanIdString(\"span\")",
+                std::any::type_name::<Option<Tenant>>(),
+                std::any::type_name::<XSpanIdString>()
+            )
+        );
+    }
+
+    #[test]
+    fn dyn_context() {
+        fn use_context<C>(context: C) -> C
+        where
+            C: Has<Option<u32>> + Push<XSpanIdString, Result = C>,
+        {
+            context.push(XSpanIdString("span".to_string()))
+        }
+
+        let mut context = use_context(DynContext::new().push(Some(1u32)));
+        assert_eq!(Has::<XSpanIdString>::get(&context).0, "span");
+        *Has::<Option<u32>>::get_mut(&mut context) = Some(2);
+        assert_eq!(context.insert(Some(3u32)), Some(Some(2)));
+
+        let (value, context): (Option<u32>, _) = context.pop();
+        assert_eq!(value, Some(3));
+        assert!(!context.contains::<Option<u32>>());
+        assert!(context.contains::<XSpanIdString>());
+
+        // Clones share values until they are changed.
+        let mut clone = context.clone();
+        Has::<XSpanIdString>::get_mut(&mut clone).0 = "other".to_string();
+        assert_eq!(Has::<XSpanIdString>::get(&context).0, "span");
+        let (value, clone) = clone.try_pop::<XSpanIdString>();
+        assert_eq!(value.unwrap().0, "other");
+        assert!(clone.try_pop::<XSpanIdString>().0.is_none());
+    }
+
+    #[test]
+    #[should_panic(expected = "DynContext has no value of type")]
+    fn dyn_context_missing() {
+        let context = DynContext::new();
+        let _: &XSpanIdString = context.get();
+    }
 }
@@ -0,0 +1,268 @@
+//! Bridging between contexts and request extensions.
+//!
+//! Middleware in this crate passes a context alongside each request, as a
+//! `(Request, Context)` tuple, where middleware written for plain hyper or
+//! tower services can only see the request, and shares data through its
+//! `http::Extensions`. The services in this module move data between the two,
+//! so that the two kinds of middleware can be mixed in one stack:
+//!
+//! - `ContextToExtensionsService` stores the whole context in the request's
+//!   extensions, and passes the plain request on.
+//! - `ExtensionsToContextService` takes the context back out of the
+//!   extensions, and passes the tuple on.
+//! - `ExtensionItemService` moves a single item, added to the extensions by
+//!   plain middleware, into the context.
+//!
+//! ```ignore
+//! let service = ExtensionsToContextService::<_, MyContext>::new(swagger_service);
+//! let service = PlainMiddleware::new(service);
+//! let service = ContextToExtensionsService::new(service);
+//! let service = AddContextService::<_, EmptyContext>::new(service);
+//! ```
+use crate::context::Push;
+use futures::future::{BoxFuture, FutureExt};
+use hyper::http::Extensions;
+use hyper::service::Service;
+use hyper::Request;
+use std::marker::PhantomData;
+
+/// Store a context in request extensions, replacing any context of the same
+/// type.
+pub fn insert_context<C>(extensions: &mut Extensions, context: C)
+where
+    C: Clone + Send + Sync + 'static,
+{
+    extensions.insert(context);
+}
+
+/// Get the context of the given type stored in request extensions, if any.
+pub fn get_context<C>(extensions: &Extensions) -> Option<&C>
+where
+    C: Send + Sync + 'static,
+{
+    extensions.get()
+}
+
+/// Remove the context of the given type from request extensions, if any.
+pub fn remove_context<C>(extensions: &mut Extensions) -> Option<C>
+where
+    C: Send + Sync + 'static,
+{
+    extensions.remove()
+}
+
+/// Middleware which stores the context of each request in its extensions,
+/// and passes the plain request to a service which doesn't take a context.
+#[derive(Clone, Debug)]
+pub struct ContextToExtensionsService<T> {
+    inner: T,
+}
+
+impl<T> ContextToExtensionsService<T> {
+    /// Create a middleware which stores contexts in request extensions.
+    pub fn new(inner: T) -> Self {
+        ContextToExtensionsService { inner }
+    }
+}
+
+impl<T, B, C> Service<(Request<B>, C)> for ContextToExtensionsService<T>
+where
+    T: Service<Request<B>>,
+    C: Clone + Send + Sync + 'static,
+{
+    type Response = T::Response;
+    type Error = T::Error;
+    type Future = T::Future;
+
+    fn call(&self, req: (Request<B>, C)) -> Self::Future {
+        let (mut request, context) = req;
+        insert_context(request.extensions_mut(), context);
+        self.inner.call(request)
+    }
+}
+
+/// Middleware which takes the context of each request out of its
+/// extensions, and passes it on with the request.
+///
+/// Requests without a context of the given type are given its default
+/// value.
+#[derive(Debug)]
+pub struct ExtensionsToContextService<T, C> {
+    inner: T,
+    marker: PhantomData<fn(C)>,
+}
+
+impl<T, C> ExtensionsToContextService<T, C> {
+    /// Create a middleware which takes contexts from request extensions.
+    pub fn new(inner: T) -> Self {
+        ExtensionsToContextService {
+            inner,
+            marker: PhantomData,
+        }
+    }
+}
+
+impl<T: Clone, C> Clone for ExtensionsToContextService<T, C> {
+    fn clone(&self) -> Self {
+        ExtensionsToContextService {
+            inner: self.inner.clone(),
+            marker: PhantomData,
+        }
+    }
+}
+
+impl<T, B, C> Service<Request<B>> for ExtensionsToContextService<T, C>
+where
+    T: Service<(Request<B>, C)>,
+    C: Default + Send + Sync + 'static,
+{
+    type Response = T::Response;
+    type Error = T::Error;
+    type Future = T::Future;
+
+    fn call(&self, mut request: Request<B>) -> Self::Future {
+        let context = remove_context(request.extensions_mut()).unwrap_or_default();
+        self.inner.call((request, context))
+    }
+}
+
+/// Middleware which takes an item out of the extensions of each request,
+/// where plain middleware has put it, and pushes it into the context as an
+/// `Option`.
+#[derive(Debug)]
+pub struct MakeExtensionItemService<T, I, RC> {
+    inner: T,
+    marker: PhantomData<fn(I, RC)>,
+}
+
+impl<T, I, RC> MakeExtensionItemService<T, I, RC> {
+    /// Create a middleware which moves the item into the context.
+    pub fn new(inner: T) -> Self {
+        MakeExtensionItemService {
+            inner,
+            marker: PhantomData,
+        }
+    }
+}
+
+impl<Inner, I, RC, Target> Service<Target> for MakeExtensionItemService<Inner, I, RC>
+where
+    Inner: Service<Target>,
+    Inner::Future: Send + 'static,
+{
+    type Error = Inner::Error;
+    type Response = ExtensionItemService<Inner::Response, I, RC>;
+    type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn call(&self, target: Target) -> Self::Future {
+        Box::pin(
+            self.inner
+                .call(target)
+                .map(|s| Ok(ExtensionItemService::new(s?))),
+        )
+    }
+}
+
+/// Middleware which takes an item out of the extensions of each request,
+/// where plain middleware has put it, and pushes it into the context as an
+/// `Option`.
+#[derive(Debug)]
+pub struct ExtensionItemService<T, I, RC> {
+    inner: T,
+    marker: PhantomData<fn(I, RC)>,
+}
+
+impl<T, I, RC> ExtensionItemService<T, I, RC> {
+    /// Create a middleware which moves the item into the context.
+    pub fn new(inner: T) -> Self {
+        ExtensionItemService {
+            inner,
+            marker: PhantomData,
+        }
+    }
+}
+
+impl<T: Clone, I, RC> Clone for ExtensionItemService<T, I, RC> {
+    fn clone(&self) -> Self {
+        ExtensionItemService {
+            inner: self.inner.clone(),
+            marker: PhantomData,
+        }
+    }
+}
+
+impl<T, I, B, RC> Service<(Request<B>, RC)> for ExtensionItemService<T, I, RC>
+where
+    RC: Push<Option<I>>,
+    T: Service<(Request<B>, RC::Result)>,
+    I: Send + Sync + 'static,
+{
+    type Response = T::Response;
+    type Error = T::Error;
+    type Future = T::Future;
+
+    fn call(&self, req: (Request<B>, RC)) -> Self::Future {
+        let (mut request, context) = req;
+        let item = request.extensions_mut().remove::<I>();
+        self.inner.call((request, context.push(item)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::context::propagation::Tenant;
+    use crate::context::Has;
+    use crate::XSpanIdString;
+    use futures::future::{ok, Ready};
+
+    crate::new_context_type!(TestContext, TestEmptyContext, XSpanIdString, Option<Tenant>);
+
+    type Context = TestContext<XSpanIdString, TestEmptyContext>;
+
+    /// Plain middleware, which reads and changes the span ID in the context,
+    /// and adds a tenant.
+    struct PlainMiddleware<T>(T);
+
+    impl<T: Service<Request<()>>> Service<Request<()>> for PlainMiddleware<T> {
+        type Response = T::Response;
+        type Error = T::Error;
+        type Future = T::Future;
+
+        fn call(&self, mut request: Request<()>) -> Self::Future {
+            let context = request.extensions_mut().get_mut::<Context>().unwrap();
+            let span = format!("{}-plain", context.get().0);
+            context.set(XSpanIdString(span));
+            request.extensions_mut().insert(Tenant("acme".to_string()));
+            self.0.call(request)
+        }
+    }
+
+    struct ContextReader;
+
+    impl<C> Service<(Request<()>, C)> for ContextReader
+    where
+        C: Has<XSpanIdString> + Has<Option<Tenant>>,
+    {
+        type Response = (String, Option<Tenant>);
+        type Error = ();
+        type Future = Ready<Result<Self::Response, ()>>;
+
+        fn call(&self, req: (Request<()>, C)) -> Self::Future {
+            let span = Has::<XSpanIdString>::get(&req.1).0.clone();
+            ok((span, Has::<Option<Tenant>>::get(&req.1).clone()))
+        }
+    }
+
+    #[tokio::test]
+    async fn test_bridge() {
+        let service = ExtensionItemService::<_, Tenant, Context>::new(ContextReader);
+        let service = ExtensionsToContextService::<_, Context>::new(service);
+        let service = ContextToExtensionsService::new(PlainMiddleware(service));
+
+        let context = TestEmptyContext.push(XSpanIdString("span".to_string()));
+        let (span, tenant) = service.call((Request::new(()), context)).await.unwrap();
+        assert_eq!(span, "span-plain");
+        assert_eq!(tenant, Some(Tenant("acme".to_string())));
+    }
+}
@@ -0,0 +1,530 @@
+//! Propagation of context items between services in request headers.
+//!
+//! Items implementing `Propagate` can be written to the headers of outgoing
+//! requests by an `InjectContextService` client middleware, and read back
+//! into the context of incoming requests by an `ExtractContextService`, so
+//! that a chain of services shares the same span ID, trace, tenant or baggage
+//! without each handler copying headers by hand.
+//!
+//! ```ignore
+//! // Server
+//! let service = MakeExtractContextService::<_, Option<Tenant>, _>::new(service);
+//! let service = MakeExtractContextService::<_, Baggage, _>::new(service);
+//! let service = MakeExtractContextService::<_, Option<TraceContext>, _>::new(service);
+//! let service = AddContextMakeService::<_, EmptyContext>::new(service);
+//!
+//! // Client, called with the context of the request being handled
+//! let client = InjectContextService::<_, Option<Tenant>>::new(client);
+//! let client = InjectContextService::<_, Baggage>::new(client);
+//! let client = InjectContextService::<_, XSpanIdString>::new(client);
+//! let client = InjectContextService::<_, Option<TraceContext>>::new(client);
+//! ```
+use crate::context::{Has, Push};
+use crate::{TraceContext, XSpanIdString, X_SPAN_ID};
+use futures::future::{BoxFuture, FutureExt};
+use hyper::header::{HeaderMap, HeaderName, HeaderValue};
+use hyper::service::Service;
+use hyper::Request;
+use percent_encoding::{percent_decode_str, utf8_percent_encode, AsciiSet, CONTROLS};
+use std::fmt;
+use std::marker::PhantomData;
+
+/// Header - `X-Tenant-ID` - identifying the tenant a request is made on
+/// behalf of.
+pub const X_TENANT_ID: &str = "X-Tenant-ID";
+
+/// Header - `baggage` - carrying application-defined properties, as defined by
+/// the W3C Baggage specification.
+pub const BAGGAGE: HeaderName = HeaderName::from_static("baggage");
+
+/// Characters which must be percent-encoded in a baggage value.
+const BAGGAGE_ENCODE_SET: &AsciiSet = &CONTROLS
+    .add(b' ')
+    .add(b'"')
+    .add(b',')
+    .add(b';')
+    .add(b'\\')
+    .add(b'%');
+
+/// Context item which can be carried between services in request headers.
+///
+/// Implement this for your own context items to propagate them.
+pub trait Propagate: Sized {
+    /// Write the item to the headers of an outgoing request.
+    fn inject(&self, headers: &mut HeaderMap);
+
+    /// Read the item from the headers of an incoming request, if present.
+    fn extract(headers: &HeaderMap) -> Option<Self>;
+}
+
+/// Optional items are only written when present, and are always read, as
+/// `None` if missing.
+impl<T: Propagate> Propagate for Option<T> {
+    fn inject(&self, headers: &mut HeaderMap) {
+        if let Some(item) = self {
+            item.inject(headers);
+        }
+    }
+
+    fn extract(headers: &HeaderMap) -> Option<Self> {
+        Some(T::extract(headers))
+    }
+}
+
+impl Propagate for XSpanIdString {
+    fn inject(&self, headers: &mut HeaderMap) {
+        if let Ok(value) = HeaderValue::from_str(&self.0) {
+            headers.insert(X_SPAN_ID, value);
+        }
+    }
+
+    fn extract(headers: &HeaderMap) -> Option<Self> {
+        headers
+            .get(X_SPAN_ID)
+            .and_then(|value| value.to_str().ok())
+            .map(|value| XSpanIdString(value.to_string()))
+    }
+}
+
+/// The trace context read from a request is a child of the caller's span, so
+/// that it identifies the span of the service handling the request, and is
+/// sent as the parent of the requests it makes in turn.
+impl Propagate for TraceContext {
+    fn inject(&self, headers: &mut HeaderMap) {
+        self.insert(headers);
+    }
+
+    fn extract(headers: &HeaderMap) -> Option<Self> {
+        TraceContext::from_headers(headers).map(|trace| trace.child())
+    }
+}
+
+/// Tenant a request is made on behalf of, carried in the `X-Tenant-ID`
+/// header.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Tenant(pub String);
+
+impl Propagate for Tenant {
+    fn inject(&self, headers: &mut HeaderMap) {
+        if let Ok(value) = HeaderValue::from_str(&self.0) {
+            headers.insert(X_TENANT_ID, value);
+        }
+    }
+
+    fn extract(headers: &HeaderMap) -> Option<Self> {
+        headers
+            .get(X_TENANT_ID)
+            .and_then(|value| value.to_str().ok())
+            .filter(|value| !value.is_empty())
+            .map(|value| Tenant(value.to_string()))
+    }
+}
+
+/// Maximum number of members sent in a `baggage` header.
+const MAX_BAGGAGE_MEMBERS: usize = 64;
+
+/// Maximum length of a `baggage` header.
+const MAX_BAGGAGE_LENGTH: usize = 8192;
+
+/// Application-defined properties, carried in the `baggage` header, such as
+/// tenant IDs or debug flags that every service in a chain should see.
+///
+/// Servers read baggage with a `MakeExtractContextService::<_, Baggage, _>`,
+/// and clients pass it on with an `InjectContextService::<_, Baggage>`.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Baggage(Vec<BaggageMember>);
+
+/// Member of `Baggage` - a key and value, with optional metadata properties.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct BaggageMember {
+    /// Key of the member.
+    pub key: String,
+    /// Value of the member.
+    pub value: String,
+    /// Properties of the member, each a key with an optional value.
+    pub properties: Vec<(String, Option<String>)>,
+}
+
+impl BaggageMember {
+    /// Create a member with no properties.
+    pub fn new<K: Into<String>, V: Into<String>>(key: K, value: V) -> Self {
+        BaggageMember {
+            key: key.into(),
+            value: value.into(),
+            properties: Vec::new(),
+        }
+    }
+
+    /// Add a property, with or without a value.
+    pub fn with_property<K: Into<String>>(mut self, key: K, value: Option<String>) -> Self {
+        self.properties.push((key.into(), value));
+        self
+    }
+
+    fn parse(member: &str) -> Option<Self> {
+        let mut parts = member.split(';');
+        let (key, value) = parts.next()?.split_once('=')?;
+        let key = key.trim();
+        if key.is_empty() {
+            return None;
+        }
+        let mut member = BaggageMember::new(key, decode_baggage(value)?);
+        for property in parts {
+            let (key, value) = match property.split_once('=') {
+                Some((key, value)) => (key, Some(decode_baggage(value)?)),
+                None => (property, None),
+            };
+            if !key.trim().is_empty() {
+                member.properties.push((key.trim().to_string(), value));
+            }
+        }
+        Some(member)
+    }
+}
+
+fn decode_baggage(value: &str) -> Option<String> {
+    percent_decode_str(value.trim())
+        .decode_utf8()
+        .ok()
+        .map(|value| value.into_owned())
+}
+
+impl fmt::Display for BaggageMember {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}={}",
+            self.key,
+            utf8_percent_encode(&self.value, BAGGAGE_ENCODE_SET)
+        )?;
+        for (key, value) in &self.properties {
+            write!(f, ";{}", key)?;
+            if let Some(value) = value {
+                write!(f, "={}", utf8_percent_encode(value, BAGGAGE_ENCODE_SET))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Baggage {
+    /// Create empty baggage.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Get the value with the given key, if any.
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.member(key).map(|member| member.value.as_str())
+    }
+
+    /// Get the member with the given key, including its properties, if any.
+    pub fn member(&self, key: &str) -> Option<&BaggageMember> {
+        self.0.iter().find(|member| member.key == key)
+    }
+
+    /// Set the value with the given key, replacing any existing member.
+    pub fn insert<K: Into<String>, V: Into<String>>(&mut self, key: K, value: V) {
+        self.insert_member(BaggageMember::new(key, value));
+    }
+
+    /// Add a member, replacing any existing member with the same key.
+    pub fn insert_member(&mut self, member: BaggageMember) {
+        match self.0.iter_mut().find(|m| m.key == member.key) {
+            Some(existing) => *existing = member,
+            None => self.0.push(member),
+        }
+    }
+
+    /// Remove the member with the given key, returning it if it was present.
+    pub fn remove(&mut self, key: &str) -> Option<BaggageMember> {
+        let index = self.0.iter().position(|member| member.key == key)?;
+        Some(self.0.remove(index))
+    }
+
+    /// Iterate over the keys and values.
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.0
+            .iter()
+            .map(|member| (member.key.as_str(), member.value.as_str()))
+    }
+
+    /// Iterate over the members.
+    pub fn members(&self) -> impl Iterator<Item = &BaggageMember> {
+        self.0.iter()
+    }
+
+    /// Whether there are no values.
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
+/// Members beyond the limits of the W3C specification - 64 members, and 8192
+/// bytes in total - are not sent.
+impl Propagate for Baggage {
+    fn inject(&self, headers: &mut HeaderMap) {
+        let mut value = String::new();
+        for member in self.0.iter().take(MAX_BAGGAGE_MEMBERS) {
+            let member = member.to_string();
+            let separator = if value.is_empty() { 0 } else { 1 };
+            if value.len() + separator + member.len() > MAX_BAGGAGE_LENGTH {
+                break;
+            }
+            if separator > 0 {
+                value.push(',');
+            }
+            value.push_str(&member);
+        }
+        if value.is_empty() {
+            return;
+        }
+        if let Ok(value) = HeaderValue::try_from(value) {
+            headers.insert(BAGGAGE, value);
+        }
+    }
+
+    fn extract(headers: &HeaderMap) -> Option<Self> {
+        let mut baggage = Baggage::new();
+        for value in headers.get_all(BAGGAGE) {
+            let Ok(value) = value.to_str() else {
+                continue;
+            };
+            for member in value.split(',').filter_map(BaggageMember::parse) {
+                baggage.insert_member(member);
+            }
+        }
+        Some(baggage).filter(|baggage| !baggage.is_empty())
+    }
+}
+
+/// Client middleware which writes a context item to the headers of outgoing
+/// requests.
+///
+/// Headers already set on the request are overwritten.
+#[derive(Debug)]
+pub struct InjectContextService<T, I> {
+    inner: T,
+    marker: PhantomData<fn(I)>,
+}
+
+impl<T, I> InjectContextService<T, I> {
+    /// Create a middleware which writes the item to outgoing requests.
+    pub fn new(inner: T) -> Self {
+        InjectContextService {
+            inner,
+            marker: PhantomData,
+        }
+    }
+}
+
+impl<T: Clone, I> Clone for InjectContextService<T, I> {
+    fn clone(&self) -> Self {
+        InjectContextService {
+            inner: self.inner.clone(),
+            marker: PhantomData,
+        }
+    }
+}
+
+impl<T, I, B, C> Service<(Request<B>, C)> for InjectContextService<T, I>
+where
+    T: Service<(Request<B>, C)>,
+    C: Has<I>,
+    I: Propagate,
+{
+    type Response = T::Response;
+    type Error = T::Error;
+    type Future = T::Future;
+
+    fn call(&self, req: (Request<B>, C)) -> Self::Future {
+        let (mut request, context) = req;
+        Has::<I>::get(&context).inject(request.headers_mut());
+        self.inner.call((request, context))
+    }
+}
+
+/// Middleware which reads a context item from the headers of incoming
+/// requests, and pushes it into their context.
+#[derive(Debug)]
+pub struct MakeExtractContextService<T, I, RC> {
+    inner: T,
+    marker: PhantomData<fn(I, RC)>,
+}
+
+impl<T, I, RC> MakeExtractContextService<T, I, RC> {
+    /// Create a middleware which reads the item from incoming requests.
+    pub fn new(inner: T) -> Self {
+        MakeExtractContextService {
+            inner,
+            marker: PhantomData,
+        }
+    }
+}
+
+impl<Inner, I, RC, Target> Service<Target> for MakeExtractContextService<Inner, I, RC>
+where
+    Inner: Service<Target>,
+    Inner::Future: Send + 'static,
+{
+    type Error = Inner::Error;
+    type Response = ExtractContextService<Inner::Response, I, RC>;
+    type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn call(&self, target: Target) -> Self::Future {
+        Box::pin(
+            self.inner
+                .call(target)
+                .map(|s| Ok(ExtractContextService::new(s?))),
+        )
+    }
+}
+
+/// Middleware which reads a context item from the headers of incoming
+/// requests, and pushes it into their context.
+///
+/// Requests without the item are given its default value.
+#[derive(Debug)]
+pub struct ExtractContextService<T, I, RC> {
+    inner: T,
+    marker: PhantomData<fn(I, RC)>,
+}
+
+impl<T, I, RC> ExtractContextService<T, I, RC> {
+    /// Create a middleware which reads the item from incoming requests.
+    pub fn new(inner: T) -> Self {
+        ExtractContextService {
+            inner,
+            marker: PhantomData,
+        }
+    }
+}
+
+impl<T: Clone, I, RC> Clone for ExtractContextService<T, I, RC> {
+    fn clone(&self) -> Self {
+        ExtractContextService {
+            inner: self.inner.clone(),
+            marker: PhantomData,
+        }
+    }
+}
+
+impl<T, I, B, RC> Service<(Request<B>, RC)> for ExtractContextService<T, I, RC>
+where
+    RC: Push<I>,
+    T: Service<(Request<B>, RC::Result)>,
+    I: Propagate + Default,
+{
+    type Response = T::Response;
+    type Error = T::Error;
+    type Future = T::Future;
+
+    fn call(&self, req: (Request<B>, RC)) -> Self::Future {
+        let (request, context) = req;
+        let item = I::extract(request.headers()).unwrap_or_default();
+        self.inner.call((request, context.push(item)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    crate::new_context_type!(TestContext, TestEmptyContext, Option<Tenant>, Baggage);
+
+    type Context = TestContext<Baggage, TestContext<Option<Tenant>, TestEmptyContext>>;
+
+    /// Client which returns the headers of the request it is given.
+    struct HeaderService;
+
+    impl Service<(Request<()>, Context)> for HeaderService {
+        type Response = HeaderMap;
+        type Error = ();
+        type Future = futures::future::Ready<Result<Self::Response, Self::Error>>;
+
+        fn call(&self, req: (Request<()>, Context)) -> Self::Future {
+            futures::future::ok(req.0.headers().clone())
+        }
+    }
+
+    /// Server which forwards requests to a client, with its context.
+    struct ForwardService<C>(C);
+
+    impl<C> Service<(Request<()>, Context)> for ForwardService<C>
+    where
+        C: Service<(Request<()>, Context)>,
+    {
+        type Response = C::Response;
+        type Error = C::Error;
+        type Future = C::Future;
+
+        fn call(&self, req: (Request<()>, Context)) -> Self::Future {
+            self.0.call((Request::new(()), req.1))
+        }
+    }
+
+    #[tokio::test]
+    async fn test_propagation() {
+        let client = InjectContextService::<_, Baggage>::new(HeaderService);
+        let client = InjectContextService::<_, Option<Tenant>>::new(client);
+        let server = ExtractContextService::<_, Baggage, _>::new(ForwardService(client));
+        let server = ExtractContextService::<_, Option<Tenant>, TestEmptyContext>::new(server);
+
+        let request = Request::get("/")
+            .header(X_TENANT_ID, "acme")
+            .header(BAGGAGE, "region=eu%2Cwest;p=1;debug, user = alice")
+            .body(())
+            .unwrap();
+        let headers = server.call((request, TestEmptyContext)).await.unwrap();
+        assert_eq!(headers[X_TENANT_ID], "acme");
+        assert_eq!(headers[BAGGAGE], "region=eu%2Cwest;p=1;debug,user=alice");
+
+        let headers = server
+            .call((Request::new(()), TestEmptyContext))
+            .await
+            .unwrap();
+        assert!(headers.is_empty());
+    }
+
+    #[test]
+    fn test_span_id() {
+        let mut headers = HeaderMap::new();
+        XSpanIdString("span".to_string()).inject(&mut headers);
+        assert_eq!(XSpanIdString::extract(&headers).unwrap().0, "span");
+    }
+
+    #[test]
+    fn test_baggage() {
+        let mut headers = HeaderMap::new();
+        headers.insert(BAGGAGE, HeaderValue::from_static("a=1;x=y%3Dz, =2, b"));
+        let mut baggage = Baggage::extract(&headers).unwrap();
+        assert_eq!(
+            baggage.member("a"),
+            Some(&BaggageMember::new("a", "1").with_property("x", Some("y=z".to_string())))
+        );
+        assert_eq!(baggage.iter().count(), 1);
+
+        let large = "v".repeat(5000);
+        baggage.insert("large", large.as_str());
+        baggage.insert("larger", large.as_str());
+        let mut headers = HeaderMap::new();
+        baggage.inject(&mut headers);
+        assert_eq!(
+            headers[BAGGAGE].len(),
+            "a=1;x=y=z,large=".len() + large.len()
+        );
+
+        assert!(baggage.remove("a").is_some());
+        assert_eq!(baggage.get("a"), None);
+    }
+
+    #[test]
+    fn test_trace_context() {
+        let mut headers = HeaderMap::new();
+        let caller = TraceContext::new();
+        caller.inject(&mut headers);
+        let trace = TraceContext::extract(&headers).unwrap();
+        assert_eq!(trace.trace_id, caller.trace_id);
+        assert_ne!(trace.span_id, caller.span_id);
+    }
+}
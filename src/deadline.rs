@@ -0,0 +1,453 @@
+//! Request deadlines, propagated between services in a header.
+//!
+//! The `DeadlineService` middleware reads the time a caller is prepared to
+//! wait for a response from a header, and stores it in the context as an
+//! `Option<Deadline>`. Given a timer, it also stops processing requests whose
+//! deadline passes, responding with `504 Gateway Timeout`. Clients can pass
+//! the remaining time on to the services they call using
+//! `client::DeadlinePropagator`, so that a whole call chain gives up together.
+use crate::context::Push;
+use futures::future::{self, BoxFuture, Either, FutureExt};
+use hyper::header::{HeaderMap, HeaderName, HeaderValue};
+use hyper::rt::Timer;
+use hyper::service::Service;
+use hyper::{Request, Response, StatusCode};
+use std::fmt;
+use std::marker::PhantomData;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// Header holding the time remaining until the deadline, in milliseconds.
+pub const X_REQUEST_DEADLINE: HeaderName = HeaderName::from_static("x-request-deadline");
+/// Header holding the time remaining until the deadline, as used by gRPC.
+pub const GRPC_TIMEOUT: HeaderName = HeaderName::from_static("grpc-timeout");
+
+/// Time by which the response to a request is needed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Deadline(pub Instant);
+
+impl Deadline {
+    /// Deadline the given time from now.
+    pub fn after(timeout: Duration) -> Self {
+        Deadline(Instant::now() + timeout)
+    }
+
+    /// Time remaining until the deadline, or zero if it has passed.
+    pub fn remaining(&self) -> Duration {
+        self.0.saturating_duration_since(Instant::now())
+    }
+
+    /// Whether the deadline has passed.
+    pub fn is_expired(&self) -> bool {
+        self.0 <= Instant::now()
+    }
+}
+
+/// Format of the time remaining until a deadline, as sent in a header.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum DeadlineFormat {
+    /// Whole number of milliseconds, e.g. `250`.
+    Millis,
+    /// Up to 8 digits and a unit, as in gRPC's `grpc-timeout` header, e.g.
+    /// `250m`. The units are `H`, `M` and `S` for hours, minutes and seconds,
+    /// and `m`, `u` and `n` for milli-, micro- and nanoseconds.
+    GrpcTimeout,
+}
+
+impl DeadlineFormat {
+    /// Parse the time remaining until a deadline.
+    pub fn parse(&self, value: &str) -> Option<Duration> {
+        match self {
+            DeadlineFormat::Millis => value.trim().parse().ok().map(Duration::from_millis),
+            DeadlineFormat::GrpcTimeout => {
+                let value = value.trim();
+                let digits = value.get(..value.len().checked_sub(1)?)?;
+                if digits.is_empty() || digits.len() > 8 {
+                    return None;
+                }
+                let amount: u64 = digits.parse().ok()?;
+                match &value[digits.len()..] {
+                    "H" => Some(Duration::from_secs(amount * 3600)),
+                    "M" => Some(Duration::from_secs(amount * 60)),
+                    "S" => Some(Duration::from_secs(amount)),
+                    "m" => Some(Duration::from_millis(amount)),
+                    "u" => Some(Duration::from_micros(amount)),
+                    "n" => Some(Duration::from_nanos(amount)),
+                    _ => None,
+                }
+            }
+        }
+    }
+
+    /// Format the time remaining until a deadline.
+    pub fn format(&self, remaining: Duration) -> String {
+        match self {
+            DeadlineFormat::Millis => remaining.as_millis().to_string(),
+            DeadlineFormat::GrpcTimeout => {
+                // Use the most precise unit which fits in 8 digits, rounding
+                // down so as not to extend the deadline.
+                const MAX: u128 = 99_999_999;
+                let nanos = remaining.as_nanos();
+                [
+                    (1, "n"),
+                    (1_000, "u"),
+                    (1_000_000, "m"),
+                    (1_000_000_000, "S"),
+                    (60_000_000_000, "M"),
+                    (3_600_000_000_000, "H"),
+                ]
+                .iter()
+                .find(|(scale, _)| nanos / scale <= MAX)
+                .map_or_else(
+                    || format!("{}H", MAX),
+                    |(scale, unit)| format!("{}{}", nanos / scale, unit),
+                )
+            }
+        }
+    }
+}
+
+/// Header holding the time remaining until a request's deadline, and its
+/// format.
+///
+/// The default is `X-Request-Deadline`, in milliseconds.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DeadlineHeader {
+    name: HeaderName,
+    format: DeadlineFormat,
+}
+
+impl Default for DeadlineHeader {
+    fn default() -> Self {
+        DeadlineHeader::new(X_REQUEST_DEADLINE, DeadlineFormat::Millis)
+    }
+}
+
+impl DeadlineHeader {
+    /// Header with the given name and format.
+    pub fn new(name: HeaderName, format: DeadlineFormat) -> Self {
+        DeadlineHeader { name, format }
+    }
+
+    /// The `grpc-timeout` header.
+    pub fn grpc_timeout() -> Self {
+        DeadlineHeader::new(GRPC_TIMEOUT, DeadlineFormat::GrpcTimeout)
+    }
+
+    /// Deadline of a request received now with the given headers, if it has
+    /// one.
+    pub fn deadline(&self, headers: &HeaderMap) -> Option<Deadline> {
+        headers
+            .get(&self.name)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| self.format.parse(value))
+            .map(Deadline::after)
+    }
+
+    /// Set the header to the time remaining until the deadline.
+    pub fn insert(&self, headers: &mut HeaderMap, deadline: Deadline) {
+        if let Ok(value) = HeaderValue::try_from(self.format.format(deadline.remaining())) {
+            headers.insert(self.name.clone(), value);
+        }
+    }
+}
+
+#[derive(Clone, Default)]
+struct Config {
+    header: DeadlineHeader,
+    default_timeout: Option<Duration>,
+    max_timeout: Option<Duration>,
+    timer: Option<Arc<dyn Timer + Send + Sync>>,
+}
+
+impl fmt::Debug for Config {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Config")
+            .field("header", &self.header)
+            .field("default_timeout", &self.default_timeout)
+            .field("max_timeout", &self.max_timeout)
+            .field("timer", &self.timer.is_some())
+            .finish()
+    }
+}
+
+impl Config {
+    fn deadline(&self, headers: &HeaderMap) -> Option<Deadline> {
+        let requested = self
+            .header
+            .deadline(headers)
+            .or_else(|| self.default_timeout.map(Deadline::after));
+        match (requested, self.max_timeout.map(Deadline::after)) {
+            (Some(requested), Some(max)) => Some(requested.min(max)),
+            (requested, max) => requested.or(max),
+        }
+    }
+}
+
+/// Middleware which stores the deadline of each request in its context, and
+/// optionally enforces it.
+#[derive(Debug)]
+pub struct MakeDeadlineService<T, RC> {
+    inner: T,
+    config: Arc<Config>,
+    marker: PhantomData<RC>,
+}
+
+impl<T, RC> MakeDeadlineService<T, RC> {
+    /// Create a middleware which reads deadlines from the given header.
+    pub fn new(inner: T, header: DeadlineHeader) -> Self {
+        MakeDeadlineService {
+            inner,
+            config: Arc::new(Config {
+                header,
+                ..Config::default()
+            }),
+            marker: PhantomData,
+        }
+    }
+
+    /// Give requests without a deadline one the given time after they're
+    /// received.
+    pub fn with_default_timeout(mut self, timeout: Duration) -> Self {
+        Arc::make_mut(&mut self.config).default_timeout = Some(timeout);
+        self
+    }
+
+    /// Limit deadlines to the given time after requests are received.
+    pub fn with_max_timeout(mut self, timeout: Duration) -> Self {
+        Arc::make_mut(&mut self.config).max_timeout = Some(timeout);
+        self
+    }
+
+    /// Use the given timer to enforce deadlines.
+    pub fn with_timer<M: Timer + Send + Sync + 'static>(mut self, timer: M) -> Self {
+        Arc::make_mut(&mut self.config).timer = Some(Arc::new(timer));
+        self
+    }
+}
+
+impl<T: Clone, RC> Clone for MakeDeadlineService<T, RC> {
+    fn clone(&self) -> Self {
+        MakeDeadlineService {
+            inner: self.inner.clone(),
+            config: self.config.clone(),
+            marker: PhantomData,
+        }
+    }
+}
+
+impl<Inner, RC, Target> Service<Target> for MakeDeadlineService<Inner, RC>
+where
+    Inner: Service<Target>,
+    Inner::Future: Send + 'static,
+    RC: Send + 'static,
+{
+    type Error = Inner::Error;
+    type Response = DeadlineService<Inner::Response, RC>;
+    type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn call(&self, target: Target) -> Self::Future {
+        let config = self.config.clone();
+        Box::pin(self.inner.call(target).map(|s| {
+            Ok(DeadlineService {
+                inner: s?,
+                config,
+                marker: PhantomData,
+            })
+        }))
+    }
+}
+
+/// Middleware which stores the deadline of each request in its context, as
+/// an `Option<Deadline>`, and optionally enforces it.
+///
+/// If a timer is provided, the inner service's future is dropped when the
+/// deadline passes, and the request is answered with `504 Gateway Timeout`.
+/// Requests whose deadline has already passed are answered immediately.
+///
+/// ```ignore
+/// let service = DeadlineService::new(inner, DeadlineHeader::grpc_timeout())
+///     .with_max_timeout(Duration::from_secs(30))
+///     .with_timer(TokioTimer::new());
+/// ```
+#[derive(Debug)]
+pub struct DeadlineService<T, RC> {
+    inner: T,
+    config: Arc<Config>,
+    marker: PhantomData<RC>,
+}
+
+impl<T, RC> DeadlineService<T, RC> {
+    /// Create a middleware which reads deadlines from the given header.
+    pub fn new(inner: T, header: DeadlineHeader) -> Self {
+        DeadlineService {
+            inner,
+            config: Arc::new(Config {
+                header,
+                ..Config::default()
+            }),
+            marker: PhantomData,
+        }
+    }
+
+    /// Give requests without a deadline one the given time after they're
+    /// received.
+    pub fn with_default_timeout(mut self, timeout: Duration) -> Self {
+        Arc::make_mut(&mut self.config).default_timeout = Some(timeout);
+        self
+    }
+
+    /// Limit deadlines to the given time after requests are received.
+    pub fn with_max_timeout(mut self, timeout: Duration) -> Self {
+        Arc::make_mut(&mut self.config).max_timeout = Some(timeout);
+        self
+    }
+
+    /// Use the given timer to enforce deadlines.
+    pub fn with_timer<M: Timer + Send + Sync + 'static>(mut self, timer: M) -> Self {
+        Arc::make_mut(&mut self.config).timer = Some(Arc::new(timer));
+        self
+    }
+}
+
+impl<T: Clone, RC> Clone for DeadlineService<T, RC> {
+    fn clone(&self) -> Self {
+        DeadlineService {
+            inner: self.inner.clone(),
+            config: self.config.clone(),
+            marker: PhantomData,
+        }
+    }
+}
+
+fn gateway_timeout<B: Default>() -> Response<B> {
+    let mut response = Response::new(B::default());
+    *response.status_mut() = StatusCode::GATEWAY_TIMEOUT;
+    response
+}
+
+impl<T, B, ResBody, RC> Service<(Request<B>, RC)> for DeadlineService<T, RC>
+where
+    RC: Push<Option<Deadline>>,
+    T: Service<(Request<B>, RC::Result), Response = Response<ResBody>>,
+    T::Future: Send + 'static,
+    T::Error: Send + 'static,
+    ResBody: Default + Send + 'static,
+{
+    type Response = Response<ResBody>;
+    type Error = T::Error;
+    type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn call(&self, req: (Request<B>, RC)) -> Self::Future {
+        let (request, context) = req;
+        let deadline = self.config.deadline(request.headers());
+
+        let timer = match (deadline, &self.config.timer) {
+            (Some(deadline), Some(timer)) => {
+                if deadline.is_expired() {
+                    return Box::pin(future::ok(gateway_timeout()));
+                }
+                Some(timer.sleep(deadline.remaining()))
+            }
+            _ => None,
+        };
+
+        let response = self.inner.call((request, context.push(deadline)));
+        match timer {
+            Some(sleep) => {
+                Box::pin(
+                    future::select(Box::pin(response), sleep).map(|result| match result {
+                        Either::Left((response, _)) => response,
+                        Either::Right(_) => Ok(gateway_timeout()),
+                    }),
+                )
+            }
+            None => Box::pin(response),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::context::Has;
+    use hyper_util::rt::TokioTimer;
+
+    crate::new_context_type!(TestContext, TestEmptyContext, Option<Deadline>);
+
+    type Context = TestContext<Option<Deadline>, TestEmptyContext>;
+
+    /// Service responding with the time remaining in milliseconds, after
+    /// sleeping for the time requested in the path.
+    #[derive(Clone)]
+    struct SleepService;
+
+    impl Service<(Request<()>, Context)> for SleepService {
+        type Response = Response<String>;
+        type Error = ();
+        type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+        fn call(&self, req: (Request<()>, Context)) -> Self::Future {
+            let remaining = Has::<Option<Deadline>>::get(&req.1).map(|d| d.remaining());
+            let sleep: u64 = req.0.uri().path()[1..].parse().unwrap();
+            Box::pin(async move {
+                tokio::time::sleep(Duration::from_millis(sleep)).await;
+                Ok(Response::new(format!(
+                    "{:?}",
+                    remaining.map(|r| (r.as_millis() + 50) / 100 * 100)
+                )))
+            })
+        }
+    }
+
+    #[test]
+    fn test_formats() {
+        let grpc = DeadlineFormat::GrpcTimeout;
+        assert_eq!(grpc.parse("250m"), Some(Duration::from_millis(250)));
+        assert_eq!(grpc.parse("2H"), Some(Duration::from_secs(7200)));
+        assert_eq!(grpc.parse("123456789S"), None);
+        assert_eq!(grpc.parse("m"), None);
+        assert_eq!(grpc.parse("10x"), None);
+        assert_eq!(grpc.format(Duration::from_millis(250)), "250000u");
+        assert_eq!(grpc.format(Duration::from_secs(1000)), "1000000m");
+        assert_eq!(grpc.format(Duration::from_secs(10_000_000)), "10000000S");
+
+        let millis = DeadlineFormat::Millis;
+        assert_eq!(millis.parse("1500"), Some(Duration::from_millis(1500)));
+        assert_eq!(millis.parse("-1"), None);
+        assert_eq!(millis.format(Duration::from_micros(1500)), "1");
+    }
+
+    #[tokio::test]
+    async fn test_deadline_service() {
+        let service = DeadlineService::<_, TestEmptyContext>::new(SleepService, Default::default());
+        // Settings made once the service has been cloned still apply to it.
+        let _clone = service.clone();
+        let service = service
+            .with_max_timeout(Duration::from_millis(500))
+            .with_timer(TokioTimer::new());
+        let call = |path: &str, deadline: Option<&'static str>| {
+            let mut request = Request::get(path);
+            if let Some(deadline) = deadline {
+                request = request.header(X_REQUEST_DEADLINE, deadline);
+            }
+            service.call((request.body(()).unwrap(), TestEmptyContext))
+        };
+
+        let response = call("/0", Some("200")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.body(), "Some(200)");
+
+        // Deadlines are limited to the maximum.
+        let response = call("/0", Some("10000")).await.unwrap();
+        assert_eq!(response.body(), "Some(500)");
+        let response = call("/0", None).await.unwrap();
+        assert_eq!(response.body(), "Some(500)");
+
+        let response = call("/1000", Some("100")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::GATEWAY_TIMEOUT);
+
+        let response = call("/0", Some("0")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::GATEWAY_TIMEOUT);
+    }
+}
@@ -0,0 +1,399 @@
+//! Signalling deprecation of API operations.
+//!
+//! Operations marked `deprecated: true` in an OpenAPI spec can be advertised
+//! to clients using the `Deprecation` header from RFC 9745, the `Sunset`
+//! header from RFC 8594, and a `Link` to documentation with the `deprecation`
+//! relation type. The `DeprecationService` middleware adds these headers to
+//! responses from deprecated routes, and `Deprecation::from_headers` allows
+//! clients to detect them.
+use futures::future::{BoxFuture, FutureExt};
+use hyper::header::{HeaderMap, HeaderName, HeaderValue, LINK};
+use hyper::service::Service;
+use hyper::{Method, Request, Response};
+use std::marker::PhantomData;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Header name for the deprecation date of a resource - RFC 9745
+pub const DEPRECATION: HeaderName = HeaderName::from_static("deprecation");
+
+/// Header name for the date after which a resource will be unavailable - RFC 8594
+pub const SUNSET: HeaderName = HeaderName::from_static("sunset");
+
+/// Deprecation details of an operation.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Deprecation {
+    /// When the operation was or will be deprecated, if known.
+    pub since: Option<SystemTime>,
+    /// When the operation will stop being available, if known.
+    pub sunset: Option<SystemTime>,
+    /// URI of documentation about the deprecation, such as a migration guide.
+    pub link: Option<String>,
+}
+
+impl Deprecation {
+    /// Deprecation with no further details.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set when the operation was or will be deprecated.
+    pub fn since(mut self, since: SystemTime) -> Self {
+        self.since = Some(since);
+        self
+    }
+
+    /// Set when the operation will stop being available.
+    pub fn sunset(mut self, sunset: SystemTime) -> Self {
+        self.sunset = Some(sunset);
+        self
+    }
+
+    /// Set the URI of documentation about the deprecation.
+    pub fn link<U: Into<String>>(mut self, link: U) -> Self {
+        self.link = Some(link.into());
+        self
+    }
+
+    /// Add headers describing this deprecation to a response.
+    ///
+    /// If the deprecation date isn't known, the operation is described as
+    /// deprecated since the Unix epoch, i.e. already deprecated.
+    pub fn apply(&self, headers: &mut HeaderMap) {
+        let since = self
+            .since
+            .and_then(|since| since.duration_since(UNIX_EPOCH).ok())
+            .unwrap_or_default();
+        if let Ok(value) = HeaderValue::from_str(&format!("@{}", since.as_secs())) {
+            headers.insert(DEPRECATION, value);
+        }
+
+        if let Some(sunset) = self.sunset {
+            if let Ok(value) = HeaderValue::from_str(&httpdate::fmt_http_date(sunset)) {
+                headers.insert(SUNSET, value);
+            }
+        }
+
+        if let Some(ref link) = self.link {
+            let link = format!("<{}>; rel=\"deprecation\"; type=\"text/html\"", link);
+            if let Ok(value) = HeaderValue::from_str(&link) {
+                headers.append(LINK, value);
+            }
+        }
+    }
+
+    /// Retrieve deprecation details from the headers of a response, if the
+    /// operation is deprecated.
+    ///
+    /// As well as the RFC 9745 format, the `Deprecation: true` and HTTP-date
+    /// formats from earlier drafts are accepted.
+    pub fn from_headers(headers: &HeaderMap) -> Option<Self> {
+        let deprecation = headers.get(DEPRECATION)?.to_str().ok()?.trim();
+        let since = if let Some(seconds) = deprecation.strip_prefix('@') {
+            Some(UNIX_EPOCH + Duration::from_secs(seconds.parse().ok()?))
+        } else if deprecation == "true" {
+            None
+        } else {
+            Some(httpdate::parse_http_date(deprecation).ok()?)
+        };
+
+        let sunset = headers
+            .get(SUNSET)
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| httpdate::parse_http_date(v.trim()).ok());
+
+        let link = headers
+            .get_all(LINK)
+            .iter()
+            .filter_map(|v| v.to_str().ok())
+            .flat_map(split_links)
+            .find_map(deprecation_link);
+
+        Some(Deprecation {
+            since,
+            sunset,
+            link,
+        })
+    }
+}
+
+/// Split a `Link` header into its individual links.
+fn split_links(header: &str) -> Vec<&str> {
+    let mut links = Vec::new();
+    let mut start = 0;
+    for (index, _) in header.match_indices(',') {
+        // Commas may appear within a link's parameters, so only split before a new URI.
+        if header[index + 1..].trim_start().starts_with('<') {
+            links.push(&header[start..index]);
+            start = index + 1;
+        }
+    }
+    links.push(&header[start..]);
+    links
+}
+
+/// The URI of a link, if it has the `deprecation` relation type.
+fn deprecation_link(link: &str) -> Option<String> {
+    let mut parts = link.split(';');
+    let uri = parts.next()?.trim().strip_prefix('<')?.strip_suffix('>')?;
+    parts
+        .filter_map(|param| param.split_once('='))
+        .any(|(name, value)| {
+            name.trim().eq_ignore_ascii_case("rel")
+                && value
+                    .trim()
+                    .trim_matches('"')
+                    .split_ascii_whitespace()
+                    .any(|rel| rel.eq_ignore_ascii_case("deprecation"))
+        })
+        .then(|| uri.to_string())
+}
+
+#[derive(Clone, Debug)]
+struct DeprecatedRoute {
+    method: Method,
+    path: String,
+    deprecation: Deprecation,
+}
+
+/// Map from route to the deprecation details of its operation.
+///
+/// Routes are identified by a method and an OpenAPI path template, in which
+/// segments such as `{petId}` match any value.
+#[derive(Clone, Debug, Default)]
+pub struct DeprecationTable {
+    routes: Vec<DeprecatedRoute>,
+}
+
+impl DeprecationTable {
+    /// Create an empty table, in which no route is deprecated.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Mark the operation with the given method and path template as deprecated.
+    pub fn deprecate<P: Into<String>>(
+        mut self,
+        method: Method,
+        path: P,
+        deprecation: Deprecation,
+    ) -> Self {
+        self.routes.push(DeprecatedRoute {
+            method,
+            path: path.into(),
+            deprecation,
+        });
+        self
+    }
+
+    /// Deprecation details for a request with the given method and path, or
+    /// `None` if it isn't deprecated.
+    pub fn get(&self, method: &Method, path: &str) -> Option<&Deprecation> {
+        self.routes
+            .iter()
+            .find(|route| route.method == method && template_matches(&route.path, path))
+            .map(|route| &route.deprecation)
+    }
+}
+
+fn template_matches(template: &str, path: &str) -> bool {
+    let mut template = template.split('/');
+    let mut path = path.split('/');
+    loop {
+        match (template.next(), path.next()) {
+            (None, None) => return true,
+            (Some(t), Some(p)) if t == p || (t.starts_with('{') && t.ends_with('}')) => {}
+            _ => return false,
+        }
+    }
+}
+
+/// Middleware which adds deprecation headers to responses from deprecated routes.
+#[derive(Debug)]
+pub struct MakeDeprecationService<T, RC> {
+    inner: T,
+    table: Arc<DeprecationTable>,
+    marker: PhantomData<RC>,
+}
+
+impl<T, RC> MakeDeprecationService<T, RC> {
+    /// Create a middleware which marks the routes in the table as deprecated.
+    pub fn new(inner: T, table: DeprecationTable) -> Self {
+        MakeDeprecationService {
+            inner,
+            table: Arc::new(table),
+            marker: PhantomData,
+        }
+    }
+}
+
+impl<Inner, RC, Target> Service<Target> for MakeDeprecationService<Inner, RC>
+where
+    Inner: Service<Target>,
+    Inner::Future: Send + 'static,
+    RC: Send + 'static,
+{
+    type Error = Inner::Error;
+    type Response = DeprecationService<Inner::Response, RC>;
+    type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn call(&self, target: Target) -> Self::Future {
+        let table = self.table.clone();
+        Box::pin(self.inner.call(target).map(|s| {
+            Ok(DeprecationService {
+                inner: s?,
+                table,
+                marker: PhantomData,
+            })
+        }))
+    }
+}
+
+/// Middleware which adds deprecation headers to responses from deprecated routes.
+///
+/// ```ignore
+/// let table = DeprecationTable::new().deprecate(
+///     Method::GET,
+///     "/pets/{petId}/owner",
+///     Deprecation::new()
+///         .sunset(sunset)
+///         .link("https://example.com/docs/migrating-owners"),
+/// );
+/// let service = DeprecationService::new(inner, table);
+/// ```
+#[derive(Debug)]
+pub struct DeprecationService<T, RC> {
+    inner: T,
+    table: Arc<DeprecationTable>,
+    marker: PhantomData<RC>,
+}
+
+impl<T, RC> DeprecationService<T, RC> {
+    /// Create a middleware which marks the routes in the table as deprecated.
+    pub fn new(inner: T, table: DeprecationTable) -> Self {
+        DeprecationService {
+            inner,
+            table: Arc::new(table),
+            marker: PhantomData,
+        }
+    }
+}
+
+impl<T, RC> Clone for DeprecationService<T, RC>
+where
+    T: Clone,
+{
+    fn clone(&self) -> Self {
+        Self {
+            inner: self.inner.clone(),
+            table: self.table.clone(),
+            marker: PhantomData,
+        }
+    }
+}
+
+impl<T, B, ResBody, RC> Service<(Request<B>, RC)> for DeprecationService<T, RC>
+where
+    T: Service<(Request<B>, RC), Response = Response<ResBody>>,
+    T::Future: Send + 'static,
+{
+    type Response = Response<ResBody>;
+    type Error = T::Error;
+    type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn call(&self, req: (Request<B>, RC)) -> Self::Future {
+        let deprecation = self.table.get(req.0.method(), req.0.uri().path()).cloned();
+
+        Box::pin(self.inner.call(req).map(move |response| {
+            let mut response = response?;
+            if let Some(deprecation) = deprecation {
+                deprecation.apply(response.headers_mut());
+            }
+            Ok(response)
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::EmptyContext;
+
+    struct OkService;
+
+    impl Service<(Request<()>, EmptyContext)> for OkService {
+        type Response = Response<()>;
+        type Error = ();
+        type Future = futures::future::Ready<Result<Self::Response, Self::Error>>;
+
+        fn call(&self, _: (Request<()>, EmptyContext)) -> Self::Future {
+            futures::future::ok(Response::new(()))
+        }
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let deprecation = Deprecation::new()
+            .since(UNIX_EPOCH + Duration::from_secs(1688169599))
+            .sunset(UNIX_EPOCH + Duration::from_secs(1719791999))
+            .link("https://example.com/deprecation");
+
+        let mut headers = HeaderMap::new();
+        deprecation.apply(&mut headers);
+        assert_eq!(headers[DEPRECATION], "@1688169599");
+        assert_eq!(headers[SUNSET], "Sun, 30 Jun 2024 23:59:59 GMT");
+        assert_eq!(
+            headers[LINK],
+            "<https://example.com/deprecation>; rel=\"deprecation\"; type=\"text/html\""
+        );
+        assert_eq!(Deprecation::from_headers(&headers), Some(deprecation));
+    }
+
+    #[test]
+    fn test_from_headers() {
+        let mut headers = HeaderMap::new();
+        assert_eq!(Deprecation::from_headers(&headers), None);
+
+        headers.insert(DEPRECATION, HeaderValue::from_static("true"));
+        headers.insert(
+            LINK,
+            HeaderValue::from_static(
+                "<https://example.com/next?a=1,2>; rel=\"next\", <https://example.com/dep>; rel=\"deprecation\"",
+            ),
+        );
+        assert_eq!(
+            Deprecation::from_headers(&headers),
+            Some(Deprecation::new().link("https://example.com/dep"))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_deprecation_service() {
+        let table = DeprecationTable::new().deprecate(
+            Method::GET,
+            "/pets/{petId}/owner",
+            Deprecation::new(),
+        );
+        let service = DeprecationService::new(OkService, table);
+        let call = |method: Method, path: &str| {
+            let request = Request::builder()
+                .method(method)
+                .uri(path)
+                .body(())
+                .unwrap();
+            service.call((request, EmptyContext))
+        };
+
+        let response = call(Method::GET, "/pets/1/owner").await.unwrap();
+        assert_eq!(response.headers()[DEPRECATION], "@0");
+
+        for (method, path) in [
+            (Method::PUT, "/pets/1/owner"),
+            (Method::GET, "/pets/1"),
+            (Method::GET, "/pets/1/owner/name"),
+        ] {
+            let response = call(method, path).await.unwrap();
+            assert!(response.headers().get(DEPRECATION).is_none());
+        }
+    }
+}
@@ -0,0 +1,202 @@
+//! Generation of representative values of model types.
+//!
+//! Mock servers and test harnesses need plausible payloads for operations
+//! whose specification has no explicit examples. `SampleValue` gives a
+//! representative instance of a type, and is implemented for primitives,
+//! collections and this crate's wrapper types. With the **derive** feature,
+//! it can be derived for models:
+//!
+//! ```ignore
+//! #[derive(Serialize, SampleValue)]
+//! struct Pet {
+//!     id: u64,
+//!     name: String,
+//!     tags: Option<Vec<String>>,
+//! }
+//!
+//! // {"id":1,"name":"string","tags":["string"]}
+//! let body = serde_json::to_string(&Pet::sample())?;
+//! ```
+//!
+//! Samples are deterministic, so they can be compared in tests. Optional and
+//! nullable values are present, and collections have a single element, so
+//! that the sample shows the shape of the whole type. Enums take their first
+//! variant, as do `oneOf` and `anyOf` types.
+use crate::{ByteArray, ByteSize, Nullable};
+use std::collections::{BTreeMap, HashMap};
+
+/// Type with a representative value.
+pub trait SampleValue {
+    /// Representative value of the type.
+    fn sample() -> Self;
+}
+
+/// Derive `SampleValue` for a struct, giving each field its sample, or for
+/// an enum, giving its first variant.
+#[cfg(feature = "derive")]
+pub use swagger_derive::SampleValue;
+
+macro_rules! impl_sample_value {
+    ($value:expr, $($t:ty),*) => {
+        $(
+            impl SampleValue for $t {
+                fn sample() -> Self {
+                    $value
+                }
+            }
+        )*
+    };
+}
+
+impl_sample_value!(1, i8, i16, i32, i64, i128, isize, u8, u16, u32, u64, u128, usize);
+impl_sample_value!(1.5, f32, f64);
+impl_sample_value!(true, bool);
+impl_sample_value!("string".to_string(), String);
+impl_sample_value!(ByteArray(b"sample".to_vec()), ByteArray);
+impl_sample_value!(ByteSize(1024), ByteSize);
+impl_sample_value!(
+    uuid::Uuid::from_u128(0x6e8b_d7a4_3f5c_4b6e_9a0d_2c1f_8e7b_5a49),
+    uuid::Uuid
+);
+
+#[cfg(feature = "serdejson")]
+impl_sample_value!(
+    serde_json::Value::Object(Default::default()),
+    serde_json::Value
+);
+
+impl<T: SampleValue> SampleValue for Option<T> {
+    fn sample() -> Self {
+        Some(T::sample())
+    }
+}
+
+impl<T: SampleValue> SampleValue for Nullable<T> {
+    fn sample() -> Self {
+        Nullable::Present(T::sample())
+    }
+}
+
+impl<T: SampleValue> SampleValue for Box<T> {
+    fn sample() -> Self {
+        Box::new(T::sample())
+    }
+}
+
+impl<T: SampleValue> SampleValue for Vec<T> {
+    fn sample() -> Self {
+        vec![T::sample()]
+    }
+}
+
+impl<T: SampleValue> SampleValue for HashMap<String, T> {
+    fn sample() -> Self {
+        HashMap::from([("key".to_string(), T::sample())])
+    }
+}
+
+impl<T: SampleValue> SampleValue for BTreeMap<String, T> {
+    fn sample() -> Self {
+        BTreeMap::from([("key".to_string(), T::sample())])
+    }
+}
+
+macro_rules! impl_sample_value_variants {
+    ($($t:ident<$first:ident $(, $others:ident)*>),* $(,)?) => {
+        $(
+            impl<$first: SampleValue + PartialEq $(, $others: PartialEq)*> SampleValue
+                for crate::$t<$first $(, $others)*>
+            {
+                fn sample() -> Self {
+                    crate::$t::$first($first::sample())
+                }
+            }
+        )*
+    };
+}
+
+impl_sample_value_variants!(
+    OneOf1<A>,
+    OneOf2<A, B>,
+    OneOf3<A, B, C>,
+    OneOf4<A, B, C, D>,
+    OneOf5<A, B, C, D, E>,
+    OneOf6<A, B, C, D, E, F>,
+    OneOf7<A, B, C, D, E, F, G>,
+    OneOf8<A, B, C, D, E, F, G, H>,
+    OneOf9<A, B, C, D, E, F, G, H, I>,
+    OneOf10<A, B, C, D, E, F, G, H, I, J>,
+    OneOf11<A, B, C, D, E, F, G, H, I, J, K>,
+    OneOf12<A, B, C, D, E, F, G, H, I, J, K, L>,
+    OneOf13<A, B, C, D, E, F, G, H, I, J, K, L, M>,
+    OneOf14<A, B, C, D, E, F, G, H, I, J, K, L, M, N>,
+    OneOf15<A, B, C, D, E, F, G, H, I, J, K, L, M, N, O>,
+    OneOf16<A, B, C, D, E, F, G, H, I, J, K, L, M, N, O, P>,
+    AnyOf1<A>,
+    AnyOf2<A, B>,
+    AnyOf3<A, B, C>,
+    AnyOf4<A, B, C, D>,
+    AnyOf5<A, B, C, D, E>,
+    AnyOf6<A, B, C, D, E, F>,
+    AnyOf7<A, B, C, D, E, F, G>,
+    AnyOf8<A, B, C, D, E, F, G, H>,
+    AnyOf9<A, B, C, D, E, F, G, H, I>,
+    AnyOf10<A, B, C, D, E, F, G, H, I, J>,
+    AnyOf11<A, B, C, D, E, F, G, H, I, J, K>,
+    AnyOf12<A, B, C, D, E, F, G, H, I, J, K, L>,
+    AnyOf13<A, B, C, D, E, F, G, H, I, J, K, L, M>,
+    AnyOf14<A, B, C, D, E, F, G, H, I, J, K, L, M, N>,
+    AnyOf15<A, B, C, D, E, F, G, H, I, J, K, L, M, N, O>,
+    AnyOf16<A, B, C, D, E, F, G, H, I, J, K, L, M, N, O, P>,
+);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::OneOf2;
+
+    #[test]
+    fn test_samples() {
+        assert_eq!(Option::<Vec<u32>>::sample(), Some(vec![1]));
+        assert_eq!(
+            Nullable::<String>::sample(),
+            Nullable::Present("string".to_string())
+        );
+        assert_eq!(
+            HashMap::<String, bool>::sample(),
+            HashMap::from([("key".to_string(), true)])
+        );
+        assert_eq!(OneOf2::<f64, String>::sample(), OneOf2::A(1.5));
+        assert_eq!(uuid::Uuid::sample(), uuid::Uuid::sample());
+    }
+
+    #[cfg(feature = "derive")]
+    #[test]
+    fn test_derive() {
+        #[derive(Debug, PartialEq, SampleValue)]
+        enum Status {
+            Available,
+            #[allow(dead_code)]
+            Sold,
+        }
+
+        #[derive(Debug, PartialEq, SampleValue)]
+        struct Tag(String);
+
+        #[derive(Debug, PartialEq, SampleValue)]
+        struct Pet {
+            id: u64,
+            status: Status,
+            tags: Option<Vec<Tag>>,
+        }
+
+        assert_eq!(
+            Pet::sample(),
+            Pet {
+                id: 1,
+                status: Status::Available,
+                tags: Some(vec![Tag("string".to_string())]),
+            }
+        );
+    }
+}
@@ -0,0 +1,484 @@
+//! Reference wiring of this crate's server and client middleware.
+//!
+//! Rather than copying an example, downstream projects and code generator
+//! templates can start from these known-good stacks, which are tested end to
+//! end as part of this crate:
+//!
+//! - `server` builds a `CompositeMakeService` serving `PetApi`, a small
+//!   reference API, behind `AddContextMakeService`, `MakeApiKeyExtractor` and
+//!   `MakeFnAuthenticator`, alongside an unauthenticated health check.
+//! - `ExampleClient` sends requests through `AuthInjector` and
+//!   `DropContextService`, as a generated client would.
+//! - `MockDownstream` stands in for a downstream server, recording the
+//!   requests it receives and returning configured responses, or samples of
+//!   model types generated by `SampleValue`.
+//!
+//! ```
+//! use hyper::service::Service;
+//! use swagger::examples_support::{server, ExampleClient};
+//!
+//! # tokio_test::block_on(async {
+//! let server = server("my-api-key").call(None).await.unwrap();
+//! let client = ExampleClient::new(server, "my-api-key");
+//! let response = client.get_pet(1).await.unwrap();
+//! assert_eq!(response.status(), 200);
+//! # });
+//! ```
+use crate::auth::{
+    ApiKeyLocation, Authorization, Challenge, MakeApiKeyExtractor, MakeFnAuthenticator, Scopes,
+};
+use crate::client::AuthInjector;
+use crate::composites::CompositeMakeService;
+use crate::context::Has;
+use crate::multipart::form::boundary;
+use crate::{
+    AddContextMakeService, ApiError, AuthData, ContentDisposition, DropContextService,
+    RedactedHeaders, SampleValue, XSpanIdString, X_SPAN_ID,
+};
+use futures::future::{BoxFuture, FutureExt};
+use http_body_util::{BodyExt, Full};
+use hyper::body::{Body, Bytes};
+use hyper::header::{HeaderValue, CONTENT_TYPE};
+use hyper::service::Service;
+use hyper::{Method, Request, Response, StatusCode};
+use std::collections::HashMap;
+use std::fmt;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+
+/// Body of requests and responses in the reference stacks.
+pub type ExampleBody = Full<Bytes>;
+
+crate::new_context_type!(
+    ExampleContext,
+    ExampleEmptyContext,
+    Option<Authorization>,
+    Option<AuthData>,
+    XSpanIdString
+);
+
+/// Context of requests to `PetApi`, as built by the `server` stack.
+pub type ServerContext = crate::make_context_ty!(
+    ExampleContext,
+    ExampleEmptyContext,
+    Option<Authorization>,
+    Option<AuthData>,
+    XSpanIdString
+);
+
+/// Context of requests sent by `ExampleClient`.
+pub type ClientContext = crate::make_context_ty!(
+    ExampleContext,
+    ExampleEmptyContext,
+    Option<AuthData>,
+    XSpanIdString
+);
+
+/// Name of the header carrying the API key.
+pub const API_KEY_HEADER: &str = "X-API-Key";
+
+fn response(status: StatusCode, body: String) -> Response<ExampleBody> {
+    let mut response = Response::new(Full::new(Bytes::from(body)));
+    *response.status_mut() = status;
+    response
+}
+
+/// Reference implementation of a generated API, for a pet store.
+///
+/// - `GET /pets/{id}` returns a JSON description of the pet, including the
+///   subject of the request's authorization and its `X-Span-ID`.
+/// - `POST /pets/{id}/photos` accepts a `multipart/form-data` body, and
+///   returns the number of parts.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct PetApi;
+
+impl<B, C> Service<(Request<B>, C)> for PetApi
+where
+    B: Body + Send + 'static,
+    B::Data: Send,
+    B::Error: fmt::Display,
+    C: Has<XSpanIdString> + Has<Option<Authorization>> + Send + 'static,
+{
+    type Response = Response<ExampleBody>;
+    type Error = ApiError;
+    type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn call(&self, (request, context): (Request<B>, C)) -> Self::Future {
+        Box::pin(async move {
+            let segments: Vec<&str> = request.uri().path().split('/').skip(1).collect();
+            let id = match segments.get(..2) {
+                Some(["pets", id]) => match id.parse::<u64>() {
+                    Ok(id) => id,
+                    Err(_) => return Ok(response(StatusCode::BAD_REQUEST, String::new())),
+                },
+                _ => return Ok(response(StatusCode::NOT_FOUND, String::new())),
+            };
+
+            match (request.method(), &segments[2..]) {
+                (&Method::GET, []) => {
+                    let subject = Has::<Option<Authorization>>::get(&context)
+                        .as_ref()
+                        .map(|authorization| authorization.subject.clone());
+                    let span_id = Has::<XSpanIdString>::get(&context).to_string();
+                    let body = serde_json::json!({
+                        "id": id,
+                        "owner": subject,
+                        "spanId": span_id,
+                    });
+                    let mut response = response(StatusCode::OK, body.to_string());
+                    response
+                        .headers_mut()
+                        .insert(CONTENT_TYPE, HeaderValue::from_static("application/json"));
+                    Ok(response)
+                }
+                (&Method::POST, ["photos"]) => {
+                    let Some(boundary) = boundary(request.headers()) else {
+                        return Ok(response(StatusCode::UNSUPPORTED_MEDIA_TYPE, String::new()));
+                    };
+                    let body = request
+                        .into_body()
+                        .collect()
+                        .await
+                        .map_err(|e| ApiError(format!("Failed to read request body: {}", e)))?
+                        .to_bytes();
+                    let delimiter = format!("--{}", boundary);
+                    let parts = String::from_utf8_lossy(&body)
+                        .lines()
+                        .filter(|line| line.trim_end() == delimiter)
+                        .count();
+                    Ok(response(StatusCode::CREATED, parts.to_string()))
+                }
+                _ => Ok(response(StatusCode::METHOD_NOT_ALLOWED, String::new())),
+            }
+        })
+    }
+}
+
+/// Health check, which always succeeds.
+#[derive(Clone, Copy, Debug, Default)]
+struct Health;
+
+impl<B> Service<Request<B>> for Health {
+    type Response = Response<ExampleBody>;
+    type Error = ApiError;
+    type Future = futures::future::Ready<Result<Self::Response, Self::Error>>;
+
+    fn call(&self, _: Request<B>) -> Self::Future {
+        futures::future::ok(response(StatusCode::OK, "OK".to_string()))
+    }
+}
+
+/// Make service which clones a service for each connection.
+#[derive(Clone, Copy, Debug)]
+struct MakeClone<S>(S);
+
+impl<S: Clone + Send + 'static, Target> Service<Target> for MakeClone<S> {
+    type Response = S;
+    type Error = ApiError;
+    type Future = futures::future::Ready<Result<S, ApiError>>;
+
+    fn call(&self, _: Target) -> Self::Future {
+        futures::future::ok(self.0.clone())
+    }
+}
+
+/// Authenticate a request by the API key in the context, which must match
+/// `api_key`, rejecting it otherwise.
+fn authenticate<B>(
+    api_key: &Arc<str>,
+    auth_data: Option<AuthData>,
+    _: &Request<B>,
+) -> futures::future::Ready<Result<Option<Authorization>, Response<ExampleBody>>> {
+    let result = match auth_data {
+        Some(AuthData::ApiKey(ref key)) if *key == **api_key => {
+            Ok(Some(Authorization::new("api-key-holder", Scopes::All)))
+        }
+        _ => Err(Challenge::new("ApiKey")
+            .with_param("header", API_KEY_HEADER)
+            .to_response()),
+    };
+    futures::future::ready(result)
+}
+
+/// Reference server stack, serving `PetApi` under `/pets` to requests with
+/// the given API key in the `X-API-Key` header, and a health check under
+/// `/health` to all requests.
+pub fn server(
+    api_key: &str,
+) -> CompositeMakeService<Option<SocketAddr>, ExampleBody, ExampleBody, ApiError, ApiError> {
+    let api_key: Arc<str> = Arc::from(api_key);
+    let authenticator = move |auth_data: Option<AuthData>, request: &Request<ExampleBody>| {
+        authenticate(&api_key, auth_data, request)
+    };
+
+    type AuthDataContext = crate::make_context_ty!(
+        ExampleContext,
+        ExampleEmptyContext,
+        Option<AuthData>,
+        XSpanIdString
+    );
+    let api = MakeFnAuthenticator::<_, _, AuthDataContext>::new(MakeClone(PetApi), authenticator);
+    let api = MakeApiKeyExtractor::new(api, ApiKeyLocation::Header(API_KEY_HEADER.to_string()));
+    let api = AddContextMakeService::<_, ExampleEmptyContext>::new(api);
+
+    let mut composite = CompositeMakeService::new();
+    composite.push(("/pets", Box::new(api)));
+    composite.push(("/health", Box::new(MakeClone(Health))));
+    composite
+}
+
+/// Reference client, sending requests with an API key to the inner service,
+/// such as an HTTP client, a `MockDownstream`, or a `server` stack.
+pub struct ExampleClient<S> {
+    inner: AuthInjector<DropContextService<S, ClientContext>>,
+    api_key: String,
+}
+
+impl<S> fmt::Debug for ExampleClient<S> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ExampleClient").finish()
+    }
+}
+
+impl<S> ExampleClient<S>
+where
+    S: Service<Request<ExampleBody>, Response = Response<ExampleBody>, Error = ApiError>,
+{
+    /// Create a client sending requests with the given API key.
+    pub fn new(inner: S, api_key: &str) -> Self {
+        ExampleClient {
+            inner: AuthInjector::new(DropContextService::new(inner))
+                .with_api_key(ApiKeyLocation::Header(API_KEY_HEADER.to_string())),
+            api_key: api_key.to_string(),
+        }
+    }
+
+    fn context(&self) -> ClientContext {
+        crate::make_context_with_defaults!(
+            ExampleContext,
+            ExampleEmptyContext;
+            Some(AuthData::apikey(&self.api_key))
+        )
+    }
+
+    /// Fetch a pet.
+    pub async fn get_pet(&self, id: u64) -> Result<Response<ExampleBody>, ApiError> {
+        let context = self.context();
+        let request = Request::get(format!("/pets/{}", id))
+            .header(X_SPAN_ID, Has::<XSpanIdString>::get(&context).to_string())
+            .body(Full::default())
+            .map_err(|e| ApiError(format!("Failed to build request: {}", e)))?;
+        self.inner.call((request, context)).await
+    }
+
+    /// Upload photos of a pet, as parts of a `multipart/form-data` body.
+    pub async fn upload_photos(
+        &self,
+        id: u64,
+        photos: &[&[u8]],
+    ) -> Result<Response<ExampleBody>, ApiError> {
+        let boundary = uuid::Uuid::new_v4().simple().to_string();
+        let mut body = Vec::new();
+        for (index, photo) in photos.iter().enumerate() {
+            body.extend_from_slice(
+                format!(
+                    "--{}\r\nContent-Disposition: {}\r\nContent-Type: image/jpeg\r\n\r\n",
+                    boundary,
+                    ContentDisposition::form_data("photo").with_filename(format!("{}.jpg", index))
+                )
+                .as_bytes(),
+            );
+            body.extend_from_slice(photo);
+            body.extend_from_slice(b"\r\n");
+        }
+        body.extend_from_slice(format!("--{}--\r\n", boundary).as_bytes());
+
+        let request = Request::post(format!("/pets/{}/photos", id))
+            .header(
+                CONTENT_TYPE,
+                format!("multipart/form-data; boundary={}", boundary),
+            )
+            .body(Full::new(Bytes::from(body)))
+            .map_err(|e| ApiError(format!("Failed to build request: {}", e)))?;
+        self.inner.call((request, self.context())).await
+    }
+}
+
+/// Request received by a `MockDownstream`.
+///
+/// Credentials are masked in its `Debug` output, so that failing tests don't
+/// print them.
+#[derive(Clone)]
+pub struct RecordedRequest {
+    /// Method of the request.
+    pub method: Method,
+    /// Path and query of the request.
+    pub uri: String,
+    /// Headers of the request.
+    pub headers: hyper::HeaderMap,
+    /// Body of the request.
+    pub body: Bytes,
+}
+
+impl fmt::Debug for RecordedRequest {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RecordedRequest")
+            .field("method", &self.method)
+            .field("uri", &self.uri)
+            .field("headers", &RedactedHeaders::new(&self.headers))
+            .field("body", &self.body)
+            .finish()
+    }
+}
+
+/// Mock downstream server, returning configured responses by path, or
+/// `404 Not Found` for other paths, and recording the requests it receives.
+///
+/// Clones share the same configuration and recorded requests.
+#[derive(Clone, Debug, Default)]
+pub struct MockDownstream {
+    responses: Arc<Mutex<HashMap<String, (StatusCode, Bytes)>>>,
+    requests: Arc<Mutex<Vec<RecordedRequest>>>,
+}
+
+impl MockDownstream {
+    /// Create a mock with no configured responses.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Respond to requests for the path with the given status and body.
+    pub fn respond<P: Into<String>, B: Into<Bytes>>(&self, path: P, status: StatusCode, body: B) {
+        self.responses
+            .lock()
+            .unwrap()
+            .insert(path.into(), (status, body.into()));
+    }
+
+    /// Respond to requests for the path with the given status, and a sample
+    /// of the given type as JSON, for when the specification has no example.
+    pub fn respond_sample<T, P>(&self, path: P, status: StatusCode)
+    where
+        T: SampleValue + serde::Serialize,
+        P: Into<String>,
+    {
+        let body = serde_json::to_vec(&T::sample()).unwrap_or_default();
+        self.respond(path, status, body);
+    }
+
+    /// Requests received so far.
+    pub fn requests(&self) -> Vec<RecordedRequest> {
+        self.requests.lock().unwrap().clone()
+    }
+}
+
+impl<B> Service<Request<B>> for MockDownstream
+where
+    B: Body + Send + 'static,
+    B::Data: Send,
+    B::Error: fmt::Display,
+{
+    type Response = Response<ExampleBody>;
+    type Error = ApiError;
+    type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn call(&self, request: Request<B>) -> Self::Future {
+        let mock = self.clone();
+        async move {
+            let (parts, body) = request.into_parts();
+            let body = body
+                .collect()
+                .await
+                .map_err(|e| ApiError(format!("Failed to read request body: {}", e)))?
+                .to_bytes();
+            let (status, response_body) = mock
+                .responses
+                .lock()
+                .unwrap()
+                .get(parts.uri.path())
+                .cloned()
+                .unwrap_or((StatusCode::NOT_FOUND, Bytes::new()));
+            mock.requests.lock().unwrap().push(RecordedRequest {
+                method: parts.method,
+                uri: parts.uri.to_string(),
+                headers: parts.headers,
+                body,
+            });
+            let mut response = Response::new(Full::new(response_body));
+            *response.status_mut() = status;
+            Ok(response)
+        }
+        .boxed()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hyper::header::WWW_AUTHENTICATE;
+
+    async fn body(response: Response<ExampleBody>) -> String {
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        String::from_utf8(body.to_vec()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_end_to_end() {
+        let make_service = server("secret");
+        assert!(make_service.check().is_ok());
+        let server = make_service.call(None).await.unwrap();
+
+        let client = ExampleClient::new(server, "secret");
+        let response = client.get_pet(7).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let pet: serde_json::Value = serde_json::from_str(&body(response).await).unwrap();
+        assert_eq!(pet["id"], 7);
+        assert_eq!(pet["owner"], "api-key-holder");
+        assert!(pet["spanId"]
+            .as_str()
+            .is_some_and(|span_id| !span_id.is_empty()));
+
+        let response = client.upload_photos(7, &[b"one", b"two"]).await.unwrap();
+        assert_eq!(response.status(), StatusCode::CREATED);
+        assert_eq!(body(response).await, "2");
+
+        let server = make_service.call(None).await.unwrap();
+        let client = ExampleClient::new(server, "wrong");
+        let response = client.get_pet(7).await.unwrap();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        assert!(response.headers().contains_key(WWW_AUTHENTICATE));
+
+        let server = make_service.call(None).await.unwrap();
+        let response = server
+            .call(Request::get("/health").body(Full::default()).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn test_mock_downstream() {
+        let mock = MockDownstream::new();
+        mock.respond("/pets/1", StatusCode::OK, "{}");
+        mock.respond_sample::<Vec<u32>, _>("/pets", StatusCode::OK);
+        let client = ExampleClient::new(mock.clone(), "secret");
+
+        let response = client.get_pet(1).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let response = client.get_pet(2).await.unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+        let requests = mock.requests();
+        assert_eq!(requests.len(), 2);
+        assert_eq!(requests[0].uri, "/pets/1");
+        assert_eq!(requests[0].headers.get(API_KEY_HEADER).unwrap(), "secret");
+        assert!(!format!("{:?}", requests[0]).contains("secret"));
+        assert!(requests[0].headers.contains_key(X_SPAN_ID));
+
+        let response = mock
+            .call(Request::get("/pets").body(ExampleBody::default()).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(body(response).await, "[1]");
+    }
+}
@@ -1,22 +1,36 @@
+use hyper::header::{HeaderMap, HeaderValue};
 use std::fmt;
 use uuid::Uuid;
 
 /// Header - `X-Span-ID` - used to track a request through a chain of microservices.
 pub const X_SPAN_ID: &str = "X-Span-ID";
 
+/// Header - `traceparent` - identifying a request in a distributed trace, as
+/// defined by the W3C Trace Context specification.
+pub const TRACEPARENT: &str = "traceparent";
+
+/// Header - `tracestate` - carrying vendor-specific trace information
+/// alongside `traceparent`.
+pub const TRACESTATE: &str = "tracestate";
+
+/// Header - `Idempotency-Key` - identifying a request which may be sent more
+/// than once, so that the server only applies it once.
+pub const IDEMPOTENCY_KEY: &str = "Idempotency-Key";
+
 /// Wrapper for a string being used as an X-Span-ID.
 #[derive(Debug, Clone)]
 pub struct XSpanIdString(pub String);
 
 impl XSpanIdString {
     /// Extract an X-Span-ID from a request header if present, and if not
-    /// generate a new one.
+    /// derive one from the `traceparent` header, or generate a new one.
     pub fn get_or_generate<T>(req: &hyper::Request<T>) -> Self {
         let x_span_id = req.headers().get(X_SPAN_ID);
 
         x_span_id
             .and_then(|x| x.to_str().ok())
             .map(|x| XSpanIdString(x.to_string()))
+            .or_else(|| TraceContext::from_headers(req.headers()).map(XSpanIdString::from))
             .unwrap_or_default()
     }
 }
@@ -32,3 +46,263 @@ impl fmt::Display for XSpanIdString {
         write!(f, "{}", self.0)
     }
 }
+
+/// The X-Span-ID is the trace ID, formatted as a UUID, so that all the
+/// services handling a request log the same ID whichever header they use.
+impl From<&TraceContext> for XSpanIdString {
+    fn from(trace: &TraceContext) -> Self {
+        XSpanIdString(Uuid::from_bytes(trace.trace_id).to_string())
+    }
+}
+
+impl From<TraceContext> for XSpanIdString {
+    fn from(trace: TraceContext) -> Self {
+        XSpanIdString::from(&trace)
+    }
+}
+
+/// Key identifying an operation to a server, so that it is only applied once
+/// however many times the request for it is sent.
+///
+/// Putting one in the context of a request lets the caller choose the key,
+/// for example to reuse it when the operation is attempted again later.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct IdempotencyKey(pub String);
+
+impl Default for IdempotencyKey {
+    /// Generate a new, random, key.
+    fn default() -> Self {
+        IdempotencyKey(Uuid::new_v4().to_string())
+    }
+}
+
+impl fmt::Display for IdempotencyKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+/// Position of a request in a distributed trace, carried in the W3C
+/// `traceparent` and `tracestate` headers.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TraceContext {
+    /// ID of the whole trace.
+    pub trace_id: [u8; 16],
+    /// ID of the span - the operation making or handling the request.
+    pub span_id: [u8; 8],
+    /// Trace flags. Only `TraceContext::SAMPLED` is defined.
+    pub flags: u8,
+    /// Contents of the `tracestate` header, if any.
+    pub state: Option<String>,
+}
+
+impl TraceContext {
+    /// Flag set when the caller may have recorded the trace.
+    pub const SAMPLED: u8 = 0x01;
+
+    /// Start a new, unsampled, trace.
+    pub fn new() -> Self {
+        TraceContext {
+            trace_id: *Uuid::new_v4().as_bytes(),
+            span_id: new_span_id(),
+            flags: 0,
+            state: None,
+        }
+    }
+
+    /// Start a new trace whose ID is taken from an X-Span-ID, if it is a UUID.
+    pub fn from_span_id(x_span_id: &XSpanIdString) -> Self {
+        let mut trace = TraceContext::new();
+        if let Ok(uuid) = Uuid::parse_str(&x_span_id.0) {
+            if !uuid.is_nil() {
+                trace.trace_id = *uuid.as_bytes();
+            }
+        }
+        trace
+    }
+
+    /// A new span in the same trace, with this span as its parent.
+    pub fn child(&self) -> Self {
+        TraceContext {
+            span_id: new_span_id(),
+            ..self.clone()
+        }
+    }
+
+    /// Whether the sampled flag is set.
+    pub fn is_sampled(&self) -> bool {
+        self.flags & Self::SAMPLED != 0
+    }
+
+    /// Parse the value of a `traceparent` header.
+    ///
+    /// Values of versions later than `00` are accepted, ignoring any extra
+    /// fields, as the specification requires.
+    pub fn parse(traceparent: &str) -> Option<Self> {
+        let traceparent = traceparent.trim();
+        let mut fields = traceparent.split('-');
+        let version = parse_hex::<1>(fields.next()?)?[0];
+        let trace_id = parse_hex::<16>(fields.next()?)?;
+        let span_id = parse_hex::<8>(fields.next()?)?;
+        let flags = parse_hex::<1>(fields.next()?)?[0];
+        let valid = match version {
+            0x00 => fields.next().is_none(),
+            0xff => false,
+            _ => true,
+        };
+        if !valid || trace_id == [0; 16] || span_id == [0; 8] {
+            return None;
+        }
+        Some(TraceContext {
+            trace_id,
+            span_id,
+            flags,
+            state: None,
+        })
+    }
+
+    /// Read the trace context from request headers, if there is a valid
+    /// `traceparent`.
+    pub fn from_headers(headers: &HeaderMap) -> Option<Self> {
+        let mut trace = headers
+            .get(TRACEPARENT)
+            .and_then(|value| value.to_str().ok())
+            .and_then(TraceContext::parse)?;
+        let state = headers
+            .get_all(TRACESTATE)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .map(str::trim)
+            .filter(|value| !value.is_empty())
+            .collect::<Vec<_>>()
+            .join(",");
+        trace.state = Some(state).filter(|state| !state.is_empty());
+        Some(trace)
+    }
+
+    /// Write the trace context to request headers, replacing any already
+    /// there.
+    pub fn insert(&self, headers: &mut HeaderMap) {
+        if let Ok(value) = HeaderValue::try_from(self.to_string()) {
+            headers.insert(TRACEPARENT, value);
+        }
+        match self
+            .state
+            .as_ref()
+            .and_then(|state| HeaderValue::from_str(state).ok())
+        {
+            Some(value) => {
+                headers.insert(TRACESTATE, value);
+            }
+            None => {
+                headers.remove(TRACESTATE);
+            }
+        }
+    }
+}
+
+impl Default for TraceContext {
+    fn default() -> Self {
+        TraceContext::new()
+    }
+}
+
+/// Formats as the value of a `traceparent` header.
+impl fmt::Display for TraceContext {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "00-")?;
+        for byte in self.trace_id {
+            write!(f, "{:02x}", byte)?;
+        }
+        write!(f, "-")?;
+        for byte in self.span_id {
+            write!(f, "{:02x}", byte)?;
+        }
+        write!(f, "-{:02x}", self.flags)
+    }
+}
+
+fn new_span_id() -> [u8; 8] {
+    let mut span_id = [0; 8];
+    span_id.copy_from_slice(&Uuid::new_v4().as_bytes()[..8]);
+    span_id
+}
+
+/// Parse exactly `N` bytes of lowercase hex.
+fn parse_hex<const N: usize>(s: &str) -> Option<[u8; N]> {
+    if s.len() != N * 2 || !s.bytes().all(|b| matches!(b, b'0'..=b'9' | b'a'..=b'f')) {
+        return None;
+    }
+    let mut bytes = [0; N];
+    for (i, byte) in bytes.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(&s[i * 2..i * 2 + 2], 16).ok()?;
+    }
+    Some(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TRACEPARENT_VALUE: &str = "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01";
+
+    #[test]
+    fn test_parse_traceparent() {
+        let trace = TraceContext::parse(TRACEPARENT_VALUE).unwrap();
+        assert!(trace.is_sampled());
+        assert_eq!(
+            trace.span_id,
+            [0x00, 0xf0, 0x67, 0xaa, 0x0b, 0xa9, 0x02, 0xb7]
+        );
+        assert_eq!(trace.to_string(), TRACEPARENT_VALUE);
+        assert_eq!(
+            XSpanIdString::from(&trace).0,
+            "4bf92f35-77b3-4da6-a3ce-929d0e0e4736"
+        );
+
+        let child = trace.child();
+        assert_eq!(child.trace_id, trace.trace_id);
+        assert_ne!(child.span_id, trace.span_id);
+
+        assert!(TraceContext::parse(&format!("01{}-extra", &TRACEPARENT_VALUE[2..])).is_some());
+        for invalid in [
+            "00-4BF92F3577B34DA6A3CE929D0E0E4736-00f067aa0ba902b7-01",
+            "00-00000000000000000000000000000000-00f067aa0ba902b7-01",
+            "00-4bf92f3577b34da6a3ce929d0e0e4736-0000000000000000-01",
+            "ff-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01",
+            "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01-extra",
+            "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7",
+        ] {
+            assert_eq!(TraceContext::parse(invalid), None, "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn test_trace_headers() {
+        let mut headers = HeaderMap::new();
+        headers.insert(TRACEPARENT, HeaderValue::from_static(TRACEPARENT_VALUE));
+        headers.append(TRACESTATE, HeaderValue::from_static("a=1"));
+        headers.append(TRACESTATE, HeaderValue::from_static("b=2"));
+        let trace = TraceContext::from_headers(&headers).unwrap();
+        assert_eq!(trace.state.as_deref(), Some("a=1,b=2"));
+
+        let mut headers = HeaderMap::new();
+        trace.insert(&mut headers);
+        assert_eq!(headers[TRACEPARENT], TRACEPARENT_VALUE);
+        assert_eq!(headers[TRACESTATE], "a=1,b=2");
+    }
+
+    #[test]
+    fn test_span_id_from_traceparent() {
+        let request = hyper::Request::get("/")
+            .header(TRACEPARENT, TRACEPARENT_VALUE)
+            .body(())
+            .unwrap();
+        let x_span_id = XSpanIdString::get_or_generate(&request);
+        assert_eq!(x_span_id.0, "4bf92f35-77b3-4da6-a3ce-929d0e0e4736");
+        assert_eq!(
+            TraceContext::from_span_id(&x_span_id).trace_id,
+            TraceContext::parse(TRACEPARENT_VALUE).unwrap().trace_id
+        );
+    }
+}
@@ -0,0 +1,255 @@
+//! Health and readiness endpoints, for mounting in a `CompositeMakeService`.
+//!
+//! A `HealthService` runs registered checks on each request and reports them
+//! as JSON, responding `200 OK` if they all pass and `503 Service
+//! Unavailable` otherwise:
+//!
+//! ```ignore
+//! let health = HealthService::new()
+//!     .with_check(Probe::Readiness, "database", move || {
+//!         let pool = pool.clone();
+//!         async move { pool.ping().await.map_err(|e| e.to_string()) }
+//!     });
+//!
+//! let mut composite_make_service = CompositeMakeService::new();
+//! composite_make_service.push(("/healthz", Box::new(health.make_service())));
+//! composite_make_service.push(("/readyz", Box::new(health.make_service())));
+//! ```
+//!
+//! which responds to `/readyz` with, for example:
+//!
+//! ```json
+//! {"status":"fail","checks":{"database":{"status":"fail","output":"connection refused"}}}
+//! ```
+use futures::future::{BoxFuture, FutureExt};
+use hyper::header::{HeaderValue, CONTENT_TYPE};
+use hyper::service::Service;
+use hyper::{Method, Request, Response, StatusCode};
+use std::fmt::{self, Write};
+use std::future::Future;
+use std::marker::PhantomData;
+use std::sync::Arc;
+
+/// Which endpoint a check is reported by.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Probe {
+    /// Whether the server is working at all, reported by both `/healthz` and
+    /// `/readyz`. A failure typically means the server should be restarted.
+    Liveness,
+    /// Whether the server can handle traffic, such as whether its database
+    /// is reachable, reported by `/readyz` only.
+    Readiness,
+}
+
+type Check = Arc<dyn Fn() -> BoxFuture<'static, Result<(), String>> + Send + Sync>;
+
+#[derive(Clone, Default)]
+struct Checks(Vec<(Probe, String, Check)>);
+
+impl fmt::Debug for Checks {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_list()
+            .entries(self.0.iter().map(|(probe, name, _)| (probe, name)))
+            .finish()
+    }
+}
+
+/// Service reporting the results of health checks.
+///
+/// Requests for a path ending in `/readyz` run all checks, and other requests
+/// run the liveness checks only. With no checks registered, every request
+/// passes.
+#[derive(Debug)]
+pub struct HealthService<ResBody> {
+    checks: Arc<Checks>,
+    marker: PhantomData<fn() -> ResBody>,
+}
+
+impl<ResBody> HealthService<ResBody> {
+    /// Create a service with no checks.
+    pub fn new() -> Self {
+        HealthService {
+            checks: Arc::new(Checks::default()),
+            marker: PhantomData,
+        }
+    }
+
+    /// Register a check, which is passed if the future it returns resolves
+    /// to `Ok`, and otherwise failed, with the error reported as its output.
+    pub fn with_check<N, F, Fut>(mut self, probe: Probe, name: N, check: F) -> Self
+    where
+        N: Into<String>,
+        F: Fn() -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<(), String>> + Send + 'static,
+    {
+        let check: Check = Arc::new(move || check().boxed());
+        Arc::make_mut(&mut self.checks)
+            .0
+            .push((probe, name.into(), check));
+        self
+    }
+
+    /// Create a make service for this service, for mounting in a
+    /// `CompositeMakeService`.
+    pub fn make_service(&self) -> MakeHealthService<ResBody> {
+        MakeHealthService(self.clone())
+    }
+}
+
+impl<ResBody> Default for HealthService<ResBody> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<ResBody> Clone for HealthService<ResBody> {
+    fn clone(&self) -> Self {
+        HealthService {
+            checks: self.checks.clone(),
+            marker: PhantomData,
+        }
+    }
+}
+
+impl<ReqBody, ResBody> Service<Request<ReqBody>> for HealthService<ResBody>
+where
+    ResBody: From<String> + Send + 'static,
+{
+    type Response = Response<ResBody>;
+    type Error = std::convert::Infallible;
+    type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn call(&self, req: Request<ReqBody>) -> Self::Future {
+        if !matches!(*req.method(), Method::GET | Method::HEAD) {
+            let mut response = Response::new(ResBody::from(String::new()));
+            *response.status_mut() = StatusCode::METHOD_NOT_ALLOWED;
+            return futures::future::ok(response).boxed();
+        }
+
+        let ready = req.uri().path().trim_end_matches('/').ends_with("/readyz");
+        let checks: Vec<_> = self
+            .checks
+            .0
+            .iter()
+            .filter(|(probe, _, _)| ready || *probe == Probe::Liveness)
+            .map(|(_, name, check)| {
+                let name = name.clone();
+                check().map(move |result| (name, result))
+            })
+            .collect();
+
+        Box::pin(async move {
+            let results = futures::future::join_all(checks).await;
+            let passed = results.iter().all(|(_, result)| result.is_ok());
+
+            let mut body = format!(
+                "{{\"status\":\"{}\",\"checks\":{{",
+                if passed { "pass" } else { "fail" }
+            );
+            for (i, (name, result)) in results.iter().enumerate() {
+                if i > 0 {
+                    body.push(',');
+                }
+                write_json_string(&mut body, name);
+                match result {
+                    Ok(()) => body.push_str(":{\"status\":\"pass\"}"),
+                    Err(output) => {
+                        body.push_str(":{\"status\":\"fail\",\"output\":");
+                        write_json_string(&mut body, output);
+                        body.push('}');
+                    }
+                }
+            }
+            body.push_str("}}");
+
+            let mut response = Response::new(ResBody::from(body));
+            if !passed {
+                *response.status_mut() = StatusCode::SERVICE_UNAVAILABLE;
+            }
+            response
+                .headers_mut()
+                .insert(CONTENT_TYPE, HeaderValue::from_static("application/json"));
+            Ok(response)
+        })
+    }
+}
+
+/// Append the string to the buffer as a JSON string literal.
+fn write_json_string(buffer: &mut String, value: &str) {
+    buffer.push('"');
+    for c in value.chars() {
+        match c {
+            '"' => buffer.push_str("\\\""),
+            '\\' => buffer.push_str("\\\\"),
+            '\n' => buffer.push_str("\\n"),
+            '\r' => buffer.push_str("\\r"),
+            '\t' => buffer.push_str("\\t"),
+            c if c.is_control() => {
+                let _ = write!(buffer, "\\u{:04x}", u32::from(c));
+            }
+            c => buffer.push(c),
+        }
+    }
+    buffer.push('"');
+}
+
+/// Make service for a `HealthService`, sharing its checks between
+/// connections.
+#[derive(Clone, Debug)]
+pub struct MakeHealthService<ResBody>(HealthService<ResBody>);
+
+impl<ResBody, Target> Service<Target> for MakeHealthService<ResBody> {
+    type Response = HealthService<ResBody>;
+    type Error = std::convert::Infallible;
+    type Future = futures::future::Ready<Result<Self::Response, Self::Error>>;
+
+    fn call(&self, _target: Target) -> Self::Future {
+        futures::future::ok(self.0.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_health_service() {
+        let service = HealthService::<String>::new()
+            .with_check(Probe::Liveness, "threads", || async { Ok(()) })
+            .with_check(Probe::Readiness, "database", || async {
+                Err("connection \"refused\"".to_string())
+            });
+
+        let call = |path: &str| service.call(Request::get(path).body(()).unwrap());
+
+        let response = call("/healthz").await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()[CONTENT_TYPE], "application/json");
+        assert_eq!(
+            response.body(),
+            r#"{"status":"pass","checks":{"threads":{"status":"pass"}}}"#
+        );
+
+        let response = call("/readyz").await.unwrap();
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(
+            response.body(),
+            r#"{"status":"fail","checks":{"threads":{"status":"pass"},"database":{"status":"fail","output":"connection \"refused\""}}}"#
+        );
+
+        let request = Request::post("/healthz").body(()).unwrap();
+        let response = service.call(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::METHOD_NOT_ALLOWED);
+
+        // Checks registered once the service has been cloned still apply.
+        let _make_service = service.make_service();
+        let service = service.with_check(Probe::Liveness, "disk", || async {
+            Err("full".to_string())
+        });
+        let response = service
+            .call(Request::get("/healthz").body(()).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+    }
+}
@@ -0,0 +1,322 @@
+//! Adapters between the request type of earlier major versions of this crate
+//! and the current middleware stack.
+//!
+//! Middleware written against swagger-rs 2.x, 3.x and 4.x handled
+//! `ContextualPayload`s - a request with its context in named fields - where
+//! current middleware handles `(Request, Context)` tuples. Clients sent
+//! requests through a `ClientService`, without their context. The adapters
+//! here let services of either generation be composed, so that a stack can
+//! be migrated one service at a time:
+//!
+//! ```ignore
+//! // A legacy service, inside current middleware
+//! let service = FromLegacyService::new(legacy_service);
+//! let service = MakeAllowAllAuthenticator::new(service, "cosmo");
+//!
+//! // A current service, inside middleware written for `ContextualPayload`s
+//! let service = legacy_middleware(IntoLegacyService::new(current_service));
+//!
+//! // Current client middleware, sending requests with a legacy client
+//! let client = Retry::new(FromLegacyClient::new(legacy_client), policy);
+//!
+//! // A legacy generated client, sending requests through current middleware
+//! let client = LegacyApiClient::new(IntoLegacyClient::new(client, EmptyContext));
+//! ```
+use crate::redact::Redaction;
+use hyper::header::HeaderMap;
+use hyper::service::Service;
+use hyper::{Request, Response};
+use std::future::Future;
+use std::marker::PhantomData;
+
+/// Request with its context, as handled by middleware in earlier major
+/// versions of this crate.
+#[derive(Debug)]
+pub struct ContextualPayload<B, C> {
+    /// The request.
+    pub inner: Request<B>,
+    /// The context of the request.
+    pub context: C,
+}
+
+impl<B, C> From<(Request<B>, C)> for ContextualPayload<B, C> {
+    fn from((inner, context): (Request<B>, C)) -> Self {
+        ContextualPayload { inner, context }
+    }
+}
+
+impl<B, C> From<ContextualPayload<B, C>> for (Request<B>, C) {
+    fn from(payload: ContextualPayload<B, C>) -> Self {
+        (payload.inner, payload.context)
+    }
+}
+
+/// Adapter taking `(Request, Context)` tuples, and passing them on to a
+/// legacy service as `ContextualPayload`s.
+#[derive(Clone, Debug)]
+pub struct FromLegacyService<T> {
+    inner: T,
+}
+
+impl<T> FromLegacyService<T> {
+    /// Wrap a legacy service.
+    pub fn new(inner: T) -> Self {
+        FromLegacyService { inner }
+    }
+
+    /// The wrapped service.
+    pub fn into_inner(self) -> T {
+        self.inner
+    }
+}
+
+impl<T, B, C> Service<(Request<B>, C)> for FromLegacyService<T>
+where
+    T: Service<ContextualPayload<B, C>>,
+{
+    type Response = T::Response;
+    type Error = T::Error;
+    type Future = T::Future;
+
+    fn call(&self, req: (Request<B>, C)) -> Self::Future {
+        self.inner.call(req.into())
+    }
+}
+
+/// Adapter taking `ContextualPayload`s, and passing them on to a current
+/// service as `(Request, Context)` tuples.
+#[derive(Clone, Debug)]
+pub struct IntoLegacyService<T> {
+    inner: T,
+}
+
+impl<T> IntoLegacyService<T> {
+    /// Wrap a current service.
+    pub fn new(inner: T) -> Self {
+        IntoLegacyService { inner }
+    }
+
+    /// The wrapped service.
+    pub fn into_inner(self) -> T {
+        self.inner
+    }
+}
+
+impl<T, B, C> Service<ContextualPayload<B, C>> for IntoLegacyService<T>
+where
+    T: Service<(Request<B>, C)>,
+{
+    type Response = T::Response;
+    type Error = T::Error;
+    type Future = T::Future;
+
+    fn call(&self, req: ContextualPayload<B, C>) -> Self::Future {
+        self.inner.call(req.into())
+    }
+}
+
+/// Client which sends requests without their context, as the
+/// `client::Service` trait of earlier major versions of this crate did.
+///
+/// This is implemented for `hyper_util`'s legacy `Client`, the successor of
+/// the `hyper::Client` it was implemented for.
+pub trait ClientService {
+    /// Body of requests sent by the client.
+    type ReqBody;
+    /// Body of responses returned by the client.
+    type ResBody;
+    /// Error returned by the client.
+    type Error;
+    /// Future response from the client.
+    type Future: Future<Output = Result<Response<Self::ResBody>, Self::Error>>;
+
+    /// Send the request.
+    fn request(&self, req: Request<Self::ReqBody>) -> Self::Future;
+}
+
+#[cfg(feature = "client")]
+impl<C, B> ClientService for hyper_util::client::legacy::Client<C, B>
+where
+    C: hyper_util::client::legacy::connect::Connect + Clone + Send + Sync + 'static,
+    B: hyper::body::Body + Send + Unpin + 'static,
+    B::Data: Send,
+    B::Error: Into<Box<dyn std::error::Error + Send + Sync>>,
+{
+    type ReqBody = B;
+    type ResBody = hyper::body::Incoming;
+    type Error = hyper_util::client::legacy::Error;
+    type Future = hyper_util::client::legacy::ResponseFuture;
+
+    fn request(&self, req: Request<B>) -> Self::Future {
+        hyper_util::client::legacy::Client::request(self, req)
+    }
+}
+
+/// Adapter taking `(Request, Context)` tuples, and sending the requests with
+/// a legacy client, dropping their contexts.
+#[derive(Clone, Debug)]
+pub struct FromLegacyClient<T> {
+    inner: T,
+}
+
+impl<T> FromLegacyClient<T> {
+    /// Wrap a legacy client.
+    pub fn new(inner: T) -> Self {
+        FromLegacyClient { inner }
+    }
+
+    /// The wrapped client.
+    pub fn into_inner(self) -> T {
+        self.inner
+    }
+}
+
+impl<T, C> Service<(Request<T::ReqBody>, C)> for FromLegacyClient<T>
+where
+    T: ClientService,
+{
+    type Response = Response<T::ResBody>;
+    type Error = T::Error;
+    type Future = T::Future;
+
+    fn call(&self, req: (Request<T::ReqBody>, C)) -> Self::Future {
+        self.inner.request(req.0)
+    }
+}
+
+/// Adapter implementing a legacy client, which passes requests on to a
+/// current service along with a copy of a fixed context.
+#[derive(Debug)]
+pub struct IntoLegacyClient<T, B, C> {
+    inner: T,
+    context: C,
+    marker: PhantomData<fn(B)>,
+}
+
+impl<T: Clone, B, C: Clone> Clone for IntoLegacyClient<T, B, C> {
+    fn clone(&self) -> Self {
+        IntoLegacyClient::new(self.inner.clone(), self.context.clone())
+    }
+}
+
+impl<T, B, C> IntoLegacyClient<T, B, C> {
+    /// Wrap a current service, passing it the given context with each request.
+    pub fn new(inner: T, context: C) -> Self {
+        IntoLegacyClient {
+            inner,
+            context,
+            marker: PhantomData,
+        }
+    }
+
+    /// The wrapped service.
+    pub fn into_inner(self) -> T {
+        self.inner
+    }
+}
+
+impl<T, B, C, ResBody> ClientService for IntoLegacyClient<T, B, C>
+where
+    T: Service<(Request<B>, C), Response = Response<ResBody>>,
+    C: Clone,
+{
+    type ReqBody = B;
+    type ResBody = ResBody;
+    type Error = T::Error;
+    type Future = T::Future;
+
+    fn request(&self, req: Request<B>) -> Self::Future {
+        self.inner.call((req, self.context.clone()))
+    }
+}
+
+/// Cloning of headers without their credentials, as the `headers` module of
+/// earlier major versions of this crate provided for logging and forwarding
+/// requests.
+pub trait SafeHeaders {
+    /// Clone the headers, leaving out those masked by the default
+    /// `Redaction`, such as `Authorization` and `Cookie`.
+    fn safe_clone(&self) -> Self;
+}
+
+impl SafeHeaders for HeaderMap {
+    fn safe_clone(&self) -> Self {
+        let redaction = Redaction::default();
+        let mut headers = HeaderMap::with_capacity(self.len());
+        for (name, value) in self {
+            if !redaction.is_redacted(name) {
+                headers.append(name, value.clone());
+            }
+        }
+        headers
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{EmptyContext, Has, Push, XSpanIdString};
+    use futures::future::{ok, Ready};
+
+    /// Legacy service, returning the span ID from the context.
+    struct LegacySpanService;
+
+    impl<B, C: Has<XSpanIdString>> Service<ContextualPayload<B, C>> for LegacySpanService {
+        type Response = String;
+        type Error = ();
+        type Future = Ready<Result<String, ()>>;
+
+        fn call(&self, req: ContextualPayload<B, C>) -> Self::Future {
+            ok(req.context.get().0.clone())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_round_trip() {
+        let service = FromLegacyService::new(IntoLegacyService::new(FromLegacyService::new(
+            LegacySpanService,
+        )));
+        let context = EmptyContext.push(XSpanIdString("span".to_string()));
+        let span = service.call((Request::new(()), context)).await.unwrap();
+        assert_eq!(span, "span");
+    }
+
+    /// Current service, returning the span ID from the context.
+    struct SpanService;
+
+    impl<C: Has<XSpanIdString>> Service<(Request<()>, C)> for SpanService {
+        type Response = Response<String>;
+        type Error = ();
+        type Future = Ready<Result<Response<String>, ()>>;
+
+        fn call(&self, req: (Request<()>, C)) -> Self::Future {
+            ok(Response::new(req.1.get().0.clone()))
+        }
+    }
+
+    #[tokio::test]
+    async fn test_client_round_trip() {
+        let context = EmptyContext.push(XSpanIdString("span".to_string()));
+        let client = IntoLegacyClient::new(SpanService, context);
+        let response = client.request(Request::new(())).await.unwrap();
+        assert_eq!(response.into_body(), "span");
+
+        // The context passed to the legacy client is dropped.
+        let service = FromLegacyClient::new(client);
+        let other = EmptyContext.push(XSpanIdString("other".to_string()));
+        let response = service.call((Request::new(()), other)).await.unwrap();
+        assert_eq!(response.into_body(), "span");
+    }
+
+    #[test]
+    fn test_safe_clone() {
+        let mut headers = HeaderMap::new();
+        headers.insert("authorization", "Bearer secret".parse().unwrap());
+        headers.append("accept", "application/json".parse().unwrap());
+        headers.append("accept", "text/plain".parse().unwrap());
+
+        let safe = headers.safe_clone();
+        assert!(!safe.contains_key("authorization"));
+        assert_eq!(safe.get_all("accept").iter().count(), 2);
+    }
+}
@@ -16,6 +16,15 @@
 //! ## Feature support
 //!
 //! - **serdevalid** - Enable support for JSON schema based validation
+//! - **oidc** - Enable support for validating tokens issued by an OpenID Connect provider
+//! - **signing** - Enable HMAC request signing and verification
+//! - **digest** - Enable support for HTTP Digest authentication
+//! - **mmap** - Enable serving large files from memory maps
+//! - **gzip** - Enable the `gzip` and `deflate` content codings, for compressing bodies
+//! - **brotli** - Enable the `br` content coding, for compressing bodies
+//! - **prometheus** - Enable a Prometheus adapter for client metrics
+//! - **derive** - Enable `#[derive(HasContext)]`, for using plain structs as contexts,
+//!   and `#[derive(SampleValue)]`, for generating sample models
 //! - **conversion** - Enable support for Frunk-based conversion - in particular,
 //!   [transmogrification](https://docs.rs/frunk/latest/frunk/#transmogrifying)
 //!
@@ -25,7 +34,12 @@
 //! - **http1** - Enable support for HTTP/1 based APIs - RFC 9112
 //! - **http2** - Enable support for HTTP/2 based APIs - RFC 9113
 //! - **tls** - Enable support for HTTP over TLS (HTTPS)
+//! - **rustls** - Enable support for HTTP over TLS (HTTPS) using rustls rather than the
+//!   platform's TLS library
 //! - **uds** - Enable support for HTTP over UDS (Unix Domain Sockets)
+//! - **mock** - Enable an in-memory connector, for testing clients without sockets
+//! - **examples_support** - Enable reference server and client stacks built from this crate's middleware
+//! - **legacy** - Enable adapters for middleware written against earlier major versions of this crate
 
 #![deny(
     missing_docs,
@@ -40,6 +54,11 @@
 use std::error;
 use std::fmt;
 
+// Allow the code generated by derive macros to refer to this crate by name in
+// its own tests.
+#[cfg(all(test, feature = "derive"))]
+extern crate self as swagger;
+
 /// Module for encoding API properties in base64.
 pub mod base64_format;
 pub use base64_format::ByteArray;
@@ -49,13 +68,35 @@ pub mod nullable_format;
 pub use nullable_format::Nullable;
 
 mod body;
-pub use body::BodyExt;
+pub use body::{BodyExt, Charset, PrefixedBody, TextDecoding, TextError};
 
 pub mod auth;
 pub use auth::{AuthData, Authorization};
 
+pub mod cache_control;
+pub use cache_control::CacheControl;
+
+pub mod config;
+pub use config::ByteSize;
+
+pub mod content_coding;
+pub use content_coding::{AcceptEncoding, ContentCoding};
+
+#[cfg(any(feature = "gzip", feature = "brotli"))]
+pub mod compression;
+#[cfg(any(feature = "gzip", feature = "brotli"))]
+pub use compression::{CompressionService, MakeCompressionService};
+
+pub mod content_disposition;
+pub use content_disposition::{ContentDisposition, DispositionType};
+
 pub mod context;
-pub use context::{ContextBuilder, ContextWrapper, EmptyContext, Has, Pop, Push};
+#[cfg(feature = "derive")]
+pub use context::HasContext;
+pub use context::{
+    ArcContextWrapper, ArcContextWrapperExt, ContextBuilder, ContextWrapper, DebugDump, DynContext,
+    EmptyContext, Has, Pop, Push,
+};
 
 /// Module with utilities for creating connectors with hyper.
 #[cfg(feature = "client")]
@@ -63,25 +104,129 @@ pub mod connector;
 #[cfg(feature = "client")]
 pub use connector::Connector;
 
+#[cfg(feature = "client")]
+pub mod client;
+
+#[cfg(all(feature = "uds", unix))]
+pub mod uds;
+
 #[cfg(all(feature = "server", any(feature = "http1", feature = "http2")))]
 pub mod composites;
 #[cfg(all(feature = "server", any(feature = "http1", feature = "http2")))]
-pub use composites::{CompositeMakeService, CompositeMakeServiceEntry, CompositeService, NotFound};
+pub use composites::{
+    CompositeMakeService, CompositeMakeServiceEntry, CompositeService, Layer, MatchMode,
+    MetricsSink, NotFound, RouteConflict, RouteMetrics, RoutePattern, SharedCompositeService,
+};
+
+#[cfg(feature = "server")]
+pub mod health;
+#[cfg(feature = "server")]
+pub use health::{HealthService, MakeHealthService, Probe};
+
+#[cfg(feature = "server")]
+pub mod split;
+#[cfg(feature = "server")]
+pub use split::{
+    MakeSplitService, SlowStart, SlowStartOverflow, SlowStartService, SplitService, SplitWeight,
+};
+
+#[cfg(all(
+    feature = "server",
+    feature = "client",
+    any(feature = "http1", feature = "http2")
+))]
+pub mod proxy;
+#[cfg(all(
+    feature = "server",
+    feature = "client",
+    any(feature = "http1", feature = "http2")
+))]
+pub use proxy::{MakeProxyService, ProxyService};
+
+#[cfg(all(feature = "server", feature = "client"))]
+pub mod shadow;
+#[cfg(all(feature = "server", feature = "client"))]
+pub use shadow::{MakeShadowService, ShadowService};
 
 pub mod add_context;
 pub use add_context::{AddContextMakeService, AddContextService};
 
+pub mod connection_info;
+pub use connection_info::{
+    ConnectionInfo, ConnectionInfoService, HasConnectionInfo, MakeConnectionInfoService,
+};
+
+pub mod deadline;
+pub use deadline::{
+    Deadline, DeadlineFormat, DeadlineHeader, DeadlineService, MakeDeadlineService,
+};
+
+pub mod deprecation;
+pub use deprecation::{Deprecation, DeprecationService, DeprecationTable, MakeDeprecationService};
+
 pub mod drop_context;
 pub use drop_context::{DropContextMakeService, DropContextService};
 
+#[cfg(feature = "mmap")]
+pub mod mmap;
+#[cfg(feature = "mmap")]
+pub use mmap::MmapBody;
+
+pub mod locale;
+pub use locale::{AcceptLanguage, Locale, LocaleService, MakeLocaleService};
+
+pub mod map_error;
+pub use map_error::{MakeMapErrorService, MapErrorService};
+
+pub mod adaptive_limit;
+pub use adaptive_limit::{
+    AdaptiveLimit, AdaptiveLimitService, AdaptiveLimitStats, LimitAlgorithm, LimitPermit,
+    MakeAdaptiveLimitService,
+};
+
+pub mod load_shed;
+pub use load_shed::{LoadShedService, LoadShedSignal, MakeLoadShedService, Priority};
+
+pub mod schedule;
+pub use schedule::{MakeSchedulerService, Schedule, SchedulerService};
+
+pub mod quota;
+pub use quota::{MakeQuotaService, MemoryQuotaStore, Quota, QuotaPeriod, QuotaService, QuotaStore};
+
+pub mod redact;
+pub use redact::{DisplayRequest, DisplayResponse, RedactedHeaders, Redaction};
+
+pub mod request_info;
+pub use request_info::{
+    MakeRequestInfoService, MatchedBasePath, RequestInfo, RequestInfoService, RouteCaptures,
+};
+
+#[cfg(feature = "serdejson")]
+pub mod serde;
+
 pub mod request_parser;
 pub use request_parser::RequestParser;
 
+pub mod validation;
+pub use validation::{ValidationError, ValidationErrors, ValidationLocation};
+
 mod header;
-pub use header::{XSpanIdString, X_SPAN_ID};
+pub use header::{
+    IdempotencyKey, TraceContext, XSpanIdString, IDEMPOTENCY_KEY, TRACEPARENT, TRACESTATE,
+    X_SPAN_ID,
+};
 
 pub mod multipart;
 
+pub mod examples;
+pub use examples::SampleValue;
+
+#[cfg(feature = "examples_support")]
+pub mod examples_support;
+
+#[cfg(feature = "legacy")]
+pub mod legacy;
+
 mod one_any_of;
 pub use one_any_of::*;
 
@@ -0,0 +1,289 @@
+//! Adaptive overload protection, driven by a signal raised by handlers.
+//!
+//! The `LoadShedService` middleware pushes a `LoadShedSignal` into the context
+//! of each request. Handlers or inner services which detect overload (e.g. an
+//! exhausted database pool) raise the signal, and the middleware then rejects
+//! lower priority requests with `503 Service Unavailable` until it is cleared.
+use crate::Push;
+use futures::future::{BoxFuture, FutureExt};
+use hyper::header::{HeaderValue, RETRY_AFTER};
+use hyper::service::Service;
+use hyper::{Request, Response, StatusCode};
+use std::marker::PhantomData;
+use std::sync::atomic::{AtomicU8, Ordering};
+use std::sync::Arc;
+
+/// Priority of a request, used to decide which requests to reject under load.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Priority {
+    /// Requests which can be deferred, e.g. bulk or batch operations.
+    Low = 1,
+    /// Ordinary requests.
+    Normal = 2,
+    /// Requests which should be served unless the service is severely overloaded.
+    High = 3,
+    /// Requests which are never rejected, e.g. health checks.
+    Critical = 4,
+}
+
+/// Signal raised by handlers to indicate that the service is overloaded.
+///
+/// Clones share the same underlying state, so raising the signal in a
+/// handler affects all subsequent requests.
+#[derive(Clone, Debug, Default)]
+pub struct LoadShedSignal(Arc<AtomicU8>);
+
+impl PartialEq for LoadShedSignal {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+impl Eq for LoadShedSignal {}
+
+impl LoadShedSignal {
+    /// Create a new signal, which is initially clear.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Raise the signal, rejecting `Low` priority requests.
+    pub fn raise(&self) {
+        self.shed_below(Priority::Normal);
+    }
+
+    /// Raise the signal, rejecting all requests with a priority lower than the
+    /// one given.
+    pub fn shed_below(&self, priority: Priority) {
+        self.0.store(priority as u8, Ordering::Relaxed);
+    }
+
+    /// Clear the signal, so no requests are rejected.
+    pub fn clear(&self) {
+        self.0.store(0, Ordering::Relaxed);
+    }
+
+    /// Whether the signal is currently raised.
+    pub fn is_raised(&self) -> bool {
+        self.0.load(Ordering::Relaxed) != 0
+    }
+
+    /// Whether requests with the given priority are currently being rejected.
+    pub fn is_shedding(&self, priority: Priority) -> bool {
+        (priority as u8) < self.0.load(Ordering::Relaxed)
+    }
+}
+
+/// Middleware which rejects lower priority requests while its
+/// `LoadShedSignal` is raised.
+#[derive(Debug)]
+pub struct MakeLoadShedService<T, F, RC> {
+    inner: T,
+    classifier: F,
+    signal: LoadShedSignal,
+    marker: PhantomData<RC>,
+}
+
+impl<T, F, RC> MakeLoadShedService<T, F, RC> {
+    /// Create a middleware which classifies requests by priority using the
+    /// given function, and sheds them according to the signal.
+    pub fn new(inner: T, classifier: F, signal: LoadShedSignal) -> Self {
+        MakeLoadShedService {
+            inner,
+            classifier,
+            signal,
+            marker: PhantomData,
+        }
+    }
+}
+
+impl<Inner, F, RC, Target> Service<Target> for MakeLoadShedService<Inner, F, RC>
+where
+    Inner: Service<Target>,
+    Inner::Future: Send + 'static,
+    F: Clone + Send + 'static,
+    RC: Send + 'static,
+{
+    type Error = Inner::Error;
+    type Response = LoadShedService<Inner::Response, F, RC>;
+    type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn call(&self, target: Target) -> Self::Future {
+        let classifier = self.classifier.clone();
+        let signal = self.signal.clone();
+        Box::pin(
+            self.inner
+                .call(target)
+                .map(|s| Ok(LoadShedService::new(s?, classifier, signal))),
+        )
+    }
+}
+
+/// Middleware which rejects lower priority requests while its
+/// `LoadShedSignal` is raised.
+///
+/// The classifier is passed each request and its context, so requests can be
+/// prioritized by route or caller identity.
+///
+/// ```ignore
+/// let signal = LoadShedSignal::new();
+/// let service = LoadShedService::new(
+///     inner,
+///     |request: &Request<Incoming>, _context: &C| {
+///         if request.uri().path().starts_with("/batch") {
+///             Priority::Low
+///         } else {
+///             Priority::Normal
+///         }
+///     },
+///     signal,
+/// );
+/// ```
+#[derive(Debug)]
+pub struct LoadShedService<T, F, RC> {
+    inner: T,
+    classifier: F,
+    signal: LoadShedSignal,
+    marker: PhantomData<RC>,
+}
+
+impl<T, F, RC> LoadShedService<T, F, RC> {
+    /// Create a middleware which classifies requests by priority using the
+    /// given function, and sheds them according to the signal.
+    pub fn new(inner: T, classifier: F, signal: LoadShedSignal) -> Self {
+        LoadShedService {
+            inner,
+            classifier,
+            signal,
+            marker: PhantomData,
+        }
+    }
+}
+
+impl<T, F, RC> Clone for LoadShedService<T, F, RC>
+where
+    T: Clone,
+    F: Clone,
+{
+    fn clone(&self) -> Self {
+        Self {
+            inner: self.inner.clone(),
+            classifier: self.classifier.clone(),
+            signal: self.signal.clone(),
+            marker: PhantomData,
+        }
+    }
+}
+
+impl<T, F, B, ResBody, RC> Service<(Request<B>, RC)> for LoadShedService<T, F, RC>
+where
+    RC: Push<LoadShedSignal>,
+    T: Service<(Request<B>, RC::Result), Response = Response<ResBody>>,
+    T::Future: Send + 'static,
+    F: Fn(&Request<B>, &RC) -> Priority,
+    ResBody: Default + Send + 'static,
+    T::Error: Send + 'static,
+{
+    type Response = Response<ResBody>;
+    type Error = T::Error;
+    type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn call(&self, req: (Request<B>, RC)) -> Self::Future {
+        let (request, context) = req;
+
+        if self
+            .signal
+            .is_shedding((self.classifier)(&request, &context))
+        {
+            let mut response = Response::new(ResBody::default());
+            *response.status_mut() = StatusCode::SERVICE_UNAVAILABLE;
+            response
+                .headers_mut()
+                .insert(RETRY_AFTER, HeaderValue::from_static("1"));
+            return Box::pin(futures::future::ok(response));
+        }
+
+        let context = context.push(self.signal.clone());
+        Box::pin(self.inner.call((request, context)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::context::Has;
+
+    crate::new_context_type!(TestContext, TestEmptyContext, LoadShedSignal);
+
+    type Context = TestContext<LoadShedSignal, TestEmptyContext>;
+
+    struct OverloadService;
+
+    impl Service<(Request<()>, Context)> for OverloadService {
+        type Response = Response<String>;
+        type Error = ();
+        type Future = futures::future::Ready<Result<Self::Response, Self::Error>>;
+
+        fn call(&self, req: (Request<()>, Context)) -> Self::Future {
+            let signal: &LoadShedSignal = req.1.get();
+            if req.0.uri().path() == "/overload" {
+                signal.raise();
+            } else if req.0.uri().path() == "/recover" {
+                signal.clear();
+            }
+            futures::future::ok(Response::new(String::new()))
+        }
+    }
+
+    async fn status(
+        service: &impl Service<(Request<()>, TestEmptyContext), Response = Response<String>, Error = ()>,
+        path: &str,
+    ) -> StatusCode {
+        service
+            .call((Request::get(path).body(()).unwrap(), TestEmptyContext))
+            .await
+            .unwrap()
+            .status()
+    }
+
+    #[test]
+    fn test_signal() {
+        let signal = LoadShedSignal::new();
+        assert!(!signal.is_shedding(Priority::Low));
+
+        signal.raise();
+        assert!(signal.is_shedding(Priority::Low));
+        assert!(!signal.is_shedding(Priority::Normal));
+
+        signal.shed_below(Priority::Critical);
+        assert!(signal.is_shedding(Priority::High));
+        assert!(!signal.is_shedding(Priority::Critical));
+
+        signal.clear();
+        assert!(!signal.is_raised());
+    }
+
+    #[tokio::test]
+    async fn test_load_shed_service() {
+        let service = LoadShedService::new(
+            OverloadService,
+            |request: &Request<()>, _: &TestEmptyContext| {
+                if request.uri().path().starts_with("/batch") {
+                    Priority::Low
+                } else {
+                    Priority::Normal
+                }
+            },
+            LoadShedSignal::new(),
+        );
+
+        assert_eq!(status(&service, "/batch").await, StatusCode::OK);
+        assert_eq!(status(&service, "/overload").await, StatusCode::OK);
+        assert_eq!(
+            status(&service, "/batch").await,
+            StatusCode::SERVICE_UNAVAILABLE
+        );
+        assert_eq!(status(&service, "/recover").await, StatusCode::OK);
+        assert_eq!(status(&service, "/batch").await, StatusCode::OK);
+    }
+}
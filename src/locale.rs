@@ -0,0 +1,329 @@
+//! Negotiation of the locale of responses using the `Accept-Language` header.
+//!
+//! The `LocaleService` middleware chooses, from the locales a server
+//! supports, the one the client most prefers, and stores it in the context
+//! as a `Locale`, so that handlers can localize messages without parsing the
+//! header themselves.
+//!
+//! A supported locale matches a language range in the header if the range is
+//! the same tag or a prefix of it, as in RFC 4647 basic filtering, so `en`
+//! matches `en-GB`. Failing that, it matches if it is a prefix of the range,
+//! so that a server supporting only `fr` serves clients asking for `fr-CH`.
+//! The server's order of preference breaks ties between equal quality values.
+//!
+//! ```
+//! use hyper::header::{HeaderMap, HeaderValue, ACCEPT_LANGUAGE};
+//! use swagger::locale::{AcceptLanguage, Locale};
+//!
+//! let supported = [Locale::new("en"), Locale::new("fr"), Locale::new("de")];
+//! let accept_language = AcceptLanguage::parse("fr-CH, en;q=0.8, *;q=0.5");
+//! assert_eq!(accept_language.negotiate(&supported), Some(&Locale::new("fr")));
+//! ```
+use crate::content_coding::parse_quality;
+use crate::context::Push;
+use futures::future::{BoxFuture, FutureExt};
+use hyper::header::{HeaderMap, ACCEPT_LANGUAGE};
+use hyper::service::Service;
+use hyper::Request;
+use std::fmt;
+use std::marker::PhantomData;
+use std::sync::Arc;
+
+/// Locale, identified by a language tag such as `en-GB`.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Locale(String);
+
+impl Locale {
+    /// Locale with the given language tag.
+    pub fn new<S: Into<String>>(tag: S) -> Self {
+        Locale(tag.into())
+    }
+
+    /// The language tag.
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+
+    /// The primary language subtag, such as `en` for `en-GB`.
+    pub fn language(&self) -> &str {
+        self.0.split('-').next().unwrap_or_default()
+    }
+}
+
+impl fmt::Display for Locale {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+/// Whether `prefix` is `tag`, or a prefix of it ending at a subtag boundary,
+/// ignoring case.
+fn is_prefix(prefix: &str, tag: &str) -> bool {
+    tag.len() >= prefix.len()
+        && tag.is_char_boundary(prefix.len())
+        && tag[..prefix.len()].eq_ignore_ascii_case(prefix)
+        && (tag.len() == prefix.len() || tag.as_bytes()[prefix.len()] == b'-')
+}
+
+/// Parsed `Accept-Language` header, giving the quality value of each
+/// language range.
+///
+/// Quality values are held in thousandths, from 0 (not acceptable) to 1000.
+/// Entries with invalid quality values are ignored.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct AcceptLanguage {
+    ranges: Vec<(String, u16)>,
+}
+
+impl AcceptLanguage {
+    /// Parse an `Accept-Language` header value.
+    pub fn parse(value: &str) -> Self {
+        let mut accept_language = AcceptLanguage::default();
+        accept_language.add(value);
+        accept_language
+    }
+
+    /// Retrieve the `Accept-Language` headers of a request, or `None` if it
+    /// has none, in which case any locale is acceptable.
+    pub fn from_headers(headers: &HeaderMap) -> Option<Self> {
+        let mut values = headers.get_all(ACCEPT_LANGUAGE).iter().peekable();
+        values.peek()?;
+
+        let mut accept_language = AcceptLanguage::default();
+        for value in values.filter_map(|v| v.to_str().ok()) {
+            accept_language.add(value);
+        }
+        Some(accept_language)
+    }
+
+    fn add(&mut self, value: &str) {
+        for entry in value.split(',').map(str::trim).filter(|e| !e.is_empty()) {
+            let mut params = entry.split(';');
+            let range = params.next().unwrap_or_default().trim();
+            let quality = params
+                .filter_map(|param| param.split_once('='))
+                .find(|(name, _)| name.trim().eq_ignore_ascii_case("q"))
+                .map(|(_, value)| parse_quality(value.trim()))
+                .unwrap_or(Some(1000));
+            if let Some(quality) = quality {
+                self.ranges.push((range.to_string(), quality));
+            }
+        }
+    }
+
+    /// Quality value of the locale, in thousandths, taken from the most
+    /// specific range matching it.
+    pub fn quality(&self, locale: &Locale) -> u16 {
+        let best = |matches: &dyn Fn(&str) -> bool| {
+            self.ranges
+                .iter()
+                .filter(|(range, _)| matches(range))
+                .max_by_key(|(range, _)| range.len())
+                .map(|(_, quality)| *quality)
+        };
+        best(&|range| range != "*" && is_prefix(range, locale.as_str()))
+            .or_else(|| best(&|range| range != "*" && is_prefix(locale.as_str(), range)))
+            .or_else(|| best(&|range| range == "*"))
+            .unwrap_or(0)
+    }
+
+    /// Choose the acceptable locale with the highest quality value, from the
+    /// locales supported by the server in order of preference.
+    ///
+    /// Returns `None` if no supported locale is acceptable.
+    pub fn negotiate<'a>(&self, supported: &'a [Locale]) -> Option<&'a Locale> {
+        let mut best = None;
+        for locale in supported {
+            let quality = self.quality(locale);
+            if quality > 0 && best.is_none_or(|(_, best)| quality > best) {
+                best = Some((locale, quality));
+            }
+        }
+        best.map(|(locale, _)| locale)
+    }
+}
+
+/// Middleware which chooses the locale of each request from its
+/// `Accept-Language` header, and stores it in the context as a `Locale`.
+#[derive(Debug)]
+pub struct MakeLocaleService<T, RC> {
+    inner: T,
+    supported: Arc<Vec<Locale>>,
+    marker: PhantomData<RC>,
+}
+
+impl<T, RC> MakeLocaleService<T, RC> {
+    /// Create a middleware supporting the given locale, which is used when
+    /// the client accepts none of the supported locales.
+    pub fn new(inner: T, default: Locale) -> Self {
+        MakeLocaleService {
+            inner,
+            supported: Arc::new(vec![default]),
+            marker: PhantomData,
+        }
+    }
+
+    /// Support another locale, less preferred than those already supported.
+    pub fn with_locale(mut self, locale: Locale) -> Self {
+        Arc::make_mut(&mut self.supported).push(locale);
+        self
+    }
+}
+
+impl<T: Clone, RC> Clone for MakeLocaleService<T, RC> {
+    fn clone(&self) -> Self {
+        MakeLocaleService {
+            inner: self.inner.clone(),
+            supported: self.supported.clone(),
+            marker: PhantomData,
+        }
+    }
+}
+
+impl<Inner, RC, Target> Service<Target> for MakeLocaleService<Inner, RC>
+where
+    Inner: Service<Target>,
+    Inner::Future: Send + 'static,
+    RC: Send + 'static,
+{
+    type Error = Inner::Error;
+    type Response = LocaleService<Inner::Response, RC>;
+    type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn call(&self, target: Target) -> Self::Future {
+        let supported = self.supported.clone();
+        Box::pin(self.inner.call(target).map(|s| {
+            Ok(LocaleService {
+                inner: s?,
+                supported,
+                marker: PhantomData,
+            })
+        }))
+    }
+}
+
+/// Middleware which chooses the locale of each request from its
+/// `Accept-Language` header, and stores it in the context as a `Locale`.
+///
+/// Requests without the header, or accepting none of the supported locales,
+/// are given the default locale.
+///
+/// ```ignore
+/// let service = LocaleService::new(inner, Locale::new("en"))
+///     .with_locale(Locale::new("fr"))
+///     .with_locale(Locale::new("de"));
+/// ```
+#[derive(Debug)]
+pub struct LocaleService<T, RC> {
+    inner: T,
+    supported: Arc<Vec<Locale>>,
+    marker: PhantomData<RC>,
+}
+
+impl<T, RC> LocaleService<T, RC> {
+    /// Create a middleware supporting the given locale, which is used when
+    /// the client accepts none of the supported locales.
+    pub fn new(inner: T, default: Locale) -> Self {
+        LocaleService {
+            inner,
+            supported: Arc::new(vec![default]),
+            marker: PhantomData,
+        }
+    }
+
+    /// Support another locale, less preferred than those already supported.
+    pub fn with_locale(mut self, locale: Locale) -> Self {
+        Arc::make_mut(&mut self.supported).push(locale);
+        self
+    }
+}
+
+impl<T: Clone, RC> Clone for LocaleService<T, RC> {
+    fn clone(&self) -> Self {
+        LocaleService {
+            inner: self.inner.clone(),
+            supported: self.supported.clone(),
+            marker: PhantomData,
+        }
+    }
+}
+
+impl<T, B, RC> Service<(Request<B>, RC)> for LocaleService<T, RC>
+where
+    RC: Push<Locale>,
+    T: Service<(Request<B>, RC::Result)>,
+{
+    type Response = T::Response;
+    type Error = T::Error;
+    type Future = T::Future;
+
+    fn call(&self, req: (Request<B>, RC)) -> Self::Future {
+        let (request, context) = req;
+        let locale = AcceptLanguage::from_headers(request.headers())
+            .and_then(|accept_language| accept_language.negotiate(&self.supported).cloned())
+            .unwrap_or_else(|| self.supported[0].clone());
+        self.inner.call((request, context.push(locale)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Has;
+    use futures::future::{ok, Ready};
+
+    crate::new_context_type!(TestContext, TestEmptyContext, Locale);
+
+    #[test]
+    fn test_negotiate() {
+        let supported = [Locale::new("en-GB"), Locale::new("fr"), Locale::new("de")];
+        let negotiate = |value: &str| {
+            AcceptLanguage::parse(value)
+                .negotiate(&supported)
+                .map(Locale::as_str)
+        };
+
+        assert_eq!(negotiate("de, fr;q=0.9"), Some("de"));
+        assert_eq!(negotiate("EN, fr"), Some("en-GB"));
+        assert_eq!(negotiate("fr-CA;q=0.5, de;q=0.4"), Some("fr"));
+        assert_eq!(negotiate("fr-CA;q=0.5, fr;q=0.1"), Some("fr"));
+        assert_eq!(negotiate("*;q=0.1, en-GB;q=0"), Some("fr"));
+        assert_eq!(negotiate("enx, es"), None);
+        assert_eq!(negotiate("de;q=2"), None);
+    }
+
+    #[derive(Clone)]
+    struct LocaleReader;
+
+    impl<C: Has<Locale>> Service<(Request<()>, C)> for LocaleReader {
+        type Response = Locale;
+        type Error = ();
+        type Future = Ready<Result<Locale, ()>>;
+
+        fn call(&self, req: (Request<()>, C)) -> Self::Future {
+            ok(req.1.get().clone())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_locale_service() {
+        let service = LocaleService::<_, TestEmptyContext>::new(LocaleReader, Locale::new("en"));
+        // Locales added once the service has been cloned still apply to it.
+        let _clone = service.clone();
+        let service = service.with_locale(Locale::new("fr"));
+
+        let request = Request::get("/")
+            .header(ACCEPT_LANGUAGE, "fr-FR, en;q=0.5")
+            .body(())
+            .unwrap();
+        let locale = service.call((request, TestEmptyContext)).await.unwrap();
+        assert_eq!(locale.as_str(), "fr");
+
+        let request = Request::get("/")
+            .header(ACCEPT_LANGUAGE, "ja")
+            .body(())
+            .unwrap();
+        let locale = service.call((request, TestEmptyContext)).await.unwrap();
+        assert_eq!(locale, Locale::new("en"));
+    }
+}
@@ -0,0 +1,318 @@
+//! Middleware mapping errors from handlers to HTTP responses.
+//!
+//! Rather than each handler converting its domain errors into responses, the
+//! handlers can return them as errors, and a `MapErrorService` wrapping them
+//! can convert them all in one place. With the **serdejson** feature, a
+//! `Problem` can be used to render the response as an RFC 9457 problem
+//! details document:
+//!
+//! ```ignore
+//! let service = MapErrorService::new(service, |error: PetStoreError| {
+//!     let status = match error {
+//!         PetStoreError::NotFound(_) => StatusCode::NOT_FOUND,
+//!         PetStoreError::Database(_) => StatusCode::SERVICE_UNAVAILABLE,
+//!     };
+//!     Problem::new(status).with_detail(error.to_string()).to_response()
+//! });
+//! ```
+use futures::future::{BoxFuture, FutureExt};
+use hyper::service::Service;
+use hyper::Response;
+use std::fmt;
+use std::sync::Arc;
+
+/// Make service which maps errors from the inner service to responses.
+pub struct MakeMapErrorService<T, F> {
+    inner: T,
+    map: Arc<F>,
+}
+
+impl<T, F> MakeMapErrorService<T, F> {
+    /// Create a make service which maps errors from the inner service to
+    /// responses with the given function.
+    pub fn new(inner: T, map: F) -> Self {
+        MakeMapErrorService {
+            inner,
+            map: Arc::new(map),
+        }
+    }
+}
+
+impl<T: Clone, F> Clone for MakeMapErrorService<T, F> {
+    fn clone(&self) -> Self {
+        MakeMapErrorService {
+            inner: self.inner.clone(),
+            map: self.map.clone(),
+        }
+    }
+}
+
+impl<T: fmt::Debug, F> fmt::Debug for MakeMapErrorService<T, F> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("MakeMapErrorService")
+            .field("inner", &self.inner)
+            .finish()
+    }
+}
+
+impl<T, F, Target> Service<Target> for MakeMapErrorService<T, F>
+where
+    T: Service<Target>,
+    T::Future: Send + 'static,
+    F: Send + Sync + 'static,
+{
+    type Response = MapErrorService<T::Response, F>;
+    type Error = T::Error;
+    type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn call(&self, target: Target) -> Self::Future {
+        let map = self.map.clone();
+        Box::pin(
+            self.inner
+                .call(target)
+                .map(|inner| Ok(MapErrorService { inner: inner?, map })),
+        )
+    }
+}
+
+/// Middleware which maps errors from the inner service to responses.
+///
+/// Each error returned by the inner service is passed to the mapping
+/// function, and the response it returns is sent in place of the error. The
+/// service therefore never fails, but keeps the error type of the inner
+/// service, so that it can be composed with other middleware.
+pub struct MapErrorService<T, F> {
+    inner: T,
+    map: Arc<F>,
+}
+
+impl<T, F> MapErrorService<T, F> {
+    /// Create a service which maps errors from the inner service to responses
+    /// with the given function.
+    pub fn new(inner: T, map: F) -> Self {
+        MapErrorService {
+            inner,
+            map: Arc::new(map),
+        }
+    }
+}
+
+impl<T: Clone, F> Clone for MapErrorService<T, F> {
+    fn clone(&self) -> Self {
+        MapErrorService {
+            inner: self.inner.clone(),
+            map: self.map.clone(),
+        }
+    }
+}
+
+impl<T: fmt::Debug, F> fmt::Debug for MapErrorService<T, F> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("MapErrorService")
+            .field("inner", &self.inner)
+            .finish()
+    }
+}
+
+impl<T, F, Req, ResBody> Service<Req> for MapErrorService<T, F>
+where
+    T: Service<Req, Response = Response<ResBody>>,
+    T::Future: Send + 'static,
+    F: Fn(T::Error) -> Response<ResBody> + Send + Sync + 'static,
+{
+    type Response = Response<ResBody>;
+    type Error = T::Error;
+    type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn call(&self, req: Req) -> Self::Future {
+        let map = self.map.clone();
+        Box::pin(
+            self.inner
+                .call(req)
+                .map(move |result| Ok(result.unwrap_or_else(|error| map(error)))),
+        )
+    }
+}
+
+#[cfg(feature = "serdejson")]
+pub use problem::{Problem, APPLICATION_PROBLEM_JSON};
+
+#[cfg(feature = "serdejson")]
+mod problem {
+    use hyper::header::{HeaderValue, CONTENT_TYPE};
+    use hyper::{Response, StatusCode};
+    use serde::Serialize;
+
+    /// Media type of problem details documents - RFC 9457
+    pub const APPLICATION_PROBLEM_JSON: &str = "application/problem+json";
+
+    /// Problem details document, describing an error in an HTTP response, as
+    /// defined in RFC 9457.
+    #[derive(Clone, Debug, PartialEq, Serialize)]
+    pub struct Problem {
+        /// URI identifying the type of problem. Defaults to `about:blank`,
+        /// meaning the problem is described by the status code alone.
+        #[serde(rename = "type", skip_serializing_if = "Option::is_none")]
+        pub problem_type: Option<String>,
+        /// Short summary of the type of problem.
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub title: Option<String>,
+        /// HTTP status code.
+        #[serde(serialize_with = "serialize_status")]
+        pub status: StatusCode,
+        /// Explanation specific to this occurrence of the problem.
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub detail: Option<String>,
+        /// URI identifying this occurrence of the problem.
+        #[serde(skip_serializing_if = "Option::is_none")]
+        pub instance: Option<String>,
+        /// Further members, specific to the type of problem.
+        #[serde(flatten)]
+        pub extensions: serde_json::Map<String, serde_json::Value>,
+    }
+
+    fn serialize_status<S: serde::Serializer>(
+        status: &StatusCode,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        serializer.serialize_u16(status.as_u16())
+    }
+
+    impl Problem {
+        /// Problem with the given status, titled with its canonical reason.
+        pub fn new(status: StatusCode) -> Self {
+            Problem {
+                problem_type: None,
+                title: status.canonical_reason().map(ToString::to_string),
+                status,
+                detail: None,
+                instance: None,
+                extensions: serde_json::Map::new(),
+            }
+        }
+
+        /// Set the URI identifying the type of problem.
+        pub fn with_type<S: Into<String>>(mut self, problem_type: S) -> Self {
+            self.problem_type = Some(problem_type.into());
+            self
+        }
+
+        /// Set the summary of the type of problem.
+        pub fn with_title<S: Into<String>>(mut self, title: S) -> Self {
+            self.title = Some(title.into());
+            self
+        }
+
+        /// Set the explanation of this occurrence of the problem.
+        pub fn with_detail<S: Into<String>>(mut self, detail: S) -> Self {
+            self.detail = Some(detail.into());
+            self
+        }
+
+        /// Set the URI identifying this occurrence of the problem.
+        pub fn with_instance<S: Into<String>>(mut self, instance: S) -> Self {
+            self.instance = Some(instance.into());
+            self
+        }
+
+        /// Add a further member.
+        pub fn with_extension<S: Into<String>>(
+            mut self,
+            name: S,
+            value: serde_json::Value,
+        ) -> Self {
+            self.extensions.insert(name.into(), value);
+            self
+        }
+
+        /// Render the problem as a response.
+        pub fn to_response<B: From<String>>(&self) -> Response<B> {
+            let body = serde_json::to_string(self).unwrap_or_default();
+            let mut response = Response::new(B::from(body));
+            *response.status_mut() = self.status;
+            response.headers_mut().insert(
+                CONTENT_TYPE,
+                HeaderValue::from_static(APPLICATION_PROBLEM_JSON),
+            );
+            response
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hyper::StatusCode;
+
+    #[derive(Debug)]
+    enum PetError {
+        NotFound(u64),
+        Database,
+    }
+
+    struct PetService;
+
+    impl Service<u64> for PetService {
+        type Response = Response<String>;
+        type Error = PetError;
+        type Future = futures::future::Ready<Result<Self::Response, Self::Error>>;
+
+        fn call(&self, id: u64) -> Self::Future {
+            futures::future::ready(match id {
+                1 => Ok(Response::new("Rex".to_string())),
+                2 => Err(PetError::Database),
+                _ => Err(PetError::NotFound(id)),
+            })
+        }
+    }
+
+    #[tokio::test]
+    async fn test_map_error() {
+        let service = MapErrorService::new(PetService, |error| {
+            let (status, body) = match error {
+                PetError::NotFound(id) => (StatusCode::NOT_FOUND, format!("No pet {}", id)),
+                PetError::Database => (StatusCode::SERVICE_UNAVAILABLE, String::new()),
+            };
+            let mut response = Response::new(body);
+            *response.status_mut() = status;
+            response
+        });
+
+        let response = service.call(1).await.unwrap();
+        assert_eq!(response.into_body(), "Rex");
+
+        let response = service.call(2).await.unwrap();
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+
+        let response = service.call(3).await.unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        assert_eq!(response.into_body(), "No pet 3");
+    }
+
+    #[cfg(feature = "serdejson")]
+    #[test]
+    fn test_problem() {
+        let response: Response<String> = Problem::new(StatusCode::NOT_FOUND)
+            .with_type("https://example.com/problems/no-such-pet")
+            .with_detail("No pet with ID 3")
+            .with_extension("petId", serde_json::json!(3))
+            .to_response();
+
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        assert_eq!(
+            response.headers().get(hyper::header::CONTENT_TYPE).unwrap(),
+            APPLICATION_PROBLEM_JSON
+        );
+        let body: serde_json::Value = serde_json::from_str(response.body()).unwrap();
+        assert_eq!(
+            body,
+            serde_json::json!({
+                "type": "https://example.com/problems/no-such-pet",
+                "title": "Not Found",
+                "status": 404,
+                "detail": "No pet with ID 3",
+                "petId": 3,
+            })
+        );
+    }
+}
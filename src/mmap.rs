@@ -0,0 +1,269 @@
+//! Serving large files from memory maps.
+//!
+//! Generated endpoints returning large artifacts, such as reports or firmware
+//! images, can serve them from a memory-mapped file rather than reading it in
+//! chunks. The body is a `Bytes` referencing the map directly, so neither the
+//! whole file nor a requested range is copied:
+//!
+//! ```ignore
+//! async fn firmware(request: &Request<Incoming>) -> Response<MmapBody> {
+//!     match MmapBody::open("/srv/firmware/latest.bin") {
+//!         Ok(body) => body.into_response(request.headers()),
+//!         Err(_) => not_found(),
+//!     }
+//! }
+//! ```
+//!
+//! Mapping a file which is then truncated by another process causes the server
+//! to crash when the missing pages are read, so this must only be used for
+//! files which are replaced, rather than modified, in place.
+use bytes::Bytes;
+use hyper::body::{Body, Frame, SizeHint};
+use hyper::header::{
+    HeaderMap, HeaderValue, ACCEPT_RANGES, CONTENT_LENGTH, CONTENT_RANGE, IF_RANGE, RANGE,
+};
+use hyper::{Response, StatusCode};
+use memmap2::Mmap;
+use std::convert::Infallible;
+use std::fs::File;
+use std::io;
+use std::ops::{Bound, RangeBounds};
+use std::path::Path;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+/// Body serving all or part of a memory-mapped file.
+#[derive(Clone, Debug)]
+pub struct MmapBody {
+    data: Option<Bytes>,
+    len: u64,
+}
+
+impl MmapBody {
+    /// Map the file at the given path, to serve it in full.
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let file = File::open(path)?;
+        // Safety: the map is only used for reading, and modifying the file
+        // while it is mapped is documented as unsupported.
+        let map = unsafe { Mmap::map(&file)? };
+        Ok(Self::from(Bytes::from_owner(map)))
+    }
+
+    /// Length of the body in bytes.
+    pub fn len(&self) -> u64 {
+        self.len
+    }
+
+    /// Whether the body is empty.
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Restrict the body to the given range of bytes, or return `None` if it
+    /// is out of bounds. This doesn't copy the data.
+    pub fn slice<R: RangeBounds<u64>>(&self, range: R) -> Option<Self> {
+        let start = match range.start_bound() {
+            Bound::Included(&start) => start,
+            Bound::Excluded(&start) => start.checked_add(1)?,
+            Bound::Unbounded => 0,
+        };
+        let end = match range.end_bound() {
+            Bound::Included(&end) => end.checked_add(1)?,
+            Bound::Excluded(&end) => end,
+            Bound::Unbounded => self.len,
+        };
+        if start > end || end > self.len {
+            return None;
+        }
+        let data = self.data.as_ref().map_or_else(Bytes::new, |data| {
+            data.slice(
+                usize::try_from(start).unwrap_or(usize::MAX)
+                    ..usize::try_from(end).unwrap_or(usize::MAX),
+            )
+        });
+        Some(Self::from(data))
+    }
+
+    /// Build a response serving the body to a request with the given headers.
+    ///
+    /// A request for a single satisfiable byte range is served with
+    /// `206 Partial Content` and only that range, and one for an unsatisfiable
+    /// range with `416 Range Not Satisfiable`. Requests for multiple ranges,
+    /// or with an `If-Range` precondition, are served the whole body.
+    pub fn into_response(self, headers: &HeaderMap) -> Response<MmapBody> {
+        let range = headers
+            .get(RANGE)
+            .filter(|_| !headers.contains_key(IF_RANGE))
+            .and_then(|range| range.to_str().ok())
+            .and_then(|range| parse_range(range, self.len));
+
+        let len = self.len;
+        let (status, body, content_range) = match range {
+            None => (StatusCode::OK, self, None),
+            Some(Some((start, end))) => match self.slice(start..=end) {
+                Some(body) => (
+                    StatusCode::PARTIAL_CONTENT,
+                    body,
+                    Some(format!("bytes {}-{}/{}", start, end, len)),
+                ),
+                None => (StatusCode::OK, self, None),
+            },
+            Some(None) => (
+                StatusCode::RANGE_NOT_SATISFIABLE,
+                MmapBody::from(Bytes::new()),
+                Some(format!("bytes */{}", len)),
+            ),
+        };
+
+        let content_length = HeaderValue::from(body.len());
+        let mut response = Response::new(body);
+        *response.status_mut() = status;
+        let headers = response.headers_mut();
+        headers.insert(ACCEPT_RANGES, HeaderValue::from_static("bytes"));
+        headers.insert(CONTENT_LENGTH, content_length);
+        if let Some(content_range) = content_range.and_then(|c| HeaderValue::try_from(c).ok()) {
+            headers.insert(CONTENT_RANGE, content_range);
+        }
+        response
+    }
+}
+
+/// Parse a `Range` header for a body of the given length, as described in
+/// RFC 9110 section 14.
+///
+/// Returns `None` if the header should be ignored, `Some(None)` if the range
+/// isn't satisfiable, and otherwise the first and last byte of the range.
+fn parse_range(range: &str, len: u64) -> Option<Option<(u64, u64)>> {
+    let spec = range.trim().strip_prefix("bytes=")?.trim();
+    if spec.contains(',') {
+        return None;
+    }
+    let (first, last) = spec.split_once('-')?;
+    let (first, last) = (first.trim(), last.trim());
+
+    if first.is_empty() {
+        // Suffix range - the last N bytes.
+        let suffix = last.parse::<u64>().ok()?;
+        if suffix == 0 || len == 0 {
+            return Some(None);
+        }
+        return Some(Some((len.saturating_sub(suffix), len - 1)));
+    }
+
+    let first = first.parse::<u64>().ok()?;
+    let last = match last {
+        "" => u64::MAX,
+        last => last.parse::<u64>().ok()?,
+    };
+    if last < first {
+        return None;
+    }
+    if first >= len {
+        return Some(None);
+    }
+    Some(Some((first, last.min(len - 1))))
+}
+
+impl From<Bytes> for MmapBody {
+    fn from(data: Bytes) -> Self {
+        MmapBody {
+            len: data.len() as u64,
+            data: Some(data).filter(|data| !data.is_empty()),
+        }
+    }
+}
+
+impl Body for MmapBody {
+    type Data = Bytes;
+    type Error = Infallible;
+
+    fn poll_frame(
+        mut self: Pin<&mut Self>,
+        _: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<Bytes>, Infallible>>> {
+        Poll::Ready(self.data.take().map(|data| Ok(Frame::data(data))))
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.data.is_none()
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        SizeHint::with_exact(self.data.as_ref().map_or(0, |data| data.len() as u64))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use http_body_util::BodyExt;
+    use std::io::Write;
+
+    fn body() -> MmapBody {
+        let path = std::env::temp_dir().join(format!("swagger-mmap-{}", uuid::Uuid::new_v4()));
+        File::create(&path)
+            .unwrap()
+            .write_all(b"0123456789")
+            .unwrap();
+        let body = MmapBody::open(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        body
+    }
+
+    async fn response(range: Option<&'static str>) -> (StatusCode, HeaderMap, Bytes) {
+        let mut headers = HeaderMap::new();
+        if let Some(range) = range {
+            headers.insert(RANGE, HeaderValue::from_static(range));
+        }
+        let (parts, body) = body().into_response(&headers).into_parts();
+        let body = body.collect().await.unwrap().to_bytes();
+        (parts.status, parts.headers, body)
+    }
+
+    #[test]
+    fn test_parse_range() {
+        assert_eq!(parse_range("bytes=0-4", 10), Some(Some((0, 4))));
+        assert_eq!(parse_range("bytes=5-", 10), Some(Some((5, 9))));
+        assert_eq!(parse_range("bytes=8-20", 10), Some(Some((8, 9))));
+        assert_eq!(parse_range("bytes=-3", 10), Some(Some((7, 9))));
+        assert_eq!(parse_range("bytes=-30", 10), Some(Some((0, 9))));
+        assert_eq!(parse_range("bytes=10-", 10), Some(None));
+        assert_eq!(parse_range("bytes=-0", 10), Some(None));
+        assert_eq!(parse_range("bytes=0-1,4-5", 10), None);
+        assert_eq!(parse_range("bytes=4-1", 10), None);
+        assert_eq!(parse_range("items=0-1", 10), None);
+    }
+
+    #[test]
+    fn test_slice() {
+        let body = body();
+        assert_eq!(body.len(), 10);
+        assert_eq!(body.slice(2..5).unwrap().len(), 3);
+        assert_eq!(body.slice(..).unwrap().len(), 10);
+        assert!(body.slice(5..11).is_none());
+    }
+
+    #[tokio::test]
+    async fn test_response() {
+        let (status, headers, body) = response(None).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(headers[ACCEPT_RANGES], "bytes");
+        assert_eq!(headers[CONTENT_LENGTH], "10");
+        assert_eq!(body, "0123456789");
+
+        let (status, headers, body) = response(Some("bytes=2-4")).await;
+        assert_eq!(status, StatusCode::PARTIAL_CONTENT);
+        assert_eq!(headers[CONTENT_RANGE], "bytes 2-4/10");
+        assert_eq!(headers[CONTENT_LENGTH], "3");
+        assert_eq!(body, "234");
+
+        let (status, headers, body) = response(Some("bytes=20-")).await;
+        assert_eq!(status, StatusCode::RANGE_NOT_SATISFIABLE);
+        assert_eq!(headers[CONTENT_RANGE], "bytes */10");
+        assert!(body.is_empty());
+
+        let (status, _, body) = response(Some("bytes=0-1,3-4")).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body.len(), 10);
+    }
+}
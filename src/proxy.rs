@@ -0,0 +1,349 @@
+//! Reverse proxy service, for forwarding requests to an upstream server.
+//!
+//! This allows a generated API to front a legacy backend within a single
+//! `CompositeMakeService`, with the legacy backend gradually being replaced.
+use crate::connection_info::{ConnectionInfo, HasConnectionInfo};
+use crate::context::Has;
+use futures::future::BoxFuture;
+use hyper::header::{HeaderMap, HeaderName, HeaderValue, CONNECTION, HOST};
+use hyper::service::Service;
+use hyper::{Request, Response, Uri};
+use std::net::SocketAddr;
+
+/// Header - `X-Forwarded-For` - IP addresses of the clients and proxies a request has passed through.
+pub const X_FORWARDED_FOR: &str = "X-Forwarded-For";
+
+/// Header - `X-Forwarded-Host` - original `Host` requested by the client.
+pub const X_FORWARDED_HOST: &str = "X-Forwarded-Host";
+
+/// Header - `X-Forwarded-Proto` - protocol used by the client to connect to the proxy.
+pub const X_FORWARDED_PROTO: &str = "X-Forwarded-Proto";
+
+/// Hop-by-hop headers, as defined in RFC 9110 section 7.6.1, which must not be
+/// forwarded by a proxy.
+const HOP_BY_HOP_HEADERS: &[&str] = &[
+    "connection",
+    "keep-alive",
+    "proxy-authenticate",
+    "proxy-authorization",
+    "proxy-connection",
+    "te",
+    "trailer",
+    "transfer-encoding",
+    "upgrade",
+];
+
+/// Remove hop-by-hop headers, including any headers nominated by the
+/// `Connection` header.
+pub fn strip_hop_by_hop_headers(headers: &mut HeaderMap) {
+    let nominated: Vec<HeaderName> = headers
+        .get_all(CONNECTION)
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(','))
+        .filter_map(|name| HeaderName::from_bytes(name.trim().as_bytes()).ok())
+        .collect();
+
+    for name in nominated {
+        headers.remove(name);
+    }
+
+    for name in HOP_BY_HOP_HEADERS {
+        headers.remove(*name);
+    }
+}
+
+/// Build the URI for the upstream request by appending the path and query of the
+/// incoming request to the upstream base URI.
+fn upstream_uri(upstream: &Uri, uri: &Uri) -> Result<Uri, hyper::http::Error> {
+    let base_path = upstream.path().trim_end_matches('/');
+    let path_and_query = uri.path_and_query().map(|p| p.as_str()).unwrap_or("/");
+
+    let mut parts = upstream.clone().into_parts();
+    parts.path_and_query = Some(format!("{}{}", base_path, path_and_query).parse()?);
+
+    Ok(Uri::from_parts(parts)?)
+}
+
+/// Make service which forwards all requests to an upstream server.
+///
+/// Example Usage
+/// =============
+///
+/// ```ignore
+/// let client = TowerToHyperService::new(Client::builder(TokioExecutor::new()).build_http());
+///
+/// let mut composite_make_service = CompositeMakeService::new();
+/// composite_make_service.push(("/v2", generated_make_service));
+/// composite_make_service.push(("/", MakeProxyService::new(client, "http://legacy:8080".parse()?)));
+/// ```
+#[derive(Debug, Clone)]
+pub struct MakeProxyService<C> {
+    client: C,
+    upstream: Uri,
+}
+
+impl<C> MakeProxyService<C> {
+    /// Create a make service which forwards requests to the upstream base URI,
+    /// using the provided client.
+    pub fn new(client: C, upstream: Uri) -> Self {
+        MakeProxyService { client, upstream }
+    }
+}
+
+impl<C, Target> Service<Target> for MakeProxyService<C>
+where
+    C: Clone,
+    Target: HasConnectionInfo,
+{
+    type Response = ProxyService<C>;
+    type Error = std::convert::Infallible;
+    type Future = futures::future::Ready<Result<Self::Response, Self::Error>>;
+
+    fn call(&self, target: Target) -> Self::Future {
+        let service = ProxyService::new(self.client.clone(), self.upstream.clone())
+            .with_connection_info(target.connection_info());
+        futures::future::ok(service)
+    }
+}
+
+/// Service which forwards requests to an upstream server.
+///
+/// The request and response bodies are passed through unchanged, so are
+/// streamed in both directions. Hop-by-hop headers are removed, and
+/// `X-Forwarded-For`, `X-Forwarded-Host` and `X-Forwarded-Proto` headers are
+/// added to the upstream request, describing the connection the request
+/// arrived on.
+///
+/// For plain requests, that is the connection the service was made for, or
+/// set with `with_connection_info`. For `(Request, Context)` tuples, it is
+/// the `ConnectionInfo` in the context, as stored by a
+/// `MakeConnectionInfoService`.
+#[derive(Debug, Clone)]
+pub struct ProxyService<C> {
+    client: C,
+    upstream: Uri,
+    info: ConnectionInfo,
+}
+
+impl<C> ProxyService<C> {
+    /// Create a service which forwards requests to the upstream base URI,
+    /// using the provided client.
+    pub fn new(client: C, upstream: Uri) -> Self {
+        ProxyService {
+            client,
+            upstream,
+            info: ConnectionInfo::default(),
+        }
+    }
+
+    /// Set the address of the connected client, used for `X-Forwarded-For`.
+    pub fn with_remote_addr(mut self, remote_addr: Option<SocketAddr>) -> Self {
+        self.info.remote_addr = remote_addr;
+        self
+    }
+
+    /// Set the details of the connection, used for `X-Forwarded-For` and
+    /// `X-Forwarded-Proto`.
+    pub fn with_connection_info(mut self, info: ConnectionInfo) -> Self {
+        self.info = info;
+        self
+    }
+
+    fn forward<ReqBody, ResBody>(
+        &self,
+        mut request: Request<ReqBody>,
+        info: &ConnectionInfo,
+    ) -> BoxFuture<'static, Result<Response<ResBody>, C::Error>>
+    where
+        C: Service<Request<ReqBody>, Response = Response<ResBody>>,
+        C::Error: From<hyper::http::Error> + Send + 'static,
+        C::Future: Send + 'static,
+        ResBody: Send + 'static,
+    {
+        let uri = match upstream_uri(&self.upstream, request.uri()) {
+            Ok(uri) => uri,
+            Err(e) => return Box::pin(futures::future::err(e.into())),
+        };
+
+        forward_headers(&mut request, info);
+        *request.uri_mut() = uri;
+
+        let response = self.client.call(request);
+
+        Box::pin(async move {
+            let mut response = response.await?;
+            strip_hop_by_hop_headers(response.headers_mut());
+            Ok(response)
+        })
+    }
+}
+
+fn forward_headers<B>(request: &mut Request<B>, info: &ConnectionInfo) {
+    let host = request.headers_mut().remove(HOST);
+    let proto = if info.tls { "https" } else { "http" };
+
+    let headers = request.headers_mut();
+    strip_hop_by_hop_headers(headers);
+
+    if let Some(remote_addr) = info.remote_addr {
+        // Earlier proxies may have added the header more than once.
+        let mut forwarded_for: Vec<String> = headers
+            .get_all(X_FORWARDED_FOR)
+            .iter()
+            .filter_map(|v| v.to_str().ok())
+            .map(str::to_string)
+            .collect();
+        forwarded_for.push(remote_addr.ip().to_string());
+        if let Ok(value) = HeaderValue::from_str(&forwarded_for.join(", ")) {
+            headers.insert(X_FORWARDED_FOR, value);
+        }
+    }
+
+    if let Some(host) = host {
+        headers.insert(X_FORWARDED_HOST, host);
+    }
+    headers.insert(X_FORWARDED_PROTO, HeaderValue::from_static(proto));
+}
+
+impl<C, ReqBody, ResBody> Service<Request<ReqBody>> for ProxyService<C>
+where
+    C: Service<Request<ReqBody>, Response = Response<ResBody>>,
+    C::Error: From<hyper::http::Error> + Send + 'static,
+    C::Future: Send + 'static,
+    ResBody: Send + 'static,
+{
+    type Response = Response<ResBody>;
+    type Error = C::Error;
+    type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn call(&self, request: Request<ReqBody>) -> Self::Future {
+        self.forward(request, &self.info)
+    }
+}
+
+impl<C, ReqBody, ResBody, RC> Service<(Request<ReqBody>, RC)> for ProxyService<C>
+where
+    C: Service<Request<ReqBody>, Response = Response<ResBody>>,
+    C::Error: From<hyper::http::Error> + Send + 'static,
+    C::Future: Send + 'static,
+    ResBody: Send + 'static,
+    RC: Has<ConnectionInfo>,
+{
+    type Response = Response<ResBody>;
+    type Error = C::Error;
+    type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn call(&self, (request, context): (Request<ReqBody>, RC)) -> Self::Future {
+        self.forward(request, context.get())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use http_body_util::{BodyExt, Full};
+    use hyper::body::Bytes;
+
+    crate::new_context_type!(TestContext, TestEmptyContext, ConnectionInfo);
+
+    #[derive(Clone)]
+    struct EchoClient;
+
+    impl Service<Request<Full<Bytes>>> for EchoClient {
+        type Response = Response<Full<Bytes>>;
+        type Error = TestError;
+        type Future = futures::future::Ready<Result<Self::Response, Self::Error>>;
+
+        fn call(&self, req: Request<Full<Bytes>>) -> Self::Future {
+            let mut response = Response::new(Full::new(Bytes::from(req.uri().to_string())));
+            *response.headers_mut() = req.headers().clone();
+            response
+                .headers_mut()
+                .insert("keep-alive", HeaderValue::from_static("timeout=5"));
+            futures::future::ok(response)
+        }
+    }
+
+    #[derive(Debug)]
+    struct TestError;
+
+    impl From<hyper::http::Error> for TestError {
+        fn from(_: hyper::http::Error) -> Self {
+            TestError
+        }
+    }
+
+    #[test]
+    fn test_upstream_uri() {
+        let upstream: Uri = "http://legacy:8080/base/".parse().unwrap();
+        let uri: Uri = "/foo/bar?baz=1".parse().unwrap();
+        assert_eq!(
+            upstream_uri(&upstream, &uri).unwrap(),
+            "http://legacy:8080/base/foo/bar?baz=1"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_proxy_service() {
+        let make_service = MakeProxyService::new(EchoClient, "http://legacy".parse().unwrap());
+        let service = make_service
+            .call(Some("10.0.0.1:1234".parse().unwrap()))
+            .await
+            .unwrap();
+
+        let request = Request::get("/foo")
+            .header(HOST, "api.example.com")
+            .header(CONNECTION, "close, x-hop")
+            .header("x-hop", "1")
+            .header("x-end-to-end", "1")
+            .header(X_FORWARDED_FOR, "192.168.0.1")
+            .body(Full::default())
+            .unwrap();
+
+        let response = service.call(request).await.unwrap();
+        let headers = response.headers().clone();
+
+        assert_eq!(
+            headers.get(X_FORWARDED_FOR).unwrap(),
+            "192.168.0.1, 10.0.0.1"
+        );
+        assert_eq!(headers.get(X_FORWARDED_HOST).unwrap(), "api.example.com");
+        assert_eq!(headers.get(X_FORWARDED_PROTO).unwrap(), "http");
+        assert_eq!(headers.get("x-end-to-end").unwrap(), "1");
+        assert!(headers.get(CONNECTION).is_none());
+        assert!(headers.get("x-hop").is_none());
+        assert!(headers.get("keep-alive").is_none());
+        assert!(headers.get(HOST).is_none());
+
+        let body = response.into_body().collect().await.unwrap().to_bytes();
+        assert_eq!(body, "http://legacy/foo");
+    }
+
+    #[tokio::test]
+    async fn test_proxy_service_context() {
+        use crate::context::Push;
+
+        let service = ProxyService::new(EchoClient, "http://legacy".parse().unwrap());
+        let context: TestContext<ConnectionInfo, TestEmptyContext> =
+            TestEmptyContext.push(ConnectionInfo {
+                remote_addr: Some("10.0.0.1:1234".parse().unwrap()),
+                local_addr: None,
+                tls: true,
+            });
+
+        let request = Request::get("/foo")
+            .header(X_FORWARDED_FOR, "192.168.0.1")
+            .header(X_FORWARDED_FOR, "192.168.0.2, 192.168.0.3")
+            .body(Full::default())
+            .unwrap();
+
+        let response = service.call((request, context)).await.unwrap();
+        let headers = response.headers();
+        assert_eq!(
+            headers.get(X_FORWARDED_FOR).unwrap(),
+            "192.168.0.1, 192.168.0.2, 192.168.0.3, 10.0.0.1"
+        );
+        assert_eq!(headers.get(X_FORWARDED_PROTO).unwrap(), "https");
+    }
+}
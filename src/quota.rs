@@ -0,0 +1,479 @@
+//! Per-caller quota accounting, for metering usage of an API over longer
+//! periods than a rate limit, such as a day or a month.
+//!
+//! Usage is counted per caller, identified by the subject of the request's
+//! `Authorization`, in fixed windows aligned to UTC hours, days or calendar
+//! months. Counts are kept in a pluggable `QuotaStore`, so they can be shared
+//! between instances of a server and survive restarts.
+use crate::auth::Authorization;
+use crate::context::Has;
+use crate::ApiError;
+use futures::future::{BoxFuture, FutureExt};
+use hyper::header::{HeaderName, HeaderValue, RETRY_AFTER};
+use hyper::service::Service;
+use hyper::{HeaderMap, Request, Response, StatusCode};
+use std::collections::HashMap;
+use std::fmt;
+use std::marker::PhantomData;
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// `RateLimit` header, from the IETF RateLimit header fields draft.
+pub const RATELIMIT: HeaderName = HeaderName::from_static("ratelimit");
+/// `X-RateLimit-Limit` header.
+pub const X_RATELIMIT_LIMIT: HeaderName = HeaderName::from_static("x-ratelimit-limit");
+/// `X-RateLimit-Remaining` header.
+pub const X_RATELIMIT_REMAINING: HeaderName = HeaderName::from_static("x-ratelimit-remaining");
+/// `X-RateLimit-Reset` header, giving the time the quota resets in seconds since the Unix epoch.
+pub const X_RATELIMIT_RESET: HeaderName = HeaderName::from_static("x-ratelimit-reset");
+
+const SECS_PER_DAY: u64 = 86_400;
+
+/// Convert days since the Unix epoch to a (year, month) in the proleptic
+/// Gregorian calendar, with months numbered from 1.
+fn month_from_days(days: u64) -> (u64, u64) {
+    // Shift the epoch to 0000-03-01, so leap days fall at the end of the year.
+    let days = days + 719_468;
+    let era = days / 146_097;
+    let day_of_era = days % 146_097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month = (5 * day_of_year + 2) / 153;
+    let month = if month < 10 { month + 3 } else { month - 9 };
+    let year = year_of_era + era * 400 + u64::from(month <= 2);
+    (year, month)
+}
+
+/// Convert the first day of a month to days since the Unix epoch.
+fn days_from_month(year: u64, month: u64) -> u64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year / 400;
+    let year_of_era = year % 400;
+    let month = if month > 2 { month - 3 } else { month + 9 };
+    let day_of_year = (153 * month + 2) / 5;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146_097 + day_of_era - 719_468
+}
+
+/// Period over which a quota applies.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum QuotaPeriod {
+    /// UTC hours.
+    Hour,
+    /// UTC days.
+    Day,
+    /// UTC calendar months.
+    Month,
+}
+
+impl QuotaPeriod {
+    /// Name of the period, used in store keys.
+    pub fn name(&self) -> &'static str {
+        match self {
+            QuotaPeriod::Hour => "hour",
+            QuotaPeriod::Day => "day",
+            QuotaPeriod::Month => "month",
+        }
+    }
+
+    /// Start and end of the window containing the given time, in seconds
+    /// since the Unix epoch.
+    pub fn window(&self, time: SystemTime) -> (u64, u64) {
+        let secs = time
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        match self {
+            QuotaPeriod::Hour => (secs / 3600 * 3600, (secs / 3600 + 1) * 3600),
+            QuotaPeriod::Day => (
+                secs / SECS_PER_DAY * SECS_PER_DAY,
+                (secs / SECS_PER_DAY + 1) * SECS_PER_DAY,
+            ),
+            QuotaPeriod::Month => {
+                let (year, month) = month_from_days(secs / SECS_PER_DAY);
+                let (next_year, next_month) = if month == 12 {
+                    (year + 1, 1)
+                } else {
+                    (year, month + 1)
+                };
+                (
+                    days_from_month(year, month) * SECS_PER_DAY,
+                    days_from_month(next_year, next_month) * SECS_PER_DAY,
+                )
+            }
+        }
+    }
+}
+
+/// Maximum number of requests a caller may make in each window of a period.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Quota {
+    /// Maximum number of requests.
+    pub limit: u64,
+    /// Period over which requests are counted.
+    pub period: QuotaPeriod,
+}
+
+impl Quota {
+    /// Create a quota of `limit` requests per period.
+    pub fn new(limit: u64, period: QuotaPeriod) -> Self {
+        Quota { limit, period }
+    }
+}
+
+/// Store of usage counts, shared between the instances of a server.
+pub trait QuotaStore: Send + Sync {
+    /// Atomically increment the count for the key, returning the new count.
+    ///
+    /// Each window has its own key, and the store may discard the count once
+    /// the window has ended, at `expires`.
+    fn increment(
+        &self,
+        key: String,
+        expires: SystemTime,
+    ) -> BoxFuture<'static, Result<u64, ApiError>>;
+}
+
+/// `QuotaStore` keeping counts in memory, for single-instance servers and tests.
+#[derive(Debug, Default)]
+pub struct MemoryQuotaStore {
+    counts: Mutex<HashMap<String, (u64, SystemTime)>>,
+}
+
+impl MemoryQuotaStore {
+    /// Create an empty store.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn lock(&self) -> MutexGuard<'_, HashMap<String, (u64, SystemTime)>> {
+        self.counts.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+impl QuotaStore for MemoryQuotaStore {
+    fn increment(
+        &self,
+        key: String,
+        expires: SystemTime,
+    ) -> BoxFuture<'static, Result<u64, ApiError>> {
+        let mut counts = self.lock();
+        if !counts.contains_key(&key) {
+            let now = SystemTime::now();
+            counts.retain(|_, (_, expires)| *expires > now);
+        }
+        let (count, _) = counts.entry(key).or_insert((0, expires));
+        *count += 1;
+        futures::future::ok(*count).boxed()
+    }
+}
+
+/// Usage of the most constrained quota of a caller, as reported in response headers.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct Usage {
+    limit: u64,
+    count: u64,
+    reset: u64,
+}
+
+impl Usage {
+    fn remaining(&self) -> u64 {
+        self.limit.saturating_sub(self.count)
+    }
+
+    fn apply(&self, headers: &mut HeaderMap, now: u64) {
+        let reset_after = self.reset.saturating_sub(now);
+        headers.insert(X_RATELIMIT_LIMIT, HeaderValue::from(self.limit));
+        headers.insert(X_RATELIMIT_REMAINING, HeaderValue::from(self.remaining()));
+        headers.insert(X_RATELIMIT_RESET, HeaderValue::from(self.reset));
+        if let Ok(value) = HeaderValue::from_str(&format!(
+            "limit={}, remaining={}, reset={}",
+            self.limit,
+            self.remaining(),
+            reset_after
+        )) {
+            headers.insert(RATELIMIT, value);
+        }
+    }
+}
+
+/// Middleware which meters requests against per-caller quotas.
+pub struct MakeQuotaService<T, S, RC> {
+    inner: T,
+    store: Arc<S>,
+    quotas: Arc<Vec<Quota>>,
+    marker: PhantomData<RC>,
+}
+
+impl<T: fmt::Debug, S, RC> fmt::Debug for MakeQuotaService<T, S, RC> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("MakeQuotaService")
+            .field("inner", &self.inner)
+            .field("quotas", &self.quotas)
+            .finish()
+    }
+}
+
+impl<T, S, RC> MakeQuotaService<T, S, RC> {
+    /// Create a middleware enforcing the given quotas, counting usage in the store.
+    pub fn new(inner: T, store: S, quotas: Vec<Quota>) -> Self {
+        MakeQuotaService {
+            inner,
+            store: Arc::new(store),
+            quotas: Arc::new(quotas),
+            marker: PhantomData,
+        }
+    }
+}
+
+impl<Inner, S, RC, Target> Service<Target> for MakeQuotaService<Inner, S, RC>
+where
+    Inner: Service<Target>,
+    Inner::Future: Send + 'static,
+    S: Send + Sync + 'static,
+    RC: Send + 'static,
+{
+    type Error = Inner::Error;
+    type Response = QuotaService<Inner::Response, S, RC>;
+    type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn call(&self, target: Target) -> Self::Future {
+        let store = self.store.clone();
+        let quotas = self.quotas.clone();
+        Box::pin(self.inner.call(target).map(|s| {
+            Ok(QuotaService {
+                inner: s?,
+                store,
+                quotas,
+                marker: PhantomData,
+            })
+        }))
+    }
+}
+
+/// Middleware which meters requests against per-caller quotas.
+///
+/// This must be placed after an authenticator, which stores the
+/// `Option<Authorization>` in the context; callers are identified by its
+/// subject, and unauthenticated requests are not metered. Each request counts
+/// against every quota, and responses carry `RateLimit` and `X-RateLimit-*`
+/// headers describing the quota with the fewest requests remaining. Requests
+/// exceeding any quota are rejected with `429 Too Many Requests` and a
+/// `Retry-After` header giving the time until the quota resets, and still
+/// count towards usage.
+///
+/// If the store fails, requests are passed on without metering, so that an
+/// outage of the store doesn't take down the API.
+///
+/// ```ignore
+/// let service = QuotaService::new(
+///     inner,
+///     MemoryQuotaStore::new(),
+///     vec![Quota::new(1000, QuotaPeriod::Day), Quota::new(20000, QuotaPeriod::Month)],
+/// );
+/// ```
+pub struct QuotaService<T, S, RC> {
+    inner: T,
+    store: Arc<S>,
+    quotas: Arc<Vec<Quota>>,
+    marker: PhantomData<RC>,
+}
+
+impl<T: fmt::Debug, S, RC> fmt::Debug for QuotaService<T, S, RC> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("QuotaService")
+            .field("inner", &self.inner)
+            .field("quotas", &self.quotas)
+            .finish()
+    }
+}
+
+impl<T, S, RC> QuotaService<T, S, RC> {
+    /// Create a middleware enforcing the given quotas, counting usage in the store.
+    pub fn new(inner: T, store: S, quotas: Vec<Quota>) -> Self {
+        QuotaService {
+            inner,
+            store: Arc::new(store),
+            quotas: Arc::new(quotas),
+            marker: PhantomData,
+        }
+    }
+}
+
+impl<T, S, RC> Clone for QuotaService<T, S, RC>
+where
+    T: Clone,
+{
+    fn clone(&self) -> Self {
+        Self {
+            inner: self.inner.clone(),
+            store: self.store.clone(),
+            quotas: self.quotas.clone(),
+            marker: PhantomData,
+        }
+    }
+}
+
+impl<T, S, B, ResBody, RC> Service<(Request<B>, RC)> for QuotaService<T, S, RC>
+where
+    RC: Has<Option<Authorization>> + Send + 'static,
+    T: Service<(Request<B>, RC), Response = Response<ResBody>> + Clone + Send + 'static,
+    T::Future: Send + 'static,
+    S: QuotaStore + 'static,
+    B: Send + 'static,
+    ResBody: Default + Send + 'static,
+{
+    type Response = Response<ResBody>;
+    type Error = T::Error;
+    type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn call(&self, req: (Request<B>, RC)) -> Self::Future {
+        let (request, context) = req;
+        let caller = Has::<Option<Authorization>>::get(&context)
+            .as_ref()
+            .map(|authorization| authorization.subject.clone());
+        let service = self.clone();
+
+        Box::pin(async move {
+            let caller = match caller {
+                Some(caller) if !service.quotas.is_empty() => caller,
+                _ => return service.inner.call((request, context)).await,
+            };
+
+            let time = SystemTime::now();
+            let now = time
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs();
+            let mut usage: Option<Usage> = None;
+            let mut exceeded: Option<Usage> = None;
+
+            for quota in service.quotas.iter() {
+                let (start, reset) = quota.period.window(time);
+                let key = format!("{}:{}:{}", caller, quota.period.name(), start);
+                let expires = UNIX_EPOCH + Duration::from_secs(reset);
+                let count = match service.store.increment(key, expires).await {
+                    Ok(count) => count,
+                    Err(_) => return service.inner.call((request, context)).await,
+                };
+
+                let current = Usage {
+                    limit: quota.limit,
+                    count,
+                    reset,
+                };
+                if count > quota.limit && exceeded.is_none_or(|e| e.reset < reset) {
+                    exceeded = Some(current);
+                }
+                if usage.is_none_or(|u| current.remaining() < u.remaining()) {
+                    usage = Some(current);
+                }
+            }
+
+            if let Some(exceeded) = exceeded {
+                let mut response = Response::new(ResBody::default());
+                *response.status_mut() = StatusCode::TOO_MANY_REQUESTS;
+                exceeded.apply(response.headers_mut(), now);
+                response.headers_mut().insert(
+                    RETRY_AFTER,
+                    HeaderValue::from(exceeded.reset.saturating_sub(now)),
+                );
+                return Ok(response);
+            }
+
+            let mut response = service.inner.call((request, context)).await?;
+            if let Some(usage) = usage {
+                usage.apply(response.headers_mut(), now);
+            }
+            Ok(response)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::auth::Scopes;
+    use crate::context::{ContextBuilder, Push};
+    use crate::EmptyContext;
+
+    type Context = ContextBuilder<Option<Authorization>, EmptyContext>;
+
+    #[test]
+    fn test_windows() {
+        // 2024-02-29T12:34:56Z
+        let time = UNIX_EPOCH + Duration::from_secs(1_709_210_096);
+        assert_eq!(
+            QuotaPeriod::Hour.window(time),
+            (1_709_208_000, 1_709_211_600)
+        );
+        assert_eq!(
+            QuotaPeriod::Day.window(time),
+            (1_709_164_800, 1_709_251_200)
+        );
+        // 2024-02-01T00:00:00Z to 2024-03-01T00:00:00Z
+        assert_eq!(
+            QuotaPeriod::Month.window(time),
+            (1_706_745_600, 1_709_251_200)
+        );
+        // 2023-12-01T00:00:00Z to 2024-01-01T00:00:00Z
+        let time = UNIX_EPOCH + Duration::from_secs(1_703_980_800);
+        assert_eq!(
+            QuotaPeriod::Month.window(time),
+            (1_701_388_800, 1_704_067_200)
+        );
+    }
+
+    #[derive(Clone)]
+    struct OkService;
+
+    impl Service<(Request<()>, Context)> for OkService {
+        type Response = Response<String>;
+        type Error = ();
+        type Future = futures::future::Ready<Result<Self::Response, Self::Error>>;
+
+        fn call(&self, _: (Request<()>, Context)) -> Self::Future {
+            futures::future::ok(Response::new("ok".to_string()))
+        }
+    }
+
+    #[tokio::test]
+    async fn test_quota_service() {
+        let service = QuotaService::new(
+            OkService,
+            MemoryQuotaStore::new(),
+            vec![
+                Quota::new(2, QuotaPeriod::Day),
+                Quota::new(10, QuotaPeriod::Month),
+            ],
+        );
+        let call = |caller: Option<&str>| {
+            let context: Context = EmptyContext
+                .push(caller.map(|caller| Authorization::new(caller.to_string(), Scopes::All)));
+            service.call((Request::new(()), context))
+        };
+
+        let response = call(Some("alice")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()[X_RATELIMIT_LIMIT], "2");
+        assert_eq!(response.headers()[X_RATELIMIT_REMAINING], "1");
+        assert!(response.headers()[RATELIMIT]
+            .to_str()
+            .unwrap()
+            .starts_with("limit=2, remaining=1, reset="));
+
+        call(Some("alice")).await.unwrap();
+        let response = call(Some("alice")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(response.headers()[X_RATELIMIT_REMAINING], "0");
+        assert!(response.headers().contains_key(RETRY_AFTER));
+
+        // Other callers have their own quota, and anonymous callers are not metered.
+        let response = call(Some("bob")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let response = call(None).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert!(!response.headers().contains_key(X_RATELIMIT_LIMIT));
+    }
+}
@@ -0,0 +1,301 @@
+//! Formatting of requests and responses for logs, with secrets masked.
+//!
+//! Printing a `Request` or `HeaderMap` with `{:?}` writes credentials such as
+//! bearer tokens, cookies and API keys into logs. The wrappers in this module
+//! print the same information with the values of sensitive headers and query
+//! parameters replaced by `[REDACTED]`:
+//!
+//! ```
+//! # use swagger::redact::DisplayRequest;
+//! let request = hyper::Request::get("/pets?api_key=secret&limit=10")
+//!     .header("Authorization", "Bearer secret")
+//!     .header("Accept", "application/json")
+//!     .body(())
+//!     .unwrap();
+//!
+//! assert_eq!(
+//!     DisplayRequest::new(&request).to_string(),
+//!     r#"GET /pets?api_key=[REDACTED]&limit=10 {"authorization": [REDACTED], "accept": "application/json"}"#
+//! );
+//! ```
+use hyper::header::{HeaderMap, HeaderName, HeaderValue};
+use hyper::{Request, Response, Uri};
+use std::fmt;
+use std::sync::OnceLock;
+
+/// Placeholder written in place of redacted values.
+const REDACTED: &str = "[REDACTED]";
+
+/// Which header and query parameter values to mask.
+///
+/// The default masks `Authorization`, `Proxy-Authorization`, `Cookie`,
+/// `Set-Cookie` and common API key and token headers, along with the
+/// `api_key` and `access_token` query parameters.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Redaction {
+    headers: Vec<HeaderName>,
+    query_parameters: Vec<String>,
+}
+
+impl Redaction {
+    /// Redaction which masks nothing.
+    pub fn new() -> Self {
+        Redaction {
+            headers: Vec::new(),
+            query_parameters: Vec::new(),
+        }
+    }
+
+    /// Also mask the value of the given header, such as the header an API
+    /// key is passed in.
+    pub fn with_header(mut self, header: HeaderName) -> Self {
+        if !self.headers.contains(&header) {
+            self.headers.push(header);
+        }
+        self
+    }
+
+    /// Also mask the value of the given query parameter.
+    pub fn with_query_parameter<S: Into<String>>(mut self, name: S) -> Self {
+        let name = name.into();
+        if !self.query_parameters.contains(&name) {
+            self.query_parameters.push(name);
+        }
+        self
+    }
+
+    /// Whether the value of the header is masked.
+    pub fn is_redacted(&self, header: &HeaderName) -> bool {
+        self.headers.contains(header)
+    }
+
+    fn default_ref() -> &'static Redaction {
+        static DEFAULT: OnceLock<Redaction> = OnceLock::new();
+        DEFAULT.get_or_init(Redaction::default)
+    }
+}
+
+impl Default for Redaction {
+    fn default() -> Self {
+        [
+            "authorization",
+            "proxy-authorization",
+            "cookie",
+            "set-cookie",
+            "x-api-key",
+            "api-key",
+            "x-auth-token",
+        ]
+        .into_iter()
+        .fold(Redaction::new(), |redaction, header| {
+            redaction.with_header(HeaderName::from_static(header))
+        })
+        .with_query_parameter("api_key")
+        .with_query_parameter("access_token")
+    }
+}
+
+/// Headers, formatted with the values of sensitive headers masked.
+///
+/// Both `Debug` and `Display` print the headers as a map, in the order they
+/// are stored.
+#[derive(Clone, Copy)]
+pub struct RedactedHeaders<'a> {
+    headers: &'a HeaderMap,
+    redaction: &'a Redaction,
+}
+
+impl<'a> RedactedHeaders<'a> {
+    /// Format the headers with the default redaction.
+    pub fn new(headers: &'a HeaderMap) -> Self {
+        RedactedHeaders {
+            headers,
+            redaction: Redaction::default_ref(),
+        }
+    }
+
+    /// Format the headers with the given redaction.
+    pub fn with_redaction(mut self, redaction: &'a Redaction) -> Self {
+        self.redaction = redaction;
+        self
+    }
+}
+
+/// Masked header value.
+struct Masked;
+
+impl fmt::Debug for Masked {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(REDACTED)
+    }
+}
+
+impl fmt::Debug for RedactedHeaders<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut map = f.debug_map();
+        for (name, value) in self.headers {
+            if self.redaction.is_redacted(name) {
+                map.entry(name, &Masked);
+            } else {
+                map.entry(name, value as &HeaderValue);
+            }
+        }
+        map.finish()
+    }
+}
+
+impl fmt::Display for RedactedHeaders<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(self, f)
+    }
+}
+
+/// URI, formatted with the values of sensitive query parameters masked.
+struct RedactedUri<'a> {
+    uri: &'a Uri,
+    redaction: &'a Redaction,
+}
+
+impl fmt::Display for RedactedUri<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let Some(query) = self.uri.query() else {
+            return write!(f, "{}", self.uri);
+        };
+        if let Some(scheme) = self.uri.scheme() {
+            write!(f, "{}://", scheme)?;
+        }
+        if let Some(authority) = self.uri.authority() {
+            write!(f, "{}", authority)?;
+        }
+        write!(f, "{}?", self.uri.path())?;
+        for (i, pair) in query.split('&').enumerate() {
+            if i > 0 {
+                f.write_str("&")?;
+            }
+            match pair.split_once('=') {
+                Some((name, _)) if self.redaction.query_parameters.iter().any(|p| p == name) => {
+                    write!(f, "{}={}", name, REDACTED)?
+                }
+                _ => f.write_str(pair)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Request line and headers of a request, formatted for logging with
+/// sensitive values masked. The body is not printed.
+pub struct DisplayRequest<'a, B> {
+    request: &'a Request<B>,
+    redaction: &'a Redaction,
+}
+
+impl<'a, B> DisplayRequest<'a, B> {
+    /// Format the request with the default redaction.
+    pub fn new(request: &'a Request<B>) -> Self {
+        DisplayRequest {
+            request,
+            redaction: Redaction::default_ref(),
+        }
+    }
+
+    /// Format the request with the given redaction.
+    pub fn with_redaction(mut self, redaction: &'a Redaction) -> Self {
+        self.redaction = redaction;
+        self
+    }
+}
+
+impl<B> fmt::Display for DisplayRequest<'_, B> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} {} {}",
+            self.request.method(),
+            RedactedUri {
+                uri: self.request.uri(),
+                redaction: self.redaction,
+            },
+            RedactedHeaders::new(self.request.headers()).with_redaction(self.redaction)
+        )
+    }
+}
+
+impl<B> fmt::Debug for DisplayRequest<'_, B> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(self, f)
+    }
+}
+
+/// Status and headers of a response, formatted for logging with sensitive
+/// values masked. The body is not printed.
+pub struct DisplayResponse<'a, B> {
+    response: &'a Response<B>,
+    redaction: &'a Redaction,
+}
+
+impl<'a, B> DisplayResponse<'a, B> {
+    /// Format the response with the default redaction.
+    pub fn new(response: &'a Response<B>) -> Self {
+        DisplayResponse {
+            response,
+            redaction: Redaction::default_ref(),
+        }
+    }
+
+    /// Format the response with the given redaction.
+    pub fn with_redaction(mut self, redaction: &'a Redaction) -> Self {
+        self.redaction = redaction;
+        self
+    }
+}
+
+impl<B> fmt::Display for DisplayResponse<'_, B> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} {}",
+            self.response.status(),
+            RedactedHeaders::new(self.response.headers()).with_redaction(self.redaction)
+        )
+    }
+}
+
+impl<B> fmt::Debug for DisplayResponse<'_, B> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Display::fmt(self, f)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_redaction() {
+        let redaction = Redaction::new()
+            .with_header(HeaderName::from_static("x-pet-key"))
+            .with_query_parameter("key");
+        let request = Request::get("http://example.com/pets?key=secret&api_key=1")
+            .header("X-Pet-Key", "secret")
+            .header("Cookie", "session=1")
+            .body(())
+            .unwrap();
+        assert_eq!(
+            DisplayRequest::new(&request)
+                .with_redaction(&redaction)
+                .to_string(),
+            r#"GET http://example.com/pets?key=[REDACTED]&api_key=1 {"x-pet-key": [REDACTED], "cookie": "session=1"}"#
+        );
+
+        let response = Response::builder()
+            .status(200)
+            .header("Set-Cookie", "session=2")
+            .body(())
+            .unwrap();
+        assert_eq!(
+            format!("{:?}", DisplayResponse::new(&response)),
+            r#"200 OK {"set-cookie": [REDACTED]}"#
+        );
+    }
+}
@@ -0,0 +1,225 @@
+//! Hyper service that stores metadata about each request in its context.
+use crate::Push;
+use futures::future::{BoxFuture, FutureExt};
+use hyper::service::Service;
+use hyper::{Method, Request, Uri};
+use std::marker::PhantomData;
+use std::time::{Duration, Instant, SystemTime};
+
+/// Base path of the `CompositeService` entry a request was routed to, stored
+/// in the request's extensions.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct MatchedBasePath(pub &'static str);
+
+/// Values captured from the request path by the pattern of the
+/// `CompositeService` entry a request was routed to, stored in the request's
+/// extensions.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct RouteCaptures(Vec<(Option<String>, String)>);
+
+impl RouteCaptures {
+    /// Value of the capture at the index, counting from zero in the order
+    /// the captures appear in the pattern.
+    pub fn get(&self, index: usize) -> Option<&str> {
+        self.0.get(index).map(|(_, value)| value.as_str())
+    }
+
+    /// Value of the capture with the name.
+    pub fn name(&self, name: &str) -> Option<&str> {
+        self.0
+            .iter()
+            .find(|(n, _)| n.as_deref() == Some(name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// Iterate over the captures, with their names if they have them.
+    pub fn iter(&self) -> impl Iterator<Item = (Option<&str>, &str)> {
+        self.0
+            .iter()
+            .map(|(name, value)| (name.as_deref(), value.as_str()))
+    }
+
+    /// Number of captures.
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    /// Whether nothing was captured.
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
+impl FromIterator<(Option<String>, String)> for RouteCaptures {
+    fn from_iter<I: IntoIterator<Item = (Option<String>, String)>>(iter: I) -> Self {
+        RouteCaptures(iter.into_iter().collect())
+    }
+}
+
+/// Metadata about a request, which remains available from its context after
+/// the request itself has been consumed.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RequestInfo {
+    /// Method of the request.
+    pub method: Method,
+    /// URI of the request, as received.
+    pub uri: Uri,
+    /// When the request was received, for measuring latency.
+    pub received: Instant,
+    /// When the request was received, for logging.
+    pub received_at: SystemTime,
+    /// Base path of the `CompositeService` entry the request was routed to,
+    /// if it was routed by one.
+    pub base_path: Option<&'static str>,
+}
+
+impl RequestInfo {
+    /// Metadata of a request received now.
+    pub fn from_request<B>(request: &Request<B>) -> Self {
+        RequestInfo {
+            method: request.method().clone(),
+            uri: request.uri().clone(),
+            received: Instant::now(),
+            received_at: SystemTime::now(),
+            base_path: request
+                .extensions()
+                .get::<MatchedBasePath>()
+                .map(|base_path| base_path.0),
+        }
+    }
+
+    /// Time since the request was received.
+    pub fn elapsed(&self) -> Duration {
+        self.received.elapsed()
+    }
+}
+
+impl Default for RequestInfo {
+    fn default() -> Self {
+        RequestInfo {
+            method: Method::default(),
+            uri: Uri::default(),
+            received: Instant::now(),
+            received_at: SystemTime::now(),
+            base_path: None,
+        }
+    }
+}
+
+/// Middleware which stores metadata about each request in its context, as a
+/// `RequestInfo`.
+#[derive(Debug)]
+pub struct MakeRequestInfoService<T, RC> {
+    inner: T,
+    marker: PhantomData<RC>,
+}
+
+impl<T, RC> MakeRequestInfoService<T, RC> {
+    /// Create a middleware that stores request metadata in the context.
+    pub fn new(inner: T) -> Self {
+        MakeRequestInfoService {
+            inner,
+            marker: PhantomData,
+        }
+    }
+}
+
+impl<Inner, RC, Target> Service<Target> for MakeRequestInfoService<Inner, RC>
+where
+    Inner: Service<Target>,
+    Inner::Future: Send + 'static,
+    RC: Send + 'static,
+{
+    type Error = Inner::Error;
+    type Response = RequestInfoService<Inner::Response, RC>;
+    type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn call(&self, target: Target) -> Self::Future {
+        Box::pin(
+            self.inner
+                .call(target)
+                .map(|s| Ok(RequestInfoService::new(s?))),
+        )
+    }
+}
+
+/// Middleware which stores metadata about each request in its context, as a
+/// `RequestInfo`.
+///
+/// This should be placed immediately after an `AddContextMakeService`, which
+/// creates the context, so that the receive time is accurate, and before any
+/// middleware which rewrites the request.
+#[derive(Debug)]
+pub struct RequestInfoService<T, RC> {
+    inner: T,
+    marker: PhantomData<RC>,
+}
+
+impl<T, RC> RequestInfoService<T, RC> {
+    /// Create a middleware that stores request metadata in the context.
+    pub fn new(inner: T) -> Self {
+        RequestInfoService {
+            inner,
+            marker: PhantomData,
+        }
+    }
+}
+
+impl<T: Clone, RC> Clone for RequestInfoService<T, RC> {
+    fn clone(&self) -> Self {
+        RequestInfoService {
+            inner: self.inner.clone(),
+            marker: PhantomData,
+        }
+    }
+}
+
+impl<T, B, RC> Service<(Request<B>, RC)> for RequestInfoService<T, RC>
+where
+    RC: Push<RequestInfo>,
+    T: Service<(Request<B>, RC::Result)>,
+{
+    type Response = T::Response;
+    type Error = T::Error;
+    type Future = T::Future;
+
+    fn call(&self, req: (Request<B>, RC)) -> Self::Future {
+        let (request, context) = req;
+        let info = RequestInfo::from_request(&request);
+        self.inner.call((request, context.push(info)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::context::Has;
+
+    crate::new_context_type!(TestContext, TestEmptyContext, RequestInfo);
+
+    type Context = TestContext<RequestInfo, TestEmptyContext>;
+
+    struct InfoService;
+
+    impl Service<(Request<()>, Context)> for InfoService {
+        type Response = RequestInfo;
+        type Error = ();
+        type Future = futures::future::Ready<Result<Self::Response, Self::Error>>;
+
+        fn call(&self, req: (Request<()>, Context)) -> Self::Future {
+            futures::future::ok(Has::<RequestInfo>::get(&req.1).clone())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_request_info() {
+        let service = RequestInfoService::new(InfoService);
+        let mut request = Request::post("/pets/1?verbose=true").body(()).unwrap();
+        request.extensions_mut().insert(MatchedBasePath("/pets"));
+
+        let info = service.call((request, TestEmptyContext)).await.unwrap();
+        assert_eq!(info.method, Method::POST);
+        assert_eq!(info.uri, "/pets/1?verbose=true");
+        assert_eq!(info.base_path, Some("/pets"));
+    }
+}
@@ -0,0 +1,411 @@
+//! Priority-aware request scheduling.
+//!
+//! The `SchedulerService` middleware classifies requests by `Priority`, and
+//! limits the number of concurrent requests in each class. Requests beyond a
+//! class' budget are queued, optionally only until a deadline, so bulk
+//! requests can't starve health checks or interactive requests of capacity.
+use crate::load_shed::Priority;
+use futures::channel::oneshot;
+use futures::future::{self, BoxFuture, Either, FutureExt};
+use hyper::header::{HeaderValue, RETRY_AFTER};
+use hyper::rt::Timer;
+use hyper::service::Service;
+use hyper::{Request, Response, StatusCode};
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+use std::marker::PhantomData;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// Concurrency budget for a single priority class.
+#[derive(Debug)]
+struct Budget {
+    limit: usize,
+    state: Mutex<BudgetState>,
+}
+
+#[derive(Debug, Default)]
+struct BudgetState {
+    in_use: usize,
+    waiters: VecDeque<oneshot::Sender<Permit>>,
+}
+
+/// Permission to process a request, returned to the budget on drop.
+#[derive(Debug)]
+struct Permit {
+    budget: Option<Arc<Budget>>,
+}
+
+impl Drop for Permit {
+    fn drop(&mut self) {
+        if let Some(budget) = self.budget.take() {
+            budget.release();
+        }
+    }
+}
+
+impl Budget {
+    fn new(limit: usize) -> Self {
+        Budget {
+            limit,
+            state: Mutex::new(BudgetState::default()),
+        }
+    }
+
+    /// Take a permit, or join the queue for one if the budget is exhausted.
+    fn acquire(self: &Arc<Self>) -> Result<Permit, oneshot::Receiver<Permit>> {
+        let mut state = self.state.lock().unwrap();
+        if state.in_use < self.limit {
+            state.in_use += 1;
+            Ok(Permit {
+                budget: Some(self.clone()),
+            })
+        } else {
+            let (tx, rx) = oneshot::channel();
+            state.waiters.push_back(tx);
+            Err(rx)
+        }
+    }
+
+    /// Pass a returned permit to the first waiter still queued, if any.
+    fn release(self: &Arc<Self>) {
+        loop {
+            // The lock must not be held while sending, as a permit which
+            // can't be delivered is dropped, and so released, by the sender.
+            let waiter = {
+                let mut state = self.state.lock().unwrap();
+                match state.waiters.pop_front() {
+                    Some(waiter) => waiter,
+                    None => {
+                        state.in_use -= 1;
+                        return;
+                    }
+                }
+            };
+
+            let permit = Permit {
+                budget: Some(self.clone()),
+            };
+            match waiter.send(permit) {
+                Ok(()) => return,
+                Err(mut permit) => permit.budget = None,
+            }
+        }
+    }
+}
+
+/// Concurrency budgets and queueing deadlines for each priority class.
+///
+/// Classes without a configured budget are never limited.
+#[derive(Clone, Default)]
+pub struct Schedule {
+    budgets: HashMap<Priority, Arc<Budget>>,
+    deadlines: HashMap<Priority, Duration>,
+    timer: Option<Arc<dyn Timer + Send + Sync>>,
+}
+
+impl fmt::Debug for Schedule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Schedule")
+            .field("budgets", &self.budgets)
+            .field("deadlines", &self.deadlines)
+            .finish()
+    }
+}
+
+impl Schedule {
+    /// Create a schedule which doesn't limit any class.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Limit the number of concurrent requests of the given priority.
+    pub fn limit(mut self, priority: Priority, concurrency: usize) -> Self {
+        self.budgets
+            .insert(priority, Arc::new(Budget::new(concurrency)));
+        self
+    }
+
+    /// Reject requests of the given priority with `503 Service Unavailable`
+    /// if they've been queued for longer than the deadline.
+    ///
+    /// This has no effect unless the class is limited and a timer is provided.
+    pub fn deadline<D: Into<Duration>>(mut self, priority: Priority, deadline: D) -> Self {
+        self.deadlines.insert(priority, deadline.into());
+        self
+    }
+
+    /// Use the given timer to enforce queueing deadlines.
+    pub fn with_timer<M: Timer + Send + Sync + 'static>(mut self, timer: M) -> Self {
+        self.timer = Some(Arc::new(timer));
+        self
+    }
+
+    /// Wait for a permit to process a request of the given priority. Returns
+    /// `None` if the deadline expired first.
+    fn admit(&self, priority: Priority) -> BoxFuture<'static, Option<Option<Permit>>> {
+        let budget = match self.budgets.get(&priority) {
+            Some(budget) => budget,
+            None => return Box::pin(future::ready(Some(None))),
+        };
+
+        let waiter = match budget.acquire() {
+            Ok(permit) => return Box::pin(future::ready(Some(Some(permit)))),
+            Err(waiter) => waiter,
+        };
+
+        match (self.deadlines.get(&priority), &self.timer) {
+            (Some(deadline), Some(timer)) => {
+                let sleep = timer.sleep(*deadline);
+                Box::pin(future::select(waiter, sleep).map(|result| match result {
+                    Either::Left((permit, _)) => Some(permit.ok()),
+                    Either::Right(_) => None,
+                }))
+            }
+            _ => Box::pin(waiter.map(|permit| Some(permit.ok()))),
+        }
+    }
+}
+
+/// Middleware which schedules requests through per-priority concurrency budgets.
+#[derive(Debug)]
+pub struct MakeSchedulerService<T, F, RC> {
+    inner: T,
+    classifier: F,
+    schedule: Arc<Schedule>,
+    marker: PhantomData<RC>,
+}
+
+impl<T, F, RC> MakeSchedulerService<T, F, RC> {
+    /// Create a middleware which classifies requests by priority using the
+    /// given function, and schedules them according to the schedule.
+    pub fn new(inner: T, classifier: F, schedule: Schedule) -> Self {
+        MakeSchedulerService {
+            inner,
+            classifier,
+            schedule: Arc::new(schedule),
+            marker: PhantomData,
+        }
+    }
+}
+
+impl<Inner, F, RC, Target> Service<Target> for MakeSchedulerService<Inner, F, RC>
+where
+    Inner: Service<Target>,
+    Inner::Future: Send + 'static,
+    F: Clone + Send + 'static,
+    RC: Send + 'static,
+{
+    type Error = Inner::Error;
+    type Response = SchedulerService<Inner::Response, F, RC>;
+    type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn call(&self, target: Target) -> Self::Future {
+        let classifier = self.classifier.clone();
+        let schedule = self.schedule.clone();
+        Box::pin(self.inner.call(target).map(|s| {
+            Ok(SchedulerService {
+                inner: s?,
+                classifier,
+                schedule,
+                marker: PhantomData,
+            })
+        }))
+    }
+}
+
+/// Middleware which schedules requests through per-priority concurrency budgets.
+///
+/// The classifier is passed each request and its context, so requests can be
+/// prioritized by route, caller identity or header. A request holds its
+/// class' budget until the inner service returns its response.
+///
+/// ```ignore
+/// let schedule = Schedule::new()
+///     .limit(Priority::Low, 4)
+///     .deadline(Priority::Low, Duration::from_secs(1))
+///     .limit(Priority::Normal, 64)
+///     .with_timer(TokioTimer::new());
+/// let service = SchedulerService::new(
+///     inner,
+///     |request: &Request<Incoming>, _context: &C| {
+///         if request.uri().path().starts_with("/batch") {
+///             Priority::Low
+///         } else {
+///             Priority::Normal
+///         }
+///     },
+///     schedule,
+/// );
+/// ```
+#[derive(Debug)]
+pub struct SchedulerService<T, F, RC> {
+    inner: T,
+    classifier: F,
+    schedule: Arc<Schedule>,
+    marker: PhantomData<RC>,
+}
+
+impl<T, F, RC> SchedulerService<T, F, RC> {
+    /// Create a middleware which classifies requests by priority using the
+    /// given function, and schedules them according to the schedule.
+    pub fn new(inner: T, classifier: F, schedule: Schedule) -> Self {
+        SchedulerService {
+            inner,
+            classifier,
+            schedule: Arc::new(schedule),
+            marker: PhantomData,
+        }
+    }
+}
+
+impl<T, F, RC> Clone for SchedulerService<T, F, RC>
+where
+    T: Clone,
+    F: Clone,
+{
+    fn clone(&self) -> Self {
+        Self {
+            inner: self.inner.clone(),
+            classifier: self.classifier.clone(),
+            schedule: self.schedule.clone(),
+            marker: PhantomData,
+        }
+    }
+}
+
+impl<T, F, B, ResBody, RC> Service<(Request<B>, RC)> for SchedulerService<T, F, RC>
+where
+    T: Service<(Request<B>, RC), Response = Response<ResBody>> + Clone + Send + 'static,
+    T::Future: Send + 'static,
+    T::Error: Send + 'static,
+    F: Fn(&Request<B>, &RC) -> Priority,
+    B: Send + 'static,
+    RC: Send + 'static,
+    ResBody: Default + Send + 'static,
+{
+    type Response = Response<ResBody>;
+    type Error = T::Error;
+    type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn call(&self, req: (Request<B>, RC)) -> Self::Future {
+        let (request, context) = req;
+        let admission = self.schedule.admit((self.classifier)(&request, &context));
+        let inner = self.inner.clone();
+
+        Box::pin(async move {
+            let _permit = match admission.await {
+                Some(permit) => permit,
+                None => {
+                    let mut response = Response::new(ResBody::default());
+                    *response.status_mut() = StatusCode::SERVICE_UNAVAILABLE;
+                    response
+                        .headers_mut()
+                        .insert(RETRY_AFTER, HeaderValue::from_static("1"));
+                    return Ok(response);
+                }
+            };
+
+            inner.call((request, context)).await
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::EmptyContext;
+    use hyper_util::rt::TokioTimer;
+
+    /// Service which waits for the request body to resolve before responding.
+    #[derive(Clone)]
+    struct WaitService;
+
+    impl Service<(Request<oneshot::Receiver<()>>, EmptyContext)> for WaitService {
+        type Response = Response<String>;
+        type Error = ();
+        type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+        fn call(&self, req: (Request<oneshot::Receiver<()>>, EmptyContext)) -> Self::Future {
+            Box::pin(req.0.into_body().map(|_| Ok(Response::new(String::new()))))
+        }
+    }
+
+    fn classify(request: &Request<oneshot::Receiver<()>>, _: &EmptyContext) -> Priority {
+        if request.uri().path().starts_with("/batch") {
+            Priority::Low
+        } else {
+            Priority::Normal
+        }
+    }
+
+    fn request(
+        path: &str,
+    ) -> (
+        oneshot::Sender<()>,
+        (Request<oneshot::Receiver<()>>, EmptyContext),
+    ) {
+        let (tx, rx) = oneshot::channel();
+        (tx, (Request::get(path).body(rx).unwrap(), EmptyContext))
+    }
+
+    #[tokio::test]
+    async fn test_budget_queues_requests() {
+        let service = SchedulerService::new(
+            WaitService,
+            classify,
+            Schedule::new().limit(Priority::Low, 1),
+        );
+
+        let (first_tx, first) = request("/batch/1");
+        let mut first = service.call(first);
+        let (second_tx, second) = request("/batch/2");
+        let mut second = service.call(second);
+        let (normal_tx, normal) = request("/interactive");
+        let normal = service.call(normal);
+
+        assert!(futures::poll!(&mut first).is_pending());
+        assert!(futures::poll!(&mut second).is_pending());
+
+        // Other classes aren't affected by the exhausted budget.
+        normal_tx.send(()).unwrap();
+        assert_eq!(normal.await.unwrap().status(), StatusCode::OK);
+
+        // The queued request is admitted once the first completes.
+        second_tx.send(()).unwrap();
+        assert!(futures::poll!(&mut second).is_pending());
+        first_tx.send(()).unwrap();
+        assert_eq!(first.await.unwrap().status(), StatusCode::OK);
+        assert_eq!(second.await.unwrap().status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn test_queueing_deadline() {
+        let schedule = Schedule::new().limit(Priority::Low, 1);
+        // Deadlines set once the schedule has been cloned still apply to it.
+        let _clone = schedule.clone();
+        let service = SchedulerService::new(
+            WaitService,
+            classify,
+            schedule
+                .deadline(Priority::Low, Duration::from_millis(10))
+                .with_timer(TokioTimer::new()),
+        );
+
+        let (first_tx, first) = request("/batch/1");
+        let mut first = service.call(first);
+        assert!(futures::poll!(&mut first).is_pending());
+
+        let (_second_tx, second) = request("/batch/2");
+        let response = service.call(second).await.unwrap();
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+
+        first_tx.send(()).unwrap();
+        assert_eq!(first.await.unwrap().status(), StatusCode::OK);
+
+        // The budget is returned, despite the expired waiter.
+        let (third_tx, third) = request("/batch/3");
+        third_tx.send(()).unwrap();
+        assert_eq!(service.call(third).await.unwrap().status(), StatusCode::OK);
+    }
+}
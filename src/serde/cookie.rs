@@ -0,0 +1,287 @@
+//! Cookie parameters, which OpenAPI writes in the `form` style.
+//!
+//! For a parameter named `color`:
+//!
+//! | Value                    | `explode: false`           | `explode: true`              |
+//! |--------------------------|----------------------------|------------------------------|
+//! | `"blue"`                 | `color=blue`               | `color=blue`                 |
+//! | `["blue", "black"]`      | `color=blue,black`         | `color=blue; color=black`    |
+//! | `{"R": 100, "G": 200}`   | `color=R,100,G,200`        | `R=100; G=200`               |
+//!
+//! Exploded lists and objects are written as a cookie for each item or
+//! property, as there's no other way to separate them in a `Cookie` header.
+//!
+//! Names are percent-encoded as in other parameters. Values are
+//! percent-encoded where they contain anything but the `cookie-octet`s of
+//! RFC 6265, so that they're valid cookies, except that the commas between
+//! items are left as OpenAPI writes them. Both are decoded when read.
+use super::form_explode;
+use super::value::{decode, encode, to_value, Shaped, Value};
+use crate::ApiError;
+use hyper::header::{HeaderMap, HeaderValue, COOKIE, SET_COOKIE};
+use percent_encoding::{utf8_percent_encode, AsciiSet, CONTROLS};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+/// Characters which are percent-encoded in cookie values: those which
+/// aren't `cookie-octet`s, and `%` itself.
+const COOKIE_VALUE_ENCODE_SET: &AsciiSet = &CONTROLS
+    .add(b' ')
+    .add(b'"')
+    .add(b'%')
+    .add(b',')
+    .add(b';')
+    .add(b'\\');
+
+/// Percent-encode a cookie value, or an item in one.
+fn encode_value(s: &str) -> String {
+    utf8_percent_encode(s, COOKIE_VALUE_ENCODE_SET).to_string()
+}
+
+/// Serialize a parameter to the name and value of each cookie, encoded.
+pub fn to_string_pairs<T: Serialize + ?Sized>(
+    name: &str,
+    value: &T,
+    explode: bool,
+) -> Result<Vec<(String, String)>, ApiError> {
+    if explode {
+        return Ok(form_explode::to_string_pairs(name, value)?
+            .into_iter()
+            .map(|(key, value)| (encode(&key), encode_value(&value)))
+            .collect());
+    }
+
+    let items = match to_value(value)? {
+        Value::Null => return Ok(Vec::new()),
+        Value::Str(value) => vec![encode_value(&value)],
+        Value::Seq(values) => {
+            let mut items = Vec::new();
+            for value in values {
+                if let Some(value) = value.into_scalar(name)? {
+                    items.push(encode_value(&value));
+                }
+            }
+            items
+        }
+        Value::Map(properties) => {
+            let mut items = Vec::new();
+            for (key, value) in properties {
+                if let Some(value) = value.into_scalar(&key)? {
+                    items.extend([encode_value(&key), encode_value(&value)]);
+                }
+            }
+            items
+        }
+    };
+    Ok(vec![(encode(name), items.join(","))])
+}
+
+/// Deserialize a parameter from the name and value of each cookie, such as
+/// all those in a `Cookie` header, still encoded.
+///
+/// If exploded, parameters are read as by
+/// [`form_explode::from_pairs`](super::form_explode::from_pairs). Otherwise
+/// they're read from the first cookie with the name of the parameter, and
+/// optional parameters are `None` if there's no such cookie.
+pub fn from_pairs<'a, T, I>(name: &str, pairs: I, explode: bool) -> Result<T, ApiError>
+where
+    T: DeserializeOwned,
+    I: IntoIterator<Item = (&'a str, &'a str)>,
+{
+    if explode {
+        let pairs = pairs
+            .into_iter()
+            .map(|(key, value)| Ok((decode(key)?, decode(value)?)))
+            .collect::<Result<Vec<_>, ApiError>>()?;
+        return form_explode::from_pairs(
+            name,
+            pairs
+                .iter()
+                .map(|(key, value)| (key.as_str(), value.as_str())),
+        );
+    }
+
+    let mut value = None;
+    for (key, v) in pairs {
+        if decode(key)? == name {
+            value = Some(v);
+            break;
+        }
+    }
+    let shaped = match value {
+        Some(value) => Shaped::from_list(name, value, &[','], false)?,
+        None => Shaped::missing(name),
+    };
+    T::deserialize(shaped)
+}
+
+/// Build a `Cookie` header from the name and value of each cookie, such as
+/// those from [`to_string_pairs`] for each cookie parameter of a request.
+pub fn to_cookie_header<I, K, V>(pairs: I) -> Result<HeaderValue, ApiError>
+where
+    I: IntoIterator<Item = (K, V)>,
+    K: AsRef<str>,
+    V: AsRef<str>,
+{
+    let cookies: Vec<_> = pairs
+        .into_iter()
+        .map(|(key, value)| format!("{}={}", key.as_ref(), value.as_ref()))
+        .collect();
+    let cookies = cookies.join("; ");
+    HeaderValue::try_from(cookies.as_str())
+        .map_err(|e| ApiError(format!("Invalid Cookie header {}: {}", cookies, e)))
+}
+
+/// Retrieve the name and value of each cookie from all `Cookie` headers in
+/// a request, still encoded.
+///
+/// Values wrapped in double quotes are unwrapped.
+pub fn from_cookie_headers(headers: &HeaderMap) -> Vec<(&str, &str)> {
+    headers
+        .get_all(COOKIE)
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(';'))
+        .filter_map(split_cookie)
+        .collect()
+}
+
+/// Build a `Set-Cookie` header for each cookie from its name and value,
+/// such as those from [`to_string_pairs`], with the attributes added to
+/// each, as in `Path=/; HttpOnly`.
+pub fn to_set_cookie_headers<I, K, V>(
+    pairs: I,
+    attributes: &str,
+) -> Result<Vec<HeaderValue>, ApiError>
+where
+    I: IntoIterator<Item = (K, V)>,
+    K: AsRef<str>,
+    V: AsRef<str>,
+{
+    pairs
+        .into_iter()
+        .map(|(key, value)| {
+            let mut cookie = format!("{}={}", key.as_ref(), value.as_ref());
+            if !attributes.is_empty() {
+                cookie.push_str("; ");
+                cookie.push_str(attributes);
+            }
+            HeaderValue::try_from(cookie.as_str())
+                .map_err(|e| ApiError(format!("Invalid Set-Cookie header {}: {}", cookie, e)))
+        })
+        .collect()
+}
+
+/// Retrieve the name and value of each cookie from all `Set-Cookie` headers
+/// in a response, still encoded, ignoring their attributes.
+pub fn from_set_cookie_headers(headers: &HeaderMap) -> Vec<(&str, &str)> {
+    headers
+        .get_all(SET_COOKIE)
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .filter_map(|v| split_cookie(v.split(';').next()?))
+        .collect()
+}
+
+/// Split a cookie into its name and value, unwrapping any double quotes
+/// around the value.
+fn split_cookie(cookie: &str) -> Option<(&str, &str)> {
+    let (key, value) = cookie.split_once('=')?;
+    let value = value.trim();
+    let value = value
+        .strip_prefix('"')
+        .and_then(|v| v.strip_suffix('"'))
+        .unwrap_or(value);
+    Some((key.trim(), value))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde::Deserialize;
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    #[allow(non_snake_case)]
+    struct Rgb {
+        R: u8,
+        G: u8,
+    }
+
+    const RGB: Rgb = Rgb { R: 100, G: 200 };
+
+    fn header<T: Serialize + ?Sized>(value: &T, explode: bool) -> HeaderValue {
+        to_cookie_header(to_string_pairs("color", value, explode).unwrap()).unwrap()
+    }
+
+    fn read<T: DeserializeOwned>(cookies: &str, explode: bool) -> Result<T, ApiError> {
+        let mut headers = HeaderMap::new();
+        headers.insert(COOKIE, HeaderValue::from_str(cookies).unwrap());
+        from_pairs("color", from_cookie_headers(&headers), explode)
+    }
+
+    #[test]
+    fn test_cookie() {
+        for (explode, list, object) in [
+            (false, "color=blue,black", "color=R,100,G,200"),
+            (true, "color=blue; color=black", "R=100; G=200"),
+        ] {
+            assert_eq!(header("blue", explode), "color=blue");
+            assert_eq!(
+                read::<String>("session=x; color=blue", explode).unwrap(),
+                "blue"
+            );
+
+            assert_eq!(header(&["blue", "black"], explode), list);
+            assert_eq!(
+                read::<Vec<String>>(list, explode).unwrap(),
+                ["blue", "black"]
+            );
+
+            assert_eq!(header(&RGB, explode), object);
+            assert_eq!(read::<Rgb>(object, explode).unwrap(), RGB);
+
+            assert_eq!(read::<Option<u8>>("session=x", explode).unwrap(), None);
+        }
+
+        assert_eq!(
+            header(&["a b", "c,d;\"e\"%"], false),
+            "color=a%20b,c%2Cd%3B%22e%22%25"
+        );
+        assert_eq!(
+            read::<Vec<String>>("color=a%20b,c%2Cd%3B%22e%22%25", false).unwrap(),
+            ["a b", "c,d;\"e\"%"]
+        );
+        assert_eq!(read::<String>("color=\"blue\"", false).unwrap(), "blue");
+        assert_eq!(
+            read::<u8>("session=x", false).unwrap_err().0,
+            "Missing parameter color"
+        );
+    }
+
+    #[test]
+    fn test_set_cookie() {
+        let pairs = to_string_pairs("color", &RGB, true).unwrap();
+        let mut headers = HeaderMap::new();
+        for value in to_set_cookie_headers(pairs, "Path=/; HttpOnly").unwrap() {
+            headers.append(SET_COOKIE, value);
+        }
+        assert_eq!(
+            headers
+                .get_all(SET_COOKIE)
+                .iter()
+                .map(|v| v.to_str().unwrap())
+                .collect::<Vec<_>>(),
+            ["R=100; Path=/; HttpOnly", "G=200; Path=/; HttpOnly"]
+        );
+        assert_eq!(
+            from_pairs::<Rgb, _>("color", from_set_cookie_headers(&headers), true).unwrap(),
+            RGB
+        );
+        assert_eq!(
+            to_set_cookie_headers([("color", "blue")], "Path=/\n")
+                .unwrap_err()
+                .0,
+            "Invalid Set-Cookie header color=blue; Path=/\n: failed to parse header value"
+        );
+    }
+}
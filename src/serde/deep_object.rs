@@ -0,0 +1,214 @@
+//! Parameters in the OpenAPI `deepObject` style.
+//!
+//! Query parameters in this style are objects, written as a pair for each
+//! property, with the property in brackets after the name of the parameter.
+//! For a parameter named `color`, `{"R": 100, "G": 200}` is written as
+//! `color[R]=100` and `color[G]=200`.
+//!
+//! OpenAPI leaves the encoding of nested values undefined, so this follows
+//! the common convention: nested objects add a bracketed property for each
+//! level, as in `filter[owner][name]=Rex`, and lists repeat the pair for
+//! each item.
+//!
+//! The pairs aren't percent-encoded, and are expected to have been decoded.
+use super::value::{to_value, Value};
+use crate::ApiError;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+/// Serialize a parameter, which must be an object, to the name and value of
+/// each query pair.
+pub fn to_string_pairs<T: Serialize + ?Sized>(
+    name: &str,
+    value: &T,
+) -> Result<Vec<(String, String)>, ApiError> {
+    value_to_pairs(name, to_value(value)?)
+}
+
+/// Convert a parameter, which must be an object, to the name and value of
+/// each query pair.
+pub(crate) fn value_to_pairs(name: &str, value: Value) -> Result<Vec<(String, String)>, ApiError> {
+    let mut pairs = Vec::new();
+    match value {
+        Value::Null => {}
+        Value::Map(entries) => push_entries(&mut pairs, name, entries)?,
+        _ => {
+            return Err(ApiError(format!(
+                "Parameter {} must be an object in deepObject style",
+                name
+            )))
+        }
+    }
+    Ok(pairs)
+}
+
+/// Add pairs for the entries of an object, with keys starting with the
+/// prefix.
+fn push_entries(
+    pairs: &mut Vec<(String, String)>,
+    prefix: &str,
+    entries: Vec<(String, Value)>,
+) -> Result<(), ApiError> {
+    for (key, value) in entries {
+        let key = format!("{}[{}]", prefix, key);
+        match value {
+            Value::Null => {}
+            Value::Str(value) => pairs.push((key, value)),
+            Value::Seq(items) => {
+                for item in items {
+                    if let Some(item) = item.into_scalar(&key)? {
+                        pairs.push((key.clone(), item));
+                    }
+                }
+            }
+            Value::Map(entries) => push_entries(pairs, &key, entries)?,
+        }
+    }
+    Ok(())
+}
+
+/// Deserialize a parameter from query pairs, such as all those in a query
+/// string, ignoring those for other parameters.
+///
+/// An optional parameter is `None` if there are no pairs for it.
+pub fn from_pairs<'a, T, I>(name: &str, pairs: I) -> Result<T, ApiError>
+where
+    T: DeserializeOwned,
+    I: IntoIterator<Item = (&'a str, &'a str)>,
+{
+    T::deserialize(pairs_to_value(name, pairs)?)
+}
+
+/// Read a parameter from query pairs, or `Value::Null` if there are none
+/// for it.
+pub(crate) fn pairs_to_value<'a, I>(name: &str, pairs: I) -> Result<Value, ApiError>
+where
+    I: IntoIterator<Item = (&'a str, &'a str)>,
+{
+    let mut object = Value::Null;
+    for (key, value) in pairs {
+        let Some(path) = key.strip_prefix(name) else {
+            continue;
+        };
+        if !path.starts_with('[') {
+            continue;
+        }
+        let path =
+            parse_path(path).ok_or_else(|| ApiError(format!("Invalid deepObject key {}", key)))?;
+        insert(&mut object, &path, value.to_string())
+            .map_err(|_| ApiError(format!("Conflicting values for {}", key)))?;
+    }
+    Ok(object)
+}
+
+/// Split `[a][b]` into `a` and `b`.
+pub(crate) fn parse_path(mut path: &str) -> Option<Vec<&str>> {
+    let mut segments = Vec::new();
+    while !path.is_empty() {
+        let (segment, rest) = path.strip_prefix('[')?.split_once(']')?;
+        segments.push(segment);
+        path = rest;
+    }
+    Some(segments)
+}
+
+/// Insert a value at the path in an object, adding to a list if there's
+/// already a value there, or failing if there's an object there.
+pub(crate) fn insert(object: &mut Value, path: &[&str], value: String) -> Result<(), ()> {
+    let Some((first, rest)) = path.split_first() else {
+        match object {
+            Value::Null => *object = Value::Str(value),
+            Value::Str(existing) => {
+                let existing = Value::Str(std::mem::take(existing));
+                *object = Value::Seq(vec![existing, Value::Str(value)]);
+            }
+            Value::Seq(items) => items.push(Value::Str(value)),
+            Value::Map(_) => return Err(()),
+        }
+        return Ok(());
+    };
+
+    if let Value::Null = object {
+        *object = Value::Map(Vec::new());
+    }
+    let Value::Map(entries) = object else {
+        return Err(());
+    };
+    let index = match entries.iter().position(|(key, _)| key == first) {
+        Some(index) => index,
+        None => {
+            entries.push((first.to_string(), Value::Null));
+            entries.len() - 1
+        }
+    };
+    insert(&mut entries[index].1, rest, value)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde::Deserialize;
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Owner {
+        name: String,
+    }
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Filter {
+        owner: Owner,
+        tags: Vec<String>,
+        limit: Option<u32>,
+    }
+
+    #[test]
+    fn test_deep_object() {
+        let filter = Filter {
+            owner: Owner {
+                name: "Rex".to_string(),
+            },
+            tags: vec!["a".to_string(), "b".to_string()],
+            limit: None,
+        };
+        let pairs = to_string_pairs("filter", &filter).unwrap();
+        assert_eq!(
+            pairs,
+            [
+                ("filter[owner][name]", "Rex"),
+                ("filter[tags]", "a"),
+                ("filter[tags]", "b"),
+            ]
+            .map(|(key, value)| (key.to_string(), value.to_string()))
+        );
+
+        let query = pairs
+            .iter()
+            .map(|(key, value)| (key.as_str(), value.as_str()))
+            .chain([("filters[limit]", "1"), ("other", "x")]);
+        assert_eq!(from_pairs::<Filter, _>("filter", query).unwrap(), filter);
+        assert_eq!(
+            from_pairs::<Option<Filter>, _>("filter", [("other", "x")]).unwrap(),
+            None
+        );
+
+        assert_eq!(
+            to_string_pairs("filter", "Rex").unwrap_err().0,
+            "Parameter filter must be an object in deepObject style"
+        );
+        assert_eq!(
+            from_pairs::<Filter, _>("filter", [("filter[owner", "Rex")])
+                .unwrap_err()
+                .0,
+            "Invalid deepObject key filter[owner"
+        );
+        assert_eq!(
+            from_pairs::<Filter, _>(
+                "filter",
+                [("filter[owner]", "Rex"), ("filter[owner][name]", "Rex")]
+            )
+            .unwrap_err()
+            .0,
+            "Conflicting values for filter[owner][name]"
+        );
+    }
+}
@@ -0,0 +1,181 @@
+//! Parameters in the OpenAPI `form` style, with `explode: true`.
+//!
+//! This is the default style of query and cookie parameters. For a parameter
+//! named `color`:
+//!
+//! | Value                              | Pairs                                      |
+//! |------------------------------------|--------------------------------------------|
+//! | `"blue"`                           | `color=blue`                               |
+//! | `["blue", "black"]`                | `color=blue`, `color=black`                |
+//! | `{"R": 100, "G": 200}`             | `R=100`, `G=200`                           |
+//!
+//! Objects are written as a pair for each property, without the name of the
+//! parameter, and properties which are lists as a pair for each item.
+//! Objects can't contain objects, which the style doesn't define.
+//!
+//! The pairs aren't percent-encoded, and are expected to have been decoded.
+use super::value::{to_value, Shaped, Value};
+use crate::ApiError;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+/// Serialize a parameter to the name and value of each query pair.
+pub fn to_string_pairs<T: Serialize + ?Sized>(
+    name: &str,
+    value: &T,
+) -> Result<Vec<(String, String)>, ApiError> {
+    value_to_pairs(name, to_value(value)?)
+}
+
+/// Convert a parameter to the name and value of each query pair.
+pub(crate) fn value_to_pairs(name: &str, value: Value) -> Result<Vec<(String, String)>, ApiError> {
+    let mut pairs = Vec::new();
+    match value {
+        Value::Null => {}
+        Value::Str(value) => pairs.push((name.to_string(), value)),
+        Value::Seq(items) => push_items(&mut pairs, name, items)?,
+        Value::Map(entries) => {
+            for (key, value) in entries {
+                match value {
+                    Value::Seq(items) => push_items(&mut pairs, &key, items)?,
+                    value => {
+                        if let Some(value) = value.into_scalar(&key)? {
+                            pairs.push((key, value));
+                        }
+                    }
+                }
+            }
+        }
+    }
+    Ok(pairs)
+}
+
+/// Add a pair with the key for each item of a list.
+fn push_items(
+    pairs: &mut Vec<(String, String)>,
+    key: &str,
+    items: Vec<Value>,
+) -> Result<(), ApiError> {
+    for item in items {
+        if let Some(item) = item.into_scalar(key)? {
+            pairs.push((key.to_string(), item));
+        }
+    }
+    Ok(())
+}
+
+/// Deserialize a parameter from query pairs, such as all those in a query
+/// string.
+///
+/// Primitives are read from the first pair with the name of the parameter,
+/// and lists from every pair with it. Objects are read from all the pairs,
+/// so an optional object is always present, with any missing properties
+/// defaulted as usual. Other optional parameters are `None` if there's no
+/// pair with their name.
+pub fn from_pairs<'a, T, I>(name: &str, pairs: I) -> Result<T, ApiError>
+where
+    T: DeserializeOwned,
+    I: IntoIterator<Item = (&'a str, &'a str)>,
+{
+    let pairs: Vec<_> = pairs.into_iter().collect();
+    T::deserialize(pairs_to_shaped(name, &pairs))
+}
+
+/// Read a parameter from query pairs, as whichever of a primitive, a list or
+/// an object is expected.
+pub(crate) fn pairs_to_shaped(name: &str, pairs: &[(&str, &str)]) -> Shaped {
+    let values: Vec<_> = pairs
+        .iter()
+        .filter(|(key, _)| *key == name)
+        .map(|(_, value)| value.to_string())
+        .collect();
+    Shaped {
+        name: name.to_string(),
+        scalar: values.first().cloned(),
+        seq: values,
+        map: Some(
+            pairs
+                .iter()
+                .map(|(key, value)| (key.to_string(), value.to_string()))
+                .collect(),
+        ),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde::Deserialize;
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    #[allow(non_snake_case)]
+    struct Rgb {
+        R: u8,
+        G: u8,
+        B: Option<u8>,
+    }
+
+    fn pairs(pairs: &[(&str, &str)]) -> Vec<(String, String)> {
+        pairs
+            .iter()
+            .map(|(key, value)| (key.to_string(), value.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn test_to_string_pairs() {
+        assert_eq!(
+            to_string_pairs("color", "blue").unwrap(),
+            pairs(&[("color", "blue")])
+        );
+        assert_eq!(
+            to_string_pairs("color", &["blue", "black"]).unwrap(),
+            pairs(&[("color", "blue"), ("color", "black")])
+        );
+        let rgb = Rgb {
+            R: 100,
+            G: 200,
+            B: None,
+        };
+        assert_eq!(
+            to_string_pairs("color", &rgb).unwrap(),
+            pairs(&[("R", "100"), ("G", "200")])
+        );
+        assert!(to_string_pairs("color", &None::<u8>).unwrap().is_empty());
+        assert_eq!(
+            to_string_pairs("color", &[["blue"]]).unwrap_err().0,
+            "Parameter color can't contain nested lists or objects"
+        );
+    }
+
+    #[test]
+    fn test_from_pairs() {
+        let query = [
+            ("color", "blue"),
+            ("R", "100"),
+            ("color", "black"),
+            ("G", "200"),
+        ];
+        assert_eq!(from_pairs::<String, _>("color", query).unwrap(), "blue");
+        assert_eq!(
+            from_pairs::<Vec<String>, _>("color", query).unwrap(),
+            ["blue", "black"]
+        );
+        assert_eq!(
+            from_pairs::<Rgb, _>("color", query).unwrap(),
+            Rgb {
+                R: 100,
+                G: 200,
+                B: None
+            }
+        );
+        assert_eq!(from_pairs::<Option<u32>, _>("limit", query).unwrap(), None);
+        assert!(from_pairs::<Vec<u32>, _>("limit", query)
+            .unwrap()
+            .is_empty());
+        assert_eq!(
+            from_pairs::<u32, _>("limit", query).unwrap_err().0,
+            "Missing parameter limit"
+        );
+    }
+}
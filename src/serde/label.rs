@@ -0,0 +1,121 @@
+//! Path parameters in the OpenAPI `label` style.
+//!
+//! | Value                    | `explode: false`           | `explode: true`              |
+//! |--------------------------|----------------------------|------------------------------|
+//! | `"blue"`                 | `.blue`                    | `.blue`                      |
+//! | `["blue", "black"]`      | `.blue.black`              | `.blue.black`                |
+//! | `{"R": 100, "G": 200}`   | `.R.100.G.200`             | `.R=100.G=200`               |
+//!
+//! Values are percent-encoded, including any `.` in them, and decoded when
+//! read. Lists written with `,` between items, as RFC 6570 describes, are
+//! also read.
+use super::value::{encode, to_value, Shaped, Value};
+use crate::ApiError;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+/// Percent-encode a name or value, including any `.` in it.
+fn encode_label(s: &str) -> String {
+    encode(s).replace('.', "%2E")
+}
+
+/// Serialize a parameter to a path segment in the `label` style.
+pub fn to_string<T: Serialize + ?Sized>(value: &T, explode: bool) -> Result<String, ApiError> {
+    let items = match to_value(value)? {
+        Value::Null => Vec::new(),
+        Value::Str(value) => vec![encode_label(&value)],
+        Value::Seq(values) => {
+            let mut items = Vec::new();
+            for value in values {
+                if let Some(value) = value.into_scalar("in label style")? {
+                    items.push(encode_label(&value));
+                }
+            }
+            items
+        }
+        Value::Map(properties) => {
+            let mut items = Vec::new();
+            for (key, value) in properties {
+                if let Some(value) = value.into_scalar(&key)? {
+                    let (key, value) = (encode_label(&key), encode_label(&value));
+                    if explode {
+                        items.push(format!("{}={}", key, value));
+                    } else {
+                        items.extend([key, value]);
+                    }
+                }
+            }
+            items
+        }
+    };
+    Ok(format!(".{}", items.join(".")))
+}
+
+/// Deserialize a parameter from a path segment in the `label` style.
+///
+/// The name of the parameter is only used in errors.
+pub fn from_str<T: DeserializeOwned>(name: &str, s: &str, explode: bool) -> Result<T, ApiError> {
+    let value = s
+        .strip_prefix('.')
+        .ok_or_else(|| ApiError(format!("Invalid label parameter {}: {}", name, s)))?;
+    let separators: &[char] = if explode { &['.'] } else { &['.', ','] };
+    T::deserialize(Shaped::from_list(name, value, separators, explode)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde::Deserialize;
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    #[allow(non_snake_case)]
+    struct Rgb {
+        R: u8,
+        G: u8,
+    }
+
+    const RGB: Rgb = Rgb { R: 100, G: 200 };
+
+    #[test]
+    fn test_label() {
+        for (explode, object) in [(false, ".R.100.G.200"), (true, ".R=100.G=200")] {
+            assert_eq!(to_string("blue", explode).unwrap(), ".blue");
+            assert_eq!(
+                from_str::<String>("color", ".blue", explode).unwrap(),
+                "blue"
+            );
+
+            assert_eq!(
+                to_string(&["blue", "black"], explode).unwrap(),
+                ".blue.black"
+            );
+            assert_eq!(
+                from_str::<Vec<String>>("color", ".blue.black", explode).unwrap(),
+                ["blue", "black"]
+            );
+
+            assert_eq!(to_string(&RGB, explode).unwrap(), object);
+            assert_eq!(from_str::<Rgb>("color", object, explode).unwrap(), RGB);
+
+            let empty: [&str; 0] = [];
+            assert_eq!(to_string(&empty, explode).unwrap(), ".");
+            assert!(from_str::<Vec<String>>("color", ".", explode)
+                .unwrap()
+                .is_empty());
+        }
+
+        assert_eq!(to_string(&[1.5, 2.0], false).unwrap(), ".1%2E5.2");
+        assert_eq!(
+            from_str::<Vec<f64>>("size", ".1%2E5.2", false).unwrap(),
+            [1.5, 2.0]
+        );
+        assert_eq!(
+            from_str::<Vec<String>>("color", ".blue,black", false).unwrap(),
+            ["blue", "black"]
+        );
+        assert_eq!(
+            from_str::<String>("color", "blue", false).unwrap_err().0,
+            "Invalid label parameter color: blue"
+        );
+    }
+}
@@ -0,0 +1,196 @@
+//! Path parameters in the OpenAPI `matrix` style.
+//!
+//! For a parameter named `color`:
+//!
+//! | Value                    | `explode: false`           | `explode: true`              |
+//! |--------------------------|----------------------------|------------------------------|
+//! | `"blue"`                 | `;color=blue`              | `;color=blue`                |
+//! | `["blue", "black"]`      | `;color=blue,black`        | `;color=blue;color=black`    |
+//! | `{"R": 100, "G": 200}`   | `;color=R,100,G,200`       | `;R=100;G=200`               |
+//!
+//! Names and values are percent-encoded, and decoded when read.
+use super::value::{decode, encode, to_value, Shaped, Value};
+use crate::ApiError;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+/// Serialize a parameter to a path segment in the `matrix` style.
+pub fn to_string<T: Serialize + ?Sized>(
+    name: &str,
+    value: &T,
+    explode: bool,
+) -> Result<String, ApiError> {
+    let name = encode(name);
+    let entries = match to_value(value)? {
+        Value::Null => Vec::new(),
+        Value::Str(value) => vec![(name.clone(), encode(&value))],
+        Value::Seq(values) => {
+            let mut items = Vec::new();
+            for value in values {
+                if let Some(value) = value.into_scalar(&name)? {
+                    items.push(encode(&value));
+                }
+            }
+            if explode {
+                items.into_iter().map(|item| (name.clone(), item)).collect()
+            } else {
+                vec![(name.clone(), items.join(","))]
+            }
+        }
+        Value::Map(properties) => {
+            let mut entries = Vec::new();
+            for (key, value) in properties {
+                if let Some(value) = value.into_scalar(&key)? {
+                    entries.push((encode(&key), encode(&value)));
+                }
+            }
+            if explode {
+                entries
+            } else {
+                let items: Vec<_> = entries
+                    .into_iter()
+                    .flat_map(|(key, value)| [key, value])
+                    .collect();
+                vec![(name.clone(), items.join(","))]
+            }
+        }
+    };
+
+    if entries.is_empty() {
+        return Ok(format!(";{}", name));
+    }
+    Ok(entries
+        .into_iter()
+        .map(|(key, value)| {
+            if value.is_empty() {
+                format!(";{}", key)
+            } else {
+                format!(";{}={}", key, value)
+            }
+        })
+        .collect())
+}
+
+/// Deserialize a parameter from a path segment in the `matrix` style.
+pub fn from_str<T: DeserializeOwned>(name: &str, s: &str, explode: bool) -> Result<T, ApiError> {
+    let invalid = || ApiError(format!("Invalid matrix parameter {}: {}", name, s));
+    let mut pairs = Vec::new();
+    for pair in s.strip_prefix(';').ok_or_else(invalid)?.split(';') {
+        match pair.split_once('=') {
+            Some((key, value)) => pairs.push((decode(key)?, Some(decode(value)?))),
+            None => pairs.push((decode(pair)?, None)),
+        }
+    }
+
+    let shaped = if explode {
+        let values: Vec<_> = pairs
+            .iter()
+            .filter(|(key, _)| key == name)
+            .filter_map(|(_, value)| value.clone())
+            .collect();
+        let present = pairs.iter().any(|(key, _)| key == name);
+        Shaped {
+            name: name.to_string(),
+            scalar: values
+                .first()
+                .cloned()
+                .or_else(|| present.then(String::new)),
+            seq: values,
+            map: Some(
+                pairs
+                    .into_iter()
+                    .filter_map(|(key, value)| Some((key, value?)))
+                    .collect(),
+            ),
+        }
+    } else {
+        let [(key, _)] = &pairs[..] else {
+            return Err(invalid());
+        };
+        if key != name {
+            return Err(invalid());
+        }
+        // Commas separating items are the only ones not percent-encoded.
+        let value = s.split_once('=').map_or("", |(_, value)| value);
+        Shaped::from_list(name, value, &[','], false)?
+    };
+    T::deserialize(shaped)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde::Deserialize;
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    #[allow(non_snake_case)]
+    struct Rgb {
+        R: u8,
+        G: u8,
+    }
+
+    const RGB: Rgb = Rgb { R: 100, G: 200 };
+
+    #[test]
+    fn test_matrix() {
+        for (explode, scalar, list, object) in [
+            (
+                false,
+                ";color=blue",
+                ";color=blue,black",
+                ";color=R,100,G,200",
+            ),
+            (
+                true,
+                ";color=blue",
+                ";color=blue;color=black",
+                ";R=100;G=200",
+            ),
+        ] {
+            assert_eq!(to_string("color", "blue", explode).unwrap(), scalar);
+            assert_eq!(
+                from_str::<String>("color", scalar, explode).unwrap(),
+                "blue"
+            );
+
+            assert_eq!(
+                to_string("color", &["blue", "black"], explode).unwrap(),
+                list
+            );
+            assert_eq!(
+                from_str::<Vec<String>>("color", list, explode).unwrap(),
+                ["blue", "black"]
+            );
+
+            assert_eq!(to_string("color", &RGB, explode).unwrap(), object);
+            assert_eq!(from_str::<Rgb>("color", object, explode).unwrap(), RGB);
+
+            let empty: [&str; 0] = [];
+            assert_eq!(to_string("color", &empty, explode).unwrap(), ";color");
+            assert!(from_str::<Vec<String>>("color", ";color", explode)
+                .unwrap()
+                .is_empty());
+        }
+
+        assert_eq!(
+            to_string("color", "a;b,c", false).unwrap(),
+            ";color=a%3Bb%2Cc"
+        );
+        assert_eq!(
+            from_str::<Vec<String>>("color", ";color=a%3Bb%2Cc,d", false).unwrap(),
+            ["a;b,c", "d"]
+        );
+        assert_eq!(
+            from_str::<String>("color", ";colour=blue", false)
+                .unwrap_err()
+                .0,
+            "Invalid matrix parameter color: ;colour=blue"
+        );
+        assert_eq!(
+            from_str::<Rgb>("color", ";color=R,100,G", false)
+                .unwrap_err()
+                .0,
+            "Parameter color isn't a valid object"
+        );
+    }
+}
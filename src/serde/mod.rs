@@ -0,0 +1,20 @@
+//! Serialization of parameters in the styles defined by OpenAPI.
+//!
+//! OpenAPI describes how path, query, header and cookie parameters are
+//! encoded by their `style` and `explode` properties. Each module here
+//! implements a style, converting between the strings it encodes and values
+//! implementing `Serialize` and `Deserialize`, so that generated code can
+//! use the same types for parameters as for bodies.
+//!
+//! Failures are reported as `ApiError`s, which implement the serde error
+//! traits for the purpose.
+pub mod cookie;
+pub mod deep_object;
+pub mod form_explode;
+pub mod label;
+pub mod matrix;
+pub mod query;
+pub mod simple;
+pub mod urlencoded;
+
+mod value;
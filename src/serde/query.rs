@@ -0,0 +1,365 @@
+//! Whole query strings, with a parameter for each field of a struct.
+//!
+//! Each parameter is written in the style configured for it in [`Styles`],
+//! or in the `form` style with `explode: true` by default. For a parameter
+//! named `color`:
+//!
+//! | Style            | `explode` | `["blue", "black"]`        | `{"R": 100, "G": 200}`     |
+//! |------------------|-----------|----------------------------|----------------------------|
+//! | `form`           | `true`    | `color=blue&color=black`   | `R=100&G=200`              |
+//! | `form`           | `false`   | `color=blue,black`         | `color=R,100,G,200`        |
+//! | `spaceDelimited` | `false`   | `color=blue%20black`       | `color=R%20100%20G%20200`  |
+//! | `pipeDelimited`  | `false`   | `color=blue%7Cblack`       | `color=R%7C100%7CG%7C200`  |
+//! | `deepObject`     | `true`    | n/a                        | `color[R]=100&color[G]=200`|
+//!
+//! The delimited styles with `explode: true` are written as the `form` style.
+//! Names and values are percent-encoded, and decoded when read, with `+`
+//! read as a space.
+use super::value::{decode, encode, to_value, Shaped, Value};
+use super::{deep_object, form_explode};
+use crate::ApiError;
+use serde::de::{self, DeserializeOwned, DeserializeSeed, IntoDeserializer, MapAccess, Visitor};
+use serde::Serialize;
+
+/// Style of a query parameter.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Style {
+    /// The `form` style.
+    Form,
+    /// The `spaceDelimited` style.
+    SpaceDelimited,
+    /// The `pipeDelimited` style.
+    PipeDelimited,
+    /// The `deepObject` style, which is always exploded.
+    DeepObject,
+}
+
+impl Style {
+    /// Separator between items when not exploded, as written and as read
+    /// once `+` and percent-encoded separators are replaced.
+    fn separator(self) -> (&'static str, char) {
+        match self {
+            Style::Form | Style::DeepObject => (",", ','),
+            Style::SpaceDelimited => ("%20", ' '),
+            Style::PipeDelimited => ("%7C", '|'),
+        }
+    }
+}
+
+/// The style and explode of each query parameter which doesn't use the
+/// default of the `form` style with `explode: true`.
+#[derive(Clone, Debug, Default)]
+pub struct Styles {
+    fields: Vec<(String, Style, bool)>,
+}
+
+impl Styles {
+    /// All parameters in the `form` style with `explode: true`.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Use the style and explode for the parameter with the given name, as
+    /// serialized.
+    pub fn with_field(mut self, name: impl Into<String>, style: Style, explode: bool) -> Self {
+        self.fields.push((name.into(), style, explode));
+        self
+    }
+
+    fn get(&self, name: &str) -> (Style, bool) {
+        self.fields
+            .iter()
+            .rev()
+            .find(|(field, _, _)| field == name)
+            .map_or((Style::Form, true), |(_, style, explode)| {
+                (*style, *explode)
+            })
+    }
+}
+
+/// Serialize a struct or map of parameters to a query string, without the
+/// leading `?`.
+///
+/// Parameters which are `None` are left out.
+pub fn to_string<T: Serialize + ?Sized>(value: &T, styles: &Styles) -> Result<String, ApiError> {
+    let fields = match to_value(value)? {
+        Value::Null => Vec::new(),
+        Value::Map(fields) => fields,
+        _ => {
+            return Err(ApiError(
+                "Query parameters must be a struct or map".to_string(),
+            ))
+        }
+    };
+
+    let mut pairs = Vec::new();
+    for (name, value) in fields {
+        let (style, explode) = styles.get(&name);
+        let encoded = match style {
+            Style::DeepObject => encode_pairs(deep_object::value_to_pairs(&name, value)?),
+            _ if explode => encode_pairs(form_explode::value_to_pairs(&name, value)?),
+            _ => match join_items(&name, value, style.separator().0)? {
+                Some(items) => vec![format!("{}={}", encode(&name), items)],
+                None => Vec::new(),
+            },
+        };
+        pairs.extend(encoded);
+    }
+    Ok(pairs.join("&"))
+}
+
+/// Percent-encode and write each pair.
+fn encode_pairs(pairs: Vec<(String, String)>) -> Vec<String> {
+    pairs
+        .into_iter()
+        .map(|(key, value)| format!("{}={}", encode(&key), encode(&value)))
+        .collect()
+}
+
+/// Write a parameter as its percent-encoded items with the separator
+/// between them, and objects as alternating keys and values, or `None` if
+/// it is missing.
+fn join_items(name: &str, value: Value, separator: &str) -> Result<Option<String>, ApiError> {
+    let items = match value {
+        Value::Null => return Ok(None),
+        Value::Str(value) => vec![encode(&value)],
+        Value::Seq(values) => {
+            let mut items = Vec::new();
+            for value in values {
+                if let Some(value) = value.into_scalar(name)? {
+                    items.push(encode(&value));
+                }
+            }
+            items
+        }
+        Value::Map(properties) => {
+            let mut items = Vec::new();
+            for (key, value) in properties {
+                if let Some(value) = value.into_scalar(&key)? {
+                    items.extend([encode(&key), encode(&value)]);
+                }
+            }
+            items
+        }
+    };
+    Ok(Some(items.join(separator)))
+}
+
+/// Deserialize a struct of parameters from a query string, without the
+/// leading `?`.
+///
+/// Keys may be repeated, as for exploded lists. Pairs which aren't for any
+/// field of the struct are ignored.
+pub fn from_str<T: DeserializeOwned>(query: &str, styles: &Styles) -> Result<T, ApiError> {
+    let mut pairs = Vec::new();
+    for pair in query.split('&').filter(|pair| !pair.is_empty()) {
+        let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+        pairs.push((decode_plus(key)?, value));
+    }
+    T::deserialize(QueryDeserializer { pairs, styles })
+}
+
+/// Percent-decode a name or value, with `+` as a space.
+fn decode_plus(s: &str) -> Result<String, ApiError> {
+    decode(&s.replace('+', "%20"))
+}
+
+/// Deserializer for a struct from query pairs, with their keys decoded.
+struct QueryDeserializer<'a> {
+    pairs: Vec<(String, &'a str)>,
+    styles: &'a Styles,
+}
+
+impl QueryDeserializer<'_> {
+    /// Read the field with the given name, from the pairs with their values
+    /// decoded.
+    fn field(&self, name: &str, decoded: &[(&str, &str)]) -> Result<Field, ApiError> {
+        let (style, explode) = self.styles.get(name);
+        if style == Style::DeepObject {
+            return Ok(Field::Value(deep_object::pairs_to_value(
+                name,
+                decoded.iter().copied(),
+            )?));
+        }
+        if explode {
+            return Ok(Field::Shaped(form_explode::pairs_to_shaped(name, decoded)));
+        }
+
+        let Some((_, value)) = self.pairs.iter().find(|(key, _)| key == name) else {
+            return Ok(Field::Shaped(Shaped::missing(name)));
+        };
+        let (encoded, separator) = style.separator();
+        let value = value
+            .replace('+', "%20")
+            .replace(encoded, &separator.to_string())
+            .replace(&encoded.to_lowercase(), &separator.to_string());
+        Ok(Field::Shaped(Shaped::from_list(
+            name,
+            &value,
+            &[separator],
+            false,
+        )?))
+    }
+}
+
+impl<'de> de::Deserializer<'de> for QueryDeserializer<'_> {
+    type Error = ApiError;
+
+    fn deserialize_any<V: Visitor<'de>>(self, _visitor: V) -> Result<V::Value, ApiError> {
+        Err(ApiError(
+            "Query parameters can only be read into a struct".to_string(),
+        ))
+    }
+
+    fn deserialize_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        fields: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, ApiError> {
+        let values = self
+            .pairs
+            .iter()
+            .map(|(key, value)| Ok((key.as_str(), decode_plus(value)?)))
+            .collect::<Result<Vec<_>, ApiError>>()?;
+        let decoded: Vec<_> = values
+            .iter()
+            .map(|(key, value)| (*key, value.as_str()))
+            .collect();
+        let fields = fields
+            .iter()
+            .map(|name| Ok((*name, self.field(name, &decoded)?)))
+            .collect::<Result<Vec<_>, ApiError>>()?;
+        visitor.visit_map(Fields {
+            fields: fields.into_iter(),
+            value: None,
+        })
+    }
+
+    serde::forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string
+        bytes byte_buf option unit unit_struct newtype_struct seq tuple
+        tuple_struct map enum identifier ignored_any
+    }
+}
+
+/// A field of the struct, read in its style.
+enum Field {
+    Shaped(Shaped),
+    Value(Value),
+}
+
+/// The fields of the struct, as a map from their names.
+struct Fields {
+    fields: std::vec::IntoIter<(&'static str, Field)>,
+    value: Option<Field>,
+}
+
+impl<'de> MapAccess<'de> for Fields {
+    type Error = ApiError;
+
+    fn next_key_seed<K: DeserializeSeed<'de>>(
+        &mut self,
+        seed: K,
+    ) -> Result<Option<K::Value>, ApiError> {
+        match self.fields.next() {
+            Some((name, value)) => {
+                self.value = Some(value);
+                seed.deserialize(name.into_deserializer()).map(Some)
+            }
+            None => Ok(None),
+        }
+    }
+
+    fn next_value_seed<V: DeserializeSeed<'de>>(&mut self, seed: V) -> Result<V::Value, ApiError> {
+        match self.value.take() {
+            Some(Field::Shaped(shaped)) => seed.deserialize(shaped),
+            Some(Field::Value(value)) => seed.deserialize(value),
+            None => Err(ApiError("Value requested before key".to_string())),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde::Deserialize;
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Filter {
+        owner: String,
+        age: Option<u8>,
+    }
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Params {
+        id: Vec<u32>,
+        tags: Vec<String>,
+        sort: Vec<String>,
+        fields: Vec<String>,
+        filter: Option<Filter>,
+        name: Option<String>,
+        limit: u32,
+    }
+
+    fn styles() -> Styles {
+        Styles::new()
+            .with_field("tags", Style::Form, false)
+            .with_field("sort", Style::PipeDelimited, false)
+            .with_field("fields", Style::SpaceDelimited, false)
+            .with_field("filter", Style::DeepObject, true)
+    }
+
+    #[test]
+    fn test_query() {
+        let params = Params {
+            id: vec![1, 2],
+            tags: vec!["a b".to_string(), "c,d".to_string()],
+            sort: vec!["name".to_string(), "-age".to_string()],
+            fields: vec!["id".to_string(), "name".to_string()],
+            filter: Some(Filter {
+                owner: "Rex".to_string(),
+                age: None,
+            }),
+            name: None,
+            limit: 10,
+        };
+        let query = to_string(&params, &styles()).unwrap();
+        assert_eq!(
+            query,
+            "id=1&id=2&tags=a%20b,c%2Cd&sort=name%7C-age&fields=id%20name\
+             &filter%5Bowner%5D=Rex&limit=10"
+        );
+        assert_eq!(from_str::<Params>(&query, &styles()).unwrap(), params);
+
+        let params = from_str::<Params>(
+            "limit=5&id=3&sort=a|b&fields=x+y&filter[owner]=Max&filter[age]=3&name=R%C3%A9&other",
+            &styles(),
+        )
+        .unwrap();
+        assert_eq!(
+            params,
+            Params {
+                id: vec![3],
+                tags: vec![],
+                sort: vec!["a".to_string(), "b".to_string()],
+                fields: vec!["x".to_string(), "y".to_string()],
+                filter: Some(Filter {
+                    owner: "Max".to_string(),
+                    age: Some(3),
+                }),
+                name: Some("Ré".to_string()),
+                limit: 5,
+            }
+        );
+
+        assert_eq!(
+            from_str::<Params>("id=1", &styles()).unwrap_err().0,
+            "Missing parameter limit"
+        );
+        assert_eq!(
+            to_string(&[1, 2], &styles()).unwrap_err().0,
+            "Query parameters must be a struct or map"
+        );
+    }
+}
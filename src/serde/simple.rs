@@ -0,0 +1,114 @@
+//! Parameters in the OpenAPI `simple` style.
+//!
+//! This is the default style of path and header parameters. Unlike the
+//! other path styles, there's nothing before the value:
+//!
+//! | Value                    | `explode: false`           | `explode: true`              |
+//! |--------------------------|----------------------------|------------------------------|
+//! | `"blue"`                 | `blue`                     | `blue`                       |
+//! | `["blue", "black"]`      | `blue,black`               | `blue,black`                 |
+//! | `{"R": 100, "G": 200}`   | `R,100,G,200`              | `R=100,G=200`                |
+//!
+//! Values are percent-encoded, including any `,` in them, and decoded when
+//! read.
+use super::value::{encode, to_value, Shaped, Value};
+use crate::ApiError;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+/// Serialize a parameter in the `simple` style.
+pub fn to_string<T: Serialize + ?Sized>(value: &T, explode: bool) -> Result<String, ApiError> {
+    let items = match to_value(value)? {
+        Value::Null => Vec::new(),
+        Value::Str(value) => vec![encode(&value)],
+        Value::Seq(values) => {
+            let mut items = Vec::new();
+            for value in values {
+                if let Some(value) = value.into_scalar("in simple style")? {
+                    items.push(encode(&value));
+                }
+            }
+            items
+        }
+        Value::Map(properties) => {
+            let mut items = Vec::new();
+            for (key, value) in properties {
+                if let Some(value) = value.into_scalar(&key)? {
+                    let (key, value) = (encode(&key), encode(&value));
+                    if explode {
+                        items.push(format!("{}={}", key, value));
+                    } else {
+                        items.extend([key, value]);
+                    }
+                }
+            }
+            items
+        }
+    };
+    Ok(items.join(","))
+}
+
+/// Deserialize a parameter in the `simple` style.
+///
+/// The name of the parameter is only used in errors.
+pub fn from_str<T: DeserializeOwned>(name: &str, s: &str, explode: bool) -> Result<T, ApiError> {
+    T::deserialize(Shaped::from_list(name, s, &[','], explode)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde::Deserialize;
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    #[allow(non_snake_case)]
+    struct Rgb {
+        R: u8,
+        G: u8,
+    }
+
+    const RGB: Rgb = Rgb { R: 100, G: 200 };
+
+    #[test]
+    fn test_simple() {
+        for (explode, object) in [(false, "R,100,G,200"), (true, "R=100,G=200")] {
+            assert_eq!(to_string("blue", explode).unwrap(), "blue");
+            assert_eq!(
+                from_str::<String>("color", "blue", explode).unwrap(),
+                "blue"
+            );
+
+            assert_eq!(
+                to_string(&["blue", "black"], explode).unwrap(),
+                "blue,black"
+            );
+            assert_eq!(
+                from_str::<Vec<String>>("color", "blue,black", explode).unwrap(),
+                ["blue", "black"]
+            );
+
+            assert_eq!(to_string(&RGB, explode).unwrap(), object);
+            assert_eq!(from_str::<Rgb>("color", object, explode).unwrap(), RGB);
+
+            let empty: [&str; 0] = [];
+            assert_eq!(to_string(&empty, explode).unwrap(), "");
+            assert!(from_str::<Vec<String>>("color", "", explode)
+                .unwrap()
+                .is_empty());
+        }
+
+        assert_eq!(to_string(&["a,b", "c"], false).unwrap(), "a%2Cb,c");
+        assert_eq!(
+            from_str::<Vec<String>>("color", "a%2Cb,c", false).unwrap(),
+            ["a,b", "c"]
+        );
+        assert_eq!(
+            from_str::<Rgb>("color", "R,100,G", false).unwrap_err().0,
+            "Parameter color isn't a valid object"
+        );
+        assert_eq!(
+            from_str::<Rgb>("color", "R,100,G,200", true).unwrap_err().0,
+            "Parameter color isn't a valid object"
+        );
+    }
+}
@@ -0,0 +1,163 @@
+//! Bodies of the `application/x-www-form-urlencoded` media type.
+//!
+//! Each property of a struct is written as a pair, and lists as a pair for
+//! each item, so `{"tags": ["a", "b"], "name": "Rex"}` is written as
+//! `tags=a&tags=b&name=Rex`. Nested objects are written with a bracketed
+//! property for each level, as in `owner[name]=Rex`, as in the `deepObject`
+//! style.
+//!
+//! When read, repeated keys are read as lists, and keys ending in `[]` are
+//! read as the same key without it. Bracketed keys are read as nested
+//! objects, but aren't needed for anything else.
+//!
+//! Names and values are percent-encoded, with spaces as `+`, and decoded
+//! when read. Lists which may be empty need `#[serde(default)]`, as there's
+//! nothing written for them.
+use super::deep_object;
+use super::form_explode;
+use super::value::{decode, encode, to_value, Value};
+use crate::ApiError;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+/// Serialize a struct or map to a form body.
+pub fn to_string<T: Serialize + ?Sized>(value: &T) -> Result<String, ApiError> {
+    let fields = match to_value(value)? {
+        Value::Null => Vec::new(),
+        Value::Map(fields) => fields,
+        _ => return Err(ApiError("Form bodies must be a struct or map".to_string())),
+    };
+
+    let mut pairs = Vec::new();
+    for (name, value) in fields {
+        match value {
+            Value::Map(_) => pairs.extend(deep_object::value_to_pairs(&name, value)?),
+            value => pairs.extend(form_explode::value_to_pairs(&name, value)?),
+        }
+    }
+    Ok(pairs
+        .into_iter()
+        .map(|(key, value)| format!("{}={}", encode_plus(&key), encode_plus(&value)))
+        .collect::<Vec<_>>()
+        .join("&"))
+}
+
+/// Deserialize a struct or map from a form body.
+pub fn from_bytes<T: DeserializeOwned>(body: &[u8]) -> Result<T, ApiError> {
+    let body =
+        std::str::from_utf8(body).map_err(|e| ApiError(format!("Invalid form body: {}", e)))?;
+
+    let mut object = Value::Map(Vec::new());
+    for pair in body.split('&').filter(|pair| !pair.is_empty()) {
+        let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+        let (key, value) = (decode_plus(key)?, decode_plus(value)?);
+
+        let name = key.strip_suffix("[]").unwrap_or(&key);
+        let path = match name.find('[') {
+            Some(index) => {
+                let mut path = vec![&name[..index]];
+                path.extend(
+                    deep_object::parse_path(&name[index..])
+                        .ok_or_else(|| ApiError(format!("Invalid form key {}", key)))?,
+                );
+                path
+            }
+            None => vec![name],
+        };
+        deep_object::insert(&mut object, &path, value)
+            .map_err(|_| ApiError(format!("Conflicting values for {}", key)))?;
+    }
+    T::deserialize(object)
+}
+
+/// Percent-encode a name or value, with spaces as `+`.
+fn encode_plus(s: &str) -> String {
+    encode(s).replace("%20", "+")
+}
+
+/// Percent-decode a name or value, with `+` as a space.
+fn decode_plus(s: &str) -> Result<String, ApiError> {
+    decode(&s.replace('+', "%20"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde::Deserialize;
+    use std::collections::BTreeMap;
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Owner {
+        name: String,
+    }
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Pet {
+        name: String,
+        age: u8,
+        #[serde(default)]
+        tags: Vec<String>,
+        owner: Option<Owner>,
+    }
+
+    #[test]
+    fn test_urlencoded() {
+        let pet = Pet {
+            name: "Rex the dog".to_string(),
+            age: 3,
+            tags: vec!["a&b".to_string(), "c".to_string()],
+            owner: Some(Owner {
+                name: "Max".to_string(),
+            }),
+        };
+        let body = to_string(&pet).unwrap();
+        assert_eq!(
+            body,
+            "name=Rex+the+dog&age=3&tags=a%26b&tags=c&owner%5Bname%5D=Max"
+        );
+        assert_eq!(from_bytes::<Pet>(body.as_bytes()).unwrap(), pet);
+
+        assert_eq!(
+            from_bytes::<Pet>(b"tags[]=x&name=Rex%20Jr&age=1&tags[]=y&owner[name]=Max").unwrap(),
+            Pet {
+                name: "Rex Jr".to_string(),
+                age: 1,
+                tags: vec!["x".to_string(), "y".to_string()],
+                owner: Some(Owner {
+                    name: "Max".to_string()
+                }),
+            }
+        );
+        assert_eq!(
+            from_bytes::<Pet>(b"name=Rex&age=1&tags=x").unwrap(),
+            Pet {
+                name: "Rex".to_string(),
+                age: 1,
+                tags: vec!["x".to_string()],
+                owner: None,
+            }
+        );
+        assert_eq!(
+            from_bytes::<BTreeMap<String, String>>(b"a=1&b=").unwrap(),
+            BTreeMap::from([
+                ("a".to_string(), "1".to_string()),
+                ("b".to_string(), String::new())
+            ])
+        );
+
+        assert_eq!(
+            to_string("Rex").unwrap_err().0,
+            "Form bodies must be a struct or map"
+        );
+        assert_eq!(
+            from_bytes::<Pet>(b"owner[name=Max").unwrap_err().0,
+            "Invalid form key owner[name"
+        );
+        assert_eq!(
+            from_bytes::<Pet>(b"owner=Max&owner[name]=Max")
+                .unwrap_err()
+                .0,
+            "Conflicting values for owner[name]"
+        );
+    }
+}
@@ -0,0 +1,839 @@
+//! Parameter values, as the strings, lists and maps which the OpenAPI styles
+//! encode.
+use crate::ApiError;
+use percent_encoding::{percent_decode_str, utf8_percent_encode, AsciiSet, NON_ALPHANUMERIC};
+use serde::de::value::{MapAccessDeserializer, MapDeserializer, SeqDeserializer};
+use serde::de::{self, IntoDeserializer, Visitor};
+use serde::ser::{self, Serialize};
+use std::fmt;
+
+/// Value of a parameter, with its scalars as strings.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) enum Value {
+    /// A missing value, such as `None`.
+    Null,
+    /// A string, number or boolean.
+    Str(String),
+    /// A list, such as a `Vec`.
+    Seq(Vec<Value>),
+    /// A map or struct, with its entries in order.
+    Map(Vec<(String, Value)>),
+}
+
+impl Value {
+    /// Group the pairs into a map, with repeated keys as lists, in the order
+    /// each key first appears.
+    pub(crate) fn from_pairs<I, K, V>(pairs: I) -> Self
+    where
+        I: IntoIterator<Item = (K, V)>,
+        K: Into<String>,
+        V: Into<String>,
+    {
+        let mut entries: Vec<(String, Value)> = Vec::new();
+        for (key, value) in pairs {
+            let (key, value) = (key.into(), Value::Str(value.into()));
+            match entries.iter_mut().find(|(k, _)| *k == key) {
+                Some((_, Value::Seq(values))) => values.push(value),
+                Some((_, existing)) => {
+                    let first = std::mem::replace(existing, Value::Null);
+                    *existing = Value::Seq(vec![first, value]);
+                }
+                None => entries.push((key, value)),
+            }
+        }
+        Value::Map(entries)
+    }
+
+    /// The scalar of a value which must be one, or an error describing the
+    /// value in the given parameter.
+    pub(crate) fn into_scalar(self, name: &str) -> Result<Option<String>, ApiError> {
+        match self {
+            Value::Null => Ok(None),
+            Value::Str(s) => Ok(Some(s)),
+            Value::Seq(_) | Value::Map(_) => Err(ApiError(format!(
+                "Parameter {} can't contain nested lists or objects",
+                name
+            ))),
+        }
+    }
+}
+
+/// Characters which are percent-encoded in parameters: all but the
+/// unreserved characters of RFC 3986.
+const PARAMETER_ENCODE_SET: &AsciiSet = &NON_ALPHANUMERIC
+    .remove(b'-')
+    .remove(b'.')
+    .remove(b'_')
+    .remove(b'~');
+
+/// Percent-encode a name or value in a parameter.
+pub(crate) fn encode(s: &str) -> String {
+    utf8_percent_encode(s, PARAMETER_ENCODE_SET).to_string()
+}
+
+/// Percent-decode a name or value in a parameter.
+pub(crate) fn decode(s: &str) -> Result<String, ApiError> {
+    percent_decode_str(s)
+        .decode_utf8()
+        .map(|s| s.into_owned())
+        .map_err(|e| ApiError(format!("Invalid parameter {}: {}", s, e)))
+}
+
+/// Convert a value to the strings, lists and maps it serializes as.
+pub(crate) fn to_value<T: Serialize + ?Sized>(value: &T) -> Result<Value, ApiError> {
+    value.serialize(ValueSerializer)
+}
+
+/// Serializer producing a `Value`.
+struct ValueSerializer;
+
+impl ser::Serializer for ValueSerializer {
+    type Ok = Value;
+    type Error = ApiError;
+    type SerializeSeq = SeqSerializer;
+    type SerializeTuple = SeqSerializer;
+    type SerializeTupleStruct = SeqSerializer;
+    type SerializeTupleVariant = SeqSerializer;
+    type SerializeMap = MapSerializer;
+    type SerializeStruct = MapSerializer;
+    type SerializeStructVariant = MapSerializer;
+
+    fn serialize_bool(self, v: bool) -> Result<Value, ApiError> {
+        Ok(Value::Str(v.to_string()))
+    }
+
+    fn serialize_i8(self, v: i8) -> Result<Value, ApiError> {
+        Ok(Value::Str(v.to_string()))
+    }
+
+    fn serialize_i16(self, v: i16) -> Result<Value, ApiError> {
+        Ok(Value::Str(v.to_string()))
+    }
+
+    fn serialize_i32(self, v: i32) -> Result<Value, ApiError> {
+        Ok(Value::Str(v.to_string()))
+    }
+
+    fn serialize_i64(self, v: i64) -> Result<Value, ApiError> {
+        Ok(Value::Str(v.to_string()))
+    }
+
+    fn serialize_u8(self, v: u8) -> Result<Value, ApiError> {
+        Ok(Value::Str(v.to_string()))
+    }
+
+    fn serialize_u16(self, v: u16) -> Result<Value, ApiError> {
+        Ok(Value::Str(v.to_string()))
+    }
+
+    fn serialize_u32(self, v: u32) -> Result<Value, ApiError> {
+        Ok(Value::Str(v.to_string()))
+    }
+
+    fn serialize_u64(self, v: u64) -> Result<Value, ApiError> {
+        Ok(Value::Str(v.to_string()))
+    }
+
+    fn serialize_f32(self, v: f32) -> Result<Value, ApiError> {
+        Ok(Value::Str(v.to_string()))
+    }
+
+    fn serialize_f64(self, v: f64) -> Result<Value, ApiError> {
+        Ok(Value::Str(v.to_string()))
+    }
+
+    fn serialize_char(self, v: char) -> Result<Value, ApiError> {
+        Ok(Value::Str(v.to_string()))
+    }
+
+    fn serialize_str(self, v: &str) -> Result<Value, ApiError> {
+        Ok(Value::Str(v.to_string()))
+    }
+
+    fn serialize_bytes(self, _v: &[u8]) -> Result<Value, ApiError> {
+        Err(ApiError(
+            "Bytes can't be serialized as a parameter".to_string(),
+        ))
+    }
+
+    fn serialize_none(self) -> Result<Value, ApiError> {
+        Ok(Value::Null)
+    }
+
+    fn serialize_some<T: Serialize + ?Sized>(self, value: &T) -> Result<Value, ApiError> {
+        value.serialize(self)
+    }
+
+    fn serialize_unit(self) -> Result<Value, ApiError> {
+        Ok(Value::Null)
+    }
+
+    fn serialize_unit_struct(self, _name: &'static str) -> Result<Value, ApiError> {
+        Ok(Value::Null)
+    }
+
+    fn serialize_unit_variant(
+        self,
+        _name: &'static str,
+        _index: u32,
+        variant: &'static str,
+    ) -> Result<Value, ApiError> {
+        Ok(Value::Str(variant.to_string()))
+    }
+
+    fn serialize_newtype_struct<T: Serialize + ?Sized>(
+        self,
+        _name: &'static str,
+        value: &T,
+    ) -> Result<Value, ApiError> {
+        value.serialize(self)
+    }
+
+    fn serialize_newtype_variant<T: Serialize + ?Sized>(
+        self,
+        _name: &'static str,
+        _index: u32,
+        variant: &'static str,
+        value: &T,
+    ) -> Result<Value, ApiError> {
+        Ok(Value::Map(vec![(variant.to_string(), to_value(value)?)]))
+    }
+
+    fn serialize_seq(self, len: Option<usize>) -> Result<SeqSerializer, ApiError> {
+        Ok(SeqSerializer {
+            variant: None,
+            items: Vec::with_capacity(len.unwrap_or(0)),
+        })
+    }
+
+    fn serialize_tuple(self, len: usize) -> Result<SeqSerializer, ApiError> {
+        self.serialize_seq(Some(len))
+    }
+
+    fn serialize_tuple_struct(
+        self,
+        _name: &'static str,
+        len: usize,
+    ) -> Result<SeqSerializer, ApiError> {
+        self.serialize_seq(Some(len))
+    }
+
+    fn serialize_tuple_variant(
+        self,
+        _name: &'static str,
+        _index: u32,
+        variant: &'static str,
+        len: usize,
+    ) -> Result<SeqSerializer, ApiError> {
+        Ok(SeqSerializer {
+            variant: Some(variant),
+            items: Vec::with_capacity(len),
+        })
+    }
+
+    fn serialize_map(self, len: Option<usize>) -> Result<MapSerializer, ApiError> {
+        Ok(MapSerializer {
+            variant: None,
+            entries: Vec::with_capacity(len.unwrap_or(0)),
+            key: None,
+        })
+    }
+
+    fn serialize_struct(self, _name: &'static str, len: usize) -> Result<MapSerializer, ApiError> {
+        self.serialize_map(Some(len))
+    }
+
+    fn serialize_struct_variant(
+        self,
+        _name: &'static str,
+        _index: u32,
+        variant: &'static str,
+        len: usize,
+    ) -> Result<MapSerializer, ApiError> {
+        Ok(MapSerializer {
+            variant: Some(variant),
+            entries: Vec::with_capacity(len),
+            key: None,
+        })
+    }
+}
+
+/// Wrap the value of an enum variant in a map keyed by the variant, as
+/// externally tagged enums are serialized.
+fn tag(variant: Option<&'static str>, value: Value) -> Value {
+    match variant {
+        Some(variant) => Value::Map(vec![(variant.to_string(), value)]),
+        None => value,
+    }
+}
+
+/// Serializer for the items of a list or tuple.
+struct SeqSerializer {
+    variant: Option<&'static str>,
+    items: Vec<Value>,
+}
+
+impl SeqSerializer {
+    fn push<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), ApiError> {
+        self.items.push(to_value(value)?);
+        Ok(())
+    }
+
+    fn finish(self) -> Result<Value, ApiError> {
+        Ok(tag(self.variant, Value::Seq(self.items)))
+    }
+}
+
+impl ser::SerializeSeq for SeqSerializer {
+    type Ok = Value;
+    type Error = ApiError;
+
+    fn serialize_element<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), ApiError> {
+        self.push(value)
+    }
+
+    fn end(self) -> Result<Value, ApiError> {
+        self.finish()
+    }
+}
+
+impl ser::SerializeTuple for SeqSerializer {
+    type Ok = Value;
+    type Error = ApiError;
+
+    fn serialize_element<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), ApiError> {
+        self.push(value)
+    }
+
+    fn end(self) -> Result<Value, ApiError> {
+        self.finish()
+    }
+}
+
+impl ser::SerializeTupleStruct for SeqSerializer {
+    type Ok = Value;
+    type Error = ApiError;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), ApiError> {
+        self.push(value)
+    }
+
+    fn end(self) -> Result<Value, ApiError> {
+        self.finish()
+    }
+}
+
+impl ser::SerializeTupleVariant for SeqSerializer {
+    type Ok = Value;
+    type Error = ApiError;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), ApiError> {
+        self.push(value)
+    }
+
+    fn end(self) -> Result<Value, ApiError> {
+        self.finish()
+    }
+}
+
+/// Serializer for the entries of a map or struct.
+struct MapSerializer {
+    variant: Option<&'static str>,
+    entries: Vec<(String, Value)>,
+    key: Option<String>,
+}
+
+impl MapSerializer {
+    fn insert<T: Serialize + ?Sized>(&mut self, key: String, value: &T) -> Result<(), ApiError> {
+        self.entries.push((key, to_value(value)?));
+        Ok(())
+    }
+
+    fn finish(self) -> Result<Value, ApiError> {
+        Ok(tag(self.variant, Value::Map(self.entries)))
+    }
+}
+
+impl ser::SerializeMap for MapSerializer {
+    type Ok = Value;
+    type Error = ApiError;
+
+    fn serialize_key<T: Serialize + ?Sized>(&mut self, key: &T) -> Result<(), ApiError> {
+        match to_value(key)? {
+            Value::Str(key) => {
+                self.key = Some(key);
+                Ok(())
+            }
+            _ => Err(ApiError("Map keys must be strings".to_string())),
+        }
+    }
+
+    fn serialize_value<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), ApiError> {
+        let key = self
+            .key
+            .take()
+            .ok_or_else(|| ApiError("Map value serialized before its key".to_string()))?;
+        self.insert(key, value)
+    }
+
+    fn end(self) -> Result<Value, ApiError> {
+        self.finish()
+    }
+}
+
+impl ser::SerializeStruct for MapSerializer {
+    type Ok = Value;
+    type Error = ApiError;
+
+    fn serialize_field<T: Serialize + ?Sized>(
+        &mut self,
+        key: &'static str,
+        value: &T,
+    ) -> Result<(), ApiError> {
+        self.insert(key.to_string(), value)
+    }
+
+    fn end(self) -> Result<Value, ApiError> {
+        self.finish()
+    }
+}
+
+impl ser::SerializeStructVariant for MapSerializer {
+    type Ok = Value;
+    type Error = ApiError;
+
+    fn serialize_field<T: Serialize + ?Sized>(
+        &mut self,
+        key: &'static str,
+        value: &T,
+    ) -> Result<(), ApiError> {
+        self.insert(key.to_string(), value)
+    }
+
+    fn end(self) -> Result<Value, ApiError> {
+        self.finish()
+    }
+}
+
+/// Implement `deserialize_*` methods which parse a string value as the
+/// given type, and otherwise defer to `deserialize_any`.
+macro_rules! deserialize_parsed {
+    ($($method:ident => $visit:ident,)*) => {
+        $(
+            fn $method<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, ApiError> {
+                match self {
+                    Value::Str(s) => visitor.$visit(s.parse().map_err(|e| {
+                        ApiError(format!("Invalid value {:?}: {}", s, e))
+                    })?),
+                    value => value.deserialize_any(visitor),
+                }
+            }
+        )*
+    };
+}
+
+/// Values are deserialized leniently: strings are parsed as whichever type
+/// is expected, a single string is a list of one item, and a missing value
+/// is `None`.
+impl<'de> de::Deserializer<'de> for Value {
+    type Error = ApiError;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, ApiError> {
+        match self {
+            Value::Null => visitor.visit_unit(),
+            Value::Str(s) => visitor.visit_string(s),
+            Value::Seq(items) => visitor.visit_seq(SeqDeserializer::new(items.into_iter())),
+            Value::Map(entries) => visitor.visit_map(MapDeserializer::new(entries.into_iter())),
+        }
+    }
+
+    deserialize_parsed! {
+        deserialize_bool => visit_bool,
+        deserialize_i8 => visit_i8,
+        deserialize_i16 => visit_i16,
+        deserialize_i32 => visit_i32,
+        deserialize_i64 => visit_i64,
+        deserialize_i128 => visit_i128,
+        deserialize_u8 => visit_u8,
+        deserialize_u16 => visit_u16,
+        deserialize_u32 => visit_u32,
+        deserialize_u64 => visit_u64,
+        deserialize_u128 => visit_u128,
+        deserialize_f32 => visit_f32,
+        deserialize_f64 => visit_f64,
+        deserialize_char => visit_char,
+    }
+
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, ApiError> {
+        match self {
+            Value::Null => visitor.visit_none(),
+            value => visitor.visit_some(value),
+        }
+    }
+
+    fn deserialize_seq<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, ApiError> {
+        match self {
+            Value::Null => visitor.visit_seq(SeqDeserializer::new(std::iter::empty::<Value>())),
+            Value::Str(s) => visitor.visit_seq(SeqDeserializer::new(std::iter::once(s))),
+            value => value.deserialize_any(visitor),
+        }
+    }
+
+    fn deserialize_tuple<V: Visitor<'de>>(
+        self,
+        _len: usize,
+        visitor: V,
+    ) -> Result<V::Value, ApiError> {
+        self.deserialize_seq(visitor)
+    }
+
+    fn deserialize_tuple_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        _len: usize,
+        visitor: V,
+    ) -> Result<V::Value, ApiError> {
+        self.deserialize_seq(visitor)
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, ApiError> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    fn deserialize_enum<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        _variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, ApiError> {
+        match self {
+            Value::Str(s) => visitor.visit_enum(s.into_deserializer()),
+            Value::Map(entries) if entries.len() == 1 => visitor.visit_enum(
+                MapAccessDeserializer::new(MapDeserializer::new(entries.into_iter())),
+            ),
+            _ => Err(ApiError("Invalid enum value".to_string())),
+        }
+    }
+
+    fn deserialize_unit<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, ApiError> {
+        visitor.visit_unit()
+    }
+
+    fn deserialize_unit_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, ApiError> {
+        visitor.visit_unit()
+    }
+
+    fn deserialize_ignored_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, ApiError> {
+        visitor.visit_unit()
+    }
+
+    serde::forward_to_deserialize_any! {
+        str string bytes byte_buf map struct identifier
+    }
+}
+
+impl<'de> IntoDeserializer<'de, ApiError> for Value {
+    type Deserializer = Self;
+
+    fn into_deserializer(self) -> Self {
+        self
+    }
+}
+
+/// Deserializer for a parameter in a style which doesn't mark whether it is
+/// a primitive, a list or an object, so is read as whichever is expected.
+pub(crate) struct Shaped {
+    /// Name of the parameter, for errors.
+    pub(crate) name: String,
+    /// The parameter read as a primitive, or `None` if it is missing.
+    pub(crate) scalar: Option<String>,
+    /// The parameter read as a list.
+    pub(crate) seq: Vec<String>,
+    /// The parameter read as the properties of an object, or `None` if it
+    /// can't be one.
+    pub(crate) map: Option<Vec<(String, String)>>,
+}
+
+impl Shaped {
+    /// Read a parameter written as a list of items with separators between
+    /// them, and objects as alternating keys and values, or as `key=value`
+    /// items if exploded. The items are percent-decoded.
+    pub(crate) fn from_list(
+        name: &str,
+        list: &str,
+        separators: &[char],
+        explode: bool,
+    ) -> Result<Self, ApiError> {
+        let items: Vec<&str> = if list.is_empty() {
+            Vec::new()
+        } else {
+            list.split(separators).collect()
+        };
+        let map = if explode {
+            items
+                .iter()
+                .map(|item| item.split_once('='))
+                .collect::<Option<Vec<_>>>()
+        } else {
+            items
+                .len()
+                .is_multiple_of(2)
+                .then(|| items.chunks(2).map(|pair| (pair[0], pair[1])).collect())
+        };
+        let map = match map {
+            Some(pairs) => Some(
+                pairs
+                    .into_iter()
+                    .map(|(key, value)| Ok((decode(key)?, decode(value)?)))
+                    .collect::<Result<Vec<_>, ApiError>>()?,
+            ),
+            None => None,
+        };
+
+        Ok(Shaped {
+            name: name.to_string(),
+            scalar: Some(decode(list)?),
+            seq: items
+                .into_iter()
+                .map(decode)
+                .collect::<Result<Vec<_>, _>>()?,
+            map,
+        })
+    }
+
+    /// A parameter which is missing, so is `None` if optional, or an empty
+    /// list or object.
+    pub(crate) fn missing(name: &str) -> Self {
+        Shaped {
+            name: name.to_string(),
+            scalar: None,
+            seq: Vec::new(),
+            map: Some(Vec::new()),
+        }
+    }
+
+    fn scalar(self) -> Result<Value, ApiError> {
+        match self.scalar {
+            Some(scalar) => Ok(Value::Str(scalar)),
+            None => Err(ApiError(format!("Missing parameter {}", self.name))),
+        }
+    }
+
+    fn seq(self) -> Value {
+        Value::Seq(self.seq.into_iter().map(Value::Str).collect())
+    }
+
+    fn map(self) -> Result<Value, ApiError> {
+        match self.map {
+            Some(pairs) => Ok(Value::from_pairs(pairs)),
+            None => Err(ApiError(format!(
+                "Parameter {} isn't a valid object",
+                self.name
+            ))),
+        }
+    }
+}
+
+/// Implement `deserialize_*` methods for primitives, reading the parameter
+/// as one.
+macro_rules! deserialize_scalar {
+    ($($method:ident)*) => {
+        $(
+            fn $method<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, ApiError> {
+                self.scalar()?.$method(visitor)
+            }
+        )*
+    };
+}
+
+impl<'de> de::Deserializer<'de> for Shaped {
+    type Error = ApiError;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, ApiError> {
+        if self.scalar.is_none() {
+            self.map()?.deserialize_any(visitor)
+        } else if self.seq.len() > 1 {
+            self.seq().deserialize_any(visitor)
+        } else {
+            self.scalar()?.deserialize_any(visitor)
+        }
+    }
+
+    deserialize_scalar! {
+        deserialize_bool deserialize_i8 deserialize_i16 deserialize_i32 deserialize_i64
+        deserialize_i128 deserialize_u8 deserialize_u16 deserialize_u32 deserialize_u64
+        deserialize_u128 deserialize_f32 deserialize_f64 deserialize_char deserialize_str
+        deserialize_string deserialize_bytes deserialize_byte_buf deserialize_identifier
+    }
+
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, ApiError> {
+        if self.scalar.is_some() {
+            visitor.visit_some(self)
+        } else {
+            visitor.visit_none()
+        }
+    }
+
+    fn deserialize_unit<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, ApiError> {
+        visitor.visit_unit()
+    }
+
+    fn deserialize_unit_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, ApiError> {
+        visitor.visit_unit()
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, ApiError> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    fn deserialize_seq<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, ApiError> {
+        self.seq().deserialize_seq(visitor)
+    }
+
+    fn deserialize_tuple<V: Visitor<'de>>(
+        self,
+        _len: usize,
+        visitor: V,
+    ) -> Result<V::Value, ApiError> {
+        self.deserialize_seq(visitor)
+    }
+
+    fn deserialize_tuple_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        _len: usize,
+        visitor: V,
+    ) -> Result<V::Value, ApiError> {
+        self.deserialize_seq(visitor)
+    }
+
+    fn deserialize_map<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, ApiError> {
+        self.map()?.deserialize_map(visitor)
+    }
+
+    fn deserialize_struct<V: Visitor<'de>>(
+        self,
+        name: &'static str,
+        fields: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, ApiError> {
+        self.map()?.deserialize_struct(name, fields, visitor)
+    }
+
+    fn deserialize_enum<V: Visitor<'de>>(
+        self,
+        name: &'static str,
+        variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, ApiError> {
+        self.scalar()?.deserialize_enum(name, variants, visitor)
+    }
+
+    fn deserialize_ignored_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, ApiError> {
+        visitor.visit_unit()
+    }
+}
+
+impl ser::Error for ApiError {
+    fn custom<T: fmt::Display>(msg: T) -> Self {
+        ApiError(msg.to_string())
+    }
+}
+
+impl de::Error for ApiError {
+    fn custom<T: fmt::Display>(msg: T) -> Self {
+        ApiError(msg.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde::{Deserialize, Serialize};
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    #[serde(rename_all = "lowercase")]
+    enum Colour {
+        Red,
+        Blue,
+    }
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Filter {
+        limit: u32,
+        tags: Vec<String>,
+        colour: Option<Colour>,
+        exact: bool,
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let filter = Filter {
+            limit: 10,
+            tags: vec!["a".to_string()],
+            colour: Some(Colour::Blue),
+            exact: false,
+        };
+        let value = to_value(&filter).unwrap();
+        assert_eq!(
+            value,
+            Value::Map(vec![
+                ("limit".to_string(), Value::Str("10".to_string())),
+                (
+                    "tags".to_string(),
+                    Value::Seq(vec![Value::Str("a".to_string())])
+                ),
+                ("colour".to_string(), Value::Str("blue".to_string())),
+                ("exact".to_string(), Value::Str("false".to_string())),
+            ])
+        );
+        assert_eq!(Filter::deserialize(value).unwrap(), filter);
+
+        // A single value is a list of one, and missing options are `None`.
+        let value = Value::from_pairs([("exact", "true"), ("limit", "5"), ("tags", "b")]);
+        assert_eq!(
+            Filter::deserialize(value).unwrap(),
+            Filter {
+                limit: 5,
+                tags: vec!["b".to_string()],
+                colour: None,
+                exact: true,
+            }
+        );
+
+        let value = Value::from_pairs([("tags", "a"), ("limit", "x"), ("tags", "b")]);
+        assert_eq!(
+            value,
+            Value::Map(vec![
+                (
+                    "tags".to_string(),
+                    Value::Seq(vec![
+                        Value::Str("a".to_string()),
+                        Value::Str("b".to_string())
+                    ])
+                ),
+                ("limit".to_string(), Value::Str("x".to_string())),
+            ])
+        );
+        assert_eq!(
+            Filter::deserialize(value).unwrap_err().0,
+            r#"Invalid value "x": invalid digit found in string"#
+        );
+    }
+}
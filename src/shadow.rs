@@ -0,0 +1,329 @@
+//! Traffic shadowing middleware, for duplicating requests to a secondary upstream.
+//!
+//! This allows teams migrating from a legacy service to a generated
+//! implementation to compare the behaviour of the two in production, without
+//! the secondary service affecting the responses returned to clients.
+use futures::channel::oneshot;
+use futures::future::{BoxFuture, FutureExt};
+use hyper::rt::Executor;
+use hyper::service::Service;
+use hyper::{Method, Request, Response, StatusCode, Uri};
+use std::fmt;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+/// Details of a shadowed request whose shadow response diverged from the primary response.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Divergence {
+    /// Method of the shadowed request.
+    pub method: Method,
+    /// URI of the shadowed request.
+    pub uri: Uri,
+    /// Status code returned by the primary service.
+    pub primary: StatusCode,
+    /// Status code returned by the shadow service, or a description of the
+    /// error if the shadow service failed.
+    pub shadow: Result<StatusCode, String>,
+}
+
+/// Hook for reporting divergences between primary and shadow responses.
+pub trait DivergenceReporter: Send + Sync {
+    /// Report a divergence.
+    fn report(&self, divergence: Divergence);
+}
+
+impl<F> DivergenceReporter for F
+where
+    F: Fn(Divergence) + Send + Sync,
+{
+    fn report(&self, divergence: Divergence) {
+        self(divergence)
+    }
+}
+
+/// Deterministic sampler, selecting a fixed fraction of requests.
+#[derive(Debug)]
+struct Sampler {
+    rate: f64,
+    count: AtomicU64,
+}
+
+impl Sampler {
+    fn new(rate: f64) -> Self {
+        Sampler {
+            rate: rate.clamp(0.0, 1.0),
+            count: AtomicU64::new(0),
+        }
+    }
+
+    fn sample(&self) -> bool {
+        let count = self.count.fetch_add(1, Ordering::Relaxed) as f64;
+        ((count + 1.0) * self.rate).floor() > (count * self.rate).floor()
+    }
+}
+
+/// Make service which duplicates a sample of requests to a shadow service.
+#[derive(Clone)]
+pub struct MakeShadowService<T, S, E> {
+    inner: T,
+    shadow: ShadowConfig<S, E>,
+}
+
+impl<T, S, E> MakeShadowService<T, S, E> {
+    /// Create a make service which duplicates a fraction of requests, between
+    /// `0.0` and `1.0`, to the shadow service, spawning shadow requests on the
+    /// provided executor.
+    pub fn new(inner: T, shadow: S, executor: E, sample_rate: f64) -> Self {
+        MakeShadowService {
+            inner,
+            shadow: ShadowConfig::new(shadow, executor, sample_rate),
+        }
+    }
+
+    /// Report divergences between primary and shadow responses to the given reporter.
+    pub fn with_reporter<R: DivergenceReporter + 'static>(mut self, reporter: R) -> Self {
+        self.shadow.reporter = Some(Arc::new(reporter));
+        self
+    }
+}
+
+impl<T, S, E> fmt::Debug for MakeShadowService<T, S, E>
+where
+    T: fmt::Debug,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("MakeShadowService")
+            .field("inner", &self.inner)
+            .field("sample_rate", &self.shadow.sampler.rate)
+            .finish()
+    }
+}
+
+impl<T, S, E, Target> Service<Target> for MakeShadowService<T, S, E>
+where
+    T: Service<Target>,
+    T::Future: Send + 'static,
+    S: Send + Sync + 'static,
+    E: Clone + Send + 'static,
+{
+    type Response = ShadowService<T::Response, S, E>;
+    type Error = T::Error;
+    type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn call(&self, target: Target) -> Self::Future {
+        let shadow = self.shadow.clone();
+        Box::pin(self.inner.call(target).map(|inner| {
+            Ok(ShadowService {
+                inner: inner?,
+                shadow,
+            })
+        }))
+    }
+}
+
+struct ShadowConfig<S, E> {
+    service: Arc<S>,
+    executor: E,
+    sampler: Arc<Sampler>,
+    reporter: Option<Arc<dyn DivergenceReporter>>,
+}
+
+impl<S, E> ShadowConfig<S, E> {
+    fn new(service: S, executor: E, sample_rate: f64) -> Self {
+        ShadowConfig {
+            service: Arc::new(service),
+            executor,
+            sampler: Arc::new(Sampler::new(sample_rate)),
+            reporter: None,
+        }
+    }
+}
+
+impl<S, E: Clone> Clone for ShadowConfig<S, E> {
+    fn clone(&self) -> Self {
+        ShadowConfig {
+            service: self.service.clone(),
+            executor: self.executor.clone(),
+            sampler: self.sampler.clone(),
+            reporter: self.reporter.clone(),
+        }
+    }
+}
+
+/// Middleware which duplicates a sample of requests to a shadow service.
+///
+/// The shadow request is spawned on the provided executor, and its response is
+/// discarded, so never affects the response returned to the client. Request
+/// bodies must be `Clone`, so will usually need to be buffered.
+///
+/// A `ProxyService` can be used as the shadow service to duplicate requests to
+/// a remote upstream.
+#[derive(Clone)]
+pub struct ShadowService<T, S, E> {
+    inner: T,
+    shadow: ShadowConfig<S, E>,
+}
+
+impl<T, S, E> ShadowService<T, S, E> {
+    /// Create a middleware which duplicates a fraction of requests, between
+    /// `0.0` and `1.0`, to the shadow service, spawning shadow requests on the
+    /// provided executor.
+    pub fn new(inner: T, shadow: S, executor: E, sample_rate: f64) -> Self {
+        ShadowService {
+            inner,
+            shadow: ShadowConfig::new(shadow, executor, sample_rate),
+        }
+    }
+
+    /// Report divergences between primary and shadow responses to the given reporter.
+    pub fn with_reporter<R: DivergenceReporter + 'static>(mut self, reporter: R) -> Self {
+        self.shadow.reporter = Some(Arc::new(reporter));
+        self
+    }
+}
+
+impl<T, S, E> fmt::Debug for ShadowService<T, S, E>
+where
+    T: fmt::Debug,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ShadowService")
+            .field("inner", &self.inner)
+            .field("sample_rate", &self.shadow.sampler.rate)
+            .finish()
+    }
+}
+
+impl<T, S, E, ReqBody, ResBody, ShadowBody> Service<Request<ReqBody>> for ShadowService<T, S, E>
+where
+    T: Service<Request<ReqBody>, Response = Response<ResBody>>,
+    T::Future: Send + 'static,
+    S: Service<Request<ReqBody>, Response = Response<ShadowBody>>,
+    S::Future: Send + 'static,
+    S::Error: fmt::Display,
+    E: Executor<BoxFuture<'static, ()>>,
+    ReqBody: Clone,
+    ResBody: Send + 'static,
+    T::Error: Send + 'static,
+{
+    type Response = T::Response;
+    type Error = T::Error;
+    type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn call(&self, req: Request<ReqBody>) -> Self::Future {
+        if !self.shadow.sampler.sample() {
+            return Box::pin(self.inner.call(req));
+        }
+
+        let method = req.method().clone();
+        let uri = req.uri().clone();
+
+        let mut shadow_req = Request::new(req.body().clone());
+        *shadow_req.method_mut() = method.clone();
+        *shadow_req.uri_mut() = uri.clone();
+        *shadow_req.version_mut() = req.version();
+        *shadow_req.headers_mut() = req.headers().clone();
+
+        let shadow_response = self.shadow.service.call(shadow_req);
+        let reporter = self.shadow.reporter.clone();
+        let (tx, rx) = oneshot::channel::<StatusCode>();
+
+        self.shadow.executor.execute(Box::pin(async move {
+            let shadow = shadow_response
+                .await
+                .map(|response| response.status())
+                .map_err(|e| e.to_string());
+
+            if let (Some(reporter), Ok(primary)) = (reporter, rx.await) {
+                if shadow != Ok(primary) {
+                    reporter.report(Divergence {
+                        method,
+                        uri,
+                        primary,
+                        shadow,
+                    });
+                }
+            }
+        }));
+
+        let response = self.inner.call(req);
+        Box::pin(async move {
+            let response = response.await?;
+            // The shadow task may have completed without a reporter, so ignore failures.
+            let _ = tx.send(response.status());
+            Ok(response)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hyper_util::rt::TokioExecutor;
+    use std::sync::Mutex;
+
+    struct StatusService(StatusCode, Arc<Mutex<u32>>);
+
+    impl Service<Request<String>> for StatusService {
+        type Response = Response<String>;
+        type Error = String;
+        type Future = futures::future::Ready<Result<Self::Response, Self::Error>>;
+
+        fn call(&self, req: Request<String>) -> Self::Future {
+            *self.1.lock().unwrap() += 1;
+            let mut response = Response::new(req.into_body());
+            *response.status_mut() = self.0;
+            futures::future::ok(response)
+        }
+    }
+
+    #[test]
+    fn test_sampler() {
+        let sampler = Sampler::new(0.25);
+        let sampled = (0..100).filter(|_| sampler.sample()).count();
+        assert_eq!(sampled, 25);
+
+        let sampler = Sampler::new(0.0);
+        assert!((0..100).all(|_| !sampler.sample()));
+    }
+
+    #[tokio::test]
+    async fn test_shadow_service() {
+        let primary_count = Arc::new(Mutex::new(0));
+        let shadow_count = Arc::new(Mutex::new(0));
+        let divergences = Arc::new(Mutex::new(Vec::new()));
+        let reported = divergences.clone();
+
+        let service = ShadowService::new(
+            StatusService(StatusCode::OK, primary_count.clone()),
+            StatusService(StatusCode::NOT_FOUND, shadow_count.clone()),
+            TokioExecutor::new(),
+            1.0,
+        )
+        .with_reporter(move |d| reported.lock().unwrap().push(d));
+
+        let response = service
+            .call(Request::post("/foo").body("body".to_string()).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.into_body(), "body");
+
+        // Allow the shadow task to complete.
+        for _ in 0..10 {
+            tokio::task::yield_now().await;
+        }
+
+        assert_eq!(*primary_count.lock().unwrap(), 1);
+        assert_eq!(*shadow_count.lock().unwrap(), 1);
+        assert_eq!(
+            *divergences.lock().unwrap(),
+            vec![Divergence {
+                method: Method::POST,
+                uri: "/foo".parse().unwrap(),
+                primary: StatusCode::OK,
+                shadow: Ok(StatusCode::NOT_FOUND),
+            }]
+        );
+    }
+}
@@ -0,0 +1,877 @@
+//! Module for routing a percentage of traffic between two services.
+//!
+//! This allows canary rollout of a new API implementation, mounted at the same
+//! base path as the existing implementation within a `CompositeMakeService`.
+use crate::auth::{api_key_from_cookie, api_key_from_query, Authorization};
+use crate::context::Has;
+use futures::channel::oneshot;
+use futures::future::{BoxFuture, FutureExt};
+use hyper::header::{HeaderMap, HeaderName};
+use hyper::service::Service;
+use hyper::{Method, Request, Response, StatusCode};
+use sha2::{Digest, Sha256};
+use std::collections::VecDeque;
+use std::fmt;
+use std::marker::PhantomData;
+use std::sync::atomic::{AtomicU32, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+use std::time::{Duration, Instant};
+
+/// Number of buckets into which traffic is divided - allows weights to be
+/// specified to a precision of 0.01%.
+const BUCKETS: u32 = 10_000;
+
+/// Runtime-adjustable weight of traffic sent to the alternate service.
+///
+/// Clones share the same underlying weight, so a handle can be kept to
+/// adjust the weight of a running `SplitService`.
+#[derive(Clone, Debug, Default)]
+pub struct SplitWeight(Arc<AtomicU32>);
+
+impl SplitWeight {
+    /// Create a weight, sending the given percentage of traffic to the alternate service.
+    pub fn new(percent: f64) -> Self {
+        let weight = SplitWeight::default();
+        weight.set(percent);
+        weight
+    }
+
+    /// Percentage of traffic being sent to the alternate service.
+    pub fn get(&self) -> f64 {
+        f64::from(self.0.load(Ordering::Relaxed)) * 100.0 / f64::from(BUCKETS)
+    }
+
+    /// Set the percentage of traffic being sent to the alternate service.
+    /// Values are clamped to the range 0-100.
+    pub fn set(&self, percent: f64) {
+        let buckets = (percent.clamp(0.0, 100.0) * f64::from(BUCKETS) / 100.0).round() as u32;
+        self.0.store(buckets, Ordering::Relaxed);
+    }
+
+    fn buckets(&self) -> u32 {
+        self.0.load(Ordering::Relaxed)
+    }
+
+    /// Create an admin service for reading and adjusting this weight, which
+    /// only adjusts it for requests whose headers the given function accepts.
+    pub fn admin_service<ResBody, F>(&self, authorize: F) -> SplitAdminService<ResBody>
+    where
+        F: Fn(&HeaderMap) -> bool + Send + Sync + 'static,
+    {
+        SplitAdminService {
+            weight: self.clone(),
+            authorize: Arc::new(authorize),
+            marker: PhantomData,
+        }
+    }
+
+    /// Create a make service for the admin service, for mounting in a `CompositeMakeService`.
+    pub fn make_admin_service<ResBody, F>(&self, authorize: F) -> MakeSplitAdminService<ResBody>
+    where
+        F: Fn(&HeaderMap) -> bool + Send + Sync + 'static,
+    {
+        MakeSplitAdminService(self.admin_service(authorize))
+    }
+}
+
+/// How requests from the same caller are kept on the same service.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Stickiness {
+    /// Requests are not sticky - each request is routed independently.
+    None,
+    /// Requests are routed based on a hash of the named header.
+    Header(HeaderName),
+    /// Requests are routed based on a hash of the named cookie.
+    Cookie(String),
+    /// Requests are routed based on a hash of the subject of the
+    /// `Authorization` in their context. This only applies to services
+    /// called with a `(Request, Context)` tuple, after authentication.
+    Subject,
+}
+
+impl Stickiness {
+    fn bucket<B>(&self, request: &Request<B>, subject: Option<&str>) -> Option<u32> {
+        match self {
+            Stickiness::None => None,
+            Stickiness::Header(name) => Some(bucket(request.headers().get(name)?.as_bytes())),
+            Stickiness::Cookie(name) => Some(bucket(
+                api_key_from_cookie(request.headers(), name)?.as_bytes(),
+            )),
+            Stickiness::Subject => Some(bucket(subject?.as_bytes())),
+        }
+    }
+}
+
+/// Bucket for a sticky key. This uses a fixed hash, so that callers stay on
+/// the same service across restarts and between instances.
+fn bucket(key: &[u8]) -> u32 {
+    let digest = Sha256::digest(key);
+    let mut hash = [0; 8];
+    hash.copy_from_slice(&digest[..8]);
+    (u64::from_be_bytes(hash) % u64::from(BUCKETS)) as u32
+}
+
+#[derive(Debug)]
+struct SplitState {
+    weight: SplitWeight,
+    stickiness: Stickiness,
+    count: AtomicU64,
+}
+
+impl SplitState {
+    /// Whether to route this request to the alternate service.
+    fn use_alternate<B>(&self, request: &Request<B>, subject: Option<&str>) -> bool {
+        let weight = self.weight.buckets();
+        match self.stickiness.bucket(request, subject) {
+            Some(bucket) => bucket < weight,
+            None => {
+                // Spread non-sticky requests evenly, by checking whether this
+                // request crosses a multiple of the weight.
+                let count = self.count.fetch_add(1, Ordering::Relaxed);
+                let weight = u64::from(weight);
+                let buckets = u64::from(BUCKETS);
+                ((count % buckets + 1) * weight) / buckets > ((count % buckets) * weight) / buckets
+            }
+        }
+    }
+}
+
+/// Make service which routes a percentage of traffic to an alternate make service.
+#[derive(Debug)]
+pub struct MakeSplitService<A, B> {
+    primary: A,
+    alternate: B,
+    state: Arc<SplitState>,
+}
+
+impl<A, B> MakeSplitService<A, B> {
+    /// Create a make service which sends a weighted percentage of traffic to the
+    /// alternate service, and the remainder to the primary service.
+    pub fn new(primary: A, alternate: B, weight: SplitWeight) -> Self {
+        MakeSplitService {
+            primary,
+            alternate,
+            state: Arc::new(SplitState {
+                weight,
+                stickiness: Stickiness::None,
+                count: AtomicU64::new(0),
+            }),
+        }
+    }
+
+    /// Keep requests from the same caller on the same service.
+    pub fn with_stickiness(self, stickiness: Stickiness) -> Self {
+        MakeSplitService {
+            state: Arc::new(SplitState {
+                weight: self.state.weight.clone(),
+                stickiness,
+                count: AtomicU64::new(0),
+            }),
+            ..self
+        }
+    }
+}
+
+impl<A, B, Target> Service<Target> for MakeSplitService<A, B>
+where
+    Target: Clone,
+    A: Service<Target>,
+    A::Future: Send + 'static,
+    A::Response: Send + 'static,
+    B: Service<Target, Error = A::Error>,
+    B::Future: Send + 'static,
+    B::Response: Send + 'static,
+{
+    type Response = SplitService<A::Response, B::Response>;
+    type Error = A::Error;
+    type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn call(&self, target: Target) -> Self::Future {
+        let state = self.state.clone();
+        let primary = self.primary.call(target.clone());
+        let alternate = self.alternate.call(target);
+        Box::pin(
+            futures::future::try_join(primary, alternate).map(|services| {
+                let (primary, alternate) = services?;
+                Ok(SplitService {
+                    primary,
+                    alternate,
+                    state,
+                })
+            }),
+        )
+    }
+}
+
+/// Service which routes a percentage of traffic to an alternate service.
+///
+/// Example Usage
+/// =============
+///
+/// ```ignore
+/// let weight = SplitWeight::new(5.0);
+///
+/// let mut composite_make_service = CompositeMakeService::new();
+/// composite_make_service.push((
+///     "/api",
+///     Box::new(
+///         MakeSplitService::new(current_make_service, canary_make_service, weight.clone())
+///             .with_stickiness(Stickiness::Header(AUTHORIZATION)),
+///     ),
+/// ));
+/// composite_make_service.push((
+///     "/admin/canary",
+///     Box::new(weight.make_admin_service(move |headers: &HeaderMap| {
+///         headers.get("x-admin-token") == Some(&admin_token)
+///     })),
+/// ));
+/// ```
+#[derive(Debug)]
+pub struct SplitService<A, B> {
+    primary: A,
+    alternate: B,
+    state: Arc<SplitState>,
+}
+
+impl<A, B> SplitService<A, B> {
+    /// Create a service which sends a weighted percentage of traffic to the
+    /// alternate service, and the remainder to the primary service.
+    pub fn new(primary: A, alternate: B, weight: SplitWeight) -> Self {
+        SplitService {
+            primary,
+            alternate,
+            state: Arc::new(SplitState {
+                weight,
+                stickiness: Stickiness::None,
+                count: AtomicU64::new(0),
+            }),
+        }
+    }
+
+    /// Keep requests from the same caller on the same service.
+    pub fn with_stickiness(self, stickiness: Stickiness) -> Self {
+        SplitService {
+            state: Arc::new(SplitState {
+                weight: self.state.weight.clone(),
+                stickiness,
+                count: AtomicU64::new(0),
+            }),
+            ..self
+        }
+    }
+}
+
+impl<A, B, ReqBody, ResBody> Service<Request<ReqBody>> for SplitService<A, B>
+where
+    A: Service<Request<ReqBody>, Response = Response<ResBody>>,
+    A::Future: Send + 'static,
+    B: Service<Request<ReqBody>, Response = Response<ResBody>, Error = A::Error>,
+    B::Future: Send + 'static,
+{
+    type Response = Response<ResBody>;
+    type Error = A::Error;
+    type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn call(&self, req: Request<ReqBody>) -> Self::Future {
+        if self.state.use_alternate(&req, None) {
+            Box::pin(self.alternate.call(req))
+        } else {
+            Box::pin(self.primary.call(req))
+        }
+    }
+}
+
+impl<A, B, ReqBody, ResBody, C> Service<(Request<ReqBody>, C)> for SplitService<A, B>
+where
+    A: Service<(Request<ReqBody>, C), Response = Response<ResBody>>,
+    A::Future: Send + 'static,
+    B: Service<(Request<ReqBody>, C), Response = Response<ResBody>, Error = A::Error>,
+    B::Future: Send + 'static,
+    C: Has<Option<Authorization>>,
+{
+    type Response = Response<ResBody>;
+    type Error = A::Error;
+    type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn call(&self, req: (Request<ReqBody>, C)) -> Self::Future {
+        let subject = Has::<Option<Authorization>>::get(&req.1)
+            .as_ref()
+            .map(|authorization| authorization.subject.as_str());
+        if self.state.use_alternate(&req.0, subject) {
+            Box::pin(self.alternate.call(req))
+        } else {
+            Box::pin(self.primary.call(req))
+        }
+    }
+}
+
+/// What happens to requests in excess of a slow start ramp.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SlowStartOverflow {
+    /// Send them to the old service, which is released once the ramp
+    /// completes.
+    Shed,
+    /// Queue them for the new service, whose concurrency ramps from a single
+    /// request up to the given limit. The old service is released at once.
+    Queue(usize),
+}
+
+/// How traffic is moved from an existing service to a newly added one.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SlowStart {
+    window: Duration,
+    overflow: SlowStartOverflow,
+}
+
+impl SlowStart {
+    /// Ramp traffic to the new service linearly over the given window,
+    /// sending the remainder to the old service.
+    pub fn new<D: Into<Duration>>(window: D) -> Self {
+        SlowStart {
+            window: window.into(),
+            overflow: SlowStartOverflow::Shed,
+        }
+    }
+
+    /// Queue requests in excess of the ramp rather than sending them to the
+    /// old service, ramping up to the given number of concurrent requests.
+    pub fn with_queue(self, max_concurrency: usize) -> Self {
+        SlowStart {
+            overflow: SlowStartOverflow::Queue(max_concurrency.max(1)),
+            ..self
+        }
+    }
+}
+
+/// Linear ramp of traffic from an existing service to a newly added one.
+#[derive(Debug)]
+struct Ramp {
+    split: SplitState,
+    start: Instant,
+    window: Duration,
+    queue: Option<Queue>,
+}
+
+impl Ramp {
+    /// Proportion of the ramp completed, from 0 to 1.
+    fn progress(&self) -> f64 {
+        let elapsed = self.start.elapsed();
+        if elapsed >= self.window {
+            1.0
+        } else {
+            elapsed.as_secs_f64() / self.window.as_secs_f64()
+        }
+    }
+
+    fn is_complete(&self) -> bool {
+        self.start.elapsed() >= self.window
+    }
+}
+
+/// Requests queued for the new service, and the number it is handling.
+#[derive(Debug)]
+struct Queue {
+    max_concurrency: usize,
+    state: Mutex<QueueState>,
+}
+
+#[derive(Debug, Default)]
+struct QueueState {
+    in_flight: usize,
+    waiting: VecDeque<oneshot::Sender<Permit>>,
+}
+
+impl Queue {
+    fn lock(&self) -> MutexGuard<'_, QueueState> {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Number of concurrent requests the new service may handle so far.
+    fn limit(&self, ramp: &Ramp) -> usize {
+        if ramp.is_complete() {
+            return usize::MAX;
+        }
+        let limit = (ramp.progress() * self.max_concurrency as f64).ceil() as usize;
+        limit.max(1)
+    }
+}
+
+/// Place in the queue of a slow start, released when dropped.
+#[derive(Debug)]
+struct Permit(Option<Arc<Ramp>>);
+
+impl Permit {
+    /// Take a place, if the new service has capacity, or join the queue.
+    fn acquire(ramp: &Arc<Ramp>) -> Result<Self, oneshot::Receiver<Permit>> {
+        let queue = ramp.queue.as_ref().expect("slow start has no queue");
+        let mut state = queue.lock();
+        if state.waiting.is_empty() && state.in_flight < queue.limit(ramp) {
+            state.in_flight += 1;
+            return Ok(Permit(Some(ramp.clone())));
+        }
+        let (sender, receiver) = oneshot::channel();
+        state.waiting.push_back(sender);
+        Err(receiver)
+    }
+}
+
+impl Drop for Permit {
+    fn drop(&mut self) {
+        let Some(ramp) = self.0.take() else {
+            return;
+        };
+        let queue = ramp.queue.as_ref().expect("slow start has no queue");
+        let limit = queue.limit(&ramp);
+        let mut state = queue.lock();
+        state.in_flight -= 1;
+        // The limit only grows, so admit as many waiters as it now allows.
+        while state.in_flight < limit {
+            let Some(waiter) = state.waiting.pop_front() else {
+                break;
+            };
+            state.in_flight += 1;
+            if let Err(mut permit) = waiter.send(Permit(Some(ramp.clone()))) {
+                // The request was abandoned. Release its place here, as the
+                // queue is already locked.
+                permit.0 = None;
+                state.in_flight -= 1;
+            }
+        }
+    }
+}
+
+/// Service which gradually moves traffic from an existing service to a newly
+/// added one, as when hot-swapping a service with
+/// `SharedCompositeService::mount_with_slow_start`.
+///
+/// This avoids latency spikes caused by sending all traffic to a new service
+/// instance with cold caches. The ramp starts when this service is created,
+/// and the old service is released once it is no longer needed.
+#[derive(Debug)]
+pub struct SlowStartService<A, B> {
+    old: Mutex<Option<A>>,
+    new: Arc<B>,
+    ramp: Arc<Ramp>,
+}
+
+impl<A, B> SlowStartService<A, B> {
+    /// Create a service which moves traffic from the old service to the new
+    /// service as configured.
+    pub fn new(old: A, new: B, slow_start: SlowStart) -> Self {
+        let (old, queue) = match slow_start.overflow {
+            SlowStartOverflow::Shed => (Some(old), None),
+            SlowStartOverflow::Queue(max_concurrency) => (
+                None,
+                Some(Queue {
+                    max_concurrency,
+                    state: Mutex::default(),
+                }),
+            ),
+        };
+        SlowStartService {
+            old: Mutex::new(old),
+            new: Arc::new(new),
+            ramp: Arc::new(Ramp {
+                split: SplitState {
+                    weight: SplitWeight::new(0.0),
+                    stickiness: Stickiness::None,
+                    count: AtomicU64::new(0),
+                },
+                start: Instant::now(),
+                window: slow_start.window,
+                queue,
+            }),
+        }
+    }
+
+    /// Whether all traffic is now being sent to the new service.
+    pub fn is_complete(&self) -> bool {
+        self.ramp.is_complete()
+    }
+
+    /// Whether the old service has been released.
+    pub fn is_released(&self) -> bool {
+        self.old
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .is_none()
+    }
+}
+
+impl<A, B, ReqBody, ResBody> Service<Request<ReqBody>> for SlowStartService<A, B>
+where
+    A: Service<Request<ReqBody>, Response = Response<ResBody>>,
+    A::Future: Send + 'static,
+    B: Service<Request<ReqBody>, Response = Response<ResBody>, Error = A::Error>
+        + Send
+        + Sync
+        + 'static,
+    B::Future: Send + 'static,
+    ReqBody: Send + 'static,
+{
+    type Response = Response<ResBody>;
+    type Error = A::Error;
+    type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn call(&self, req: Request<ReqBody>) -> Self::Future {
+        if self.ramp.queue.is_some() {
+            let new = self.new.clone();
+            return match Permit::acquire(&self.ramp) {
+                Ok(permit) => Box::pin(new.call(req).map(move |result| {
+                    drop(permit);
+                    result
+                })),
+                Err(waiting) => Box::pin(async move {
+                    // This fails only if the queue has been dropped along
+                    // with the service, leaving nothing to wait for.
+                    let permit = waiting.await;
+                    let result = new.call(req).await;
+                    drop(permit);
+                    result
+                }),
+            };
+        }
+
+        let mut old = self.old.lock().unwrap_or_else(PoisonError::into_inner);
+        if self.ramp.is_complete() {
+            // Requests the old service is already handling are unaffected.
+            old.take();
+        }
+        if let Some(old) = old.as_ref() {
+            self.ramp.split.weight.set(self.ramp.progress() * 100.0);
+            if !self.ramp.split.use_alternate(&req, None) {
+                return Box::pin(old.call(req));
+            }
+        }
+        Box::pin(self.new.call(req))
+    }
+}
+
+/// Make service for the admin service of a `SplitWeight`.
+#[derive(Debug)]
+pub struct MakeSplitAdminService<ResBody>(SplitAdminService<ResBody>);
+
+impl<ResBody> Clone for MakeSplitAdminService<ResBody> {
+    fn clone(&self) -> Self {
+        MakeSplitAdminService(self.0.clone())
+    }
+}
+
+impl<ResBody, Target> Service<Target> for MakeSplitAdminService<ResBody> {
+    type Response = SplitAdminService<ResBody>;
+    type Error = std::convert::Infallible;
+    type Future = futures::future::Ready<Result<Self::Response, Self::Error>>;
+
+    fn call(&self, _target: Target) -> Self::Future {
+        futures::future::ok(self.0.clone())
+    }
+}
+
+/// Admin service for a `SplitWeight`.
+///
+/// `GET` requests return the current percentage of traffic sent to the
+/// alternate service, and `PUT` or `POST` requests with a `weight` query
+/// parameter, e.g. `?weight=12.5`, adjust it. As anyone who can adjust the
+/// weight controls where traffic goes, these are rejected with
+/// `403 Forbidden` unless the function passed to `SplitWeight::admin_service`
+/// accepts their headers, such as by checking for an admin token.
+pub struct SplitAdminService<ResBody> {
+    weight: SplitWeight,
+    authorize: Arc<dyn Fn(&HeaderMap) -> bool + Send + Sync>,
+    marker: PhantomData<fn() -> ResBody>,
+}
+
+impl<ResBody> Clone for SplitAdminService<ResBody> {
+    fn clone(&self) -> Self {
+        SplitAdminService {
+            weight: self.weight.clone(),
+            authorize: self.authorize.clone(),
+            marker: PhantomData,
+        }
+    }
+}
+
+impl<ResBody> fmt::Debug for SplitAdminService<ResBody> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SplitAdminService")
+            .field("weight", &self.weight)
+            .finish()
+    }
+}
+
+impl<ReqBody, ResBody> Service<Request<ReqBody>> for SplitAdminService<ResBody>
+where
+    ResBody: From<String>,
+{
+    type Response = Response<ResBody>;
+    type Error = std::convert::Infallible;
+    type Future = futures::future::Ready<Result<Self::Response, Self::Error>>;
+
+    fn call(&self, req: Request<ReqBody>) -> Self::Future {
+        let status = match *req.method() {
+            Method::GET => StatusCode::OK,
+            Method::PUT | Method::POST if !(self.authorize)(req.headers()) => StatusCode::FORBIDDEN,
+            Method::PUT | Method::POST => {
+                match api_key_from_query(req.uri(), "weight")
+                    .and_then(|weight| weight.parse::<f64>().ok())
+                    .filter(|weight| (0.0..=100.0).contains(weight))
+                {
+                    Some(weight) => {
+                        self.weight.set(weight);
+                        StatusCode::OK
+                    }
+                    None => StatusCode::BAD_REQUEST,
+                }
+            }
+            _ => StatusCode::METHOD_NOT_ALLOWED,
+        };
+
+        let body = if status == StatusCode::OK {
+            self.weight.get().to_string()
+        } else {
+            String::new()
+        };
+
+        futures::future::ok(
+            Response::builder()
+                .status(status)
+                .body(ResBody::from(body))
+                .unwrap(),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Clone, Copy)]
+    struct NamedService(&'static str);
+
+    impl Service<Option<std::net::SocketAddr>> for NamedService {
+        type Response = NamedService;
+        type Error = ();
+        type Future = futures::future::Ready<Result<Self::Response, Self::Error>>;
+
+        fn call(&self, _target: Option<std::net::SocketAddr>) -> Self::Future {
+            futures::future::ok(*self)
+        }
+    }
+
+    impl Service<Request<()>> for NamedService {
+        type Response = Response<&'static str>;
+        type Error = ();
+        type Future = futures::future::Ready<Result<Self::Response, Self::Error>>;
+
+        fn call(&self, _req: Request<()>) -> Self::Future {
+            futures::future::ok(Response::new(self.0))
+        }
+    }
+
+    impl<C> Service<(Request<()>, C)> for NamedService {
+        type Response = Response<&'static str>;
+        type Error = ();
+        type Future = futures::future::Ready<Result<Self::Response, Self::Error>>;
+
+        fn call(&self, _req: (Request<()>, C)) -> Self::Future {
+            futures::future::ok(Response::new(self.0))
+        }
+    }
+
+    async fn count_alternate<S>(service: &S, requests: usize) -> usize
+    where
+        S: Service<Request<()>, Response = Response<&'static str>, Error = ()>,
+    {
+        let mut count = 0;
+        for _ in 0..requests {
+            if *service.call(Request::new(())).await.unwrap().body() == "alternate" {
+                count += 1;
+            }
+        }
+        count
+    }
+
+    #[test]
+    fn test_weight() {
+        let weight = SplitWeight::new(12.5);
+        assert_eq!(weight.get(), 12.5);
+        weight.set(150.0);
+        assert_eq!(weight.get(), 100.0);
+    }
+
+    #[tokio::test]
+    async fn test_split_service() {
+        let weight = SplitWeight::new(10.0);
+        let service = SplitService::new(
+            NamedService("primary"),
+            NamedService("alternate"),
+            weight.clone(),
+        );
+
+        assert_eq!(count_alternate(&service, 100).await, 10);
+
+        weight.set(50.0);
+        assert_eq!(count_alternate(&service, 100).await, 50);
+    }
+
+    #[cfg(any(feature = "http1", feature = "http2"))]
+    #[tokio::test]
+    async fn test_split_in_composite() {
+        let weight = SplitWeight::new(100.0);
+        let mut composite_make_service = crate::CompositeMakeService::new();
+        composite_make_service.push((
+            "/api",
+            Box::new(MakeSplitService::new(
+                NamedService("primary"),
+                NamedService("alternate"),
+                weight.clone(),
+            )),
+        ));
+        let service = composite_make_service.call(None).await.unwrap();
+        let request = || Request::get("/api/pets").body(()).unwrap();
+
+        assert_eq!(
+            service.call(request()).await.unwrap().into_body(),
+            "alternate"
+        );
+        weight.set(0.0);
+        assert_eq!(
+            service.call(request()).await.unwrap().into_body(),
+            "primary"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_sticky_split_service() {
+        let service = SplitService::new(
+            NamedService("primary"),
+            NamedService("alternate"),
+            SplitWeight::new(50.0),
+        )
+        .with_stickiness(Stickiness::Header(HeaderName::from_static("x-user")));
+
+        let request = || {
+            Request::builder()
+                .header("x-user", "alice")
+                .body(())
+                .unwrap()
+        };
+        let first = service.call(request()).await.unwrap().into_body();
+        for _ in 0..10 {
+            assert_eq!(service.call(request()).await.unwrap().into_body(), first);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_subject_stickiness() {
+        use crate::auth::Scopes;
+        use crate::context::{ContextBuilder, Push};
+        use crate::EmptyContext;
+
+        let service = SplitService::new(
+            NamedService("primary"),
+            NamedService("alternate"),
+            SplitWeight::new(50.0),
+        )
+        .with_stickiness(Stickiness::Subject);
+
+        let request = |subject: &str| {
+            let context: ContextBuilder<Option<Authorization>, EmptyContext> =
+                EmptyContext.push(Some(Authorization::new(subject, Scopes::All)));
+            service.call((Request::new(()), context))
+        };
+        let mut routed = Vec::new();
+        for subject in ["alice", "bob", "carol", "dave", "eve", "frank"] {
+            let first = request(subject).await.unwrap().into_body();
+            for _ in 0..10 {
+                assert_eq!(request(subject).await.unwrap().into_body(), first);
+            }
+            routed.push(first);
+        }
+        // The hash is fixed, so subjects go the same way on every instance.
+        assert_eq!(bucket(b"alice"), 207);
+        assert!(routed.contains(&"primary") && routed.contains(&"alternate"));
+    }
+
+    #[tokio::test]
+    async fn test_slow_start_service() {
+        let service = SlowStartService::new(
+            NamedService("old"),
+            NamedService("alternate"),
+            SlowStart::new(Duration::from_secs(3600)),
+        );
+        assert!(!service.is_complete());
+        assert_eq!(count_alternate(&service, 100).await, 0);
+        assert!(!service.is_released());
+
+        let service = SlowStartService::new(
+            NamedService("old"),
+            NamedService("alternate"),
+            SlowStart::new(Duration::from_secs(0)),
+        );
+        assert!(service.is_complete());
+        assert_eq!(count_alternate(&service, 100).await, 100);
+        assert!(service.is_released());
+    }
+
+    #[tokio::test]
+    async fn test_slow_start_queue() {
+        let service = SlowStartService::new(
+            NamedService("old"),
+            NamedService("alternate"),
+            SlowStart::new(Duration::from_secs(3600)).with_queue(10),
+        );
+        assert!(service.is_released());
+
+        // Only one request is handled at a time at the start of the ramp.
+        let first = service.call(Request::new(()));
+        let mut second = service.call(Request::new(()));
+        assert!((&mut second).now_or_never().is_none());
+        assert_eq!(first.await.unwrap().into_body(), "alternate");
+        assert_eq!(second.await.unwrap().into_body(), "alternate");
+
+        // Abandoned requests give up their place in the queue.
+        let first = service.call(Request::new(()));
+        drop(service.call(Request::new(())));
+        let third = service.call(Request::new(()));
+        drop(first);
+        assert_eq!(third.await.unwrap().into_body(), "alternate");
+    }
+
+    #[tokio::test]
+    async fn test_admin_service() {
+        let weight = SplitWeight::new(0.0);
+        let admin = weight.admin_service::<String, _>(|headers: &HeaderMap| {
+            headers
+                .get("x-admin-token")
+                .is_some_and(|token| token == "secret")
+        });
+        let put = |uri: &str| {
+            Request::put(uri)
+                .header("x-admin-token", "secret")
+                .body(())
+                .unwrap()
+        };
+
+        let response = admin.call(put("/?weight=25")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(weight.get(), 25.0);
+
+        let response = admin.call(put("/?weight=foo")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+        let response: Response<String> = admin
+            .call(Request::get("/").body(()).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(response.into_body(), "25");
+
+        // Requests which aren't authorized can't adjust the weight.
+        let response = admin
+            .call(Request::put("/?weight=50").body(()).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+        assert_eq!(weight.get(), 25.0);
+    }
+}
@@ -0,0 +1,67 @@
+//! Serving HTTP over Unix domain sockets.
+//!
+//! Servers for local clients, such as those reached through
+//! `Connector::builder().uds(path)`, can listen on a Unix socket rather than a
+//! TCP port:
+//!
+//! ```ignore
+//! let listener = swagger::uds::bind("/var/run/petstore.sock")?;
+//! loop {
+//!     let (stream, _) = listener.accept().await?;
+//!     let service = make_service.call(&stream).await?;
+//!     tokio::spawn(
+//!         hyper::server::conn::http1::Builder::new()
+//!             .serve_connection(hyper_util::rt::TokioIo::new(stream), service),
+//!     );
+//! }
+//! ```
+use std::io;
+use std::os::unix::fs::FileTypeExt;
+use std::path::Path;
+use tokio::net::UnixListener;
+
+/// Listen on a Unix socket at the given path.
+///
+/// A socket left behind by a server which has exited is removed first, but
+/// this fails with `AddrInUse` if a server is still listening on it. Must be
+/// called from within a Tokio runtime.
+pub fn bind<P: AsRef<Path>>(path: P) -> io::Result<UnixListener> {
+    let path = path.as_ref();
+    match std::fs::symlink_metadata(path) {
+        Ok(metadata) if metadata.file_type().is_socket() => {
+            match std::os::unix::net::UnixStream::connect(path) {
+                Ok(_) => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::AddrInUse,
+                        format!("{} is in use", path.display()),
+                    ))
+                }
+                Err(e) if e.kind() == io::ErrorKind::ConnectionRefused => {
+                    std::fs::remove_file(path)?
+                }
+                Err(e) => return Err(e),
+            }
+        }
+        _ => {}
+    }
+    UnixListener::bind(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_bind() {
+        let path = std::env::temp_dir().join(format!("swagger-uds-{}.sock", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+
+        let listener = bind(&path).unwrap();
+        assert_eq!(bind(&path).unwrap_err().kind(), io::ErrorKind::AddrInUse);
+
+        // Rebinding succeeds once the first listener has gone
+        drop(listener);
+        let _listener = bind(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,300 @@
+//! Accumulation of validation errors while building a request.
+//!
+//! Generated parsing stops at the first bad parameter, so a client with
+//! several mistakes has to fix them one round-trip at a time. Collecting the
+//! problems in `ValidationErrors` instead lets a server report all of them in
+//! a single response:
+//!
+//! ```
+//! # use swagger::validation::{ValidationErrors, ValidationLocation};
+//! let mut errors = ValidationErrors::new();
+//! let limit = errors.check(ValidationLocation::Query, "limit", "ten".parse::<u32>());
+//! let pet_id = errors.require(ValidationLocation::Path, "petId", None::<u64>);
+//!
+//! let result = errors.into_result((limit, pet_id));
+//! assert_eq!(result.unwrap_err().len(), 2);
+//! ```
+use crate::ApiError;
+use hyper::StatusCode;
+use std::error;
+use std::fmt;
+
+/// Part of the request a value was taken from.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serdejson", derive(serde::Serialize))]
+#[cfg_attr(feature = "serdejson", serde(rename_all = "lowercase"))]
+pub enum ValidationLocation {
+    /// Path parameter.
+    Path,
+    /// Query parameter.
+    Query,
+    /// Header.
+    Header,
+    /// Request body.
+    Body,
+}
+
+impl fmt::Display for ValidationLocation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            ValidationLocation::Path => "path",
+            ValidationLocation::Query => "query",
+            ValidationLocation::Header => "header",
+            ValidationLocation::Body => "body",
+        })
+    }
+}
+
+/// A single problem with a request.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serdejson", derive(serde::Serialize))]
+pub struct ValidationError {
+    /// Name of the parameter or header, or path to the field in the body.
+    pub field: String,
+    /// Part of the request the field is in.
+    pub location: ValidationLocation,
+    /// Human-readable description of the problem.
+    pub message: String,
+    /// Machine-readable identifier for the kind of problem, such as
+    /// `required` or `invalid`.
+    pub code: String,
+}
+
+impl ValidationError {
+    /// Create an error.
+    pub fn new<F, C, M>(location: ValidationLocation, field: F, code: C, message: M) -> Self
+    where
+        F: Into<String>,
+        C: Into<String>,
+        M: Into<String>,
+    {
+        ValidationError {
+            field: field.into(),
+            location,
+            message: message.into(),
+            code: code.into(),
+        }
+    }
+}
+
+impl fmt::Display for ValidationError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} {}: {}", self.location, self.field, self.message)
+    }
+}
+
+impl error::Error for ValidationError {}
+
+/// Collection of the problems found with a request.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ValidationErrors(Vec<ValidationError>);
+
+impl ValidationErrors {
+    /// Create an empty collection.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Record an error.
+    pub fn push(&mut self, error: ValidationError) {
+        self.0.push(error);
+    }
+
+    /// Record an error built from its parts.
+    pub fn add<F, C, M>(&mut self, location: ValidationLocation, field: F, code: C, message: M)
+    where
+        F: Into<String>,
+        C: Into<String>,
+        M: Into<String>,
+    {
+        self.push(ValidationError::new(location, field, code, message));
+    }
+
+    /// Take the value from a parse result, recording an `invalid` error if
+    /// parsing failed.
+    pub fn check<T, E: fmt::Display>(
+        &mut self,
+        location: ValidationLocation,
+        field: &str,
+        result: Result<T, E>,
+    ) -> Option<T> {
+        result
+            .map_err(|e| self.add(location, field, "invalid", e.to_string()))
+            .ok()
+    }
+
+    /// Take a value which must be present, recording a `required` error if
+    /// it is missing.
+    pub fn require<T>(
+        &mut self,
+        location: ValidationLocation,
+        field: &str,
+        value: Option<T>,
+    ) -> Option<T> {
+        if value.is_none() {
+            self.add(location, field, "required", "missing required value");
+        }
+        value
+    }
+
+    /// Whether no errors have been recorded.
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// Number of errors recorded.
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    /// Iterate over the errors, in the order they were recorded.
+    pub fn iter(&self) -> std::slice::Iter<'_, ValidationError> {
+        self.0.iter()
+    }
+
+    /// Return the value if no errors were recorded, and the errors otherwise.
+    pub fn into_result<T>(self, value: T) -> Result<T, Self> {
+        if self.is_empty() {
+            Ok(value)
+        } else {
+            Err(self)
+        }
+    }
+
+    /// Status to respond with: `422 Unprocessable Entity` if the only
+    /// problems are in the body, which was well-formed enough to inspect,
+    /// and `400 Bad Request` otherwise.
+    pub fn status(&self) -> StatusCode {
+        if self
+            .iter()
+            .all(|error| error.location == ValidationLocation::Body)
+        {
+            StatusCode::UNPROCESSABLE_ENTITY
+        } else {
+            StatusCode::BAD_REQUEST
+        }
+    }
+
+    /// Problem details document describing the errors, listed in its
+    /// `errors` member.
+    #[cfg(feature = "serdejson")]
+    pub fn to_problem(&self) -> crate::map_error::Problem {
+        crate::map_error::Problem::new(self.status())
+            .with_detail(format!("request has {} validation error(s)", self.len()))
+            .with_extension("errors", serde_json::to_value(&self.0).unwrap_or_default())
+    }
+
+    /// Render the errors as a problem details response.
+    #[cfg(feature = "serdejson")]
+    pub fn to_response<B: From<String>>(&self) -> hyper::Response<B> {
+        self.to_problem().to_response()
+    }
+}
+
+impl fmt::Display for ValidationErrors {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (i, error) in self.iter().enumerate() {
+            if i > 0 {
+                f.write_str("; ")?;
+            }
+            write!(f, "{}", error)?;
+        }
+        Ok(())
+    }
+}
+
+impl error::Error for ValidationErrors {}
+
+impl From<ValidationError> for ValidationErrors {
+    fn from(error: ValidationError) -> Self {
+        ValidationErrors(vec![error])
+    }
+}
+
+impl From<ValidationErrors> for ApiError {
+    fn from(errors: ValidationErrors) -> Self {
+        ApiError(errors.to_string())
+    }
+}
+
+impl Extend<ValidationError> for ValidationErrors {
+    fn extend<I: IntoIterator<Item = ValidationError>>(&mut self, iter: I) {
+        self.0.extend(iter);
+    }
+}
+
+impl FromIterator<ValidationError> for ValidationErrors {
+    fn from_iter<I: IntoIterator<Item = ValidationError>>(iter: I) -> Self {
+        ValidationErrors(iter.into_iter().collect())
+    }
+}
+
+impl IntoIterator for ValidationErrors {
+    type Item = ValidationError;
+    type IntoIter = std::vec::IntoIter<ValidationError>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.0.into_iter()
+    }
+}
+
+impl<'a> IntoIterator for &'a ValidationErrors {
+    type Item = &'a ValidationError;
+    type IntoIter = std::slice::Iter<'a, ValidationError>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_accumulation() {
+        let mut errors = ValidationErrors::new();
+        let limit = errors.check(ValidationLocation::Query, "limit", "10".parse::<u32>());
+        assert_eq!(limit, Some(10));
+        assert!(errors.is_empty());
+
+        errors.check(ValidationLocation::Header, "X-Count", "-".parse::<u32>());
+        errors.require(ValidationLocation::Body, "name", None::<String>);
+        assert_eq!(errors.len(), 2);
+        assert_eq!(errors.status(), StatusCode::BAD_REQUEST);
+        assert_eq!(
+            errors.to_string(),
+            "header X-Count: invalid digit found in string; body name: missing required value"
+        );
+
+        let body_only: ValidationErrors = errors
+            .into_iter()
+            .filter(|e| e.location == ValidationLocation::Body)
+            .collect();
+        assert_eq!(body_only.status(), StatusCode::UNPROCESSABLE_ENTITY);
+        assert!(body_only.into_result(()).is_err());
+    }
+
+    #[cfg(feature = "serdejson")]
+    #[test]
+    fn test_response() {
+        let errors = ValidationErrors::from(ValidationError::new(
+            ValidationLocation::Path,
+            "petId",
+            "invalid",
+            "not a number",
+        ));
+        let response: hyper::Response<String> = errors.to_response();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        let body: serde_json::Value = serde_json::from_str(response.body()).unwrap();
+        assert_eq!(
+            body["errors"],
+            serde_json::json!([{
+                "field": "petId",
+                "location": "path",
+                "message": "not a number",
+                "code": "invalid",
+            }])
+        );
+    }
+}
@@ -0,0 +1,157 @@
+//! Derive macros for the `swagger` crate.
+//!
+//! These are re-exported by `swagger` when its **derive** feature is enabled,
+//! and should be used from there.
+
+#![deny(missing_docs, unused_qualifications)]
+
+use proc_macro::TokenStream;
+use quote::quote;
+use syn::spanned::Spanned;
+use syn::{parse_macro_input, Data, DeriveInput, Error, Fields};
+
+/// Implement `Has<T>`, `Push<T>` and `Pop<T>` for the type of each field of a
+/// struct, so that it can be used as a context.
+///
+/// See `swagger::HasContext` for details.
+#[proc_macro_derive(HasContext)]
+pub fn derive_has_context(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    expand(input)
+        .unwrap_or_else(Error::into_compile_error)
+        .into()
+}
+
+/// Implement `SampleValue` for a struct, giving each field its sample, or for
+/// an enum, giving its first variant.
+///
+/// See `swagger::examples` for details.
+#[proc_macro_derive(SampleValue)]
+pub fn derive_sample_value(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    expand_sample_value(input)
+        .unwrap_or_else(Error::into_compile_error)
+        .into()
+}
+
+fn expand_sample_value(input: DeriveInput) -> syn::Result<proc_macro2::TokenStream> {
+    let (path, fields) = match &input.data {
+        Data::Struct(data) => (quote! { Self }, &data.fields),
+        Data::Enum(data) => match data.variants.first() {
+            Some(variant) => {
+                let ident = &variant.ident;
+                (quote! { Self::#ident }, &variant.fields)
+            }
+            None => {
+                return Err(Error::new(
+                    input.ident.span(),
+                    "SampleValue can't be derived for enums without variants",
+                ))
+            }
+        },
+        Data::Union(_) => {
+            return Err(Error::new(
+                input.ident.span(),
+                "SampleValue can't be derived for unions",
+            ))
+        }
+    };
+
+    let sample = quote! { ::swagger::examples::SampleValue::sample() };
+    let value = match fields {
+        Fields::Named(fields) => {
+            let fields = fields.named.iter().map(|field| {
+                let ident = &field.ident;
+                quote! { #ident: #sample }
+            });
+            quote! { #path { #(#fields),* } }
+        }
+        Fields::Unnamed(fields) => {
+            let fields = fields.unnamed.iter().map(|_| &sample);
+            quote! { #path(#(#fields),*) }
+        }
+        Fields::Unit => path,
+    };
+
+    let name = &input.ident;
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+    Ok(quote! {
+        impl #impl_generics ::swagger::examples::SampleValue for #name #ty_generics #where_clause {
+            fn sample() -> Self {
+                #value
+            }
+        }
+    })
+}
+
+fn expand(input: DeriveInput) -> syn::Result<proc_macro2::TokenStream> {
+    let fields = match &input.data {
+        Data::Struct(data) => match &data.fields {
+            Fields::Named(fields) => &fields.named,
+            _ => {
+                return Err(Error::new(
+                    input.ident.span(),
+                    "HasContext can only be derived for structs with named fields",
+                ))
+            }
+        },
+        _ => {
+            return Err(Error::new(
+                input.ident.span(),
+                "HasContext can only be derived for structs",
+            ))
+        }
+    };
+
+    for (index, field) in fields.iter().enumerate() {
+        if fields.iter().take(index).any(|other| other.ty == field.ty) {
+            return Err(Error::new(
+                field.ty.span(),
+                "HasContext requires each field to have a distinct type",
+            ));
+        }
+    }
+
+    let name = &input.ident;
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+
+    let impls = fields.iter().map(|field| {
+        let ident = &field.ident;
+        let ty = &field.ty;
+        quote! {
+            impl #impl_generics ::swagger::Has<#ty> for #name #ty_generics #where_clause {
+                fn get(&self) -> &#ty {
+                    &self.#ident
+                }
+
+                fn get_mut(&mut self) -> &mut #ty {
+                    &mut self.#ident
+                }
+
+                fn set(&mut self, value: #ty) {
+                    self.#ident = value;
+                }
+            }
+
+            impl #impl_generics ::swagger::Push<#ty> for #name #ty_generics #where_clause {
+                type Result = Self;
+
+                fn push(mut self, value: #ty) -> Self {
+                    self.#ident = value;
+                    self
+                }
+            }
+
+            impl #impl_generics ::swagger::Pop<#ty> for #name #ty_generics #where_clause {
+                type Result = Self;
+
+                fn pop(mut self) -> (#ty, Self) {
+                    let value = ::std::mem::take(&mut self.#ident);
+                    (value, self)
+                }
+            }
+        }
+    });
+
+    Ok(quote! { #(#impls)* })
+}